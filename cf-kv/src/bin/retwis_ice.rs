@@ -3,6 +3,7 @@ use cf_kv::{
     cornflakes_dynamic::{CornflakesClient, CornflakesKVServer},
     flatbuffers::{FlatbuffersClient, FlatbuffersKVServer},
     protobuf::{ProtobufClient, ProtobufKVServer},
+    redis::RedisClient,
     retwis::{retwis_keys, RetwisClient, RetwisServerLoader},
     retwis_run_datapath::*,
     run_client_retwis, run_server_retwis, KVClient,
@@ -51,6 +52,9 @@ fn main() -> Result<()> {
             SerializationType::Protobuf => {
                 run_client_retwis!(ProtobufClient<IceConnection>, IceConnection, opt);
             }
+            SerializationType::Redis => {
+                run_client_retwis!(RedisClient<IceConnection>, IceConnection, opt);
+            }
             _ => {
                 unimplemented!();
             }
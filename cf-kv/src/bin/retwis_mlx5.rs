@@ -3,6 +3,7 @@ use cf_kv::{
     cornflakes_dynamic::{CornflakesClient, CornflakesKVServer},
     flatbuffers::{FlatbuffersClient, FlatbuffersKVServer},
     protobuf::{ProtobufClient, ProtobufKVServer},
+    redis::{RedisClient, RedisKVServer},
     retwis::{retwis_keys, RetwisClient, RetwisServerLoader},
     retwis_run_datapath::*,
     run_client_retwis, run_server_retwis, KVClient,
@@ -34,6 +35,9 @@ fn main() -> Result<()> {
             SerializationType::Protobuf => {
                 run_server_retwis!(ProtobufKVServer<Mlx5Connection>, Mlx5Connection, opt);
             }
+            SerializationType::Redis => {
+                run_server_retwis!(RedisKVServer<Mlx5Connection>, Mlx5Connection, opt);
+            }
             _ => {
                 unimplemented!();
             }
@@ -51,6 +55,9 @@ fn main() -> Result<()> {
             SerializationType::Protobuf => {
                 run_client_retwis!(ProtobufClient<Mlx5Connection>, Mlx5Connection, opt);
             }
+            SerializationType::Redis => {
+                run_client_retwis!(RedisClient<Mlx5Connection>, Mlx5Connection, opt);
+            }
             _ => {
                 unimplemented!();
             }
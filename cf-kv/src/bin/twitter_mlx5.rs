@@ -3,6 +3,7 @@ use cf_kv::{
     cornflakes_dynamic::{CornflakesClient, CornflakesKVServer},
     flatbuffers::{FlatbuffersClient, FlatbuffersKVServer},
     protobuf::{ProtobufClient, ProtobufKVServer},
+    redis::{RedisClient, RedisKVServer},
     run_client_twitter, run_server_twitter,
     run_twitter::*,
     twitter::{TwitterClient, TwitterServerLoader},
@@ -35,6 +36,9 @@ fn main() -> Result<()> {
             SerializationType::Protobuf => {
                 run_server_twitter!(ProtobufKVServer<Mlx5Connection>, Mlx5Connection, opt);
             }
+            SerializationType::Redis => {
+                run_server_twitter!(RedisKVServer<Mlx5Connection>, Mlx5Connection, opt);
+            }
             _ => {
                 unimplemented!();
             }
@@ -52,6 +56,9 @@ fn main() -> Result<()> {
             SerializationType::Protobuf => {
                 run_client_twitter!(ProtobufClient<Mlx5Connection>, Mlx5Connection, opt);
             }
+            SerializationType::Redis => {
+                run_client_twitter!(RedisClient<Mlx5Connection>, Mlx5Connection, opt);
+            }
             _ => {
                 unimplemented!();
             }
@@ -0,0 +1,34 @@
+use cf_kv::{
+    redis::{RedisClient, RedisKVServer},
+    run_client, run_server,
+    ycsb::{YCSBClient, YCSBServerLoader},
+    ycsb_run_datapath::*,
+    KVClient,
+};
+use color_eyre::eyre::{bail, Result};
+use cornflakes_libos::{
+    datapath::Datapath, state_machine::client::ClientSM, state_machine::server::ServerSM,
+};
+use cornflakes_utils::{global_debug_init, AppMode, SerializationType};
+use linux_datapath::datapath::connection::LinuxConnection;
+use structopt::StructOpt;
+
+fn main() -> Result<()> {
+    let mut opt = YCSBOpt::from_args();
+    global_debug_init(opt.trace_level)?;
+    check_opt(&mut opt)?;
+
+    // the other servers reply through datapath calls the kernel datapath does not implement
+    if opt.serialization != SerializationType::Redis {
+        bail!("The linux datapath only supports redis serialization.");
+    }
+    match opt.mode {
+        AppMode::Server => {
+            run_server!(RedisKVServer<LinuxConnection>, LinuxConnection, opt);
+        }
+        AppMode::Client => {
+            run_client!(RedisClient<LinuxConnection>, LinuxConnection, opt);
+        }
+    }
+    Ok(())
+}
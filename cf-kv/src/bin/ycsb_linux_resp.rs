@@ -0,0 +1,31 @@
+use cf_kv::{redis::RedisKVServer, run_server, ycsb::YCSBServerLoader, ycsb_run_datapath::*};
+use color_eyre::eyre::{bail, Result};
+use cornflakes_libos::{datapath::Datapath, state_machine::server::ServerSM};
+use cornflakes_utils::{global_debug_init, AppMode, SerializationType};
+use linux_datapath::datapath::tcp::LinuxRespConnection;
+use structopt::StructOpt;
+
+fn main() -> Result<()> {
+    let mut opt = YCSBOpt::from_args();
+    global_debug_init(opt.trace_level)?;
+    check_opt(&mut opt)?;
+
+    // streams carry bare RESP, so only redis clients (e.g., redis-cli, redis-benchmark) can talk
+    // to the server
+    if opt.serialization != SerializationType::Redis {
+        bail!("The linux RESP datapath only supports redis serialization.");
+    }
+    // shards and replicas talk to each other with headers that are not RESP
+    if opt.num_shards > 1 || opt.replication_config.is_some() {
+        bail!("The linux RESP datapath runs a single unreplicated server.");
+    }
+    match opt.mode {
+        AppMode::Server => {
+            run_server!(RedisKVServer<LinuxRespConnection>, LinuxRespConnection, opt);
+        }
+        AppMode::Client => {
+            bail!("Use a stock redis client, such as redis-benchmark, to drive the RESP server.");
+        }
+    }
+    Ok(())
+}
@@ -3,7 +3,7 @@ use cf_kv::{
     cornflakes_dynamic::{CornflakesClient, CornflakesKVServer},
     flatbuffers::{FlatbuffersClient, FlatbuffersKVServer},
     protobuf::{ProtobufClient, ProtobufKVServer},
-    redis::RedisClient,
+    redis::{RedisClient, RedisKVServer},
     run_client, run_server,
     ycsb::{YCSBClient, YCSBServerLoader},
     ycsb_run_datapath::*,
@@ -37,6 +37,9 @@ fn main() -> Result<()> {
             SerializationType::Protobuf => {
                 run_server!(ProtobufKVServer<Mlx5Connection>, Mlx5Connection, opt);
             }
+            SerializationType::Redis => {
+                run_server!(RedisKVServer<Mlx5Connection>, Mlx5Connection, opt);
+            }
             _ => {
                 unimplemented!();
             }
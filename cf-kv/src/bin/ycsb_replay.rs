@@ -0,0 +1,105 @@
+use cf_kv::{redis::RedisKVServer, ycsb::YCSBServerLoader, ycsb_run_datapath::*};
+use color_eyre::eyre::{bail, ensure, Result};
+use cornflakes_libos::{
+    admission::AdmissionController,
+    datapath::Datapath,
+    pcap::{PacketCapture, PcapReplay, ReplayFinished, DEFAULT_CAPTURE_QUEUE_DEPTH},
+    state_machine::server::ServerSM,
+};
+use cornflakes_utils::{global_debug_init, AppMode, SerializationType};
+use linux_datapath::datapath::connection::LinuxConnection;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "YCSB KV Store Replay.",
+    about = "Replays the requests in a server packet capture through the YCSB KV server, offline, and checks its responses against the captured ones."
+)]
+struct ReplayOpt {
+    #[structopt(flatten)]
+    ycsb: YCSBOpt,
+    #[structopt(
+        long = "replay_pcap",
+        help = "Capture recorded at the server (e.g., with --capture_pcap) at --server_ip and the configured server port."
+    )]
+    replay_pcap: String,
+    #[structopt(
+        long = "replay_output",
+        help = "Record the replayed requests and the server's responses to this pcap file."
+    )]
+    replay_output: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let mut opt = ReplayOpt::from_args();
+    global_debug_init(opt.ycsb.trace_level)?;
+    check_opt(&mut opt.ycsb)?;
+    let ycsb = &opt.ycsb;
+
+    // replay runs on the kernel datapath, which only supports redis serialization
+    if ycsb.mode != AppMode::Server || ycsb.serialization != SerializationType::Redis {
+        bail!("Replay runs a server with redis serialization.");
+    }
+    let mut datapath_params =
+        LinuxConnection::parse_config_file(&ycsb.config_file, &ycsb.server_ip)?;
+    let addresses = LinuxConnection::compute_affinity(&datapath_params, 1, None, AppMode::Server)?;
+    let context = LinuxConnection::global_init(1, &mut datapath_params, addresses)?
+        .into_iter()
+        .nth(0)
+        .unwrap();
+    let replay = PcapReplay::from_file(&opt.replay_pcap, &context.get_address_info())?;
+    let mut connection = LinuxConnection::new_replay(context, replay)?;
+    if let Some(replay_output) = &opt.replay_output {
+        connection.set_packet_capture(PacketCapture::new(
+            replay_output,
+            DEFAULT_CAPTURE_QUEUE_DEPTH,
+        )?)?;
+    }
+
+    let mut load_generator = YCSBServerLoader::new(
+        ycsb.value_size_generator.clone(),
+        ycsb.num_values,
+        ycsb.num_keys,
+        ycsb.allocate_contiguously,
+        ycsb.use_linked_list,
+    );
+    load_generator.set_ordered_index(ycsb.ordered_index);
+    let mut kv_server = RedisKVServer::new(
+        ycsb.trace_file.as_str(),
+        load_generator,
+        &mut connection,
+        ycsb.push_buf_type,
+        ycsb.use_linked_list,
+    )?;
+    kv_server.init(&mut connection)?;
+    let admission_controller = admission_config(ycsb).map(AdmissionController::new);
+    let res = match is_baseline(ycsb) {
+        true => kv_server.run_state_machine_baseline_with_admission_control(
+            &mut connection,
+            admission_controller,
+        ),
+        false => kv_server.run_state_machine_with_controllers(
+            &mut connection,
+            None,
+            admission_controller,
+        ),
+    };
+    match res {
+        Ok(()) => {}
+        Err(e) if e.downcast_ref::<ReplayFinished>().is_some() => {}
+        Err(e) => {
+            return Err(e);
+        }
+    }
+
+    let replay = connection.replay().unwrap();
+    let stats = replay.stats();
+    stats.dump("Replay finished");
+    ensure!(
+        stats.mismatched == 0 && replay.missing_responses() == 0,
+        "Replay diverged from the capture: {} responses differ, {} captured responses were not sent",
+        stats.mismatched,
+        replay.missing_responses()
+    );
+    Ok(())
+}
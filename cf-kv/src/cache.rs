@@ -0,0 +1,658 @@
+//! Memory budget, eviction and TTL bookkeeping for the cf-kv stores.
+//!
+//! A `Cache` tracks how many bytes each key holds (registered buffers, or heap copies of small
+//! values; see `storage`) and picks eviction victims under LRU, CLOCK or S3-FIFO. Caches built
+//! on one `CacheBudget` split a single memory budget. A cache never owns the buffers: the store
+//! drops the victims it returns from its map. That is safe with zero-copy sends in flight, since
+//! each send holds its own reference to the buffer's metadata and the memory only returns to its
+//! mempool once the last reference is gone.
+use super::ResponseStatus;
+use color_eyre::eyre::{bail, Report, Result};
+use hashbrown::{HashMap, HashSet};
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    ops::Add,
+    rc::Rc,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// S3-FIFO keeps newly inserted keys in a small queue holding this fraction of the budget.
+const S3_FIFO_SMALL_QUEUE_DIVISOR: usize = 10;
+/// S3-FIFO caps per-key access counts at this value.
+const S3_FIFO_MAX_FREQ: u8 = 3;
+/// Recency queues hold stale positions until they grow past this many entries beyond twice the
+/// number of live keys, then get compacted.
+const QUEUE_COMPACTION_SLACK: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    Lru,
+    Clock,
+    S3Fifo,
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        EvictionPolicy::Lru
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = color_eyre::eyre::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "lru" | "LRU" => EvictionPolicy::Lru,
+            "clock" | "CLOCK" => EvictionPolicy::Clock,
+            "s3fifo" | "s3-fifo" | "S3FIFO" | "S3-FIFO" => EvictionPolicy::S3Fifo,
+            x => bail!("{} eviction policy unknown.", x),
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Ceiling on value bytes a store holds; None leaves the store unbounded.
+    pub capacity_bytes: Option<usize>,
+    pub policy: EvictionPolicy,
+    /// TTL for puts that do not carry their own; None means such keys never expire.
+    pub default_ttl: Option<Duration>,
+}
+
+impl CacheConfig {
+    pub fn new(
+        capacity_bytes: Option<usize>,
+        policy: EvictionPolicy,
+        default_ttl: Option<Duration>,
+    ) -> Self {
+        CacheConfig {
+            capacity_bytes,
+            policy,
+            default_ttl,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Keys dropped by the eviction policy to stay under the memory budget.
+    pub evictions: u64,
+    /// Keys dropped because their TTL passed.
+    pub expirations: u64,
+    /// Value bytes held by keys the cache tracks.
+    pub used_bytes: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            hits = self.hits,
+            misses = self.misses,
+            hit_rate = self.hit_rate(),
+            evictions = self.evictions,
+            expirations = self.expirations,
+            used_bytes = self.used_bytes,
+            "{}",
+            msg
+        );
+    }
+}
+
+impl Add for CacheStats {
+    type Output = CacheStats;
+
+    fn add(self, other: CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            evictions: self.evictions + other.evictions,
+            expirations: self.expirations + other.expirations,
+            used_bytes: self.used_bytes + other.used_bytes,
+        }
+    }
+}
+
+/// Bytes charged across every cache that shares a memory budget. Each cache evicts only its
+/// own keys, so a store whose own keys cannot make room for an insert has to refuse it.
+#[derive(Debug, Default, Clone)]
+pub struct CacheBudget(Rc<Cell<usize>>);
+
+impl CacheBudget {
+    pub fn used_bytes(&self) -> usize {
+        self.0.get()
+    }
+
+    fn charge(&self, bytes: usize) {
+        self.0.set(self.0.get() + bytes);
+    }
+
+    fn release(&self, bytes: usize) {
+        self.0.set(self.0.get() - bytes);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    bytes: usize,
+    expires_at: Option<Instant>,
+    /// Identifies the entry's current queue position; positions with an older stamp are stale.
+    stamp: u64,
+    /// CLOCK reference bit (0 or 1) or S3-FIFO access count.
+    freq: u8,
+    /// Whether the entry sits in the S3-FIFO main queue rather than the small one.
+    in_main: bool,
+    /// Whether a lookup found the entry expired and queued it for reclamation.
+    expired: bool,
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        match self.expires_at {
+            Some(expires_at) => now >= expires_at,
+            None => false,
+        }
+    }
+}
+
+/// Per-store eviction and TTL state.
+///
+/// Keys are only tracked when the store is bounded or the key has a TTL, so an unbounded store
+/// without TTLs pays for nothing but the hit and miss counters. Expired keys are reclaimed
+/// lazily: a lookup that finds one reports a miss, and the next insert hands it back to the
+/// store for removal (or the eviction policy reaches it first). Keys are reference counted so
+/// queue positions (one per LRU hit) share the entry's allocation.
+#[derive(Debug, Default)]
+pub struct Cache {
+    config: CacheConfig,
+    entries: HashMap<Rc<str>, Entry>,
+    /// Bytes held by this cache's own entries.
+    used_bytes: usize,
+    /// Bytes held by every cache sharing the budget, this one included.
+    budget: CacheBudget,
+    next_stamp: u64,
+    /// LRU order or CLOCK ring for those policies; the S3-FIFO small queue otherwise.
+    queue: VecDeque<(Rc<str>, u64)>,
+    /// S3-FIFO main queue.
+    main: VecDeque<(Rc<str>, u64)>,
+    /// Bytes held by entries in the S3-FIFO small queue.
+    small_bytes: usize,
+    /// S3-FIFO ghost queue: recently evicted keys, readmitted straight into the main queue.
+    ghost: VecDeque<Rc<str>>,
+    ghost_set: HashSet<Rc<str>>,
+    expired: Vec<Rc<str>>,
+    stats: CacheStats,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self::with_budget(config, CacheBudget::default())
+    }
+
+    /// A cache whose `capacity_bytes` bounds the bytes of every cache built on `budget`
+    /// together; the caches should share a config.
+    pub fn with_budget(config: CacheConfig, budget: CacheBudget) -> Self {
+        Cache {
+            config,
+            budget,
+            ..Default::default()
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            used_bytes: self.used_bytes as u64,
+            ..self.stats
+        }
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        self.config.capacity_bytes.is_some()
+    }
+
+    /// Records a lookup of a key the store does (`present`) or does not hold. Returns whether
+    /// the store should serve it, which is false for keys whose TTL has passed.
+    pub fn record_lookup(&mut self, key: &str, present: bool) -> bool {
+        if !present {
+            self.stats.misses += 1;
+            return false;
+        }
+        if self.entries.is_empty() {
+            self.stats.hits += 1;
+            return true;
+        }
+        let (key, entry) = match self.entries.get_key_value_mut(key) {
+            Some(key_and_entry) => key_and_entry,
+            None => {
+                self.stats.hits += 1;
+                return true;
+            }
+        };
+        if entry.is_expired(Instant::now()) {
+            if !entry.expired {
+                entry.expired = true;
+                self.expired.push(key.clone());
+            }
+            self.stats.misses += 1;
+            return false;
+        }
+        self.stats.hits += 1;
+        match self.config.policy {
+            EvictionPolicy::Lru => {
+                if self.config.capacity_bytes.is_some() {
+                    self.next_stamp += 1;
+                    entry.stamp = self.next_stamp;
+                    self.queue.push_back((key.clone(), entry.stamp));
+                    self.maybe_compact();
+                }
+            }
+            EvictionPolicy::Clock => {
+                entry.freq = 1;
+            }
+            EvictionPolicy::S3Fifo => {
+                entry.freq = std::cmp::min(entry.freq + 1, S3_FIFO_MAX_FREQ);
+            }
+        }
+        true
+    }
+
+    /// Records that `key` now holds `bytes` of values (replacing whatever it held
+    /// before), expiring after `ttl` or the default TTL. Returns the keys the store must drop to
+    /// stay within the budget, including expired keys found by earlier lookups; `key` itself is
+    /// never among them. Fails, changing nothing, if `bytes` cannot fit even after evicting
+    /// every other key this cache holds.
+    pub fn record_insert(
+        &mut self,
+        key: &str,
+        bytes: usize,
+        ttl: Option<Duration>,
+    ) -> Result<Vec<String>> {
+        if let Some(capacity) = self.config.capacity_bytes {
+            if bytes > capacity {
+                bail!(
+                    "Value of {} bytes for key {} exceeds the {}-byte memory budget",
+                    bytes,
+                    key,
+                    capacity
+                );
+            }
+            let other_bytes = self.budget.used_bytes() - self.used_bytes;
+            if other_bytes + bytes > capacity {
+                return Err(Report::new(ResponseStatus::ServerError).wrap_err(format!(
+                    "Value of {} bytes for key {} does not fit in the memory budget: other stores hold {} of {} bytes",
+                    bytes, key, other_bytes, capacity
+                )));
+            }
+        }
+        let now = Instant::now();
+        let expires_at = ttl.or(self.config.default_ttl).map(|ttl| now + ttl);
+        self.untrack(key);
+
+        let mut victims = Vec::new();
+        for expired_key in std::mem::take(&mut self.expired) {
+            // the key may have been rewritten since the lookup that found it expired
+            let still_expired = self.entries.get(&expired_key).map(|entry| entry.expired);
+            if still_expired == Some(true) && self.untrack(&expired_key) {
+                self.stats.expirations += 1;
+                victims.push(expired_key.to_string());
+            }
+        }
+
+        let capacity = match self.config.capacity_bytes {
+            Some(capacity) => capacity,
+            None => {
+                if let Some(expires_at) = expires_at {
+                    self.track(key, bytes, Some(expires_at), false);
+                }
+                return Ok(victims);
+            }
+        };
+        while self.budget.used_bytes() + bytes > capacity {
+            match self.next_victim(capacity, now) {
+                Some(victim) => victims.push(victim),
+                None => break,
+            }
+        }
+        let in_main = self.config.policy == EvictionPolicy::S3Fifo && self.ghost_set.contains(key);
+        self.track(key, bytes, expires_at, in_main);
+        Ok(victims)
+    }
+
+    /// Records that the store removed `key`.
+    pub fn record_remove(&mut self, key: &str) {
+        self.untrack(key);
+    }
+
+    /// Whether `key`'s TTL (if any) has yet to pass. Unlike `record_lookup`, this counts neither
+    /// a hit nor a miss and leaves the key's eviction priority alone, for reads such as scans
+    /// that should not look like client interest in every key they pass over.
+    pub fn is_live(&self, key: &str) -> bool {
+        match self.entries.get(key) {
+            Some(entry) => !entry.is_expired(Instant::now()),
+            None => true,
+        }
+    }
+
+    /// Registered-buffer bytes currently charged to `key` (0 if untracked).
+    pub fn charged_bytes(&self, key: &str) -> usize {
+        self.entries.get(key).map(|entry| entry.bytes).unwrap_or(0)
+    }
+
+    /// Time left before `key` expires: None if it has no TTL, zero if it already has.
+    pub fn remaining_ttl(&self, key: &str) -> Option<Duration> {
+        let expires_at = self.entries.get(key)?.expires_at?;
+        Some(expires_at.saturating_duration_since(Instant::now()))
+    }
+
+    fn track(&mut self, key: &str, bytes: usize, expires_at: Option<Instant>, in_main: bool) {
+        let key: Rc<str> = Rc::from(key);
+        self.next_stamp += 1;
+        let entry = Entry {
+            bytes,
+            expires_at,
+            stamp: self.next_stamp,
+            freq: 0,
+            in_main,
+            expired: false,
+        };
+        if self.config.capacity_bytes.is_some() {
+            match in_main {
+                true => self.main.push_back((key.clone(), entry.stamp)),
+                false => {
+                    self.small_bytes += bytes;
+                    self.queue.push_back((key.clone(), entry.stamp));
+                }
+            }
+        }
+        self.used_bytes += bytes;
+        self.budget.charge(bytes);
+        self.entries.insert(key, entry);
+    }
+
+    /// Stops tracking `key`; its queue positions go stale. Returns whether it was tracked.
+    fn untrack(&mut self, key: &str) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                self.used_bytes -= entry.bytes;
+                self.budget.release(entry.bytes);
+                if self.config.capacity_bytes.is_some() && !entry.in_main {
+                    self.small_bytes -= entry.bytes;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn evict(&mut self, key: Rc<str>, now: Instant) -> Rc<str> {
+        if let Some(entry) = self.entries.get(&key) {
+            match entry.is_expired(now) {
+                true => self.stats.expirations += 1,
+                false => self.stats.evictions += 1,
+            }
+        }
+        self.untrack(&key);
+        key
+    }
+
+    /// Pops the first position in `queue` that still refers to a live entry.
+    fn pop_live(
+        queue: &mut VecDeque<(Rc<str>, u64)>,
+        entries: &HashMap<Rc<str>, Entry>,
+    ) -> Option<(Rc<str>, u64)> {
+        while let Some((key, stamp)) = queue.pop_front() {
+            if entries.get(&key).map(|entry| entry.stamp) == Some(stamp) {
+                return Some((key, stamp));
+            }
+        }
+        None
+    }
+
+    fn next_victim(&mut self, capacity: usize, now: Instant) -> Option<String> {
+        match self.config.policy {
+            EvictionPolicy::Lru => {
+                let (key, _) = Self::pop_live(&mut self.queue, &self.entries)?;
+                Some(self.evict(key, now).to_string())
+            }
+            EvictionPolicy::Clock => loop {
+                let (key, stamp) = Self::pop_live(&mut self.queue, &self.entries)?;
+                let entry = self.entries.get_mut(&key).unwrap();
+                if entry.freq > 0 && !entry.is_expired(now) {
+                    entry.freq = 0;
+                    self.queue.push_back((key, stamp));
+                    continue;
+                }
+                return Some(self.evict(key, now).to_string());
+            },
+            EvictionPolicy::S3Fifo => loop {
+                let mut from_small = None;
+                if self.small_bytes > capacity / S3_FIFO_SMALL_QUEUE_DIVISOR {
+                    from_small = Self::pop_live(&mut self.queue, &self.entries);
+                }
+                if from_small.is_none() {
+                    if let Some((key, stamp)) = Self::pop_live(&mut self.main, &self.entries) {
+                        let entry = self.entries.get_mut(&key).unwrap();
+                        if entry.freq > 0 && !entry.is_expired(now) {
+                            entry.freq -= 1;
+                            self.main.push_back((key, stamp));
+                            continue;
+                        }
+                        return Some(self.evict(key, now).to_string());
+                    }
+                    from_small = Self::pop_live(&mut self.queue, &self.entries);
+                }
+                let (key, stamp) = from_small?;
+                let entry = self.entries.get_mut(&key).unwrap();
+                if entry.freq > 0 && !entry.is_expired(now) {
+                    // accessed while in the small queue: promote to the main queue
+                    entry.freq = 0;
+                    entry.in_main = true;
+                    self.small_bytes -= entry.bytes;
+                    self.main.push_back((key, stamp));
+                    continue;
+                }
+                let key = self.evict(key, now);
+                let victim = key.to_string();
+                self.remember_ghost(key);
+                return Some(victim);
+            },
+        }
+    }
+
+    fn remember_ghost(&mut self, key: Rc<str>) {
+        if !self.ghost_set.insert(key.clone()) {
+            return;
+        }
+        self.ghost.push_back(key);
+        while self.ghost.len() > std::cmp::max(self.entries.len(), 1) {
+            if let Some(old) = self.ghost.pop_front() {
+                self.ghost_set.remove(&old);
+            }
+        }
+    }
+
+    /// LRU lookups push a new position per hit; drop the stale ones once they pile up.
+    fn maybe_compact(&mut self) {
+        if self.queue.len() <= 2 * self.entries.len() + QUEUE_COMPACTION_SLACK {
+            return;
+        }
+        let entries = &self.entries;
+        self.queue
+            .retain(|(key, stamp)| entries.get(key).map(|entry| entry.stamp) == Some(*stamp));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounded(capacity: usize, policy: EvictionPolicy) -> Cache {
+        Cache::new(CacheConfig::new(Some(capacity), policy, None))
+    }
+
+    fn insert(cache: &mut Cache, key: &str, bytes: usize) -> Vec<String> {
+        cache.record_insert(key, bytes, None).unwrap()
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let mut cache = bounded(30, EvictionPolicy::Lru);
+        for key in ["a", "b", "c"] {
+            assert!(insert(&mut cache, key, 10).is_empty());
+        }
+        assert!(cache.record_lookup("a", true));
+        assert_eq!(insert(&mut cache, "d", 10), vec!["b".to_string()]);
+        assert_eq!(insert(&mut cache, "e", 10), vec!["c".to_string()]);
+        assert_eq!(insert(&mut cache, "f", 10), vec!["a".to_string()]);
+        assert_eq!(cache.stats().evictions, 3);
+        assert_eq!(cache.stats().used_bytes, 30);
+    }
+
+    #[test]
+    fn lru_survives_queue_compaction() {
+        let mut cache = bounded(20, EvictionPolicy::Lru);
+        insert(&mut cache, "a", 10);
+        insert(&mut cache, "b", 10);
+        for _ in 0..2 * QUEUE_COMPACTION_SLACK {
+            assert!(cache.record_lookup("b", true));
+            assert!(cache.record_lookup("a", true));
+        }
+        assert!(cache.queue.len() <= 2 * cache.entries.len() + QUEUE_COMPACTION_SLACK + 1);
+        assert_eq!(insert(&mut cache, "c", 10), vec!["b".to_string()]);
+    }
+
+    #[test]
+    fn clock_gives_referenced_keys_a_second_chance() {
+        let mut cache = bounded(30, EvictionPolicy::Clock);
+        for key in ["a", "b", "c"] {
+            insert(&mut cache, key, 10);
+        }
+        assert!(cache.record_lookup("a", true));
+        assert_eq!(insert(&mut cache, "d", 10), vec!["b".to_string()]);
+        // a's reference bit was cleared on the first pass
+        assert_eq!(insert(&mut cache, "e", 10), vec!["c".to_string()]);
+        assert_eq!(insert(&mut cache, "f", 10), vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn s3fifo_promotes_accessed_keys_and_readmits_ghosts() {
+        let mut cache = bounded(100, EvictionPolicy::S3Fifo);
+        insert(&mut cache, "a", 40);
+        insert(&mut cache, "b", 40);
+        assert!(cache.record_lookup("a", true));
+        // a was accessed in the small queue, so it moves to the main queue instead
+        assert_eq!(insert(&mut cache, "c", 40), vec!["b".to_string()]);
+        assert!(cache.entries.get("a").unwrap().in_main);
+        assert!(cache.ghost_set.contains("b"));
+        // b comes back from the ghost queue straight into the main queue
+        cache.record_remove("c");
+        assert!(insert(&mut cache, "b", 40).is_empty());
+        assert!(cache.entries.get("b").unwrap().in_main);
+        assert_eq!(cache.small_bytes, 0);
+        // new keys still start out in the small queue, which is evicted from first
+        insert(&mut cache, "d", 20);
+        assert_eq!(insert(&mut cache, "e", 20), vec!["d".to_string()]);
+    }
+
+    #[test]
+    fn expired_keys_miss_and_are_reclaimed() {
+        let mut cache = Cache::new(CacheConfig::default());
+        cache.record_insert("a", 10, Some(Duration::ZERO)).unwrap();
+        cache
+            .record_insert("b", 10, Some(Duration::from_secs(3600)))
+            .unwrap();
+        assert!(!cache.record_lookup("a", true));
+        assert!(cache.record_lookup("b", true));
+        assert_eq!(cache.remaining_ttl("a"), Some(Duration::ZERO));
+        assert!(cache.remaining_ttl("b").unwrap() > Duration::ZERO);
+        assert_eq!(insert(&mut cache, "c", 10), vec!["a".to_string()]);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.expirations), (1, 1, 1));
+        // unbounded stores only track keys with a TTL
+        assert_eq!(cache.charged_bytes("c"), 0);
+        assert_eq!(cache.remaining_ttl("c"), None);
+    }
+
+    #[test]
+    fn liveness_checks_leave_stats_and_recency_alone() {
+        let mut cache = bounded(30, EvictionPolicy::Lru);
+        insert(&mut cache, "a", 10);
+        insert(&mut cache, "b", 10);
+        cache.record_insert("c", 10, Some(Duration::ZERO)).unwrap();
+        assert!(cache.is_live("a"));
+        assert!(!cache.is_live("c"));
+        assert!(cache.is_live("untracked"));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (0, 0));
+        // checking "a" did not make it more recently used than "b"
+        assert_eq!(
+            insert(&mut cache, "d", 20),
+            vec!["a".to_string(), "b".to_string()]
+        );
+    }
+
+    #[test]
+    fn default_ttl_applies_to_puts_without_one() {
+        let mut cache = Cache::new(CacheConfig::new(
+            None,
+            EvictionPolicy::Lru,
+            Some(Duration::ZERO),
+        ));
+        insert(&mut cache, "a", 10);
+        assert!(!cache.record_lookup("a", true));
+        // rewriting the key clears the pending expiration
+        cache
+            .record_insert("a", 10, Some(Duration::from_secs(3600)))
+            .unwrap();
+        assert!(cache.record_lookup("a", true));
+        assert!(insert(&mut cache, "b", 10).is_empty());
+    }
+
+    #[test]
+    fn caches_share_one_budget() {
+        let config = CacheConfig::new(Some(100), EvictionPolicy::Lru, None);
+        let budget = CacheBudget::default();
+        let mut first = Cache::with_budget(config, budget.clone());
+        let mut second = Cache::with_budget(config, budget.clone());
+        insert(&mut first, "a", 60);
+        assert!(insert(&mut second, "x", 30).is_empty());
+        // the second cache makes room from its own keys
+        assert_eq!(insert(&mut second, "y", 30), vec!["x".to_string()]);
+        assert_eq!(budget.used_bytes(), 90);
+        let e = second.record_insert("z", 50, None).unwrap_err();
+        assert_eq!(ResponseStatus::from_error(&e), ResponseStatus::ServerError);
+        assert_eq!(budget.used_bytes(), 90);
+        assert_eq!(second.charged_bytes("y"), 30);
+        first.record_remove("a");
+        assert_eq!(insert(&mut second, "z", 50), Vec::<String>::new());
+        assert_eq!(budget.used_bytes(), 80);
+    }
+
+    #[test]
+    fn oversized_keys_are_rejected() {
+        let mut cache = bounded(100, EvictionPolicy::Lru);
+        insert(&mut cache, "a", 60);
+        // replacing a key's value does not count its old bytes against the new ones
+        assert!(insert(&mut cache, "a", 100).is_empty());
+        let e = cache.record_insert("a", 101, None).unwrap_err();
+        assert_eq!(ResponseStatus::from_error(&e), ResponseStatus::BadRequest);
+        assert_eq!(cache.charged_bytes("a"), 100);
+        assert!(cache.record_insert("b", 101, None).is_err());
+        assert_eq!(cache.charged_bytes("b"), 0);
+        assert_eq!(cache.stats().used_bytes, 100);
+    }
+}
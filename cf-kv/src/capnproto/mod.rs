@@ -1,9 +1,13 @@
 use super::{
-    kv_capnp, ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType,
-    ServerLoadGenerator, REQ_TYPE_SIZE,
+    combined_cache_stats, combined_storage_stats, delete_from_stores, failed_request_status,
+    kv_capnp, replication::Replicator, scan_end_key, shard::ShardRouter, signed_delta,
+    ttl_from_micros, ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType,
+    ResponseStatus, ServerLoadGenerator, REQ_TYPE_SIZE,
 };
 use byteorder::{ByteOrder, LittleEndian};
-use capnp::message::{Allocator, Builder, Reader, ReaderOptions, ReaderSegments, SegmentArray};
+use capnp::message::{
+    Allocator, Builder, HeapAllocator, Reader, ReaderOptions, ReaderSegments, SegmentArray,
+};
 use color_eyre::eyre::{bail, ensure, Result, WrapErr};
 use cornflakes_libos::{
     allocator::MempoolID,
@@ -15,7 +19,18 @@ use std::marker::PhantomData;
 const FRAMING_ENTRY_SIZE: usize = 8;
 
 fn read_context(buf: &[u8]) -> Result<Vec<&[u8]>> {
+    ensure!(
+        buf.len() >= FRAMING_ENTRY_SIZE,
+        "Capnproto message of {} bytes has no framing",
+        buf.len()
+    );
     let num_segments = LittleEndian::read_u32(&buf[0..4]) as usize;
+    ensure!(
+        FRAMING_ENTRY_SIZE * (1 + num_segments) <= buf.len(),
+        "Capnproto framing for {} segments overruns message of {} bytes",
+        num_segments,
+        buf.len()
+    );
     tracing::debug!(
         num_segments = num_segments,
         buf_len = buf.len(),
@@ -27,7 +42,15 @@ fn read_context(buf: &[u8]) -> Result<Vec<&[u8]>> {
         let data_offset = LittleEndian::read_u32(&buf[cur_idx..(cur_idx + 4)]) as usize;
         let size = LittleEndian::read_u32(&buf[(cur_idx + 4)..cur_idx + 8]) as usize;
         tracing::debug!("Segment {} size: {}", i, size);
-        segments.push(&buf[data_offset..(data_offset + size)]);
+        match buf.get(data_offset..(data_offset + size)) {
+            Some(segment) => segments.push(segment),
+            None => bail!(
+                "Capnproto segment {} at offset {} overruns message of {} bytes",
+                i,
+                data_offset,
+                buf.len()
+            ),
+        }
     }
     Ok(segments)
 }
@@ -73,24 +96,24 @@ where
         tracing::debug!("Received get request for key: {:?}", get_request.get_key());
         let key = get_request.get_key()?;
         let value = match self.use_linked_list() {
-            true => match linked_list_kv_server.get(key) {
-                Some(v) => v.as_ref().get_buffer(),
-                None => {
-                    bail!("Could not find value for key: {:?}", key);
-                }
-            },
-            false => match kv_server.get(key) {
-                Some(v) => v,
-                None => {
-                    bail!("Cannot find value for key in KV store: {:?}", key);
-                }
-            },
+            true => linked_list_kv_server
+                .get(key)
+                .map(|v| v.as_ref().get_buffer()),
+            false => kv_server.get(key),
         };
-        tracing::debug!("Value len: {:?}", value.as_ref().len());
 
         // construct response
         let mut response = builder.init_root::<kv_capnp::get_resp::Builder>();
         response.set_id(get_request.get_id());
+        let value = match value {
+            Some(v) => v,
+            None => {
+                tracing::debug!(key, "Cannot find value for key in KV store");
+                response.set_status(ResponseStatus::NotFound.as_u32());
+                return Ok(());
+            }
+        };
+        tracing::debug!("Value len: {:?}", value.as_ref().len());
         response.set_val(value.as_ref());
         Ok(())
     }
@@ -112,26 +135,26 @@ where
             .wrap_err("Failed to deserialize GetFromListReq.")?;
         tracing::debug!("Received get request for key: {:?}", get_request.get_key());
         let key = get_request.get_key()?;
-        let value = match list_kv_server.get(key) {
-            Some(list) => match list.get(get_request.get_idx() as usize) {
-                Some(v) => v,
-                None => {
-                    bail!(
-                        "Could not find idx {} for key {} in list kv server",
-                        get_request.get_idx(),
-                        key
-                    );
-                }
-            },
-            None => {
-                bail!("Could not find value for key: {:?}", key);
-            }
-        };
-        tracing::debug!("Value len: {:?}", value.as_ref().len());
+        let value = list_kv_server
+            .get(key)
+            .and_then(|list| list.get(get_request.get_idx() as usize));
 
         // construct response
         let mut response = builder.init_root::<kv_capnp::get_resp::Builder>();
         response.set_id(get_request.get_id());
+        let value = match value {
+            Some(v) => v,
+            None => {
+                tracing::debug!(
+                    key,
+                    idx = get_request.get_idx(),
+                    "Could not find idx for key in list kv server"
+                );
+                response.set_status(ResponseStatus::NotFound.as_u32());
+                return Ok(());
+            }
+        };
+        tracing::debug!("Value len: {:?}", value.as_ref().len());
         response.set_val(value.as_ref());
         Ok(())
     }
@@ -155,10 +178,17 @@ where
             .wrap_err("Failed to deserialize PutReq.")?;
         let key = put_request.get_key()?;
         let value = put_request.get_val()?;
+        let ttl = ttl_from_micros(put_request.get_ttl_us());
         if self.use_linked_list() {
-            linked_list_kv_server.insert_with_copies(key, value, datapath, mempool_ids)?;
+            linked_list_kv_server.insert_with_copies_and_ttl(
+                key,
+                value,
+                ttl,
+                datapath,
+                mempool_ids,
+            )?;
         } else {
-            kv_server.insert_with_copies(key, value, datapath, mempool_ids)?;
+            kv_server.insert_with_copies_and_ttl(key, value, ttl, datapath, mempool_ids)?;
         }
 
         // construct response
@@ -187,26 +217,27 @@ where
 
         let mut response = builder.init_root::<kv_capnp::get_m_resp::Builder>();
         response.set_id(getm_request.get_id());
-        let mut list = response.init_vals(keys.len());
+        let mut status = ResponseStatus::Ok;
+        let mut list = response.reborrow().init_vals(keys.len());
         for (i, key_res) in keys.iter().enumerate() {
             let key = key_res?;
             let value = match self.use_linked_list() {
-                true => match linked_list_kv_server.get(key) {
-                    Some(v) => v.as_ref().as_ref(),
-                    None => {
-                        bail!("Could not find value for key: {:?}", key);
-                    }
-                },
-                false => match kv_server.get(key) {
-                    Some(v) => v.as_ref(),
-                    None => {
-                        bail!("Cannot find value for key in KV store: {:?}", key);
-                    }
-                },
+                true => linked_list_kv_server.get(key).map(|v| v.as_ref().as_ref()),
+                false => kv_server.get(key).map(|v| v.as_ref()),
+            };
+            // missing keys are left as empty values so the list lines up with the keys
+            let value: &[u8] = match value {
+                Some(v) => v,
+                None => {
+                    tracing::debug!(key, "Cannot find value for key in KV store");
+                    status = ResponseStatus::NotFound;
+                    continue;
+                }
             };
             tracing::debug!("Value len: {:?}", value.as_ref().len());
             list.set(i as u32, value.as_ref());
         }
+        response.set_status(status.as_u32());
         Ok(())
     }
 
@@ -284,6 +315,11 @@ where
                 demikernel::timer!("do get on key");
                 linked_list_kv.get(key)
             };
+            if node_option.is_none() {
+                tracing::debug!(key, "Cannot find value for key in KV store");
+                response.set_status(ResponseStatus::NotFound.as_u32());
+                return Ok(());
+            }
             let range_len = {
                 // TODO: range parsing is buggy?
                 if range_end == -1 || range_end == 0 {
@@ -330,7 +366,9 @@ where
             let values = match list_kv_server.get(key) {
                 Some(v) => v,
                 None => {
-                    bail!("Cannot find value for key in KV store: {:?}", key);
+                    tracing::debug!(key, "Cannot find value for key in KV store");
+                    response.set_status(ResponseStatus::NotFound.as_u32());
+                    return Ok(());
                 }
             };
             tracing::debug!("Values len: {:?}", values.len());
@@ -374,6 +412,182 @@ where
         response.set_id(putlist_request.get_id());
         Ok(())
     }
+
+    fn handle_delete<T>(
+        &self,
+        kv_server: &mut KVServer<D>,
+        list_kv_server: &mut ListKVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        pkt: &ReceivedPkt<D>,
+        builder: &mut Builder<T>,
+    ) -> Result<()>
+    where
+        T: Allocator,
+    {
+        let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+        let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+        let message_reader = Reader::new(segment_array, ReaderOptions::default());
+        let delete_request = message_reader
+            .get_root::<kv_capnp::delete_req::Reader>()
+            .wrap_err("Failed to deserialize DeleteReq.")?;
+        let key = delete_request.get_key()?;
+
+        let mut response = builder.init_root::<kv_capnp::put_resp::Builder>();
+        response.set_id(delete_request.get_id());
+        if !delete_from_stores(key, kv_server, list_kv_server, linked_list_kv_server) {
+            tracing::debug!(key, "Cannot find key to delete in KV store");
+            response.set_status(ResponseStatus::NotFound.as_u32());
+        }
+        Ok(())
+    }
+
+    fn handle_deletem<T>(
+        &self,
+        kv_server: &mut KVServer<D>,
+        list_kv_server: &mut ListKVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        pkt: &ReceivedPkt<D>,
+        builder: &mut Builder<T>,
+    ) -> Result<()>
+    where
+        T: Allocator,
+    {
+        let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+        let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+        let message_reader = Reader::new(segment_array, ReaderOptions::default());
+        let deletem_request = message_reader
+            .get_root::<kv_capnp::delete_m_req::Reader>()
+            .wrap_err("Failed to deserialize DeleteMReq.")?;
+        let mut status = ResponseStatus::Ok;
+        for key_res in deletem_request.get_keys()?.iter() {
+            let key = key_res?;
+            if !delete_from_stores(key, kv_server, list_kv_server, linked_list_kv_server) {
+                tracing::debug!(key, "Cannot find key to delete in KV store");
+                status = ResponseStatus::NotFound;
+            }
+        }
+
+        let mut response = builder.init_root::<kv_capnp::put_resp::Builder>();
+        response.set_id(deletem_request.get_id());
+        response.set_status(status.as_u32());
+        Ok(())
+    }
+
+    fn handle_cas<T>(
+        &self,
+        kv_server: &mut KVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        mempool_ids: &mut Vec<MempoolID>,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        builder: &mut Builder<T>,
+    ) -> Result<()>
+    where
+        T: Allocator,
+    {
+        let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+        let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+        let message_reader = Reader::new(segment_array, ReaderOptions::default());
+        let cas_request = message_reader
+            .get_root::<kv_capnp::cas_req::Reader>()
+            .wrap_err("Failed to deserialize CasReq.")?;
+        let key = cas_request.get_key()?;
+        let value = cas_request.get_val()?;
+        let expected_version = cas_request.get_version();
+        let (status, version) = if self.use_linked_list() {
+            linked_list_kv_server.compare_and_swap(
+                key,
+                expected_version,
+                value,
+                datapath,
+                mempool_ids,
+            )?
+        } else {
+            kv_server.compare_and_swap(key, expected_version, value, datapath, mempool_ids)?
+        };
+
+        let mut response = builder.init_root::<kv_capnp::cas_resp::Builder>();
+        response.set_id(cas_request.get_id());
+        response.set_version(version);
+        response.set_status(status.as_u32());
+        Ok(())
+    }
+
+    fn handle_scan<T>(
+        &self,
+        kv_server: &KVServer<D>,
+        pkt: &ReceivedPkt<D>,
+        builder: &mut Builder<T>,
+    ) -> Result<()>
+    where
+        T: Allocator,
+    {
+        let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+        let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+        let message_reader = Reader::new(segment_array, ReaderOptions::default());
+        let scan_request = message_reader
+            .get_root::<kv_capnp::scan_req::Reader>()
+            .wrap_err("Failed to deserialize ScanReq.")?;
+        let values = match self.use_linked_list() {
+            true => None,
+            false => kv_server.scan(
+                scan_request.get_start_key()?,
+                scan_end_key(scan_request.get_end_key()?),
+                scan_request.get_count() as usize,
+            ),
+        };
+
+        let mut response = builder.init_root::<kv_capnp::get_m_resp::Builder>();
+        response.set_id(scan_request.get_id());
+        match values {
+            Some(values) => {
+                let mut list = response.reborrow().init_vals(values.len() as u32);
+                for (i, value) in values.iter().enumerate() {
+                    list.set(i as u32, value.as_ref());
+                }
+            }
+            None => {
+                tracing::debug!("Received scan, but the kv store keeps no ordered index");
+                response.set_status(ResponseStatus::BadRequest.as_u32());
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_incr<T>(
+        &self,
+        kv_server: &mut KVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        mempool_ids: &mut Vec<MempoolID>,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        builder: &mut Builder<T>,
+        negate: bool,
+    ) -> Result<()>
+    where
+        T: Allocator,
+    {
+        let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+        let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+        let message_reader = Reader::new(segment_array, ReaderOptions::default());
+        let incr_request = message_reader
+            .get_root::<kv_capnp::incr_req::Reader>()
+            .wrap_err("Failed to deserialize IncrReq.")?;
+        let key = incr_request.get_key()?;
+        let (status, value) = match signed_delta(incr_request.get_delta(), negate) {
+            Some(delta) => match self.use_linked_list() {
+                true => linked_list_kv_server.increment(key, delta, datapath, mempool_ids)?,
+                false => kv_server.increment(key, delta, datapath, mempool_ids)?,
+            },
+            None => (ResponseStatus::BadRequest, 0),
+        };
+
+        let mut response = builder.init_root::<kv_capnp::incr_resp::Builder>();
+        response.set_id(incr_request.get_id());
+        response.set_val(value);
+        response.set_status(status.as_u32());
+        Ok(())
+    }
 }
 
 pub struct CapnprotoKVServer<D>
@@ -386,6 +600,8 @@ where
     mempool_ids: Vec<MempoolID>,
     serializer: CapnprotoSerializer<D>,
     push_buf_type: PushBufType,
+    shard_router: Option<ShardRouter>,
+    replicator: Option<Replicator<D>>,
     arena: bumpalo::Bump,
 }
 
@@ -411,6 +627,8 @@ where
             linked_list_kv_server: linked_list_kv,
             mempool_ids: mempool_ids,
             push_buf_type: push_buf_type,
+            shard_router: None,
+            replicator: None,
             serializer: CapnprotoSerializer::new(use_linked_list),
             arena: bumpalo::Bump::with_capacity(
                 ArenaOrderedSga::arena_size(
@@ -421,223 +639,334 @@ where
             ),
         })
     }
-}
 
-impl<D> ServerSM for CapnprotoKVServer<D>
-where
-    D: Datapath,
-{
-    type Datapath = D;
-    #[inline]
-    fn push_buf_type(&self) -> PushBufType {
-        self.push_buf_type
+    /// Makes this server one shard of a multi-core server.
+    pub fn set_shard_router(&mut self, router: ShardRouter) {
+        self.shard_router = Some(router);
     }
 
-    #[inline]
-    fn process_requests_single_buf(
+    /// Makes this server a replica, following the given replication config.
+    pub fn set_replicator(&mut self, replicator: Replicator<D>) {
+        self.replicator = Some(replicator);
+    }
+
+    /// Handles one request, building its response in `builder`.
+    fn build_response(
         &mut self,
-        sga: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
-        datapath: &mut Self::Datapath,
+        message_type: MsgType,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        builder: &mut Builder<HeapAllocator>,
     ) -> Result<()> {
-        let pkts_len = sga.len();
-        for (i, pkt) in sga.into_iter().enumerate() {
-            let mut builder = Builder::new_default();
-            let message_type = MsgType::from_packet(&pkt)?;
-            match message_type {
-                MsgType::Get => {
-                    self.serializer.handle_get(
-                        &self.kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                        &mut builder,
-                    )?;
-                }
-                MsgType::GetFromList => {
-                    self.serializer.handle_get_from_list(
-                        &self.list_kv_server,
-                        &pkt,
-                        &mut builder,
-                    )?;
-                }
-                MsgType::GetM(_size) => {
-                    self.serializer.handle_getm(
-                        &self.kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                        &mut builder,
-                    )?;
-                }
-                MsgType::GetList(_size) => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle getlist capnproto");
-                    self.serializer.handle_getlist(
-                        &self.list_kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                        &mut builder,
-                    )?;
+        match message_type {
+            MsgType::Get => {
+                self.serializer.handle_get(
+                    &self.kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    builder,
+                )?;
+            }
+            MsgType::GetFromList => {
+                self.serializer
+                    .handle_get_from_list(&self.list_kv_server, pkt, builder)?;
+            }
+            MsgType::GetM(_size) => {
+                self.serializer.handle_getm(
+                    &self.kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    builder,
+                )?;
+            }
+            MsgType::GetList(_size) => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle getlist capnproto");
+                self.serializer.handle_getlist(
+                    &self.list_kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    builder,
+                )?;
+            }
+            MsgType::Put => {
+                self.serializer.handle_put(
+                    &mut self.kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    builder,
+                )?;
+            }
+            MsgType::PutM(_size) => {
+                self.serializer.handle_putm(
+                    &mut self.kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    builder,
+                )?;
+            }
+            MsgType::PutList(_size) => {
+                self.serializer.handle_putlist(
+                    &mut self.list_kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    builder,
+                )?;
+            }
+            MsgType::AddUser => {
+                let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+                let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+                let message_reader = Reader::new(segment_array, ReaderOptions::default());
+                let add_user_request = message_reader
+                    .get_root::<kv_capnp::add_user::Reader>()
+                    .wrap_err("Failed to deserialize AddUser.")?;
+                let keys = add_user_request.get_keys()?;
+                let values = add_user_request.get_vals()?;
+
+                let mut response = builder.init_root::<kv_capnp::add_user_response::Builder>();
+                let first_value = match self.kv_server.get(keys.get(0)?) {
+                    Some(v) => v,
+                    None => {
+                        bail!("Cannot find value for key in KV store: {:?}", keys.get(0)?);
+                    }
+                };
+                response.set_first_val(first_value.as_ref());
+                for (key_res, val_res) in keys.iter().zip(values.iter()) {
+                    let key = key_res?;
+                    let val = val_res?;
+                    self.kv_server
+                        .insert_with_copies(key, val, datapath, &mut self.mempool_ids)?;
                 }
-                MsgType::Put => {
-                    self.serializer.handle_put(
-                        &mut self.kv_server,
-                        &mut self.linked_list_kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
+            }
+            MsgType::FollowUnfollow => {
+                let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+                let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+                let message_reader = Reader::new(segment_array, ReaderOptions::default());
+                let follow_unfollow_request = message_reader
+                    .get_root::<kv_capnp::follow_unfollow::Reader>()
+                    .wrap_err("Failed to deserialize FollowUnfollow.")?;
+                let keys = follow_unfollow_request.get_keys()?;
+                let values = follow_unfollow_request.get_vals()?;
+
+                let response = builder.init_root::<kv_capnp::follow_unfollow_response::Builder>();
+                let mut list = response.init_original_vals(2);
+
+                for (i, (key_res, new_val_res)) in
+                    keys.iter().zip(values.iter()).enumerate().take(2)
+                {
+                    let key = key_res?;
+                    let new_val = new_val_res?;
+                    let old_val = match self.kv_server.get(key) {
+                        Some(v) => v,
+                        None => {
+                            bail!("Cannot find value for key in KV store: {:?}", keys.get(0)?);
+                        }
+                    };
+                    list.set(i as u32, old_val.as_ref());
+                    self.kv_server.insert_with_copies(
+                        key,
+                        new_val,
                         datapath,
-                        &mut builder,
+                        &mut self.mempool_ids,
                     )?;
                 }
-                MsgType::PutM(_size) => {
-                    self.serializer.handle_putm(
-                        &mut self.kv_server,
-                        &mut self.linked_list_kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
+            }
+            MsgType::PostTweet => {
+                let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+                let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+                let message_reader = Reader::new(segment_array, ReaderOptions::default());
+                let post_tweet_request = message_reader
+                    .get_root::<kv_capnp::post_tweet::Reader>()
+                    .wrap_err("Failed to deserialize Post Tweet.")?;
+                let keys = post_tweet_request.get_keys()?;
+                let values = post_tweet_request.get_vals()?;
+
+                let response = builder.init_root::<kv_capnp::post_tweet_response::Builder>();
+                let mut list = response.init_vals(3);
+
+                for (i, (key_res, new_val_res)) in
+                    keys.iter().zip(values.iter()).enumerate().take(3)
+                {
+                    let key = key_res?;
+                    let new_val = new_val_res?;
+                    let old_val = match self.kv_server.get(key) {
+                        Some(v) => v,
+                        None => {
+                            bail!("Cannot find value for key in KV store: {:?}", keys.get(0)?);
+                        }
+                    };
+                    list.set(i as u32, old_val.as_ref());
+                    self.kv_server.insert_with_copies(
+                        key,
+                        new_val,
                         datapath,
-                        &mut builder,
+                        &mut self.mempool_ids,
                     )?;
                 }
-                MsgType::PutList(_size) => {
-                    self.serializer.handle_putlist(
-                        &mut self.list_kv_server,
-                        &mut self.linked_list_kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
+                for (key_res, new_val_res) in keys.iter().zip(values.iter()).skip(3).take(2) {
+                    let key = key_res?;
+                    let new_val = new_val_res?;
+                    self.kv_server.insert_with_copies(
+                        key,
+                        new_val,
                         datapath,
-                        &mut builder,
+                        &mut self.mempool_ids,
                     )?;
                 }
-                MsgType::AddUser => {
-                    let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
-                    let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
-                    let message_reader = Reader::new(segment_array, ReaderOptions::default());
-                    let add_user_request = message_reader
-                        .get_root::<kv_capnp::add_user::Reader>()
-                        .wrap_err("Failed to deserialize AddUser.")?;
-                    let keys = add_user_request.get_keys()?;
-                    let values = add_user_request.get_vals()?;
-
-                    let mut response = builder.init_root::<kv_capnp::add_user_response::Builder>();
-                    let first_value = match self.kv_server.get(keys.get(0)?) {
+            }
+            MsgType::GetTimeline(_) => {
+                let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+                let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+                let message_reader = Reader::new(segment_array, ReaderOptions::default());
+                let get_timeline_request = message_reader
+                    .get_root::<kv_capnp::get_timeline::Reader>()
+                    .wrap_err("Failed to deserialize Get Timeline.")?;
+                let keys = get_timeline_request.get_keys()?;
+
+                let response = builder.init_root::<kv_capnp::get_timeline_response::Builder>();
+                let mut list = response.init_vals(keys.len());
+
+                for (i, key_res) in keys.iter().enumerate() {
+                    let key = key_res?;
+                    let old_val = match self.kv_server.get(key) {
                         Some(v) => v,
                         None => {
                             bail!("Cannot find value for key in KV store: {:?}", keys.get(0)?);
                         }
                     };
-                    response.set_first_val(first_value.as_ref());
-                    for (key_res, val_res) in keys.iter().zip(values.iter()) {
-                        let key = key_res?;
-                        let val = val_res?;
-                        self.kv_server.insert_with_copies(
-                            key,
-                            val,
-                            datapath,
-                            &mut self.mempool_ids,
-                        )?;
-                    }
-                }
-                MsgType::FollowUnfollow => {
-                    let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
-                    let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
-                    let message_reader = Reader::new(segment_array, ReaderOptions::default());
-                    let follow_unfollow_request = message_reader
-                        .get_root::<kv_capnp::follow_unfollow::Reader>()
-                        .wrap_err("Failed to deserialize FollowUnfollow.")?;
-                    let keys = follow_unfollow_request.get_keys()?;
-                    let values = follow_unfollow_request.get_vals()?;
-
-                    let response =
-                        builder.init_root::<kv_capnp::follow_unfollow_response::Builder>();
-                    let mut list = response.init_original_vals(2);
-
-                    for (i, (key_res, new_val_res)) in
-                        keys.iter().zip(values.iter()).enumerate().take(2)
-                    {
-                        let key = key_res?;
-                        let new_val = new_val_res?;
-                        let old_val = match self.kv_server.get(key) {
-                            Some(v) => v,
-                            None => {
-                                bail!("Cannot find value for key in KV store: {:?}", keys.get(0)?);
-                            }
-                        };
-                        list.set(i as u32, old_val.as_ref());
-                        self.kv_server.insert_with_copies(
-                            key,
-                            new_val,
-                            datapath,
-                            &mut self.mempool_ids,
-                        )?;
-                    }
+                    list.set(i as u32, old_val.as_ref());
                 }
-                MsgType::PostTweet => {
-                    let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
-                    let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
-                    let message_reader = Reader::new(segment_array, ReaderOptions::default());
-                    let post_tweet_request = message_reader
-                        .get_root::<kv_capnp::post_tweet::Reader>()
-                        .wrap_err("Failed to deserialize Post Tweet.")?;
-                    let keys = post_tweet_request.get_keys()?;
-                    let values = post_tweet_request.get_vals()?;
-
-                    let response = builder.init_root::<kv_capnp::post_tweet_response::Builder>();
-                    let mut list = response.init_vals(3);
-
-                    for (i, (key_res, new_val_res)) in
-                        keys.iter().zip(values.iter()).enumerate().take(3)
+            }
+            MsgType::Delete => {
+                self.serializer.handle_delete(
+                    &mut self.kv_server,
+                    &mut self.list_kv_server,
+                    &mut self.linked_list_kv_server,
+                    pkt,
+                    builder,
+                )?;
+            }
+            MsgType::DeleteM(_size) => {
+                self.serializer.handle_deletem(
+                    &mut self.kv_server,
+                    &mut self.list_kv_server,
+                    &mut self.linked_list_kv_server,
+                    pkt,
+                    builder,
+                )?;
+            }
+            MsgType::CompareAndSwap => {
+                self.serializer.handle_cas(
+                    &mut self.kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    builder,
+                )?;
+            }
+            MsgType::Increment | MsgType::Decrement => {
+                self.serializer.handle_incr(
+                    &mut self.kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    builder,
+                    message_type == MsgType::Decrement,
+                )?;
+            }
+            MsgType::Scan => {
+                self.serializer.handle_scan(&self.kv_server, pkt, builder)?;
+            }
+            _ => {
+                bail!(
+                    "Capnproto server does not support {:?} requests",
+                    message_type
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<D> ServerSM for CapnprotoKVServer<D>
+where
+    D: Datapath,
+{
+    type Datapath = D;
+    #[inline]
+    fn push_buf_type(&self) -> PushBufType {
+        self.push_buf_type
+    }
+
+    fn steer_packets(
+        &mut self,
+        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        datapath: &mut Self::Datapath,
+    ) -> Result<Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>> {
+        let pkts = match &mut self.shard_router {
+            Some(router) => router.steer(pkts, datapath)?,
+            None => pkts,
+        };
+        match &mut self.replicator {
+            Some(replicator) => replicator.steer(pkts, datapath),
+            None => Ok(pkts),
+        }
+    }
+
+    fn log_stats(&self, datapath: &Self::Datapath) {
+        datapath.transmit_stats().dump("Server transmit stats");
+        datapath
+            .allocator_memory_stats()
+            .dump("Server allocator memory stats");
+        combined_cache_stats(
+            &self.kv_server,
+            &self.list_kv_server,
+            &self.linked_list_kv_server,
+        )
+        .dump("Server cache stats");
+        combined_storage_stats(
+            &self.kv_server,
+            &self.list_kv_server,
+            &self.linked_list_kv_server,
+        )
+        .dump("Server storage stats");
+        if let Some(router) = &self.shard_router {
+            router.stats().dump("Server shard stats");
+        }
+        if let Some(replicator) = &self.replicator {
+            replicator.stats().dump("Server replication stats");
+        }
+    }
+
+    #[inline]
+    fn process_requests_single_buf(
+        &mut self,
+        sga: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        datapath: &mut Self::Datapath,
+    ) -> Result<()> {
+        let pkts_len = sga.len();
+        for (i, pkt) in sga.into_iter().enumerate() {
+            let mut builder = Builder::new_default();
+            match MsgType::from_packet(&pkt) {
+                Ok(message_type) => {
+                    if let Err(e) = self.build_response(message_type, &pkt, datapath, &mut builder)
                     {
-                        let key = key_res?;
-                        let new_val = new_val_res?;
-                        let old_val = match self.kv_server.get(key) {
-                            Some(v) => v,
-                            None => {
-                                bail!("Cannot find value for key in KV store: {:?}", keys.get(0)?);
-                            }
-                        };
-                        list.set(i as u32, old_val.as_ref());
-                        self.kv_server.insert_with_copies(
-                            key,
-                            new_val,
-                            datapath,
-                            &mut self.mempool_ids,
-                        )?;
-                    }
-                    for (key_res, new_val_res) in keys.iter().zip(values.iter()).skip(3).take(2) {
-                        let key = key_res?;
-                        let new_val = new_val_res?;
-                        self.kv_server.insert_with_copies(
-                            key,
-                            new_val,
-                            datapath,
-                            &mut self.mempool_ids,
-                        )?;
+                        let status = failed_request_status(&pkt, Some(message_type), &e);
+                        builder = Builder::new_default();
+                        build_error_response(&mut builder, Some(message_type), status);
                     }
                 }
-                MsgType::GetTimeline(_) => {
-                    let segment_array_vec = read_context(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
-                    let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
-                    let message_reader = Reader::new(segment_array, ReaderOptions::default());
-                    let get_timeline_request = message_reader
-                        .get_root::<kv_capnp::get_timeline::Reader>()
-                        .wrap_err("Failed to deserialize Get Timeline.")?;
-                    let keys = get_timeline_request.get_keys()?;
-
-                    let response = builder.init_root::<kv_capnp::get_timeline_response::Builder>();
-                    let mut list = response.init_vals(keys.len());
-
-                    for (i, key_res) in keys.iter().enumerate() {
-                        let key = key_res?;
-                        let old_val = match self.kv_server.get(key) {
-                            Some(v) => v,
-                            None => {
-                                bail!("Cannot find value for key in KV store: {:?}", keys.get(0)?);
-                            }
-                        };
-                        list.set(i as u32, old_val.as_ref());
-                    }
-                }
-                _ => {
-                    unimplemented!();
+                Err(e) => {
+                    let status = failed_request_status(&pkt, None, &e);
+                    build_error_response(&mut builder, None, status);
                 }
             }
             {
@@ -671,6 +1000,64 @@ where
     }
 }
 
+/// Builds an otherwise empty response of the type the client expects for `msg_type`, carrying
+/// `status`, for a request the server could not handle. Requests of unknown type get a put
+/// response.
+fn build_error_response<T>(
+    builder: &mut Builder<T>,
+    msg_type: Option<MsgType>,
+    status: ResponseStatus,
+) where
+    T: Allocator,
+{
+    let status = status.as_u32();
+    match msg_type {
+        Some(MsgType::Get) | Some(MsgType::GetFromList) => {
+            builder
+                .init_root::<kv_capnp::get_resp::Builder>()
+                .set_status(status);
+        }
+        Some(MsgType::GetM(_)) | Some(MsgType::Scan) => {
+            builder
+                .init_root::<kv_capnp::get_m_resp::Builder>()
+                .set_status(status);
+        }
+        Some(MsgType::GetList(_)) => {
+            builder
+                .init_root::<kv_capnp::get_list_resp::Builder>()
+                .set_status(status);
+        }
+        Some(MsgType::CompareAndSwap) => {
+            builder
+                .init_root::<kv_capnp::cas_resp::Builder>()
+                .set_status(status);
+        }
+        Some(MsgType::Increment) | Some(MsgType::Decrement) => {
+            builder
+                .init_root::<kv_capnp::incr_resp::Builder>()
+                .set_status(status);
+        }
+        // retwis responses carry no status; an empty one holds no values
+        Some(MsgType::AddUser) => {
+            builder.init_root::<kv_capnp::add_user_response::Builder>();
+        }
+        Some(MsgType::FollowUnfollow) => {
+            builder.init_root::<kv_capnp::follow_unfollow_response::Builder>();
+        }
+        Some(MsgType::PostTweet) => {
+            builder.init_root::<kv_capnp::post_tweet_response::Builder>();
+        }
+        Some(MsgType::GetTimeline(_)) => {
+            builder.init_root::<kv_capnp::get_timeline_response::Builder>();
+        }
+        _ => {
+            builder
+                .init_root::<kv_capnp::put_resp::Builder>()
+                .set_status(status);
+        }
+    }
+}
+
 fn fill_in_context_without_arena<T>(builder: &Builder<T>, framing: &mut [u8]) -> Result<usize>
 where
     T: Allocator,
@@ -749,6 +1136,48 @@ where
         }
     }
 
+    fn deserialize_response_status(&self, msg_type: MsgType, buf: &[u8]) -> Result<ResponseStatus> {
+        let segment_array_vec = read_context(buf)?;
+        let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+        let message_reader = Reader::new(segment_array, ReaderOptions::default());
+        let status = match msg_type {
+            MsgType::Get | MsgType::GetFromList => message_reader
+                .get_root::<kv_capnp::get_resp::Reader>()
+                .wrap_err("Failed to deserialize GetResp.")?
+                .get_status(),
+            MsgType::GetM(_) | MsgType::Scan => message_reader
+                .get_root::<kv_capnp::get_m_resp::Reader>()
+                .wrap_err("Failed to deserialize GetMResp.")?
+                .get_status(),
+            MsgType::GetList(_) => message_reader
+                .get_root::<kv_capnp::get_list_resp::Reader>()
+                .wrap_err("Failed to deserialize GetListResp.")?
+                .get_status(),
+            MsgType::Put
+            | MsgType::PutM(_)
+            | MsgType::PutList(_)
+            | MsgType::AppendToList(_)
+            | MsgType::Delete
+            | MsgType::DeleteM(_) => message_reader
+                .get_root::<kv_capnp::put_resp::Reader>()
+                .wrap_err("Failed to deserialize PutResp.")?
+                .get_status(),
+            MsgType::CompareAndSwap => message_reader
+                .get_root::<kv_capnp::cas_resp::Reader>()
+                .wrap_err("Failed to deserialize CasResp.")?
+                .get_status(),
+            MsgType::Increment | MsgType::Decrement => message_reader
+                .get_root::<kv_capnp::incr_resp::Reader>()
+                .wrap_err("Failed to deserialize IncrResp.")?
+                .get_status(),
+            // retwis responses do not carry a status
+            _ => {
+                return Ok(ResponseStatus::Ok);
+            }
+        };
+        ResponseStatus::from_u32(status)
+    }
+
     fn deserialize_get_response(&self, buf: &[u8]) -> Result<Vec<u8>> {
         let segment_array_vec = read_context(buf)?;
         let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
@@ -804,6 +1233,26 @@ where
         }
     }
 
+    fn deserialize_cas_response(&self, buf: &[u8]) -> Result<u64> {
+        let segment_array_vec = read_context(buf)?;
+        let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+        let message_reader = Reader::new(segment_array, ReaderOptions::default());
+        let cas_resp = message_reader
+            .get_root::<kv_capnp::cas_resp::Reader>()
+            .wrap_err("Failed to deserialize CasResp.")?;
+        Ok(cas_resp.get_version())
+    }
+
+    fn deserialize_incr_response(&self, buf: &[u8]) -> Result<i64> {
+        let segment_array_vec = read_context(buf)?;
+        let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
+        let message_reader = Reader::new(segment_array, ReaderOptions::default());
+        let incr_resp = message_reader
+            .get_root::<kv_capnp::incr_resp::Reader>()
+            .wrap_err("Failed to deserialize IncrResp.")?;
+        Ok(incr_resp.get_val())
+    }
+
     fn check_add_user_num_values(&self, buf: &[u8]) -> Result<usize> {
         let segment_array_vec = read_context(buf)?;
         let segment_array = SegmentArray::new(&segment_array_vec.as_slice());
@@ -976,6 +1425,94 @@ where
         return Ok(full_size);
     }
 
+    fn serialize_delete(&self, buf: &mut [u8], key: &str, _datapath: &D) -> Result<usize> {
+        let mut builder = Builder::new_default();
+        let mut delete_req = builder.init_root::<kv_capnp::delete_req::Builder>();
+        delete_req.set_key(&key);
+        let framing_size = fill_in_context_without_arena(&builder, buf)?;
+        let full_size = copy_into_buf(buf, framing_size, &builder)?;
+        return Ok(full_size);
+    }
+
+    fn serialize_deletem(
+        &self,
+        buf: &mut [u8],
+        keys: &Vec<String>,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut builder = Builder::new_default();
+        let deletem_req = builder.init_root::<kv_capnp::delete_m_req::Builder>();
+        let mut keys_list = deletem_req.init_keys(keys.len() as _);
+        for (i, key) in keys.iter().enumerate() {
+            keys_list.set(i as u32, key);
+        }
+        let framing_size = fill_in_context_without_arena(&builder, buf)?;
+        let full_size = copy_into_buf(buf, framing_size, &builder)?;
+        return Ok(full_size);
+    }
+
+    fn serialize_cas(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        version: u64,
+        value: &str,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut builder = Builder::new_default();
+        let mut cas_req = builder.init_root::<kv_capnp::cas_req::Builder>();
+        cas_req.set_key(&key);
+        cas_req.set_version(version);
+        cas_req.set_val(&value.as_bytes());
+        let framing_size = fill_in_context_without_arena(&builder, buf)?;
+        let full_size = copy_into_buf(buf, framing_size, &builder)?;
+        return Ok(full_size);
+    }
+
+    fn serialize_increment(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut builder = Builder::new_default();
+        let mut incr_req = builder.init_root::<kv_capnp::incr_req::Builder>();
+        incr_req.set_key(&key);
+        incr_req.set_delta(delta);
+        let framing_size = fill_in_context_without_arena(&builder, buf)?;
+        let full_size = copy_into_buf(buf, framing_size, &builder)?;
+        return Ok(full_size);
+    }
+
+    fn serialize_decrement(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        datapath: &D,
+    ) -> Result<usize> {
+        self.serialize_increment(buf, key, delta, datapath)
+    }
+
+    fn serialize_scan(
+        &self,
+        buf: &mut [u8],
+        start_key: &str,
+        end_key: Option<&str>,
+        count: u64,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut builder = Builder::new_default();
+        let mut scan_req = builder.init_root::<kv_capnp::scan_req::Builder>();
+        scan_req.set_start_key(&start_key);
+        scan_req.set_end_key(&end_key.unwrap_or_default());
+        scan_req.set_count(count);
+        let framing_size = fill_in_context_without_arena(&builder, buf)?;
+        let full_size = copy_into_buf(buf, framing_size, &builder)?;
+        return Ok(full_size);
+    }
+
     fn serialize_get_list(&self, buf: &mut [u8], key: &str, _datapath: &D) -> Result<usize> {
         let mut builder = Builder::new_default();
         let mut getlist_req = builder.init_root::<kv_capnp::get_list_req::Builder>();
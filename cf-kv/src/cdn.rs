@@ -16,10 +16,11 @@
 //!     ]
 //! }
 use super::{
-    allocate_datapath_buffer, ClientSerializer, KVServer, LinkedListKVServer, ListKVServer,
-    MsgType, RequestGenerator, ServerLoadGenerator, REQ_TYPE_SIZE,
+    allocate_datapath_buffer, cache::CacheConfig, shard::ShardSpec, ClientSerializer, KVServer,
+    LinkedListKVServer, ListKVServer, MsgType, RequestGenerator, ResponseStatus,
+    ServerLoadGenerator, REQ_TYPE_SIZE,
 };
-use color_eyre::eyre::{bail, Result};
+use color_eyre::eyre::{bail, ensure, Result};
 use cornflakes_libos::{allocator::MempoolID, datapath::Datapath, MsgID};
 use hashbrown::HashMap;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
@@ -107,20 +108,33 @@ impl CdnServerLine {
 pub struct CdnServerLoader {
     key_length: usize,
     max_num_lines: usize,
+    cache_config: CacheConfig,
+    shard: Option<ShardSpec>,
 }
 
 impl CdnServerLoader {
-    pub fn new(key_length: usize, max_num_lines: usize) -> Self {
+    pub fn new(key_length: usize, max_num_lines: usize, cache_config: CacheConfig) -> Self {
         CdnServerLoader {
             key_length,
             max_num_lines,
+            cache_config,
+            shard: None,
         }
     }
+
+    /// Only load objects the given shard owns.
+    pub fn set_shard(&mut self, shard: ShardSpec) {
+        self.shard = Some(shard);
+    }
 }
 
 impl ServerLoadGenerator for CdnServerLoader {
     type RequestLine = CdnServerLine;
 
+    fn cache_config(&self) -> CacheConfig {
+        self.cache_config
+    }
+
     fn read_request(&self, line: &str) -> Result<Self::RequestLine> {
         // Tragen generates lines in the form <timestamp>,<obj_id>,<obj_size_kb>
         let line = line.split(',').collect::<Vec<_>>();
@@ -205,6 +219,11 @@ impl ServerLoadGenerator for CdnServerLoader {
         if list_kv_server.contains_key(key.as_str()) {
             return Ok(());
         }
+        if let Some(shard) = &self.shard {
+            if !shard.owns(key.as_str()) {
+                return Ok(());
+            }
+        }
         for i in 0..request.num_keys() {
             let value_size_kb = request.get_value_size_kb(i);
             let char = thread_rng().sample(&Alphanumeric) as char;
@@ -213,7 +232,7 @@ impl ServerLoadGenerator for CdnServerLoader {
             let _ = datapath_buffer.write(value.as_bytes())?;
             buffer_vec.push(datapath_buffer);
         }
-        list_kv_server.insert(key.to_string(), buffer_vec);
+        list_kv_server.insert(key.to_string(), buffer_vec)?;
         Ok(())
     }
 }
@@ -243,6 +262,9 @@ impl CdnClient {
         let total_thread_clients = total_num_threads * total_num_clients;
         our_thread_client == (obj_id % total_thread_clients)
     }
+
+    /// With a `shard`, only requests objects that shard owns, split among the client threads
+    /// pinned to it (those with `thread_id % num_shards == shard_id`).
     pub fn new(
         request_file: &str,
         num_total_requests: usize,
@@ -252,7 +274,20 @@ impl CdnClient {
         total_num_threads: usize,
         total_num_clients: usize,
         max_num_lines: usize,
+        shard: Option<ShardSpec>,
     ) -> Result<Self> {
+        if let Some(shard) = &shard {
+            ensure!(
+                thread_id % shard.num_shards == shard.shard_id,
+                "Thread {} cannot send requests for shard {} of {}",
+                thread_id,
+                shard.shard_id,
+                shard.num_shards
+            );
+        }
+        let sender_index = shard.map(|shard| {
+            shard.sender_index(client_id, thread_id, total_num_clients, total_num_threads)
+        });
         let mut request_ids = Vec::with_capacity(num_total_requests);
         let mut all_keys: HashMap<KeyMetadata, String> = HashMap::default();
         let file = std::fs::File::open(request_file)?;
@@ -266,13 +301,20 @@ impl CdnClient {
             let obj_id = line[1].parse::<usize>()?;
             let value_size_kb = line[2].parse::<usize>()?;
             // hash object id to see if we are responsible for this
-            if !CdnClient::is_responsible_for(
-                obj_id,
-                thread_id,
-                client_id,
-                total_num_threads,
-                total_num_clients,
-            ) {
+            let responsible = match (&shard, sender_index) {
+                (Some(shard), Some((our_index, num_senders))) => {
+                    obj_id % num_senders == our_index
+                        && shard.owns(get_key(obj_id, key_length).as_str())
+                }
+                _ => CdnClient::is_responsible_for(
+                    obj_id,
+                    thread_id,
+                    client_id,
+                    total_num_threads,
+                    total_num_clients,
+                ),
+            };
+            if !responsible {
                 continue;
             }
             let line = CdnServerLine::new(obj_id, value_size_kb, key_length);
@@ -424,6 +466,17 @@ impl RequestGenerator for CdnClient {
         D: Datapath,
     {
         if cfg!(debug_assertions) {
+            let status =
+                serializer.deserialize_response_status(self.message_type(request)?, buf)?;
+            if status == ResponseStatus::NotFound {
+                // a bounded server may have evicted or expired the object
+                tracing::debug!("Cache miss for {:?}", request);
+                return Ok(true);
+            }
+            if status != ResponseStatus::Ok {
+                tracing::warn!(status =? status, "Received error response for {:?}", request);
+                return Ok(false);
+            }
             let val = serializer.deserialize_get_response(buf)?;
             if val.len() != request.0.val_size {
                 return Ok(false);
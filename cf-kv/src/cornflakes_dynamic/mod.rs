@@ -53,8 +53,10 @@ use cornflakes_libos::{
 use kv_serializer::*;
 
 use super::{
-    ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType, ServerLoadGenerator,
-    REQ_TYPE_SIZE,
+    combined_cache_stats, combined_storage_stats, delete_from_stores, failed_request_status,
+    replication::Replicator, scan_end_key, shard::ShardRouter, signed_delta, storage::StoredValue,
+    ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType, ResponseStatus,
+    ServerLoadGenerator, REQ_TYPE_SIZE,
 };
 use color_eyre::eyre::{bail, ensure, Result};
 use std::marker::PhantomData;
@@ -91,6 +93,21 @@ where
         self.with_copies
     }
 
+    /// Values a scan asks for, or None if the kv store keeps no ordered index (stores of linked
+    /// lists never do).
+    fn scan<'kv>(
+        &self,
+        kv_server: &'kv KVServer<D>,
+        start_key: &str,
+        end_key: &str,
+        count: u64,
+    ) -> Option<Vec<&'kv StoredValue<D>>> {
+        match self.use_linked_list() {
+            true => None,
+            false => kv_server.scan(start_key, scan_end_key(end_key), count as usize),
+        }
+    }
+
     fn handle_put_serialize_and_send<'kv, 'arena>(
         &self,
         msg_id: MsgID,
@@ -156,33 +173,12 @@ where
             //#[cfg(feature = "profiler")]
             //demikernel::timer!("Get value from kv");
             match self.use_linked_list() {
-                true => match linked_list_kv_server.get(get_req.get_key().to_str()?) {
-                    Some(v) => v.as_ref().get_buffer(),
-                    None => {
-                        bail!(
-                            "Could not find value for key: {:?}",
-                            get_req.get_key().to_str()
-                        );
-                    }
-                },
-                false => match kv_server.get(get_req.get_key().to_str()?) {
-                    Some(v) => v,
-                    None => {
-                        bail!(
-                            "Could not find value for key: {:?}",
-                            get_req.get_key().to_str()
-                        );
-                    }
-                },
+                true => linked_list_kv_server
+                    .get(get_req.get_key().to_str()?)
+                    .map(|v| v.as_ref().get_buffer()),
+                false => kv_server.get(get_req.get_key().to_str()?),
             }
         };
-
-        tracing::debug!(
-            "For given key {:?}, found value {:?} with length {}",
-            get_req.get_key().to_str()?,
-            value.as_ref().as_ptr(),
-            value.as_ref().len()
-        );
         let mut get_resp = kv_serializer_hybrid::GetResp::new_in(arena);
         let mut copy_context = {
             //#[cfg(feature = "profiler")]
@@ -191,6 +187,28 @@ where
         };
 
         get_resp.set_id(get_req.get_id());
+        let value = match value {
+            Some(v) => v,
+            None => {
+                tracing::debug!(key =? get_req.get_key().to_str(), "Could not find value for key");
+                get_resp.set_status(ResponseStatus::NotFound.as_u32());
+                datapath.queue_cornflakes_obj(
+                    msg_id,
+                    conn_id,
+                    &mut copy_context,
+                    get_resp,
+                    end_batch,
+                )?;
+                return Ok(());
+            }
+        };
+
+        tracing::debug!(
+            "For given key {:?}, found value {:?} with length {}",
+            get_req.get_key().to_str()?,
+            value.as_ref().as_ptr(),
+            value.as_ref().len()
+        );
 
         {
             //#[cfg(feature = "profiler")]
@@ -220,25 +238,24 @@ where
     {
         let mut get_req = kv_serializer_hybrid::GetReq::new_in(arena);
         get_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+        let mut get_resp = kv_serializer_hybrid::GetResp::new_in(arena);
+        get_resp.set_id(get_req.get_id());
+
+        // initialize copy context
+        let mut copy_context = CopyContext::new(arena, datapath)?;
         let value = match self.use_linked_list() {
-            true => match linked_list_kv_server.get(get_req.get_key().to_str()?) {
-                Some(v) => v.as_ref().get_buffer(),
-                None => {
-                    bail!(
-                        "Could not find value for key: {:?}",
-                        get_req.get_key().to_str()
-                    );
-                }
-            },
-            false => match kv_server.get(get_req.get_key().to_str()?) {
-                Some(v) => v,
-                None => {
-                    bail!(
-                        "Could not find value for key: {:?}",
-                        get_req.get_key().to_str()
-                    );
-                }
-            },
+            true => linked_list_kv_server
+                .get(get_req.get_key().to_str()?)
+                .map(|v| v.as_ref().get_buffer()),
+            false => kv_server.get(get_req.get_key().to_str()?),
+        };
+        let value = match value {
+            Some(v) => v,
+            None => {
+                tracing::debug!(key =? get_req.get_key().to_str(), "Could not find value for key");
+                get_resp.set_status(ResponseStatus::NotFound.as_u32());
+                return get_resp.serialize_into_arena_datapath_sga(datapath, copy_context, arena);
+            }
         };
         tracing::debug!(
             "For given key {:?}, found value {:?} with length {}",
@@ -246,11 +263,6 @@ where
             value.as_ref().as_ptr(),
             value.as_ref().len()
         );
-        let mut get_resp = kv_serializer_hybrid::GetResp::new_in(arena);
-        get_resp.set_id(get_req.get_id());
-
-        // initialize copy context
-        let mut copy_context = CopyContext::new(arena, datapath)?;
         get_resp.set_val(dynamic_rcsga_hybrid_hdr::CFBytes::new(
             value.as_ref(),
             datapath,
@@ -321,24 +333,26 @@ where
         let mut copy_context = CopyContext::new(arena, datapath)?;
         getm_resp.init_vals(getm_req.get_keys().len(), arena);
         let vals = getm_resp.get_mut_vals();
+        let mut status = ResponseStatus::Ok;
         for key in getm_req.get_keys().iter() {
             let value = {
                 tracing::debug!("Key bytes: {:?}", key);
                 #[cfg(feature = "profiler")]
                 demikernel::timer!("got value");
                 match self.use_linked_list() {
-                    true => match linked_list_kv_server.get(key.to_str()?) {
-                        Some(v) => v.as_ref().get_buffer(),
-                        None => {
-                            bail!("Could not find value for key: {:?}", key.to_str());
-                        }
-                    },
-                    false => match kv_server.get(key.to_str()?) {
-                        Some(v) => v,
-                        None => {
-                            bail!("Could not find value for key: {:?}", key.to_str());
-                        }
-                    },
+                    true => linked_list_kv_server
+                        .get(key.to_str()?)
+                        .map(|v| v.as_ref().get_buffer()),
+                    false => kv_server.get(key.to_str()?),
+                }
+            };
+            let value = match value {
+                Some(v) => v,
+                None => {
+                    tracing::debug!(key =? key.to_str(), "Could not find value for key");
+                    status = ResponseStatus::NotFound;
+                    vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::default());
+                    continue;
                 }
             };
             tracing::debug!(
@@ -358,6 +372,7 @@ where
             }
         }
         getm_resp.set_id(getm_req.get_id());
+        getm_resp.set_status(status.as_u32());
         tracing::debug!("Finished setting all pointers");
         tracing::debug!("Sending back {:?}", getm_resp);
 
@@ -386,24 +401,26 @@ where
         getm_resp.init_vals(getm_req.get_keys().len(), arena);
         let vals = getm_resp.get_mut_vals();
         let mut copy_context = CopyContext::new(arena, datapath)?;
+        let mut status = ResponseStatus::Ok;
         for key in getm_req.get_keys().iter() {
             let value = {
                 tracing::debug!("Key bytes: {:?}", key);
                 #[cfg(feature = "profiler")]
                 demikernel::timer!("got value");
                 match self.use_linked_list() {
-                    true => match linked_list_kv_server.get(key.to_str()?) {
-                        Some(v) => v.get_buffer(),
-                        None => {
-                            bail!("Could not find value for key: {:?}", key.to_str());
-                        }
-                    },
-                    false => match kv_server.get(key.to_str()?) {
-                        Some(v) => v,
-                        None => {
-                            bail!("Could not find value for key: {:?}", key.to_str());
-                        }
-                    },
+                    true => linked_list_kv_server
+                        .get(key.to_str()?)
+                        .map(|v| v.get_buffer()),
+                    false => kv_server.get(key.to_str()?),
+                }
+            };
+            let value = match value {
+                Some(v) => v,
+                None => {
+                    tracing::debug!(key =? key.to_str(), "Could not find value for key");
+                    status = ResponseStatus::NotFound;
+                    vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::default());
+                    continue;
                 }
             };
             tracing::debug!(
@@ -423,6 +440,7 @@ where
             }
         }
         getm_resp.set_id(getm_req.get_id());
+        getm_resp.set_status(status.as_u32());
         tracing::debug!("Sending back {:?}", getm_resp);
 
         let datapath_sga = {
@@ -433,6 +451,91 @@ where
         Ok(datapath_sga)
     }
 
+    fn handle_scan_serialize_and_send<'kv, 'arena>(
+        &self,
+        msg_id: MsgID,
+        conn_id: ConnID,
+        end_batch: bool,
+        kv_server: &'kv KVServer<D>,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        arena: &'arena bumpalo::Bump,
+    ) -> Result<()>
+    where
+        'kv: 'arena,
+    {
+        let mut scan_req = kv_serializer_hybrid::ScanReq::new_in(arena);
+        scan_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
+        let mut getm_resp = kv_serializer_hybrid::GetMResp::new_in(arena);
+        getm_resp.set_id(scan_req.get_id());
+        let mut copy_context = CopyContext::new(arena, datapath)?;
+        match self.scan(
+            kv_server,
+            scan_req.get_start_key().to_str()?,
+            scan_req.get_end_key().to_str()?,
+            scan_req.get_count(),
+        ) {
+            Some(values) => {
+                getm_resp.init_vals(values.len(), arena);
+                let vals = getm_resp.get_mut_vals();
+                for value in values.iter() {
+                    vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::new(
+                        value.as_ref(),
+                        datapath,
+                        &mut copy_context,
+                    )?);
+                }
+            }
+            None => {
+                tracing::debug!("Received scan, but the kv store keeps no ordered index");
+                getm_resp.set_status(ResponseStatus::BadRequest.as_u32());
+            }
+        }
+
+        datapath.queue_cornflakes_obj(msg_id, conn_id, &mut copy_context, getm_resp, end_batch)?;
+        Ok(())
+    }
+
+    fn handle_scan<'kv, 'arena>(
+        &self,
+        kv_server: &'kv KVServer<D>,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        arena: &'arena bumpalo::Bump,
+    ) -> Result<ArenaDatapathSga<'arena, D>>
+    where
+        'kv: 'arena,
+    {
+        let mut scan_req = kv_serializer_hybrid::ScanReq::new_in(arena);
+        scan_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
+        let mut getm_resp = kv_serializer_hybrid::GetMResp::new_in(arena);
+        getm_resp.set_id(scan_req.get_id());
+        let mut copy_context = CopyContext::new(arena, datapath)?;
+        match self.scan(
+            kv_server,
+            scan_req.get_start_key().to_str()?,
+            scan_req.get_end_key().to_str()?,
+            scan_req.get_count(),
+        ) {
+            Some(values) => {
+                getm_resp.init_vals(values.len(), arena);
+                let vals = getm_resp.get_mut_vals();
+                for value in values.iter() {
+                    vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::new(
+                        value.as_ref(),
+                        datapath,
+                        &mut copy_context,
+                    )?);
+                }
+            }
+            None => {
+                tracing::debug!("Received scan, but the kv store keeps no ordered index");
+                getm_resp.set_status(ResponseStatus::BadRequest.as_u32());
+            }
+        }
+        getm_resp.serialize_into_arena_datapath_sga(datapath, copy_context, arena)
+    }
+
     fn handle_putm<'arena>(
         &self,
         kv_server: &mut KVServer<D>,
@@ -506,6 +609,21 @@ where
                 demikernel::timer!("do get on key");
                 linked_list_kv.get(getlist_req.get_key().to_str()?)
             };
+            if node_option.is_none() {
+                tracing::debug!(
+                    key =? getlist_req.get_key().to_str(),
+                    "Could not find value for key"
+                );
+                getlist_resp.set_status(ResponseStatus::NotFound.as_u32());
+                datapath.queue_cornflakes_obj(
+                    msg_id,
+                    conn_id,
+                    &mut copy_context,
+                    getlist_resp,
+                    end_batch,
+                )?;
+                return Ok(());
+            }
             // TODO: likely possible to inline this with iterating over the nodes below
             let range_len = {
                 if range_end == -1 {
@@ -565,10 +683,19 @@ where
             let value_list = match list_kv_server.get(getlist_req.get_key().to_str()?) {
                 Some(v) => v,
                 None => {
-                    bail!(
-                        "Could not find value for key: {:?}",
-                        getlist_req.get_key().to_str()
+                    tracing::debug!(
+                        key =? getlist_req.get_key().to_str(),
+                        "Could not find value for key"
                     );
+                    getlist_resp.set_status(ResponseStatus::NotFound.as_u32());
+                    datapath.queue_cornflakes_obj(
+                        msg_id,
+                        conn_id,
+                        &mut copy_context,
+                        getlist_resp,
+                        end_batch,
+                    )?;
+                    return Ok(());
                 }
             };
 
@@ -614,6 +741,18 @@ where
             let range_start = getlist_req.get_range_start();
             let range_end = getlist_req.get_range_end();
             let mut node_option = linked_list_kv_server.get(getlist_req.get_key().to_str()?);
+            if node_option.is_none() {
+                tracing::debug!(
+                    key =? getlist_req.get_key().to_str(),
+                    "Could not find value for key"
+                );
+                getlist_resp.set_status(ResponseStatus::NotFound.as_u32());
+                return getlist_resp.serialize_into_arena_datapath_sga(
+                    datapath,
+                    copy_context,
+                    arena,
+                );
+            }
 
             let range_len = {
                 if range_end == -1 {
@@ -657,9 +796,15 @@ where
             let value_list = match list_kv_server.get(getlist_req.get_key().to_str()?) {
                 Some(v) => v,
                 None => {
-                    bail!(
-                        "Could not find value for key: {:?}",
-                        getlist_req.get_key().to_str()
+                    tracing::debug!(
+                        key =? getlist_req.get_key().to_str(),
+                        "Could not find value for key"
+                    );
+                    getlist_resp.set_status(ResponseStatus::NotFound.as_u32());
+                    return getlist_resp.serialize_into_arena_datapath_sga(
+                        datapath,
+                        copy_context,
+                        arena,
                     );
                 }
             };
@@ -728,6 +873,8 @@ where
     mempool_ids: Vec<MempoolID>,
     serializer: CornflakesSerializer<D>,
     push_buf_type: PushBufType,
+    shard_router: Option<ShardRouter>,
+    replicator: Option<Replicator<D>>,
 }
 
 impl<D> CornflakesKVServer<D>
@@ -757,524 +904,801 @@ where
             linked_list_kv_server: linked_list_kv,
             mempool_ids,
             push_buf_type,
+            shard_router: None,
+            replicator: None,
             serializer,
         })
     }
-}
 
-impl<D> ServerSM for CornflakesKVServer<D>
-where
-    D: Datapath,
-{
-    type Datapath = D;
-    #[inline]
-    fn push_buf_type(&self) -> PushBufType {
-        self.push_buf_type
+    /// Makes this server one shard of a multi-core server.
+    pub fn set_shard_router(&mut self, router: ShardRouter) {
+        self.shard_router = Some(router);
     }
 
-    #[inline]
-    fn process_requests_hybrid_arena_sga(
+    /// Makes this server a replica, following the given replication config.
+    pub fn set_replicator(&mut self, replicator: Replicator<D>) {
+        self.replicator = Some(replicator);
+    }
+
+    /// Handles deletes, compare-and-swaps and increments for the push paths without handlers of
+    /// their own. Their responses carry no values, so they are built once and copied out.
+    fn process_request_without_values(
         &mut self,
-        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
-        datapath: &mut Self::Datapath,
-        arena: &mut bumpalo::Bump,
+        msg_type: MsgType,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        end_batch: bool,
     ) -> Result<()> {
-        // queue as metadata vec in the datapath
-        // for microbenchmark
-        let end = pkts.len();
-        for (i, pkt) in pkts.into_iter().enumerate() {
-            let end_batch = i == (end - 1);
-            let msg_type = MsgType::from_packet(&pkt)?;
-            tracing::debug!(
-                msg_type =? msg_type,
-                "Received packet with data {:?} ptr and length {}",
-                pkt.seg(0).as_ref().as_ptr(),
-                pkt.data_len()
-            );
-            match msg_type {
-                MsgType::Get => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle get hybrid object");
-                    let mut get_req = { kv_serializer_hybrid_arena_object::GetReq::new_in(arena) };
-                    {
-                        #[cfg(feature = "profiler")]
-                        demikernel::timer!("Deserialize pkt");
-                        get_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-                    }
-                    let mut get_resp = kv_serializer_hybrid_arena_object::GetResp::new_in(arena);
-                    get_resp.set_id(get_req.get_id());
-
-                    let value = {
-                        #[cfg(feature = "profiler")]
-                        demikernel::timer!("Get value from kv");
-                        match self.serializer.use_linked_list() {
-                            true => {
-                                match self.linked_list_kv_server.get(get_req.get_key().to_str()?) {
-                                    Some(v) => v.as_ref().get_buffer(),
-                                    None => {
-                                        bail!(
-                                            "Could not find value for key: {:?}",
-                                            get_req.get_key().to_str()
-                                        );
-                                    }
-                                }
-                            }
-                            false => match self.kv_server.get(get_req.get_key().to_str()?) {
-                                Some(v) => v,
-                                None => {
-                                    bail!(
-                                        "Could not find value for key: {:?}",
-                                        get_req.get_key().to_str()
-                                    );
-                                }
-                            },
-                        }
-                    };
-
+        let response = match msg_type {
+            MsgType::Delete => {
+                let mut delete_req = DeleteReq::<D>::new();
+                delete_req.deserialize_from_pkt(pkt, REQ_TYPE_SIZE)?;
+                let mut put_resp = PutResp::<D>::new();
+                put_resp.set_id(delete_req.get_id());
+                if !delete_from_stores(
+                    delete_req.get_key().to_str()?,
+                    &mut self.kv_server,
+                    &mut self.list_kv_server,
+                    &mut self.linked_list_kv_server,
+                ) {
                     tracing::debug!(
-                        "For given key {:?}, found value {:?} with length {}",
-                        get_req.get_key().to_str()?,
-                        value.as_ref().as_ptr(),
-                        value.as_ref().len()
+                        key =? delete_req.get_key().to_str(),
+                        "Could not find key to delete"
                     );
-
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Set value get hybrid arena");
-                    {
-                        get_resp.set_val(dynamic_object_arena_hdr::CFBytes::new(
-                            value.as_ref(),
-                            datapath,
-                            arena,
-                        )?);
-                    }
-                    let metadata_vec = get_resp.serialize_into_metadata_vec(datapath)?;
-
-                    datapath.queue_metadata_vec(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        metadata_vec,
-                        end_batch,
-                    )?;
+                    put_resp.set_status(ResponseStatus::NotFound.as_u32());
                 }
-                MsgType::GetList(_) => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle getlist hybrid arena object");
-                    let mut getlist_req =
-                        kv_serializer_hybrid_arena_object::GetListReq::new_in(arena);
-                    {
-                        #[cfg(feature = "profiler")]
-                        demikernel::timer!("deserialize");
-                        getlist_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-                    }
-                    let mut getlist_resp =
-                        kv_serializer_hybrid_arena_object::GetListResp::new_in(arena);
-                    getlist_resp.set_id(getlist_req.get_id());
-
-                    if self.serializer.use_linked_list() {
-                        let range_start = getlist_req.get_range_start();
-                        let range_end = getlist_req.get_range_end();
-                        let mut node_option = {
-                            #[cfg(feature = "profiler")]
-                            demikernel::timer!("get key # 1");
-                            self.linked_list_kv_server
-                                .get(getlist_req.get_key().to_str()?)
-                        };
-                        let range_len = {
-                            if range_end == -1 {
-                                let mut len = 0;
-                                while let Some(node) = node_option {
-                                    len += 1;
-                                    node_option = node.get_next();
-                                }
-                                len - range_start as usize
-                            } else {
-                                ensure!(
-                                    range_start < range_end,
-                                    "Cannot process get list with range_end < range_start"
-                                );
-                                (range_end - range_start) as usize
-                            }
-                        };
-
-                        {
-                            #[cfg(feature = "profiler")]
-                            demikernel::timer!("init val list arena");
-                            getlist_resp.init_val_list(range_len, arena);
-                        }
-                        let list = getlist_resp.get_mut_val_list();
-                        let mut node_option = {
-                            #[cfg(feature = "profiler")]
-                            demikernel::timer!("get key # 2");
-                            self.linked_list_kv_server
-                                .get(getlist_req.get_key().to_str()?)
-                        };
-
-                        let mut idx = 0;
-                        while let Some(node) = node_option {
-                            if idx < range_start {
-                                node_option = node.get_next();
-                                idx += 1;
-                                continue;
-                            } else if idx as usize == range_len {
-                                tracing::debug!("Got to idx = range len");
-                                break;
-                            }
-                            tracing::debug!(
-                                "Appending value to linked list with size {}",
-                                node.get_data().len()
-                            );
-                            {
-                                #[cfg(feature = "profiler")]
-                                demikernel::timer!("append to list");
-                                list.append(dynamic_object_arena_hdr::CFBytes::new(
-                                    node.get_data(),
-                                    datapath,
-                                    arena,
-                                )?);
-                            }
-                            node_option = node.get_next();
-                            idx += 1;
-                        }
-                    } else {
-                        let value_list =
-                            match self.list_kv_server.get(getlist_req.get_key().to_str()?) {
-                                Some(v) => v,
-                                None => {
-                                    bail!(
-                                        "Could not find value for key: {:?}",
-                                        getlist_req.get_key().to_str()
-                                    );
-                                }
-                            };
-
-                        getlist_resp.init_val_list(value_list.len(), arena);
-                        let list = getlist_resp.get_mut_val_list();
-                        for value in value_list.iter() {
-                            list.append(dynamic_object_arena_hdr::CFBytes::new(
-                                value.as_ref(),
-                                datapath,
-                                arena,
-                            )?);
-                        }
+                put_resp.serialize_to_owned(datapath)?
+            }
+            MsgType::DeleteM(_) => {
+                let mut deletem_req = DeleteMReq::<D>::new();
+                deletem_req.deserialize_from_pkt(pkt, REQ_TYPE_SIZE)?;
+                let mut status = ResponseStatus::Ok;
+                for key in deletem_req.get_keys().iter() {
+                    if !delete_from_stores(
+                        key.to_str()?,
+                        &mut self.kv_server,
+                        &mut self.list_kv_server,
+                        &mut self.linked_list_kv_server,
+                    ) {
+                        tracing::debug!(key =? key.to_str(), "Could not find key to delete");
+                        status = ResponseStatus::NotFound;
                     }
-                    let metadata_vec = getlist_resp.serialize_into_metadata_vec(datapath)?;
-
-                    datapath.queue_metadata_vec(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        metadata_vec,
-                        end_batch,
-                    )?;
                 }
-                MsgType::Put => {
-                    let mut put_req = kv_serializer_hybrid_arena_object::PutReq::new_in(arena);
-                    put_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-                    if self.serializer.use_linked_list() {
-                        self.linked_list_kv_server.insert_with_copies(
-                            put_req.get_key().to_str()?,
-                            put_req.get_val().as_ref(),
+                let mut put_resp = PutResp::<D>::new();
+                put_resp.set_id(deletem_req.get_id());
+                put_resp.set_status(status.as_u32());
+                put_resp.serialize_to_owned(datapath)?
+            }
+            MsgType::CompareAndSwap => {
+                let mut cas_req = CASReq::<D>::new();
+                cas_req.deserialize_from_pkt(pkt, REQ_TYPE_SIZE)?;
+                let (status, version) = match self.serializer.use_linked_list() {
+                    true => self.linked_list_kv_server.compare_and_swap(
+                        cas_req.get_key().to_str()?,
+                        cas_req.get_version(),
+                        cas_req.get_val().as_bytes(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?,
+                    false => self.kv_server.compare_and_swap(
+                        cas_req.get_key().to_str()?,
+                        cas_req.get_version(),
+                        cas_req.get_val().as_bytes(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?,
+                };
+                let mut cas_resp = CASResp::<D>::new();
+                cas_resp.set_id(cas_req.get_id());
+                cas_resp.set_version(version);
+                cas_resp.set_status(status.as_u32());
+                cas_resp.serialize_to_owned(datapath)?
+            }
+            MsgType::Increment | MsgType::Decrement => {
+                let mut incr_req = IncrReq::<D>::new();
+                incr_req.deserialize_from_pkt(pkt, REQ_TYPE_SIZE)?;
+                let negate = msg_type == MsgType::Decrement;
+                let (status, value) = match signed_delta(incr_req.get_delta(), negate) {
+                    Some(delta) => match self.serializer.use_linked_list() {
+                        true => self.linked_list_kv_server.increment(
+                            incr_req.get_key().to_str()?,
+                            delta,
                             datapath,
                             &mut self.mempool_ids,
-                        )?;
-                    } else {
-                        self.kv_server.insert_with_copies(
-                            put_req.get_key().to_str()?,
-                            put_req.get_val().as_ref(),
+                        )?,
+                        false => self.kv_server.increment(
+                            incr_req.get_key().to_str()?,
+                            delta,
                             datapath,
                             &mut self.mempool_ids,
-                        )?;
-                    }
-                    let mut put_resp = kv_serializer_hybrid_arena_object::PutResp::new_in(arena);
-                    put_resp.set_id(put_req.get_id());
-                    let metadata_vec = put_resp.serialize_into_metadata_vec(datapath)?;
-                    datapath.queue_metadata_vec(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        metadata_vec,
-                        end_batch,
-                    )?;
-                }
-                _ => {
-                    unimplemented!();
-                }
+                        )?,
+                    },
+                    None => (ResponseStatus::BadRequest, 0),
+                };
+                let mut incr_resp = IncrResp::<D>::new();
+                incr_resp.set_id(incr_req.get_id());
+                incr_resp.set_val(value);
+                incr_resp.set_status(status.as_u32());
+                incr_resp.serialize_to_owned(datapath)?
             }
-        }
-        Ok(())
+            _ => {
+                bail!("{:?} responses carry values", msg_type);
+            }
+        };
+        datapath.queue_single_buffer_with_copy((pkt.msg_id(), pkt.conn_id(), &response), end_batch)
     }
 
-    #[inline]
-    fn process_requests_hybrid_object(
+    /// Handles one request for `process_requests_hybrid_arena_sga`.
+    fn process_request_hybrid_arena_sga(
         &mut self,
-        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
-        datapath: &mut Self::Datapath,
+        msg_type: MsgType,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        arena: &bumpalo::Bump,
+        end_batch: bool,
     ) -> Result<()> {
-        let end = pkts.len();
-        for (i, pkt) in pkts.into_iter().enumerate() {
-            let end_batch = i == (end - 1);
-            let msg_type = MsgType::from_packet(&pkt)?;
-            tracing::debug!(
-                msg_type =? msg_type,
-                "Received packet with data {:?} ptr and length {}",
-                pkt.seg(0).as_ref().as_ptr(),
-                pkt.data_len()
-            );
-            match msg_type {
-                MsgType::Get => {
+        match msg_type {
+            MsgType::Get => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle get hybrid object");
+                let mut get_req = { kv_serializer_hybrid_arena_object::GetReq::new_in(arena) };
+                {
                     #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle get hybrid object");
-                    let mut get_req = { kv_serializer_hybrid_object::GetReq::new() };
-                    {
-                        #[cfg(feature = "profiler")]
-                        demikernel::timer!("Deserialize pkt");
-                        get_req.deserialize(&pkt, REQ_TYPE_SIZE)?;
+                    demikernel::timer!("Deserialize pkt");
+                    get_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                }
+                let mut get_resp = kv_serializer_hybrid_arena_object::GetResp::new_in(arena);
+                get_resp.set_id(get_req.get_id());
+
+                let value = {
+                    #[cfg(feature = "profiler")]
+                    demikernel::timer!("Get value from kv");
+                    match self.serializer.use_linked_list() {
+                        true => self
+                            .linked_list_kv_server
+                            .get(get_req.get_key().to_str()?)
+                            .map(|v| v.as_ref().get_buffer()),
+                        false => self.kv_server.get(get_req.get_key().to_str()?),
+                    }
+                };
+                let value = match value {
+                    Some(v) => v,
+                    None => {
+                        tracing::debug!(
+                            key =? get_req.get_key().to_str(),
+                            "Could not find value for key"
+                        );
+                        get_resp.set_status(ResponseStatus::NotFound.as_u32());
+                        let metadata_vec = get_resp.serialize_into_metadata_vec(datapath)?;
+                        datapath.queue_metadata_vec(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            metadata_vec,
+                            end_batch,
+                        )?;
+                        return Ok(());
                     }
-                    let mut get_resp = kv_serializer_hybrid_object::GetResp::new();
-                    get_resp.set_id(get_req.get_id());
+                };
 
-                    let value = {
-                        //#[cfg(feature = "profiler")]
-                        //demikernel::timer!("Get value from kv");
-                        match self.serializer.use_linked_list() {
-                            true => {
-                                match self.linked_list_kv_server.get(get_req.get_key().to_str()?) {
-                                    Some(v) => v.as_ref().get_buffer(),
-                                    None => {
-                                        bail!(
-                                            "Could not find value for key: {:?}",
-                                            get_req.get_key().to_str()
-                                        );
-                                    }
-                                }
+                tracing::debug!(
+                    "For given key {:?}, found value {:?} with length {}",
+                    get_req.get_key().to_str()?,
+                    value.as_ref().as_ptr(),
+                    value.as_ref().len()
+                );
+
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Set value get hybrid arena");
+                {
+                    get_resp.set_val(dynamic_object_arena_hdr::CFBytes::new(
+                        value.as_ref(),
+                        datapath,
+                        arena,
+                    )?);
+                }
+                let metadata_vec = get_resp.serialize_into_metadata_vec(datapath)?;
+
+                datapath.queue_metadata_vec(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    metadata_vec,
+                    end_batch,
+                )?;
+            }
+            MsgType::GetList(_) => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle getlist hybrid arena object");
+                let mut getlist_req = kv_serializer_hybrid_arena_object::GetListReq::new_in(arena);
+                {
+                    #[cfg(feature = "profiler")]
+                    demikernel::timer!("deserialize");
+                    getlist_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                }
+                let mut getlist_resp =
+                    kv_serializer_hybrid_arena_object::GetListResp::new_in(arena);
+                getlist_resp.set_id(getlist_req.get_id());
+
+                if self.serializer.use_linked_list() {
+                    let range_start = getlist_req.get_range_start();
+                    let range_end = getlist_req.get_range_end();
+                    let mut node_option = {
+                        #[cfg(feature = "profiler")]
+                        demikernel::timer!("get key # 1");
+                        self.linked_list_kv_server
+                            .get(getlist_req.get_key().to_str()?)
+                    };
+                    if node_option.is_none() {
+                        tracing::debug!(
+                            key =? getlist_req.get_key().to_str(),
+                            "Could not find value for key"
+                        );
+                        getlist_resp.set_status(ResponseStatus::NotFound.as_u32());
+                        let metadata_vec = getlist_resp.serialize_into_metadata_vec(datapath)?;
+                        datapath.queue_metadata_vec(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            metadata_vec,
+                            end_batch,
+                        )?;
+                        return Ok(());
+                    }
+                    let range_len = {
+                        if range_end == -1 {
+                            let mut len = 0;
+                            while let Some(node) = node_option {
+                                len += 1;
+                                node_option = node.get_next();
                             }
-                            false => match self.kv_server.get(get_req.get_key().to_str()?) {
-                                Some(v) => v,
-                                None => {
-                                    bail!(
-                                        "Could not find value for key: {:?}",
-                                        get_req.get_key().to_str()
-                                    );
-                                }
-                            },
+                            len - range_start as usize
+                        } else {
+                            ensure!(
+                                range_start < range_end,
+                                "Cannot process get list with range_end < range_start"
+                            );
+                            (range_end - range_start) as usize
                         }
                     };
 
-                    tracing::debug!(
-                        "For given key {:?}, found value {:?} with length {}",
-                        get_req.get_key().to_str()?,
-                        value.as_ref().as_ptr(),
-                        value.as_ref().len()
-                    );
-
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Set value get");
                     {
-                        get_resp
-                            .set_val(dynamic_object_hdr::CFBytes::new(value.as_ref(), datapath)?);
+                        #[cfg(feature = "profiler")]
+                        demikernel::timer!("init val list arena");
+                        getlist_resp.init_val_list(range_len, arena);
                     }
+                    let list = getlist_resp.get_mut_val_list();
+                    let mut node_option = {
+                        #[cfg(feature = "profiler")]
+                        demikernel::timer!("get key # 2");
+                        self.linked_list_kv_server
+                            .get(getlist_req.get_key().to_str()?)
+                    };
 
-                    datapath.queue_cornflakes_hybrid_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        get_resp,
-                        end_batch,
-                    )?;
-                }
-                MsgType::GetM(_) => {
-                    let mut getm_req = kv_serializer_hybrid_object::GetMReq::new();
-                    getm_req.deserialize(&pkt, REQ_TYPE_SIZE)?;
-                    let mut getm_resp = kv_serializer_hybrid_object::GetMResp::new();
-                    getm_resp.init_vals(getm_req.get_keys().len());
-                    getm_resp.set_id(getm_req.get_id());
-
-                    let vals = getm_resp.get_mut_vals();
-                    for key in getm_req.get_keys().iter() {
-                        let value = {
-                            tracing::debug!("Key bytes: {:?}", key);
-                            #[cfg(feature = "profiler")]
-                            demikernel::timer!("got value");
-                            match self.serializer.use_linked_list() {
-                                true => match self.linked_list_kv_server.get(key.to_str()?) {
-                                    Some(v) => v.as_ref().get_buffer(),
-                                    None => {
-                                        bail!("Could not find value for key: {:?}", key.to_str());
-                                    }
-                                },
-                                false => match self.kv_server.get(key.to_str()?) {
-                                    Some(v) => v,
-                                    None => {
-                                        bail!("Could not find value for key: {:?}", key.to_str());
-                                    }
-                                },
-                            }
-                        };
+                    let mut idx = 0;
+                    while let Some(node) = node_option {
+                        if idx < range_start {
+                            node_option = node.get_next();
+                            idx += 1;
+                            continue;
+                        } else if idx as usize == range_len {
+                            tracing::debug!("Got to idx = range len");
+                            break;
+                        }
                         tracing::debug!(
-                            "For given key {:?}, found value {:?} with length {}",
-                            key.to_str()?,
-                            value.as_ref().as_ptr(),
-                            value.as_ref().len()
+                            "Appending value to linked list with size {}",
+                            node.get_data().len()
                         );
                         {
                             #[cfg(feature = "profiler")]
-                            demikernel::timer!("append value");
-                            vals.append(dynamic_object_hdr::CFBytes::new(
-                                value.as_ref(),
+                            demikernel::timer!("append to list");
+                            list.append(dynamic_object_arena_hdr::CFBytes::new(
+                                node.get_data(),
                                 datapath,
+                                arena,
                             )?);
                         }
+                        node_option = node.get_next();
+                        idx += 1;
                     }
-                    datapath.queue_cornflakes_hybrid_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        getm_resp,
-                        end_batch,
+                } else {
+                    let value_list = match self.list_kv_server.get(getlist_req.get_key().to_str()?)
+                    {
+                        Some(v) => v,
+                        None => {
+                            tracing::debug!(
+                                key =? getlist_req.get_key().to_str(),
+                                "Could not find value for key"
+                            );
+                            getlist_resp.set_status(ResponseStatus::NotFound.as_u32());
+                            let metadata_vec =
+                                getlist_resp.serialize_into_metadata_vec(datapath)?;
+                            datapath.queue_metadata_vec(
+                                pkt.msg_id(),
+                                pkt.conn_id(),
+                                metadata_vec,
+                                end_batch,
+                            )?;
+                            return Ok(());
+                        }
+                    };
+
+                    getlist_resp.init_val_list(value_list.len(), arena);
+                    let list = getlist_resp.get_mut_val_list();
+                    for value in value_list.iter() {
+                        list.append(dynamic_object_arena_hdr::CFBytes::new(
+                            value.as_ref(),
+                            datapath,
+                            arena,
+                        )?);
+                    }
+                }
+                let metadata_vec = getlist_resp.serialize_into_metadata_vec(datapath)?;
+
+                datapath.queue_metadata_vec(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    metadata_vec,
+                    end_batch,
+                )?;
+            }
+            MsgType::Put => {
+                let mut put_req = kv_serializer_hybrid_arena_object::PutReq::new_in(arena);
+                put_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                if self.serializer.use_linked_list() {
+                    self.linked_list_kv_server.insert_with_copies(
+                        put_req.get_key().to_str()?,
+                        put_req.get_val().as_ref(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                } else {
+                    self.kv_server.insert_with_copies(
+                        put_req.get_key().to_str()?,
+                        put_req.get_val().as_ref(),
+                        datapath,
+                        &mut self.mempool_ids,
                     )?;
                 }
-                MsgType::GetList(_) => {
+                let mut put_resp = kv_serializer_hybrid_arena_object::PutResp::new_in(arena);
+                put_resp.set_id(put_req.get_id());
+                let metadata_vec = put_resp.serialize_into_metadata_vec(datapath)?;
+                datapath.queue_metadata_vec(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    metadata_vec,
+                    end_batch,
+                )?;
+            }
+            MsgType::Scan => {
+                let mut scan_req = kv_serializer_hybrid_arena_object::ScanReq::new_in(arena);
+                scan_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                let values = self.serializer.scan(
+                    &self.kv_server,
+                    scan_req.get_start_key().to_str()?,
+                    scan_req.get_end_key().to_str()?,
+                    scan_req.get_count(),
+                );
+                let mut getm_resp = kv_serializer_hybrid_arena_object::GetMResp::new_in(arena);
+                getm_resp.set_id(scan_req.get_id());
+                match values {
+                    Some(values) => {
+                        getm_resp.init_vals(values.len(), arena);
+                        let vals = getm_resp.get_mut_vals();
+                        for value in values.iter() {
+                            vals.append(dynamic_object_arena_hdr::CFBytes::new(
+                                value.as_ref(),
+                                datapath,
+                                arena,
+                            )?);
+                        }
+                    }
+                    None => {
+                        tracing::debug!("Received scan, but the kv store keeps no ordered index");
+                        getm_resp.set_status(ResponseStatus::BadRequest.as_u32());
+                    }
+                }
+                let metadata_vec = getm_resp.serialize_into_metadata_vec(datapath)?;
+                datapath.queue_metadata_vec(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    metadata_vec,
+                    end_batch,
+                )?;
+            }
+            MsgType::Delete
+            | MsgType::DeleteM(_)
+            | MsgType::CompareAndSwap
+            | MsgType::Increment
+            | MsgType::Decrement => {
+                self.process_request_without_values(msg_type, pkt, datapath, end_batch)?;
+            }
+            _ => {
+                bail!("Cornflakes server does not support {:?} requests", msg_type);
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles one request for `process_requests_hybrid_object`.
+    fn process_request_hybrid_object(
+        &mut self,
+        msg_type: MsgType,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        end_batch: bool,
+    ) -> Result<()> {
+        match msg_type {
+            MsgType::Get => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle get hybrid object");
+                let mut get_req = { kv_serializer_hybrid_object::GetReq::new() };
+                {
                     #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle getlist hybrid object");
-                    let mut getlist_req = { kv_serializer_hybrid_object::GetListReq::new() };
-                    {
-                        #[cfg(feature = "profiler")]
-                        demikernel::timer!("Deserialize pkt");
-                        getlist_req.deserialize(&pkt, REQ_TYPE_SIZE)?;
+                    demikernel::timer!("Deserialize pkt");
+                    get_req.deserialize(pkt, REQ_TYPE_SIZE)?;
+                }
+                let mut get_resp = kv_serializer_hybrid_object::GetResp::new();
+                get_resp.set_id(get_req.get_id());
+
+                let value = {
+                    //#[cfg(feature = "profiler")]
+                    //demikernel::timer!("Get value from kv");
+                    match self.serializer.use_linked_list() {
+                        true => self
+                            .linked_list_kv_server
+                            .get(get_req.get_key().to_str()?)
+                            .map(|v| v.as_ref().get_buffer()),
+                        false => self.kv_server.get(get_req.get_key().to_str()?),
                     }
-                    let mut getlist_resp = kv_serializer_hybrid_object::GetListResp::new();
-                    getlist_resp.set_id(getlist_req.get_id());
+                };
+                let value = match value {
+                    Some(v) => v,
+                    None => {
+                        tracing::debug!(
+                            key =? get_req.get_key().to_str(),
+                            "Could not find value for key"
+                        );
+                        get_resp.set_status(ResponseStatus::NotFound.as_u32());
+                        datapath.queue_cornflakes_hybrid_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            get_resp,
+                            end_batch,
+                        )?;
+                        return Ok(());
+                    }
+                };
 
-                    if self.serializer.use_linked_list() {
-                        let range_start = getlist_req.get_range_start();
-                        let range_end = getlist_req.get_range_end();
-                        let mut node_option = {
-                            #[cfg(feature = "profiler")]
-                            demikernel::timer!("get key # 1");
-                            self.linked_list_kv_server
-                                .get(getlist_req.get_key().to_str()?)
-                        };
-                        let range_len = {
-                            if range_end == -1 {
-                                let mut len = 0;
-                                while let Some(node) = node_option {
-                                    len += 1;
-                                    node_option = node.get_next();
-                                }
-                                len - range_start as usize
-                            } else {
-                                ensure!(
-                                    range_start < range_end,
-                                    "Cannot process get list with range_end < range_start"
-                                );
-                                (range_end - range_start) as usize
-                            }
-                        };
+                tracing::debug!(
+                    "For given key {:?}, found value {:?} with length {}",
+                    get_req.get_key().to_str()?,
+                    value.as_ref().as_ptr(),
+                    value.as_ref().len()
+                );
 
-                        {
-                            #[cfg(feature = "profiler")]
-                            demikernel::timer!("init val list");
-                            getlist_resp.init_val_list(range_len);
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Set value get");
+                {
+                    get_resp.set_val(dynamic_object_hdr::CFBytes::new(value.as_ref(), datapath)?);
+                }
+
+                datapath.queue_cornflakes_hybrid_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    get_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::GetM(_) => {
+                let mut getm_req = kv_serializer_hybrid_object::GetMReq::new();
+                getm_req.deserialize(pkt, REQ_TYPE_SIZE)?;
+                let mut getm_resp = kv_serializer_hybrid_object::GetMResp::new();
+                getm_resp.init_vals(getm_req.get_keys().len());
+                getm_resp.set_id(getm_req.get_id());
+
+                let mut status = ResponseStatus::Ok;
+                let vals = getm_resp.get_mut_vals();
+                for key in getm_req.get_keys().iter() {
+                    let value = {
+                        tracing::debug!("Key bytes: {:?}", key);
+                        #[cfg(feature = "profiler")]
+                        demikernel::timer!("got value");
+                        match self.serializer.use_linked_list() {
+                            true => self
+                                .linked_list_kv_server
+                                .get(key.to_str()?)
+                                .map(|v| v.as_ref().get_buffer()),
+                            false => self.kv_server.get(key.to_str()?),
+                        }
+                    };
+                    let value = match value {
+                        Some(v) => v,
+                        None => {
+                            tracing::debug!(
+                                key =? key.to_str(),
+                                "Could not find value for key"
+                            );
+                            status = ResponseStatus::NotFound;
+                            vals.append(dynamic_object_hdr::CFBytes::default());
+                            continue;
                         }
-                        let list = getlist_resp.get_mut_val_list();
+                    };
+                    tracing::debug!(
+                        "For given key {:?}, found value {:?} with length {}",
+                        key.to_str()?,
+                        value.as_ref().as_ptr(),
+                        value.as_ref().len()
+                    );
+                    {
+                        #[cfg(feature = "profiler")]
+                        demikernel::timer!("append value");
+                        vals.append(dynamic_object_hdr::CFBytes::new(value.as_ref(), datapath)?);
+                    }
+                }
+                getm_resp.set_status(status.as_u32());
+                datapath.queue_cornflakes_hybrid_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    getm_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::GetList(_) => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle getlist hybrid object");
+                let mut getlist_req = { kv_serializer_hybrid_object::GetListReq::new() };
+                {
+                    #[cfg(feature = "profiler")]
+                    demikernel::timer!("Deserialize pkt");
+                    getlist_req.deserialize(pkt, REQ_TYPE_SIZE)?;
+                }
+                let mut getlist_resp = kv_serializer_hybrid_object::GetListResp::new();
+                getlist_resp.set_id(getlist_req.get_id());
 
-                        let mut node_option = {
-                            #[cfg(feature = "profiler")]
-                            demikernel::timer!("get key # 2");
-                            self.linked_list_kv_server
-                                .get(getlist_req.get_key().to_str()?)
-                        };
-
-                        let mut idx = 0;
-                        while let Some(node) = node_option {
-                            if idx < range_start {
+                if self.serializer.use_linked_list() {
+                    let range_start = getlist_req.get_range_start();
+                    let range_end = getlist_req.get_range_end();
+                    let mut node_option = {
+                        #[cfg(feature = "profiler")]
+                        demikernel::timer!("get key # 1");
+                        self.linked_list_kv_server
+                            .get(getlist_req.get_key().to_str()?)
+                    };
+                    if node_option.is_none() {
+                        tracing::debug!(
+                            key =? getlist_req.get_key().to_str(),
+                            "Could not find value for key"
+                        );
+                        getlist_resp.set_status(ResponseStatus::NotFound.as_u32());
+                        datapath.queue_cornflakes_hybrid_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            getlist_resp,
+                            end_batch,
+                        )?;
+                        return Ok(());
+                    }
+                    let range_len = {
+                        if range_end == -1 {
+                            let mut len = 0;
+                            while let Some(node) = node_option {
+                                len += 1;
                                 node_option = node.get_next();
-                                idx += 1;
-                                continue;
-                            } else if idx as usize == range_len {
-                                tracing::debug!("Got to idx = range len");
-                                break;
                             }
-                            tracing::debug!(
-                                "Appending value to linked list with size {}",
-                                node.get_data().len()
+                            len - range_start as usize
+                        } else {
+                            ensure!(
+                                range_start < range_end,
+                                "Cannot process get list with range_end < range_start"
                             );
-                            {
-                                #[cfg(feature = "profiler")]
-                                demikernel::timer!("append to linked list");
-                                list.append(dynamic_object_hdr::CFBytes::new(
-                                    node.get_data(),
-                                    datapath,
-                                )?);
-                            }
+                            (range_end - range_start) as usize
+                        }
+                    };
+
+                    {
+                        #[cfg(feature = "profiler")]
+                        demikernel::timer!("init val list");
+                        getlist_resp.init_val_list(range_len);
+                    }
+                    let list = getlist_resp.get_mut_val_list();
+
+                    let mut node_option = {
+                        #[cfg(feature = "profiler")]
+                        demikernel::timer!("get key # 2");
+                        self.linked_list_kv_server
+                            .get(getlist_req.get_key().to_str()?)
+                    };
+
+                    let mut idx = 0;
+                    while let Some(node) = node_option {
+                        if idx < range_start {
                             node_option = node.get_next();
                             idx += 1;
+                            continue;
+                        } else if idx as usize == range_len {
+                            tracing::debug!("Got to idx = range len");
+                            break;
                         }
-                    } else {
-                        let value_list =
-                            match self.list_kv_server.get(getlist_req.get_key().to_str()?) {
-                                Some(v) => v,
-                                None => {
-                                    bail!(
-                                        "Could not find value for key: {:?}",
-                                        getlist_req.get_key().to_str()
-                                    );
-                                }
-                            };
-
-                        getlist_resp.init_val_list(value_list.len());
-                        let list = getlist_resp.get_mut_val_list();
-                        for value in value_list.iter() {
+                        tracing::debug!(
+                            "Appending value to linked list with size {}",
+                            node.get_data().len()
+                        );
+                        {
+                            #[cfg(feature = "profiler")]
+                            demikernel::timer!("append to linked list");
                             list.append(dynamic_object_hdr::CFBytes::new(
-                                value.as_ref(),
+                                node.get_data(),
                                 datapath,
                             )?);
                         }
+                        node_option = node.get_next();
+                        idx += 1;
                     }
+                } else {
+                    let value_list = match self.list_kv_server.get(getlist_req.get_key().to_str()?)
+                    {
+                        Some(v) => v,
+                        None => {
+                            tracing::debug!(
+                                key =? getlist_req.get_key().to_str(),
+                                "Could not find value for key"
+                            );
+                            getlist_resp.set_status(ResponseStatus::NotFound.as_u32());
+                            datapath.queue_cornflakes_hybrid_object(
+                                pkt.msg_id(),
+                                pkt.conn_id(),
+                                getlist_resp,
+                                end_batch,
+                            )?;
+                            return Ok(());
+                        }
+                    };
 
-                    datapath.queue_cornflakes_hybrid_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        getlist_resp,
-                        end_batch,
+                    getlist_resp.init_val_list(value_list.len());
+                    let list = getlist_resp.get_mut_val_list();
+                    for value in value_list.iter() {
+                        list.append(dynamic_object_hdr::CFBytes::new(value.as_ref(), datapath)?);
+                    }
+                }
+
+                datapath.queue_cornflakes_hybrid_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    getlist_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::Put => {
+                let mut put_req = kv_serializer_hybrid_object::PutReq::new();
+                put_req.deserialize(pkt, REQ_TYPE_SIZE)?;
+                if self.serializer.use_linked_list() {
+                    self.linked_list_kv_server.insert_with_copies(
+                        put_req.get_key().to_str()?,
+                        put_req.get_val().as_ref(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                } else {
+                    self.kv_server.insert_with_copies(
+                        put_req.get_key().to_str()?,
+                        put_req.get_val().as_ref(),
+                        datapath,
+                        &mut self.mempool_ids,
                     )?;
                 }
-                MsgType::Put => {
-                    let mut put_req = kv_serializer_hybrid_object::PutReq::new();
-                    put_req.deserialize(&pkt, REQ_TYPE_SIZE)?;
+                let mut put_resp = kv_serializer_hybrid_object::PutResp::new();
+                put_resp.set_id(put_req.get_id());
+
+                datapath.queue_cornflakes_hybrid_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    put_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::PutM(_) => {
+                let mut putm_req = kv_serializer_hybrid_object::PutMReq::new();
+                putm_req.deserialize(pkt, REQ_TYPE_SIZE)?;
+                for (key, value) in putm_req.get_keys().iter().zip(putm_req.get_vals().iter()) {
                     if self.serializer.use_linked_list() {
                         self.linked_list_kv_server.insert_with_copies(
-                            put_req.get_key().to_str()?,
-                            put_req.get_val().as_ref(),
+                            key.to_str()?,
+                            value.as_ref(),
                             datapath,
                             &mut self.mempool_ids,
                         )?;
                     } else {
                         self.kv_server.insert_with_copies(
-                            put_req.get_key().to_str()?,
-                            put_req.get_val().as_ref(),
+                            key.to_str()?,
+                            value.as_ref(),
                             datapath,
                             &mut self.mempool_ids,
                         )?;
                     }
-                    let mut put_resp = kv_serializer_hybrid_object::PutResp::new();
-                    put_resp.set_id(put_req.get_id());
-
-                    datapath.queue_cornflakes_hybrid_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        put_resp,
-                        end_batch,
+                }
+                let mut put_resp = kv_serializer_hybrid_object::PutResp::new();
+                put_resp.set_id(putm_req.get_id());
+                datapath.queue_cornflakes_hybrid_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    put_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::PutList(_) => {
+                let mut putlist_req = kv_serializer_hybrid_object::PutListReq::new();
+                putlist_req.deserialize(pkt, REQ_TYPE_SIZE)?;
+                let key = putlist_req.get_key();
+                let values_iterator = putlist_req.get_vals().iter().map(|value| value.as_ref());
+
+                if self.serializer.use_linked_list() {
+                    self.linked_list_kv_server.insert_list_with_copies(
+                        key.to_str()?,
+                        values_iterator,
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                } else {
+                    self.list_kv_server.insert_with_copies(
+                        key.to_str()?,
+                        values_iterator,
+                        datapath,
+                        &mut self.mempool_ids,
                     )?;
                 }
-                MsgType::PutM(_) => {
-                    let mut putm_req = kv_serializer_hybrid_object::PutMReq::new();
-                    putm_req.deserialize(&pkt, REQ_TYPE_SIZE)?;
-                    for (key, value) in putm_req.get_keys().iter().zip(putm_req.get_vals().iter()) {
-                        if self.serializer.use_linked_list() {
+                let mut put_resp = kv_serializer_hybrid_object::PutResp::new();
+                put_resp.set_id(putlist_req.get_id());
+                datapath.queue_cornflakes_hybrid_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    put_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::AddUser => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle add user");
+                let mut add_user = kv_serializer_hybrid_object::AddUser::new();
+                add_user.deserialize(pkt, REQ_TYPE_SIZE)?;
+
+                let mut add_user_response = kv_serializer_hybrid_object::AddUserResponse::new();
+                match self.serializer.use_linked_list() {
+                    true => {
+                        let value = self
+                            .linked_list_kv_server
+                            .remove(add_user.get_keys()[0].to_str()?)
+                            .unwrap();
+                        add_user_response.set_first_value(dynamic_object_hdr::CFBytes::new(
+                            value.as_ref().as_ref(),
+                            datapath,
+                        )?);
+                        for (key, value) in
+                            add_user.get_keys().iter().zip(add_user.get_values().iter())
+                        {
                             self.linked_list_kv_server.insert_with_copies(
                                 key.to_str()?,
                                 value.as_ref(),
                                 datapath,
                                 &mut self.mempool_ids,
                             )?;
-                        } else {
+                        }
+                        datapath.queue_cornflakes_hybrid_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            add_user_response,
+                            end_batch,
+                        )?;
+                    }
+                    false => {
+                        let value = self
+                            .kv_server
+                            .remove(add_user.get_keys()[0].to_str()?)
+                            .unwrap();
+                        add_user_response.set_first_value(dynamic_object_hdr::CFBytes::new(
+                            value.as_ref(),
+                            datapath,
+                        )?);
+                        for (key, value) in
+                            add_user.get_keys().iter().zip(add_user.get_values().iter())
+                        {
                             self.kv_server.insert_with_copies(
                                 key.to_str()?,
                                 value.as_ref(),
@@ -1282,666 +1706,738 @@ where
                                 &mut self.mempool_ids,
                             )?;
                         }
+                        datapath.queue_cornflakes_hybrid_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            add_user_response,
+                            end_batch,
+                        )?;
                     }
-                    let mut put_resp = kv_serializer_hybrid_object::PutResp::new();
-                    put_resp.set_id(putm_req.get_id());
-                    datapath.queue_cornflakes_hybrid_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        put_resp,
-                        end_batch,
-                    )?;
                 }
-                MsgType::PutList(_) => {
-                    let mut putlist_req = kv_serializer_hybrid_object::PutListReq::new();
-                    putlist_req.deserialize(&pkt, REQ_TYPE_SIZE)?;
-                    let key = putlist_req.get_key();
-                    let values_iterator = putlist_req.get_vals().iter().map(|value| value.as_ref());
-
-                    if self.serializer.use_linked_list() {
-                        self.linked_list_kv_server.insert_list_with_copies(
-                            key.to_str()?,
-                            values_iterator,
-                            datapath,
-                            &mut self.mempool_ids,
+            }
+            MsgType::FollowUnfollow => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle follow unfollow");
+                let mut follow_unfollow = kv_serializer_hybrid_object::FollowUnfollow::new();
+                follow_unfollow.deserialize(pkt, REQ_TYPE_SIZE)?;
+                tracing::debug!("Deserialized follow unfollow: {:?}", follow_unfollow);
+                let mut follow_unfollow_response =
+                    kv_serializer_hybrid_object::FollowUnfollowResponse::new();
+                follow_unfollow_response.init_original_values(2);
+                let response_vals = follow_unfollow_response.get_mut_original_values();
+                match self.serializer.use_linked_list() {
+                    true => {
+                        for (cf_key, value) in follow_unfollow
+                            .get_keys()
+                            .iter()
+                            .zip(follow_unfollow.get_values().iter())
+                            .take(2)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.linked_list_kv_server.remove(key).unwrap();
+                            self.linked_list_kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                            response_vals.append(dynamic_object_hdr::CFBytes::new(
+                                old_value.as_ref().as_ref(),
+                                datapath,
+                            )?);
+                        }
+                        datapath.queue_cornflakes_hybrid_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            follow_unfollow_response,
+                            end_batch,
                         )?;
-                    } else {
-                        self.list_kv_server.insert_with_copies(
-                            key.to_str()?,
-                            values_iterator,
-                            datapath,
-                            &mut self.mempool_ids,
+                    }
+                    false => {
+                        for (cf_key, value) in follow_unfollow
+                            .get_keys()
+                            .iter()
+                            .zip(follow_unfollow.get_values().iter())
+                            .take(2)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.kv_server.remove(key).unwrap();
+                            self.kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                            response_vals.append(dynamic_object_hdr::CFBytes::new(
+                                old_value.as_ref(),
+                                datapath,
+                            )?);
+                        }
+                        datapath.queue_cornflakes_hybrid_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            follow_unfollow_response,
+                            end_batch,
                         )?;
                     }
-                    let mut put_resp = kv_serializer_hybrid_object::PutResp::new();
-                    put_resp.set_id(putlist_req.get_id());
-                    datapath.queue_cornflakes_hybrid_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        put_resp,
-                        end_batch,
-                    )?;
                 }
-                MsgType::AddUser => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle add user");
-                    let mut add_user = kv_serializer_hybrid_object::AddUser::new();
-                    add_user.deserialize(&pkt, REQ_TYPE_SIZE)?;
-
-                    let mut add_user_response = kv_serializer_hybrid_object::AddUserResponse::new();
-                    match self.serializer.use_linked_list() {
-                        true => {
-                            let value = self
-                                .linked_list_kv_server
-                                .remove(add_user.get_keys()[0].to_str()?)
-                                .unwrap();
-                            add_user_response.set_first_value(dynamic_object_hdr::CFBytes::new(
-                                value.as_ref().as_ref(),
+            }
+            MsgType::PostTweet => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle post tweet");
+                let mut post_tweet = kv_serializer_hybrid_object::PostTweet::new();
+                post_tweet.deserialize(pkt, REQ_TYPE_SIZE)?;
+
+                let mut post_tweet_response = kv_serializer_hybrid_object::PostTweetResponse::new();
+                post_tweet_response.init_values(3);
+                let response_vals = post_tweet_response.get_mut_values();
+                match self.serializer.use_linked_list() {
+                    true => {
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .take(3)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.linked_list_kv_server.remove(key).unwrap();
+                            self.linked_list_kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
                                 datapath,
-                            )?);
-                            for (key, value) in
-                                add_user.get_keys().iter().zip(add_user.get_values().iter())
-                            {
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            datapath.queue_cornflakes_hybrid_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                add_user_response,
-                                end_batch,
+                                &mut self.mempool_ids,
                             )?;
+                            response_vals.append(dynamic_object_hdr::CFBytes::new(
+                                old_value.as_ref().as_ref(),
+                                datapath,
+                            )?);
                         }
-                        false => {
-                            let value = self
-                                .kv_server
-                                .remove(add_user.get_keys()[0].to_str()?)
-                                .unwrap();
-                            add_user_response.set_first_value(dynamic_object_hdr::CFBytes::new(
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .skip(3)
+                            .take(2)
+                        {
+                            self.linked_list_kv_server.insert_with_copies(
+                                cf_key.to_str()?,
                                 value.as_ref(),
                                 datapath,
-                            )?);
-                            for (key, value) in
-                                add_user.get_keys().iter().zip(add_user.get_values().iter())
-                            {
-                                self.kv_server.insert_with_copies(
-                                    key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            datapath.queue_cornflakes_hybrid_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                add_user_response,
-                                end_batch,
+                                &mut self.mempool_ids,
                             )?;
                         }
+                        datapath.queue_cornflakes_hybrid_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            post_tweet_response,
+                            end_batch,
+                        )?;
                     }
-                }
-                MsgType::FollowUnfollow => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle follow unfollow");
-                    let mut follow_unfollow = kv_serializer_hybrid_object::FollowUnfollow::new();
-                    follow_unfollow.deserialize(&pkt, REQ_TYPE_SIZE)?;
-                    tracing::debug!("Deserialized follow unfollow: {:?}", follow_unfollow);
-                    let mut follow_unfollow_response =
-                        kv_serializer_hybrid_object::FollowUnfollowResponse::new();
-                    follow_unfollow_response.init_original_values(2);
-                    let response_vals = follow_unfollow_response.get_mut_original_values();
-                    match self.serializer.use_linked_list() {
-                        true => {
-                            for (cf_key, value) in follow_unfollow
-                                .get_keys()
-                                .iter()
-                                .zip(follow_unfollow.get_values().iter())
-                                .take(2)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.linked_list_kv_server.remove(key).unwrap();
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_object_hdr::CFBytes::new(
-                                    old_value.as_ref().as_ref(),
-                                    datapath,
-                                )?);
-                            }
-                            datapath.queue_cornflakes_hybrid_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                follow_unfollow_response,
-                                end_batch,
+                    false => {
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .take(3)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.kv_server.remove(key).unwrap();
+                            self.kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
                             )?;
+                            response_vals.append(dynamic_object_hdr::CFBytes::new(
+                                old_value.as_ref(),
+                                datapath,
+                            )?);
                         }
-                        false => {
-                            for (cf_key, value) in follow_unfollow
-                                .get_keys()
-                                .iter()
-                                .zip(follow_unfollow.get_values().iter())
-                                .take(2)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.kv_server.remove(key).unwrap();
-                                self.kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_object_hdr::CFBytes::new(
-                                    old_value.as_ref(),
-                                    datapath,
-                                )?);
-                            }
-                            datapath.queue_cornflakes_hybrid_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                follow_unfollow_response,
-                                end_batch,
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .skip(3)
+                            .take(2)
+                        {
+                            self.kv_server.insert_with_copies(
+                                cf_key.to_str()?,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
                             )?;
                         }
+
+                        datapath.queue_cornflakes_hybrid_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            post_tweet_response,
+                            end_batch,
+                        )?;
                     }
                 }
-                MsgType::PostTweet => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle post tweet");
-                    let mut post_tweet = kv_serializer_hybrid_object::PostTweet::new();
-                    post_tweet.deserialize(&pkt, REQ_TYPE_SIZE)?;
-
-                    let mut post_tweet_response =
-                        kv_serializer_hybrid_object::PostTweetResponse::new();
-                    post_tweet_response.init_values(3);
-                    let response_vals = post_tweet_response.get_mut_values();
-                    match self.serializer.use_linked_list() {
+            }
+            MsgType::GetTimeline(_) => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle get timeline");
+                let mut get_timeline = kv_serializer_hybrid_object::GetTimeline::new();
+                get_timeline.deserialize(pkt, REQ_TYPE_SIZE)?;
+
+                let mut get_timeline_response =
+                    kv_serializer_hybrid_object::GetTimelineResponse::new();
+                get_timeline_response.init_values(get_timeline.get_keys().len());
+                let response_vals = get_timeline_response.get_mut_values();
+                for (_i, key) in get_timeline.get_keys().iter().enumerate() {
+                    let cf_bytes = match self.serializer.use_linked_list() {
                         true => {
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .take(3)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.linked_list_kv_server.remove(key).unwrap();
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_object_hdr::CFBytes::new(
-                                    old_value.as_ref().as_ref(),
-                                    datapath,
-                                )?);
-                            }
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .skip(3)
-                                .take(2)
-                            {
-                                self.linked_list_kv_server.insert_with_copies(
-                                    cf_key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            datapath.queue_cornflakes_hybrid_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                post_tweet_response,
-                                end_batch,
-                            )?;
+                            let val = self.linked_list_kv_server.get(key.to_str()?).unwrap();
+                            tracing::debug!(
+                                msg_id = pkt.msg_id(),
+                                conn_id = pkt.conn_id(),
+                                key_idx = _i,
+                                "Get timeline val size {}",
+                                val.as_ref().as_ref().len()
+                            );
+                            dynamic_object_hdr::CFBytes::new(val.as_ref().as_ref(), datapath)?
                         }
                         false => {
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .take(3)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.kv_server.remove(key).unwrap();
-                                self.kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_object_hdr::CFBytes::new(
-                                    old_value.as_ref(),
-                                    datapath,
-                                )?);
-                            }
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .skip(3)
-                                .take(2)
-                            {
-                                self.kv_server.insert_with_copies(
-                                    cf_key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-
-                            datapath.queue_cornflakes_hybrid_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                post_tweet_response,
-                                end_batch,
-                            )?;
+                            let val = self.kv_server.get(key.to_str()?).unwrap();
+                            tracing::debug!(
+                                msg_id = pkt.msg_id(),
+                                conn_id = pkt.conn_id(),
+                                key_idx = _i,
+                                "Get timeline val size {}",
+                                val.as_ref().len()
+                            );
+                            dynamic_object_hdr::CFBytes::new(val.as_ref(), datapath)?
                         }
-                    }
+                    };
+                    response_vals.append(cf_bytes);
                 }
-                MsgType::GetTimeline(_) => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle get timeline");
-                    let mut get_timeline = kv_serializer_hybrid_object::GetTimeline::new();
-                    get_timeline.deserialize(&pkt, REQ_TYPE_SIZE)?;
-
-                    let mut get_timeline_response =
-                        kv_serializer_hybrid_object::GetTimelineResponse::new();
-                    get_timeline_response.init_values(get_timeline.get_keys().len());
-                    let response_vals = get_timeline_response.get_mut_values();
-                    for (_i, key) in get_timeline.get_keys().iter().enumerate() {
-                        let cf_bytes = match self.serializer.use_linked_list() {
-                            true => {
-                                let val = self.linked_list_kv_server.get(key.to_str()?).unwrap();
-                                tracing::debug!(
-                                    msg_id = pkt.msg_id(),
-                                    conn_id = pkt.conn_id(),
-                                    key_idx = _i,
-                                    "Get timeline val size {}",
-                                    val.as_ref().as_ref().len()
-                                );
-                                dynamic_object_hdr::CFBytes::new(val.as_ref().as_ref(), datapath)?
-                            }
-                            false => {
-                                let val = self.kv_server.get(key.to_str()?).unwrap();
-                                tracing::debug!(
-                                    msg_id = pkt.msg_id(),
-                                    conn_id = pkt.conn_id(),
-                                    key_idx = _i,
-                                    "Get timeline val size {}",
-                                    val.as_ref().len()
-                                );
-                                dynamic_object_hdr::CFBytes::new(val.as_ref(), datapath)?
-                            }
-                        };
-                        response_vals.append(cf_bytes);
+                tracing::debug!(
+                    "Sending back get timeline response with msg id {}",
+                    pkt.msg_id()
+                );
+                datapath.queue_cornflakes_hybrid_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    get_timeline_response,
+                    end_batch,
+                )?;
+            }
+            MsgType::Scan => {
+                let mut scan_req = kv_serializer_hybrid_object::ScanReq::new();
+                scan_req.deserialize(pkt, REQ_TYPE_SIZE)?;
+                let values = self.serializer.scan(
+                    &self.kv_server,
+                    scan_req.get_start_key().to_str()?,
+                    scan_req.get_end_key().to_str()?,
+                    scan_req.get_count(),
+                );
+                let mut getm_resp = kv_serializer_hybrid_object::GetMResp::new();
+                getm_resp.set_id(scan_req.get_id());
+                match values {
+                    Some(values) => {
+                        getm_resp.init_vals(values.len());
+                        let vals = getm_resp.get_mut_vals();
+                        for value in values.iter() {
+                            vals.append(dynamic_object_hdr::CFBytes::new(
+                                value.as_ref(),
+                                datapath,
+                            )?);
+                        }
+                    }
+                    None => {
+                        tracing::debug!("Received scan, but the kv store keeps no ordered index");
+                        getm_resp.set_status(ResponseStatus::BadRequest.as_u32());
                     }
-                    tracing::debug!(
-                        "Sending back get timeline response with msg id {}",
-                        pkt.msg_id()
-                    );
-                    datapath.queue_cornflakes_hybrid_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        get_timeline_response,
-                        end_batch,
-                    )?;
-                }
-                _ => {
-                    unimplemented!();
                 }
+                datapath.queue_cornflakes_hybrid_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    getm_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::Delete
+            | MsgType::DeleteM(_)
+            | MsgType::CompareAndSwap
+            | MsgType::Increment
+            | MsgType::Decrement => {
+                self.process_request_without_values(msg_type, pkt, datapath, end_batch)?;
+            }
+            _ => {
+                bail!("Cornflakes server does not support {:?} requests", msg_type);
             }
         }
         Ok(())
     }
 
-    #[inline]
-    fn process_requests_hybrid_arena_object(
+    /// Handles one request for `process_requests_hybrid_arena_object`.
+    fn process_request_hybrid_arena_object(
         &mut self,
-        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
-        datapath: &mut Self::Datapath,
-        arena: &mut bumpalo::Bump,
+        msg_type: MsgType,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        arena: &bumpalo::Bump,
+        end_batch: bool,
     ) -> Result<()> {
-        let end = pkts.len();
-        for (i, pkt) in pkts.into_iter().enumerate() {
-            let end_batch = i == (end - 1);
-            let msg_type = MsgType::from_packet(&pkt)?;
-            tracing::debug!(
-                msg_type =? msg_type,
-                "Received packet with data {:?} ptr and length {}",
-                pkt.seg(0).as_ref().as_ptr(),
-                pkt.data_len()
-            );
-            match msg_type {
-                MsgType::Get => {
+        match msg_type {
+            MsgType::Get => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle get hybrid object");
+                let mut get_req = { kv_serializer_hybrid_arena_object::GetReq::new_in(arena) };
+                {
                     #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle get hybrid object");
-                    let mut get_req = { kv_serializer_hybrid_arena_object::GetReq::new_in(arena) };
-                    {
-                        #[cfg(feature = "profiler")]
-                        demikernel::timer!("Deserialize pkt");
-                        get_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
+                    demikernel::timer!("Deserialize pkt");
+                    get_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                }
+                let mut get_resp = kv_serializer_hybrid_arena_object::GetResp::new_in(arena);
+                get_resp.set_id(get_req.get_id());
+
+                let value = {
+                    #[cfg(feature = "profiler")]
+                    demikernel::timer!("Get value from kv");
+                    match self.serializer.use_linked_list() {
+                        true => self
+                            .linked_list_kv_server
+                            .get(get_req.get_key().to_str()?)
+                            .map(|v| v.as_ref().get_buffer()),
+                        false => self.kv_server.get(get_req.get_key().to_str()?),
+                    }
+                };
+                let value = match value {
+                    Some(v) => v,
+                    None => {
+                        tracing::debug!(
+                            key =? get_req.get_key().to_str(),
+                            "Could not find value for key"
+                        );
+                        get_resp.set_status(ResponseStatus::NotFound.as_u32());
+                        datapath.queue_cornflakes_arena_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            get_resp,
+                            end_batch,
+                        )?;
+                        return Ok(());
+                    }
+                };
+
+                tracing::debug!(
+                    "For given key {:?}, found value {:?} with length {}",
+                    get_req.get_key().to_str()?,
+                    value.as_ref().as_ptr(),
+                    value.as_ref().len()
+                );
+
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Set value get hybrid arena");
+                {
+                    get_resp.set_val(dynamic_object_arena_hdr::CFBytes::new(
+                        value.as_ref(),
+                        datapath,
+                        arena,
+                    )?);
+                }
+
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    get_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::GetFromList => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle get from list hybrid arena object");
+                let mut get_req =
+                    { kv_serializer_hybrid_arena_object::GetFromListReq::new_in(arena) };
+                {
+                    #[cfg(feature = "profiler")]
+                    demikernel::timer!("Deserialize pkt");
+                    get_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                }
+                let mut get_resp = kv_serializer_hybrid_arena_object::GetResp::new_in(arena);
+                get_resp.set_id(get_req.get_id());
+
+                let key = get_req.get_key().to_str()?;
+                let value = {
+                    #[cfg(feature = "profiler")]
+                    demikernel::timer!("Retrieve value");
+                    self.list_kv_server
+                        .get(key)
+                        .and_then(|list| list.get(get_req.get_idx() as usize))
+                };
+                let value = match value {
+                    Some(v) => v,
+                    None => {
+                        tracing::debug!(
+                            key,
+                            idx = get_req.get_idx(),
+                            "Could not find value for key and index"
+                        );
+                        get_resp.set_status(ResponseStatus::NotFound.as_u32());
+                        datapath.queue_cornflakes_arena_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            get_resp,
+                            end_batch,
+                        )?;
+                        return Ok(());
                     }
-                    let mut get_resp = kv_serializer_hybrid_arena_object::GetResp::new_in(arena);
-                    get_resp.set_id(get_req.get_id());
+                };
+
+                tracing::debug!(
+                    "For given key {:?}, found value {:?} with length {}",
+                    get_req.get_key().to_str()?,
+                    value.as_ref().as_ptr(),
+                    value.as_ref().len()
+                );
+
+                {
+                    #[cfg(feature = "profiler")]
+                    demikernel::timer!("Set value get hybrid arena");
+                    get_resp.set_val(dynamic_object_arena_hdr::CFBytes::new(
+                        value.as_ref(),
+                        datapath,
+                        arena,
+                    )?);
+                }
 
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    get_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::GetM(_) => {
+                let mut getm_req = kv_serializer_hybrid_arena_object::GetMReq::new_in(arena);
+                getm_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                let mut getm_resp = kv_serializer_hybrid_arena_object::GetMResp::new_in(arena);
+                getm_resp.init_vals(getm_req.get_keys().len(), arena);
+                getm_resp.set_id(getm_req.get_id());
+
+                let mut status = ResponseStatus::Ok;
+                let vals = getm_resp.get_mut_vals();
+                for key in getm_req.get_keys().iter() {
                     let value = {
+                        tracing::debug!("Key bytes: {:?}", key);
                         #[cfg(feature = "profiler")]
-                        demikernel::timer!("Get value from kv");
+                        demikernel::timer!("got value");
                         match self.serializer.use_linked_list() {
-                            true => {
-                                match self.linked_list_kv_server.get(get_req.get_key().to_str()?) {
-                                    Some(v) => v.as_ref().get_buffer(),
-                                    None => {
-                                        bail!(
-                                            "Could not find value for key: {:?}",
-                                            get_req.get_key().to_str()
-                                        );
-                                    }
-                                }
-                            }
-                            false => match self.kv_server.get(get_req.get_key().to_str()?) {
-                                Some(v) => v,
-                                None => {
-                                    bail!(
-                                        "Could not find value for key: {:?}",
-                                        get_req.get_key().to_str()
-                                    );
-                                }
-                            },
+                            true => self
+                                .linked_list_kv_server
+                                .get(key.to_str()?)
+                                .map(|v| v.as_ref().get_buffer()),
+                            false => self.kv_server.get(key.to_str()?),
+                        }
+                    };
+                    let value = match value {
+                        Some(v) => v,
+                        None => {
+                            tracing::debug!(
+                                key =? key.to_str(),
+                                "Could not find value for key"
+                            );
+                            status = ResponseStatus::NotFound;
+                            vals.append(dynamic_object_arena_hdr::CFBytes::Copied(
+                                bumpalo::collections::Vec::new_in(arena),
+                            ));
+                            continue;
                         }
                     };
-
                     tracing::debug!(
                         "For given key {:?}, found value {:?} with length {}",
-                        get_req.get_key().to_str()?,
+                        key.to_str()?,
                         value.as_ref().as_ptr(),
                         value.as_ref().len()
                     );
-
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Set value get hybrid arena");
                     {
-                        get_resp.set_val(dynamic_object_arena_hdr::CFBytes::new(
+                        #[cfg(feature = "profiler")]
+                        demikernel::timer!("append value");
+                        vals.append(dynamic_object_arena_hdr::CFBytes::new(
                             value.as_ref(),
                             datapath,
                             arena,
                         )?);
                     }
-
-                    datapath.queue_cornflakes_arena_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        get_resp,
-                        end_batch,
-                    )?;
                 }
-                MsgType::GetFromList => {
+                getm_resp.set_status(status.as_u32());
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    getm_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::GetList(_) => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle getlist hybrid arena object");
+                let mut getlist_req = kv_serializer_hybrid_arena_object::GetListReq::new_in(arena);
+                {
                     #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle get from list hybrid arena object");
-                    let mut get_req =
-                        { kv_serializer_hybrid_arena_object::GetFromListReq::new_in(arena) };
-                    {
+                    demikernel::timer!("deserialize");
+                    getlist_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                }
+                let mut getlist_resp =
+                    kv_serializer_hybrid_arena_object::GetListResp::new_in(arena);
+                getlist_resp.set_id(getlist_req.get_id());
+
+                if self.serializer.use_linked_list() {
+                    let range_start = getlist_req.get_range_start();
+                    let range_end = getlist_req.get_range_end();
+                    let mut node_option = {
                         #[cfg(feature = "profiler")]
-                        demikernel::timer!("Deserialize pkt");
-                        get_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
+                        demikernel::timer!("get key # 1");
+                        self.linked_list_kv_server
+                            .get(getlist_req.get_key().to_str()?)
+                    };
+                    if node_option.is_none() {
+                        tracing::debug!(
+                            key =? getlist_req.get_key().to_str(),
+                            "Could not find value for key"
+                        );
+                        getlist_resp.set_status(ResponseStatus::NotFound.as_u32());
+                        datapath.queue_cornflakes_arena_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            getlist_resp,
+                            end_batch,
+                        )?;
+                        return Ok(());
                     }
-                    let mut get_resp = kv_serializer_hybrid_arena_object::GetResp::new_in(arena);
-                    get_resp.set_id(get_req.get_id());
-
-                    let key = get_req.get_key().to_str()?;
-                    let value = {
-                        #[cfg(feature = "profiler")]
-                        demikernel::timer!("Retrieve value");
-                        match self.list_kv_server.get(key) {
-                            Some(list) => match list.get(get_req.get_idx() as usize) {
-                                Some(v) => v,
-                                None => {
-                                    bail!(
-                                        "Could not find idx {} for key {} in list kv server",
-                                        get_req.get_idx(),
-                                        key
-                                    );
-                                }
-                            },
-                            None => {
-                                bail!("Could not find value for key: {:?}", key);
+                    let range_len = {
+                        if range_end == -1 {
+                            let mut len = 0;
+                            while let Some(node) = node_option {
+                                len += 1;
+                                node_option = node.get_next();
                             }
+                            len - range_start as usize
+                        } else {
+                            ensure!(
+                                range_start < range_end,
+                                "Cannot process get list with range_end < range_start"
+                            );
+                            (range_end - range_start) as usize
                         }
                     };
 
-                    tracing::debug!(
-                        "For given key {:?}, found value {:?} with length {}",
-                        get_req.get_key().to_str()?,
-                        value.as_ref().as_ptr(),
-                        value.as_ref().len()
-                    );
-
                     {
                         #[cfg(feature = "profiler")]
-                        demikernel::timer!("Set value get hybrid arena");
-                        get_resp.set_val(dynamic_object_arena_hdr::CFBytes::new(
-                            value.as_ref(),
-                            datapath,
-                            arena,
-                        )?);
+                        demikernel::timer!("init val list arena");
+                        getlist_resp.init_val_list(range_len, arena);
                     }
+                    let list = getlist_resp.get_mut_val_list();
+                    let mut node_option = {
+                        #[cfg(feature = "profiler")]
+                        demikernel::timer!("get key # 2");
+                        self.linked_list_kv_server
+                            .get(getlist_req.get_key().to_str()?)
+                    };
 
-                    datapath.queue_cornflakes_arena_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        get_resp,
-                        end_batch,
-                    )?;
-                }
-                MsgType::GetM(_) => {
-                    let mut getm_req = kv_serializer_hybrid_arena_object::GetMReq::new_in(arena);
-                    getm_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-                    let mut getm_resp = kv_serializer_hybrid_arena_object::GetMResp::new_in(arena);
-                    getm_resp.init_vals(getm_req.get_keys().len(), arena);
-                    getm_resp.set_id(getm_req.get_id());
-
-                    let vals = getm_resp.get_mut_vals();
-                    for key in getm_req.get_keys().iter() {
-                        let value = {
-                            tracing::debug!("Key bytes: {:?}", key);
-                            #[cfg(feature = "profiler")]
-                            demikernel::timer!("got value");
-                            match self.serializer.use_linked_list() {
-                                true => match self.linked_list_kv_server.get(key.to_str()?) {
-                                    Some(v) => v.as_ref().get_buffer(),
-                                    None => {
-                                        bail!("Could not find value for key: {:?}", key.to_str());
-                                    }
-                                },
-                                false => match self.kv_server.get(key.to_str()?) {
-                                    Some(v) => v,
-                                    None => {
-                                        bail!("Could not find value for key: {:?}", key.to_str());
-                                    }
-                                },
-                            }
-                        };
+                    let mut idx = 0;
+                    while let Some(node) = node_option {
+                        if idx < range_start {
+                            node_option = node.get_next();
+                            idx += 1;
+                            continue;
+                        } else if idx as usize == range_len {
+                            tracing::debug!("Got to idx = range len");
+                            break;
+                        }
                         tracing::debug!(
-                            "For given key {:?}, found value {:?} with length {}",
-                            key.to_str()?,
-                            value.as_ref().as_ptr(),
-                            value.as_ref().len()
+                            "Appending value to linked list with size {}",
+                            node.get_data().len()
                         );
                         {
                             #[cfg(feature = "profiler")]
-                            demikernel::timer!("append value");
-                            vals.append(dynamic_object_arena_hdr::CFBytes::new(
-                                value.as_ref(),
+                            demikernel::timer!("append to list");
+                            list.append(dynamic_object_arena_hdr::CFBytes::new(
+                                node.get_data(),
                                 datapath,
                                 arena,
                             )?);
                         }
+                        node_option = node.get_next();
+                        idx += 1;
                     }
-                    datapath.queue_cornflakes_arena_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        getm_resp,
-                        end_batch,
-                    )?;
-                }
-                MsgType::GetList(_) => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle getlist hybrid arena object");
-                    let mut getlist_req =
-                        kv_serializer_hybrid_arena_object::GetListReq::new_in(arena);
+                } else {
+                    let value_list = match self.list_kv_server.get(getlist_req.get_key().to_str()?)
                     {
-                        #[cfg(feature = "profiler")]
-                        demikernel::timer!("deserialize");
-                        getlist_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-                    }
-                    let mut getlist_resp =
-                        kv_serializer_hybrid_arena_object::GetListResp::new_in(arena);
-                    getlist_resp.set_id(getlist_req.get_id());
-
-                    if self.serializer.use_linked_list() {
-                        let range_start = getlist_req.get_range_start();
-                        let range_end = getlist_req.get_range_end();
-                        let mut node_option = {
-                            #[cfg(feature = "profiler")]
-                            demikernel::timer!("get key # 1");
-                            self.linked_list_kv_server
-                                .get(getlist_req.get_key().to_str()?)
-                        };
-                        let range_len = {
-                            if range_end == -1 {
-                                let mut len = 0;
-                                while let Some(node) = node_option {
-                                    len += 1;
-                                    node_option = node.get_next();
-                                }
-                                len - range_start as usize
-                            } else {
-                                ensure!(
-                                    range_start < range_end,
-                                    "Cannot process get list with range_end < range_start"
-                                );
-                                (range_end - range_start) as usize
-                            }
-                        };
-
-                        {
-                            #[cfg(feature = "profiler")]
-                            demikernel::timer!("init val list arena");
-                            getlist_resp.init_val_list(range_len, arena);
-                        }
-                        let list = getlist_resp.get_mut_val_list();
-                        let mut node_option = {
-                            #[cfg(feature = "profiler")]
-                            demikernel::timer!("get key # 2");
-                            self.linked_list_kv_server
-                                .get(getlist_req.get_key().to_str()?)
-                        };
-
-                        let mut idx = 0;
-                        while let Some(node) = node_option {
-                            if idx < range_start {
-                                node_option = node.get_next();
-                                idx += 1;
-                                continue;
-                            } else if idx as usize == range_len {
-                                tracing::debug!("Got to idx = range len");
-                                break;
-                            }
+                        Some(v) => v,
+                        None => {
                             tracing::debug!(
-                                "Appending value to linked list with size {}",
-                                node.get_data().len()
+                                key =? getlist_req.get_key().to_str(),
+                                "Could not find value for key"
                             );
-                            {
-                                #[cfg(feature = "profiler")]
-                                demikernel::timer!("append to list");
-                                list.append(dynamic_object_arena_hdr::CFBytes::new(
-                                    node.get_data(),
-                                    datapath,
-                                    arena,
-                                )?);
-                            }
-                            node_option = node.get_next();
-                            idx += 1;
-                        }
-                    } else {
-                        let value_list =
-                            match self.list_kv_server.get(getlist_req.get_key().to_str()?) {
-                                Some(v) => v,
-                                None => {
-                                    bail!(
-                                        "Could not find value for key: {:?}",
-                                        getlist_req.get_key().to_str()
-                                    );
-                                }
-                            };
-
-                        getlist_resp.init_val_list(value_list.len(), arena);
-                        let list = getlist_resp.get_mut_val_list();
-                        for value in value_list.iter() {
-                            list.append(dynamic_object_arena_hdr::CFBytes::new(
-                                value.as_ref(),
-                                datapath,
-                                arena,
-                            )?);
+                            getlist_resp.set_status(ResponseStatus::NotFound.as_u32());
+                            datapath.queue_cornflakes_arena_object(
+                                pkt.msg_id(),
+                                pkt.conn_id(),
+                                getlist_resp,
+                                end_batch,
+                            )?;
+                            return Ok(());
                         }
+                    };
+
+                    getlist_resp.init_val_list(value_list.len(), arena);
+                    let list = getlist_resp.get_mut_val_list();
+                    for value in value_list.iter() {
+                        list.append(dynamic_object_arena_hdr::CFBytes::new(
+                            value.as_ref(),
+                            datapath,
+                            arena,
+                        )?);
                     }
+                }
 
-                    datapath.queue_cornflakes_arena_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        getlist_resp,
-                        end_batch,
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    getlist_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::Put => {
+                let mut put_req = kv_serializer_hybrid_arena_object::PutReq::new_in(arena);
+                put_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                if self.serializer.use_linked_list() {
+                    self.linked_list_kv_server.insert_with_copies(
+                        put_req.get_key().to_str()?,
+                        put_req.get_val().as_ref(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                } else {
+                    self.kv_server.insert_with_copies(
+                        put_req.get_key().to_str()?,
+                        put_req.get_val().as_ref(),
+                        datapath,
+                        &mut self.mempool_ids,
                     )?;
                 }
-                MsgType::Put => {
-                    let mut put_req = kv_serializer_hybrid_arena_object::PutReq::new_in(arena);
-                    put_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
+                let mut put_resp = kv_serializer_hybrid_arena_object::PutResp::new_in(arena);
+                put_resp.set_id(put_req.get_id());
+
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    put_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::PutM(_) => {
+                let mut putm_req = kv_serializer_hybrid_arena_object::PutMReq::new_in(arena);
+                putm_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                for (key, value) in putm_req.get_keys().iter().zip(putm_req.get_vals().iter()) {
                     if self.serializer.use_linked_list() {
                         self.linked_list_kv_server.insert_with_copies(
-                            put_req.get_key().to_str()?,
-                            put_req.get_val().as_ref(),
+                            key.to_str()?,
+                            value.as_ref(),
                             datapath,
                             &mut self.mempool_ids,
                         )?;
                     } else {
                         self.kv_server.insert_with_copies(
-                            put_req.get_key().to_str()?,
-                            put_req.get_val().as_ref(),
+                            key.to_str()?,
+                            value.as_ref(),
                             datapath,
                             &mut self.mempool_ids,
                         )?;
                     }
-                    let mut put_resp = kv_serializer_hybrid_arena_object::PutResp::new_in(arena);
-                    put_resp.set_id(put_req.get_id());
-
-                    datapath.queue_cornflakes_arena_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        put_resp,
-                        end_batch,
+                }
+                let mut put_resp = kv_serializer_hybrid_arena_object::PutResp::new_in(arena);
+                put_resp.set_id(putm_req.get_id());
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    put_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::PutList(_) => {
+                let mut putlist_req = kv_serializer_hybrid_arena_object::PutListReq::new_in(arena);
+                putlist_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                let key = putlist_req.get_key();
+                let values_iterator = putlist_req.get_vals().iter().map(|value| value.as_ref());
+
+                if self.serializer.use_linked_list() {
+                    self.linked_list_kv_server.insert_list_with_copies(
+                        key.to_str()?,
+                        values_iterator,
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                } else {
+                    self.list_kv_server.insert_with_copies(
+                        key.to_str()?,
+                        values_iterator,
+                        datapath,
+                        &mut self.mempool_ids,
                     )?;
                 }
-                MsgType::PutM(_) => {
-                    let mut putm_req = kv_serializer_hybrid_arena_object::PutMReq::new_in(arena);
-                    putm_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-                    for (key, value) in putm_req.get_keys().iter().zip(putm_req.get_vals().iter()) {
-                        if self.serializer.use_linked_list() {
+                let mut put_resp = kv_serializer_hybrid_arena_object::PutResp::new_in(arena);
+                put_resp.set_id(putlist_req.get_id());
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    put_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::AddUser => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle add user");
+                let mut add_user = kv_serializer_hybrid_arena_object::AddUser::new_in(arena);
+                add_user.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+
+                let mut add_user_response =
+                    kv_serializer_hybrid_arena_object::AddUserResponse::new_in(arena);
+                match self.serializer.use_linked_list() {
+                    true => {
+                        let value = self
+                            .linked_list_kv_server
+                            .remove(add_user.get_keys()[0].to_str()?)
+                            .unwrap();
+                        add_user_response.set_first_value(dynamic_object_arena_hdr::CFBytes::new(
+                            value.as_ref().as_ref(),
+                            datapath,
+                            arena,
+                        )?);
+                        for (key, value) in
+                            add_user.get_keys().iter().zip(add_user.get_values().iter())
+                        {
                             self.linked_list_kv_server.insert_with_copies(
                                 key.to_str()?,
                                 value.as_ref(),
                                 datapath,
                                 &mut self.mempool_ids,
                             )?;
-                        } else {
+                        }
+                        datapath.queue_cornflakes_arena_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            add_user_response,
+                            end_batch,
+                        )?;
+                    }
+                    false => {
+                        let value = self
+                            .kv_server
+                            .remove(add_user.get_keys()[0].to_str()?)
+                            .unwrap();
+                        add_user_response.set_first_value(dynamic_object_arena_hdr::CFBytes::new(
+                            value.as_ref(),
+                            datapath,
+                            arena,
+                        )?);
+                        for (key, value) in
+                            add_user.get_keys().iter().zip(add_user.get_values().iter())
+                        {
                             self.kv_server.insert_with_copies(
                                 key.to_str()?,
                                 value.as_ref(),
@@ -1949,738 +2445,1414 @@ where
                                 &mut self.mempool_ids,
                             )?;
                         }
+                        datapath.queue_cornflakes_arena_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            add_user_response,
+                            end_batch,
+                        )?;
                     }
-                    let mut put_resp = kv_serializer_hybrid_arena_object::PutResp::new_in(arena);
-                    put_resp.set_id(putm_req.get_id());
-                    datapath.queue_cornflakes_arena_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        put_resp,
-                        end_batch,
-                    )?;
                 }
-                MsgType::PutList(_) => {
-                    let mut putlist_req =
-                        kv_serializer_hybrid_arena_object::PutListReq::new_in(arena);
-                    putlist_req.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-                    let key = putlist_req.get_key();
-                    let values_iterator = putlist_req.get_vals().iter().map(|value| value.as_ref());
-
-                    if self.serializer.use_linked_list() {
-                        self.linked_list_kv_server.insert_list_with_copies(
-                            key.to_str()?,
-                            values_iterator,
-                            datapath,
-                            &mut self.mempool_ids,
+            }
+            MsgType::FollowUnfollow => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle follow unfollow");
+                let mut follow_unfollow =
+                    kv_serializer_hybrid_arena_object::FollowUnfollow::new_in(arena);
+                follow_unfollow.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                tracing::debug!("Deserialized follow unfollow: {:?}", follow_unfollow);
+                let mut follow_unfollow_response =
+                    kv_serializer_hybrid_arena_object::FollowUnfollowResponse::new_in(arena);
+                follow_unfollow_response.init_original_values(2, arena);
+                let response_vals = follow_unfollow_response.get_mut_original_values();
+                match self.serializer.use_linked_list() {
+                    true => {
+                        for (cf_key, value) in follow_unfollow
+                            .get_keys()
+                            .iter()
+                            .zip(follow_unfollow.get_values().iter())
+                            .take(2)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.linked_list_kv_server.remove(key).unwrap();
+                            self.linked_list_kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                            response_vals.append(dynamic_object_arena_hdr::CFBytes::new(
+                                old_value.as_ref().as_ref(),
+                                datapath,
+                                arena,
+                            )?);
+                        }
+                        datapath.queue_cornflakes_arena_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            follow_unfollow_response,
+                            end_batch,
                         )?;
-                    } else {
-                        self.list_kv_server.insert_with_copies(
-                            key.to_str()?,
-                            values_iterator,
-                            datapath,
-                            &mut self.mempool_ids,
+                    }
+                    false => {
+                        for (cf_key, value) in follow_unfollow
+                            .get_keys()
+                            .iter()
+                            .zip(follow_unfollow.get_values().iter())
+                            .take(2)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.kv_server.remove(key).unwrap();
+                            self.kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                            response_vals.append(dynamic_object_arena_hdr::CFBytes::new(
+                                old_value.as_ref(),
+                                datapath,
+                                arena,
+                            )?);
+                        }
+                        datapath.queue_cornflakes_arena_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            follow_unfollow_response,
+                            end_batch,
                         )?;
                     }
-                    let mut put_resp = kv_serializer_hybrid_arena_object::PutResp::new_in(arena);
-                    put_resp.set_id(putlist_req.get_id());
-                    datapath.queue_cornflakes_arena_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        put_resp,
-                        end_batch,
-                    )?;
                 }
-                MsgType::AddUser => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle add user");
-                    let mut add_user = kv_serializer_hybrid_arena_object::AddUser::new_in(arena);
-                    add_user.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-
-                    let mut add_user_response =
-                        kv_serializer_hybrid_arena_object::AddUserResponse::new_in(arena);
-                    match self.serializer.use_linked_list() {
-                        true => {
-                            let value = self
-                                .linked_list_kv_server
-                                .remove(add_user.get_keys()[0].to_str()?)
-                                .unwrap();
-                            add_user_response.set_first_value(
-                                dynamic_object_arena_hdr::CFBytes::new(
-                                    value.as_ref().as_ref(),
-                                    datapath,
-                                    arena,
-                                )?,
-                            );
-                            for (key, value) in
-                                add_user.get_keys().iter().zip(add_user.get_values().iter())
-                            {
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            datapath.queue_cornflakes_arena_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                add_user_response,
-                                end_batch,
+            }
+            MsgType::PostTweet => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle post tweet");
+                let mut post_tweet = kv_serializer_hybrid_arena_object::PostTweet::new_in(arena);
+                post_tweet.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+
+                let mut post_tweet_response =
+                    kv_serializer_hybrid_arena_object::PostTweetResponse::new_in(arena);
+                post_tweet_response.init_values(3, arena);
+                let response_vals = post_tweet_response.get_mut_values();
+                match self.serializer.use_linked_list() {
+                    true => {
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .take(3)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.linked_list_kv_server.remove(key).unwrap();
+                            self.linked_list_kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
                             )?;
+                            response_vals.append(dynamic_object_arena_hdr::CFBytes::new(
+                                old_value.as_ref().as_ref(),
+                                datapath,
+                                arena,
+                            )?);
                         }
-                        false => {
-                            let value = self
-                                .kv_server
-                                .remove(add_user.get_keys()[0].to_str()?)
-                                .unwrap();
-                            add_user_response.set_first_value(
-                                dynamic_object_arena_hdr::CFBytes::new(
-                                    value.as_ref(),
-                                    datapath,
-                                    arena,
-                                )?,
-                            );
-                            for (key, value) in
-                                add_user.get_keys().iter().zip(add_user.get_values().iter())
-                            {
-                                self.kv_server.insert_with_copies(
-                                    key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            datapath.queue_cornflakes_arena_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                add_user_response,
-                                end_batch,
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .skip(3)
+                            .take(2)
+                        {
+                            self.linked_list_kv_server.insert_with_copies(
+                                cf_key.to_str()?,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
                             )?;
                         }
+                        datapath.queue_cornflakes_arena_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            post_tweet_response,
+                            end_batch,
+                        )?;
                     }
-                }
-                MsgType::FollowUnfollow => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle follow unfollow");
-                    let mut follow_unfollow =
-                        kv_serializer_hybrid_arena_object::FollowUnfollow::new_in(arena);
-                    follow_unfollow.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-                    tracing::debug!("Deserialized follow unfollow: {:?}", follow_unfollow);
-                    let mut follow_unfollow_response =
-                        kv_serializer_hybrid_arena_object::FollowUnfollowResponse::new_in(arena);
-                    follow_unfollow_response.init_original_values(2, arena);
-                    let response_vals = follow_unfollow_response.get_mut_original_values();
-                    match self.serializer.use_linked_list() {
-                        true => {
-                            for (cf_key, value) in follow_unfollow
-                                .get_keys()
-                                .iter()
-                                .zip(follow_unfollow.get_values().iter())
-                                .take(2)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.linked_list_kv_server.remove(key).unwrap();
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_object_arena_hdr::CFBytes::new(
-                                    old_value.as_ref().as_ref(),
-                                    datapath,
-                                    arena,
-                                )?);
-                            }
-                            datapath.queue_cornflakes_arena_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                follow_unfollow_response,
-                                end_batch,
+                    false => {
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .take(3)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.kv_server.remove(key).unwrap();
+                            self.kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
                             )?;
+                            response_vals.append(dynamic_object_arena_hdr::CFBytes::new(
+                                old_value.as_ref(),
+                                datapath,
+                                arena,
+                            )?);
                         }
-                        false => {
-                            for (cf_key, value) in follow_unfollow
-                                .get_keys()
-                                .iter()
-                                .zip(follow_unfollow.get_values().iter())
-                                .take(2)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.kv_server.remove(key).unwrap();
-                                self.kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_object_arena_hdr::CFBytes::new(
-                                    old_value.as_ref(),
-                                    datapath,
-                                    arena,
-                                )?);
-                            }
-                            datapath.queue_cornflakes_arena_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                follow_unfollow_response,
-                                end_batch,
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .skip(3)
+                            .take(2)
+                        {
+                            self.kv_server.insert_with_copies(
+                                cf_key.to_str()?,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
                             )?;
                         }
+
+                        datapath.queue_cornflakes_arena_object(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            post_tweet_response,
+                            end_batch,
+                        )?;
                     }
                 }
-                MsgType::PostTweet => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle post tweet");
-                    let mut post_tweet =
-                        kv_serializer_hybrid_arena_object::PostTweet::new_in(arena);
-                    post_tweet.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-
-                    let mut post_tweet_response =
-                        kv_serializer_hybrid_arena_object::PostTweetResponse::new_in(arena);
-                    post_tweet_response.init_values(3, arena);
-                    let response_vals = post_tweet_response.get_mut_values();
-                    match self.serializer.use_linked_list() {
+            }
+            MsgType::GetTimeline(_) => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle get timeline");
+                let mut get_timeline =
+                    kv_serializer_hybrid_arena_object::GetTimeline::new_in(arena);
+                get_timeline.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+
+                let mut get_timeline_response =
+                    kv_serializer_hybrid_arena_object::GetTimelineResponse::new_in(arena);
+                get_timeline_response.init_values(get_timeline.get_keys().len(), arena);
+                let response_vals = get_timeline_response.get_mut_values();
+                for (_i, key) in get_timeline.get_keys().iter().enumerate() {
+                    let cf_bytes = match self.serializer.use_linked_list() {
                         true => {
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .take(3)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.linked_list_kv_server.remove(key).unwrap();
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_object_arena_hdr::CFBytes::new(
-                                    old_value.as_ref().as_ref(),
-                                    datapath,
-                                    arena,
-                                )?);
-                            }
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .skip(3)
-                                .take(2)
-                            {
-                                self.linked_list_kv_server.insert_with_copies(
-                                    cf_key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            datapath.queue_cornflakes_arena_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                post_tweet_response,
-                                end_batch,
-                            )?;
+                            let val = self.linked_list_kv_server.get(key.to_str()?).unwrap();
+                            tracing::debug!(
+                                msg_id = pkt.msg_id(),
+                                conn_id = pkt.conn_id(),
+                                key_idx = _i,
+                                "Get timeline val size {}",
+                                val.as_ref().as_ref().len()
+                            );
+                            dynamic_object_arena_hdr::CFBytes::new(
+                                val.as_ref().as_ref(),
+                                datapath,
+                                arena,
+                            )?
                         }
                         false => {
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .take(3)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.kv_server.remove(key).unwrap();
-                                self.kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_object_arena_hdr::CFBytes::new(
-                                    old_value.as_ref(),
-                                    datapath,
-                                    arena,
-                                )?);
-                            }
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .skip(3)
-                                .take(2)
-                            {
-                                self.kv_server.insert_with_copies(
-                                    cf_key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-
-                            datapath.queue_cornflakes_arena_object(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                post_tweet_response,
-                                end_batch,
-                            )?;
+                            let val = self.kv_server.get(key.to_str()?).unwrap();
+                            tracing::debug!(
+                                msg_id = pkt.msg_id(),
+                                conn_id = pkt.conn_id(),
+                                key_idx = _i,
+                                "Get timeline val size {}",
+                                val.as_ref().len()
+                            );
+                            dynamic_object_arena_hdr::CFBytes::new(val.as_ref(), datapath, arena)?
                         }
-                    }
+                    };
+                    response_vals.append(cf_bytes);
                 }
-                MsgType::GetTimeline(_) => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle get timeline");
-                    let mut get_timeline =
-                        kv_serializer_hybrid_arena_object::GetTimeline::new_in(arena);
-                    get_timeline.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-
-                    let mut get_timeline_response =
-                        kv_serializer_hybrid_arena_object::GetTimelineResponse::new_in(arena);
-                    get_timeline_response.init_values(get_timeline.get_keys().len(), arena);
-                    let response_vals = get_timeline_response.get_mut_values();
-                    for (_i, key) in get_timeline.get_keys().iter().enumerate() {
-                        let cf_bytes = match self.serializer.use_linked_list() {
-                            true => {
-                                let val = self.linked_list_kv_server.get(key.to_str()?).unwrap();
-                                tracing::debug!(
-                                    msg_id = pkt.msg_id(),
-                                    conn_id = pkt.conn_id(),
-                                    key_idx = _i,
-                                    "Get timeline val size {}",
-                                    val.as_ref().as_ref().len()
-                                );
-                                dynamic_object_arena_hdr::CFBytes::new(
-                                    val.as_ref().as_ref(),
-                                    datapath,
-                                    arena,
-                                )?
-                            }
-                            false => {
-                                let val = self.kv_server.get(key.to_str()?).unwrap();
-                                tracing::debug!(
-                                    msg_id = pkt.msg_id(),
-                                    conn_id = pkt.conn_id(),
-                                    key_idx = _i,
-                                    "Get timeline val size {}",
-                                    val.as_ref().len()
-                                );
-                                dynamic_object_arena_hdr::CFBytes::new(
-                                    val.as_ref(),
-                                    datapath,
-                                    arena,
-                                )?
-                            }
-                        };
-                        response_vals.append(cf_bytes);
-                    }
+                tracing::debug!(
+                    "Sending back get timeline response with msg id {}",
+                    pkt.msg_id()
+                );
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    get_timeline_response,
+                    end_batch,
+                )?;
+            }
+            MsgType::Delete => {
+                let mut delete_req = kv_serializer_hybrid_arena_object::DeleteReq::new_in(arena);
+                delete_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                let mut put_resp = kv_serializer_hybrid_arena_object::PutResp::new_in(arena);
+                put_resp.set_id(delete_req.get_id());
+                if !delete_from_stores(
+                    delete_req.get_key().to_str()?,
+                    &mut self.kv_server,
+                    &mut self.list_kv_server,
+                    &mut self.linked_list_kv_server,
+                ) {
                     tracing::debug!(
-                        "Sending back get timeline response with msg id {}",
-                        pkt.msg_id()
+                        key =? delete_req.get_key().to_str(),
+                        "Could not find key to delete"
                     );
-                    datapath.queue_cornflakes_arena_object(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        get_timeline_response,
-                        end_batch,
-                    )?;
+                    put_resp.set_status(ResponseStatus::NotFound.as_u32());
                 }
-                _ => {
-                    unimplemented!();
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    put_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::DeleteM(_) => {
+                let mut deletem_req = kv_serializer_hybrid_arena_object::DeleteMReq::new_in(arena);
+                deletem_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                let mut status = ResponseStatus::Ok;
+                for key in deletem_req.get_keys().iter() {
+                    if !delete_from_stores(
+                        key.to_str()?,
+                        &mut self.kv_server,
+                        &mut self.list_kv_server,
+                        &mut self.linked_list_kv_server,
+                    ) {
+                        tracing::debug!(key =? key.to_str(), "Could not find key to delete");
+                        status = ResponseStatus::NotFound;
+                    }
                 }
+                let mut put_resp = kv_serializer_hybrid_arena_object::PutResp::new_in(arena);
+                put_resp.set_id(deletem_req.get_id());
+                put_resp.set_status(status.as_u32());
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    put_resp,
+                    end_batch,
+                )?;
             }
-        }
-        Ok(())
-    }
-
-    #[inline]
-    fn process_requests_object(
-        &mut self,
-        sga: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
-        datapath: &mut Self::Datapath,
-        arena: &mut bumpalo::Bump,
-    ) -> Result<()> {
-        let end = sga.len();
-        for (i, pkt) in sga.into_iter().enumerate() {
-            let end_batch = i == (end - 1);
-            let msg_type = MsgType::from_packet(&pkt)?;
-            tracing::debug!(
-                msg_type =? msg_type,
-                "Received packet with data {:?} ptr and length {}",
-                pkt.seg(0).as_ref().as_ptr(),
-                pkt.data_len()
-            );
-            match msg_type {
-                MsgType::Get => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle get serialize and send");
-                    self.serializer.handle_get_serialize_and_send(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        end_batch,
-                        &self.kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
+            MsgType::CompareAndSwap => {
+                let mut cas_req = kv_serializer_hybrid_arena_object::CASReq::new_in(arena);
+                cas_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                let (status, version) = match self.serializer.use_linked_list() {
+                    true => self.linked_list_kv_server.compare_and_swap(
+                        cas_req.get_key().to_str()?,
+                        cas_req.get_version(),
+                        cas_req.get_val().as_ref(),
                         datapath,
-                        &arena,
-                    )?;
-                }
-                MsgType::Put => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle put serialize and send");
-                    self.serializer.handle_put_serialize_and_send(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        end_batch,
-                        &mut self.kv_server,
-                        &mut self.linked_list_kv_server,
                         &mut self.mempool_ids,
-                        &pkt,
+                    )?,
+                    false => self.kv_server.compare_and_swap(
+                        cas_req.get_key().to_str()?,
+                        cas_req.get_version(),
+                        cas_req.get_val().as_ref(),
                         datapath,
-                        &arena,
-                    )?;
-                }
-                MsgType::GetM(_size) => {
-                    self.serializer.handle_getm_serialize_and_send(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        end_batch,
-                        &self.kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                        datapath,
-                        arena,
-                    )?;
-                }
-                MsgType::PutM(_size) => {
-                    unimplemented!();
+                        &mut self.mempool_ids,
+                    )?,
+                };
+                let mut cas_resp = kv_serializer_hybrid_arena_object::CASResp::new_in(arena);
+                cas_resp.set_id(cas_req.get_id());
+                cas_resp.set_version(version);
+                cas_resp.set_status(status.as_u32());
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    cas_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::Increment | MsgType::Decrement => {
+                let mut incr_req = kv_serializer_hybrid_arena_object::IncrReq::new_in(arena);
+                incr_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                let negate = msg_type == MsgType::Decrement;
+                let (status, value) = match signed_delta(incr_req.get_delta(), negate) {
+                    Some(delta) => match self.serializer.use_linked_list() {
+                        true => self.linked_list_kv_server.increment(
+                            incr_req.get_key().to_str()?,
+                            delta,
+                            datapath,
+                            &mut self.mempool_ids,
+                        )?,
+                        false => self.kv_server.increment(
+                            incr_req.get_key().to_str()?,
+                            delta,
+                            datapath,
+                            &mut self.mempool_ids,
+                        )?,
+                    },
+                    None => (ResponseStatus::BadRequest, 0),
+                };
+                let mut incr_resp = kv_serializer_hybrid_arena_object::IncrResp::new_in(arena);
+                incr_resp.set_id(incr_req.get_id());
+                incr_resp.set_val(value);
+                incr_resp.set_status(status.as_u32());
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    incr_resp,
+                    end_batch,
+                )?;
+            }
+            MsgType::Scan => {
+                let mut scan_req = kv_serializer_hybrid_arena_object::ScanReq::new_in(arena);
+                scan_req.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                let values = self.serializer.scan(
+                    &self.kv_server,
+                    scan_req.get_start_key().to_str()?,
+                    scan_req.get_end_key().to_str()?,
+                    scan_req.get_count(),
+                );
+                let mut getm_resp = kv_serializer_hybrid_arena_object::GetMResp::new_in(arena);
+                getm_resp.set_id(scan_req.get_id());
+                match values {
+                    Some(values) => {
+                        getm_resp.init_vals(values.len(), arena);
+                        let vals = getm_resp.get_mut_vals();
+                        for value in values.iter() {
+                            // values above the copying threshold go out zero-copy,
+                            // straight from the stored buffers
+                            vals.append(dynamic_object_arena_hdr::CFBytes::new(
+                                value.as_ref(),
+                                datapath,
+                                arena,
+                            )?);
+                        }
+                    }
+                    None => {
+                        tracing::debug!("Received scan, but the kv store keeps no ordered index");
+                        getm_resp.set_status(ResponseStatus::BadRequest.as_u32());
+                    }
                 }
-                MsgType::GetList(_size) => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle getlist and queue cornflakes obj");
-                    self.serializer.handle_getlist_serialize_and_send(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        end_batch,
-                        &self.list_kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                        datapath,
-                        &arena,
-                    )?;
+                datapath.queue_cornflakes_arena_object(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    getm_resp,
+                    end_batch,
+                )?;
+            }
+            _ => {
+                bail!("Cornflakes server does not support {:?} requests", msg_type);
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles one request for `process_requests_object`.
+    fn process_request_object(
+        &mut self,
+        msg_type: MsgType,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        arena: &bumpalo::Bump,
+        end_batch: bool,
+    ) -> Result<()> {
+        match msg_type {
+            MsgType::Get => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle get serialize and send");
+                self.serializer.handle_get_serialize_and_send(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    end_batch,
+                    &self.kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    datapath,
+                    arena,
+                )?;
+            }
+            MsgType::Put => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle put serialize and send");
+                self.serializer.handle_put_serialize_and_send(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    end_batch,
+                    &mut self.kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    arena,
+                )?;
+            }
+            MsgType::GetM(_size) => {
+                self.serializer.handle_getm_serialize_and_send(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    end_batch,
+                    &self.kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    datapath,
+                    arena,
+                )?;
+            }
+            MsgType::PutM(_size) => {
+                bail!("Cornflakes server does not support {:?} requests", msg_type);
+            }
+            MsgType::GetList(_size) => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle getlist and queue cornflakes obj");
+                self.serializer.handle_getlist_serialize_and_send(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    end_batch,
+                    &self.list_kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    datapath,
+                    arena,
+                )?;
+            }
+            MsgType::PutList(_size) => {
+                bail!("Cornflakes server does not support {:?} requests", msg_type);
+            }
+            MsgType::AddUser => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle add user");
+                let mut add_user = kv_serializer_hybrid::AddUser::<D>::new_in(arena);
+                add_user.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+
+                let mut add_user_response =
+                    kv_serializer_hybrid::AddUserResponse::<D>::new_in(arena);
+                let mut copy_context = CopyContext::new(arena, datapath)?;
+                match self.serializer.use_linked_list() {
+                    true => {
+                        let value = self
+                            .linked_list_kv_server
+                            .remove(add_user.get_keys()[0].to_str()?)
+                            .unwrap();
+                        // will increment the reference count of value, or copy into the copy
+                        // context
+                        add_user_response.set_first_value(dynamic_rcsga_hybrid_hdr::CFBytes::new(
+                            value.as_ref().as_ref(),
+                            datapath,
+                            &mut copy_context,
+                        )?);
+                        for (key, value) in
+                            add_user.get_keys().iter().zip(add_user.get_values().iter())
+                        {
+                            self.linked_list_kv_server.insert_with_copies(
+                                key.to_str()?,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                        }
+                        datapath.queue_cornflakes_obj(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            &mut copy_context,
+                            add_user_response,
+                            end_batch,
+                        )?;
+                    }
+                    false => {
+                        let value = self
+                            .kv_server
+                            .remove(add_user.get_keys()[0].to_str()?)
+                            .unwrap();
+                        add_user_response.set_first_value(dynamic_rcsga_hybrid_hdr::CFBytes::new(
+                            value.as_ref(),
+                            datapath,
+                            &mut copy_context,
+                        )?);
+                        for (key, value) in
+                            add_user.get_keys().iter().zip(add_user.get_values().iter())
+                        {
+                            self.kv_server.insert_with_copies(
+                                key.to_str()?,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                        }
+                        datapath.queue_cornflakes_obj(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            &mut copy_context,
+                            add_user_response,
+                            end_batch,
+                        )?;
+                    }
                 }
-                MsgType::PutList(_size) => {
-                    unimplemented!();
+            }
+            MsgType::FollowUnfollow => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle follow unfollow");
+                let mut follow_unfollow = kv_serializer_hybrid::FollowUnfollow::<D>::new_in(arena);
+                follow_unfollow.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+                tracing::debug!("Deserialized follow unfollow: {:?}", follow_unfollow);
+                let mut follow_unfollow_response =
+                    kv_serializer_hybrid::FollowUnfollowResponse::<D>::new_in(arena);
+                let mut copy_context = CopyContext::new(arena, datapath)?;
+                follow_unfollow_response.init_original_values(2, arena);
+                let response_vals = follow_unfollow_response.get_mut_original_values();
+                match self.serializer.use_linked_list() {
+                    true => {
+                        for (cf_key, value) in follow_unfollow
+                            .get_keys()
+                            .iter()
+                            .zip(follow_unfollow.get_values().iter())
+                            .take(2)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.linked_list_kv_server.remove(key).unwrap();
+                            self.linked_list_kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                            response_vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::new(
+                                old_value.as_ref().as_ref(),
+                                datapath,
+                                &mut copy_context,
+                            )?);
+                        }
+                        datapath.queue_cornflakes_obj(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            &mut copy_context,
+                            follow_unfollow_response,
+                            end_batch,
+                        )?;
+                    }
+                    false => {
+                        for (cf_key, value) in follow_unfollow
+                            .get_keys()
+                            .iter()
+                            .zip(follow_unfollow.get_values().iter())
+                            .take(2)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.kv_server.remove(key).unwrap();
+                            self.kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                            response_vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::new(
+                                old_value.as_ref(),
+                                datapath,
+                                &mut copy_context,
+                            )?);
+                        }
+                        datapath.queue_cornflakes_obj(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            &mut copy_context,
+                            follow_unfollow_response,
+                            end_batch,
+                        )?;
+                    }
                 }
-                MsgType::AddUser => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle add user");
-                    let mut add_user = kv_serializer_hybrid::AddUser::<D>::new_in(arena);
-                    add_user.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
+            }
+            MsgType::PostTweet => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle post tweet");
+                let mut post_tweet = kv_serializer_hybrid::PostTweet::<D>::new_in(arena);
+                post_tweet.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+
+                let mut post_tweet_response =
+                    kv_serializer_hybrid::PostTweetResponse::<D>::new_in(arena);
+                let mut copy_context = CopyContext::new(arena, datapath)?;
+                post_tweet_response.init_values(3, arena);
+                let response_vals = post_tweet_response.get_mut_values();
+                match self.serializer.use_linked_list() {
+                    true => {
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .take(3)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.linked_list_kv_server.remove(key).unwrap();
+                            self.linked_list_kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                            response_vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::new(
+                                old_value.as_ref().as_ref(),
+                                datapath,
+                                &mut copy_context,
+                            )?);
+                        }
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .skip(3)
+                            .take(2)
+                        {
+                            self.linked_list_kv_server.insert_with_copies(
+                                cf_key.to_str()?,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                        }
+                        datapath.queue_cornflakes_obj(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            &mut copy_context,
+                            post_tweet_response,
+                            end_batch,
+                        )?;
+                    }
+                    false => {
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .take(3)
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.kv_server.remove(key).unwrap();
+                            self.kv_server.insert_with_copies(
+                                key,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                            response_vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::new(
+                                old_value.as_ref(),
+                                datapath,
+                                &mut copy_context,
+                            )?);
+                        }
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .skip(3)
+                            .take(2)
+                        {
+                            self.kv_server.insert_with_copies(
+                                cf_key.to_str()?,
+                                value.as_ref(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                        }
 
-                    let mut add_user_response =
-                        kv_serializer_hybrid::AddUserResponse::<D>::new_in(arena);
-                    let mut copy_context = CopyContext::new(arena, datapath)?;
-                    match self.serializer.use_linked_list() {
+                        datapath.queue_cornflakes_obj(
+                            pkt.msg_id(),
+                            pkt.conn_id(),
+                            &mut copy_context,
+                            post_tweet_response,
+                            end_batch,
+                        )?;
+                    }
+                }
+            }
+            MsgType::GetTimeline(_) => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle get timeline");
+                let mut get_timeline = kv_serializer_hybrid::GetTimeline::<D>::new_in(arena);
+                get_timeline.deserialize(pkt, REQ_TYPE_SIZE, arena)?;
+
+                let mut get_timeline_response =
+                    kv_serializer_hybrid::GetTimelineResponse::<D>::new_in(arena);
+                let mut copy_context = CopyContext::new(arena, datapath)?;
+                get_timeline_response.init_values(get_timeline.get_keys().len(), arena);
+                let response_vals = get_timeline_response.get_mut_values();
+                for (_i, key) in get_timeline.get_keys().iter().enumerate() {
+                    let cf_bytes = match self.serializer.use_linked_list() {
                         true => {
-                            let value = self
-                                .linked_list_kv_server
-                                .remove(add_user.get_keys()[0].to_str()?)
-                                .unwrap();
-                            // will increment the reference count of value, or copy into the copy
-                            // context
-                            add_user_response.set_first_value(
-                                dynamic_rcsga_hybrid_hdr::CFBytes::new(
-                                    value.as_ref().as_ref(),
-                                    datapath,
-                                    &mut copy_context,
-                                )?,
+                            let val = self.linked_list_kv_server.get(key.to_str()?).unwrap();
+                            tracing::debug!(
+                                msg_id = pkt.msg_id(),
+                                conn_id = pkt.conn_id(),
+                                key_idx = _i,
+                                "Get timeline val size {}",
+                                val.as_ref().as_ref().len()
                             );
-                            for (key, value) in
-                                add_user.get_keys().iter().zip(add_user.get_values().iter())
-                            {
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            datapath.queue_cornflakes_obj(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
+                            dynamic_rcsga_hybrid_hdr::CFBytes::new(
+                                val.as_ref().as_ref(),
+                                datapath,
                                 &mut copy_context,
-                                add_user_response,
-                                end_batch,
-                            )?;
+                            )?
                         }
                         false => {
-                            let value = self
-                                .kv_server
-                                .remove(add_user.get_keys()[0].to_str()?)
-                                .unwrap();
-                            add_user_response.set_first_value(
-                                dynamic_rcsga_hybrid_hdr::CFBytes::new(
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut copy_context,
-                                )?,
+                            let val = self.kv_server.get(key.to_str()?).unwrap();
+                            tracing::debug!(
+                                msg_id = pkt.msg_id(),
+                                conn_id = pkt.conn_id(),
+                                key_idx = _i,
+                                "Get timeline val size {}",
+                                val.as_ref().len()
                             );
-                            for (key, value) in
-                                add_user.get_keys().iter().zip(add_user.get_values().iter())
-                            {
-                                self.kv_server.insert_with_copies(
-                                    key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            datapath.queue_cornflakes_obj(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
+                            dynamic_rcsga_hybrid_hdr::CFBytes::new(
+                                val.as_ref(),
+                                datapath,
                                 &mut copy_context,
-                                add_user_response,
-                                end_batch,
+                            )?
+                        }
+                    };
+                    response_vals.append(cf_bytes);
+                }
+                tracing::debug!(
+                    "Sending back get timeline response with msg id {}",
+                    pkt.msg_id()
+                );
+                datapath.queue_cornflakes_obj(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    &mut copy_context,
+                    get_timeline_response,
+                    end_batch,
+                )?;
+            }
+            MsgType::Scan => {
+                self.serializer.handle_scan_serialize_and_send(
+                    pkt.msg_id(),
+                    pkt.conn_id(),
+                    end_batch,
+                    &self.kv_server,
+                    pkt,
+                    datapath,
+                    arena,
+                )?;
+            }
+            MsgType::Delete
+            | MsgType::DeleteM(_)
+            | MsgType::CompareAndSwap
+            | MsgType::Increment
+            | MsgType::Decrement => {
+                self.process_request_without_values(msg_type, pkt, datapath, end_batch)?;
+            }
+            _ => {
+                bail!("Cornflakes server does not support {:?} requests", msg_type);
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles one request for `process_requests_arena_ordered_sga`.
+    fn process_request_arena_ordered_sga(
+        &mut self,
+        msg_type: MsgType,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        arena: &bumpalo::Bump,
+        end_batch: bool,
+    ) -> Result<()> {
+        match msg_type {
+            MsgType::Get => {
+                let sga = self.serializer.handle_get(
+                    &self.kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    datapath,
+                    arena,
+                )?;
+                datapath.queue_arena_datapath_sga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
+            }
+            MsgType::Put => {
+                let sga = self.serializer.handle_put(
+                    &mut self.kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    arena,
+                )?;
+                datapath
+                    .queue_arena_ordered_rcsga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
+            }
+            MsgType::GetM(_size) => {
+                let sga = self.serializer.handle_getm(
+                    &self.kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    datapath,
+                    arena,
+                )?;
+                datapath.queue_arena_datapath_sga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
+            }
+            MsgType::PutM(_size) => {
+                let sga = self.serializer.handle_putm(
+                    &mut self.kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    arena,
+                )?;
+                datapath
+                    .queue_arena_ordered_rcsga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
+            }
+            MsgType::GetList(_size) => {
+                let sga = self.serializer.handle_getlist(
+                    &self.list_kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    datapath,
+                    arena,
+                )?;
+                datapath.queue_arena_datapath_sga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
+            }
+            MsgType::PutList(_size) => {
+                let sga = self.serializer.handle_putlist(
+                    &mut self.list_kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    arena,
+                )?;
+                datapath
+                    .queue_arena_ordered_rcsga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
+            }
+            MsgType::AddUser => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle add user");
+                let mut add_user = AddUser::<D>::new();
+                add_user.deserialize_from_pkt(pkt, REQ_TYPE_SIZE)?;
+
+                let mut add_user_response = AddUserResponse::<D>::new();
+                match self.serializer.use_linked_list() {
+                    true => {
+                        /*let value = self
+                            .linked_list_kv_server
+                            .remove(add_user.get_keys()[0].to_str()?)
+                            .unwrap();
+                        add_user_response
+                            .set_first_value(CFBytes::new(value.as_ref().as_ref(), datapath)?);
+                        for (key, value) in
+                            add_user.get_keys().iter().zip(add_user.get_values().iter())
+                        {
+                            self.linked_list_kv_server.insert_with_copies(
+                                key.to_str()?,
+                                value.as_bytes(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                        }
+                        let mut arena_sga = ArenaOrderedRcSga::allocate(
+                            add_user_response.num_scatter_gather_entries(),
+                            arena,
+                        );
+                        add_user_response.serialize_into_arena_sga(
+                            &mut arena_sga,
+                            arena,
+                            datapath,
+                            self.serializer.with_copies(),
+                        )?;
+                        datapath.queue_arena_ordered_rcsga(
+                            (pkt.msg_id(), pkt.conn_id(), arena_sga),
+                            end_batch,
+                        )?*/
+                        bail!(
+                            "The linked list store does not support {:?} requests",
+                            msg_type
+                        );
+                    }
+                    false => {
+                        let value = self
+                            .kv_server
+                            .remove(add_user.get_keys()[0].to_str()?)
+                            .unwrap();
+                        add_user_response.set_first_value(CFBytes::new(value.as_ref(), datapath)?);
+                        for (key, value) in
+                            add_user.get_keys().iter().zip(add_user.get_values().iter())
+                        {
+                            self.kv_server.insert_with_copies(
+                                key.to_str()?,
+                                value.as_bytes(),
+                                datapath,
+                                &mut self.mempool_ids,
                             )?;
                         }
+                        let mut arena_sga = ArenaOrderedRcSga::allocate(
+                            add_user_response.num_scatter_gather_entries(),
+                            arena,
+                        );
+                        add_user_response.serialize_into_arena_sga(
+                            &mut arena_sga,
+                            arena,
+                            datapath,
+                            self.serializer.with_copies(),
+                        )?;
+
+                        datapath.queue_arena_ordered_rcsga(
+                            (pkt.msg_id(), pkt.conn_id(), arena_sga),
+                            end_batch,
+                        )?;
                     }
                 }
-                MsgType::FollowUnfollow => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle follow unfollow");
-                    let mut follow_unfollow =
-                        kv_serializer_hybrid::FollowUnfollow::<D>::new_in(arena);
-                    follow_unfollow.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-                    tracing::debug!("Deserialized follow unfollow: {:?}", follow_unfollow);
-                    let mut follow_unfollow_response =
-                        kv_serializer_hybrid::FollowUnfollowResponse::<D>::new_in(arena);
-                    let mut copy_context = CopyContext::new(arena, datapath)?;
-                    follow_unfollow_response.init_original_values(2, arena);
-                    let response_vals = follow_unfollow_response.get_mut_original_values();
-                    match self.serializer.use_linked_list() {
-                        true => {
-                            for (cf_key, value) in follow_unfollow
-                                .get_keys()
-                                .iter()
-                                .zip(follow_unfollow.get_values().iter())
-                                .take(2)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.linked_list_kv_server.remove(key).unwrap();
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::new(
-                                    old_value.as_ref().as_ref(),
-                                    datapath,
-                                    &mut copy_context,
-                                )?);
-                            }
-                            datapath.queue_cornflakes_obj(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                &mut copy_context,
-                                follow_unfollow_response,
-                                end_batch,
+            }
+            MsgType::FollowUnfollow => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle follow unfollow");
+                let mut follow_unfollow = FollowUnfollow::<D>::new();
+                follow_unfollow.deserialize_from_pkt(pkt, REQ_TYPE_SIZE)?;
+
+                tracing::debug!("Deserialized follow unfollow: {:?}", follow_unfollow);
+                let mut follow_unfollow_response = FollowUnfollowResponse::<D>::new();
+                follow_unfollow_response.init_original_values(2);
+                let response_vals = follow_unfollow_response.get_mut_original_values();
+                match self.serializer.use_linked_list() {
+                    true => {
+                        /*for (i, (cf_key, value)) in follow_unfollow
+                            .get_keys()
+                            .iter()
+                            .zip(follow_unfollow.get_values().iter())
+                            .take(2)
+                            .enumerate()
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.linked_list_kv_server.remove(key).unwrap();
+                            response_vals
+                                .append(CFBytes::new(old_value.as_ref().as_ref(), datapath)?);
+                            self.linked_list_kv_server.insert_with_copies(
+                                key,
+                                value.as_bytes(),
+                                datapath,
+                                &mut self.mempool_ids,
                             )?;
                         }
-                        false => {
-                            for (cf_key, value) in follow_unfollow
-                                .get_keys()
-                                .iter()
-                                .zip(follow_unfollow.get_values().iter())
-                                .take(2)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.kv_server.remove(key).unwrap();
-                                self.kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::new(
-                                    old_value.as_ref(),
-                                    datapath,
-                                    &mut copy_context,
-                                )?);
-                            }
-                            datapath.queue_cornflakes_obj(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                &mut copy_context,
-                                follow_unfollow_response,
-                                end_batch,
+                        let mut arena_sga = ArenaOrderedRcSga::allocate(
+                            follow_unfollow_response.num_scatter_gather_entries(),
+                            arena,
+                        );
+                        follow_unfollow_response.serialize_into_arena_sga(
+                            &mut arena_sga,
+                            arena,
+                            datapath,
+                            self.serializer.with_copies(),
+                        )?;
+
+                        datapath.queue_arena_ordered_rcsga(
+                            (pkt.msg_id(), pkt.conn_id(), arena_sga),
+                            end_batch,
+                        )?;*/
+                        bail!(
+                            "The linked list store does not support {:?} requests",
+                            msg_type
+                        );
+                    }
+                    false => {
+                        let mut old_values: [StoredValue<D>; 2] =
+                            [StoredValue::default(), StoredValue::default()];
+                        for (i, (cf_key, value)) in follow_unfollow
+                            .get_keys()
+                            .iter()
+                            .zip(follow_unfollow.get_values().iter())
+                            .take(2)
+                            .enumerate()
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.kv_server.remove(key).unwrap();
+                            old_values[i] = old_value;
+                            self.kv_server.insert_with_copies(
+                                key,
+                                value.as_bytes(),
+                                datapath,
+                                &mut self.mempool_ids,
                             )?;
                         }
+                        response_vals.append(CFBytes::new(old_values[0].as_ref(), datapath)?);
+                        response_vals.append(CFBytes::new(old_values[1].as_ref(), datapath)?);
+                        let mut arena_sga = ArenaOrderedRcSga::allocate(
+                            follow_unfollow_response.num_scatter_gather_entries(),
+                            arena,
+                        );
+                        follow_unfollow_response.serialize_into_arena_sga(
+                            &mut arena_sga,
+                            arena,
+                            datapath,
+                            self.serializer.with_copies(),
+                        )?;
+
+                        datapath.queue_arena_ordered_rcsga(
+                            (pkt.msg_id(), pkt.conn_id(), arena_sga),
+                            end_batch,
+                        )?;
                     }
                 }
-                MsgType::PostTweet => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle post tweet");
-                    let mut post_tweet = kv_serializer_hybrid::PostTweet::<D>::new_in(arena);
-                    post_tweet.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-
-                    let mut post_tweet_response =
-                        kv_serializer_hybrid::PostTweetResponse::<D>::new_in(arena);
-                    let mut copy_context = CopyContext::new(arena, datapath)?;
-                    post_tweet_response.init_values(3, arena);
-                    let response_vals = post_tweet_response.get_mut_values();
-                    match self.serializer.use_linked_list() {
-                        true => {
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .take(3)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.linked_list_kv_server.remove(key).unwrap();
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::new(
-                                    old_value.as_ref().as_ref(),
-                                    datapath,
-                                    &mut copy_context,
-                                )?);
-                            }
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .skip(3)
-                                .take(2)
-                            {
-                                self.linked_list_kv_server.insert_with_copies(
-                                    cf_key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            datapath.queue_cornflakes_obj(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                &mut copy_context,
-                                post_tweet_response,
-                                end_batch,
+            }
+            MsgType::PostTweet => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle post tweet");
+                let mut post_tweet = PostTweet::<D>::new();
+                post_tweet.deserialize_from_pkt(pkt, REQ_TYPE_SIZE)?;
+
+                let mut post_tweet_response = PostTweetResponse::<D>::new();
+                post_tweet_response.init_values(3);
+                let response_vals = post_tweet_response.get_mut_values();
+                match self.serializer.use_linked_list() {
+                    true => {
+                        /*for (i, (cf_key, value)) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .take(3)
+                            .enumerate()
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.linked_list_kv_server.remove(key).unwrap();
+                            response_vals
+                                .append(CFBytes::new(old_value.as_ref().as_ref(), datapath)?);
+                            self.linked_list_kv_server.insert_with_copies(
+                                key,
+                                value.as_bytes(),
+                                datapath,
+                                &mut self.mempool_ids,
                             )?;
                         }
-                        false => {
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .take(3)
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.kv_server.remove(key).unwrap();
-                                self.kv_server.insert_with_copies(
-                                    key,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                                response_vals.append(dynamic_rcsga_hybrid_hdr::CFBytes::new(
-                                    old_value.as_ref(),
-                                    datapath,
-                                    &mut copy_context,
-                                )?);
-                            }
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .skip(3)
-                                .take(2)
-                            {
-                                self.kv_server.insert_with_copies(
-                                    cf_key.to_str()?,
-                                    value.as_ref(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .skip(3)
+                            .take(2)
+                        {
+                            self.linked_list_kv_server.insert_with_copies(
+                                cf_key.to_str()?,
+                                value.as_bytes(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                        }
+                        let mut arena_sga = ArenaOrderedRcSga::allocate(
+                            post_tweet_response.num_scatter_gather_entries(),
+                            arena,
+                        );
+                        post_tweet_response.serialize_into_arena_sga(
+                            &mut arena_sga,
+                            arena,
+                            datapath,
+                            self.serializer.with_copies(),
+                        )?;
 
-                            datapath.queue_cornflakes_obj(
-                                pkt.msg_id(),
-                                pkt.conn_id(),
-                                &mut copy_context,
-                                post_tweet_response,
-                                end_batch,
+                        datapath.queue_arena_ordered_rcsga(
+                            (pkt.msg_id(), pkt.conn_id(), arena_sga),
+                            end_batch,
+                        )?;*/
+                        bail!(
+                            "The linked list store does not support {:?} requests",
+                            msg_type
+                        );
+                    }
+                    false => {
+                        let mut old_values: [StoredValue<D>; 3] = [
+                            StoredValue::default(),
+                            StoredValue::default(),
+                            StoredValue::default(),
+                        ];
+                        for (i, (cf_key, value)) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .take(3)
+                            .enumerate()
+                        {
+                            let key = cf_key.to_str()?;
+                            let old_value = self.kv_server.remove(key).unwrap();
+                            old_values[i] = old_value;
+                            self.kv_server.insert_with_copies(
+                                key,
+                                value.as_bytes(),
+                                datapath,
+                                &mut self.mempool_ids,
                             )?;
                         }
+                        response_vals.append(CFBytes::new(old_values[0].as_ref(), datapath)?);
+                        response_vals.append(CFBytes::new(old_values[1].as_ref(), datapath)?);
+                        response_vals.append(CFBytes::new(old_values[2].as_ref(), datapath)?);
+                        for (cf_key, value) in post_tweet
+                            .get_keys()
+                            .iter()
+                            .zip(post_tweet.get_values().iter())
+                            .skip(3)
+                            .take(2)
+                        {
+                            self.kv_server.insert_with_copies(
+                                cf_key.to_str()?,
+                                value.as_bytes(),
+                                datapath,
+                                &mut self.mempool_ids,
+                            )?;
+                        }
+                        let mut arena_sga = ArenaOrderedRcSga::allocate(
+                            post_tweet_response.num_scatter_gather_entries(),
+                            arena,
+                        );
+                        post_tweet_response.serialize_into_arena_sga(
+                            &mut arena_sga,
+                            arena,
+                            datapath,
+                            self.serializer.with_copies(),
+                        )?;
+
+                        datapath.queue_arena_ordered_rcsga(
+                            (pkt.msg_id(), pkt.conn_id(), arena_sga),
+                            end_batch,
+                        )?;
                     }
                 }
-                MsgType::GetTimeline(_) => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle get timeline");
-                    let mut get_timeline = kv_serializer_hybrid::GetTimeline::<D>::new_in(arena);
-                    get_timeline.deserialize(&pkt, REQ_TYPE_SIZE, arena)?;
-
-                    let mut get_timeline_response =
-                        kv_serializer_hybrid::GetTimelineResponse::<D>::new_in(arena);
-                    let mut copy_context = CopyContext::new(arena, datapath)?;
-                    get_timeline_response.init_values(get_timeline.get_keys().len(), arena);
-                    let response_vals = get_timeline_response.get_mut_values();
-                    for (_i, key) in get_timeline.get_keys().iter().enumerate() {
-                        let cf_bytes = match self.serializer.use_linked_list() {
-                            true => {
-                                let val = self.linked_list_kv_server.get(key.to_str()?).unwrap();
-                                tracing::debug!(
-                                    msg_id = pkt.msg_id(),
-                                    conn_id = pkt.conn_id(),
-                                    key_idx = _i,
-                                    "Get timeline val size {}",
-                                    val.as_ref().as_ref().len()
-                                );
-                                dynamic_rcsga_hybrid_hdr::CFBytes::new(
-                                    val.as_ref().as_ref(),
-                                    datapath,
-                                    &mut copy_context,
-                                )?
-                            }
-                            false => {
-                                let val = self.kv_server.get(key.to_str()?).unwrap();
-                                tracing::debug!(
-                                    msg_id = pkt.msg_id(),
-                                    conn_id = pkt.conn_id(),
-                                    key_idx = _i,
-                                    "Get timeline val size {}",
-                                    val.as_ref().len()
-                                );
-                                dynamic_rcsga_hybrid_hdr::CFBytes::new(
-                                    val.as_ref(),
-                                    datapath,
-                                    &mut copy_context,
-                                )?
-                            }
-                        };
-                        response_vals.append(cf_bytes);
-                    }
-                    tracing::debug!(
-                        "Sending back get timeline response with msg id {}",
-                        pkt.msg_id()
-                    );
-                    datapath.queue_cornflakes_obj(
-                        pkt.msg_id(),
-                        pkt.conn_id(),
-                        &mut copy_context,
-                        get_timeline_response,
-                        end_batch,
-                    )?;
+            }
+            MsgType::GetTimeline(_) => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("Handle get timeline");
+                let mut get_timeline = GetTimeline::<D>::new();
+                get_timeline.deserialize_from_pkt(pkt, REQ_TYPE_SIZE)?;
+
+                let mut get_timeline_response = GetTimelineResponse::<D>::new();
+                get_timeline_response.init_values(get_timeline.get_keys().len());
+                let response_vals = get_timeline_response.get_mut_values();
+                for (_i, key) in get_timeline.get_keys().iter().enumerate() {
+                    let cf_bytes = match self.serializer.use_linked_list() {
+                        true => {
+                            /*let val = self.linked_list_kv_server.get(key.to_str()?).unwrap();
+                            tracing::debug!(
+                                msg_id = pkt.msg_id(),
+                                conn_id = pkt.conn_id(),
+                                key_idx = _i,
+                                "Get timeline val size {}",
+                                val.as_ref().as_ref().len()
+                            );
+                            CFBytes::new(val.as_ref().as_ref(), datapath)?*/
+                            bail!(
+                                "The linked list store does not support {:?} requests",
+                                msg_type
+                            );
+                        }
+                        false => {
+                            let val = self.kv_server.get(key.to_str()?).unwrap();
+                            tracing::debug!(
+                                msg_id = pkt.msg_id(),
+                                conn_id = pkt.conn_id(),
+                                key_idx = _i,
+                                "Get timeline val size {}",
+                                val.as_ref().len()
+                            );
+                            CFBytes::new(val.as_ref(), datapath)?
+                        }
+                    };
+                    response_vals.append(cf_bytes);
+                }
+                let mut arena_sga = ArenaOrderedRcSga::allocate(
+                    get_timeline_response.num_scatter_gather_entries(),
+                    arena,
+                );
+                get_timeline_response.serialize_into_arena_sga(
+                    &mut arena_sga,
+                    arena,
+                    datapath,
+                    self.serializer.with_copies(),
+                )?;
+
+                tracing::debug!(
+                    "Sending back get timeline response with msg id {}",
+                    pkt.msg_id()
+                );
+                datapath.queue_arena_ordered_rcsga(
+                    (pkt.msg_id(), pkt.conn_id(), arena_sga),
+                    end_batch,
+                )?;
+            }
+            MsgType::Scan => {
+                let sga = self
+                    .serializer
+                    .handle_scan(&self.kv_server, pkt, datapath, arena)?;
+                datapath.queue_arena_datapath_sga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
+            }
+            MsgType::Delete
+            | MsgType::DeleteM(_)
+            | MsgType::CompareAndSwap
+            | MsgType::Increment
+            | MsgType::Decrement => {
+                self.process_request_without_values(msg_type, pkt, datapath, end_batch)?;
+            }
+            _ => {
+                bail!("Cornflakes server does not support {:?} requests", msg_type);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<D> ServerSM for CornflakesKVServer<D>
+where
+    D: Datapath,
+{
+    type Datapath = D;
+    #[inline]
+    fn push_buf_type(&self) -> PushBufType {
+        self.push_buf_type
+    }
+
+    fn steer_packets(
+        &mut self,
+        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        datapath: &mut Self::Datapath,
+    ) -> Result<Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>> {
+        let pkts = match &mut self.shard_router {
+            Some(router) => router.steer(pkts, datapath)?,
+            None => pkts,
+        };
+        match &mut self.replicator {
+            Some(replicator) => replicator.steer(pkts, datapath),
+            None => Ok(pkts),
+        }
+    }
+
+    fn log_stats(&self, datapath: &Self::Datapath) {
+        datapath.transmit_stats().dump("Server transmit stats");
+        datapath
+            .allocator_memory_stats()
+            .dump("Server allocator memory stats");
+        combined_cache_stats(
+            &self.kv_server,
+            &self.list_kv_server,
+            &self.linked_list_kv_server,
+        )
+        .dump("Server cache stats");
+        combined_storage_stats(
+            &self.kv_server,
+            &self.list_kv_server,
+            &self.linked_list_kv_server,
+        )
+        .dump("Server storage stats");
+        if let Some(router) = &self.shard_router {
+            router.stats().dump("Server shard stats");
+        }
+        if let Some(replicator) = &self.replicator {
+            replicator.stats().dump("Server replication stats");
+        }
+    }
+
+    #[inline]
+    fn process_requests_hybrid_arena_sga(
+        &mut self,
+        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        datapath: &mut Self::Datapath,
+        arena: &mut bumpalo::Bump,
+    ) -> Result<()> {
+        // queue as metadata vec in the datapath
+        // for microbenchmark
+        let end = pkts.len();
+        for (i, pkt) in pkts.into_iter().enumerate() {
+            let end_batch = i == (end - 1);
+            let msg_type = match MsgType::from_packet(&pkt) {
+                Ok(msg_type) => msg_type,
+                Err(e) => {
+                    let status = failed_request_status(&pkt, None, &e);
+                    queue_error_response(None, status, &pkt, datapath, end_batch)?;
+                    continue;
+                }
+            };
+            tracing::debug!(
+                msg_type =? msg_type,
+                "Received packet with data {:?} ptr and length {}",
+                pkt.seg(0).as_ref().as_ptr(),
+                pkt.data_len()
+            );
+            if let Err(e) =
+                self.process_request_hybrid_arena_sga(msg_type, &pkt, datapath, arena, end_batch)
+            {
+                let status = failed_request_status(&pkt, Some(msg_type), &e);
+                queue_error_response(Some(msg_type), status, &pkt, datapath, end_batch)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn process_requests_hybrid_object(
+        &mut self,
+        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        datapath: &mut Self::Datapath,
+    ) -> Result<()> {
+        let end = pkts.len();
+        for (i, pkt) in pkts.into_iter().enumerate() {
+            let end_batch = i == (end - 1);
+            let msg_type = match MsgType::from_packet(&pkt) {
+                Ok(msg_type) => msg_type,
+                Err(e) => {
+                    let status = failed_request_status(&pkt, None, &e);
+                    queue_error_response(None, status, &pkt, datapath, end_batch)?;
+                    continue;
+                }
+            };
+            tracing::debug!(
+                msg_type =? msg_type,
+                "Received packet with data {:?} ptr and length {}",
+                pkt.seg(0).as_ref().as_ptr(),
+                pkt.data_len()
+            );
+            if let Err(e) = self.process_request_hybrid_object(msg_type, &pkt, datapath, end_batch)
+            {
+                let status = failed_request_status(&pkt, Some(msg_type), &e);
+                queue_error_response(Some(msg_type), status, &pkt, datapath, end_batch)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn process_requests_hybrid_arena_object(
+        &mut self,
+        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        datapath: &mut Self::Datapath,
+        arena: &mut bumpalo::Bump,
+    ) -> Result<()> {
+        let end = pkts.len();
+        for (i, pkt) in pkts.into_iter().enumerate() {
+            let end_batch = i == (end - 1);
+            let msg_type = match MsgType::from_packet(&pkt) {
+                Ok(msg_type) => msg_type,
+                Err(e) => {
+                    let status = failed_request_status(&pkt, None, &e);
+                    queue_error_response(None, status, &pkt, datapath, end_batch)?;
+                    continue;
                 }
-                _ => {
-                    unimplemented!();
+            };
+            tracing::debug!(
+                msg_type =? msg_type,
+                "Received packet with data {:?} ptr and length {}",
+                pkt.seg(0).as_ref().as_ptr(),
+                pkt.data_len()
+            );
+            if let Err(e) =
+                self.process_request_hybrid_arena_object(msg_type, &pkt, datapath, arena, end_batch)
+            {
+                let status = failed_request_status(&pkt, Some(msg_type), &e);
+                queue_error_response(Some(msg_type), status, &pkt, datapath, end_batch)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn process_requests_object(
+        &mut self,
+        sga: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        datapath: &mut Self::Datapath,
+        arena: &mut bumpalo::Bump,
+    ) -> Result<()> {
+        let end = sga.len();
+        for (i, pkt) in sga.into_iter().enumerate() {
+            let end_batch = i == (end - 1);
+            let msg_type = match MsgType::from_packet(&pkt) {
+                Ok(msg_type) => msg_type,
+                Err(e) => {
+                    let status = failed_request_status(&pkt, None, &e);
+                    queue_error_response(None, status, &pkt, datapath, end_batch)?;
+                    continue;
                 }
+            };
+            tracing::debug!(
+                msg_type =? msg_type,
+                "Received packet with data {:?} ptr and length {}",
+                pkt.seg(0).as_ref().as_ptr(),
+                pkt.data_len()
+            );
+            if let Err(e) = self.process_request_object(msg_type, &pkt, datapath, arena, end_batch)
+            {
+                let status = failed_request_status(&pkt, Some(msg_type), &e);
+                queue_error_response(Some(msg_type), status, &pkt, datapath, end_batch)?;
             }
         }
 
@@ -2698,424 +3870,25 @@ where
         let end = sga.len();
         for (i, pkt) in sga.into_iter().enumerate() {
             let end_batch = i == (end - 1);
-            let msg_type = MsgType::from_packet(&pkt)?;
+            let msg_type = match MsgType::from_packet(&pkt) {
+                Ok(msg_type) => msg_type,
+                Err(e) => {
+                    let status = failed_request_status(&pkt, None, &e);
+                    queue_error_response(None, status, &pkt, datapath, end_batch)?;
+                    continue;
+                }
+            };
             tracing::debug!(
                 msg_type =? msg_type,
                 "Received packet with data {:?} ptr and length {}",
                 pkt.seg(0).as_ref().as_ptr(),
                 pkt.data_len()
             );
-            match msg_type {
-                MsgType::Get => {
-                    let sga = self.serializer.handle_get(
-                        &self.kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                        datapath,
-                        &arena,
-                    )?;
-                    datapath
-                        .queue_arena_datapath_sga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
-                }
-                MsgType::Put => {
-                    let sga = self.serializer.handle_put(
-                        &mut self.kv_server,
-                        &mut self.linked_list_kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
-                        datapath,
-                        &arena,
-                    )?;
-                    datapath
-                        .queue_arena_ordered_rcsga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
-                }
-                MsgType::GetM(_size) => {
-                    let sga = self.serializer.handle_getm(
-                        &self.kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                        datapath,
-                        &arena,
-                    )?;
-                    datapath
-                        .queue_arena_datapath_sga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
-                }
-                MsgType::PutM(_size) => {
-                    let sga = self.serializer.handle_putm(
-                        &mut self.kv_server,
-                        &mut self.linked_list_kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
-                        datapath,
-                        &arena,
-                    )?;
-                    datapath
-                        .queue_arena_ordered_rcsga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
-                }
-                MsgType::GetList(_size) => {
-                    let sga = self.serializer.handle_getlist(
-                        &self.list_kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                        datapath,
-                        &arena,
-                    )?;
-                    datapath
-                        .queue_arena_datapath_sga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
-                }
-                MsgType::PutList(_size) => {
-                    let sga = self.serializer.handle_putlist(
-                        &mut self.list_kv_server,
-                        &mut self.linked_list_kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
-                        datapath,
-                        &arena,
-                    )?;
-                    datapath
-                        .queue_arena_ordered_rcsga((pkt.msg_id(), pkt.conn_id(), sga), end_batch)?;
-                }
-                MsgType::AddUser => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle add user");
-                    let mut add_user = AddUser::<D>::new();
-                    add_user.deserialize_from_pkt(&pkt, REQ_TYPE_SIZE)?;
-
-                    let mut add_user_response = AddUserResponse::<D>::new();
-                    match self.serializer.use_linked_list() {
-                        true => {
-                            /*let value = self
-                                .linked_list_kv_server
-                                .remove(add_user.get_keys()[0].to_str()?)
-                                .unwrap();
-                            add_user_response
-                                .set_first_value(CFBytes::new(value.as_ref().as_ref(), datapath)?);
-                            for (key, value) in
-                                add_user.get_keys().iter().zip(add_user.get_values().iter())
-                            {
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key.to_str()?,
-                                    value.as_bytes(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            let mut arena_sga = ArenaOrderedRcSga::allocate(
-                                add_user_response.num_scatter_gather_entries(),
-                                &arena,
-                            );
-                            add_user_response.serialize_into_arena_sga(
-                                &mut arena_sga,
-                                arena,
-                                datapath,
-                                self.serializer.with_copies(),
-                            )?;
-                            datapath.queue_arena_ordered_rcsga(
-                                (pkt.msg_id(), pkt.conn_id(), arena_sga),
-                                end_batch,
-                            )?*/
-                            unimplemented!();
-                        }
-                        false => {
-                            let value = self
-                                .kv_server
-                                .remove(add_user.get_keys()[0].to_str()?)
-                                .unwrap();
-                            add_user_response
-                                .set_first_value(CFBytes::new(value.as_ref(), datapath)?);
-                            for (key, value) in
-                                add_user.get_keys().iter().zip(add_user.get_values().iter())
-                            {
-                                self.kv_server.insert_with_copies(
-                                    key.to_str()?,
-                                    value.as_bytes(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            let mut arena_sga = ArenaOrderedRcSga::allocate(
-                                add_user_response.num_scatter_gather_entries(),
-                                &arena,
-                            );
-                            add_user_response.serialize_into_arena_sga(
-                                &mut arena_sga,
-                                arena,
-                                datapath,
-                                self.serializer.with_copies(),
-                            )?;
-
-                            datapath.queue_arena_ordered_rcsga(
-                                (pkt.msg_id(), pkt.conn_id(), arena_sga),
-                                end_batch,
-                            )?;
-                        }
-                    }
-                }
-                MsgType::FollowUnfollow => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle follow unfollow");
-                    let mut follow_unfollow = FollowUnfollow::<D>::new();
-                    follow_unfollow.deserialize_from_pkt(&pkt, REQ_TYPE_SIZE)?;
-
-                    tracing::debug!("Deserialized follow unfollow: {:?}", follow_unfollow);
-                    let mut follow_unfollow_response = FollowUnfollowResponse::<D>::new();
-                    follow_unfollow_response.init_original_values(2);
-                    let response_vals = follow_unfollow_response.get_mut_original_values();
-                    match self.serializer.use_linked_list() {
-                        true => {
-                            /*for (i, (cf_key, value)) in follow_unfollow
-                                .get_keys()
-                                .iter()
-                                .zip(follow_unfollow.get_values().iter())
-                                .take(2)
-                                .enumerate()
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.linked_list_kv_server.remove(key).unwrap();
-                                response_vals
-                                    .append(CFBytes::new(old_value.as_ref().as_ref(), datapath)?);
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key,
-                                    value.as_bytes(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            let mut arena_sga = ArenaOrderedRcSga::allocate(
-                                follow_unfollow_response.num_scatter_gather_entries(),
-                                &arena,
-                            );
-                            follow_unfollow_response.serialize_into_arena_sga(
-                                &mut arena_sga,
-                                arena,
-                                datapath,
-                                self.serializer.with_copies(),
-                            )?;
-
-                            datapath.queue_arena_ordered_rcsga(
-                                (pkt.msg_id(), pkt.conn_id(), arena_sga),
-                                end_batch,
-                            )?;*/
-                            unimplemented!();
-                        }
-                        false => {
-                            let mut old_values: [D::DatapathBuffer; 2] =
-                                [D::DatapathBuffer::default(), D::DatapathBuffer::default()];
-                            for (i, (cf_key, value)) in follow_unfollow
-                                .get_keys()
-                                .iter()
-                                .zip(follow_unfollow.get_values().iter())
-                                .take(2)
-                                .enumerate()
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.kv_server.remove(key).unwrap();
-                                old_values[i] = old_value;
-                                self.kv_server.insert_with_copies(
-                                    key,
-                                    value.as_bytes(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            response_vals.append(CFBytes::new(old_values[0].as_ref(), datapath)?);
-                            response_vals.append(CFBytes::new(old_values[1].as_ref(), datapath)?);
-                            let mut arena_sga = ArenaOrderedRcSga::allocate(
-                                follow_unfollow_response.num_scatter_gather_entries(),
-                                &arena,
-                            );
-                            follow_unfollow_response.serialize_into_arena_sga(
-                                &mut arena_sga,
-                                arena,
-                                datapath,
-                                self.serializer.with_copies(),
-                            )?;
-
-                            datapath.queue_arena_ordered_rcsga(
-                                (pkt.msg_id(), pkt.conn_id(), arena_sga),
-                                end_batch,
-                            )?;
-                        }
-                    }
-                }
-                MsgType::PostTweet => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle post tweet");
-                    let mut post_tweet = PostTweet::<D>::new();
-                    post_tweet.deserialize_from_pkt(&pkt, REQ_TYPE_SIZE)?;
-
-                    let mut post_tweet_response = PostTweetResponse::<D>::new();
-                    post_tweet_response.init_values(3);
-                    let response_vals = post_tweet_response.get_mut_values();
-                    match self.serializer.use_linked_list() {
-                        true => {
-                            /*for (i, (cf_key, value)) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .take(3)
-                                .enumerate()
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.linked_list_kv_server.remove(key).unwrap();
-                                response_vals
-                                    .append(CFBytes::new(old_value.as_ref().as_ref(), datapath)?);
-                                self.linked_list_kv_server.insert_with_copies(
-                                    key,
-                                    value.as_bytes(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .skip(3)
-                                .take(2)
-                            {
-                                self.linked_list_kv_server.insert_with_copies(
-                                    cf_key.to_str()?,
-                                    value.as_bytes(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            let mut arena_sga = ArenaOrderedRcSga::allocate(
-                                post_tweet_response.num_scatter_gather_entries(),
-                                &arena,
-                            );
-                            post_tweet_response.serialize_into_arena_sga(
-                                &mut arena_sga,
-                                arena,
-                                datapath,
-                                self.serializer.with_copies(),
-                            )?;
-
-                            datapath.queue_arena_ordered_rcsga(
-                                (pkt.msg_id(), pkt.conn_id(), arena_sga),
-                                end_batch,
-                            )?;*/
-                            unimplemented!();
-                        }
-                        false => {
-                            let mut old_values: [D::DatapathBuffer; 3] = [
-                                D::DatapathBuffer::default(),
-                                D::DatapathBuffer::default(),
-                                D::DatapathBuffer::default(),
-                            ];
-                            for (i, (cf_key, value)) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .take(3)
-                                .enumerate()
-                            {
-                                let key = cf_key.to_str()?;
-                                let old_value = self.kv_server.remove(key).unwrap();
-                                old_values[i] = old_value;
-                                self.kv_server.insert_with_copies(
-                                    key,
-                                    value.as_bytes(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            response_vals.append(CFBytes::new(old_values[0].as_ref(), datapath)?);
-                            response_vals.append(CFBytes::new(old_values[1].as_ref(), datapath)?);
-                            response_vals.append(CFBytes::new(old_values[2].as_ref(), datapath)?);
-                            for (cf_key, value) in post_tweet
-                                .get_keys()
-                                .iter()
-                                .zip(post_tweet.get_values().iter())
-                                .skip(3)
-                                .take(2)
-                            {
-                                self.kv_server.insert_with_copies(
-                                    cf_key.to_str()?,
-                                    value.as_bytes(),
-                                    datapath,
-                                    &mut self.mempool_ids,
-                                )?;
-                            }
-                            let mut arena_sga = ArenaOrderedRcSga::allocate(
-                                post_tweet_response.num_scatter_gather_entries(),
-                                &arena,
-                            );
-                            post_tweet_response.serialize_into_arena_sga(
-                                &mut arena_sga,
-                                arena,
-                                datapath,
-                                self.serializer.with_copies(),
-                            )?;
-
-                            datapath.queue_arena_ordered_rcsga(
-                                (pkt.msg_id(), pkt.conn_id(), arena_sga),
-                                end_batch,
-                            )?;
-                        }
-                    }
-                }
-                MsgType::GetTimeline(_) => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("Handle get timeline");
-                    let mut get_timeline = GetTimeline::<D>::new();
-                    get_timeline.deserialize_from_pkt(&pkt, REQ_TYPE_SIZE)?;
-
-                    let mut get_timeline_response = GetTimelineResponse::<D>::new();
-                    get_timeline_response.init_values(get_timeline.get_keys().len());
-                    let response_vals = get_timeline_response.get_mut_values();
-                    for (_i, key) in get_timeline.get_keys().iter().enumerate() {
-                        let cf_bytes = match self.serializer.use_linked_list() {
-                            true => {
-                                /*let val = self.linked_list_kv_server.get(key.to_str()?).unwrap();
-                                tracing::debug!(
-                                    msg_id = pkt.msg_id(),
-                                    conn_id = pkt.conn_id(),
-                                    key_idx = _i,
-                                    "Get timeline val size {}",
-                                    val.as_ref().as_ref().len()
-                                );
-                                CFBytes::new(val.as_ref().as_ref(), datapath)?*/
-                                unimplemented!();
-                            }
-                            false => {
-                                let val = self.kv_server.get(key.to_str()?).unwrap();
-                                tracing::debug!(
-                                    msg_id = pkt.msg_id(),
-                                    conn_id = pkt.conn_id(),
-                                    key_idx = _i,
-                                    "Get timeline val size {}",
-                                    val.as_ref().len()
-                                );
-                                CFBytes::new(val.as_ref(), datapath)?
-                            }
-                        };
-                        response_vals.append(cf_bytes);
-                    }
-                    let mut arena_sga = ArenaOrderedRcSga::allocate(
-                        get_timeline_response.num_scatter_gather_entries(),
-                        &arena,
-                    );
-                    get_timeline_response.serialize_into_arena_sga(
-                        &mut arena_sga,
-                        arena,
-                        datapath,
-                        self.serializer.with_copies(),
-                    )?;
-
-                    tracing::debug!(
-                        "Sending back get timeline response with msg id {}",
-                        pkt.msg_id()
-                    );
-                    datapath.queue_arena_ordered_rcsga(
-                        (pkt.msg_id(), pkt.conn_id(), arena_sga),
-                        end_batch,
-                    )?;
-                }
-                _ => {
-                    unimplemented!();
-                }
+            if let Err(e) =
+                self.process_request_arena_ordered_sga(msg_type, &pkt, datapath, arena, end_batch)
+            {
+                let status = failed_request_status(&pkt, Some(msg_type), &e);
+                queue_error_response(Some(msg_type), status, &pkt, datapath, end_batch)?;
             }
         }
         arena.reset();
@@ -3123,6 +3896,64 @@ where
     }
 }
 
+/// Answers a request the server could not handle with an otherwise empty response of the type the
+/// client expects for `msg_type`, carrying `status`. Requests of unknown type get a put response.
+/// The response is copied, so this works on every push path.
+fn queue_error_response<D>(
+    msg_type: Option<MsgType>,
+    status: ResponseStatus,
+    pkt: &ReceivedPkt<D>,
+    datapath: &mut D,
+    end_batch: bool,
+) -> Result<()>
+where
+    D: Datapath,
+{
+    let status = status.as_u32();
+    let response = match msg_type {
+        Some(MsgType::Get) | Some(MsgType::GetFromList) => {
+            let mut resp = GetResp::<D>::new();
+            resp.set_status(status);
+            resp.serialize_to_owned(datapath)?
+        }
+        Some(MsgType::GetM(_)) | Some(MsgType::Scan) => {
+            let mut resp = GetMResp::<D>::new();
+            resp.set_status(status);
+            resp.serialize_to_owned(datapath)?
+        }
+        Some(MsgType::GetList(_)) => {
+            let mut resp = GetListResp::<D>::new();
+            resp.set_status(status);
+            resp.serialize_to_owned(datapath)?
+        }
+        Some(MsgType::CompareAndSwap) => {
+            let mut resp = CASResp::<D>::new();
+            resp.set_status(status);
+            resp.serialize_to_owned(datapath)?
+        }
+        Some(MsgType::Increment) | Some(MsgType::Decrement) => {
+            let mut resp = IncrResp::<D>::new();
+            resp.set_status(status);
+            resp.serialize_to_owned(datapath)?
+        }
+        // retwis responses carry no status; an empty one holds no values
+        Some(MsgType::AddUser) => AddUserResponse::<D>::new().serialize_to_owned(datapath)?,
+        Some(MsgType::FollowUnfollow) => {
+            FollowUnfollowResponse::<D>::new().serialize_to_owned(datapath)?
+        }
+        Some(MsgType::PostTweet) => PostTweetResponse::<D>::new().serialize_to_owned(datapath)?,
+        Some(MsgType::GetTimeline(_)) => {
+            GetTimelineResponse::<D>::new().serialize_to_owned(datapath)?
+        }
+        _ => {
+            let mut resp = PutResp::<D>::new();
+            resp.set_status(status);
+            resp.serialize_to_owned(datapath)?
+        }
+    };
+    datapath.queue_single_buffer_with_copy((pkt.msg_id(), pkt.conn_id(), &response), end_batch)
+}
+
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct CornflakesClient<D>
 where
@@ -3144,6 +3975,51 @@ where
         }
     }
 
+    fn deserialize_response_status(&self, msg_type: MsgType, buf: &[u8]) -> Result<ResponseStatus> {
+        let status = match msg_type {
+            MsgType::Get | MsgType::GetFromList => {
+                let mut get_resp = GetResp::<D>::new();
+                get_resp.deserialize_from_buf(buf)?;
+                get_resp.get_status()
+            }
+            MsgType::GetM(_) | MsgType::Scan => {
+                let mut getm_resp = GetMResp::<D>::new();
+                getm_resp.deserialize_from_buf(buf)?;
+                getm_resp.get_status()
+            }
+            MsgType::GetList(_) => {
+                let mut getlist_resp = GetListResp::<D>::new();
+                getlist_resp.deserialize_from_buf(buf)?;
+                getlist_resp.get_status()
+            }
+            MsgType::Put
+            | MsgType::PutM(_)
+            | MsgType::PutList(_)
+            | MsgType::AppendToList(_)
+            | MsgType::Delete
+            | MsgType::DeleteM(_) => {
+                let mut put_resp = PutResp::<D>::new();
+                put_resp.deserialize_from_buf(buf)?;
+                put_resp.get_status()
+            }
+            MsgType::CompareAndSwap => {
+                let mut cas_resp = CASResp::<D>::new();
+                cas_resp.deserialize_from_buf(buf)?;
+                cas_resp.get_status()
+            }
+            MsgType::Increment | MsgType::Decrement => {
+                let mut incr_resp = IncrResp::<D>::new();
+                incr_resp.deserialize_from_buf(buf)?;
+                incr_resp.get_status()
+            }
+            // retwis responses do not carry a status
+            _ => {
+                return Ok(ResponseStatus::Ok);
+            }
+        };
+        ResponseStatus::from_u32(status)
+    }
+
     fn deserialize_get_response(&self, buf: &[u8]) -> Result<Vec<u8>> {
         let mut get_resp = GetResp::<D>::new();
         get_resp.deserialize_from_buf(buf)?;
@@ -3184,6 +4060,18 @@ where
         }
     }
 
+    fn deserialize_cas_response(&self, buf: &[u8]) -> Result<u64> {
+        let mut cas_resp = CASResp::<D>::new();
+        cas_resp.deserialize_from_buf(buf)?;
+        Ok(cas_resp.get_version())
+    }
+
+    fn deserialize_incr_response(&self, buf: &[u8]) -> Result<i64> {
+        let mut incr_resp = IncrResp::<D>::new();
+        incr_resp.deserialize_from_buf(buf)?;
+        Ok(incr_resp.get_val())
+    }
+
     fn check_add_user_num_values(&self, buf: &[u8]) -> Result<usize> {
         let mut add_user = AddUserResponse::<D>::new();
         add_user.deserialize_from_buf(buf)?;
@@ -3419,4 +4307,73 @@ where
         }
         get_timeline.serialize_into_buf(datapath, buf)
     }
+
+    fn serialize_delete(&self, buf: &mut [u8], key: &str, datapath: &D) -> Result<usize> {
+        let mut delete = DeleteReq::<D>::new();
+        delete.set_key(CFString::new_from_str(key));
+        delete.serialize_into_buf(datapath, buf)
+    }
+
+    fn serialize_deletem(&self, buf: &mut [u8], keys: &Vec<String>, datapath: &D) -> Result<usize> {
+        let mut deletem = DeleteMReq::<D>::new();
+        deletem.init_keys(keys.len());
+        let keys_list = deletem.get_mut_keys();
+        for key in keys.iter() {
+            keys_list.append(CFString::new_from_str(key.as_str()));
+        }
+        deletem.serialize_into_buf(datapath, buf)
+    }
+
+    fn serialize_cas(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        version: u64,
+        value: &str,
+        datapath: &D,
+    ) -> Result<usize> {
+        let mut cas = CASReq::new();
+        cas.set_key(CFString::new_from_str(key));
+        cas.set_version(version);
+        cas.set_val(CFBytes::new_from_bytes(value.as_bytes()));
+        cas.serialize_into_buf(datapath, buf)
+    }
+
+    fn serialize_increment(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        datapath: &D,
+    ) -> Result<usize> {
+        let mut incr = IncrReq::<D>::new();
+        incr.set_key(CFString::new_from_str(key));
+        incr.set_delta(delta);
+        incr.serialize_into_buf(datapath, buf)
+    }
+
+    fn serialize_decrement(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        datapath: &D,
+    ) -> Result<usize> {
+        self.serialize_increment(buf, key, delta, datapath)
+    }
+
+    fn serialize_scan(
+        &self,
+        buf: &mut [u8],
+        start_key: &str,
+        end_key: Option<&str>,
+        count: u64,
+        datapath: &D,
+    ) -> Result<usize> {
+        let mut scan = ScanReq::<D>::new();
+        scan.set_start_key(CFString::new_from_str(start_key));
+        scan.set_end_key(CFString::new_from_str(end_key.unwrap_or_default()));
+        scan.set_count(count);
+        scan.serialize_into_buf(datapath, buf)
+    }
 }
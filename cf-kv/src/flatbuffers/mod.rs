@@ -8,8 +8,10 @@ pub mod kv_api {
     include!(concat!(env!("OUT_DIR"), "/cf_kv_fb_generated.rs"));
 }
 use super::{
-    ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType, ServerLoadGenerator,
-    REQ_TYPE_SIZE,
+    combined_cache_stats, combined_storage_stats, delete_from_stores, failed_request_status,
+    replication::Replicator, scan_end_key, shard::ShardRouter, signed_delta, ttl_from_micros,
+    ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType, ResponseStatus,
+    ServerLoadGenerator, REQ_TYPE_SIZE,
 };
 use color_eyre::eyre::{bail, ensure, Result};
 use cornflakes_libos::{
@@ -53,18 +55,26 @@ where
     ) -> Result<()> {
         let get_request = root::<cf_kv_fbs::GetReq>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
         let value = match self.use_linked_list() {
-            true => match linked_list_kv_server.get(get_request.key().unwrap()) {
-                Some(v) => v.as_ref().as_ref(),
-                None => {
-                    bail!("Could not find value for key: {:?}", get_request.key());
-                }
-            },
-            false => match kv_server.get(get_request.key().unwrap()) {
-                Some(v) => v.as_ref(),
-                None => {
-                    bail!("Could not find value for key: {:?}", get_request.key());
-                }
-            },
+            true => linked_list_kv_server
+                .get(get_request.key().unwrap())
+                .map(|v| v.as_ref().as_ref()),
+            false => kv_server
+                .get(get_request.key().unwrap())
+                .map(|v| v.as_ref()),
+        };
+        let value = match value {
+            Some(v) => v,
+            None => {
+                tracing::debug!(key =? get_request.key(), "Could not find value for key");
+                let args = cf_kv_fbs::GetRespArgs {
+                    val: None,
+                    id: get_request.id(),
+                    status: ResponseStatus::NotFound.as_u32(),
+                };
+                let get_resp = cf_kv_fbs::GetResp::create(builder, &args);
+                builder.finish(get_resp, None);
+                return Ok(());
+            }
         };
 
         tracing::debug!(
@@ -76,6 +86,7 @@ where
         let args = cf_kv_fbs::GetRespArgs {
             val: Some(builder.create_vector_direct::<u8>(value)),
             id: get_request.id(),
+            status: ResponseStatus::Ok.as_u32(),
         };
 
         let get_resp = cf_kv_fbs::GetResp::create(builder, &args);
@@ -97,23 +108,26 @@ where
         let value = {
             #[cfg(feature = "profiler")]
             demikernel::timer!("Retrieve value");
-            match list_kv_server.get(get_request.key().unwrap()) {
-                Some(list) => match list.get(get_request.idx() as usize) {
-                    Some(v) => v,
-                    None => {
-                        bail!(
-                            "Could not find idx {} for key {} in list kv server",
-                            get_request.idx(),
-                            get_request.key().unwrap(),
-                        );
-                    }
-                },
-                None => {
-                    bail!(
-                        "Could not find value for key: {:?}",
-                        get_request.key().unwrap()
-                    );
-                }
+            list_kv_server
+                .get(get_request.key().unwrap())
+                .and_then(|list| list.get(get_request.idx() as usize))
+        };
+        let value = match value {
+            Some(v) => v,
+            None => {
+                tracing::debug!(
+                    key =? get_request.key(),
+                    idx = get_request.idx(),
+                    "Could not find idx for key in list kv server"
+                );
+                let args = cf_kv_fbs::GetRespArgs {
+                    val: None,
+                    id: get_request.id(),
+                    status: ResponseStatus::NotFound.as_u32(),
+                };
+                let get_resp = cf_kv_fbs::GetResp::create(builder, &args);
+                builder.finish(get_resp, None);
+                return Ok(());
             }
         };
 
@@ -131,6 +145,7 @@ where
                     Some(builder.create_vector_direct::<u8>(value.as_ref()))
                 },
                 id: get_request.id(),
+                status: ResponseStatus::Ok.as_u32(),
             }
         };
 
@@ -152,13 +167,17 @@ where
         builder: &mut FlatBufferBuilder,
     ) -> Result<()> {
         let put_req = root::<cf_kv_fbs::PutReq>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
-        kv_server.insert_with_copies(
+        kv_server.insert_with_copies_and_ttl(
             put_req.key().unwrap(),
             put_req.val().unwrap(),
+            ttl_from_micros(put_req.ttl_us()),
             datapath,
             mempool_ids,
         )?;
-        let args = cf_kv_fbs::PutRespArgs { id: put_req.id() };
+        let args = cf_kv_fbs::PutRespArgs {
+            id: put_req.id(),
+            status: ResponseStatus::Ok.as_u32(),
+        };
         let get_resp = cf_kv_fbs::PutResp::create(builder, &args);
         builder.finish(get_resp, None);
         Ok(())
@@ -177,24 +196,23 @@ where
             root::<cf_kv_fbs::GetMReq>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])
         }?;
         let keys = getm_request.keys().unwrap();
+        let mut status = ResponseStatus::Ok;
         let args_vec_res: Result<Vec<cf_kv_fbs::ValueArgs>> = keys
             .iter()
             .map(|key| {
                 #[cfg(feature = "profiler")]
                 demikernel::timer!("got value");
                 let value = match self.use_linked_list() {
-                    true => match linked_list_kv_server.get(key) {
-                        Some(v) => v.as_ref().as_ref(),
-                        None => {
-                            bail!("Could not find value for key: {:?}", key);
-                        }
-                    },
-                    false => match kv_server.get(key) {
-                        Some(v) => v.as_ref(),
-                        None => {
-                            bail!("Could not find value for key: {:?}", key);
-                        }
-                    },
+                    true => linked_list_kv_server.get(key).map(|v| v.as_ref().as_ref()),
+                    false => kv_server.get(key).map(|v| v.as_ref()),
+                };
+                let value: &[u8] = match value {
+                    Some(v) => v,
+                    None => {
+                        tracing::debug!(key, "Could not find value for key");
+                        status = ResponseStatus::NotFound;
+                        &[]
+                    }
                 };
                 {
                     #[cfg(feature = "profiler")]
@@ -212,6 +230,7 @@ where
         let getm_resp_args = cf_kv_fbs::GetMRespArgs {
             id: getm_request.id(),
             vals: Some(builder.create_vector(args_vec.as_slice())),
+            status: status.as_u32(),
         };
         let getm_resp = cf_kv_fbs::GetMResp::create(builder, &getm_resp_args);
         builder.finish(getm_resp, None);
@@ -235,6 +254,7 @@ where
         }
         let args = cf_kv_fbs::PutRespArgs {
             id: putm_request.id(),
+            status: ResponseStatus::Ok.as_u32(),
         };
 
         let put_resp = cf_kv_fbs::PutResp::create(builder, &args);
@@ -264,6 +284,17 @@ where
                 demikernel::timer!("do get on key");
                 linked_list_kv_server.get(key)
             };
+            if node_option.is_none() {
+                tracing::debug!(key, "Cannot find value for key in KV store");
+                let getlist_resp_args = cf_kv_fbs::GetListRespArgs {
+                    id: getlist_request.id(),
+                    vals: None,
+                    status: ResponseStatus::NotFound.as_u32(),
+                };
+                let getlist_resp = cf_kv_fbs::GetListResp::create(builder, &getlist_resp_args);
+                builder.finish(getlist_resp, None);
+                return Ok(());
+            }
 
             let range_len = {
                 // TODO: hack: flatbuffers doesn't seem to be recognizing -1
@@ -321,6 +352,7 @@ where
                 let getlist_resp_args = cf_kv_fbs::GetListRespArgs {
                     id: getlist_request.id(),
                     vals: Some(builder.create_vector(args_vec.as_slice())),
+                    status: ResponseStatus::Ok.as_u32(),
                 };
                 let getlist_resp = cf_kv_fbs::GetListResp::create(builder, &getlist_resp_args);
                 builder.finish(getlist_resp, None)
@@ -329,7 +361,15 @@ where
             let vals = match list_kv_server.get(key) {
                 Some(v) => v,
                 None => {
-                    bail!("Cannot find value for key in KV store: {:?}", key);
+                    tracing::debug!(key, "Cannot find value for key in KV store");
+                    let getlist_resp_args = cf_kv_fbs::GetListRespArgs {
+                        id: getlist_request.id(),
+                        vals: None,
+                        status: ResponseStatus::NotFound.as_u32(),
+                    };
+                    let getlist_resp = cf_kv_fbs::GetListResp::create(builder, &getlist_resp_args);
+                    builder.finish(getlist_resp, None);
+                    return Ok(());
                 }
             };
             let args_vec: Vec<cf_kv_fbs::ValueArgs> = vals
@@ -345,6 +385,7 @@ where
             let getlist_resp_args = cf_kv_fbs::GetListRespArgs {
                 id: getlist_request.id(),
                 vals: Some(builder.create_vector(args_vec.as_slice())),
+                status: ResponseStatus::Ok.as_u32(),
             };
             {
                 #[cfg(feature = "profiler")]
@@ -374,12 +415,168 @@ where
         list_kv_server.insert_with_copies(key, values, datapath, mempool_ids)?;
         let args = cf_kv_fbs::PutRespArgs {
             id: putlist_request.id(),
+            status: ResponseStatus::Ok.as_u32(),
         };
 
         let put_resp = cf_kv_fbs::PutResp::create(builder, &args);
         builder.finish(put_resp, None);
         Ok(())
     }
+
+    fn handle_delete(
+        &self,
+        kv_server: &mut KVServer<D>,
+        list_kv_server: &mut ListKVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        pkt: &ReceivedPkt<D>,
+        builder: &mut FlatBufferBuilder,
+    ) -> Result<()> {
+        let delete_req = root::<cf_kv_fbs::DeleteReq>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+        let key = delete_req.key().unwrap();
+        let mut status = ResponseStatus::Ok;
+        if !delete_from_stores(key, kv_server, list_kv_server, linked_list_kv_server) {
+            tracing::debug!(key, "Could not find key to delete");
+            status = ResponseStatus::NotFound;
+        }
+        let args = cf_kv_fbs::PutRespArgs {
+            id: delete_req.id(),
+            status: status.as_u32(),
+        };
+        let put_resp = cf_kv_fbs::PutResp::create(builder, &args);
+        builder.finish(put_resp, None);
+        Ok(())
+    }
+
+    fn handle_deletem(
+        &self,
+        kv_server: &mut KVServer<D>,
+        list_kv_server: &mut ListKVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        pkt: &ReceivedPkt<D>,
+        builder: &mut FlatBufferBuilder,
+    ) -> Result<()> {
+        let deletem_req = root::<cf_kv_fbs::DeleteMReq>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+        let mut status = ResponseStatus::Ok;
+        for key in deletem_req.keys().unwrap().iter() {
+            if !delete_from_stores(key, kv_server, list_kv_server, linked_list_kv_server) {
+                tracing::debug!(key, "Could not find key to delete");
+                status = ResponseStatus::NotFound;
+            }
+        }
+        let args = cf_kv_fbs::PutRespArgs {
+            id: deletem_req.id(),
+            status: status.as_u32(),
+        };
+        let put_resp = cf_kv_fbs::PutResp::create(builder, &args);
+        builder.finish(put_resp, None);
+        Ok(())
+    }
+
+    fn handle_cas(
+        &self,
+        kv_server: &mut KVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        mempool_ids: &mut Vec<MempoolID>,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        builder: &mut FlatBufferBuilder,
+    ) -> Result<()> {
+        let cas_req = root::<cf_kv_fbs::CASReq>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+        let key = cas_req.key().unwrap();
+        let val = cas_req.val().unwrap();
+        let (status, version) = match self.use_linked_list() {
+            true => linked_list_kv_server.compare_and_swap(
+                key,
+                cas_req.version(),
+                val,
+                datapath,
+                mempool_ids,
+            )?,
+            false => {
+                kv_server.compare_and_swap(key, cas_req.version(), val, datapath, mempool_ids)?
+            }
+        };
+        let args = cf_kv_fbs::CASRespArgs {
+            id: cas_req.id(),
+            version: version,
+            status: status.as_u32(),
+        };
+        let cas_resp = cf_kv_fbs::CASResp::create(builder, &args);
+        builder.finish(cas_resp, None);
+        Ok(())
+    }
+
+    fn handle_scan(
+        &self,
+        kv_server: &KVServer<D>,
+        pkt: &ReceivedPkt<D>,
+        builder: &mut FlatBufferBuilder,
+    ) -> Result<()> {
+        let scan_request = root::<cf_kv_fbs::ScanReq>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+        let values = match self.use_linked_list() {
+            true => None,
+            false => kv_server.scan(
+                scan_request.start_key().unwrap_or_default(),
+                scan_end_key(scan_request.end_key().unwrap_or_default()),
+                scan_request.count() as usize,
+            ),
+        };
+        let mut status = ResponseStatus::Ok;
+        let values = match values {
+            Some(values) => values,
+            None => {
+                tracing::debug!("Received scan, but the kv store keeps no ordered index");
+                status = ResponseStatus::BadRequest;
+                Vec::default()
+            }
+        };
+        let args_vec: Vec<WIPOffset<cf_kv_fbs::Value>> = values
+            .iter()
+            .map(|value| {
+                let args = cf_kv_fbs::ValueArgs {
+                    data: Some(builder.create_vector_direct::<u8>(value.as_ref())),
+                };
+                cf_kv_fbs::Value::create(builder, &args)
+            })
+            .collect();
+        let getm_resp_args = cf_kv_fbs::GetMRespArgs {
+            id: scan_request.id(),
+            vals: Some(builder.create_vector(args_vec.as_slice())),
+            status: status.as_u32(),
+        };
+        let getm_resp = cf_kv_fbs::GetMResp::create(builder, &getm_resp_args);
+        builder.finish(getm_resp, None);
+        Ok(())
+    }
+
+    fn handle_incr(
+        &self,
+        kv_server: &mut KVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        mempool_ids: &mut Vec<MempoolID>,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        builder: &mut FlatBufferBuilder,
+        negate: bool,
+    ) -> Result<()> {
+        let incr_req = root::<cf_kv_fbs::IncrReq>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+        let key = incr_req.key().unwrap();
+        let (status, value) = match signed_delta(incr_req.delta(), negate) {
+            Some(delta) => match self.use_linked_list() {
+                true => linked_list_kv_server.increment(key, delta, datapath, mempool_ids)?,
+                false => kv_server.increment(key, delta, datapath, mempool_ids)?,
+            },
+            None => (ResponseStatus::BadRequest, 0),
+        };
+        let args = cf_kv_fbs::IncrRespArgs {
+            id: incr_req.id(),
+            val: value,
+            status: status.as_u32(),
+        };
+        let incr_resp = cf_kv_fbs::IncrResp::create(builder, &args);
+        builder.finish(incr_resp, None);
+        Ok(())
+    }
 }
 
 pub struct FlatbuffersKVServer<'fbb, D>
@@ -392,6 +589,8 @@ where
     mempool_ids: Vec<MempoolID>,
     serializer: FlatbuffersSerializer<D>,
     push_buf_type: PushBufType,
+    shard_router: Option<ShardRouter>,
+    replicator: Option<Replicator<D>>,
     builder: FlatBufferBuilder<'fbb>,
 }
 
@@ -417,10 +616,281 @@ where
             linked_list_kv_server: linked_list_kv,
             mempool_ids: mempool_ids,
             push_buf_type: push_buf_type,
+            shard_router: None,
+            replicator: None,
             serializer: FlatbuffersSerializer::new(use_linked_list_kv),
             builder: FlatBufferBuilder::new(),
         })
     }
+
+    /// Makes this server one shard of a multi-core server.
+    pub fn set_shard_router(&mut self, router: ShardRouter) {
+        self.shard_router = Some(router);
+    }
+
+    /// Makes this server a replica, following the given replication config.
+    pub fn set_replicator(&mut self, replicator: Replicator<D>) {
+        self.replicator = Some(replicator);
+    }
+
+    /// Handles one request, building its response in `self.builder`.
+    fn build_response(
+        &mut self,
+        message_type: MsgType,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+    ) -> Result<()> {
+        match message_type {
+            MsgType::Get => {
+                self.serializer.handle_get(
+                    &self.kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    &mut self.builder,
+                )?;
+            }
+            MsgType::GetFromList => {
+                self.serializer.handle_get_from_list(
+                    &self.list_kv_server,
+                    pkt,
+                    &mut self.builder,
+                )?;
+            }
+            MsgType::GetM(_size) => {
+                self.serializer.handle_getm(
+                    &self.kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    &mut self.builder,
+                )?;
+            }
+            MsgType::GetList(_size) => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle getlist flatbuffers");
+                self.serializer.handle_getlist(
+                    &self.list_kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                    &mut self.builder,
+                )?;
+            }
+            MsgType::Put => {
+                self.serializer.handle_put(
+                    &mut self.kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    &mut self.builder,
+                )?;
+            }
+            MsgType::PutM(_size) => {
+                self.serializer.handle_putm(
+                    &mut self.kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    &mut self.builder,
+                )?;
+            }
+            MsgType::PutList(_size) => {
+                self.serializer.handle_putlist(
+                    &mut self.list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    &mut self.builder,
+                )?;
+            }
+            MsgType::AddUser => {
+                let add_user = root::<cf_kv_fbs::AddUser>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+                let keys = add_user.keys().unwrap();
+                let vals = add_user.vals().unwrap();
+                let value = self.kv_server.get(keys.get(0)).unwrap();
+                let args = cf_kv_fbs::AddUserResponseArgs {
+                    first_value: Some(self.builder.create_vector_direct::<u8>(value.as_ref())),
+                };
+                for (key, val) in keys.iter().zip(vals.iter()) {
+                    self.kv_server.insert_with_copies(
+                        key,
+                        val.data().unwrap(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                }
+
+                let add_user_response =
+                    cf_kv_fbs::AddUserResponse::create(&mut self.builder, &args);
+                self.builder.finish(add_user_response, None);
+            }
+            MsgType::FollowUnfollow => {
+                let follow_unfollow =
+                    root::<cf_kv_fbs::FollowUnfollow>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+                let keys = follow_unfollow.keys().unwrap();
+                let args_vec_res: Result<Vec<cf_kv_fbs::ValueArgs>> = keys
+                    .iter()
+                    .map(|key| {
+                        let v = match self.kv_server.get(key) {
+                            Some(v) => v,
+                            None => {
+                                bail!("Cannot find value for key in KV store: {:?}", key);
+                            }
+                        };
+                        Ok(cf_kv_fbs::ValueArgs {
+                            data: Some(self.builder.create_vector_direct::<u8>(v.as_ref())),
+                        })
+                    })
+                    .collect();
+                let args_vec: Vec<WIPOffset<cf_kv_fbs::Value>> = args_vec_res?
+                    .iter()
+                    .map(|args| cf_kv_fbs::Value::create(&mut self.builder, args))
+                    .collect();
+
+                let args = cf_kv_fbs::FollowUnfollowResponseArgs {
+                    original_vals: Some(self.builder.create_vector(args_vec.as_slice())),
+                };
+                let vals = follow_unfollow.vals().unwrap();
+                for (key, val) in keys.iter().zip(vals.iter()) {
+                    self.kv_server.insert_with_copies(
+                        key,
+                        val.data().unwrap(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                }
+
+                let follow_unfollow_response =
+                    cf_kv_fbs::FollowUnfollowResponse::create(&mut self.builder, &args);
+                self.builder.finish(follow_unfollow_response, None);
+            }
+            MsgType::PostTweet => {
+                // 3 gets, 5 puts
+                let post_tweet =
+                    root::<cf_kv_fbs::PostTweet>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+                let keys = post_tweet.keys().unwrap();
+                let args_vec_res: Result<Vec<cf_kv_fbs::ValueArgs>> = keys
+                    .iter()
+                    .take(3)
+                    .map(|key| {
+                        let v = match self.kv_server.get(key) {
+                            Some(v) => v,
+                            None => {
+                                bail!(
+                                    "Cannot find value for key in KV store: {:?}; len: {}; keys: {:?}",
+                                    key,
+                                    self.kv_server.len(),keys
+                                );
+                            }
+                        };
+                        Ok(cf_kv_fbs::ValueArgs {
+                            data: Some(self.builder.create_vector_direct::<u8>(v.as_ref())),
+                        })
+                    })
+                    .collect();
+                let args_vec: Vec<WIPOffset<cf_kv_fbs::Value>> = args_vec_res?
+                    .iter()
+                    .map(|args| cf_kv_fbs::Value::create(&mut self.builder, args))
+                    .collect();
+
+                let args = cf_kv_fbs::PostTweetResponseArgs {
+                    vals: Some(self.builder.create_vector(args_vec.as_slice())),
+                };
+                let vals = post_tweet.vals().unwrap();
+                for (key, val) in keys.iter().zip(vals.iter()) {
+                    self.kv_server.insert_with_copies(
+                        key,
+                        val.data().unwrap(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                }
+
+                let post_tweet_response =
+                    cf_kv_fbs::PostTweetResponse::create(&mut self.builder, &args);
+                self.builder.finish(post_tweet_response, None);
+            }
+            MsgType::GetTimeline(_) => {
+                let get_timeline_request =
+                    root::<cf_kv_fbs::GetTimeline>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
+                let keys = get_timeline_request.keys().unwrap();
+                let args_vec_res: Result<Vec<cf_kv_fbs::ValueArgs>> = keys
+                    .iter()
+                    .map(|key| {
+                        let v = match self.kv_server.get(key) {
+                            Some(v) => v,
+                            None => {
+                                bail!("Cannot find value for key in KV store: {:?}", key);
+                            }
+                        };
+                        Ok(cf_kv_fbs::ValueArgs {
+                            data: Some(self.builder.create_vector_direct::<u8>(v.as_ref())),
+                        })
+                    })
+                    .collect();
+                let args_vec: Vec<WIPOffset<cf_kv_fbs::Value>> = args_vec_res?
+                    .iter()
+                    .map(|args| cf_kv_fbs::Value::create(&mut self.builder, args))
+                    .collect();
+                let get_timeline_resp_args = cf_kv_fbs::GetTimelineResponseArgs {
+                    vals: Some(self.builder.create_vector(args_vec.as_slice())),
+                };
+                let get_timeline_resp = cf_kv_fbs::GetTimelineResponse::create(
+                    &mut self.builder,
+                    &get_timeline_resp_args,
+                );
+                self.builder.finish(get_timeline_resp, None);
+            }
+            MsgType::Delete => {
+                self.serializer.handle_delete(
+                    &mut self.kv_server,
+                    &mut self.list_kv_server,
+                    &mut self.linked_list_kv_server,
+                    pkt,
+                    &mut self.builder,
+                )?;
+            }
+            MsgType::DeleteM(_size) => {
+                self.serializer.handle_deletem(
+                    &mut self.kv_server,
+                    &mut self.list_kv_server,
+                    &mut self.linked_list_kv_server,
+                    pkt,
+                    &mut self.builder,
+                )?;
+            }
+            MsgType::CompareAndSwap => {
+                self.serializer.handle_cas(
+                    &mut self.kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    &mut self.builder,
+                )?;
+            }
+            MsgType::Increment | MsgType::Decrement => {
+                self.serializer.handle_incr(
+                    &mut self.kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    &mut self.builder,
+                    message_type == MsgType::Decrement,
+                )?;
+            }
+            MsgType::Scan => {
+                self.serializer
+                    .handle_scan(&self.kv_server, pkt, &mut self.builder)?;
+            }
+            _ => {
+                bail!(
+                    "Flatbuffers server does not support {:?} requests",
+                    message_type
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<'fbb, D> ServerSM for FlatbuffersKVServer<'fbb, D>
@@ -433,6 +903,46 @@ where
         self.push_buf_type
     }
 
+    fn steer_packets(
+        &mut self,
+        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        datapath: &mut Self::Datapath,
+    ) -> Result<Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>> {
+        let pkts = match &mut self.shard_router {
+            Some(router) => router.steer(pkts, datapath)?,
+            None => pkts,
+        };
+        match &mut self.replicator {
+            Some(replicator) => replicator.steer(pkts, datapath),
+            None => Ok(pkts),
+        }
+    }
+
+    fn log_stats(&self, datapath: &Self::Datapath) {
+        datapath.transmit_stats().dump("Server transmit stats");
+        datapath
+            .allocator_memory_stats()
+            .dump("Server allocator memory stats");
+        combined_cache_stats(
+            &self.kv_server,
+            &self.list_kv_server,
+            &self.linked_list_kv_server,
+        )
+        .dump("Server cache stats");
+        combined_storage_stats(
+            &self.kv_server,
+            &self.list_kv_server,
+            &self.linked_list_kv_server,
+        )
+        .dump("Server storage stats");
+        if let Some(router) = &self.shard_router {
+            router.stats().dump("Server shard stats");
+        }
+        if let Some(replicator) = &self.replicator {
+            replicator.stats().dump("Server replication stats");
+        }
+    }
+
     #[inline]
     fn process_requests_single_buf(
         &mut self,
@@ -441,221 +951,108 @@ where
     ) -> Result<()> {
         let pkts_len = sga.len();
         for (i, pkt) in sga.into_iter().enumerate() {
+            let end_batch = i == (pkts_len - 1);
             self.builder.reset();
-            let message_type = MsgType::from_packet(&pkt)?;
-            match message_type {
-                MsgType::Get => {
-                    self.serializer.handle_get(
-                        &self.kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                        &mut self.builder,
-                    )?;
-                }
-                MsgType::GetFromList => {
-                    self.serializer.handle_get_from_list(
-                        &self.list_kv_server,
-                        &pkt,
-                        &mut self.builder,
-                    )?;
-                }
-                MsgType::GetM(_size) => {
-                    self.serializer.handle_getm(
-                        &self.kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                        &mut self.builder,
-                    )?;
-                }
-                MsgType::GetList(_size) => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle getlist flatbuffers");
-                    self.serializer.handle_getlist(
-                        &self.list_kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                        &mut self.builder,
-                    )?;
-                }
-                MsgType::Put => {
-                    self.serializer.handle_put(
-                        &mut self.kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
-                        datapath,
-                        &mut self.builder,
-                    )?;
-                }
-                MsgType::PutM(_size) => {
-                    self.serializer.handle_putm(
-                        &mut self.kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
-                        datapath,
-                        &mut self.builder,
-                    )?;
-                }
-                MsgType::PutList(_size) => {
-                    self.serializer.handle_putlist(
-                        &mut self.list_kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
-                        datapath,
-                        &mut self.builder,
-                    )?;
-                }
-                MsgType::AddUser => {
-                    let add_user =
-                        root::<cf_kv_fbs::AddUser>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
-                    let keys = add_user.keys().unwrap();
-                    let vals = add_user.vals().unwrap();
-                    let value = self.kv_server.get(keys.get(0)).unwrap();
-                    let args = cf_kv_fbs::AddUserResponseArgs {
-                        first_value: Some(self.builder.create_vector_direct::<u8>(value.as_ref())),
-                    };
-                    for (key, val) in keys.iter().zip(vals.iter()) {
-                        self.kv_server.insert_with_copies(
-                            key,
-                            val.data().unwrap(),
-                            datapath,
-                            &mut self.mempool_ids,
-                        )?;
+            match MsgType::from_packet(&pkt) {
+                Ok(message_type) => {
+                    if let Err(e) = self.build_response(message_type, &pkt, datapath) {
+                        let status = failed_request_status(&pkt, Some(message_type), &e);
+                        build_error_response(&mut self.builder, Some(message_type), status);
                     }
-
-                    let add_user_response =
-                        cf_kv_fbs::AddUserResponse::create(&mut self.builder, &args);
-                    self.builder.finish(add_user_response, None);
                 }
-                MsgType::FollowUnfollow => {
-                    let follow_unfollow =
-                        root::<cf_kv_fbs::FollowUnfollow>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
-                    let keys = follow_unfollow.keys().unwrap();
-                    let args_vec_res: Result<Vec<cf_kv_fbs::ValueArgs>> = keys
-                        .iter()
-                        .map(|key| {
-                            let v = match self.kv_server.get(key) {
-                                Some(v) => v,
-                                None => {
-                                    bail!("Cannot find value for key in KV store: {:?}", key);
-                                }
-                            };
-                            Ok(cf_kv_fbs::ValueArgs {
-                                data: Some(self.builder.create_vector_direct::<u8>(v.as_ref())),
-                            })
-                        })
-                        .collect();
-                    let args_vec: Vec<WIPOffset<cf_kv_fbs::Value>> = args_vec_res?
-                        .iter()
-                        .map(|args| cf_kv_fbs::Value::create(&mut self.builder, args))
-                        .collect();
-
-                    let args = cf_kv_fbs::FollowUnfollowResponseArgs {
-                        original_vals: Some(self.builder.create_vector(args_vec.as_slice())),
-                    };
-                    let vals = follow_unfollow.vals().unwrap();
-                    for (key, val) in keys.iter().zip(vals.iter()) {
-                        self.kv_server.insert_with_copies(
-                            key,
-                            val.data().unwrap(),
-                            datapath,
-                            &mut self.mempool_ids,
-                        )?;
-                    }
-
-                    let follow_unfollow_response =
-                        cf_kv_fbs::FollowUnfollowResponse::create(&mut self.builder, &args);
-                    self.builder.finish(follow_unfollow_response, None);
-                }
-                MsgType::PostTweet => {
-                    // 3 gets, 5 puts
-                    let post_tweet =
-                        root::<cf_kv_fbs::PostTweet>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
-                    let keys = post_tweet.keys().unwrap();
-                    let args_vec_res: Result<Vec<cf_kv_fbs::ValueArgs>> = keys
-                        .iter()
-                        .take(3)
-                        .map(|key| {
-                            let v = match self.kv_server.get(key) {
-                                Some(v) => v,
-                                None => {
-                                    bail!(
-                                        "Cannot find value for key in KV store: {:?}; len: {}; keys: {:?}",
-                                        key,
-                                        self.kv_server.len(),keys
-                                    );
-                                }
-                            };
-                            Ok(cf_kv_fbs::ValueArgs {
-                                data: Some(self.builder.create_vector_direct::<u8>(v.as_ref())),
-                            })
-                        })
-                        .collect();
-                    let args_vec: Vec<WIPOffset<cf_kv_fbs::Value>> = args_vec_res?
-                        .iter()
-                        .map(|args| cf_kv_fbs::Value::create(&mut self.builder, args))
-                        .collect();
-
-                    let args = cf_kv_fbs::PostTweetResponseArgs {
-                        vals: Some(self.builder.create_vector(args_vec.as_slice())),
-                    };
-                    let vals = post_tweet.vals().unwrap();
-                    for (key, val) in keys.iter().zip(vals.iter()) {
-                        self.kv_server.insert_with_copies(
-                            key,
-                            val.data().unwrap(),
-                            datapath,
-                            &mut self.mempool_ids,
-                        )?;
-                    }
-
-                    let post_tweet_response =
-                        cf_kv_fbs::PostTweetResponse::create(&mut self.builder, &args);
-                    self.builder.finish(post_tweet_response, None);
-                }
-                MsgType::GetTimeline(_) => {
-                    let get_timeline_request =
-                        root::<cf_kv_fbs::GetTimeline>(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])?;
-                    let keys = get_timeline_request.keys().unwrap();
-                    let args_vec_res: Result<Vec<cf_kv_fbs::ValueArgs>> = keys
-                        .iter()
-                        .map(|key| {
-                            let v = match self.kv_server.get(key) {
-                                Some(v) => v,
-                                None => {
-                                    bail!("Cannot find value for key in KV store: {:?}", key);
-                                }
-                            };
-                            Ok(cf_kv_fbs::ValueArgs {
-                                data: Some(self.builder.create_vector_direct::<u8>(v.as_ref())),
-                            })
-                        })
-                        .collect();
-                    let args_vec: Vec<WIPOffset<cf_kv_fbs::Value>> = args_vec_res?
-                        .iter()
-                        .map(|args| cf_kv_fbs::Value::create(&mut self.builder, args))
-                        .collect();
-                    let get_timeline_resp_args = cf_kv_fbs::GetTimelineResponseArgs {
-                        vals: Some(self.builder.create_vector(args_vec.as_slice())),
-                    };
-                    let get_timeline_resp = cf_kv_fbs::GetTimelineResponse::create(
-                        &mut self.builder,
-                        &get_timeline_resp_args,
-                    );
-                    self.builder.finish(get_timeline_resp, None);
-                }
-                _ => {
-                    unimplemented!();
+                Err(e) => {
+                    let status = failed_request_status(&pkt, None, &e);
+                    build_error_response(&mut self.builder, None, status);
                 }
             }
             datapath.queue_single_buffer_with_copy(
                 (pkt.msg_id(), pkt.conn_id(), &self.builder.finished_data()),
-                i == (pkts_len - 1),
+                end_batch,
             )?;
         }
         Ok(())
     }
 }
 
+/// Builds an otherwise empty response of the type the client expects for `msg_type`, carrying
+/// `status`, for a request the server could not handle. Requests of unknown type get a put
+/// response.
+fn build_error_response(
+    builder: &mut FlatBufferBuilder,
+    msg_type: Option<MsgType>,
+    status: ResponseStatus,
+) {
+    builder.reset();
+    let status = status.as_u32();
+    match msg_type {
+        Some(MsgType::Get) | Some(MsgType::GetFromList) => {
+            let args = cf_kv_fbs::GetRespArgs {
+                status,
+                ..Default::default()
+            };
+            let response = cf_kv_fbs::GetResp::create(builder, &args);
+            builder.finish(response, None);
+        }
+        Some(MsgType::GetM(_)) | Some(MsgType::Scan) => {
+            let args = cf_kv_fbs::GetMRespArgs {
+                status,
+                ..Default::default()
+            };
+            let response = cf_kv_fbs::GetMResp::create(builder, &args);
+            builder.finish(response, None);
+        }
+        Some(MsgType::GetList(_)) => {
+            let args = cf_kv_fbs::GetListRespArgs {
+                status,
+                ..Default::default()
+            };
+            let response = cf_kv_fbs::GetListResp::create(builder, &args);
+            builder.finish(response, None);
+        }
+        Some(MsgType::CompareAndSwap) => {
+            let args = cf_kv_fbs::CASRespArgs {
+                status,
+                ..Default::default()
+            };
+            let response = cf_kv_fbs::CASResp::create(builder, &args);
+            builder.finish(response, None);
+        }
+        Some(MsgType::Increment) | Some(MsgType::Decrement) => {
+            let args = cf_kv_fbs::IncrRespArgs {
+                status,
+                ..Default::default()
+            };
+            let response = cf_kv_fbs::IncrResp::create(builder, &args);
+            builder.finish(response, None);
+        }
+        // retwis responses carry no status; an empty one holds no values
+        Some(MsgType::AddUser) => {
+            let response = cf_kv_fbs::AddUserResponse::create(builder, &Default::default());
+            builder.finish(response, None);
+        }
+        Some(MsgType::FollowUnfollow) => {
+            let response = cf_kv_fbs::FollowUnfollowResponse::create(builder, &Default::default());
+            builder.finish(response, None);
+        }
+        Some(MsgType::PostTweet) => {
+            let response = cf_kv_fbs::PostTweetResponse::create(builder, &Default::default());
+            builder.finish(response, None);
+        }
+        Some(MsgType::GetTimeline(_)) => {
+            let response = cf_kv_fbs::GetTimelineResponse::create(builder, &Default::default());
+            builder.finish(response, None);
+        }
+        _ => {
+            let args = cf_kv_fbs::PutRespArgs {
+                status,
+                ..Default::default()
+            };
+            let response = cf_kv_fbs::PutResp::create(builder, &args);
+            builder.finish(response, None);
+        }
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct FlatbuffersClient<D>
 where
@@ -677,6 +1074,27 @@ where
         }
     }
 
+    fn deserialize_response_status(&self, msg_type: MsgType, buf: &[u8]) -> Result<ResponseStatus> {
+        let status = match msg_type {
+            MsgType::Get | MsgType::GetFromList => root::<cf_kv_fbs::GetResp>(buf)?.status(),
+            MsgType::GetM(_) | MsgType::Scan => root::<cf_kv_fbs::GetMResp>(buf)?.status(),
+            MsgType::GetList(_) => root::<cf_kv_fbs::GetListResp>(buf)?.status(),
+            MsgType::Put
+            | MsgType::PutM(_)
+            | MsgType::PutList(_)
+            | MsgType::AppendToList(_)
+            | MsgType::Delete
+            | MsgType::DeleteM(_) => root::<cf_kv_fbs::PutResp>(buf)?.status(),
+            MsgType::CompareAndSwap => root::<cf_kv_fbs::CASResp>(buf)?.status(),
+            MsgType::Increment | MsgType::Decrement => root::<cf_kv_fbs::IncrResp>(buf)?.status(),
+            // retwis responses do not carry a status
+            _ => {
+                return Ok(ResponseStatus::Ok);
+            }
+        };
+        ResponseStatus::from_u32(status)
+    }
+
     fn deserialize_get_response(&self, buf: &[u8]) -> Result<Vec<u8>> {
         let get_resp = root::<cf_kv_fbs::GetResp>(buf)?;
         match get_resp.val() {
@@ -719,6 +1137,16 @@ where
         };
     }
 
+    fn deserialize_cas_response(&self, buf: &[u8]) -> Result<u64> {
+        let cas_resp = root::<cf_kv_fbs::CASResp>(buf)?;
+        Ok(cas_resp.version())
+    }
+
+    fn deserialize_incr_response(&self, buf: &[u8]) -> Result<i64> {
+        let incr_resp = root::<cf_kv_fbs::IncrResp>(buf)?;
+        Ok(incr_resp.val())
+    }
+
     fn check_add_user_num_values(&self, buf: &[u8]) -> Result<usize> {
         let add_user_resp = root::<cf_kv_fbs::AddUserResponse>(buf)?;
         match add_user_resp.first_value() {
@@ -1013,6 +1441,105 @@ where
         builder.finish(get_timeline, None);
         Ok(copy_into_buf(buf, &builder))
     }
+
+    fn serialize_delete(&self, buf: &mut [u8], key: &str, _datapath: &D) -> Result<usize> {
+        let mut builder = FlatBufferBuilder::new();
+        let args = cf_kv_fbs::DeleteReqArgs {
+            id: 0,
+            key: Some(builder.create_string(key.as_ref())),
+        };
+        let delete_req = cf_kv_fbs::DeleteReq::create(&mut builder, &args);
+        builder.finish(delete_req, None);
+        Ok(copy_into_buf(buf, &builder))
+    }
+
+    fn serialize_deletem(
+        &self,
+        buf: &mut [u8],
+        keys: &Vec<String>,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut builder = FlatBufferBuilder::new();
+        let keys_vec: Vec<WIPOffset<&str>> = keys
+            .iter()
+            .map(|key| builder.create_string(key.as_str()))
+            .collect();
+        let args = cf_kv_fbs::DeleteMReqArgs {
+            id: 0,
+            keys: Some(builder.create_vector(keys_vec.as_slice())),
+        };
+        let deletem_req = cf_kv_fbs::DeleteMReq::create(&mut builder, &args);
+        builder.finish(deletem_req, None);
+        Ok(copy_into_buf(buf, &builder))
+    }
+
+    fn serialize_cas(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        version: u64,
+        value: &str,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut builder = FlatBufferBuilder::new();
+        let args = cf_kv_fbs::CASReqArgs {
+            id: 0,
+            key: Some(builder.create_string(key.as_ref())),
+            version: version,
+            val: Some(builder.create_vector_direct::<u8>(value.as_bytes())),
+        };
+        let cas_req = cf_kv_fbs::CASReq::create(&mut builder, &args);
+        builder.finish(cas_req, None);
+        Ok(copy_into_buf(buf, &builder))
+    }
+
+    fn serialize_increment(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut builder = FlatBufferBuilder::new();
+        let args = cf_kv_fbs::IncrReqArgs {
+            id: 0,
+            key: Some(builder.create_string(key.as_ref())),
+            delta: delta,
+        };
+        let incr_req = cf_kv_fbs::IncrReq::create(&mut builder, &args);
+        builder.finish(incr_req, None);
+        Ok(copy_into_buf(buf, &builder))
+    }
+
+    fn serialize_decrement(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        datapath: &D,
+    ) -> Result<usize> {
+        self.serialize_increment(buf, key, delta, datapath)
+    }
+
+    fn serialize_scan(
+        &self,
+        buf: &mut [u8],
+        start_key: &str,
+        end_key: Option<&str>,
+        count: u64,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut builder = FlatBufferBuilder::new();
+        let args = cf_kv_fbs::ScanReqArgs {
+            id: 0,
+            start_key: Some(builder.create_string(start_key)),
+            end_key: Some(builder.create_string(end_key.unwrap_or_default())),
+            count: count,
+        };
+        let scan_req = cf_kv_fbs::ScanReq::create(&mut builder, &args);
+        builder.finish(scan_req, None);
+        Ok(copy_into_buf(buf, &builder))
+    }
 }
 
 fn copy_into_buf<'fbb>(buf: &mut [u8], builder: &FlatBufferBuilder<'fbb>) -> usize {
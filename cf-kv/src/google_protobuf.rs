@@ -1,6 +1,6 @@
 use super::{
-    allocate_datapath_buffer, ClientSerializer, KVServer, LinkedListKVServer, ListKVServer,
-    MsgType, RequestGenerator, ServerLoadGenerator, REQ_TYPE_SIZE,
+    shard::ShardSpec, ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType,
+    RequestGenerator, ResponseStatus, ServerLoadGenerator, REQ_TYPE_SIZE,
 };
 use color_eyre::eyre::{bail, ensure, Result, WrapErr};
 use cornflakes_libos::{allocator::MempoolID, datapath::Datapath};
@@ -9,7 +9,6 @@ use rand::{
     distributions::{Alphanumeric, Distribution, Uniform, WeightedIndex},
     thread_rng, Rng,
 };
-use std::io::Write;
 
 // taken from: https://sagark.org/assets/pubs/protoacc-micro2021-preprint.pdf
 // figure 4c
@@ -192,6 +191,7 @@ pub struct GoogleProtobufServerLoader {
     num_values_distribution: NumValuesDistribution,
     value_size_generator: ValueSizeDistribution,
     max_size: usize,
+    shard: Option<ShardSpec>,
 }
 
 impl GoogleProtobufServerLoader {
@@ -208,8 +208,14 @@ impl GoogleProtobufServerLoader {
             num_values_distribution,
             value_size_generator,
             max_size,
+            shard: None,
         }
     }
+
+    /// Only load keys the given shard owns.
+    pub fn set_shard(&mut self, shard: ShardSpec) {
+        self.shard = Some(shard);
+    }
 }
 
 impl ServerLoadGenerator for GoogleProtobufServerLoader {
@@ -304,13 +310,21 @@ impl ServerLoadGenerator for GoogleProtobufServerLoader {
     where
         D: Datapath,
     {
+        if let Some(shard) = &self.shard {
+            if !shard.owns(request.key()) {
+                return Ok(());
+            }
+        }
         // for google protobuf loader, only used linked list kv server
         let char = thread_rng().sample(&Alphanumeric) as char;
         for value_size in request.value_sizes().iter() {
             let value: String = std::iter::repeat(char).take(*value_size).collect();
-            let mut datapath_buffer = allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
-            let _ = datapath_buffer.write(value.as_bytes())?;
-            linked_list_kv_server.insert(request.key().to_string(), datapath_buffer);
+            linked_list_kv_server.append_with_copies(
+                request.key(),
+                value.as_bytes(),
+                datapath,
+                mempool_ids,
+            )?;
         }
         Ok(())
     }
@@ -346,6 +360,27 @@ impl GoogleProtobufClient {
             cur_key: 0,
         }
     }
+
+    /// Resamples the keys to request from the keys the given shard owns, keeping the number of
+    /// requests the same.
+    pub fn set_shard(&mut self, shard: ShardSpec) -> Result<()> {
+        let owned: Vec<usize> = (0..self.keys.len())
+            .filter(|idx| shard.owns(&self.keys[*idx]))
+            .collect();
+        ensure!(
+            !owned.is_empty(),
+            "Shard {} of {} owns none of the keys",
+            shard.shard_id,
+            shard.num_shards
+        );
+        let key_distr = Uniform::from(0..owned.len());
+        let mut rng = thread_rng();
+        for idx in self.keys_to_sample.iter_mut() {
+            *idx = owned[key_distr.sample(&mut rng)];
+        }
+        self.cur_key = 0;
+        Ok(())
+    }
 }
 
 impl Default for GoogleProtobufClient {
@@ -403,7 +438,7 @@ impl RequestGenerator for GoogleProtobufClient {
 
     fn check_response<S, D>(
         &self,
-        _request: &<Self as RequestGenerator>::RequestLine,
+        request: &<Self as RequestGenerator>::RequestLine,
         buf: &[u8],
         serializer: &S,
         _kv: &HashMap<String, String>,
@@ -413,6 +448,11 @@ impl RequestGenerator for GoogleProtobufClient {
         S: ClientSerializer<D>,
         D: Datapath,
     {
+        let status = serializer.deserialize_response_status(self.message_type(request)?, buf)?;
+        if status != ResponseStatus::Ok {
+            tracing::warn!(status =? status, "Received error response for {:?}", request);
+            return Ok(false);
+        }
         let vals = serializer.deserialize_getlist_response(buf)?;
         match vals.len() > 0 {
             true => return Ok(true),
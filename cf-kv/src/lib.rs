@@ -1,15 +1,20 @@
+pub mod cache;
 pub mod capnproto;
 pub mod cdn;
 pub mod cornflakes_dynamic;
 pub mod flatbuffers;
 pub mod google_protobuf;
 pub mod protobuf;
+pub mod persistence;
 pub mod redis;
+pub mod replication;
 pub mod retwis;
 pub mod retwis_run_datapath;
 pub mod run_cdn;
 pub mod run_google_protobuf;
 pub mod run_twitter;
+pub mod shard;
+pub mod storage;
 pub mod twitter;
 pub mod ycsb;
 pub mod ycsb_run_datapath;
@@ -27,24 +32,39 @@ mod kv_capnp {
 
 use byteorder::{BigEndian, ByteOrder};
 use bytes::Bytes;
+use cache::{Cache, CacheBudget, CacheConfig, CacheStats};
 use color_eyre::eyre::{bail, Result};
 use cornflakes_libos::{
     allocator::MempoolID,
     datapath::{pad_mempool_size, Datapath, ReceivedPkt},
+    loadgen::flow_control::FlowControlConfig,
     state_machine::client::ClientSM,
     timing::{ManualHistogram, SizedManualHistogram},
     utils::AddressInfo,
     MsgID,
 };
 use hashbrown::HashMap;
+use persistence::{DurableLog, LogRecord, PersistenceConfig, RecoveryStats};
+use shard::set_shard_hint;
 use std::{
+    cell::RefCell,
+    collections::BTreeSet,
     fs::File,
     io::{prelude::*, BufReader},
     marker::PhantomData,
+    ops::Bound,
+    time::Duration,
 };
+use storage::{StorageStats, StoredValue};
 
 // 8 bytes at front of message for framing
 pub const REQ_TYPE_SIZE: usize = 4;
+/// Message type ids fit in the low byte of the type field; the high byte carries the shard hint
+/// (see `shard::set_shard_hint`).
+const MSG_TYPE_MASK: u16 = 0x00ff;
+/// Most values one scan response carries. Scans that ask for more, or for no limit, are cut
+/// short.
+pub const MAX_SCAN_COUNT: usize = 100;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum MsgType {
@@ -60,16 +80,32 @@ pub enum MsgType {
     PostTweet,          // Post Tweet Retwis,
     GetTimeline(usize), // Get timeline
     GetFromList,        // Get item from list kv. List index should be encoded in message itself.
+    Delete,             // single delete
+    DeleteM(u16),       // multiple key delete
+    CompareAndSwap,     // put conditioned on the key's current version
+    Increment,          // add delta to numeric value
+    Decrement,          // subtract delta from numeric value
+    Scan,               // values of a key range, in key order
 }
 
 impl MsgType {
     /// Reads first four bytes of packet to determine message type.
     pub fn from_packet<D: Datapath>(packet: &ReceivedPkt<D>) -> Result<Self> {
-        let buf = &packet.seg(0).as_ref();
+        MsgType::from_header(packet.seg(0).as_ref())
+    }
+
+    /// Reads the message type from the first four bytes of a request.
+    pub fn from_header(buf: &[u8]) -> Result<Self> {
+        if buf.len() < REQ_TYPE_SIZE {
+            bail!("request of {} bytes is too short for its type", buf.len());
+        }
         let msg_type = &buf[0..2];
         let size = &buf[2..4];
 
-        match (BigEndian::read_u16(msg_type), BigEndian::read_u16(size)) {
+        match (
+            BigEndian::read_u16(msg_type) & MSG_TYPE_MASK,
+            BigEndian::read_u16(size),
+        ) {
             (0, 1) => Ok(MsgType::Get),
             (1, 1) => Ok(MsgType::Put),
             (2, size) => Ok(MsgType::GetM(size)),
@@ -82,12 +118,42 @@ impl MsgType {
             (9, 0) => Ok(MsgType::PostTweet),
             (10, 0) => Ok(MsgType::GetTimeline(0)),
             (11, 0) => Ok(MsgType::GetFromList),
+            (12, 1) => Ok(MsgType::Delete),
+            (13, size) => Ok(MsgType::DeleteM(size)),
+            (14, 1) => Ok(MsgType::CompareAndSwap),
+            (15, 1) => Ok(MsgType::Increment),
+            (16, 1) => Ok(MsgType::Decrement),
+            (17, 1) => Ok(MsgType::Scan),
             (x, y) => {
                 bail!("unrecognized message type for kv store app: {}, {}", x, y);
             }
         }
     }
 
+    /// Whether requests of this type change the stores.
+    pub fn is_write(&self) -> bool {
+        match self {
+            MsgType::Get
+            | MsgType::GetM(_)
+            | MsgType::GetList(_)
+            | MsgType::GetTimeline(_)
+            | MsgType::GetFromList
+            | MsgType::Scan => false,
+            MsgType::Put
+            | MsgType::PutM(_)
+            | MsgType::PutList(_)
+            | MsgType::AppendToList(_)
+            | MsgType::AddUser
+            | MsgType::FollowUnfollow
+            | MsgType::PostTweet
+            | MsgType::Delete
+            | MsgType::DeleteM(_)
+            | MsgType::CompareAndSwap
+            | MsgType::Increment
+            | MsgType::Decrement => true,
+        }
+    }
+
     /// Writes message type into first four bytes of provided buffer.
     fn to_buf(&self, buf: &mut [u8]) {
         match self {
@@ -139,15 +205,111 @@ impl MsgType {
                 BigEndian::write_u16(&mut buf[0..2], 11);
                 BigEndian::write_u16(&mut buf[2..4], 0);
             }
+            MsgType::Delete => {
+                BigEndian::write_u16(&mut buf[0..2], 12);
+                BigEndian::write_u16(&mut buf[2..4], 1);
+            }
+            MsgType::DeleteM(size) => {
+                BigEndian::write_u16(&mut buf[0..2], 13);
+                BigEndian::write_u16(&mut buf[2..4], *size);
+            }
+            MsgType::CompareAndSwap => {
+                BigEndian::write_u16(&mut buf[0..2], 14);
+                BigEndian::write_u16(&mut buf[2..4], 1);
+            }
+            MsgType::Increment => {
+                BigEndian::write_u16(&mut buf[0..2], 15);
+                BigEndian::write_u16(&mut buf[2..4], 1);
+            }
+            MsgType::Decrement => {
+                BigEndian::write_u16(&mut buf[0..2], 16);
+                BigEndian::write_u16(&mut buf[2..4], 1);
+            }
+            MsgType::Scan => {
+                BigEndian::write_u16(&mut buf[0..2], 17);
+                BigEndian::write_u16(&mut buf[2..4], 1);
+            }
         }
     }
 }
 
+/// Status carried in the `status` field of every kv response. `Ok` is zero, so a response that
+/// never sets the field decodes as a success.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u32)]
+pub enum ResponseStatus {
+    Ok = 0,
+    NotFound = 1,
+    WrongType = 2,
+    BadRequest = 3,
+    ServerError = 4,
+    VersionMismatch = 5,
+}
+
+impl ResponseStatus {
+    pub fn from_u32(status: u32) -> Result<Self> {
+        match status {
+            0 => Ok(ResponseStatus::Ok),
+            1 => Ok(ResponseStatus::NotFound),
+            2 => Ok(ResponseStatus::WrongType),
+            3 => Ok(ResponseStatus::BadRequest),
+            4 => Ok(ResponseStatus::ServerError),
+            5 => Ok(ResponseStatus::VersionMismatch),
+            x => {
+                bail!("unrecognized response status for kv store app: {}", x);
+            }
+        }
+    }
+
+    #[inline]
+    pub fn as_u32(&self) -> u32 {
+        *self as u32
+    }
+
+    /// Status to answer a request whose handler failed with `e`: the status the error was
+    /// tagged with, or `BadRequest` for requests that could not be decoded.
+    pub fn from_error(e: &color_eyre::eyre::Report) -> Self {
+        e.downcast_ref::<ResponseStatus>()
+            .copied()
+            .unwrap_or(ResponseStatus::BadRequest)
+    }
+}
+
+impl std::fmt::Display for ResponseStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for ResponseStatus {}
+
+/// Logs a request the server could not handle and returns the status to reply with, so one bad
+/// request does not take down the server. `msg_type` is None if the type itself was unreadable.
+pub fn failed_request_status<D>(
+    pkt: &ReceivedPkt<D>,
+    msg_type: Option<MsgType>,
+    e: &color_eyre::eyre::Report,
+) -> ResponseStatus
+where
+    D: Datapath,
+{
+    let status = ResponseStatus::from_error(e);
+    tracing::warn!(
+        msg_id = pkt.msg_id(),
+        conn_id = pkt.conn_id(),
+        msg_type =? msg_type,
+        status =? status,
+        "Failed to handle request: {:?}",
+        e
+    );
+    status
+}
+
 pub struct KVNode<D>
 where
     D: Datapath,
 {
-    buffer: D::DatapathBuffer,
+    buffer: StoredValue<D>,
     next: Option<Box<KVNode<D>>>,
 }
 
@@ -175,14 +337,14 @@ impl<D> KVNode<D>
 where
     D: Datapath,
 {
-    pub fn new(buf: D::DatapathBuffer) -> Self {
+    pub fn new(buf: StoredValue<D>) -> Self {
         KVNode {
             buffer: buf,
             next: None,
         }
     }
 
-    pub fn append(&mut self, buf: D::DatapathBuffer) {
+    pub fn append(&mut self, buf: StoredValue<D>) {
         match &mut self.next {
             Some(ref mut node) => {
                 node.append(buf);
@@ -204,7 +366,7 @@ where
         }
     }
 
-    pub fn get_buffer(&self) -> &D::DatapathBuffer {
+    pub fn get_buffer(&self) -> &StoredValue<D> {
         &self.buffer
     }
 
@@ -216,7 +378,7 @@ where
         self.next.as_ref()
     }
 
-    pub fn replace_data(&mut self, elt: D::DatapathBuffer) {
+    pub fn replace_data(&mut self, elt: StoredValue<D>) {
         self.buffer = elt;
     }
 }
@@ -226,6 +388,11 @@ where
     D: Datapath,
 {
     map: HashMap<String, Box<KVNode<D>>>,
+    versions: HashMap<String, u64>,
+    cache: RefCell<Cache>,
+    /// Values shorter than this are kept on the heap; see `KVServer::set_inline_threshold`.
+    inline_threshold: usize,
+    storage_stats: StorageStats,
 }
 
 impl<D> LinkedListKVServer<D>
@@ -233,8 +400,86 @@ where
     D: Datapath,
 {
     pub fn new() -> Self {
+        Self::with_cache_config(CacheConfig::default())
+    }
+
+    pub fn with_cache_config(config: CacheConfig) -> Self {
+        Self::with_cache(Cache::new(config))
+    }
+
+    /// See `KVServer::with_cache`.
+    pub fn with_cache(cache: Cache) -> Self {
         LinkedListKVServer {
             map: HashMap::default(),
+            versions: HashMap::default(),
+            cache: RefCell::new(cache),
+            inline_threshold: 0,
+            storage_stats: StorageStats::default(),
+        }
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.borrow().stats()
+    }
+
+    /// See `KVServer::set_inline_threshold`.
+    pub fn set_inline_threshold(&mut self, threshold: usize) {
+        self.inline_threshold = threshold;
+    }
+
+    pub fn storage_stats(&self) -> StorageStats {
+        self.storage_stats
+    }
+
+    /// Charges `bytes` to the key and drops whatever the cache evicts to make room; see
+    /// `KVServer::admit`.
+    fn admit(&mut self, key: &str, bytes: usize, ttl: Option<Duration>) -> Result<()> {
+        for victim in self.cache.get_mut().record_insert(key, bytes, ttl)? {
+            if self.unstore(&victim).is_some() {
+                self.bump_version(&victim);
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts the list into the map, keeping the storage counters current.
+    fn store(&mut self, key: String, node: Box<KVNode<D>>) {
+        self.record_list(&node, true);
+        if let Some(old_node) = self.map.insert(key, node) {
+            self.record_list(&old_node, false);
+        }
+    }
+
+    fn unstore(&mut self, key: &str) -> Option<Box<KVNode<D>>> {
+        let node = self.map.remove(key);
+        if let Some(node) = &node {
+            self.record_list(node, false);
+        }
+        node
+    }
+
+    fn record_list(&mut self, node: &KVNode<D>, insert: bool) {
+        let mut node = Some(node);
+        while let Some(current) = node {
+            match insert {
+                true => self.storage_stats.record_insert(current.get_buffer()),
+                false => self.storage_stats.record_remove(current.get_buffer()),
+            }
+            node = current.get_next().map(|next| next.as_ref());
+        }
+    }
+
+    /// Current version of the key; see `KVServer::version`.
+    pub fn version(&self, key: &str) -> u64 {
+        self.versions.get(key).copied().unwrap_or(0)
+    }
+
+    fn bump_version(&mut self, key: &str) {
+        match self.versions.get_mut(key) {
+            Some(version) => *version += 1,
+            None => {
+                self.versions.insert(key.to_string(), 1);
+            }
         }
     }
 
@@ -246,6 +491,7 @@ where
         &self.map
     }
 
+    /// Changes made through the map are not reflected in `storage_stats`.
     pub fn get_mut_map(&mut self) -> &mut HashMap<String, Box<KVNode<D>>> {
         &mut self.map
     }
@@ -255,22 +501,57 @@ where
     }
 
     pub fn get(&self, key: &str) -> Option<&Box<KVNode<D>>> {
-        self.map.get(key)
+        let node = self.map.get(key);
+        match self.cache.borrow_mut().record_lookup(key, node.is_some()) {
+            true => node,
+            false => None,
+        }
     }
 
     pub fn remove(&mut self, key: &str) -> Option<Box<KVNode<D>>> {
-        self.map.remove(key)
+        let node = self.unstore(key);
+        if node.is_some() {
+            self.cache.get_mut().record_remove(key);
+            self.bump_version(key);
+        }
+        node
+    }
+
+    /// Appends a buffer the caller allocated to the key's list; see `KVServer::insert`.
+    pub fn insert(&mut self, key: String, value: D::DatapathBuffer) -> Result<()> {
+        let value = StoredValue::from_buffer(value, self.inline_threshold);
+        let bytes = charged_value_bytes(&value, pad_mempool_size);
+        self.append_value(key, value, bytes)
     }
 
-    pub fn insert(&mut self, key: String, value: D::DatapathBuffer) {
+    /// Like `insert`, but copies the value in, so a value under the inline threshold never
+    /// takes up a registered buffer.
+    pub fn append_with_copies(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<()> {
+        let value = StoredValue::with_copies(value, self.inline_threshold, datapath, mempool_ids)?;
+        let bytes = charged_value_bytes(&value, |len| datapath.mempool_size_class(len));
+        self.append_value(key.to_string(), value, bytes)
+    }
+
+    fn append_value(&mut self, key: String, value: StoredValue<D>, bytes: usize) -> Result<()> {
+        let bytes = self.cache.get_mut().charged_bytes(&key) + bytes;
+        self.admit(&key, bytes, None)?;
+        self.bump_version(&key);
         match self.map.get_mut(&key) {
             Some(ref mut node) => {
+                self.storage_stats.record_insert(&value);
                 node.as_mut().append(value);
             }
             None => {
-                self.map.insert(key, Box::new(KVNode::new(value)));
+                self.store(key, Box::new(KVNode::new(value)));
             }
         }
+        Ok(())
     }
 
     pub fn insert_with_copies(
@@ -280,13 +561,66 @@ where
         datapath: &mut D,
         mempool_ids: &mut Vec<MempoolID>,
     ) -> Result<()> {
-        let key = key.to_string();
-        let mut datapath_buffer = allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
-        let _ = datapath_buffer.write(value)?;
-        self.map.insert(key, Box::new(KVNode::new(datapath_buffer)));
+        self.insert_with_copies_and_ttl(key, value, None, datapath, mempool_ids)
+    }
+
+    /// Like `insert_with_copies`, but the key expires after `ttl` (or the store's default TTL
+    /// if None).
+    pub fn insert_with_copies_and_ttl(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<()> {
+        let value = StoredValue::with_copies(value, self.inline_threshold, datapath, mempool_ids)?;
+        self.admit(
+            key,
+            charged_value_bytes(&value, |len| datapath.mempool_size_class(len)),
+            ttl,
+        )?;
+        self.bump_version(key);
+        self.store(key.to_string(), Box::new(KVNode::new(value)));
         Ok(())
     }
 
+    /// Replaces the value only if the key is still at `expected_version`. Returns the status
+    /// and the key's version after the call.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected_version: u64,
+        value: &[u8],
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<(ResponseStatus, u64)> {
+        let version = self.version(key);
+        if version != expected_version {
+            return Ok((ResponseStatus::VersionMismatch, version));
+        }
+        self.insert_with_copies(key, value, datapath, mempool_ids)?;
+        Ok((ResponseStatus::Ok, self.version(key)))
+    }
+
+    /// Adds `delta` to the numeric value at the head of the key's list, replacing the list with
+    /// the result.
+    pub fn increment(
+        &mut self,
+        key: &str,
+        delta: i64,
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<(ResponseStatus, i64)> {
+        match apply_increment(self.get(key).map(|node| node.get_data()), delta) {
+            Ok(value) => {
+                self.insert_with_copies(key, value.to_string().as_bytes(), datapath, mempool_ids)?;
+                Ok((ResponseStatus::Ok, value))
+            }
+            Err(status) => Ok((status, 0)),
+        }
+    }
+
     pub fn insert_list_with_copies<'a>(
         &mut self,
         key: &str,
@@ -294,21 +628,26 @@ where
         datapath: &mut D,
         mempool_ids: &mut Vec<MempoolID>,
     ) -> Result<()> {
+        let mut bytes = 0;
         let first_buffer = {
             let value = values.next().unwrap();
-            let mut datapath_buffer = allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
-            let _ = datapath_buffer.write(value)?;
-            datapath_buffer
+            let value =
+                StoredValue::with_copies(value, self.inline_threshold, datapath, mempool_ids)?;
+            bytes += charged_value_bytes(&value, |len| datapath.mempool_size_class(len));
+            value
         };
 
         let mut kv_node = KVNode::new(first_buffer);
         while let Some(value) = values.next() {
-            let mut datapath_buffer = allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
-            let _ = datapath_buffer.write(value)?;
-            kv_node.append(datapath_buffer);
+            let value =
+                StoredValue::with_copies(value, self.inline_threshold, datapath, mempool_ids)?;
+            bytes += charged_value_bytes(&value, |len| datapath.mempool_size_class(len));
+            kv_node.append(value);
         }
 
-        self.map.insert(key.to_string(), Box::new(kv_node));
+        self.admit(key, bytes, None)?;
+        self.bump_version(key);
+        self.store(key.to_string(), Box::new(kv_node));
         Ok(())
     }
     pub fn keys(&self) -> Vec<String> {
@@ -320,7 +659,20 @@ pub struct KVServer<D>
 where
     D: Datapath,
 {
-    map: HashMap<String, D::DatapathBuffer>,
+    map: HashMap<String, StoredValue<D>>,
+    /// Per-key write counters for compare-and-swap. Entries outlive deletes so a key that is
+    /// removed and re-inserted never returns to a version a client has already seen.
+    versions: HashMap<String, u64>,
+    /// Memory budget, eviction and TTL state. Behind a `RefCell` so lookups through `&self`
+    /// still count hits and misses and update recency.
+    cache: RefCell<Cache>,
+    /// Keys in order, for range scans; only kept once `enable_ordered_index` is called.
+    index: Option<BTreeSet<String>>,
+    /// Durable log of mutations; only kept once `enable_persistence` is called.
+    log: Option<DurableLog>,
+    /// Values shorter than this are kept on the heap rather than in registered buffers.
+    inline_threshold: usize,
+    storage_stats: StorageStats,
 }
 
 impl<D> KVServer<D>
@@ -328,8 +680,185 @@ where
     D: Datapath,
 {
     pub fn new() -> Self {
+        Self::with_cache_config(CacheConfig::default())
+    }
+
+    pub fn with_cache_config(config: CacheConfig) -> Self {
+        Self::with_cache(Cache::new(config))
+    }
+
+    /// A store tracked by `cache`, which may share its memory budget with other stores.
+    pub fn with_cache(cache: Cache) -> Self {
         KVServer {
             map: HashMap::default(),
+            versions: HashMap::default(),
+            cache: RefCell::new(cache),
+            index: None,
+            log: None,
+            inline_threshold: 0,
+            storage_stats: StorageStats::default(),
+        }
+    }
+
+    /// Values shorter than `threshold` bytes inserted from now on are kept on the heap instead
+    /// of in registered buffers; see `storage`. 0, the default, keeps every value in a
+    /// registered buffer.
+    pub fn set_inline_threshold(&mut self, threshold: usize) {
+        self.inline_threshold = threshold;
+    }
+
+    pub fn storage_stats(&self) -> StorageStats {
+        self.storage_stats
+    }
+
+    /// Inserts the value into the map, keeping the storage counters current.
+    fn store(&mut self, key: String, value: StoredValue<D>) {
+        self.storage_stats.record_insert(&value);
+        if let Some(old_value) = self.map.insert(key, value) {
+            self.storage_stats.record_remove(&old_value);
+        }
+    }
+
+    fn unstore(&mut self, key: &str) -> Option<StoredValue<D>> {
+        let value = self.map.remove(key);
+        if let Some(value) = &value {
+            self.storage_stats.record_remove(value);
+        }
+        value
+    }
+
+    /// Keeps the keys sorted so the store can serve range scans, at the cost of a second copy
+    /// of every key and an ordered insert on every new key.
+    pub fn enable_ordered_index(&mut self) {
+        self.index = Some(self.map.keys().cloned().collect());
+    }
+
+    pub fn has_ordered_index(&self) -> bool {
+        self.index.is_some()
+    }
+
+    fn index_key(&mut self, key: &str) {
+        if let Some(index) = &mut self.index {
+            if !index.contains(key) {
+                index.insert(key.to_string());
+            }
+        }
+    }
+
+    fn unindex_key(&mut self, key: &str) {
+        if let Some(index) = &mut self.index {
+            index.remove(key);
+        }
+    }
+
+    /// Recovers whatever snapshot and logs `config.dir` holds, copying the values into the
+    /// store, then logs every mutation from here on, starting with a snapshot of
+    /// the store's contents. Changes made through `get_mut_map` are not logged.
+    pub fn enable_persistence(
+        &mut self,
+        config: &PersistenceConfig,
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<RecoveryStats> {
+        let stats = persistence::replay(&config.dir, |record| match record {
+            LogRecord::Put { key, value, ttl_us } => self.insert_with_copies_and_ttl(
+                &key,
+                &value,
+                ttl_from_micros(ttl_us),
+                datapath,
+                mempool_ids,
+            ),
+            LogRecord::Delete { key } => {
+                self.remove(&key);
+                Ok(())
+            }
+            record => bail!(
+                "Unexpected list record in kv store log for key {}",
+                record.key()
+            ),
+        })?;
+        let log = DurableLog::open(
+            config.clone(),
+            self.map.keys().filter_map(|key| self.snapshot_record(key)),
+        )?;
+        self.log = Some(log);
+        Ok(stats)
+    }
+
+    /// Logs a mutation, starting a new snapshot first if one is due. An unfinished snapshot
+    /// copies a few more keys with every mutation, starting with the mutated key if it has yet
+    /// to, so each snapshot copy of the store's values is spread over many requests. Puts are
+    /// logged before they are applied, so the snapshot copies the key's value from before the
+    /// put; deletes are logged after, which is harmless since the key's absence then matches
+    /// the delete that follows in the log.
+    fn persist(&mut self, record: impl FnOnce() -> LogRecord) {
+        let log = match &mut self.log {
+            Some(log) => log,
+            None => return,
+        };
+        if log.snapshot_due() {
+            log.start_snapshot(self.map.keys().cloned());
+        }
+        let record = record();
+        let keys = log.keys_to_snapshot(record.key());
+        let snapshot_records = keys
+            .iter()
+            .filter_map(|key| self.snapshot_record(key))
+            .collect();
+        if let Some(log) = &mut self.log {
+            log.snapshot_records(snapshot_records);
+            log.append(record);
+        }
+    }
+
+    /// The key as a snapshot holds it: a put carrying its remaining TTL. None for keys that
+    /// are gone, or have expired but not been reclaimed.
+    fn snapshot_record(&self, key: &str) -> Option<LogRecord> {
+        let value = self.map.get(key)?;
+        let ttl_us = match self.cache.borrow().remaining_ttl(key) {
+            Some(ttl) if ttl.is_zero() => return None,
+            // a TTL under a microsecond must not read back as no TTL
+            Some(ttl) => std::cmp::max(ttl.as_micros() as u64, 1),
+            None => 0,
+        };
+        Some(LogRecord::Put {
+            key: key.to_string(),
+            value: value.as_ref().to_vec(),
+            ttl_us,
+        })
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.borrow().stats()
+    }
+
+    /// Charges `bytes` to the key, then drops the keys the cache evicts (or found expired) to
+    /// stay within the budget. Dropping a buffer here only releases the store's reference;
+    /// zero-copy sends still in flight keep it valid until they complete. Fails, leaving the
+    /// store untouched, if the value cannot fit in the budget.
+    fn admit(&mut self, key: &str, bytes: usize, ttl: Option<Duration>) -> Result<()> {
+        for victim in self.cache.get_mut().record_insert(key, bytes, ttl)? {
+            if self.unstore(&victim).is_some() {
+                self.bump_version(&victim);
+                self.unindex_key(&victim);
+                self.persist(|| LogRecord::Delete { key: victim });
+            }
+        }
+        Ok(())
+    }
+
+    /// Current version of the key: 0 if it has never been written, bumped on every insert and
+    /// remove.
+    pub fn version(&self, key: &str) -> u64 {
+        self.versions.get(key).copied().unwrap_or(0)
+    }
+
+    fn bump_version(&mut self, key: &str) {
+        match self.versions.get_mut(key) {
+            Some(version) => *version += 1,
+            None => {
+                self.versions.insert(key.to_string(), 1);
+            }
         }
     }
 
@@ -341,24 +870,86 @@ where
         self.map.len()
     }
 
-    pub fn get_map(&self) -> &HashMap<String, D::DatapathBuffer> {
+    pub fn get_map(&self) -> &HashMap<String, StoredValue<D>> {
         &self.map
     }
 
-    pub fn get_mut_map(&mut self) -> &mut HashMap<String, D::DatapathBuffer> {
+    /// Changes made through the map are not reflected in `storage_stats`.
+    pub fn get_mut_map(&mut self) -> &mut HashMap<String, StoredValue<D>> {
         &mut self.map
     }
 
-    pub fn get(&self, key: &str) -> Option<&D::DatapathBuffer> {
-        self.map.get(key)
+    /// Returns None for keys whose TTL has passed, even if they have not been reclaimed yet.
+    pub fn get(&self, key: &str) -> Option<&StoredValue<D>> {
+        let value = self.map.get(key);
+        match self.cache.borrow_mut().record_lookup(key, value.is_some()) {
+            true => value,
+            false => None,
+        }
     }
 
-    pub fn remove(&mut self, key: &str) -> Option<D::DatapathBuffer> {
-        self.map.remove(key)
+    pub fn remove(&mut self, key: &str) -> Option<StoredValue<D>> {
+        let value = self.unstore(key);
+        if value.is_some() {
+            self.persist(|| LogRecord::Delete {
+                key: key.to_string(),
+            });
+            self.cache.get_mut().record_remove(key);
+            self.bump_version(key);
+            self.unindex_key(key);
+        }
+        value
     }
 
-    pub fn insert(&mut self, key: String, value: D::DatapathBuffer) {
-        self.map.insert(key, value);
+    /// Values of up to `count` keys from `start_key` (inclusive) to `end_key` (exclusive, or the
+    /// end of the keyspace if None), in key order. A `count` of 0 or above `MAX_SCAN_COUNT` is
+    /// capped at `MAX_SCAN_COUNT`. Expired keys are skipped. Returns None if the store does not
+    /// keep an ordered index. Scanned keys count neither as cache hits nor as recent uses.
+    pub fn scan(
+        &self,
+        start_key: &str,
+        end_key: Option<&str>,
+        count: usize,
+    ) -> Option<Vec<&StoredValue<D>>> {
+        let index = self.index.as_ref()?;
+        let count = match count {
+            0 => MAX_SCAN_COUNT,
+            count => std::cmp::min(count, MAX_SCAN_COUNT),
+        };
+        let end = match end_key {
+            // BTreeSet::range panics on inverted ranges
+            Some(end_key) if end_key <= start_key => {
+                return Some(Vec::default());
+            }
+            Some(end_key) => Bound::Excluded(end_key),
+            None => Bound::Unbounded,
+        };
+        let cache = self.cache.borrow();
+        Some(
+            index
+                .range::<str, _>((Bound::Included(start_key), end))
+                .filter(|key| cache.is_live(key))
+                .filter_map(|key| self.map.get(key.as_str()))
+                .take(count)
+                .collect(),
+        )
+    }
+
+    /// Inserts a buffer the caller allocated, copying it onto the heap (and releasing it) if it
+    /// is under the inline threshold. Without the datapath at hand, the buffer is charged the
+    /// default power-of-two size class for its length.
+    pub fn insert(&mut self, key: String, value: D::DatapathBuffer) -> Result<()> {
+        let value = StoredValue::from_buffer(value, self.inline_threshold);
+        self.admit(&key, charged_value_bytes(&value, pad_mempool_size), None)?;
+        self.persist(|| LogRecord::Put {
+            key: key.clone(),
+            value: value.as_ref().to_vec(),
+            ttl_us: 0,
+        });
+        self.bump_version(&key);
+        self.index_key(&key);
+        self.store(key, value);
+        Ok(())
     }
 
     pub fn insert_with_copies(
@@ -368,18 +959,176 @@ where
         datapath: &mut D,
         mempool_ids: &mut Vec<MempoolID>,
     ) -> Result<()> {
-        let key = key.to_string();
-        let mut datapath_buffer = allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
-        let _ = datapath_buffer.write(value)?;
-        self.map.insert(key, datapath_buffer);
+        self.insert_with_copies_and_ttl(key, value, None, datapath, mempool_ids)
+    }
+
+    /// Like `insert_with_copies`, but the key expires after `ttl` (or the store's default TTL
+    /// if None).
+    pub fn insert_with_copies_and_ttl(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<()> {
+        let stored_value =
+            StoredValue::with_copies(value, self.inline_threshold, datapath, mempool_ids)?;
+        self.admit(
+            key,
+            charged_value_bytes(&stored_value, |len| datapath.mempool_size_class(len)),
+            ttl,
+        )?;
+        self.persist(|| LogRecord::Put {
+            key: key.to_string(),
+            value: value.to_vec(),
+            ttl_us: ttl.map(|ttl| ttl.as_micros() as u64).unwrap_or(0),
+        });
+        self.bump_version(key);
+        self.index_key(key);
+        self.store(key.to_string(), stored_value);
         Ok(())
     }
 
+    /// Replaces the value only if the key is still at `expected_version` (0 for a key that was
+    /// never written). Returns the status and the key's version after the call.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected_version: u64,
+        value: &[u8],
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<(ResponseStatus, u64)> {
+        let version = self.version(key);
+        if version != expected_version {
+            return Ok((ResponseStatus::VersionMismatch, version));
+        }
+        self.insert_with_copies(key, value, datapath, mempool_ids)?;
+        Ok((ResponseStatus::Ok, self.version(key)))
+    }
+
+    /// Adds `delta` to the value stored as an ASCII decimal integer, treating a missing key as
+    /// 0. Returns the status and the new value.
+    pub fn increment(
+        &mut self,
+        key: &str,
+        delta: i64,
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<(ResponseStatus, i64)> {
+        match apply_increment(self.get(key).map(|v| v.as_ref()), delta) {
+            Ok(value) => {
+                self.insert_with_copies(key, value.to_string().as_bytes(), datapath, mempool_ids)?;
+                Ok((ResponseStatus::Ok, value))
+            }
+            Err(status) => Ok((status, 0)),
+        }
+    }
+
     pub fn keys(&self) -> Vec<String> {
         self.map.keys().cloned().collect::<Vec<String>>()
     }
 }
 
+/// Parses `current` as a decimal integer and adds `delta`. Values that are not numbers are
+/// `WrongType`; results that overflow an i64 are `BadRequest`.
+pub fn apply_increment(
+    current: Option<&[u8]>,
+    delta: i64,
+) -> std::result::Result<i64, ResponseStatus> {
+    let current = match current {
+        Some(bytes) => std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|s| s.trim().parse::<i64>().ok())
+            .ok_or(ResponseStatus::WrongType)?,
+        None => 0,
+    };
+    current.checked_add(delta).ok_or(ResponseStatus::BadRequest)
+}
+
+/// Turns the unsigned delta carried on the wire into the signed amount to add; `negate` is set
+/// for decrements. Returns None if the delta does not fit in an i64.
+pub fn signed_delta(delta: u64, negate: bool) -> Option<i64> {
+    let delta = i64::try_from(delta).ok()?;
+    match negate {
+        true => Some(-delta),
+        false => Some(delta),
+    }
+}
+
+/// End key of a scan as carried on the wire, where an empty key stands for the end of the
+/// keyspace.
+pub fn scan_end_key(end_key: &str) -> Option<&str> {
+    match end_key {
+        "" => None,
+        end_key => Some(end_key),
+    }
+}
+
+/// Cache counters summed over the three stores.
+pub fn combined_cache_stats<D>(
+    kv_server: &KVServer<D>,
+    list_kv_server: &ListKVServer<D>,
+    linked_list_kv_server: &LinkedListKVServer<D>,
+) -> CacheStats
+where
+    D: Datapath,
+{
+    kv_server.cache_stats() + list_kv_server.cache_stats() + linked_list_kv_server.cache_stats()
+}
+
+/// Storage counters summed over the three stores.
+pub fn combined_storage_stats<D>(
+    kv_server: &KVServer<D>,
+    list_kv_server: &ListKVServer<D>,
+    linked_list_kv_server: &LinkedListKVServer<D>,
+) -> StorageStats
+where
+    D: Datapath,
+{
+    kv_server.storage_stats()
+        + list_kv_server.storage_stats()
+        + linked_list_kv_server.storage_stats()
+}
+
+/// Bytes a value is charged against the cache budget: heap values their length, registered
+/// buffers the size class `size_class` gives for their length.
+fn charged_value_bytes<D>(value: &StoredValue<D>, size_class: impl FnOnce(usize) -> usize) -> usize
+where
+    D: Datapath,
+{
+    match value {
+        StoredValue::Inline(data) => data.len(),
+        StoredValue::Registered(buf) => size_class(buf.as_ref().len()),
+    }
+}
+
+/// TTL carried on a put request; 0 means the put did not set one.
+pub fn ttl_from_micros(ttl_us: u64) -> Option<Duration> {
+    match ttl_us {
+        0 => None,
+        us => Some(Duration::from_micros(us)),
+    }
+}
+
+/// Removes the key from every store, since a key may have been loaded into any of them. Returns
+/// whether any store held it.
+pub fn delete_from_stores<D>(
+    key: &str,
+    kv_server: &mut KVServer<D>,
+    list_kv_server: &mut ListKVServer<D>,
+    linked_list_kv_server: &mut LinkedListKVServer<D>,
+) -> bool
+where
+    D: Datapath,
+{
+    let in_kv = kv_server.remove(key).is_some();
+    let in_list_kv = list_kv_server.remove(key).is_some();
+    let in_linked_list_kv = linked_list_kv_server.remove(key).is_some();
+    in_kv || in_list_kv || in_linked_list_kv
+}
+
 pub fn allocate_and_copy_into_datapath_buffer<D>(
     value: &[u8],
     datapath: &mut D,
@@ -397,7 +1146,12 @@ pub struct ListKVServer<D>
 where
     D: Datapath,
 {
-    map: HashMap<String, Vec<D::DatapathBuffer>>,
+    map: HashMap<String, Vec<StoredValue<D>>>,
+    cache: RefCell<Cache>,
+    log: Option<DurableLog>,
+    /// Values shorter than this are kept on the heap; see `KVServer::set_inline_threshold`.
+    inline_threshold: usize,
+    storage_stats: StorageStats,
 }
 
 impl<D> ListKVServer<D>
@@ -405,9 +1159,138 @@ where
     D: Datapath,
 {
     fn new() -> Self {
+        Self::with_cache_config(CacheConfig::default())
+    }
+
+    pub fn with_cache_config(config: CacheConfig) -> Self {
+        Self::with_cache(Cache::new(config))
+    }
+
+    /// See `KVServer::with_cache`.
+    pub fn with_cache(cache: Cache) -> Self {
         ListKVServer {
             map: HashMap::default(),
+            cache: RefCell::new(cache),
+            log: None,
+            inline_threshold: 0,
+            storage_stats: StorageStats::default(),
+        }
+    }
+
+    /// See `KVServer::set_inline_threshold`.
+    pub fn set_inline_threshold(&mut self, threshold: usize) {
+        self.inline_threshold = threshold;
+    }
+
+    pub fn storage_stats(&self) -> StorageStats {
+        self.storage_stats
+    }
+
+    /// Inserts the list into the map, keeping the storage counters current.
+    fn store(&mut self, key: String, list: Vec<StoredValue<D>>) {
+        list.iter()
+            .for_each(|value| self.storage_stats.record_insert(value));
+        if let Some(old_list) = self.map.insert(key, list) {
+            old_list
+                .iter()
+                .for_each(|value| self.storage_stats.record_remove(value));
+        }
+    }
+
+    fn unstore(&mut self, key: &str) -> Option<Vec<StoredValue<D>>> {
+        let list = self.map.remove(key);
+        if let Some(list) = &list {
+            list.iter()
+                .for_each(|value| self.storage_stats.record_remove(value));
         }
+        list
+    }
+
+    /// Recovers the store and logs its mutations from here on; see
+    /// `KVServer::enable_persistence`.
+    pub fn enable_persistence(
+        &mut self,
+        config: &PersistenceConfig,
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<RecoveryStats> {
+        let stats = persistence::replay(&config.dir, |record| match record {
+            LogRecord::PutList { key, values } => self.insert_with_copies(
+                &key,
+                values.iter().map(|value| value.as_slice()),
+                datapath,
+                mempool_ids,
+            ),
+            LogRecord::Append { key, value } => {
+                self.append_with_copies(&key, &value, datapath, mempool_ids)
+            }
+            LogRecord::Delete { key } => {
+                self.remove(&key);
+                Ok(())
+            }
+            record => bail!(
+                "Unexpected put record in list store log for key {}",
+                record.key()
+            ),
+        })?;
+        let log = DurableLog::open(
+            config.clone(),
+            self.map.keys().filter_map(|key| self.snapshot_record(key)),
+        )?;
+        self.log = Some(log);
+        Ok(stats)
+    }
+
+    /// Logs a mutation; see `KVServer::persist`. Appends in particular must be logged before
+    /// they are applied, or the snapshot copy of the list would hold the value twice once
+    /// replayed.
+    fn persist(&mut self, record: impl FnOnce() -> LogRecord) {
+        let log = match &mut self.log {
+            Some(log) => log,
+            None => return,
+        };
+        if log.snapshot_due() {
+            log.start_snapshot(self.map.keys().cloned());
+        }
+        let record = record();
+        let keys = log.keys_to_snapshot(record.key());
+        let snapshot_records = keys
+            .iter()
+            .filter_map(|key| self.snapshot_record(key))
+            .collect();
+        if let Some(log) = &mut self.log {
+            log.snapshot_records(snapshot_records);
+            log.append(record);
+        }
+    }
+
+    /// The list as a snapshot holds it: a list put. List puts carry no TTL, so recovered lists
+    /// get the store's default TTL afresh; lists that are gone or have already expired have no
+    /// record.
+    fn snapshot_record(&self, key: &str) -> Option<LogRecord> {
+        let list = self.map.get(key)?;
+        if self.cache.borrow().remaining_ttl(key) == Some(Duration::ZERO) {
+            return None;
+        }
+        Some(LogRecord::PutList {
+            key: key.to_string(),
+            values: list.iter().map(|value| value.as_ref().to_vec()).collect(),
+        })
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.borrow().stats()
+    }
+
+    /// Charges `bytes` to the key and drops whatever the cache evicts to make room; see
+    /// `KVServer::admit`.
+    fn admit(&mut self, key: &str, bytes: usize, ttl: Option<Duration>) -> Result<()> {
+        for victim in self.cache.get_mut().record_insert(key, bytes, ttl)? {
+            if self.unstore(&victim).is_some() {
+                self.persist(|| LogRecord::Delete { key: victim });
+            }
+        }
+        Ok(())
     }
 
     pub fn len(&self) -> usize {
@@ -418,33 +1301,92 @@ where
         self.map.contains_key(key)
     }
 
-    pub fn get_map(&self) -> &HashMap<String, Vec<D::DatapathBuffer>> {
+    pub fn get_map(&self) -> &HashMap<String, Vec<StoredValue<D>>> {
         &self.map
     }
 
-    pub fn get_mut_map(&mut self) -> &mut HashMap<String, Vec<D::DatapathBuffer>> {
+    /// Changes made through the map are not reflected in `storage_stats`.
+    pub fn get_mut_map(&mut self) -> &mut HashMap<String, Vec<StoredValue<D>>> {
         &mut self.map
     }
 
-    pub fn get(&self, key: &str) -> Option<&Vec<D::DatapathBuffer>> {
-        self.map.get(key)
+    pub fn get(&self, key: &str) -> Option<&Vec<StoredValue<D>>> {
+        let list = self.map.get(key);
+        match self.cache.borrow_mut().record_lookup(key, list.is_some()) {
+            true => list,
+            false => None,
+        }
     }
 
-    pub fn insert(&mut self, key: String, value: Vec<D::DatapathBuffer>) {
-        self.map.insert(key, value);
+    /// Inserts buffers the caller allocated, copying those under the inline threshold onto the
+    /// heap; charged as in `KVServer::insert`.
+    pub fn insert(&mut self, key: String, value: Vec<D::DatapathBuffer>) -> Result<()> {
+        let list: Vec<StoredValue<D>> = value
+            .into_iter()
+            .map(|buf| StoredValue::from_buffer(buf, self.inline_threshold))
+            .collect();
+        let bytes = list
+            .iter()
+            .map(|value| charged_value_bytes(value, pad_mempool_size))
+            .sum();
+        self.admit(&key, bytes, None)?;
+        self.persist(|| LogRecord::PutList {
+            key: key.clone(),
+            values: list.iter().map(|value| value.as_ref().to_vec()).collect(),
+        });
+        self.store(key, list);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Vec<StoredValue<D>>> {
+        let list = self.unstore(key);
+        if list.is_some() {
+            self.persist(|| LogRecord::Delete {
+                key: key.to_string(),
+            });
+            self.cache.get_mut().record_remove(key);
+        }
+        list
     }
 
-    pub fn append(&mut self, key: String, value: D::DatapathBuffer) {
+    /// Appends a buffer the caller allocated to the key's list; see `insert`.
+    pub fn append(&mut self, key: String, value: D::DatapathBuffer) -> Result<()> {
+        let value = StoredValue::from_buffer(value, self.inline_threshold);
+        let bytes = charged_value_bytes(&value, pad_mempool_size);
+        self.append_value(key, value, bytes)
+    }
+
+    /// Like `append`, but copies the value in, so a value under the inline threshold never
+    /// takes up a registered buffer.
+    pub fn append_with_copies(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<()> {
+        let value = StoredValue::with_copies(value, self.inline_threshold, datapath, mempool_ids)?;
+        let bytes = charged_value_bytes(&value, |len| datapath.mempool_size_class(len));
+        self.append_value(key.to_string(), value, bytes)
+    }
+
+    fn append_value(&mut self, key: String, value: StoredValue<D>, bytes: usize) -> Result<()> {
+        let bytes = self.cache.get_mut().charged_bytes(&key) + bytes;
+        self.admit(&key, bytes, None)?;
+        self.persist(|| LogRecord::Append {
+            key: key.clone(),
+            value: value.as_ref().to_vec(),
+        });
+        self.storage_stats.record_insert(&value);
         match self.map.get_mut(&key) {
             Some(list) => {
                 list.push(value);
             }
             None => {
-                let mut vec = Vec::with_capacity(1);
-                vec.push(value);
-                self.map.insert(key, vec);
+                self.map.insert(key, vec![value]);
             }
         }
+        Ok(())
     }
 
     pub fn insert_with_copies<'a>(
@@ -454,15 +1396,21 @@ where
         datapath: &mut D,
         mempool_ids: &mut Vec<MempoolID>,
     ) -> Result<()> {
-        let datapath_buffers: Result<Vec<D::DatapathBuffer>> = values
+        let mut bytes = 0;
+        let list = values
             .map(|value| {
-                let mut datapath_buffer =
-                    allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
-                let _ = datapath_buffer.write(value)?;
-                Ok(datapath_buffer)
+                let value =
+                    StoredValue::with_copies(value, self.inline_threshold, datapath, mempool_ids)?;
+                bytes += charged_value_bytes(&value, |len| datapath.mempool_size_class(len));
+                Ok(value)
             })
-            .collect();
-        self.map.insert(key.to_string(), datapath_buffers?);
+            .collect::<Result<Vec<StoredValue<D>>>>()?;
+        self.admit(key, bytes, None)?;
+        self.persist(|| LogRecord::PutList {
+            key: key.to_string(),
+            values: list.iter().map(|value| value.as_ref().to_vec()).collect(),
+        });
+        self.store(key.to_string(), list);
         Ok(())
     }
 
@@ -479,21 +1427,26 @@ fn allocate_datapath_buffer<D>(
 where
     D: Datapath,
 {
-    match datapath.allocate(pad_mempool_size(size))? {
+    let padded_size = datapath.mempool_size_class(size);
+    match datapath.allocate(padded_size)? {
         Some(buf) => Ok(buf),
         None => {
-            mempool_ids.append(&mut datapath.add_memory_pool_with_size(
-                size,
-                unsafe { cornflakes_libos::datapath::NUM_PAGES },
-                unsafe { cornflakes_libos::datapath::NUM_REGISTRATIONS },
-                unsafe { cornflakes_libos::datapath::REGISTER_AT_START },
-            )?);
+            let config = datapath.get_mempool_config();
+            // e.g. the mempool ceiling was reached
+            let mut new_ids = datapath
+                .add_memory_pool_with_size(
+                    size,
+                    config.num_pages,
+                    config.num_registrations,
+                    config.register_at_start,
+                )
+                .map_err(|e| e.wrap_err(ResponseStatus::ServerError))?;
+            mempool_ids.append(&mut new_ids);
             tracing::info!("Added mempool");
             match datapath.allocate(size)? {
                 Some(buf) => Ok(buf),
-                None => {
-                    panic!("Could not allocate");
-                }
+                None => Err(color_eyre::eyre::Report::new(ResponseStatus::ServerError)
+                    .wrap_err(format!("Could not allocate buffer of size {}", size))),
             }
         }
     }
@@ -512,6 +1465,23 @@ pub trait ServerLoadGenerator {
         Ok((kv_server, list_kv_server))
     }
 
+    /// Memory budget, eviction policy and default TTL for the stores `new_kv_state` creates;
+    /// unbounded with no TTL by default.
+    fn cache_config(&self) -> CacheConfig {
+        CacheConfig::default()
+    }
+
+    /// Whether the kv store `new_kv_state` creates keeps an ordered index for range scans.
+    fn ordered_index(&self) -> bool {
+        false
+    }
+
+    /// Where the stores `new_kv_state` creates keep their snapshots and logs; the stores are
+    /// not persisted by default.
+    fn persistence_config(&self) -> Option<PersistenceConfig> {
+        None
+    }
+
     fn new_kv_state<D>(
         &self,
         file: &str,
@@ -526,25 +1496,72 @@ pub trait ServerLoadGenerator {
     where
         D: Datapath,
     {
-        let mut kv_server = KVServer::new();
-        let mut list_kv_server = ListKVServer::new();
-        let mut linked_list_kv_server = LinkedListKVServer::new();
+        // the stores split one budget between them
+        let cache_config = self.cache_config();
+        let budget = CacheBudget::default();
+        let mut kv_server = KVServer::with_cache(Cache::with_budget(cache_config, budget.clone()));
+        if self.ordered_index() {
+            kv_server.enable_ordered_index();
+        }
+        let mut list_kv_server =
+            ListKVServer::with_cache(Cache::with_budget(cache_config, budget.clone()));
+        let mut linked_list_kv_server =
+            LinkedListKVServer::with_cache(Cache::with_budget(cache_config, budget));
+        // values the datapath would copy into responses anyway need not pin registered memory;
+        // the threshold is the one in effect now, so an adaptive controller may move past it
+        let inline_threshold = datapath.get_copying_threshold();
+        kv_server.set_inline_threshold(inline_threshold);
+        list_kv_server.set_inline_threshold(inline_threshold);
+        linked_list_kv_server.set_inline_threshold(inline_threshold);
         let mut mempool_ids: Vec<MempoolID> = Vec::default();
-        self.load_file(
-            file,
-            &mut kv_server,
-            &mut list_kv_server,
-            &mut linked_list_kv_server,
-            &mut mempool_ids,
-            datapath,
-            use_linked_list_kv,
-        )?;
+        let persistence_config = self.persistence_config();
+        let recover = match &persistence_config {
+            Some(_) if use_linked_list_kv => {
+                bail!("Persistence is not supported for the linked list kv store.");
+            }
+            Some(config) => {
+                persistence::has_state(&config.for_store("kv").dir)?
+                    || persistence::has_state(&config.for_store("list_kv").dir)?
+            }
+            None => false,
+        };
+        // the persisted state already reflects the trace, plus whatever clients changed since
+        if recover {
+            tracing::info!(
+                trace = file,
+                "Recovering persisted state instead of loading trace file"
+            );
+        } else {
+            self.load_file(
+                file,
+                &mut kv_server,
+                &mut list_kv_server,
+                &mut linked_list_kv_server,
+                &mut mempool_ids,
+                datapath,
+                use_linked_list_kv,
+            )?;
+        }
+        if let Some(config) = &persistence_config {
+            let kv_stats = kv_server.enable_persistence(
+                &config.for_store("kv"),
+                datapath,
+                &mut mempool_ids,
+            )?;
+            let list_kv_stats = list_kv_server.enable_persistence(
+                &config.for_store("list_kv"),
+                datapath,
+                &mut mempool_ids,
+            )?;
+            tracing::info!(dir =? config.dir, kv =? kv_stats, list_kv =? list_kv_stats, "Persisting kv stores");
+        }
 
+        let config = datapath.get_mempool_config();
         datapath.allocate_fallback_mempools(
             &mut mempool_ids,
-            unsafe { cornflakes_libos::datapath::NUM_PAGES },
-            unsafe { cornflakes_libos::datapath::NUM_REGISTRATIONS },
-            unsafe { cornflakes_libos::datapath::REGISTER_AT_START },
+            config.num_pages,
+            config.num_registrations,
+            config.register_at_start,
         )?;
         Ok((
             kv_server,
@@ -682,12 +1699,22 @@ where
     where
         Self: Sized;
 
+    /// Reads the status field out of a response to a request of the given type.
+    fn deserialize_response_status(&self, msg_type: MsgType, buf: &[u8]) -> Result<ResponseStatus>;
+
     fn deserialize_get_response(&self, buf: &[u8]) -> Result<Vec<u8>>;
 
+    /// Also reads scan responses, which have the same shape.
     fn deserialize_getm_response(&self, buf: &[u8]) -> Result<Vec<Vec<u8>>>;
 
     fn deserialize_getlist_response(&self, buf: &[u8]) -> Result<Vec<Vec<u8>>>;
 
+    /// Returns the key's version after a compare-and-swap (the current version on a mismatch).
+    fn deserialize_cas_response(&self, buf: &[u8]) -> Result<u64>;
+
+    /// Returns the value after an increment or decrement.
+    fn deserialize_incr_response(&self, buf: &[u8]) -> Result<i64>;
+
     fn check_add_user_num_values(&self, buf: &[u8]) -> Result<usize>;
 
     fn check_follow_unfollow_num_values(&self, buf: &[u8]) -> Result<usize>;
@@ -768,6 +1795,48 @@ where
         idx: usize,
         _datapath: &D,
     ) -> Result<usize>;
+
+    fn serialize_delete(&self, buf: &mut [u8], key: &str, datapath: &D) -> Result<usize>;
+
+    fn serialize_deletem(&self, buf: &mut [u8], keys: &Vec<String>, datapath: &D) -> Result<usize>;
+
+    fn serialize_cas(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        version: u64,
+        value: &str,
+        datapath: &D,
+    ) -> Result<usize>;
+
+    fn serialize_increment(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        datapath: &D,
+    ) -> Result<usize>;
+
+    /// Serializers that encode increments and decrements as the same message (the message type
+    /// carries the sign) can forward this to `serialize_increment`.
+    fn serialize_decrement(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        datapath: &D,
+    ) -> Result<usize>;
+
+    /// Scans from `start_key` up to `end_key` (to the end of the keyspace if None), returning at
+    /// most `count` values (0 for the server's limit).
+    fn serialize_scan(
+        &self,
+        buf: &mut [u8],
+        start_key: &str,
+        end_key: Option<&str>,
+        count: u64,
+        datapath: &D,
+    ) -> Result<usize>;
 }
 
 pub struct KVClient<R, C, D>
@@ -795,6 +1864,10 @@ where
     using_retries: bool,
     ref_kv: HashMap<String, String>,
     ref_list_kv: HashMap<String, Vec<String>>,
+    /// Server shard every request from this client is stamped for, if the server is sharded.
+    shard: Option<usize>,
+    /// Flow control for open-loop runs, if enabled.
+    flow_control: Option<FlowControlConfig>,
 }
 
 impl<R, C, D> KVClient<R, C, D>
@@ -834,9 +1907,22 @@ where
             using_retries: using_retries,
             ref_kv: ref_kv,
             ref_list_kv: ref_list_kv,
+            shard: None,
+            flow_control: None,
         })
     }
 
+    /// Stamps every request with the server shard this client sends to, so that a shard that
+    /// receives it anyway (e.g., via RSS) can forward it to the owner.
+    pub fn set_shard(&mut self, shard: usize) {
+        self.shard = Some(shard);
+    }
+
+    /// Holds back scheduled requests while the server's queueing delay is above the target.
+    pub fn set_flow_control(&mut self, flow_control: FlowControlConfig) {
+        self.flow_control = Some(flow_control);
+    }
+
     pub fn write_request_into_new_bytes(
         &self,
         request: &R::RequestLine,
@@ -849,16 +1935,23 @@ where
             &self.serializer,
             datapath,
         )?;
+        if let Some(shard) = self.shard {
+            set_shard_hint(bytes.as_mut_slice(), shard);
+        }
         Ok(Bytes::copy_from_slice(&bytes.as_slice()[0..serialized_len]))
     }
 
     pub fn write_request<'a>(&mut self, request: &R::RequestLine, datapath: &D) -> Result<usize> {
-        self.request_generator.serialize_request(
+        let serialized_len = self.request_generator.serialize_request(
             &request,
             &mut self.buf,
             &self.serializer,
             datapath,
-        )
+        )?;
+        if let Some(shard) = self.shard {
+            set_shard_hint(self.buf.as_mut_slice(), shard);
+        }
+        Ok(serialized_len)
     }
 }
 
@@ -953,6 +2046,10 @@ where
         self.server_addr.clone()
     }
 
+    fn flow_control_config(&self) -> Option<FlowControlConfig> {
+        self.flow_control
+    }
+
     fn prep_requests(
         &mut self,
         nb_requests: usize,
@@ -1017,6 +2114,11 @@ where
     ) -> Result<bool> {
         // if in debug mode, check whether the bytes are what they should be
         tracing::debug!(id = sga.msg_id(), size = sga.data_len(), "Received sga");
+        if sga.is_overloaded_reply() {
+            // the server shed the request, so it is still in flight and may be retried
+            tracing::debug!(id = sga.msg_id(), "Received overloaded reply");
+            return Ok(false);
+        }
         if self.using_retries {
             if let Some(_) = self.outgoing_requests.remove(&sga.msg_id()) {
             } else {
@@ -1061,4 +2163,200 @@ where
         let buf_size = self.write_request(&req, datapath)?;
         Ok(&self.buf.as_slice()[0..buf_size])
     }
+
+    fn msg_dropped_cb(&mut self, id: MsgID) -> Result<()> {
+        self.outgoing_requests.remove(&id);
+        self.outgoing_msg_types.remove(&id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::buffer;
+    use color_eyre::eyre::{eyre, Report, WrapErr};
+    use linux_datapath::datapath::connection::LinuxConnection;
+
+    const ALL_TYPES: [MsgType; 18] = [
+        MsgType::Get,
+        MsgType::Put,
+        MsgType::GetM(3),
+        MsgType::PutM(3),
+        MsgType::GetList(2),
+        MsgType::PutList(2),
+        MsgType::AppendToList(1),
+        MsgType::AddUser,
+        MsgType::FollowUnfollow,
+        MsgType::PostTweet,
+        MsgType::GetTimeline(0),
+        MsgType::GetFromList,
+        MsgType::Delete,
+        MsgType::DeleteM(4),
+        MsgType::CompareAndSwap,
+        MsgType::Increment,
+        MsgType::Decrement,
+        MsgType::Scan,
+    ];
+
+    #[test]
+    fn msg_types_round_trip() {
+        for msg_type in ALL_TYPES.iter() {
+            let mut buf = [0u8; REQ_TYPE_SIZE];
+            msg_type.to_buf(&mut buf);
+            assert_eq!(MsgType::from_header(&buf).unwrap(), *msg_type);
+        }
+    }
+
+    #[test]
+    fn shard_hint_does_not_change_msg_type() {
+        let mut buf = [0u8; REQ_TYPE_SIZE];
+        MsgType::Scan.to_buf(&mut buf);
+        set_shard_hint(&mut buf, 5);
+        assert_eq!(MsgType::from_header(&buf).unwrap(), MsgType::Scan);
+    }
+
+    #[test]
+    fn malformed_headers_are_errors() {
+        // too short to hold a type
+        assert!(MsgType::from_header(&[]).is_err());
+        assert!(MsgType::from_header(&[0, 0, 0]).is_err());
+        // unknown type
+        assert!(MsgType::from_header(&[0, 200, 0, 1]).is_err());
+        // known type with the wrong count
+        assert!(MsgType::from_header(&[0, 0, 0, 2]).is_err());
+    }
+
+    #[test]
+    fn untagged_errors_are_bad_requests() {
+        let err = MsgType::from_header(&[0, 200, 0, 1]).unwrap_err();
+        assert_eq!(ResponseStatus::from_error(&err), ResponseStatus::BadRequest);
+        let err: Result<()> = Err(eyre!("could not decode key")).wrap_err("Failed to get");
+        assert_eq!(
+            ResponseStatus::from_error(&err.unwrap_err()),
+            ResponseStatus::BadRequest
+        );
+    }
+
+    #[test]
+    fn tagged_errors_keep_their_status() {
+        let err = Report::new(ResponseStatus::ServerError).wrap_err("Could not allocate");
+        assert_eq!(ResponseStatus::from_error(&err), ResponseStatus::ServerError);
+        let err = eyre!("mempool ceiling reached").wrap_err(ResponseStatus::ServerError);
+        assert_eq!(ResponseStatus::from_error(&err), ResponseStatus::ServerError);
+    }
+
+    #[test]
+    fn response_status_round_trips() {
+        for status in [
+            ResponseStatus::Ok,
+            ResponseStatus::NotFound,
+            ResponseStatus::WrongType,
+            ResponseStatus::BadRequest,
+            ResponseStatus::ServerError,
+            ResponseStatus::VersionMismatch,
+        ] {
+            assert_eq!(ResponseStatus::from_u32(status.as_u32()).unwrap(), status);
+        }
+        assert!(ResponseStatus::from_u32(6).is_err());
+    }
+
+    #[test]
+    fn small_list_values_live_on_the_heap() {
+        let mut list_kv_server: ListKVServer<LinuxConnection> = ListKVServer::new();
+        list_kv_server.set_inline_threshold(8);
+        list_kv_server
+            .insert(
+                "list".to_string(),
+                vec![buffer(b"short"), buffer(b"long enough")],
+            )
+            .unwrap();
+        list_kv_server
+            .append("list".to_string(), buffer(b"tiny"))
+            .unwrap();
+        let list = list_kv_server.get("list").unwrap();
+        assert_eq!(
+            list.iter()
+                .map(|value| value.is_inline())
+                .collect::<Vec<bool>>(),
+            vec![true, false, true]
+        );
+        assert_eq!(
+            list.iter()
+                .map(|value| value.as_ref())
+                .collect::<Vec<&[u8]>>(),
+            vec![&b"short"[..], b"long enough", b"tiny"]
+        );
+        assert_eq!(
+            list_kv_server.storage_stats(),
+            StorageStats {
+                inline_values: 2,
+                inline_bytes: 9,
+                registered_values: 1,
+                registered_bytes: 11,
+            }
+        );
+        assert!(list_kv_server.remove("list").is_some());
+        assert_eq!(list_kv_server.storage_stats(), StorageStats::default());
+    }
+
+    #[test]
+    fn replaced_values_leave_the_storage_stats() {
+        let mut list_kv_server: ListKVServer<LinuxConnection> = ListKVServer::new();
+        list_kv_server
+            .insert("list".to_string(), vec![buffer(b"abc")])
+            .unwrap();
+        list_kv_server
+            .insert("list".to_string(), vec![buffer(b"abcdef"), buffer(b"x")])
+            .unwrap();
+        assert_eq!(
+            list_kv_server.storage_stats(),
+            StorageStats {
+                registered_values: 2,
+                registered_bytes: 7,
+                ..StorageStats::default()
+            }
+        );
+
+        let mut kv_server: KVServer<LinuxConnection> = KVServer::new();
+        kv_server.set_inline_threshold(4);
+        kv_server
+            .insert("key".to_string(), buffer(b"abcd"))
+            .unwrap();
+        kv_server.insert("key".to_string(), buffer(b"abc")).unwrap();
+        assert_eq!(
+            kv_server.storage_stats(),
+            StorageStats {
+                inline_values: 1,
+                inline_bytes: 3,
+                ..StorageStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn combined_storage_stats_cover_every_store() {
+        let mut kv_server: KVServer<LinuxConnection> = KVServer::new();
+        kv_server.set_inline_threshold(4);
+        kv_server.insert("a".to_string(), buffer(b"abc")).unwrap();
+        let mut list_kv_server: ListKVServer<LinuxConnection> = ListKVServer::new();
+        list_kv_server
+            .insert("b".to_string(), vec![buffer(b"abcd")])
+            .unwrap();
+        let mut linked_list_kv_server: LinkedListKVServer<LinuxConnection> =
+            LinkedListKVServer::new();
+        linked_list_kv_server.set_inline_threshold(16);
+        linked_list_kv_server
+            .insert("c".to_string(), buffer(b"abcde"))
+            .unwrap();
+        assert_eq!(
+            combined_storage_stats(&kv_server, &list_kv_server, &linked_list_kv_server),
+            StorageStats {
+                inline_values: 2,
+                inline_bytes: 8,
+                registered_values: 1,
+                registered_bytes: 4,
+            }
+        );
+    }
 }
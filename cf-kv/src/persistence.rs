@@ -0,0 +1,736 @@
+//! Optional durability for the kv stores: an append-only log of mutations plus periodic
+//! snapshots.
+//!
+//! A store with persistence enabled hands each mutation to its `DurableLog`, which copies it
+//! into a `LogRecord` and sends it to a writer thread, so the request path rarely waits on the
+//! disk: only when the writer falls so far behind that its bounded channel fills up. The writer
+//! appends records to `log-<epoch>` and flushes (and, if configured, fsyncs) after every batch
+//! it drains from the channel; a crash loses at most the records still queued.
+//!
+//! When persistence is enabled, the store's contents are written to the first snapshot before
+//! it serves any request. After that, every `snapshot_interval` a new epoch starts: mutations
+//! go to the new epoch's log, and the store copies its keys into `snapshot-<epoch>` a few at a
+//! time, alongside the mutations it logs. A key about to change is copied first, so the snapshot
+//! holds the store as it was when the epoch started, and replaying the epoch's log over it
+//! brings it up to date. Once a snapshot is complete and durable, the files of older epochs are
+//! deleted.
+//!
+//! Recovery loads the newest snapshot, then replays the logs of its epoch and later in order.
+//! Each store copies the recovered values into registered mempool buffers, as it does when it
+//! loads a trace, so they can be sent zero-copy.
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use color_eyre::eyre::{bail, Result, WrapErr};
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const LOG_PREFIX: &str = "log-";
+const TMP_SUFFIX: &str = ".tmp";
+/// Commands the writer may fall behind by before mutations block on it.
+const CHANNEL_CAPACITY: usize = 4096;
+/// Keys copied into an unfinished snapshot per mutation logged.
+const SNAPSHOT_KEYS_PER_MUTATION: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistenceConfig {
+    /// Directory holding the store's snapshots and logs.
+    pub dir: PathBuf,
+    /// How often to snapshot the store; never after the first snapshot if None, so the log
+    /// grows without bound.
+    pub snapshot_interval: Option<Duration>,
+    /// Whether to fsync the log after every batch, rather than only flush it to the OS.
+    pub sync: bool,
+}
+
+impl PersistenceConfig {
+    pub fn new(dir: PathBuf, snapshot_interval: Option<Duration>, sync: bool) -> Self {
+        PersistenceConfig {
+            dir,
+            snapshot_interval,
+            sync,
+        }
+    }
+
+    /// The same settings for a store kept in its own subdirectory.
+    pub fn for_store(&self, name: &str) -> Self {
+        PersistenceConfig {
+            dir: self.dir.join(name),
+            ..self.clone()
+        }
+    }
+}
+
+/// One mutation of a store, or one key of a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogRecord {
+    /// Sets the key's value; `ttl_us` is 0 if the put did not set a TTL.
+    Put {
+        key: String,
+        value: Vec<u8>,
+        ttl_us: u64,
+    },
+    PutList {
+        key: String,
+        values: Vec<Vec<u8>>,
+    },
+    Append {
+        key: String,
+        value: Vec<u8>,
+    },
+    Delete {
+        key: String,
+    },
+}
+
+impl LogRecord {
+    fn op(&self) -> u8 {
+        match self {
+            LogRecord::Put { .. } => 0,
+            LogRecord::PutList { .. } => 1,
+            LogRecord::Append { .. } => 2,
+            LogRecord::Delete { .. } => 3,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        match self {
+            LogRecord::Put { key, .. }
+            | LogRecord::PutList { key, .. }
+            | LogRecord::Append { key, .. }
+            | LogRecord::Delete { key } => key,
+        }
+    }
+
+    /// Writes the record as: op (u8), TTL in microseconds (u64), key length (u32), number of
+    /// values (u32), the key, then each value as its length (u32) and bytes. Integers are
+    /// little endian.
+    pub fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let (ttl_us, values): (u64, Vec<&[u8]>) = match self {
+            LogRecord::Put { value, ttl_us, .. } => (*ttl_us, vec![value.as_slice()]),
+            LogRecord::PutList { values, .. } => {
+                (0, values.iter().map(|value| value.as_slice()).collect())
+            }
+            LogRecord::Append { value, .. } => (0, vec![value.as_slice()]),
+            LogRecord::Delete { .. } => (0, vec![]),
+        };
+        writer.write_u8(self.op())?;
+        writer.write_u64::<LittleEndian>(ttl_us)?;
+        writer.write_u32::<LittleEndian>(self.key().len() as u32)?;
+        writer.write_u32::<LittleEndian>(values.len() as u32)?;
+        writer.write_all(self.key().as_bytes())?;
+        for value in values {
+            writer.write_u32::<LittleEndian>(value.len() as u32)?;
+            writer.write_all(value)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the next record. Returns None at the end of the file, and also for a record cut
+    /// short by a crash mid-write, which is the only way the tail of a log can be incomplete.
+    pub fn decode<R: Read>(reader: &mut R) -> Result<Option<Self>> {
+        let mut op = [0u8; 1];
+        if reader.read(&mut op)? == 0 {
+            return Ok(None);
+        }
+        match Self::decode_body(op[0], reader) {
+            Ok(record) => Ok(Some(record)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                tracing::warn!("Ignoring record cut short at the end of the log");
+                Ok(None)
+            }
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                bail!("Corrupt log record: {}", e);
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn decode_body<R: Read>(op: u8, reader: &mut R) -> io::Result<Self> {
+        let ttl_us = reader.read_u64::<LittleEndian>()?;
+        let key_len = reader.read_u32::<LittleEndian>()? as usize;
+        let num_values = reader.read_u32::<LittleEndian>()? as usize;
+        let mut key = vec![0u8; key_len];
+        reader.read_exact(&mut key)?;
+        let key = String::from_utf8(key)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "key is not utf-8"))?;
+        let mut values = Vec::with_capacity(num_values);
+        for _ in 0..num_values {
+            let len = reader.read_u32::<LittleEndian>()? as usize;
+            let mut value = vec![0u8; len];
+            reader.read_exact(&mut value)?;
+            values.push(value);
+        }
+        let record = match (op, values.len()) {
+            (0, 1) => LogRecord::Put {
+                key,
+                value: values.pop().unwrap(),
+                ttl_us,
+            },
+            (1, _) => LogRecord::PutList { key, values },
+            (2, 1) => LogRecord::Append {
+                key,
+                value: values.pop().unwrap(),
+            },
+            (3, 0) => LogRecord::Delete { key },
+            (op, num_values) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("op {} with {} values", op, num_values),
+                ));
+            }
+        };
+        Ok(record)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryStats {
+    pub snapshot_records: usize,
+    pub log_records: usize,
+}
+
+enum Command {
+    Append(LogRecord),
+    /// Log to `epoch` from now on, and start writing its snapshot.
+    StartSnapshot {
+        epoch: u64,
+    },
+    /// Records of keys copied into the unfinished snapshot.
+    SnapshotRecords(Vec<LogRecord>),
+    /// Every key is in the snapshot of `epoch`: make it durable and drop older epochs.
+    FinishSnapshot {
+        epoch: u64,
+    },
+}
+
+/// A store's handle on its log. Dropping it flushes the records still queued and stops the
+/// writer thread.
+pub struct DurableLog {
+    config: PersistenceConfig,
+    epoch: u64,
+    last_snapshot: Instant,
+    /// Keys the unfinished snapshot has yet to copy; None once it is finished.
+    unsnapshotted: Option<HashSet<String>>,
+    sender: Option<SyncSender<Command>>,
+    writer: Option<JoinHandle<Result<()>>>,
+    /// Set once the writer has stopped, so the failure is only reported once.
+    failed: bool,
+}
+
+impl DurableLog {
+    /// Starts logging to the directory, first writing `records` (the store's current contents)
+    /// as the snapshot of a new epoch. The snapshot is written before this returns, which the
+    /// store does before it serves requests. Epochs continue from the files already there.
+    pub fn open(
+        config: PersistenceConfig,
+        records: impl Iterator<Item = LogRecord>,
+    ) -> Result<Self> {
+        fs::create_dir_all(&config.dir).wrap_err(format!("Could not create {:?}", config.dir))?;
+        let epoch = latest_epoch(&config.dir)?
+            .map(|epoch| epoch + 1)
+            .unwrap_or(0);
+        tracing::info!(dir =? config.dir, epoch, "Opening durable log");
+        let mut snapshot = SnapshotWriter::create(&config.dir, epoch)?;
+        for record in records {
+            snapshot.write(&record)?;
+        }
+        snapshot.finish()?;
+
+        let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+        let dir = config.dir.clone();
+        let sync = config.sync;
+        let writer = std::thread::spawn(move || run_writer(dir, sync, epoch, receiver));
+        Ok(DurableLog {
+            config,
+            epoch,
+            last_snapshot: Instant::now(),
+            unsnapshotted: None,
+            sender: Some(sender),
+            writer: Some(writer),
+            failed: false,
+        })
+    }
+
+    pub fn append(&mut self, record: LogRecord) {
+        self.send(Command::Append(record));
+    }
+
+    /// Whether the store should start a new snapshot; never while one is unfinished.
+    pub fn snapshot_due(&self) -> bool {
+        match self.config.snapshot_interval {
+            Some(interval) => {
+                self.unsnapshotted.is_none() && self.last_snapshot.elapsed() >= interval
+            }
+            None => false,
+        }
+    }
+
+    /// Starts a new epoch, whose snapshot must hold `keys` (the store's keys now). The store
+    /// copies them in with `snapshot_records` as it goes.
+    pub fn start_snapshot(&mut self, keys: impl Iterator<Item = String>) {
+        self.epoch += 1;
+        self.last_snapshot = Instant::now();
+        let epoch = self.epoch;
+        self.send(Command::StartSnapshot { epoch });
+        let keys: HashSet<String> = keys.collect();
+        match keys.is_empty() {
+            true => self.send(Command::FinishSnapshot { epoch }),
+            false => self.unsnapshotted = Some(keys),
+        }
+    }
+
+    /// Keys the store should copy into the unfinished snapshot before it logs a mutation of
+    /// `key`: `key` itself if the snapshot has yet to copy it, so the snapshot sees it as it was
+    /// when the epoch started, plus the next few of the rest. Each key is handed out once.
+    pub fn keys_to_snapshot(&mut self, key: &str) -> Vec<String> {
+        let unsnapshotted = match &mut self.unsnapshotted {
+            Some(unsnapshotted) => unsnapshotted,
+            None => return Vec::default(),
+        };
+        let mut keys: Vec<String> = Vec::with_capacity(SNAPSHOT_KEYS_PER_MUTATION + 1);
+        if let Some(key) = unsnapshotted.take(key) {
+            keys.push(key);
+        }
+        keys.extend(
+            unsnapshotted
+                .iter()
+                .take(SNAPSHOT_KEYS_PER_MUTATION)
+                .cloned(),
+        );
+        for key in keys.iter() {
+            unsnapshotted.remove(key);
+        }
+        keys
+    }
+
+    /// Adds the records of keys from `keys_to_snapshot` to the unfinished snapshot, finishing
+    /// it once every key is in. Keys that have since been deleted or expired have no record.
+    pub fn snapshot_records(&mut self, records: Vec<LogRecord>) {
+        if !records.is_empty() {
+            self.send(Command::SnapshotRecords(records));
+        }
+        if let Some(unsnapshotted) = &self.unsnapshotted {
+            if unsnapshotted.is_empty() {
+                self.unsnapshotted = None;
+                let epoch = self.epoch;
+                self.send(Command::FinishSnapshot { epoch });
+            }
+        }
+    }
+
+    fn send(&mut self, command: Command) {
+        let sent = match &self.sender {
+            Some(sender) => sender.send(command).is_ok(),
+            None => false,
+        };
+        if !sent && !self.failed {
+            // the writer only hangs up when it fails; the join in drop reports why
+            tracing::error!(dir =? self.config.dir, "Durable log writer stopped; mutations are no longer persisted");
+            self.failed = true;
+        }
+    }
+}
+
+impl Drop for DurableLog {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            match writer.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    tracing::error!(dir =? self.config.dir, "Durable log writer failed: {:?}", e);
+                }
+                Err(e) => {
+                    tracing::error!(dir =? self.config.dir, "Durable log writer panicked: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Logs to `epoch`, whose snapshot has already been written, until told to start another.
+fn run_writer(dir: PathBuf, sync: bool, epoch: u64, receiver: Receiver<Command>) -> Result<()> {
+    let mut log = open_log(&dir, epoch)?;
+    let mut snapshot: Option<SnapshotWriter> = None;
+    while let Ok(command) = receiver.recv() {
+        let mut next = Some(command);
+        // drain whatever is queued before flushing, so bursts share one write and sync
+        while let Some(command) = next {
+            match command {
+                Command::Append(record) => record.encode(&mut log)?,
+                Command::StartSnapshot { epoch } => {
+                    finish_log(&mut log, sync)?;
+                    log = open_log(&dir, epoch)?;
+                    snapshot = Some(SnapshotWriter::create(&dir, epoch)?);
+                }
+                Command::SnapshotRecords(records) => match &mut snapshot {
+                    Some(snapshot) => {
+                        for record in records.iter() {
+                            snapshot.write(record)?;
+                        }
+                    }
+                    None => bail!("Snapshot records sent before the snapshot started"),
+                },
+                Command::FinishSnapshot { epoch } => match snapshot.take() {
+                    Some(snapshot) if snapshot.epoch == epoch => snapshot.finish()?,
+                    _ => bail!("Finished snapshot {} before starting it", epoch),
+                },
+            }
+            next = receiver.try_recv().ok();
+        }
+        finish_log(&mut log, sync)?;
+    }
+    Ok(())
+}
+
+fn open_log(dir: &Path, epoch: u64) -> Result<BufWriter<File>> {
+    Ok(BufWriter::new(
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(format!("{}{}", LOG_PREFIX, epoch)))?,
+    ))
+}
+
+fn finish_log(log: &mut BufWriter<File>, sync: bool) -> Result<()> {
+    log.flush()?;
+    if sync {
+        log.get_ref().sync_data()?;
+    }
+    Ok(())
+}
+
+/// A snapshot being written under a temporary name; it is renamed once complete and synced, so
+/// a snapshot file is never partially written.
+struct SnapshotWriter {
+    dir: PathBuf,
+    epoch: u64,
+    writer: BufWriter<File>,
+    num_records: usize,
+}
+
+impl SnapshotWriter {
+    fn create(dir: &Path, epoch: u64) -> Result<Self> {
+        Ok(SnapshotWriter {
+            dir: dir.to_path_buf(),
+            epoch,
+            writer: BufWriter::new(File::create(snapshot_tmp_path(dir, epoch))?),
+            num_records: 0,
+        })
+    }
+
+    fn write(&mut self, record: &LogRecord) -> Result<()> {
+        record.encode(&mut self.writer)?;
+        self.num_records += 1;
+        Ok(())
+    }
+
+    /// Makes the snapshot durable, then deletes the files of older epochs, which it covers.
+    fn finish(mut self) -> Result<()> {
+        let path = self.dir.join(format!("{}{}", SNAPSHOT_PREFIX, self.epoch));
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        fs::rename(snapshot_tmp_path(&self.dir, self.epoch), &path)?;
+        File::open(&self.dir)?.sync_all()?;
+        tracing::debug!(snapshot =? path, records = self.num_records, "Wrote snapshot");
+        remove_before(&self.dir, self.epoch)
+    }
+}
+
+fn snapshot_tmp_path(dir: &Path, epoch: u64) -> PathBuf {
+    dir.join(format!("{}{}{}", SNAPSHOT_PREFIX, epoch, TMP_SUFFIX))
+}
+
+/// Deletes the snapshots (finished or not) and logs of epochs before `epoch`, which its
+/// snapshot covers.
+fn remove_before(dir: &Path, epoch: u64) -> Result<()> {
+    let (snapshot_epochs, log_epochs) = list_epochs(dir)?;
+    for old_epoch in snapshot_epochs.into_iter().filter(|e| *e < epoch) {
+        fs::remove_file(dir.join(format!("{}{}", SNAPSHOT_PREFIX, old_epoch)))?;
+    }
+    for old_epoch in log_epochs.into_iter().filter(|e| *e < epoch) {
+        fs::remove_file(dir.join(format!("{}{}", LOG_PREFIX, old_epoch)))?;
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let old_tmp = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(SNAPSHOT_PREFIX))
+            .and_then(|name| name.strip_suffix(TMP_SUFFIX))
+            .and_then(|old_epoch| old_epoch.parse::<u64>().ok())
+            .map(|old_epoch| old_epoch < epoch)
+            .unwrap_or(false);
+        if old_tmp {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Epochs of the snapshots and of the logs in the directory, each sorted. Leftover temporary
+/// snapshots are ignored.
+fn list_epochs(dir: &Path) -> Result<(Vec<u64>, Vec<u64>)> {
+    let mut snapshot_epochs = Vec::default();
+    let mut log_epochs = Vec::default();
+    if !dir.exists() {
+        return Ok((snapshot_epochs, log_epochs));
+    }
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        if let Some(Ok(epoch)) = name.strip_prefix(SNAPSHOT_PREFIX).map(str::parse::<u64>) {
+            snapshot_epochs.push(epoch);
+        } else if let Some(Ok(epoch)) = name.strip_prefix(LOG_PREFIX).map(str::parse::<u64>) {
+            log_epochs.push(epoch);
+        }
+    }
+    snapshot_epochs.sort_unstable();
+    log_epochs.sort_unstable();
+    Ok((snapshot_epochs, log_epochs))
+}
+
+fn latest_epoch(dir: &Path) -> Result<Option<u64>> {
+    let (snapshot_epochs, log_epochs) = list_epochs(dir)?;
+    Ok(std::cmp::max(
+        snapshot_epochs.last().copied(),
+        log_epochs.last().copied(),
+    ))
+}
+
+/// Whether the directory holds a snapshot or log to recover from.
+pub fn has_state(dir: &Path) -> Result<bool> {
+    Ok(latest_epoch(dir)?.is_some())
+}
+
+/// Feeds `apply` the newest snapshot's records, then those of the logs written since, in the
+/// order they were logged.
+pub fn replay(dir: &Path, mut apply: impl FnMut(LogRecord) -> Result<()>) -> Result<RecoveryStats> {
+    let (snapshot_epochs, log_epochs) = list_epochs(dir)?;
+    let mut stats = RecoveryStats::default();
+    let start_epoch = match snapshot_epochs.last() {
+        Some(epoch) => {
+            let path = dir.join(format!("{}{}", SNAPSHOT_PREFIX, epoch));
+            stats.snapshot_records = replay_file(&path, &mut apply)?;
+            *epoch
+        }
+        None => 0,
+    };
+    for epoch in log_epochs.into_iter().filter(|e| *e >= start_epoch) {
+        let path = dir.join(format!("{}{}", LOG_PREFIX, epoch));
+        stats.log_records += replay_file(&path, &mut apply)?;
+    }
+    tracing::info!(dir =? dir, stats =? stats, "Replayed durable state");
+    Ok(stats)
+}
+
+fn replay_file(path: &Path, apply: &mut impl FnMut(LogRecord) -> Result<()>) -> Result<usize> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut num_records = 0;
+    while let Some(record) =
+        LogRecord::decode(&mut reader).wrap_err(format!("Could not read {:?}", path))?
+    {
+        apply(record)?;
+        num_records += 1;
+    }
+    Ok(num_records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("cf-kv-persistence-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn put(key: &str, value: &str) -> LogRecord {
+        LogRecord::Put {
+            key: key.to_string(),
+            value: value.as_bytes().to_vec(),
+            ttl_us: 0,
+        }
+    }
+
+    fn replay_all(dir: &Path) -> (Vec<LogRecord>, RecoveryStats) {
+        let mut records = Vec::default();
+        let stats = replay(dir, |record| {
+            records.push(record);
+            Ok(())
+        })
+        .unwrap();
+        (records, stats)
+    }
+
+    fn encode_all(records: &[LogRecord]) -> Vec<u8> {
+        let mut buf = Vec::default();
+        for record in records.iter() {
+            record.encode(&mut buf).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let records = vec![
+            LogRecord::Put {
+                key: "a".to_string(),
+                value: b"value".to_vec(),
+                ttl_us: 1500,
+            },
+            LogRecord::PutList {
+                key: "b".to_string(),
+                values: vec![b"x".to_vec(), Vec::default(), b"yz".to_vec()],
+            },
+            LogRecord::Append {
+                key: "b".to_string(),
+                value: b"w".to_vec(),
+            },
+            LogRecord::Delete {
+                key: "a".to_string(),
+            },
+        ];
+        let buf = encode_all(&records);
+        let mut reader = buf.as_slice();
+        for record in records.iter() {
+            assert_eq!(
+                LogRecord::decode(&mut reader).unwrap().as_ref(),
+                Some(record)
+            );
+        }
+        assert_eq!(LogRecord::decode(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn torn_tails_are_dropped() {
+        let records = vec![put("a", "1"), put("b", "22")];
+        let first_len = encode_all(&records[..1]).len();
+        let buf = encode_all(&records);
+        // a crash may cut the last record anywhere, including right after its op
+        for len in (first_len + 1)..buf.len() {
+            let mut reader = &buf[..len];
+            assert_eq!(
+                LogRecord::decode(&mut reader).unwrap().as_ref(),
+                Some(&records[0])
+            );
+            assert_eq!(LogRecord::decode(&mut reader).unwrap(), None);
+        }
+        // a complete record that makes no sense is corruption, not a torn tail
+        let mut corrupt = buf.clone();
+        corrupt[0] = 7;
+        assert!(LogRecord::decode(&mut corrupt.as_slice()).is_err());
+    }
+
+    #[test]
+    fn replay_applies_the_snapshot_then_the_logs_in_order() {
+        let dir = test_dir("replay");
+        let config = PersistenceConfig::new(dir.clone(), None, false);
+        {
+            let mut log =
+                DurableLog::open(config.clone(), vec![put("a", "1")].into_iter()).unwrap();
+            log.append(put("b", "2"));
+            log.append(LogRecord::Delete {
+                key: "a".to_string(),
+            });
+        }
+        // reopening starts a new epoch from a snapshot of whatever the store recovered
+        {
+            let mut log = DurableLog::open(config, vec![put("b", "2")].into_iter()).unwrap();
+            log.append(put("b", "3"));
+            log.append(put("c", "4"));
+        }
+        let (records, stats) = replay_all(&dir);
+        assert_eq!(records, vec![put("b", "2"), put("b", "3"), put("c", "4")]);
+        assert_eq!(
+            stats,
+            RecoveryStats {
+                snapshot_records: 1,
+                log_records: 2
+            }
+        );
+        assert_eq!(list_epochs(&dir).unwrap(), (vec![1], vec![1]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unfinished_snapshots_fall_back_to_the_previous_epoch() {
+        let dir = test_dir("unfinished");
+        let config = PersistenceConfig::new(dir.clone(), Some(Duration::ZERO), false);
+        {
+            let mut log = DurableLog::open(config, vec![put("a", "1")].into_iter()).unwrap();
+            log.append(put("b", "2"));
+            assert!(log.snapshot_due());
+            log.start_snapshot(vec!["a".to_string(), "b".to_string()].into_iter());
+            assert!(!log.snapshot_due());
+            log.append(put("c", "3"));
+        }
+        let (records, _) = replay_all(&dir);
+        assert_eq!(records, vec![put("a", "1"), put("b", "2"), put("c", "3")]);
+        assert_eq!(list_epochs(&dir).unwrap(), (vec![0], vec![0, 1]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn finished_snapshots_replace_older_epochs() {
+        let dir = test_dir("finished");
+        let config = PersistenceConfig::new(dir.clone(), Some(Duration::ZERO), false);
+        {
+            let mut log = DurableLog::open(config, vec![put("a", "1")].into_iter()).unwrap();
+            log.append(put("b", "2"));
+            log.start_snapshot(vec!["a".to_string(), "b".to_string()].into_iter());
+            // the store copies "b" before logging the put that changes it
+            let keys = log.keys_to_snapshot("b");
+            assert_eq!(keys.len(), 2);
+            assert_eq!(keys[0], "b");
+            log.snapshot_records(vec![put("b", "2"), put("a", "1")]);
+            log.append(put("b", "3"));
+            assert!(log.snapshot_due());
+        }
+        let (records, stats) = replay_all(&dir);
+        assert_eq!(records, vec![put("b", "2"), put("a", "1"), put("b", "3")]);
+        assert_eq!(stats.snapshot_records, 2);
+        assert_eq!(list_epochs(&dir).unwrap(), (vec![1], vec![1]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots_hand_out_each_key_once() {
+        let dir = test_dir("keys");
+        let config = PersistenceConfig::new(dir.clone(), Some(Duration::ZERO), false);
+        {
+            let mut log = DurableLog::open(config, std::iter::empty()).unwrap();
+            let num_keys = 2 * SNAPSHOT_KEYS_PER_MUTATION + 10;
+            log.start_snapshot((0..num_keys).map(|i| format!("key{}", i)));
+            let mut handed_out = HashSet::new();
+            let first = log.keys_to_snapshot("key7");
+            assert_eq!(first[0], "key7");
+            assert_eq!(first.len(), SNAPSHOT_KEYS_PER_MUTATION + 1);
+            handed_out.extend(first);
+            log.snapshot_records(Vec::default());
+            while !log.snapshot_due() {
+                let keys = log.keys_to_snapshot("key7");
+                assert!(!keys.is_empty());
+                for key in keys {
+                    assert!(handed_out.insert(key));
+                }
+                log.snapshot_records(Vec::default());
+            }
+            assert_eq!(handed_out.len(), num_keys);
+            assert!(log.keys_to_snapshot("key7").is_empty());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
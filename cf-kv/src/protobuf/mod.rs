@@ -10,8 +10,10 @@ pub mod kv_messages {
 }
 
 use super::{
-    ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType, ServerLoadGenerator,
-    REQ_TYPE_SIZE,
+    combined_cache_stats, combined_storage_stats, delete_from_stores, failed_request_status,
+    replication::Replicator, scan_end_key, shard::ShardRouter, signed_delta, ttl_from_micros,
+    ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType, ResponseStatus,
+    ServerLoadGenerator, REQ_TYPE_SIZE,
 };
 use color_eyre::eyre::{bail, ensure, Result, WrapErr};
 use cornflakes_libos::{
@@ -61,28 +63,22 @@ where
             //#[cfg(feature = "profiler")]
             //demikernel::timer!("Get value from map");
             match self.use_linked_list {
-                true => match linked_list_kv_server.get(&get_request.key) {
-                    Some(v) => v.as_ref().as_ref(),
-                    None => {
-                        bail!(
-                            "Cannot find value for key in KV store: {:?}",
-                            get_request.key
-                        );
-                    }
-                },
-                false => match kv_server.get(&get_request.key) {
-                    Some(v) => v.as_ref(),
-                    None => {
-                        bail!(
-                            "Cannot find value for key in KV store: {:?}",
-                            get_request.key
-                        );
-                    }
-                },
+                true => linked_list_kv_server
+                    .get(&get_request.key)
+                    .map(|v| v.as_ref().as_ref()),
+                false => kv_server.get(&get_request.key).map(|v| v.as_ref()),
             }
         };
         let mut get_resp = kv_messages::GetResp::new();
         get_resp.id = get_request.id;
+        let value = match value {
+            Some(v) => v,
+            None => {
+                tracing::debug!(key =? get_request.key, "Cannot find value for key in KV store");
+                get_resp.status = ResponseStatus::NotFound.as_u32();
+                return Ok(get_resp);
+            }
+        };
         tracing::debug!(
             "found value {:?} with length {}",
             value.as_ref().as_ptr(),
@@ -113,23 +109,20 @@ where
         let value = {
             #[cfg(feature = "profiler")]
             demikernel::timer!("Retrieve value");
-            match list_kv_server.get(&get_request.key) {
-                Some(list) => match list.get(get_request.idx as usize) {
-                    Some(v) => v,
-                    None => {
-                        bail!(
-                            "Could not find value index {} for key {} in KVStore",
-                            get_request.idx,
-                            get_request.key
-                        );
-                    }
-                },
-                None => {
-                    bail!(
-                        "Cannot find value for key in KV store: {:?}",
-                        get_request.key,
-                    );
-                }
+            list_kv_server
+                .get(&get_request.key)
+                .and_then(|list| list.get(get_request.idx as usize))
+        };
+        let value = match value {
+            Some(v) => v,
+            None => {
+                tracing::debug!(
+                    key =? get_request.key,
+                    idx = get_request.idx,
+                    "Could not find value index for key in KVStore"
+                );
+                get_resp.status = ResponseStatus::NotFound.as_u32();
+                return Ok(get_resp);
             }
         };
         {
@@ -150,9 +143,10 @@ where
         let put_request =
             kv_messages::PutReq::parse_from_bytes(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])
                 .wrap_err("Failed to deserialize proto PutReq")?;
-        kv_server.insert_with_copies(
+        kv_server.insert_with_copies_and_ttl(
             &put_request.key.as_str(),
             &put_request.val.as_slice(),
+            ttl_from_micros(put_request.ttl_us),
             datapath,
             mempool_ids,
         )?;
@@ -174,22 +168,24 @@ where
                 .wrap_err("Failed to deserialize proto GetMReq")?
         };
         let mut vals: Vec<Vec<u8>> = Vec::with_capacity(getm_request.keys.len());
+        let mut status = ResponseStatus::Ok;
         for key in getm_request.keys.iter() {
             #[cfg(feature = "profiler")]
             demikernel::timer!("Get value");
             let value = match self.use_linked_list {
-                true => match linked_list_kv_server.get(&key.as_str()) {
-                    Some(v) => v.as_ref().as_ref(),
-                    None => {
-                        bail!("Cannot find value for key in KV store: {:?}", &key.as_str());
-                    }
-                },
-                false => match kv_server.get(&key.as_str()) {
-                    Some(v) => v.as_ref(),
-                    None => {
-                        bail!("Cannot find value for key in KV store: {:?}", &key.as_str(),);
-                    }
-                },
+                true => linked_list_kv_server
+                    .get(&key.as_str())
+                    .map(|v| v.as_ref().as_ref()),
+                false => kv_server.get(&key.as_str()).map(|v| v.as_ref()),
+            };
+            let value = match value {
+                Some(v) => v,
+                None => {
+                    tracing::debug!(key = key.as_str(), "Cannot find value for key in KV store");
+                    status = ResponseStatus::NotFound;
+                    vals.push(Vec::default());
+                    continue;
+                }
             };
             {
                 #[cfg(feature = "profiler")]
@@ -200,6 +196,7 @@ where
         let mut getm_resp = kv_messages::GetMResp::new();
         getm_resp.id = getm_request.id;
         getm_resp.vals = vals;
+        getm_resp.status = status.as_u32();
         Ok(getm_resp)
     }
 
@@ -242,6 +239,16 @@ where
                     demikernel::timer!("do get on key");
                     linked_list_kv_server.get(&getlist_request.key.as_str())
                 };
+                if node_option.is_none() {
+                    tracing::debug!(
+                        key =? getlist_request.key,
+                        "Cannot find values for key in KV store"
+                    );
+                    let mut getlist_resp = kv_messages::GetListResp::new();
+                    getlist_resp.id = getlist_request.id;
+                    getlist_resp.status = ResponseStatus::NotFound.as_u32();
+                    return Ok(getlist_resp);
+                }
 
                 // todo: again, why is range_end being parsed buggy?
                 let range_len = {
@@ -290,10 +297,14 @@ where
                 let values = match list_kv_server.get(&getlist_request.key) {
                     Some(v) => v,
                     None => {
-                        bail!(
-                            "Cannot find values for key in KV store: {:?}",
-                            getlist_request.key
+                        tracing::debug!(
+                            key =? getlist_request.key,
+                            "Cannot find values for key in KV store"
                         );
+                        let mut getlist_resp = kv_messages::GetListResp::new();
+                        getlist_resp.id = getlist_request.id;
+                        getlist_resp.status = ResponseStatus::NotFound.as_u32();
+                        return Ok(getlist_resp);
                     }
                 };
                 let mut values_list: Vec<Vec<u8>> = Vec::with_capacity(values.len());
@@ -330,6 +341,159 @@ where
         put_resp.id = putlist_request.id;
         Ok(put_resp)
     }
+
+    fn handle_delete(
+        &self,
+        kv_server: &mut KVServer<D>,
+        list_kv_server: &mut ListKVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        pkt: &ReceivedPkt<D>,
+    ) -> Result<kv_messages::PutResp> {
+        let delete_request =
+            kv_messages::DeleteReq::parse_from_bytes(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])
+                .wrap_err("Failed to deserialize proto DeleteReq")?;
+        let mut put_resp = kv_messages::PutResp::new();
+        put_resp.id = delete_request.id;
+        if !delete_from_stores(
+            delete_request.key.as_str(),
+            kv_server,
+            list_kv_server,
+            linked_list_kv_server,
+        ) {
+            tracing::debug!(key =? delete_request.key, "Cannot find key to delete in KV store");
+            put_resp.status = ResponseStatus::NotFound.as_u32();
+        }
+        Ok(put_resp)
+    }
+
+    fn handle_deletem(
+        &self,
+        kv_server: &mut KVServer<D>,
+        list_kv_server: &mut ListKVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        pkt: &ReceivedPkt<D>,
+    ) -> Result<kv_messages::PutResp> {
+        let deletem_request =
+            kv_messages::DeleteMReq::parse_from_bytes(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])
+                .wrap_err("Failed to deserialize proto DeleteMReq")?;
+        let mut status = ResponseStatus::Ok;
+        for key in deletem_request.keys.iter() {
+            if !delete_from_stores(
+                key.as_str(),
+                kv_server,
+                list_kv_server,
+                linked_list_kv_server,
+            ) {
+                tracing::debug!(key = key.as_str(), "Cannot find key to delete in KV store");
+                status = ResponseStatus::NotFound;
+            }
+        }
+        let mut put_resp = kv_messages::PutResp::new();
+        put_resp.id = deletem_request.id;
+        put_resp.status = status.as_u32();
+        Ok(put_resp)
+    }
+
+    fn handle_cas(
+        &self,
+        kv_server: &mut KVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        mempool_ids: &mut Vec<MempoolID>,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+    ) -> Result<kv_messages::CASResp> {
+        let cas_request =
+            kv_messages::CASReq::parse_from_bytes(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])
+                .wrap_err("Failed to deserialize proto CASReq")?;
+        let (status, version) = match self.use_linked_list {
+            true => linked_list_kv_server.compare_and_swap(
+                cas_request.key.as_str(),
+                cas_request.version,
+                cas_request.val.as_slice(),
+                datapath,
+                mempool_ids,
+            )?,
+            false => kv_server.compare_and_swap(
+                cas_request.key.as_str(),
+                cas_request.version,
+                cas_request.val.as_slice(),
+                datapath,
+                mempool_ids,
+            )?,
+        };
+        let mut cas_resp = kv_messages::CASResp::new();
+        cas_resp.id = cas_request.id;
+        cas_resp.version = version;
+        cas_resp.status = status.as_u32();
+        Ok(cas_resp)
+    }
+
+    fn handle_scan(
+        &self,
+        kv_server: &KVServer<D>,
+        pkt: &ReceivedPkt<D>,
+    ) -> Result<kv_messages::GetMResp> {
+        let scan_request =
+            kv_messages::ScanReq::parse_from_bytes(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])
+                .wrap_err("Failed to deserialize proto ScanReq")?;
+        let mut getm_resp = kv_messages::GetMResp::new();
+        getm_resp.id = scan_request.id;
+        let values = match self.use_linked_list {
+            true => None,
+            false => kv_server.scan(
+                scan_request.start_key.as_str(),
+                scan_end_key(scan_request.end_key.as_str()),
+                scan_request.count as usize,
+            ),
+        };
+        match values {
+            Some(values) => {
+                getm_resp.vals = values.iter().map(|v| v.as_ref().to_vec()).collect();
+            }
+            None => {
+                tracing::debug!("Received scan, but the kv store keeps no ordered index");
+                getm_resp.status = ResponseStatus::BadRequest.as_u32();
+            }
+        }
+        Ok(getm_resp)
+    }
+
+    fn handle_incr(
+        &self,
+        kv_server: &mut KVServer<D>,
+        linked_list_kv_server: &mut LinkedListKVServer<D>,
+        mempool_ids: &mut Vec<MempoolID>,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        negate: bool,
+    ) -> Result<kv_messages::IncrResp> {
+        let incr_request =
+            kv_messages::IncrReq::parse_from_bytes(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])
+                .wrap_err("Failed to deserialize proto IncrReq")?;
+        let mut incr_resp = kv_messages::IncrResp::new();
+        incr_resp.id = incr_request.id;
+        let delta = match signed_delta(incr_request.delta, negate) {
+            Some(delta) => delta,
+            None => {
+                incr_resp.status = ResponseStatus::BadRequest.as_u32();
+                return Ok(incr_resp);
+            }
+        };
+        let (status, value) = match self.use_linked_list {
+            true => linked_list_kv_server.increment(
+                incr_request.key.as_str(),
+                delta,
+                datapath,
+                mempool_ids,
+            )?,
+            false => {
+                kv_server.increment(incr_request.key.as_str(), delta, datapath, mempool_ids)?
+            }
+        };
+        incr_resp.val = value;
+        incr_resp.status = status.as_u32();
+        Ok(incr_resp)
+    }
 }
 
 pub struct ProtobufKVServer<D>
@@ -342,6 +506,8 @@ where
     mempool_ids: Vec<MempoolID>,
     serializer: ProtobufSerializer<D>,
     push_buf_type: PushBufType,
+    shard_router: Option<ShardRouter>,
+    replicator: Option<Replicator<D>>,
 }
 
 impl<D> ProtobufKVServer<D>
@@ -367,8 +533,239 @@ where
             mempool_ids: mempool_ids,
             push_buf_type: push_buf_type,
             serializer: ProtobufSerializer::new(use_linked_list),
+            shard_router: None,
+            replicator: None,
         })
     }
+
+    /// Makes this server one shard of a multi-core server.
+    pub fn set_shard_router(&mut self, router: ShardRouter) {
+        self.shard_router = Some(router);
+    }
+
+    /// Makes this server a replica, following the given replication config.
+    pub fn set_replicator(&mut self, replicator: Replicator<D>) {
+        self.replicator = Some(replicator);
+    }
+
+    /// Handles one request and queues its response.
+    fn process_request_single_buf(
+        &mut self,
+        message_type: MsgType,
+        pkt: &ReceivedPkt<D>,
+        datapath: &mut D,
+        end_batch: bool,
+    ) -> Result<()> {
+        match message_type {
+            MsgType::Get => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle get and queue protobuf");
+                let response = self.serializer.handle_get(
+                    &self.kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                )?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::GetFromList => {
+                let response = self
+                    .serializer
+                    .handle_get_from_list(&self.list_kv_server, pkt)?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::GetM(_size) => {
+                let response = self.serializer.handle_getm(
+                    &self.kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                )?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::GetList(_size) => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle getlist and queue protobuf");
+                let response = self.serializer.handle_getlist(
+                    &self.list_kv_server,
+                    &self.linked_list_kv_server,
+                    pkt,
+                )?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::Put => {
+                #[cfg(feature = "profiler")]
+                demikernel::timer!("handle put protobuf and queue protobuf");
+                let response = self.serializer.handle_put(
+                    &mut self.kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                )?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::PutM(_size) => {
+                let response = self.serializer.handle_putm(
+                    &mut self.kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                )?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::PutList(_size) => {
+                let response = self.serializer.handle_putlist(
+                    &mut self.list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                )?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::AddUser => {
+                let request =
+                    kv_messages::AddUser::parse_from_bytes(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])
+                        .wrap_err("Failed to deserialize proto PutMReq")?;
+                let mut response = kv_messages::AddUserResponse::new();
+                let old_value = self.kv_server.get(&request.keys[0].as_str()).unwrap();
+                response.first_val = old_value.as_ref().to_vec();
+                for (key, value) in request.keys.iter().zip(request.vals.iter()) {
+                    self.kv_server.insert_with_copies(
+                        key.as_str(),
+                        value.as_slice(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                }
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::FollowUnfollow => {
+                let request = kv_messages::FollowUnfollow::parse_from_bytes(
+                    &pkt.seg(0).as_ref()[REQ_TYPE_SIZE..],
+                )
+                .wrap_err("Failed to deserialize proto PutMReq")?;
+                let mut response = kv_messages::FollowUnfollowResponse::new();
+                let mut list: Vec<Vec<u8>> = Vec::with_capacity(2);
+                for (key, value) in request.keys.iter().zip(request.vals.iter()) {
+                    let old_value = self.kv_server.remove(&key.as_str()).unwrap();
+                    self.kv_server.insert_with_copies(
+                        key.as_str(),
+                        value.as_slice(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                    list.push(old_value.as_ref().to_vec());
+                }
+                response.original_vals = list;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::PostTweet => {
+                let request =
+                    kv_messages::PostTweet::parse_from_bytes(&pkt.seg(0).as_ref()[REQ_TYPE_SIZE..])
+                        .wrap_err("Failed to deserialize proto PutMReq")?;
+                let mut response = kv_messages::PostTweetResponse::new();
+                let mut list: Vec<Vec<u8>> = Vec::with_capacity(2);
+                for (key, value) in request.keys.iter().zip(request.vals.iter()).take(3) {
+                    let old_value = self.kv_server.remove(&key.as_str()).unwrap();
+                    self.kv_server.insert_with_copies(
+                        key.as_str(),
+                        value.as_slice(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                    list.push(old_value.as_ref().to_vec());
+                }
+                response.vals = list;
+                for (key, value) in request.keys.iter().zip(request.vals.iter()).skip(3).take(2) {
+                    self.kv_server.insert_with_copies(
+                        key.as_str(),
+                        value.as_slice(),
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                }
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::GetTimeline(_) => {
+                let request = kv_messages::GetTimeline::parse_from_bytes(
+                    &pkt.seg(0).as_ref()[REQ_TYPE_SIZE..],
+                )
+                .wrap_err("Failed to deserialize proto PutMReq")?;
+                let mut response = kv_messages::GetTimelineResponse::new();
+                let mut list: Vec<Vec<u8>> = Vec::with_capacity(request.keys.len());
+                for key in request.keys.iter() {
+                    let old_value = self.kv_server.get(&key.as_str()).unwrap();
+                    list.push(old_value.as_ref().to_vec());
+                }
+                response.vals = list;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::Delete => {
+                let response = self.serializer.handle_delete(
+                    &mut self.kv_server,
+                    &mut self.list_kv_server,
+                    &mut self.linked_list_kv_server,
+                    pkt,
+                )?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::DeleteM(_size) => {
+                let response = self.serializer.handle_deletem(
+                    &mut self.kv_server,
+                    &mut self.list_kv_server,
+                    &mut self.linked_list_kv_server,
+                    pkt,
+                )?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::CompareAndSwap => {
+                let response = self.serializer.handle_cas(
+                    &mut self.kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                )?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::Increment | MsgType::Decrement => {
+                let response = self.serializer.handle_incr(
+                    &mut self.kv_server,
+                    &mut self.linked_list_kv_server,
+                    &mut self.mempool_ids,
+                    pkt,
+                    datapath,
+                    message_type == MsgType::Decrement,
+                )?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::Scan => {
+                let response = self.serializer.handle_scan(&self.kv_server, pkt)?;
+                datapath
+                    .queue_protobuf_message((pkt.msg_id(), pkt.conn_id(), &response), end_batch)?;
+            }
+            MsgType::AppendToList(_size) => {
+                bail!(
+                    "Protobuf server does not support {:?} requests",
+                    message_type
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<D> ServerSM for ProtobufKVServer<D>
@@ -381,6 +778,46 @@ where
         self.push_buf_type
     }
 
+    fn steer_packets(
+        &mut self,
+        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        datapath: &mut Self::Datapath,
+    ) -> Result<Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>> {
+        let pkts = match &mut self.shard_router {
+            Some(router) => router.steer(pkts, datapath)?,
+            None => pkts,
+        };
+        match &mut self.replicator {
+            Some(replicator) => replicator.steer(pkts, datapath),
+            None => Ok(pkts),
+        }
+    }
+
+    fn log_stats(&self, datapath: &Self::Datapath) {
+        datapath.transmit_stats().dump("Server transmit stats");
+        datapath
+            .allocator_memory_stats()
+            .dump("Server allocator memory stats");
+        combined_cache_stats(
+            &self.kv_server,
+            &self.list_kv_server,
+            &self.linked_list_kv_server,
+        )
+        .dump("Server cache stats");
+        combined_storage_stats(
+            &self.kv_server,
+            &self.list_kv_server,
+            &self.linked_list_kv_server,
+        )
+        .dump("Server storage stats");
+        if let Some(router) = &self.shard_router {
+            router.stats().dump("Server shard stats");
+        }
+        if let Some(replicator) = &self.replicator {
+            replicator.stats().dump("Server replication stats");
+        }
+    }
+
     #[inline]
     fn process_requests_single_buf(
         &mut self,
@@ -389,194 +826,90 @@ where
     ) -> Result<()> {
         let pkts_len = sga.len();
         for (i, pkt) in sga.into_iter().enumerate() {
-            let message_type = MsgType::from_packet(&pkt)?;
-            match message_type {
-                MsgType::Get => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle get and queue protobuf");
-                    let response = self.serializer.handle_get(
-                        &self.kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                    )?;
-                    datapath.queue_protobuf_message(
-                        (pkt.msg_id(), pkt.conn_id(), &response),
-                        i == (pkts_len - 1),
-                    )?;
-                }
-                MsgType::GetFromList => {
-                    let response = self
-                        .serializer
-                        .handle_get_from_list(&self.list_kv_server, &pkt)?;
-                    datapath.queue_protobuf_message(
-                        (pkt.msg_id(), pkt.conn_id(), &response),
-                        i == (pkts_len - 1),
-                    )?;
-                }
-                MsgType::GetM(_size) => {
-                    let response = self.serializer.handle_getm(
-                        &self.kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                    )?;
-                    datapath.queue_protobuf_message(
-                        (pkt.msg_id(), pkt.conn_id(), &response),
-                        i == (pkts_len - 1),
-                    )?;
-                }
-                MsgType::GetList(_size) => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle getlist and queue protobuf");
-                    let response = self.serializer.handle_getlist(
-                        &self.list_kv_server,
-                        &self.linked_list_kv_server,
-                        &pkt,
-                    )?;
-                    datapath.queue_protobuf_message(
-                        (pkt.msg_id(), pkt.conn_id(), &response),
-                        i == (pkts_len - 1),
-                    )?;
-                }
-                MsgType::Put => {
-                    #[cfg(feature = "profiler")]
-                    demikernel::timer!("handle put protobuf and queue protobuf");
-                    let response = self.serializer.handle_put(
-                        &mut self.kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
-                        datapath,
-                    )?;
-                    datapath.queue_protobuf_message(
-                        (pkt.msg_id(), pkt.conn_id(), &response),
-                        i == (pkts_len - 1),
-                    )?;
-                }
-                MsgType::PutM(_size) => {
-                    let response = self.serializer.handle_putm(
-                        &mut self.kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
-                        datapath,
-                    )?;
-                    datapath.queue_protobuf_message(
-                        (pkt.msg_id(), pkt.conn_id(), &response),
-                        i == (pkts_len - 1),
-                    )?;
-                }
-                MsgType::PutList(_size) => {
-                    let response = self.serializer.handle_putlist(
-                        &mut self.list_kv_server,
-                        &mut self.mempool_ids,
-                        &pkt,
-                        datapath,
-                    )?;
-                    datapath.queue_protobuf_message(
-                        (pkt.msg_id(), pkt.conn_id(), &response),
-                        i == (pkts_len - 1),
-                    )?;
-                }
-                MsgType::AddUser => {
-                    let request = kv_messages::AddUser::parse_from_bytes(
-                        &pkt.seg(0).as_ref()[REQ_TYPE_SIZE..],
-                    )
-                    .wrap_err("Failed to deserialize proto PutMReq")?;
-                    let mut response = kv_messages::AddUserResponse::new();
-                    let old_value = self.kv_server.get(&request.keys[0].as_str()).unwrap();
-                    response.first_val = old_value.as_ref().to_vec();
-                    for (key, value) in request.keys.iter().zip(request.vals.iter()) {
-                        self.kv_server.insert_with_copies(
-                            key.as_str(),
-                            value.as_slice(),
-                            datapath,
-                            &mut self.mempool_ids,
-                        )?;
-                    }
-                    datapath.queue_protobuf_message(
-                        (pkt.msg_id(), pkt.conn_id(), &response),
-                        i == (pkts_len - 1),
-                    )?;
-                }
-                MsgType::FollowUnfollow => {
-                    let request = kv_messages::FollowUnfollow::parse_from_bytes(
-                        &pkt.seg(0).as_ref()[REQ_TYPE_SIZE..],
-                    )
-                    .wrap_err("Failed to deserialize proto PutMReq")?;
-                    let mut response = kv_messages::FollowUnfollowResponse::new();
-                    let mut list: Vec<Vec<u8>> = Vec::with_capacity(2);
-                    for (key, value) in request.keys.iter().zip(request.vals.iter()) {
-                        let old_value = self.kv_server.remove(&key.as_str()).unwrap();
-                        self.kv_server.insert_with_copies(
-                            key.as_str(),
-                            value.as_slice(),
-                            datapath,
-                            &mut self.mempool_ids,
-                        )?;
-                        list.push(old_value.as_ref().to_vec());
-                    }
-                    response.original_vals = list;
-                    datapath.queue_protobuf_message(
-                        (pkt.msg_id(), pkt.conn_id(), &response),
-                        i == (pkts_len - 1),
-                    )?;
-                }
-                MsgType::PostTweet => {
-                    let request = kv_messages::PostTweet::parse_from_bytes(
-                        &pkt.seg(0).as_ref()[REQ_TYPE_SIZE..],
-                    )
-                    .wrap_err("Failed to deserialize proto PutMReq")?;
-                    let mut response = kv_messages::PostTweetResponse::new();
-                    let mut list: Vec<Vec<u8>> = Vec::with_capacity(2);
-                    for (key, value) in request.keys.iter().zip(request.vals.iter()).take(3) {
-                        let old_value = self.kv_server.remove(&key.as_str()).unwrap();
-                        self.kv_server.insert_with_copies(
-                            key.as_str(),
-                            value.as_slice(),
-                            datapath,
-                            &mut self.mempool_ids,
-                        )?;
-                        list.push(old_value.as_ref().to_vec());
-                    }
-                    response.vals = list;
-                    for (key, value) in request.keys.iter().zip(request.vals.iter()).skip(3).take(2)
-                    {
-                        self.kv_server.insert_with_copies(
-                            key.as_str(),
-                            value.as_slice(),
-                            datapath,
-                            &mut self.mempool_ids,
-                        )?;
-                    }
-                    datapath.queue_protobuf_message(
-                        (pkt.msg_id(), pkt.conn_id(), &response),
-                        i == (pkts_len - 1),
-                    )?;
-                }
-                MsgType::GetTimeline(_) => {
-                    let request = kv_messages::GetTimeline::parse_from_bytes(
-                        &pkt.seg(0).as_ref()[REQ_TYPE_SIZE..],
-                    )
-                    .wrap_err("Failed to deserialize proto PutMReq")?;
-                    let mut response = kv_messages::GetTimelineResponse::new();
-                    let mut list: Vec<Vec<u8>> = Vec::with_capacity(request.keys.len());
-                    for key in request.keys.iter() {
-                        let old_value = self.kv_server.get(&key.as_str()).unwrap();
-                        list.push(old_value.as_ref().to_vec());
-                    }
-                    response.vals = list;
-                    datapath.queue_protobuf_message(
-                        (pkt.msg_id(), pkt.conn_id(), &response),
-                        i == (pkts_len - 1),
-                    )?;
-                }
-                MsgType::AppendToList(_size) => {
-                    unimplemented!();
+            let end_batch = i == (pkts_len - 1);
+            let message_type = match MsgType::from_packet(&pkt) {
+                Ok(message_type) => message_type,
+                Err(e) => {
+                    let status = failed_request_status(&pkt, None, &e);
+                    queue_error_response(None, status, &pkt, datapath, end_batch)?;
+                    continue;
                 }
+            };
+            if let Err(e) = self.process_request_single_buf(message_type, &pkt, datapath, end_batch)
+            {
+                let status = failed_request_status(&pkt, Some(message_type), &e);
+                queue_error_response(Some(message_type), status, &pkt, datapath, end_batch)?;
             }
         }
         Ok(())
     }
 }
 
+/// Answers a request the server could not handle with an otherwise empty response of the type the
+/// client expects for `msg_type`, carrying `status`. Requests of unknown type get a put response.
+fn queue_error_response<D>(
+    msg_type: Option<MsgType>,
+    status: ResponseStatus,
+    pkt: &ReceivedPkt<D>,
+    datapath: &mut D,
+    end_batch: bool,
+) -> Result<()>
+where
+    D: Datapath,
+{
+    let (msg_id, conn_id) = (pkt.msg_id(), pkt.conn_id());
+    let status = status.as_u32();
+    match msg_type {
+        Some(MsgType::Get) | Some(MsgType::GetFromList) => {
+            let mut response = kv_messages::GetResp::new();
+            response.status = status;
+            datapath.queue_protobuf_message((msg_id, conn_id, &response), end_batch)
+        }
+        Some(MsgType::GetM(_)) | Some(MsgType::Scan) => {
+            let mut response = kv_messages::GetMResp::new();
+            response.status = status;
+            datapath.queue_protobuf_message((msg_id, conn_id, &response), end_batch)
+        }
+        Some(MsgType::GetList(_)) => {
+            let mut response = kv_messages::GetListResp::new();
+            response.status = status;
+            datapath.queue_protobuf_message((msg_id, conn_id, &response), end_batch)
+        }
+        Some(MsgType::CompareAndSwap) => {
+            let mut response = kv_messages::CASResp::new();
+            response.status = status;
+            datapath.queue_protobuf_message((msg_id, conn_id, &response), end_batch)
+        }
+        Some(MsgType::Increment) | Some(MsgType::Decrement) => {
+            let mut response = kv_messages::IncrResp::new();
+            response.status = status;
+            datapath.queue_protobuf_message((msg_id, conn_id, &response), end_batch)
+        }
+        // retwis responses carry no status; an empty one holds no values
+        Some(MsgType::AddUser) => datapath.queue_protobuf_message(
+            (msg_id, conn_id, &kv_messages::AddUserResponse::new()),
+            end_batch,
+        ),
+        Some(MsgType::FollowUnfollow) => datapath.queue_protobuf_message(
+            (msg_id, conn_id, &kv_messages::FollowUnfollowResponse::new()),
+            end_batch,
+        ),
+        Some(MsgType::PostTweet) => datapath.queue_protobuf_message(
+            (msg_id, conn_id, &kv_messages::PostTweetResponse::new()),
+            end_batch,
+        ),
+        Some(MsgType::GetTimeline(_)) => datapath.queue_protobuf_message(
+            (msg_id, conn_id, &kv_messages::GetTimelineResponse::new()),
+            end_batch,
+        ),
+        _ => {
+            let mut response = kv_messages::PutResp::new();
+            response.status = status;
+            datapath.queue_protobuf_message((msg_id, conn_id, &response), end_batch)
+        }
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct ProtobufClient<D>
 where
@@ -598,6 +931,51 @@ where
         }
     }
 
+    fn deserialize_response_status(&self, msg_type: MsgType, buf: &[u8]) -> Result<ResponseStatus> {
+        let status = match msg_type {
+            MsgType::Get | MsgType::GetFromList => {
+                kv_messages::GetResp::parse_from_bytes(buf)
+                    .wrap_err("Could not parse get_resp from message")?
+                    .status
+            }
+            MsgType::GetM(_) | MsgType::Scan => {
+                kv_messages::GetMResp::parse_from_bytes(buf)
+                    .wrap_err("Could not parse getm_resp from message")?
+                    .status
+            }
+            MsgType::GetList(_) => {
+                kv_messages::GetListResp::parse_from_bytes(buf)
+                    .wrap_err("Could not parse getlist_resp from message")?
+                    .status
+            }
+            MsgType::Put
+            | MsgType::PutM(_)
+            | MsgType::PutList(_)
+            | MsgType::AppendToList(_)
+            | MsgType::Delete
+            | MsgType::DeleteM(_) => {
+                kv_messages::PutResp::parse_from_bytes(buf)
+                    .wrap_err("Could not parse put_resp from message")?
+                    .status
+            }
+            MsgType::CompareAndSwap => {
+                kv_messages::CASResp::parse_from_bytes(buf)
+                    .wrap_err("Could not parse cas_resp from message")?
+                    .status
+            }
+            MsgType::Increment | MsgType::Decrement => {
+                kv_messages::IncrResp::parse_from_bytes(buf)
+                    .wrap_err("Could not parse incr_resp from message")?
+                    .status
+            }
+            // retwis responses do not carry a status
+            _ => {
+                return Ok(ResponseStatus::Ok);
+            }
+        };
+        ResponseStatus::from_u32(status)
+    }
+
     fn deserialize_get_response(&self, buf: &[u8]) -> Result<Vec<u8>> {
         let get_resp = kv_messages::GetResp::parse_from_bytes(buf)
             .wrap_err("Could not parse get_resp from message")?;
@@ -616,6 +994,18 @@ where
         return Ok(getlist_resp.val_list.clone());
     }
 
+    fn deserialize_cas_response(&self, buf: &[u8]) -> Result<u64> {
+        let cas_resp = kv_messages::CASResp::parse_from_bytes(buf)
+            .wrap_err("Could not parse cas_resp from message")?;
+        return Ok(cas_resp.version);
+    }
+
+    fn deserialize_incr_response(&self, buf: &[u8]) -> Result<i64> {
+        let incr_resp = kv_messages::IncrResp::parse_from_bytes(buf)
+            .wrap_err("Could not parse incr_resp from message")?;
+        return Ok(incr_resp.val);
+    }
+
     fn check_add_user_num_values(&self, buf: &[u8]) -> Result<usize> {
         let add_user_resp = kv_messages::AddUserResponse::parse_from_bytes(buf)?;
         return Ok((add_user_resp.first_val.len() != 0) as usize);
@@ -886,4 +1276,114 @@ where
 
         Ok(output_stream.total_bytes_written() as _)
     }
+
+    fn serialize_delete(&self, buf: &mut [u8], key: &str, _datapath: &D) -> Result<usize> {
+        let mut output_stream = CodedOutputStream::bytes(buf);
+        let mut delete_req = kv_messages::DeleteReq::new();
+        delete_req.key = key.to_string();
+        delete_req
+            .write_to(&mut output_stream)
+            .wrap_err("Failed to write into CodedOutputStream for DeleteReq proto")?;
+        output_stream
+            .flush()
+            .wrap_err("Failed to flush output stream.")?;
+
+        Ok(output_stream.total_bytes_written() as _)
+    }
+
+    fn serialize_deletem(
+        &self,
+        buf: &mut [u8],
+        keys: &Vec<String>,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut output_stream = CodedOutputStream::bytes(buf);
+        let mut deletem_req = kv_messages::DeleteMReq::new();
+        deletem_req.keys = keys.clone();
+        deletem_req
+            .write_to(&mut output_stream)
+            .wrap_err("Failed to write into CodedOutputStream for DeleteMReq proto")?;
+        output_stream
+            .flush()
+            .wrap_err("Failed to flush output stream.")?;
+
+        Ok(output_stream.total_bytes_written() as _)
+    }
+
+    fn serialize_cas(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        version: u64,
+        value: &str,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut output_stream = CodedOutputStream::bytes(buf);
+        let mut cas_req = kv_messages::CASReq::new();
+        cas_req.key = key.to_string();
+        cas_req.version = version;
+        cas_req.val = value.as_bytes().to_vec();
+        cas_req
+            .write_to(&mut output_stream)
+            .wrap_err("Failed to write into CodedOutputStream for CASReq proto")?;
+        output_stream
+            .flush()
+            .wrap_err("Failed to flush output stream.")?;
+
+        Ok(output_stream.total_bytes_written() as _)
+    }
+
+    fn serialize_increment(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut output_stream = CodedOutputStream::bytes(buf);
+        let mut incr_req = kv_messages::IncrReq::new();
+        incr_req.key = key.to_string();
+        incr_req.delta = delta;
+        incr_req
+            .write_to(&mut output_stream)
+            .wrap_err("Failed to write into CodedOutputStream for IncrReq proto")?;
+        output_stream
+            .flush()
+            .wrap_err("Failed to flush output stream.")?;
+
+        Ok(output_stream.total_bytes_written() as _)
+    }
+
+    fn serialize_decrement(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        datapath: &D,
+    ) -> Result<usize> {
+        self.serialize_increment(buf, key, delta, datapath)
+    }
+
+    fn serialize_scan(
+        &self,
+        buf: &mut [u8],
+        start_key: &str,
+        end_key: Option<&str>,
+        count: u64,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let mut output_stream = CodedOutputStream::bytes(buf);
+        let mut scan_req = kv_messages::ScanReq::new();
+        scan_req.start_key = start_key.to_string();
+        scan_req.end_key = end_key.unwrap_or_default().to_string();
+        scan_req.count = count;
+        scan_req
+            .write_to(&mut output_stream)
+            .wrap_err("Failed to write into CodedOutputStream for ScanReq proto")?;
+        output_stream
+            .flush()
+            .wrap_err("Failed to flush output stream.")?;
+
+        Ok(output_stream.total_bytes_written() as _)
+    }
 }
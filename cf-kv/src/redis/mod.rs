@@ -1,9 +1,804 @@
-use cornflakes_libos::datapath::Datapath;
+use cornflakes_libos::{
+    allocator::MempoolID,
+    datapath::{Datapath, MetadataOps, PushBufType, ReceivedPkt},
+    state_machine::server::ServerSM,
+    ConnID, MsgID,
+};
 use redis;
 
-use super::ClientSerializer;
-use color_eyre::eyre::Result;
-use std::marker::PhantomData;
+use super::{
+    allocate_and_copy_into_datapath_buffer, combined_cache_stats, combined_storage_stats,
+    delete_from_stores,
+    replication::Replicator,
+    retwis::{ADD_USER_GETS, FOLLOW_UNFOLLOW_GETS, POST_TWEET_GETS},
+    shard::ShardRouter,
+    ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType, ResponseStatus,
+    ServerLoadGenerator, REQ_TYPE_SIZE,
+};
+use color_eyre::eyre::{bail, ensure, Result, WrapErr};
+use std::{marker::PhantomData, time::Duration};
+
+/// Most arguments accepted in a single command.
+const MAX_COMMAND_ARGS: usize = 1 << 20;
+const WRONG_TYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+const NOT_AN_INTEGER: &str = "ERR value is not an integer or out of range";
+const SYNTAX_ERROR: &str = "ERR syntax error";
+
+/// Arity and key positions of a supported command, following redis's command table: a
+/// negative arity is a minimum, and the keys are every `key_step`-th argument from `first_key`
+/// on (only `first_key` if the step is 0, none if `first_key` is 0).
+fn command_spec(name: &[u8]) -> Option<(isize, usize, usize)> {
+    match name {
+        b"PING" => Some((-1, 0, 0)),
+        b"GET" => Some((2, 1, 0)),
+        b"SET" => Some((-3, 1, 0)),
+        b"MGET" => Some((-2, 1, 1)),
+        b"MSET" => Some((-3, 1, 2)),
+        b"DEL" => Some((-2, 1, 1)),
+        b"RPUSH" => Some((-3, 1, 0)),
+        b"LRANGE" => Some((4, 1, 0)),
+        b"LINDEX" => Some((3, 1, 0)),
+        b"INCR" | b"DECR" => Some((2, 1, 0)),
+        b"INCRBY" | b"DECRBY" => Some((3, 1, 0)),
+        _ => None,
+    }
+}
+
+/// Requests from cf-kv's own client carry the message type header in front of the command;
+/// stock redis clients send bare RESP.
+fn strip_kv_header(buf: &[u8]) -> &[u8] {
+    if buf.first() != Some(&b'*') && buf.get(REQ_TYPE_SIZE) == Some(&b'*') {
+        &buf[REQ_TYPE_SIZE..]
+    } else {
+        buf
+    }
+}
+
+/// Splits one command (a RESP array of bulk strings) off the front of `buf`. Returns its
+/// arguments, which borrow from `buf`, and the number of bytes it took up.
+fn parse_command(buf: &[u8]) -> Result<(Vec<&[u8]>, usize)> {
+    ensure!(
+        buf.first() == Some(&b'*'),
+        "expected '*', got {:?}",
+        buf.first()
+    );
+    let (num_args, mut pos) = parse_length(buf, 1)?;
+    ensure!(
+        num_args > 0 && num_args <= MAX_COMMAND_ARGS,
+        "invalid multibulk length {}",
+        num_args
+    );
+    let mut args = Vec::with_capacity(num_args);
+    for _ in 0..num_args {
+        ensure!(
+            buf.get(pos) == Some(&b'$'),
+            "expected '$', got {:?}",
+            buf.get(pos)
+        );
+        let (len, start) = parse_length(buf, pos + 1)?;
+        ensure!(
+            buf.len() - start >= 2
+                && len <= buf.len() - start - 2
+                && &buf[start + len..start + len + 2] == b"\r\n",
+            "truncated bulk string"
+        );
+        args.push(&buf[start..start + len]);
+        pos = start + len + 2;
+    }
+    Ok((args, pos))
+}
+
+/// Parses the decimal length that starts at `pos` and ends with CRLF. Returns it and the
+/// position just past the CRLF.
+fn parse_length(buf: &[u8], pos: usize) -> Result<(usize, usize)> {
+    let line_len = match buf[pos..].windows(2).position(|w| w == b"\r\n") {
+        Some(line_len) => line_len,
+        None => bail!("unterminated line"),
+    };
+    match std::str::from_utf8(&buf[pos..pos + line_len])
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+    {
+        Some(len) => Ok((len, pos + line_len + 2)),
+        None => bail!("invalid length"),
+    }
+}
+
+fn parse_integer(arg: &[u8]) -> Option<i64> {
+    std::str::from_utf8(arg).ok()?.parse::<i64>().ok()
+}
+
+/// TTL from SET's options; only `EX seconds` and `PX milliseconds` are supported.
+fn parse_set_options(options: &[&[u8]]) -> std::result::Result<Option<Duration>, &'static str> {
+    match options {
+        [] => Ok(None),
+        [unit, amount] => {
+            let amount = match parse_integer(amount) {
+                Some(amount) if amount > 0 => amount as u64,
+                _ => return Err("ERR invalid expire time in 'set' command"),
+            };
+            match unit.to_ascii_uppercase().as_slice() {
+                b"EX" => Ok(Some(Duration::from_secs(amount))),
+                b"PX" => Ok(Some(Duration::from_millis(amount))),
+                _ => Err(SYNTAX_ERROR),
+            }
+        }
+        _ => Err(SYNTAX_ERROR),
+    }
+}
+
+/// Resolves LRANGE's inclusive, possibly negative indices against a list of `len` values.
+fn lrange_bounds(len: usize, start: i64, stop: i64) -> std::ops::Range<usize> {
+    let len = len as i64;
+    let start = match start < 0 {
+        true => std::cmp::max(len + start, 0),
+        false => start,
+    };
+    let stop = match stop < 0 {
+        true => len + stop,
+        false => std::cmp::min(stop, len - 1),
+    };
+    if start > stop || start >= len {
+        return 0..0;
+    }
+    start as usize..(stop + 1) as usize
+}
+
+enum ReplySegment<D>
+where
+    D: Datapath,
+{
+    /// Offset and length of a run of bytes in the copied buffer.
+    Copied(usize, usize),
+    ZeroCopy(D::DatapathMetadata),
+}
+
+/// A RESP reply under construction. Framing and small values are copied into one buffer, while
+/// values at or above the copying threshold are sent from the buffers they are stored in.
+struct RespReply<D>
+where
+    D: Datapath,
+{
+    copied: Vec<u8>,
+    segments: Vec<ReplySegment<D>>,
+    /// Start of the copied bytes not yet closed off into a segment.
+    run_start: usize,
+}
+
+impl<D> RespReply<D>
+where
+    D: Datapath,
+{
+    fn new() -> Self {
+        RespReply {
+            copied: Vec::new(),
+            segments: Vec::new(),
+            run_start: 0,
+        }
+    }
+
+    fn line(&mut self, prefix: u8, line: &[u8]) {
+        self.copied.push(prefix);
+        self.copied.extend_from_slice(line);
+        self.copied.extend_from_slice(b"\r\n");
+    }
+
+    fn simple(&mut self, status: &str) {
+        self.line(b'+', status.as_bytes());
+    }
+
+    fn error(&mut self, message: &str) {
+        self.line(b'-', message.as_bytes());
+    }
+
+    fn integer(&mut self, val: i64) {
+        self.line(b':', val.to_string().as_bytes());
+    }
+
+    fn nil(&mut self) {
+        self.line(b'$', b"-1");
+    }
+
+    fn array(&mut self, len: usize) {
+        self.line(b'*', len.to_string().as_bytes());
+    }
+
+    fn bulk(&mut self, value: &[u8], datapath: &D) -> Result<()> {
+        self.line(b'$', value.len().to_string().as_bytes());
+        match self.zero_copy_metadata(value, datapath)? {
+            Some(metadata) => {
+                self.close_run();
+                self.segments.push(ReplySegment::ZeroCopy(metadata));
+            }
+            None => {
+                self.copied.extend_from_slice(value);
+            }
+        }
+        self.copied.extend_from_slice(b"\r\n");
+        Ok(())
+    }
+
+    fn zero_copy_metadata(
+        &self,
+        value: &[u8],
+        datapath: &D,
+    ) -> Result<Option<D::DatapathMetadata>> {
+        // a zero-copy value also splits the copied bytes around it into another segment
+        if value.len() < datapath.get_copying_threshold()
+            || self.segments.len() + 3 > datapath.get_max_segments()
+        {
+            return Ok(None);
+        }
+        datapath.recover_metadata(value)
+    }
+
+    fn close_run(&mut self) {
+        if self.copied.len() > self.run_start {
+            self.segments.push(ReplySegment::Copied(
+                self.run_start,
+                self.copied.len() - self.run_start,
+            ));
+            self.run_start = self.copied.len();
+        }
+    }
+
+    fn send(
+        mut self,
+        msg_id: MsgID,
+        conn_id: ConnID,
+        end_batch: bool,
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<()> {
+        if !self
+            .segments
+            .iter()
+            .any(|segment| matches!(segment, ReplySegment::ZeroCopy(_)))
+        {
+            return datapath.queue_single_buffer_with_copy(
+                (msg_id, conn_id, self.copied.as_slice()),
+                end_batch,
+            );
+        }
+        self.close_run();
+        let copied = allocate_and_copy_into_datapath_buffer(&self.copied, datapath, mempool_ids)?;
+        let copied = match datapath.get_metadata(copied)? {
+            Some(metadata) => metadata,
+            None => bail!("Could not get metadata for copied reply buffer"),
+        };
+        let metadata_vec = self
+            .segments
+            .into_iter()
+            .map(|segment| match segment {
+                ReplySegment::Copied(start, len) => {
+                    let mut metadata = copied.clone();
+                    metadata.set_data_len_and_offset(len, copied.offset() + start)?;
+                    Ok(metadata)
+                }
+                ReplySegment::ZeroCopy(metadata) => Ok(metadata),
+            })
+            .collect::<Result<Vec<D::DatapathMetadata>>>()?;
+        datapath.queue_metadata_vec(msg_id, conn_id, metadata_vec, end_batch)
+    }
+}
+
+/// Serves the RESP protocol that stock redis clients speak, on top of the cf-kv stores:
+/// GET, SET (with EX/PX), MGET, MSET, DEL, RPUSH, LRANGE, LINDEX, INCR/DECR(BY), PING and
+/// MULTI/EXEC/DISCARD. Pipelined commands in one packet are answered in one reply, and a
+/// transaction must begin and end within one packet. Stock clients such as `redis-cli` and
+/// `redis-benchmark` reach it over the bare RESP streams of the linux `LinuxRespConnection`;
+/// the other datapaths frame every request with a message id.
+pub struct RedisKVServer<D>
+where
+    D: Datapath,
+{
+    kv_server: KVServer<D>,
+    list_kv_server: ListKVServer<D>,
+    linked_list_kv_server: LinkedListKVServer<D>,
+    mempool_ids: Vec<MempoolID>,
+    push_buf_type: PushBufType,
+    use_linked_list: bool,
+    shard_router: Option<ShardRouter>,
+    replicator: Option<Replicator<D>>,
+}
+
+impl<D> RedisKVServer<D>
+where
+    D: Datapath,
+{
+    pub fn new<L>(
+        file: &str,
+        load_generator: L,
+        datapath: &mut D,
+        push_buf_type: PushBufType,
+        use_linked_list: bool,
+    ) -> Result<Self>
+    where
+        L: ServerLoadGenerator,
+    {
+        let (kv, list_kv, linked_list_kv_server, mempool_ids) =
+            load_generator.new_kv_state(file, datapath, use_linked_list)?;
+        Ok(RedisKVServer {
+            kv_server: kv,
+            list_kv_server: list_kv,
+            linked_list_kv_server: linked_list_kv_server,
+            mempool_ids: mempool_ids,
+            push_buf_type: push_buf_type,
+            use_linked_list: use_linked_list,
+            shard_router: None,
+            replicator: None,
+        })
+    }
+
+    /// Makes this server one shard of a multi-core server.
+    pub fn set_shard_router(&mut self, router: ShardRouter) {
+        self.shard_router = Some(router);
+    }
+
+    /// Makes this server a replica, following the given replication config.
+    pub fn set_replicator(&mut self, replicator: Replicator<D>) {
+        self.replicator = Some(replicator);
+    }
+
+    fn value(&self, key: &str) -> Option<&[u8]> {
+        match self.use_linked_list {
+            true => self
+                .linked_list_kv_server
+                .get(key)
+                .map(|node| node.get_data()),
+            false => self.kv_server.get(key).map(|v| v.as_ref()),
+        }
+    }
+
+    fn holds_string(&self, key: &str) -> bool {
+        self.kv_server.contains_key(key) || self.linked_list_kv_server.contains_key(key)
+    }
+
+    fn set(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+        datapath: &mut D,
+    ) -> Result<()> {
+        // SET replaces a key of any type
+        self.list_kv_server.remove(key);
+        match self.use_linked_list {
+            true => self.linked_list_kv_server.insert_with_copies_and_ttl(
+                key,
+                value,
+                ttl,
+                datapath,
+                &mut self.mempool_ids,
+            ),
+            false => self.kv_server.insert_with_copies_and_ttl(
+                key,
+                value,
+                ttl,
+                datapath,
+                &mut self.mempool_ids,
+            ),
+        }
+    }
+
+    /// Runs one command, adding its reply. Client mistakes are answered with error replies;
+    /// only failures of the server itself are returned as errors.
+    fn handle_command(
+        &mut self,
+        args: &[&[u8]],
+        reply: &mut RespReply<D>,
+        datapath: &mut D,
+    ) -> Result<()> {
+        let name = args[0].to_ascii_uppercase();
+        let (arity, first_key, key_step) = match command_spec(&name) {
+            Some(spec) => spec,
+            None => {
+                reply.error(&format!(
+                    "ERR unknown command '{}'",
+                    String::from_utf8_lossy(args[0])
+                ));
+                return Ok(());
+            }
+        };
+        let arity_ok = match arity >= 0 {
+            true => args.len() == arity as usize,
+            false => args.len() >= arity.unsigned_abs(),
+        };
+        if !arity_ok || (name == b"MSET" && args.len() % 2 == 0) {
+            reply.error(&format!(
+                "ERR wrong number of arguments for '{}' command",
+                String::from_utf8_lossy(&name).to_lowercase()
+            ));
+            return Ok(());
+        }
+        let key_args: Vec<&[u8]> = match (first_key, key_step) {
+            (0, _) => Vec::new(),
+            (first, 0) => vec![args[first]],
+            (first, step) => args[first..].iter().step_by(step).copied().collect(),
+        };
+        let keys = match key_args
+            .into_iter()
+            .map(std::str::from_utf8)
+            .collect::<std::result::Result<Vec<&str>, _>>()
+        {
+            Ok(keys) => keys,
+            Err(_) => {
+                reply.error("ERR keys must be valid UTF-8");
+                return Ok(());
+            }
+        };
+
+        match name.as_slice() {
+            b"PING" => match args.len() {
+                1 => reply.simple("PONG"),
+                _ => reply.bulk(args[1], datapath)?,
+            },
+            b"GET" => match self.value(keys[0]) {
+                Some(value) => reply.bulk(value, datapath)?,
+                None if self.list_kv_server.contains_key(keys[0]) => reply.error(WRONG_TYPE),
+                None => reply.nil(),
+            },
+            b"MGET" => {
+                reply.array(keys.len());
+                for key in keys.iter() {
+                    match self.value(key) {
+                        Some(value) => reply.bulk(value, datapath)?,
+                        None => reply.nil(),
+                    }
+                }
+            }
+            b"SET" => match parse_set_options(&args[3..]) {
+                Ok(ttl) => {
+                    self.set(keys[0], args[2], ttl, datapath)?;
+                    reply.simple("OK");
+                }
+                Err(message) => reply.error(message),
+            },
+            b"MSET" => {
+                for (key, value) in keys.iter().zip(args[2..].iter().step_by(2)) {
+                    self.set(key, value, None, datapath)?;
+                }
+                reply.simple("OK");
+            }
+            b"DEL" => {
+                let deleted = keys
+                    .iter()
+                    .filter(|key| {
+                        delete_from_stores(
+                            key,
+                            &mut self.kv_server,
+                            &mut self.list_kv_server,
+                            &mut self.linked_list_kv_server,
+                        )
+                    })
+                    .count();
+                reply.integer(deleted as i64);
+            }
+            b"RPUSH" => {
+                if self.holds_string(keys[0]) {
+                    reply.error(WRONG_TYPE);
+                    return Ok(());
+                }
+                for value in args[2..].iter() {
+                    self.list_kv_server.append_with_copies(
+                        keys[0],
+                        value,
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?;
+                }
+                let len = self
+                    .list_kv_server
+                    .get_map()
+                    .get(keys[0])
+                    .map(|list| list.len())
+                    .unwrap_or(0);
+                reply.integer(len as i64);
+            }
+            b"LRANGE" => {
+                if self.holds_string(keys[0]) {
+                    reply.error(WRONG_TYPE);
+                    return Ok(());
+                }
+                let (start, stop) = match (parse_integer(args[2]), parse_integer(args[3])) {
+                    (Some(start), Some(stop)) => (start, stop),
+                    _ => {
+                        reply.error(NOT_AN_INTEGER);
+                        return Ok(());
+                    }
+                };
+                match self.list_kv_server.get(keys[0]) {
+                    Some(list) => {
+                        let range = lrange_bounds(list.len(), start, stop);
+                        reply.array(range.len());
+                        for value in list[range].iter() {
+                            reply.bulk(value.as_ref(), datapath)?;
+                        }
+                    }
+                    None => reply.array(0),
+                }
+            }
+            b"LINDEX" => {
+                if self.holds_string(keys[0]) {
+                    reply.error(WRONG_TYPE);
+                    return Ok(());
+                }
+                let idx = match parse_integer(args[2]) {
+                    Some(idx) => idx,
+                    None => {
+                        reply.error(NOT_AN_INTEGER);
+                        return Ok(());
+                    }
+                };
+                let value = self.list_kv_server.get(keys[0]).and_then(|list| {
+                    let range = lrange_bounds(list.len(), idx, idx);
+                    list[range].first()
+                });
+                match value {
+                    Some(value) => reply.bulk(value.as_ref(), datapath)?,
+                    None => reply.nil(),
+                }
+            }
+            b"INCR" | b"DECR" | b"INCRBY" | b"DECRBY" => {
+                if self.list_kv_server.contains_key(keys[0]) {
+                    reply.error(WRONG_TYPE);
+                    return Ok(());
+                }
+                let delta = match args.len() {
+                    2 => Some(1),
+                    _ => parse_integer(args[2]),
+                };
+                let delta = match name.starts_with(b"DECR") {
+                    true => delta.and_then(|delta| delta.checked_neg()),
+                    false => delta,
+                };
+                let delta = match delta {
+                    Some(delta) => delta,
+                    None => {
+                        reply.error(NOT_AN_INTEGER);
+                        return Ok(());
+                    }
+                };
+                let (status, value) = match self.use_linked_list {
+                    true => self.linked_list_kv_server.increment(
+                        keys[0],
+                        delta,
+                        datapath,
+                        &mut self.mempool_ids,
+                    )?,
+                    false => {
+                        self.kv_server
+                            .increment(keys[0], delta, datapath, &mut self.mempool_ids)?
+                    }
+                };
+                match status {
+                    ResponseStatus::Ok => reply.integer(value),
+                    ResponseStatus::BadRequest => {
+                        reply.error("ERR increment or decrement would overflow")
+                    }
+                    _ => reply.error(NOT_AN_INTEGER),
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Runs the commands in one request, queueing those between MULTI and EXEC until the
+    /// transaction is executed.
+    fn handle_request(
+        &mut self,
+        mut commands: &[u8],
+        reply: &mut RespReply<D>,
+        datapath: &mut D,
+    ) -> Result<()> {
+        if commands.is_empty() {
+            reply.error("ERR Protocol error: empty request");
+            return Ok(());
+        }
+        // queued commands, and whether one of them was rejected
+        let mut transaction: Option<(Vec<Vec<&[u8]>>, bool)> = None;
+        while !commands.is_empty() {
+            let (args, len) = match parse_command(commands) {
+                Ok(command) => command,
+                Err(e) => {
+                    reply.error(&format!("ERR Protocol error: {}", e));
+                    return Ok(());
+                }
+            };
+            commands = &commands[len..];
+            let name = args[0].to_ascii_uppercase();
+            match (name.as_slice(), &mut transaction) {
+                (b"MULTI", None) => {
+                    transaction = Some((Vec::new(), false));
+                    reply.simple("OK");
+                }
+                (b"MULTI", Some(_)) => reply.error("ERR MULTI calls can not be nested"),
+                (b"EXEC", Some((_, true))) => {
+                    transaction = None;
+                    reply.error("EXECABORT Transaction discarded because of previous errors.");
+                }
+                (b"EXEC", Some(_)) => {
+                    let (queued, _) = transaction.take().unwrap();
+                    reply.array(queued.len());
+                    for args in queued.iter() {
+                        self.handle_command(args, reply, datapath)?;
+                    }
+                }
+                (b"DISCARD", Some(_)) => {
+                    transaction = None;
+                    reply.simple("OK");
+                }
+                (b"EXEC" | b"DISCARD", None) => reply.error(&format!(
+                    "ERR {} without MULTI",
+                    String::from_utf8_lossy(&name)
+                )),
+                (_, Some((_, aborted))) if command_spec(&name).is_none() => {
+                    *aborted = true;
+                    reply.error(&format!(
+                        "ERR unknown command '{}'",
+                        String::from_utf8_lossy(args[0])
+                    ));
+                }
+                (_, Some((queued, _))) => {
+                    queued.push(args);
+                    reply.simple("QUEUED");
+                }
+                (_, None) => self.handle_command(&args, reply, datapath)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<D> ServerSM for RedisKVServer<D>
+where
+    D: Datapath,
+{
+    type Datapath = D;
+    #[inline]
+    fn push_buf_type(&self) -> PushBufType {
+        self.push_buf_type
+    }
+
+    fn steer_packets(
+        &mut self,
+        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        datapath: &mut Self::Datapath,
+    ) -> Result<Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>> {
+        let pkts = match &mut self.shard_router {
+            Some(router) => router.steer(pkts, datapath)?,
+            None => pkts,
+        };
+        match &mut self.replicator {
+            Some(replicator) => replicator.steer(pkts, datapath),
+            None => Ok(pkts),
+        }
+    }
+
+    fn log_stats(&self, datapath: &Self::Datapath) {
+        datapath.transmit_stats().dump("Server transmit stats");
+        datapath
+            .allocator_memory_stats()
+            .dump("Server allocator memory stats");
+        combined_cache_stats(
+            &self.kv_server,
+            &self.list_kv_server,
+            &self.linked_list_kv_server,
+        )
+        .dump("Server cache stats");
+        combined_storage_stats(
+            &self.kv_server,
+            &self.list_kv_server,
+            &self.linked_list_kv_server,
+        )
+        .dump("Server storage stats");
+        if let Some(router) = &self.shard_router {
+            router.stats().dump("Server shard stats");
+        }
+        if let Some(replicator) = &self.replicator {
+            replicator.stats().dump("Server replication stats");
+        }
+    }
+
+    fn process_requests_single_buf(
+        &mut self,
+        sga: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        datapath: &mut Self::Datapath,
+    ) -> Result<()> {
+        let pkts_len = sga.len();
+        for (i, pkt) in sga.into_iter().enumerate() {
+            // commands are parsed in place unless the request spans several segments
+            let contiguous: Vec<u8>;
+            let data = match pkt.num_segs() {
+                1 => pkt.seg(0).as_ref(),
+                _ => {
+                    contiguous = pkt
+                        .iter()
+                        .flat_map(|seg| seg.as_ref().iter().copied())
+                        .collect();
+                    contiguous.as_slice()
+                }
+            };
+            let mut reply = RespReply::new();
+            if let Err(e) = self.handle_request(strip_kv_header(data), &mut reply, datapath) {
+                // a reply already half written for the failed request is thrown away
+                tracing::warn!(msg_id = pkt.msg_id(), "Failed to handle request: {:?}", e);
+                reply = RespReply::new();
+                reply.error(&format!("ERR {}", e).replace(&['\r', '\n'][..], " "));
+            }
+            reply.send(
+                pkt.msg_id(),
+                pkt.conn_id(),
+                i == (pkts_len - 1),
+                datapath,
+                &mut self.mempool_ids,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Packs a Retwis request as one transaction: it reads the first `num_gets` keys, then writes
+/// each key with its value, like the Retwis handlers of the other servers.
+fn pack_retwis_transaction(num_gets: usize, keys: &Vec<&str>, values: &Vec<String>) -> Vec<u8> {
+    let mut data = redis::cmd("MULTI").get_packed_command();
+    for key in keys.iter().take(num_gets) {
+        data.extend(redis::cmd("GET").arg(*key).get_packed_command());
+    }
+    let mut mset = redis::cmd("MSET");
+    for (key, value) in keys.iter().zip(values.iter()) {
+        mset.arg(*key).arg(value);
+    }
+    data.extend(mset.get_packed_command());
+    data.extend(redis::cmd("EXEC").get_packed_command());
+    data
+}
+
+/// Replies to the commands of a transaction. EXEC's reply follows the `+OK` to MULTI and a
+/// `+QUEUED` for each queued command.
+fn exec_reply(buf: &[u8]) -> Result<Vec<redis::Value>> {
+    let mut rest = buf;
+    while rest.starts_with(b"+") {
+        match rest.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => rest = &rest[end + 2..],
+            None => bail!("Truncated redis transaction response"),
+        }
+    }
+    match redis::parse_redis_value(rest).wrap_err("Failed to parse redis EXEC response")? {
+        redis::Value::Bulk(vals) => Ok(vals),
+        val => bail!("Expected array reply to EXEC, got {:?}", val),
+    }
+}
+
+/// Number of values found by the reads in a transaction or MGET reply.
+fn count_found_values(buf: &[u8]) -> Result<usize> {
+    let vals = match buf.starts_with(b"+") {
+        true => exec_reply(buf)?,
+        false => match redis::parse_redis_value(buf).wrap_err("Failed to parse redis response")? {
+            redis::Value::Bulk(vals) => vals,
+            val => bail!("Expected array reply, got {:?}", val),
+        },
+    };
+    Ok(vals
+        .iter()
+        .filter(|val| matches!(val, redis::Value::Data(_)))
+        .count())
+}
+
+/// Values of an array reply (MGET, LRANGE).
+fn array_values(buf: &[u8]) -> Result<Vec<Vec<u8>>> {
+    match redis::parse_redis_value(buf).wrap_err("Failed to parse redis response")? {
+        redis::Value::Bulk(vals) => vals
+            .into_iter()
+            .map(|val| match val {
+                redis::Value::Data(data) => Ok(data),
+                val => bail!("Expected bulk string in array reply, got {:?}", val),
+            })
+            .collect(),
+        val => bail!("Expected array reply, got {:?}", val),
+    }
+}
 
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct RedisClient<D>
@@ -26,36 +821,74 @@ where
         }
     }
 
-    fn deserialize_get_response(&self, _buf: &[u8]) -> Result<Vec<u8>> {
-        unimplemented!()
+    fn deserialize_response_status(&self, msg_type: MsgType, buf: &[u8]) -> Result<ResponseStatus> {
+        // error replies are `-<CODE> <message>`; a missing key comes back as a nil bulk string,
+        // or as a nil element of the array for MGET, and DEL counts the keys it removed.
+        // Transactions start with the `+OK` to MULTI.
+        if buf.starts_with(b"-WRONGTYPE") {
+            return Ok(ResponseStatus::WrongType);
+        } else if buf.starts_with(b"-ERR") {
+            return Ok(ResponseStatus::BadRequest);
+        } else if buf.starts_with(b"-") {
+            return Ok(ResponseStatus::ServerError);
+        }
+        match redis::parse_redis_value(buf).wrap_err("Failed to parse redis response")? {
+            redis::Value::Nil => Ok(ResponseStatus::NotFound),
+            redis::Value::Int(0) if matches!(msg_type, MsgType::Delete | MsgType::DeleteM(_)) => {
+                Ok(ResponseStatus::NotFound)
+            }
+            redis::Value::Bulk(vals) if vals.iter().any(|v| matches!(v, redis::Value::Nil)) => {
+                Ok(ResponseStatus::NotFound)
+            }
+            _ => Ok(ResponseStatus::Ok),
+        }
     }
 
-    fn deserialize_getm_response(&self, _buf: &[u8]) -> Result<Vec<Vec<u8>>> {
-        unimplemented!()
+    fn deserialize_get_response(&self, buf: &[u8]) -> Result<Vec<u8>> {
+        match redis::parse_redis_value(buf).wrap_err("Failed to parse redis response")? {
+            redis::Value::Data(data) => Ok(data),
+            val => bail!("Expected bulk string reply to GET, got {:?}", val),
+        }
+    }
+
+    fn deserialize_getm_response(&self, buf: &[u8]) -> Result<Vec<Vec<u8>>> {
+        array_values(buf)
     }
 
-    fn deserialize_getlist_response(&self, _buf: &[u8]) -> Result<Vec<Vec<u8>>> {
-        unimplemented!()
+    fn deserialize_getlist_response(&self, buf: &[u8]) -> Result<Vec<Vec<u8>>> {
+        array_values(buf)
     }
 
-    fn check_add_user_num_values(&self, _buf: &[u8]) -> Result<usize> {
-        unimplemented!()
+    fn deserialize_cas_response(&self, _buf: &[u8]) -> Result<u64> {
+        bail!("Redis has no versioned put.");
     }
 
-    fn check_follow_unfollow_num_values(&self, _buf: &[u8]) -> Result<usize> {
-        unimplemented!()
+    fn deserialize_incr_response(&self, buf: &[u8]) -> Result<i64> {
+        match redis::parse_redis_value(buf).wrap_err("Failed to parse redis response")? {
+            redis::Value::Int(val) => Ok(val),
+            val => bail!("Expected integer reply to INCRBY/DECRBY, got {:?}", val),
+        }
     }
 
-    fn check_post_tweet_num_values(&self, _buf: &[u8]) -> Result<usize> {
-        unimplemented!()
+    fn check_add_user_num_values(&self, buf: &[u8]) -> Result<usize> {
+        let vals = exec_reply(buf)?;
+        Ok(matches!(vals.first(), Some(redis::Value::Data(data)) if !data.is_empty()) as usize)
     }
 
-    fn check_get_timeline_num_values(&self, _buf: &[u8]) -> Result<usize> {
-        unimplemented!()
+    fn check_follow_unfollow_num_values(&self, buf: &[u8]) -> Result<usize> {
+        count_found_values(buf)
     }
 
-    fn check_retwis_response_num_values(&self, _buf: &[u8]) -> Result<usize> {
-        unimplemented!()
+    fn check_post_tweet_num_values(&self, buf: &[u8]) -> Result<usize> {
+        count_found_values(buf)
+    }
+
+    fn check_get_timeline_num_values(&self, buf: &[u8]) -> Result<usize> {
+        count_found_values(buf)
+    }
+
+    fn check_retwis_response_num_values(&self, buf: &[u8]) -> Result<usize> {
+        count_found_values(buf)
     }
 
     fn serialize_get(&self, buf: &mut [u8], key: &str, _datapath: &D) -> Result<usize> {
@@ -66,12 +899,14 @@ where
 
     fn serialize_get_from_list(
         &self,
-        _buf: &mut [u8],
-        _key: &str,
-        _idx: usize,
+        buf: &mut [u8],
+        key: &str,
+        idx: usize,
         _datapath: &D,
     ) -> Result<usize> {
-        unimplemented!();
+        let data = redis::cmd("LINDEX").arg(key).arg(idx).get_packed_command();
+        buf[0..data.len()].copy_from_slice(&data);
+        Ok(data.len())
     }
 
     fn serialize_put(
@@ -130,63 +965,250 @@ where
         Ok(data.len())
     }
 
+    // a list put replaces the whole list, so the old one is deleted in the same transaction
     fn serialize_put_list(
         &self,
-        _buf: &mut [u8],
-        _key: &str,
-        _values: &Vec<String>,
+        buf: &mut [u8],
+        key: &str,
+        values: &Vec<String>,
         _datapath: &D,
     ) -> Result<usize> {
-        unimplemented!()
+        let mut data = redis::cmd("MULTI").get_packed_command();
+        data.extend(redis::cmd("DEL").arg(key).get_packed_command());
+        if !values.is_empty() {
+            data.extend(
+                redis::cmd("RPUSH")
+                    .arg(key)
+                    .arg(values)
+                    .get_packed_command(),
+            );
+        }
+        data.extend(redis::cmd("EXEC").get_packed_command());
+        buf[0..data.len()].copy_from_slice(&data);
+        Ok(data.len())
     }
 
     fn serialize_append(
         &self,
-        _buf: &mut [u8],
-        _key: &str,
-        _value: &str,
+        buf: &mut [u8],
+        key: &str,
+        value: &str,
         _datapath: &D,
     ) -> Result<usize> {
-        unimplemented!()
+        let data = redis::cmd("RPUSH").arg(key).arg(value).get_packed_command();
+        buf[0..data.len()].copy_from_slice(&data);
+        Ok(data.len())
     }
 
     fn serialize_add_user(
         &self,
-        _buf: &mut [u8],
-        _keys: &Vec<&str>,
-        _values: &Vec<String>,
+        buf: &mut [u8],
+        keys: &Vec<&str>,
+        values: &Vec<String>,
         _datapath: &D,
     ) -> Result<usize> {
-        unimplemented!()
+        let data = pack_retwis_transaction(ADD_USER_GETS, keys, values);
+        buf[0..data.len()].copy_from_slice(&data);
+        Ok(data.len())
     }
 
     fn serialize_add_follow_unfollow(
         &self,
-        _buf: &mut [u8],
-        _keys: &Vec<&str>,
-        _values: &Vec<String>,
+        buf: &mut [u8],
+        keys: &Vec<&str>,
+        values: &Vec<String>,
         _datapath: &D,
     ) -> Result<usize> {
-        unimplemented!()
+        let data = pack_retwis_transaction(FOLLOW_UNFOLLOW_GETS, keys, values);
+        buf[0..data.len()].copy_from_slice(&data);
+        Ok(data.len())
     }
 
     fn serialize_post_tweet(
         &self,
-        _buf: &mut [u8],
-        _keys: &Vec<&str>,
-        _values: &Vec<String>,
+        buf: &mut [u8],
+        keys: &Vec<&str>,
+        values: &Vec<String>,
         _datapath: &D,
     ) -> Result<usize> {
-        unimplemented!()
+        let data = pack_retwis_transaction(POST_TWEET_GETS, keys, values);
+        buf[0..data.len()].copy_from_slice(&data);
+        Ok(data.len())
     }
 
     fn serialize_get_timeline(
         &self,
-        _buf: &mut [u8],
-        _keys: &Vec<&str>,
+        buf: &mut [u8],
+        keys: &Vec<&str>,
         _values: &Vec<String>,
         _datapath: &D,
     ) -> Result<usize> {
-        unimplemented!()
+        let data = redis::cmd("MGET").arg(keys).get_packed_command();
+        buf[0..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn serialize_delete(&self, buf: &mut [u8], key: &str, _datapath: &D) -> Result<usize> {
+        let data = redis::cmd("DEL").arg(key).get_packed_command();
+        buf[0..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn serialize_deletem(
+        &self,
+        buf: &mut [u8],
+        keys: &Vec<String>,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let data = {
+            let mut cmd = redis::cmd("DEL");
+            let mut cmd_ref = &mut cmd;
+            for key in keys {
+                cmd_ref = cmd_ref.arg(key);
+            }
+            cmd.get_packed_command()
+        };
+        buf[0..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn serialize_cas(
+        &self,
+        _buf: &mut [u8],
+        _key: &str,
+        _version: u64,
+        _value: &str,
+        _datapath: &D,
+    ) -> Result<usize> {
+        // keys carry no version, and WATCH/MULTI would take several round trips
+        bail!("Redis has no versioned put.");
+    }
+
+    fn serialize_increment(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let data = redis::cmd("INCRBY")
+            .arg(key)
+            .arg(delta)
+            .get_packed_command();
+        buf[0..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn serialize_decrement(
+        &self,
+        buf: &mut [u8],
+        key: &str,
+        delta: u64,
+        _datapath: &D,
+    ) -> Result<usize> {
+        let data = redis::cmd("DECRBY")
+            .arg(key)
+            .arg(delta)
+            .get_packed_command();
+        buf[0..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn serialize_scan(
+        &self,
+        _buf: &mut [u8],
+        _start_key: &str,
+        _end_key: Option<&str>,
+        _count: u64,
+        _datapath: &D,
+    ) -> Result<usize> {
+        // SCAN in redis walks the keyspace in hash order and returns keys, not values
+        bail!("Redis has no ordered range scan over values.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_split_off_pipelined_requests() {
+        let request = b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$0\r\n\r\n";
+        let (args, len) = parse_command(request).unwrap();
+        assert_eq!(args, vec![&b"GET"[..], &b"foo"[..]]);
+        assert_eq!(len, 22);
+        let (args, len) = parse_command(&request[len..]).unwrap();
+        assert_eq!(args, vec![&b"SET"[..], &b"k"[..], &b""[..]]);
+        assert_eq!(len, request.len() - 22);
+    }
+
+    #[test]
+    fn bulk_strings_may_hold_crlf() {
+        let (args, len) = parse_command(b"*2\r\n$4\r\nPING\r\n$4\r\na\r\nb\r\n").unwrap();
+        assert_eq!(args, vec![&b"PING"[..], &b"a\r\nb"[..]]);
+        assert_eq!(len, 24);
+    }
+
+    #[test]
+    fn malformed_commands_are_rejected() {
+        let requests: &[&[u8]] = &[
+            b"",
+            b"GET foo\r\n",
+            b"*0\r\n",
+            b"*-1\r\n",
+            b"*x\r\n",
+            b"*2\r\n$3\r\nGET\r\n",
+            b"*1\r\n:3\r\n",
+            b"*1\r\n$3\r\nGE",
+            b"*1\r\n$3\r\nGETX\r\n",
+            b"*1\r\n$3",
+        ];
+        for request in requests.iter() {
+            assert!(parse_command(request).is_err(), "{:?}", request);
+        }
+    }
+
+    #[test]
+    fn kv_header_is_stripped_only_before_a_command() {
+        assert_eq!(strip_kv_header(b"*1\r\n"), b"*1\r\n");
+        assert_eq!(strip_kv_header(b"\x00\x01\x00\x00*1\r\n"), b"*1\r\n");
+        assert_eq!(strip_kv_header(b"\x00\x01\x00\x00"), b"\x00\x01\x00\x00");
+    }
+
+    #[test]
+    fn lrange_bounds_follow_redis() {
+        assert_eq!(lrange_bounds(5, 0, -1), 0..5);
+        assert_eq!(lrange_bounds(5, 1, 2), 1..3);
+        assert_eq!(lrange_bounds(5, -2, -1), 3..5);
+        assert_eq!(lrange_bounds(5, -100, 100), 0..5);
+        assert_eq!(lrange_bounds(5, 3, 1), 0..0);
+        assert_eq!(lrange_bounds(5, 5, 10), 0..0);
+        assert_eq!(lrange_bounds(5, 0, -6), 0..0);
+        assert_eq!(lrange_bounds(0, 0, -1), 0..0);
+        // LINDEX resolves one index the same way
+        assert_eq!(lrange_bounds(5, -1, -1), 4..5);
+        assert_eq!(lrange_bounds(5, 7, 7), 0..0);
+    }
+
+    #[test]
+    fn set_options_give_the_ttl() {
+        assert_eq!(parse_set_options(&[]), Ok(None));
+        assert_eq!(
+            parse_set_options(&[b"ex", b"10"]),
+            Ok(Some(Duration::from_secs(10)))
+        );
+        assert_eq!(
+            parse_set_options(&[b"PX", b"250"]),
+            Ok(Some(Duration::from_millis(250)))
+        );
+        assert_eq!(parse_set_options(&[b"NX"]), Err(SYNTAX_ERROR));
+        assert_eq!(parse_set_options(&[b"KEEPTTL", b"1"]), Err(SYNTAX_ERROR));
+        assert_eq!(parse_set_options(&[b"EX", b"10", b"NX"]), Err(SYNTAX_ERROR));
+        for amount in [&b"0"[..], b"-5", b"ten"].iter() {
+            assert_eq!(
+                parse_set_options(&[b"EX", amount]),
+                Err("ERR invalid expire time in 'set' command")
+            );
+        }
     }
 }
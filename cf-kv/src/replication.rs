@@ -0,0 +1,727 @@
+//! Primary-backup replication of the kv stores over the datapath.
+//!
+//! Every replica runs a normal kv server. The primary holds back each write it receives from a
+//! client and forwards the received packet to the backups as a scatter-gather array of a small
+//! header naming the client, followed by the same receive buffers, so forwarding never copies
+//! the values. A backup strips the header and processes a forwarded write like any other
+//! request, and its response doubles as the acknowledgement. Once `acks` backups
+//! have confirmed a write (and every earlier one), the primary processes it too, which applies
+//! it and answers the client; writes therefore apply in the same order everywhere. Reads are
+//! served by whichever replica receives them. Forwarding needs a datapath that can send
+//! received buffers by reference (`Datapath::queue_metadata_vec`), such as mlx5.
+//!
+//! The replicas, the primary and the number of acknowledgements are read from a JSON
+//! configuration file that every server polls. Failing over means writing a new file with a
+//! higher epoch and a different primary: the new primary starts forwarding to the remaining
+//! backups, and backups only accept forwarded writes from the current primary. Writes in flight
+//! at a failover are abandoned and left to client retries. Clients pick the primary from the
+//! file when they start.
+//!
+//! Forwarded writes that are not acknowledged within `ack_timeout_ms` are dropped without being
+//! applied on the primary, so the client retries them, as it would a lost packet. Backups may
+//! have applied such a write already, so each remembers the last `DEDUPE_WINDOW` writes it
+//! applied by client address and message id, and acknowledges a retry of one without applying
+//! it again; non-idempotent writes such as appends and increments thus still apply once per
+//! backup. The window outlives failovers, as the header names the client by address rather
+//! than by a replica's connection id. A new primary does not check it, though, so a write it
+//! applied as a backup before the failover is applied again if the client retries it there.
+use super::MsgType;
+use color_eyre::eyre::{bail, ensure, Result, WrapErr};
+use cornflakes_libos::{
+    datapath::{Datapath, MetadataOps, ReceivedPkt},
+    utils::AddressInfo,
+    ConnID, MsgID,
+};
+use serde::Deserialize;
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::{self, read_to_string},
+    io::Write,
+    net::{IpAddr, Ipv6Addr},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often servers check the replication configuration file for changes.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Acknowledgements are tracked in a bitmap with one bit per backup.
+const MAX_REPLICAS: usize = 64;
+/// Forwarded writes a backup remembers having applied.
+const DEDUPE_WINDOW: usize = 1 << 16;
+/// Length of the header the primary puts in front of forwarded writes: two zero bytes, so the
+/// header carries no shard hint, then the client's IP (IPv4-mapped for IPv4), UDP port and
+/// message id.
+const FORWARD_HEADER_LEN: usize = 24;
+
+fn default_ack_timeout_ms() -> u64 {
+    100
+}
+
+/// Contents of the replication config file, e.g.
+/// `{"epoch": 1, "replicas": ["10.0.0.1", "10.0.0.2"], "primary": 0, "acks": 1}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ReplicationConfig {
+    /// Bumped on every change; servers ignore files whose epoch is not newer than the one they
+    /// run.
+    pub epoch: u64,
+    /// Server IPs of every replica, including the primary. Their MAC addresses and port come
+    /// from the datapath config file.
+    pub replicas: Vec<IpAddr>,
+    /// Index of the primary in `replicas`.
+    pub primary: usize,
+    /// Backups that must confirm a write before the primary applies it and answers the client.
+    /// With 0, writes are forwarded but the client is answered right away.
+    pub acks: usize,
+    #[serde(default = "default_ack_timeout_ms")]
+    pub ack_timeout_ms: u64,
+}
+
+impl ReplicationConfig {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let config: ReplicationConfig = serde_json::from_str(&read_to_string(path)?)
+            .wrap_err(format!("Could not parse replication config {:?}", path))?;
+        ensure!(
+            config.replicas.len() <= MAX_REPLICAS,
+            "At most {} replicas are supported, got {}.",
+            MAX_REPLICAS,
+            config.replicas.len()
+        );
+        ensure!(
+            config.primary < config.replicas.len(),
+            "Primary index {} is out of range for {} replicas.",
+            config.primary,
+            config.replicas.len()
+        );
+        ensure!(
+            config.acks < config.replicas.len(),
+            "Cannot wait for {} acks with {} backups.",
+            config.acks,
+            config.replicas.len() - 1
+        );
+        Ok(config)
+    }
+
+    pub fn primary_ip(&self) -> IpAddr {
+        self.replicas[self.primary]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Primary,
+    Backup,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplicationStats {
+    /// Client writes the primary forwarded to its backups.
+    pub forwarded: u64,
+    /// Acknowledgements the primary received from backups.
+    pub acks: u64,
+    /// Forwarded writes that collected enough acknowledgements and were applied.
+    pub committed: u64,
+    /// Forwarded writes dropped because they timed out or a failover abandoned them.
+    pub abandoned: u64,
+    /// Client writes a backup refused because only the primary takes them.
+    pub rejected: u64,
+    /// Forwarded writes a backup had already applied, acknowledged without applying them again.
+    pub duplicates: u64,
+    /// Packets from other replicas that belong to an earlier configuration, e.g. forwards from
+    /// a demoted primary or acknowledgements for abandoned writes.
+    pub stale: u64,
+}
+
+impl ReplicationStats {
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            forwarded = self.forwarded,
+            acks = self.acks,
+            committed = self.committed,
+            abandoned = self.abandoned,
+            rejected = self.rejected,
+            duplicates = self.duplicates,
+            stale = self.stale,
+            "{}",
+            msg
+        );
+    }
+}
+
+/// Names a client write the same way on every replica, which each know the client by a
+/// different connection id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ClientWrite {
+    ip: Ipv6Addr,
+    port: u16,
+    msg_id: MsgID,
+}
+
+impl ClientWrite {
+    fn new(client: &AddressInfo, msg_id: MsgID) -> Self {
+        let ip = match client.ip_addr {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+        ClientWrite {
+            ip,
+            port: client.udp_port,
+            msg_id,
+        }
+    }
+
+    fn to_header(self) -> [u8; FORWARD_HEADER_LEN] {
+        let mut header = [0u8; FORWARD_HEADER_LEN];
+        header[2..18].copy_from_slice(&self.ip.octets());
+        header[18..20].copy_from_slice(&self.port.to_le_bytes());
+        header[20..24].copy_from_slice(&self.msg_id.to_le_bytes());
+        header
+    }
+
+    fn from_header(header: &[u8]) -> Option<Self> {
+        if header.len() < FORWARD_HEADER_LEN {
+            return None;
+        }
+        let mut ip = [0u8; 16];
+        ip.copy_from_slice(&header[2..18]);
+        Some(ClientWrite {
+            ip: Ipv6Addr::from(ip),
+            port: u16::from_le_bytes([header[18], header[19]]),
+            msg_id: MsgID::from_le_bytes([header[20], header[21], header[22], header[23]]),
+        })
+    }
+}
+
+/// The last `capacity` writes a backup applied.
+struct DedupeWindow {
+    capacity: usize,
+    applied: HashSet<ClientWrite>,
+    order: VecDeque<ClientWrite>,
+}
+
+impl DedupeWindow {
+    fn new(capacity: usize) -> Self {
+        DedupeWindow {
+            capacity,
+            applied: HashSet::default(),
+            order: VecDeque::default(),
+        }
+    }
+
+    /// Records the write as applied; returns false if it already was.
+    fn insert(&mut self, write: ClientWrite) -> bool {
+        if !self.applied.insert(write) {
+            return false;
+        }
+        self.order.push_back(write);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.applied.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// A client write the primary is waiting on acknowledgements for.
+struct PendingWrite<T> {
+    seq: MsgID,
+    pkt: T,
+    /// Bit i is set once backup i has acknowledged.
+    acked_by: u64,
+    forwarded_at: Instant,
+}
+
+/// Writes the primary has forwarded, in order, with the acknowledgements they collected.
+struct PendingWrites<T> {
+    writes: VecDeque<PendingWrite<T>>,
+}
+
+impl<T> PendingWrites<T> {
+    fn new() -> Self {
+        PendingWrites {
+            writes: VecDeque::default(),
+        }
+    }
+
+    fn push(&mut self, seq: MsgID, pkt: T, forwarded_at: Instant) {
+        self.writes.push_back(PendingWrite {
+            seq,
+            pkt,
+            acked_by: 0,
+            forwarded_at,
+        });
+    }
+
+    /// Records backup `backup`'s acknowledgement of `seq`; returns false if no pending write
+    /// has that sequence number.
+    fn record_ack(&mut self, backup: usize, seq: MsgID) -> bool {
+        match self.writes.iter_mut().find(|write| write.seq == seq) {
+            Some(write) => {
+                write.acked_by |= 1 << backup;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Pops the writes at the front that have `acks` acknowledgements, in the order they were
+    /// forwarded, and drops those at the front forwarded `ack_timeout` or longer before `now`.
+    /// Returns the committed writes and the number dropped.
+    fn release(&mut self, acks: usize, ack_timeout: Duration, now: Instant) -> (Vec<T>, usize) {
+        let mut committed = Vec::default();
+        let mut abandoned = 0;
+        while let Some(write) = self.writes.front() {
+            if write.acked_by.count_ones() as usize >= acks {
+                committed.push(self.writes.pop_front().unwrap().pkt);
+            } else if now.saturating_duration_since(write.forwarded_at) >= ack_timeout {
+                tracing::debug!(seq = write.seq, "Abandoning write that timed out");
+                self.writes.pop_front();
+                abandoned += 1;
+            } else {
+                break;
+            }
+        }
+        (committed, abandoned)
+    }
+
+    /// Drops every pending write; returns how many there were.
+    fn abandon_all(&mut self) -> usize {
+        let num_writes = self.writes.len();
+        self.writes.clear();
+        num_writes
+    }
+}
+
+/// One server's part in replication: forwards and holds back writes on the primary, and fences
+/// off writes that do not come from the primary on backups.
+pub struct Replicator<D>
+where
+    D: Datapath,
+{
+    config_path: PathBuf,
+    /// Datapath config file, for the replicas' MAC addresses and port.
+    datapath_config_file: String,
+    our_ip: IpAddr,
+    config: ReplicationConfig,
+    config_modified: Option<SystemTime>,
+    last_config_poll: Instant,
+    role: Role,
+    /// Connections to the backups, when primary.
+    backups: Vec<ConnID>,
+    /// Connection to the primary, when backup.
+    primary: Option<ConnID>,
+    /// Connections to every other server that has been a replica, so that their packets are
+    /// never mistaken for client requests; responses do not parse as requests.
+    peers: Vec<ConnID>,
+    /// Message id of the next forwarded write; backups answer with it.
+    next_seq: MsgID,
+    pending: PendingWrites<ReceivedPkt<D>>,
+    /// Forwarded writes applied here while a backup.
+    applied: DedupeWindow,
+    stats: ReplicationStats,
+}
+
+impl<D> Replicator<D>
+where
+    D: Datapath,
+{
+    pub fn new(
+        config_path: &str,
+        datapath_config_file: &str,
+        our_ip: IpAddr,
+        datapath: &mut D,
+    ) -> Result<Self> {
+        let config_path = PathBuf::from(config_path);
+        let config = ReplicationConfig::from_file(&config_path)?;
+        if !config.replicas.contains(&our_ip) {
+            bail!(
+                "Server {} is not one of the replicas in {:?}.",
+                our_ip,
+                config_path
+            );
+        }
+        let mut replicator = Replicator {
+            config_modified: fs::metadata(&config_path)?.modified().ok(),
+            config_path,
+            datapath_config_file: datapath_config_file.to_string(),
+            our_ip,
+            config: config.clone(),
+            last_config_poll: Instant::now(),
+            role: Role::Backup,
+            backups: Vec::default(),
+            primary: None,
+            peers: Vec::default(),
+            next_seq: 0,
+            pending: PendingWrites::new(),
+            applied: DedupeWindow::new(DEDUPE_WINDOW),
+            stats: ReplicationStats::default(),
+        };
+        replicator.apply_config(config, datapath)?;
+        Ok(replicator)
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn stats(&self) -> ReplicationStats {
+        self.stats
+    }
+
+    /// Sorts received packets: acknowledgements are consumed, client writes on the primary are
+    /// forwarded and held back, and writes whose acknowledgements are complete are released.
+    /// Returns the packets to process now.
+    pub fn steer(
+        &mut self,
+        pkts: Vec<ReceivedPkt<D>>,
+        datapath: &mut D,
+    ) -> Result<Vec<ReceivedPkt<D>>> {
+        if self.last_config_poll.elapsed() >= CONFIG_POLL_INTERVAL {
+            self.poll_config(datapath)?;
+        }
+        match self.role {
+            Role::Primary => self.steer_primary(pkts, datapath),
+            Role::Backup => self.steer_backup(pkts, datapath),
+        }
+    }
+
+    fn steer_primary(
+        &mut self,
+        pkts: Vec<ReceivedPkt<D>>,
+        datapath: &mut D,
+    ) -> Result<Vec<ReceivedPkt<D>>> {
+        let mut local = Vec::with_capacity(pkts.len());
+        let mut writes = Vec::default();
+        for pkt in pkts.into_iter() {
+            if let Some(backup) = self.backups.iter().position(|c| *c == pkt.conn_id()) {
+                self.record_ack(backup, pkt.msg_id());
+            } else if self.peers.contains(&pkt.conn_id()) {
+                self.stats.stale += 1;
+            } else if is_write(&pkt) {
+                writes.push(pkt);
+            } else {
+                local.push(pkt);
+            }
+        }
+
+        let num_forwards = writes.len() * self.backups.len();
+        let mut forwarded = 0;
+        for pkt in writes.into_iter() {
+            let seq = self.next_seq;
+            self.next_seq = self.next_seq.wrapping_add(1);
+            let client = match datapath.connection_address(pkt.conn_id()) {
+                Some(client) => client,
+                None => bail!("Replication needs a datapath that tracks client addresses."),
+            };
+            let header = ClientWrite::new(&client, pkt.msg_id()).to_header();
+            for backup in self.backups.iter() {
+                forwarded += 1;
+                let header_metadata = match datapath.allocate(FORWARD_HEADER_LEN)? {
+                    Some(mut buf) => {
+                        let _ = buf.write(&header)?;
+                        datapath.get_metadata(buf)?
+                    }
+                    None => None,
+                };
+                let header_metadata = match header_metadata {
+                    Some(metadata) => metadata,
+                    // the backup never acknowledges the write, so it times out like a lost one
+                    None => {
+                        tracing::warn!(seq, "Could not allocate header for forwarded write");
+                        continue;
+                    }
+                };
+                // past the header, the forward references the receive buffers the client's
+                // request landed in
+                let mut metadata_vec = Vec::with_capacity(pkt.num_segs() + 1);
+                metadata_vec.push(header_metadata);
+                metadata_vec.extend(pkt.iter().cloned());
+                datapath.queue_metadata_vec(
+                    seq,
+                    *backup,
+                    metadata_vec,
+                    forwarded == num_forwards,
+                )?;
+            }
+            self.stats.forwarded += 1;
+            self.pending.push(seq, pkt, Instant::now());
+        }
+        self.release(&mut local);
+        Ok(local)
+    }
+
+    fn steer_backup(
+        &mut self,
+        pkts: Vec<ReceivedPkt<D>>,
+        datapath: &mut D,
+    ) -> Result<Vec<ReceivedPkt<D>>> {
+        let mut local = Vec::with_capacity(pkts.len());
+        for mut pkt in pkts.into_iter() {
+            if Some(pkt.conn_id()) == self.primary {
+                let write = match strip_forward_header(&mut pkt)? {
+                    Some(write) => write,
+                    None => {
+                        tracing::warn!(
+                            msg_id = pkt.msg_id(),
+                            "Dropping forward without a client header"
+                        );
+                        self.stats.stale += 1;
+                        continue;
+                    }
+                };
+                if self.applied.insert(write) {
+                    local.push(pkt);
+                } else {
+                    // the primary abandoned an earlier forward of this write, which was
+                    // applied here; acknowledge the retry without applying it again
+                    tracing::debug!(seq = pkt.msg_id(), write =? write, "Acknowledging duplicate write");
+                    self.stats.duplicates += 1;
+                    datapath.queue_single_buffer_with_copy(
+                        (pkt.msg_id(), pkt.conn_id(), &write.to_header()),
+                        true,
+                    )?;
+                }
+            } else if self.peers.contains(&pkt.conn_id()) {
+                self.stats.stale += 1;
+            } else if is_write(&pkt) {
+                // the client times out and retries, by which point it may know the new primary
+                tracing::debug!(
+                    msg_id = pkt.msg_id(),
+                    conn_id = pkt.conn_id(),
+                    "Backup rejecting write"
+                );
+                self.stats.rejected += 1;
+            } else {
+                local.push(pkt);
+            }
+        }
+        Ok(local)
+    }
+
+    fn record_ack(&mut self, backup: usize, seq: MsgID) {
+        match self.pending.record_ack(backup, seq) {
+            true => self.stats.acks += 1,
+            false => self.stats.stale += 1,
+        }
+    }
+
+    /// Moves writes with enough acknowledgements, in the order they were forwarded, to `local`,
+    /// and drops writes at the front that timed out.
+    fn release(&mut self, local: &mut Vec<ReceivedPkt<D>>) {
+        let ack_timeout = Duration::from_millis(self.config.ack_timeout_ms);
+        let (committed, abandoned) =
+            self.pending
+                .release(self.config.acks, ack_timeout, Instant::now());
+        self.stats.committed += committed.len() as u64;
+        self.stats.abandoned += abandoned as u64;
+        local.extend(committed);
+    }
+
+    fn poll_config(&mut self, datapath: &mut D) -> Result<()> {
+        self.last_config_poll = Instant::now();
+        let modified = fs::metadata(&self.config_path)?.modified().ok();
+        if modified == self.config_modified {
+            return Ok(());
+        }
+        self.config_modified = modified;
+        match ReplicationConfig::from_file(&self.config_path) {
+            Ok(config) if config.epoch > self.config.epoch => self.apply_config(config, datapath),
+            Ok(_) => Ok(()),
+            Err(e) => {
+                // the file may be caught mid-write; the next change triggers another read
+                tracing::warn!("Ignoring replication config: {:?}", e);
+                Ok(())
+            }
+        }
+    }
+
+    fn apply_config(&mut self, config: ReplicationConfig, datapath: &mut D) -> Result<()> {
+        self.stats.abandoned += self.pending.abandon_all() as u64;
+        self.backups.clear();
+        self.primary = None;
+        for ip in config.replicas.iter().filter(|ip| **ip != self.our_ip) {
+            let conn_id = datapath.connect(self.replica_address(ip)?)?;
+            if !self.peers.contains(&conn_id) {
+                self.peers.push(conn_id);
+            }
+            if *ip == config.primary_ip() {
+                self.primary = Some(conn_id);
+            } else {
+                self.backups.push(conn_id);
+            }
+        }
+        if config.primary_ip() == self.our_ip {
+            self.role = Role::Primary;
+        } else {
+            if !config.replicas.contains(&self.our_ip) {
+                tracing::warn!(ip =? self.our_ip, epoch = config.epoch, "Server was removed from the replicas; only serving reads");
+            }
+            self.backups.clear();
+            self.role = Role::Backup;
+        }
+        tracing::info!(epoch = config.epoch, role =? self.role, primary =? config.primary_ip(), acks = config.acks, "Applied replication config");
+        self.config = config;
+        Ok(())
+    }
+
+    fn replica_address(&self, ip: &IpAddr) -> Result<AddressInfo> {
+        let (mac, ip, port) = cornflakes_utils::parse_server_addr(&self.datapath_config_file, ip)?;
+        Ok(AddressInfo::from_known_host(port, ip, mac))
+    }
+}
+
+/// Strips the header the primary puts in front of a forwarded write, returning the client write
+/// it names; None if the packet is too short to carry one.
+fn strip_forward_header<D>(pkt: &mut ReceivedPkt<D>) -> Result<Option<ClientWrite>>
+where
+    D: Datapath,
+{
+    if pkt.num_segs() == 0 || pkt.seg(0).data_len() < FORWARD_HEADER_LEN {
+        return Ok(None);
+    }
+    let write = ClientWrite::from_header(pkt.seg(0).as_ref());
+    if let Some(seg) = pkt.iter_mut().next() {
+        let (data_len, offset) = (seg.data_len(), seg.offset());
+        seg.set_data_len_and_offset(data_len - FORWARD_HEADER_LEN, offset + FORWARD_HEADER_LEN)?;
+    }
+    Ok(write)
+}
+
+/// Whether the packet is a request that changes the stores. Packets that do not parse as kv
+/// requests (e.g., no-ops) are left to the server.
+fn is_write<D>(pkt: &ReceivedPkt<D>) -> bool
+where
+    D: Datapath,
+{
+    !pkt.is_noop()
+        && match MsgType::from_packet(pkt) {
+            Ok(msg_type) => msg_type.is_write(),
+            Err(_) => false,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn client_write(ip: IpAddr, port: u16, msg_id: MsgID) -> ClientWrite {
+        ClientWrite::new(&AddressInfo::new(port, ip, Default::default()), msg_id)
+    }
+
+    #[test]
+    fn forward_headers_round_trip() {
+        for ip in [
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)),
+            IpAddr::V6("fe80::1".parse().unwrap()),
+        ] {
+            let write = client_write(ip, 54321, 0xdeadbeef);
+            let header = write.to_header();
+            // forwards must not look stamped for another shard
+            assert_eq!(header[0], 0);
+            assert_eq!(ClientWrite::from_header(&header), Some(write));
+        }
+        assert_eq!(
+            ClientWrite::from_header(&[0u8; FORWARD_HEADER_LEN - 1]),
+            None
+        );
+    }
+
+    #[test]
+    fn clients_are_told_apart_by_address_and_message_id() {
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7));
+        let write = client_write(ip, 1000, 5);
+        assert_ne!(write, client_write(ip, 1001, 5));
+        assert_ne!(write, client_write(ip, 1000, 6));
+        assert_ne!(
+            write,
+            client_write(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 8)), 1000, 5)
+        );
+    }
+
+    #[test]
+    fn dedupe_window_forgets_the_oldest_writes() {
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7));
+        let mut window = DedupeWindow::new(2);
+        assert!(window.insert(client_write(ip, 1000, 1)));
+        assert!(!window.insert(client_write(ip, 1000, 1)));
+        assert!(window.insert(client_write(ip, 1000, 2)));
+        assert!(window.insert(client_write(ip, 1000, 3)));
+        // write 1 fell out of the window; 2 and 3 are still remembered
+        assert!(window.insert(client_write(ip, 1000, 1)));
+        assert!(!window.insert(client_write(ip, 1000, 3)));
+    }
+
+    #[test]
+    fn acknowledged_writes_commit_in_forward_order() {
+        let now = Instant::now();
+        let mut pending = PendingWrites::new();
+        pending.push(0, "a", now);
+        pending.push(1, "b", now);
+        // "b" is fully acknowledged first, but waits for "a"
+        assert!(pending.record_ack(0, 1));
+        assert!(pending.record_ack(1, 1));
+        assert!(pending.record_ack(0, 0));
+        assert_eq!(pending.release(2, TIMEOUT, now), (vec![], 0));
+        assert!(pending.record_ack(1, 0));
+        assert_eq!(pending.release(2, TIMEOUT, now), (vec!["a", "b"], 0));
+        assert!(pending.writes.is_empty());
+        // acknowledgements for writes no longer pending are stale
+        assert!(!pending.record_ack(0, 0));
+    }
+
+    #[test]
+    fn repeated_acks_from_one_backup_count_once() {
+        let now = Instant::now();
+        let mut pending = PendingWrites::new();
+        pending.push(0, "a", now);
+        assert!(pending.record_ack(0, 0));
+        assert!(pending.record_ack(0, 0));
+        assert_eq!(pending.release(2, TIMEOUT, now), (vec![], 0));
+        assert!(pending.record_ack(1, 0));
+        assert_eq!(pending.release(2, TIMEOUT, now), (vec!["a"], 0));
+    }
+
+    #[test]
+    fn unacknowledged_writes_time_out() {
+        let start = Instant::now();
+        let mut pending = PendingWrites::new();
+        pending.push(0, "a", start);
+        pending.push(1, "b", start + TIMEOUT / 2);
+        pending.push(2, "c", start + TIMEOUT);
+        assert!(pending.record_ack(0, 2));
+        assert_eq!(
+            pending.release(1, TIMEOUT, start + TIMEOUT / 2),
+            (vec![], 0)
+        );
+        // "a" times out, then "b" blocks the acknowledged "c" until it times out too
+        assert_eq!(pending.release(1, TIMEOUT, start + TIMEOUT), (vec![], 1));
+        assert_eq!(
+            pending.release(1, TIMEOUT, start + TIMEOUT * 3 / 2),
+            (vec!["c"], 1)
+        );
+        assert!(!pending.record_ack(0, 1));
+    }
+
+    #[test]
+    fn failover_abandons_pending_writes() {
+        let now = Instant::now();
+        let mut pending = PendingWrites::new();
+        pending.push(0, "a", now);
+        pending.push(1, "b", now);
+        assert!(pending.record_ack(0, 0));
+        assert_eq!(pending.abandon_all(), 2);
+        assert!(!pending.record_ack(0, 1));
+        assert_eq!(pending.release(0, TIMEOUT, now), (vec![], 0));
+    }
+
+    #[test]
+    fn writes_without_acks_commit_right_away() {
+        let now = Instant::now();
+        let mut pending = PendingWrites::new();
+        pending.push(0, "a", now);
+        assert_eq!(pending.release(0, TIMEOUT, now), (vec!["a"], 0));
+    }
+}
@@ -1,6 +1,6 @@
 use super::{
-    allocate_datapath_buffer, ClientSerializer, KVServer, LinkedListKVServer, ListKVServer,
-    MsgType, RequestGenerator, ServerLoadGenerator, REQ_TYPE_SIZE,
+    shard::ShardSpec, ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType,
+    RequestGenerator, ServerLoadGenerator, REQ_TYPE_SIZE,
 };
 use color_eyre::eyre::{bail, ensure, Result, WrapErr};
 use cornflakes_libos::{allocator::MempoolID, datapath::Datapath};
@@ -9,7 +9,6 @@ use rand::{
     distributions::{Alphanumeric, Distribution, Uniform, WeightedIndex},
     thread_rng, Rng,
 };
-use std::io::Write;
 use zipf::ZipfDistribution;
 
 pub const ADD_USER_GETS: usize = 1;
@@ -200,6 +199,7 @@ pub struct RetwisServerLoader {
     num_keys: usize,
     key_length: usize,
     value_size_generator: RetwisValueSizeGenerator,
+    shard: Option<ShardSpec>,
 }
 
 impl RetwisServerLoader {
@@ -212,8 +212,14 @@ impl RetwisServerLoader {
             num_keys: num_keys,
             key_length: key_length,
             value_size_generator: value_size_generator,
+            shard: None,
         }
     }
+
+    /// Only load keys the given shard owns.
+    pub fn set_shard(&mut self, shard: ShardSpec) {
+        self.shard = Some(shard);
+    }
 }
 
 impl ServerLoadGenerator for RetwisServerLoader {
@@ -296,14 +302,22 @@ impl ServerLoadGenerator for RetwisServerLoader {
     where
         D: Datapath,
     {
+        if let Some(shard) = &self.shard {
+            if !shard.owns(request.key()) {
+                return Ok(());
+            }
+        }
         let char = thread_rng().sample(&Alphanumeric) as char;
         let value: String = std::iter::repeat(char).take(request.value_size).collect();
-        let mut datapath_buffer = allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
-        let _ = datapath_buffer.write(value.as_bytes())?;
         if use_linked_list_kv_server {
-            linked_list_kv_server.insert(request.key().to_string(), datapath_buffer);
+            linked_list_kv_server.append_with_copies(
+                request.key(),
+                value.as_bytes(),
+                datapath,
+                mempool_ids,
+            )?;
         } else {
-            kv_server.insert(request.key().to_string(), datapath_buffer);
+            kv_server.insert_with_copies(request.key(), value.as_bytes(), datapath, mempool_ids)?;
         }
         Ok(())
     }
@@ -311,6 +325,7 @@ impl ServerLoadGenerator for RetwisServerLoader {
 
 #[derive(Debug, Clone)]
 pub struct RetwisClient {
+    zipf_coefficient: f64,
     zipf_distribution: ZipfDistribution,
     value_generator: RetwisValueSizeGenerator,
     request_generator: WeightedIndex<usize>,
@@ -356,6 +371,7 @@ impl RetwisClient {
         let zipf = ZipfDistribution::new(keys.len(), zipf_coefficient).unwrap();
         tracing::info!("Finished initializing zipf");
         Ok(RetwisClient {
+            zipf_coefficient,
             zipf_distribution: zipf,
             value_generator: value_generator,
             keys: keys,
@@ -375,9 +391,24 @@ impl RetwisClient {
     }
 
     pub fn set_zipf(&mut self, zipf_coefficient: f64) {
+        self.zipf_coefficient = zipf_coefficient;
         self.zipf_distribution = ZipfDistribution::new(self.keys.len(), zipf_coefficient).unwrap();
     }
 
+    /// Only draws keys the given shard owns, so that no request spans shards. Keys keep their
+    /// relative popularity within the shard.
+    pub fn set_shard(&mut self, shard: ShardSpec) -> Result<()> {
+        self.keys.retain(|key| shard.owns(key));
+        ensure!(
+            !self.keys.is_empty(),
+            "Shard {} of {} owns none of the keys",
+            shard.shard_id,
+            shard.num_shards
+        );
+        self.set_zipf(self.zipf_coefficient);
+        Ok(())
+    }
+
     pub fn set_value_generator(&mut self, gen: RetwisValueSizeGenerator) {
         self.value_generator = gen;
     }
@@ -1,41 +1,104 @@
-use super::retwis::{RetwisRequestDistribution, RetwisValueSizeGenerator};
+use super::{
+    retwis::{RetwisRequestDistribution, RetwisValueSizeGenerator},
+    shard::check_num_shards,
+};
 use color_eyre::eyre::{bail, Result};
 use cornflakes_libos::{
+    admission::AdmissionConfig,
     datapath::{InlineMode, PushBufType},
     loadgen::request_schedule::DistributionType,
 };
-use cornflakes_utils::{AppMode, SerializationType, TraceLevel};
-use std::net::Ipv4Addr;
+use cornflakes_utils::{AppMode, CopyingThreshold, SerializationType, TraceLevel};
+use std::net::IpAddr;
 use structopt::StructOpt;
 
 #[macro_export]
 macro_rules! run_server_retwis(
     ($kv_server: ty, $datapath: ty, $opt: ident) => {
+        if $opt.num_shards > 1 {
+            $crate::run_sharded_server_retwis!($kv_server, $datapath, $opt);
+            return Ok(());
+        }
         let is_baseline = is_baseline(&$opt);
-        cornflakes_libos::datapath::set_mempool_params($opt.num_pages_per_mempool, $opt.num_registrations, !$opt.do_not_register_at_start);
         let mut datapath_params = <$datapath as Datapath>::parse_config_file(&$opt.config_file, &$opt.server_ip)?;
         let addresses = <$datapath as Datapath>::compute_affinity(&datapath_params, 1, None, AppMode::Server)?;
         let per_thread_contexts = <$datapath as Datapath>::global_init(1, &mut datapath_params, addresses)?;
         let mut connection = <$datapath as Datapath>::per_thread_init(datapath_params, per_thread_contexts.into_iter().nth(0).unwrap(),
         AppMode::Server)?;
+        connection.set_mempool_config(cornflakes_libos::allocator::MempoolConfig {
+            num_pages: $opt.num_pages_per_mempool,
+            num_registrations: $opt.num_registrations,
+            register_at_start: !$opt.do_not_register_at_start,
+            ..Default::default()
+        })?;
 
-        connection.set_copying_threshold($opt.copying_threshold);
+        connection.set_copying_threshold($opt.copying_threshold.thresh());
         connection.set_inline_mode($opt.inline_mode);
-        tracing::info!(threshold = $opt.copying_threshold, "Setting zero-copy copying threshold");
+        tracing::info!(threshold = $opt.copying_threshold.thresh(), "Setting zero-copy copying threshold");
 
         // init retwis load generator
         let load_generator = RetwisServerLoader::new($opt.num_keys, $opt.key_size, $opt.value_size_generator);
         let mut kv_server = <$kv_server>::new("", load_generator, &mut connection, $opt.push_buf_type, false)?;
         kv_server.init(&mut connection)?;
         kv_server.write_ready($opt.ready_file.clone())?;
+        let admission_controller = admission_config(&$opt).map(cornflakes_libos::admission::AdmissionController::new);
         if is_baseline {
-            kv_server.run_state_machine_baseline(&mut connection)?;
+            kv_server.run_state_machine_baseline_with_admission_control(&mut connection, admission_controller)?;
+        } else if $opt.copying_threshold.is_adaptive() {
+            tracing::info!("Tuning copying threshold online");
+            kv_server.run_state_machine_with_controllers(&mut connection, Some(cornflakes_libos::adaptive_threshold::AdaptiveThresholdController::default()), admission_controller)?;
         } else {
-            kv_server.run_state_machine(&mut connection)?;
+            kv_server.run_state_machine_with_controllers(&mut connection, None, admission_controller)?;
         }
     }
 );
 
+/// Runs one server thread per shard; see `run_sharded_server`.
+#[macro_export]
+macro_rules! run_sharded_server_retwis(
+    ($kv_server: ty, $datapath: ty, $opt: ident) => {
+        let num_shards = $opt.num_shards;
+        let mut datapath_params = <$datapath as Datapath>::parse_config_file(&$opt.config_file, &$opt.server_ip)?;
+        let addresses = <$datapath as Datapath>::compute_affinity(&datapath_params, num_shards, None, AppMode::Server)?;
+        let per_thread_contexts = <$datapath as Datapath>::global_init(num_shards, &mut datapath_params, addresses)?;
+        let opt_clone = $opt.clone();
+        $crate::shard::run_shards(per_thread_contexts, $opt.forward_misrouted, move |per_thread_context, router, loaded| {
+            let is_baseline = is_baseline(&opt_clone);
+            let mut connection = <$datapath as Datapath>::per_thread_init(datapath_params, per_thread_context, AppMode::Server)?;
+            connection.set_mempool_config(cornflakes_libos::allocator::MempoolConfig {
+                num_pages: opt_clone.num_pages_per_mempool,
+                num_registrations: opt_clone.num_registrations,
+                register_at_start: !opt_clone.do_not_register_at_start,
+                ..Default::default()
+            })?;
+            connection.set_copying_threshold(opt_clone.copying_threshold.thresh());
+            connection.set_inline_mode(opt_clone.inline_mode);
+
+            let shard = router.spec();
+            tracing::info!(shard = shard.shard_id, num_shards = shard.num_shards, "Loading server shard");
+            let mut load_generator = RetwisServerLoader::new(opt_clone.num_keys, opt_clone.key_size, opt_clone.value_size_generator.clone());
+            load_generator.set_shard(shard);
+            let mut kv_server = <$kv_server>::new("", load_generator, &mut connection, opt_clone.push_buf_type, false)?;
+            kv_server.set_shard_router(router);
+            kv_server.init(&mut connection)?;
+            // the ready file is written once every shard has loaded its part of the keys
+            loaded.wait();
+            if shard.shard_id == 0 {
+                kv_server.write_ready(opt_clone.ready_file.clone())?;
+            }
+            let admission_controller = admission_config(&opt_clone).map(cornflakes_libos::admission::AdmissionController::new);
+            if is_baseline {
+                kv_server.run_state_machine_baseline_with_admission_control(&mut connection, admission_controller)?;
+            } else if opt_clone.copying_threshold.is_adaptive() {
+                kv_server.run_state_machine_with_controllers(&mut connection, Some(cornflakes_libos::adaptive_threshold::AdaptiveThresholdController::default()), admission_controller)?;
+            } else {
+                kv_server.run_state_machine_with_controllers(&mut connection, None, admission_controller)?;
+            }
+            Ok(())
+        })?;
+    }
+);
+
 #[macro_export]
 macro_rules! run_client_retwis(
     ($serializer: ty, $datapath: ty, $opt: ident) => {
@@ -65,9 +128,11 @@ macro_rules! run_client_retwis(
             .zip(per_thread_contexts.into_iter())
             .enumerate()
         {
+            // each thread sends to one shard, at the port that shard's queue listens on
+            let shard = i % $opt.num_shards;
         let thread_keys = retwis_keys.clone();
         let server_addr_clone =
-            cornflakes_libos::utils::AddressInfo::new(server_addr.2, server_addr.1.clone(), server_addr.0.clone());
+            cornflakes_libos::utils::AddressInfo::from_known_host(<$datapath as Datapath>::server_queue_port(server_addr.2, shard), server_addr.1.clone(), server_addr.0);
             let datapath_params_clone = datapath_params.clone();
 
             let max_num_requests = num_rtts;
@@ -96,10 +161,16 @@ macro_rules! run_client_retwis(
                 tracing::info!("Finished initializing datapath connection for thread {}", i);
                 let size = opt_clone.value_size_generator.avg_size();
                 let mut retwis_client = RetwisClient::new(thread_keys, opt_clone.zipf, opt_clone.value_size_generator, opt_clone.retwis_distribution)?;
+                if opt_clone.num_shards > 1 {
+                    retwis_client.set_shard($crate::shard::ShardSpec::new(shard, opt_clone.num_shards))?;
+                }
                 tracing::info!("Finished initializing retwis client");
 
                 let mut server_load_generator_opt: Option<(&str, RetwisServerLoader)> = None;
                 let mut kv_client: KVClient<RetwisClient, $serializer, $datapath> = KVClient::new(retwis_client, server_addr_clone, max_num_requests,opt_clone.retries, server_load_generator_opt)?;
+                if opt_clone.num_shards > 1 {
+                    kv_client.set_shard(shard);
+                }
 
 
                 kv_client.init(&mut connection)?;
@@ -136,6 +207,15 @@ fn is_cf(opt: &RetwisOpt) -> bool {
         || opt.serialization == SerializationType::CornflakesOneCopyDynamic
 }
 
+/// Admission control settings for each server thread, if rate limiting or load shedding is on.
+pub fn admission_config(opt: &RetwisOpt) -> Option<AdmissionConfig> {
+    AdmissionConfig::from_flags(
+        opt.admission_rate,
+        opt.admission_burst,
+        opt.admission_queue_depth,
+    )
+}
+
 pub fn is_baseline(opt: &RetwisOpt) -> bool {
     !(opt.serialization == SerializationType::CornflakesOneCopyDynamic
         || opt.serialization == SerializationType::CornflakesDynamic)
@@ -146,9 +226,21 @@ pub fn check_opt(opt: &mut RetwisOpt) -> Result<()> {
         bail!("For non-cornflakes serialization, push buf type must be single buffer.");
     }
 
+    // the baseline and one-copy servers do not pick between copying and zero-copy
+    if opt.copying_threshold.is_adaptive()
+        && (is_baseline(opt) || opt.serialization == SerializationType::CornflakesOneCopyDynamic)
+    {
+        bail!("An adaptive copying threshold is only supported for CornflakesDynamic.");
+    }
+
+    check_num_shards(opt.num_shards)?;
+    if opt.num_shards > 1 && opt.serialization == SerializationType::Redis {
+        bail!("Redis requests do not carry a cf-kv header, so they cannot be sharded.");
+    }
+
     if opt.serialization == SerializationType::CornflakesOneCopyDynamic {
         // copy all segments
-        opt.copying_threshold = usize::MAX;
+        opt.copying_threshold = CopyingThreshold::new(usize::MAX);
     }
 
     Ok(())
@@ -190,10 +282,10 @@ pub struct RetwisOpt {
     pub inline_mode: InlineMode,
     #[structopt(
         long = "copy_threshold",
-        help = "Datapath copy threshold. Copies everything below this threshold. If set to 0, tries to use zero-copy for everything. If set to infinity, uses zero-copy for nothing.",
+        help = "Datapath copy threshold. Copies everything below this threshold. If set to 0, tries to use zero-copy for everything. If set to infinity, uses zero-copy for nothing. If set to adaptive, tunes the threshold online.",
         default_value = "256"
     )]
-    pub copying_threshold: usize,
+    pub copying_threshold: CopyingThreshold,
     #[structopt(
         short = "r",
         long = "rate",
@@ -206,9 +298,9 @@ pub struct RetwisOpt {
         help = "Server ip address",
         default_value = "127.0.0.1"
     )]
-    pub server_ip: Ipv4Addr,
+    pub server_ip: IpAddr,
     #[structopt(long = "our_ip", help = "Our ip address", default_value = "127.0.0.1")]
-    pub our_ip: Ipv4Addr,
+    pub our_ip: IpAddr,
     #[structopt(
         long = "serialization",
         help = "Serialization library to use",
@@ -283,4 +375,33 @@ pub struct RetwisOpt {
         help = "Register mempool memory at start"
     )]
     pub do_not_register_at_start: bool,
+    #[structopt(
+        long = "num_shards",
+        help = "Number of server shards (cores), each owning part of the keyspace.",
+        default_value = "1"
+    )]
+    pub num_shards: usize,
+    #[structopt(
+        long = "forward_misrouted",
+        help = "Forward requests that reach the wrong server shard to the owning shard; with false, shards serve them from their own stores.",
+        parse(try_from_str),
+        default_value = "true"
+    )]
+    pub forward_misrouted: bool,
+    #[structopt(
+        long = "admission_rate",
+        help = "Server: requests per second admitted from each client connection; requests above the rate are shed."
+    )]
+    pub admission_rate: Option<f64>,
+    #[structopt(
+        long = "admission_burst",
+        help = "Server: requests a client connection may send back to back above --admission_rate.",
+        default_value = "32"
+    )]
+    pub admission_burst: u64,
+    #[structopt(
+        long = "admission_queue_depth",
+        help = "Server: admitted requests that may wait to be processed (per shard); requests arriving beyond it are shed with an overloaded reply."
+    )]
+    pub admission_queue_depth: Option<usize>,
 }
@@ -0,0 +1,370 @@
+//! Partitioning the keyspace across per-core server shards.
+//!
+//! Each shard is a server thread with its own datapath queue and its own stores, so shards never
+//! share state. Clients pin each thread to one shard, only send it requests whose (first) key the
+//! shard owns, and address it at `Datapath::server_queue_port`. Datapaths that steer by port
+//! deliver those requests straight to the owning queue; datapaths that spread flows with RSS may
+//! not, so clients also stamp the owning shard into the request header and a shard can forward
+//! requests it does not own to their owner over an in-process channel.
+use color_eyre::eyre::{bail, Result};
+use cornflakes_libos::{
+    datapath::{Datapath, ReceivedPkt},
+    utils::AddressInfo,
+    MsgID,
+};
+use std::{
+    io::Write,
+    sync::{
+        mpsc::{channel, Receiver, Sender, TryRecvError},
+        Arc, Barrier,
+    },
+};
+
+/// Byte of the request header that carries the shard hint: the high byte of the message type,
+/// which `MsgType::from_packet` ignores.
+const SHARD_HINT_OFFSET: usize = 0;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Shard that owns the key. Uses FNV-1a so that clients and servers agree regardless of build.
+pub fn shard_for_key(key: &str, num_shards: usize) -> usize {
+    let mut hash = FNV_OFFSET_BASIS;
+    for byte in key.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    (hash % num_shards as u64) as usize
+}
+
+/// Stamps the destination shard into a serialized request. The hint is stored off by one so
+/// that requests from clients that do not shard (hint 0) are processed wherever they land.
+pub fn set_shard_hint(buf: &mut [u8], shard: usize) {
+    buf[SHARD_HINT_OFFSET] = (shard + 1) as u8;
+}
+
+/// Shard the request was stamped for, if any.
+pub fn shard_hint(buf: &[u8]) -> Option<usize> {
+    match buf[SHARD_HINT_OFFSET] {
+        0 => None,
+        hint => Some(hint as usize - 1),
+    }
+}
+
+/// Shard hints are carried in a single byte of the request header, which caps the shard count.
+pub fn check_num_shards(num_shards: usize) -> Result<()> {
+    if num_shards == 0 || num_shards > u8::MAX as usize - 1 {
+        bail!(
+            "Number of shards must be between 1 and {}, got {}.",
+            u8::MAX - 1,
+            num_shards
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardSpec {
+    pub shard_id: usize,
+    pub num_shards: usize,
+}
+
+impl ShardSpec {
+    pub fn new(shard_id: usize, num_shards: usize) -> Self {
+        ShardSpec {
+            shard_id,
+            num_shards,
+        }
+    }
+
+    pub fn owns(&self, key: &str) -> bool {
+        shard_for_key(key, self.num_shards) == self.shard_id
+    }
+
+    /// Where a client thread stands among the threads, across all clients, that send to this
+    /// shard: returns its index and the number of such threads. Clients pin thread `thread_id`
+    /// to shard `thread_id % num_shards`, so the thread must be one of this shard's.
+    pub fn sender_index(
+        &self,
+        client_id: usize,
+        thread_id: usize,
+        total_num_clients: usize,
+        total_num_threads: usize,
+    ) -> (usize, usize) {
+        let threads_per_client = (total_num_threads - self.shard_id).div_ceil(self.num_shards);
+        (
+            client_id * threads_per_client + thread_id / self.num_shards,
+            threads_per_client * total_num_clients,
+        )
+    }
+}
+
+/// Runs a copy of `run_shard` for each shard, on its own thread pinned to core `shard_id + 1`, with
+/// the shard's datapath context and router, and waits for every shard to exit. Shards reach
+/// the barrier once they have loaded their part of the trace, so one of them can then tell
+/// clients the server is ready.
+pub fn run_shards<C, F>(per_thread_contexts: Vec<C>, forward: bool, run_shard: F) -> Result<()>
+where
+    C: Send + 'static,
+    F: FnOnce(C, ShardRouter, &Barrier) -> Result<()> + Send + Clone + 'static,
+{
+    let num_shards = per_thread_contexts.len();
+    let routers = ShardRouter::mesh(num_shards, forward);
+    let loaded = Arc::new(Barrier::new(num_shards));
+    let mut threads: Vec<std::thread::JoinHandle<Result<()>>> = vec![];
+    for (i, (per_thread_context, router)) in
+        per_thread_contexts.into_iter().zip(routers).enumerate()
+    {
+        let loaded = loaded.clone();
+        let run_shard = run_shard.clone();
+        threads.push(std::thread::spawn(move || {
+            match affinity::set_thread_affinity(&vec![i + 1]) {
+                Ok(_) => {}
+                Err(e) => {
+                    bail!(
+                        "Could not set thread affinity for shard {} on core {}: {:?}",
+                        i,
+                        i + 1,
+                        e
+                    )
+                }
+            }
+            run_shard(per_thread_context, router, &loaded)
+        }));
+    }
+
+    for child in threads {
+        match child.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                tracing::warn!("Shard failed: {:?}", e);
+                bail!("Failed shard");
+            }
+            Err(e) => {
+                tracing::warn!("Failed to join shard thread: {:?}", e);
+                bail!("Failed to join thread");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ShardStats {
+    /// Requests received for another shard and forwarded to it.
+    pub forwarded: u64,
+    /// Requests other shards forwarded to this one.
+    pub received_forwarded: u64,
+    /// Requests received for another shard but processed here, because forwarding is off or
+    /// the client's address is unknown.
+    pub misrouted: u64,
+}
+
+impl ShardStats {
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            forwarded = self.forwarded,
+            received_forwarded = self.received_forwarded,
+            misrouted = self.misrouted,
+            "{}",
+            msg
+        );
+    }
+}
+
+/// A request copied out of another shard's receive buffers.
+#[derive(Debug)]
+struct ForwardedRequest {
+    msg_id: MsgID,
+    client: AddressInfo,
+    data: Vec<u8>,
+}
+
+/// One shard's view of the others: where it forwards requests it does not own and where it
+/// picks up requests forwarded to it.
+pub struct ShardRouter {
+    spec: ShardSpec,
+    forward: bool,
+    peers: Vec<Sender<ForwardedRequest>>,
+    inbox: Receiver<ForwardedRequest>,
+    stats: ShardStats,
+}
+
+impl ShardRouter {
+    /// Creates a router for each of `num_shards` shards, all connected to each other.
+    /// With `forward` off, shards process misrouted requests against their own stores.
+    pub fn mesh(num_shards: usize, forward: bool) -> Vec<ShardRouter> {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..num_shards).map(|_| channel()).unzip();
+        receivers
+            .into_iter()
+            .enumerate()
+            .map(|(shard_id, inbox)| ShardRouter {
+                spec: ShardSpec::new(shard_id, num_shards),
+                forward,
+                peers: senders.clone(),
+                inbox,
+                stats: ShardStats::default(),
+            })
+            .collect()
+    }
+
+    pub fn spec(&self) -> ShardSpec {
+        self.spec
+    }
+
+    pub fn stats(&self) -> ShardStats {
+        self.stats
+    }
+
+    /// Forwards received packets stamped for another shard and appends requests forwarded to
+    /// this shard, copied into buffers from this shard's datapath.
+    pub fn steer<D>(
+        &mut self,
+        pkts: Vec<ReceivedPkt<D>>,
+        datapath: &mut D,
+    ) -> Result<Vec<ReceivedPkt<D>>>
+    where
+        D: Datapath,
+    {
+        let mut local = Vec::with_capacity(pkts.len());
+        for pkt in pkts.into_iter() {
+            let owner = match pkt.is_noop() {
+                true => None,
+                false => shard_hint(pkt.seg(0).as_ref()),
+            };
+            match owner {
+                Some(owner) if owner != self.spec.shard_id && owner < self.spec.num_shards => {
+                    match (self.forward, datapath.connection_address(pkt.conn_id())) {
+                        (true, Some(client)) => {
+                            let mut data = Vec::with_capacity(pkt.data_len());
+                            for i in 0..pkt.num_segs() {
+                                data.extend_from_slice(pkt.seg(i).as_ref());
+                            }
+                            // the owner only stops listening when the whole server exits
+                            let _ = self.peers[owner].send(ForwardedRequest {
+                                msg_id: pkt.msg_id(),
+                                client,
+                                data,
+                            });
+                            self.stats.forwarded += 1;
+                        }
+                        _ => {
+                            self.stats.misrouted += 1;
+                            local.push(pkt);
+                        }
+                    }
+                }
+                _ => local.push(pkt),
+            }
+        }
+
+        loop {
+            let request = match self.inbox.try_recv() {
+                Ok(request) => request,
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+            };
+            let conn_id = datapath.connect(request.client)?;
+            let metadata = match datapath.allocate(request.data.len())? {
+                Some(mut buf) => {
+                    let _ = buf.write(request.data.as_slice())?;
+                    datapath.get_metadata(buf)?
+                }
+                None => None,
+            };
+            match metadata {
+                Some(metadata) => {
+                    self.stats.received_forwarded += 1;
+                    local.push(ReceivedPkt::new(vec![metadata], request.msg_id, conn_id));
+                }
+                None => {
+                    // the client retries or times out the request
+                    tracing::warn!(
+                        msg_id = request.msg_id,
+                        len = request.data.len(),
+                        "Could not allocate buffer for forwarded request"
+                    );
+                }
+            }
+        }
+        Ok(local)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shard_for_key_is_fnv_1a() {
+        // reference FNV-1a 64-bit hashes
+        assert_eq!(
+            shard_for_key("", usize::MAX),
+            (FNV_OFFSET_BASIS % usize::MAX as u64) as usize
+        );
+        let hash_of_a: u64 = 0xaf63dc4c8601ec8c;
+        for num_shards in 1..=8 {
+            assert_eq!(
+                shard_for_key("a", num_shards),
+                (hash_of_a % num_shards as u64) as usize
+            );
+        }
+    }
+
+    #[test]
+    fn every_key_has_one_owner() {
+        let num_shards = 4;
+        let specs: Vec<ShardSpec> = (0..num_shards)
+            .map(|shard_id| ShardSpec::new(shard_id, num_shards))
+            .collect();
+        let mut owned = vec![0usize; num_shards];
+        for i in 0..10000 {
+            let key = format!("key_{}", i);
+            let owners: Vec<usize> = specs
+                .iter()
+                .filter(|spec| spec.owns(&key))
+                .map(|spec| spec.shard_id)
+                .collect();
+            assert_eq!(owners, vec![shard_for_key(&key, num_shards)]);
+            owned[owners[0]] += 1;
+        }
+        // the hash spreads keys roughly evenly
+        for count in owned {
+            assert!(count > 2000 && count < 3000, "{}", count);
+        }
+    }
+
+    #[test]
+    fn shard_hints_round_trip() {
+        let mut buf = [0u8; 4];
+        assert_eq!(shard_hint(&buf), None);
+        for shard in [0, 1, 7, u8::MAX as usize - 1] {
+            set_shard_hint(&mut buf, shard);
+            assert_eq!(shard_hint(&buf), Some(shard));
+        }
+        // the hint only takes up its own byte
+        assert_eq!(&buf[1..], &[0u8; 3]);
+    }
+
+    #[test]
+    fn sender_indices_cover_each_shard() {
+        let (num_shards, total_num_clients, total_num_threads) = (3, 2, 7);
+        for shard_id in 0..num_shards {
+            let spec = ShardSpec::new(shard_id, num_shards);
+            let mut indices = vec![];
+            for client_id in 0..total_num_clients {
+                for thread_id in (shard_id..total_num_threads).step_by(num_shards) {
+                    let (index, num_senders) = spec.sender_index(
+                        client_id,
+                        thread_id,
+                        total_num_clients,
+                        total_num_threads,
+                    );
+                    assert!(index < num_senders);
+                    indices.push(index);
+                }
+            }
+            indices.sort();
+            assert_eq!(indices, (0..indices.len()).collect::<Vec<_>>());
+        }
+    }
+}
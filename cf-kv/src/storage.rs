@@ -0,0 +1,210 @@
+//! Where the kv stores keep their values.
+//!
+//! Values shorter than a store's inline threshold live on the heap: responses copy values that
+//! small anyway, so pinning a registered buffer for them only wastes mempool memory. Larger
+//! values live in registered buffers so responses can send them zero-copy. The threshold is
+//! applied when a value is inserted; values already stored keep their representation.
+
+use super::allocate_datapath_buffer;
+use color_eyre::eyre::Result;
+use cornflakes_libos::{allocator::MempoolID, datapath::Datapath};
+use std::{io::Write, ops::Add};
+
+pub enum StoredValue<D>
+where
+    D: Datapath,
+{
+    /// Copied out of the datapath; sent by copying into the response.
+    Inline(Box<[u8]>),
+    /// Registered datapath buffer; sent zero-copy.
+    Registered(D::DatapathBuffer),
+}
+
+impl<D> Default for StoredValue<D>
+where
+    D: Datapath,
+{
+    fn default() -> Self {
+        StoredValue::Inline(Box::default())
+    }
+}
+
+impl<D> AsRef<[u8]> for StoredValue<D>
+where
+    D: Datapath,
+{
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            StoredValue::Inline(data) => data.as_ref(),
+            StoredValue::Registered(buf) => buf.as_ref(),
+        }
+    }
+}
+
+impl<D> StoredValue<D>
+where
+    D: Datapath,
+{
+    /// Copies `value` onto the heap if it is shorter than `inline_threshold`, or into a newly
+    /// allocated registered buffer otherwise.
+    pub fn with_copies(
+        value: &[u8],
+        inline_threshold: usize,
+        datapath: &mut D,
+        mempool_ids: &mut Vec<MempoolID>,
+    ) -> Result<Self> {
+        if value.len() < inline_threshold {
+            return Ok(StoredValue::Inline(value.into()));
+        }
+        let mut datapath_buffer = allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
+        let _ = datapath_buffer.write(value)?;
+        Ok(StoredValue::Registered(datapath_buffer))
+    }
+
+    /// Keeps a buffer the caller allocated, unless it is shorter than `inline_threshold`, in
+    /// which case its contents are copied onto the heap and the buffer is released.
+    pub fn from_buffer(buf: D::DatapathBuffer, inline_threshold: usize) -> Self {
+        match buf.as_ref().len() < inline_threshold {
+            true => StoredValue::Inline(buf.as_ref().into()),
+            false => StoredValue::Registered(buf),
+        }
+    }
+
+    pub fn is_inline(&self) -> bool {
+        matches!(self, StoredValue::Inline(_))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StorageStats {
+    /// Values held on the heap because they were under the inline threshold.
+    pub inline_values: u64,
+    pub inline_bytes: u64,
+    /// Values held in registered buffers.
+    pub registered_values: u64,
+    pub registered_bytes: u64,
+}
+
+impl StorageStats {
+    pub fn record_insert<D>(&mut self, value: &StoredValue<D>)
+    where
+        D: Datapath,
+    {
+        let len = value.as_ref().len() as u64;
+        match value.is_inline() {
+            true => {
+                self.inline_values += 1;
+                self.inline_bytes += len;
+            }
+            false => {
+                self.registered_values += 1;
+                self.registered_bytes += len;
+            }
+        }
+    }
+
+    pub fn record_remove<D>(&mut self, value: &StoredValue<D>)
+    where
+        D: Datapath,
+    {
+        let len = value.as_ref().len() as u64;
+        match value.is_inline() {
+            true => {
+                self.inline_values -= 1;
+                self.inline_bytes -= len;
+            }
+            false => {
+                self.registered_values -= 1;
+                self.registered_bytes -= len;
+            }
+        }
+    }
+
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            inline_values = self.inline_values,
+            inline_bytes = self.inline_bytes,
+            registered_values = self.registered_values,
+            registered_bytes = self.registered_bytes,
+            "{}",
+            msg
+        );
+    }
+}
+
+impl Add for StorageStats {
+    type Output = StorageStats;
+
+    fn add(self, other: StorageStats) -> StorageStats {
+        StorageStats {
+            inline_values: self.inline_values + other.inline_values,
+            inline_bytes: self.inline_bytes + other.inline_bytes,
+            registered_values: self.registered_values + other.registered_values,
+            registered_bytes: self.registered_bytes + other.registered_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use linux_datapath::datapath::connection::{LinuxConnection, MutableByteBuffer};
+
+    type Value = StoredValue<LinuxConnection>;
+
+    pub(crate) fn buffer(data: &[u8]) -> MutableByteBuffer {
+        let mut buf = MutableByteBuffer::new_with_capacity(data.len());
+        buf.write_all(data).unwrap();
+        buf
+    }
+
+    #[test]
+    fn buffers_under_the_threshold_move_onto_the_heap() {
+        let value = Value::from_buffer(buffer(b"small"), 6);
+        assert!(value.is_inline());
+        assert_eq!(value.as_ref(), b"small");
+        let value = Value::from_buffer(buffer(b"larger"), 6);
+        assert!(!value.is_inline());
+        assert_eq!(value.as_ref(), b"larger");
+        // the default threshold keeps every buffer
+        assert!(!Value::from_buffer(buffer(b""), 0).is_inline());
+    }
+
+    #[test]
+    fn stats_count_values_where_they_live() {
+        let small = Value::from_buffer(buffer(b"abc"), 4);
+        let large = Value::from_buffer(buffer(b"abcdefgh"), 4);
+        let mut stats = StorageStats::default();
+        stats.record_insert(&small);
+        stats.record_insert(&large);
+        stats.record_insert(&large);
+        assert_eq!(
+            stats,
+            StorageStats {
+                inline_values: 1,
+                inline_bytes: 3,
+                registered_values: 2,
+                registered_bytes: 16,
+            }
+        );
+        stats.record_remove(&small);
+        stats.record_remove(&large);
+        let one_large = StorageStats {
+            registered_values: 1,
+            registered_bytes: 8,
+            ..StorageStats::default()
+        };
+        assert_eq!(stats, one_large);
+        let mut other = StorageStats::default();
+        other.record_insert(&small);
+        assert_eq!(
+            stats + other,
+            StorageStats {
+                inline_values: 1,
+                inline_bytes: 3,
+                ..one_large
+            }
+        );
+    }
+}
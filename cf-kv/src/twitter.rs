@@ -1,8 +1,8 @@
 use super::{
-    allocate_datapath_buffer, ClientSerializer, KVServer, LinkedListKVServer, ListKVServer,
-    MsgType, RequestGenerator, ServerLoadGenerator, REQ_TYPE_SIZE,
+    shard::ShardSpec, ClientSerializer, KVServer, LinkedListKVServer, ListKVServer, MsgType,
+    RequestGenerator, ResponseStatus, ServerLoadGenerator, REQ_TYPE_SIZE,
 };
-use color_eyre::eyre::{bail, Result};
+use color_eyre::eyre::{bail, ensure, Result};
 use cornflakes_libos::{
     allocator::MempoolID,
     datapath::Datapath,
@@ -294,6 +294,8 @@ pub struct TwitterLineMetadata {
 }
 
 impl TwitterLineMetadata {
+    /// With a `shard`, only lines whose key the shard owns are kept, split among the client
+    /// threads pinned to it.
     pub fn new(
         line: &str,
         client_id: usize,
@@ -303,6 +305,7 @@ impl TwitterLineMetadata {
         val_index: usize,
         value_size: Option<usize>,
         ignore_sets: bool,
+        shard: Option<&ShardSpec>,
     ) -> Result<Option<(Self, usize)>> {
         // parse the comma separated line
         let parts = line.split(",").collect::<Vec<&str>>();
@@ -317,16 +320,24 @@ impl TwitterLineMetadata {
             return Ok(None);
         }
         let line_id = parts[4].parse::<usize>()?;
-        if !is_responsible_for(
-            line_id,
-            client_id,
-            thread_id,
-            total_num_clients,
-            total_num_threads,
-        ) {
+        let k = parts[1];
+        let responsible = match shard {
+            Some(shard) => {
+                let (our_index, num_senders) =
+                    shard.sender_index(client_id, thread_id, total_num_clients, total_num_threads);
+                line_id % num_senders == our_index && shard.owns(k)
+            }
+            None => is_responsible_for(
+                line_id,
+                client_id,
+                thread_id,
+                total_num_clients,
+                total_num_threads,
+            ),
+        };
+        if !responsible {
             return Ok(None);
         }
-        let k = parts[1];
         let mut v_size = parts[3].parse::<usize>()?;
         if let Some(x) = value_size {
             v_size = x;
@@ -387,6 +398,8 @@ pub struct TwitterClient {
     ignore_sets: bool,
     // ignore packets per second,
     ignore_pps: bool,
+    // server shard this thread sends to, if the server is sharded
+    shard: Option<ShardSpec>,
 }
 
 impl TwitterClient {
@@ -426,9 +439,24 @@ impl TwitterClient {
             value_size,
             ignore_sets,
             ignore_pps,
+            shard: None,
         })
     }
 
+    /// Only send requests whose key the given shard owns; see `YCSBClient::set_shard`. Must be
+    /// called before `generate_packet_schedule_and_metadata`.
+    pub fn set_shard(&mut self, shard: ShardSpec) -> Result<()> {
+        ensure!(
+            self.thread_id % shard.num_shards == shard.shard_id,
+            "Thread {} cannot send requests for shard {} of {}",
+            self.thread_id,
+            shard.shard_id,
+            shard.num_shards
+        );
+        self.shard = Some(shard);
+        Ok(())
+    }
+
     pub fn generate_packet_schedule_and_metadata(
         &mut self,
         request_file: &str,
@@ -458,6 +486,7 @@ impl TwitterClient {
                         cur_val_index,
                         self.value_size,
                         self.ignore_sets,
+                        self.shard.as_ref(),
                     ) {
                         Ok(twitter_req_option) => match twitter_req_option {
                             Some((twitter_req, time)) => {
@@ -623,6 +652,14 @@ impl RequestGenerator for TwitterClient {
         S: ClientSerializer<D>,
         D: Datapath,
     {
+        if cfg!(debug_assertions) {
+            let status =
+                serializer.deserialize_response_status(self.message_type(request)?, buf)?;
+            if status != ResponseStatus::Ok {
+                tracing::warn!(status =? status, "Received error response for {:?}", request);
+                return Ok(false);
+            }
+        }
         match request.msg_type() {
             MsgType::Get => {
                 if cfg!(debug_assertions) {
@@ -721,6 +758,8 @@ pub struct TwitterServerLoader {
     min_keys_to_load: usize,
     // optional (override) value size
     value_size: Option<usize>,
+    // only load keys this shard owns
+    shard: Option<ShardSpec>,
 }
 
 impl TwitterServerLoader {
@@ -729,6 +768,20 @@ impl TwitterServerLoader {
             twitter_end_time: end_time,
             min_keys_to_load: min_num_keys,
             value_size,
+            shard: None,
+        }
+    }
+
+    /// Only load keys the given shard owns; each shard loads its share of the minimum number of
+    /// keys.
+    pub fn set_shard(&mut self, shard: ShardSpec) {
+        self.shard = Some(shard);
+    }
+
+    fn min_keys_to_load(&self) -> usize {
+        match &self.shard {
+            Some(shard) => self.min_keys_to_load.div_ceil(shard.num_shards),
+            None => self.min_keys_to_load,
         }
     }
 }
@@ -760,6 +813,7 @@ impl ServerLoadGenerator for TwitterServerLoader {
         let reader = BufReader::new(file);
         let mut lines_iterator = reader.lines();
         let mut last_time = 0;
+        let min_keys_to_load = self.min_keys_to_load();
         while !(reached_end_time && reached_min_keys) {
             let mut lines_vec: Vec<Self::RequestLine> = Vec::with_capacity(batch_size);
             for _ in 0..batch_size {
@@ -770,9 +824,9 @@ impl ServerLoadGenerator for TwitterServerLoader {
                         if request.get_time() > self.twitter_end_time {
                             reached_end_time = true;
                         }
-                        if kv_server.len() >= self.min_keys_to_load
-                            || linked_list_kv_server.len() >= self.min_keys_to_load
-                            || list_kv_server.len() >= self.min_keys_to_load
+                        if kv_server.len() >= min_keys_to_load
+                            || linked_list_kv_server.len() >= min_keys_to_load
+                            || list_kv_server.len() >= min_keys_to_load
                         {
                             reached_min_keys = true;
                         }
@@ -839,10 +893,12 @@ impl ServerLoadGenerator for TwitterServerLoader {
                     // only allocate for new keys not seen before
                     return Ok(());
                 }
-                let mut datapath_buffer =
-                    allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
-                let _ = datapath_buffer.write(value.as_bytes())?;
-                kv_server.insert(key.to_string(), datapath_buffer);
+                if let Some(shard) = &self.shard {
+                    if !shard.owns(key) {
+                        return Ok(());
+                    }
+                }
+                kv_server.insert_with_copies(key, value.as_bytes(), datapath, mempool_ids)?;
             }
             _ => {}
         }
@@ -1,13 +1,14 @@
 use super::{
-    allocate_datapath_buffer, ClientSerializer, KVServer, LinkedListKVServer, ListKVServer,
-    MsgType, RequestGenerator, ServerLoadGenerator, REQ_TYPE_SIZE,
+    persistence::PersistenceConfig, shard::ShardSpec, ClientSerializer, KVServer,
+    LinkedListKVServer, ListKVServer, MsgType, RequestGenerator, ResponseStatus,
+    ServerLoadGenerator, MAX_SCAN_COUNT, REQ_TYPE_SIZE,
 };
 use color_eyre::eyre::{bail, ensure, Result, WrapErr};
 use cornflakes_libos::{allocator::MempoolID, datapath::Datapath};
 use hashbrown::HashMap;
 use std::{
     fs::File,
-    io::{prelude::*, BufReader, Lines, Write},
+    io::{prelude::*, BufReader, Lines},
 };
 const MAX_BATCHES: usize = 8;
 const DEFAULT_VALUE_SIZE: usize = 4096;
@@ -117,6 +118,8 @@ pub struct YCSBLine {
     keys: Vec<String>,
     vals: Vec<String>,
     req_type: MsgType,
+    // expected version for CAS, or the amount for INCR/DECR
+    arg: u64,
 }
 
 impl YCSBLine {
@@ -157,6 +160,7 @@ impl YCSBLine {
                     keys: keys,
                     vals: Vec::default(),
                     req_type: msg_type,
+                    arg: 0,
                 })
             }
             "UPDATE" => {
@@ -179,6 +183,66 @@ impl YCSBLine {
                     keys: keys,
                     vals: std::iter::repeat(sized_value).take(num_values).collect(),
                     req_type: msg_type,
+                    arg: 0,
+                })
+            }
+            "DELETE" => {
+                let mut msg_type = MsgType::Delete;
+                if num_keys > 1 {
+                    msg_type = MsgType::DeleteM(num_keys as u16);
+                }
+                Ok(YCSBLine {
+                    keys: keys,
+                    vals: Vec::default(),
+                    req_type: msg_type,
+                    arg: 0,
+                })
+            }
+            "CAS" => {
+                let version = split
+                    .next()
+                    .unwrap()
+                    .parse::<u64>()
+                    .wrap_err("Could not parse version for CAS")?;
+                let val = &split.next().unwrap();
+                let sized_value = std::iter::repeat(val.chars().nth(0).unwrap())
+                    .take(value_size)
+                    .collect::<String>();
+                Ok(YCSBLine {
+                    keys: keys,
+                    vals: vec![sized_value],
+                    req_type: MsgType::CompareAndSwap,
+                    arg: version,
+                })
+            }
+            "INCR" | "DECR" => {
+                let delta = split
+                    .next()
+                    .unwrap()
+                    .parse::<u64>()
+                    .wrap_err("Could not parse delta for INCR/DECR")?;
+                let msg_type = match req {
+                    "INCR" => MsgType::Increment,
+                    _ => MsgType::Decrement,
+                };
+                Ok(YCSBLine {
+                    keys: keys,
+                    vals: Vec::default(),
+                    req_type: msg_type,
+                    arg: delta,
+                })
+            }
+            "SCAN" => {
+                let count = split
+                    .next()
+                    .unwrap()
+                    .parse::<u64>()
+                    .wrap_err("Could not parse count for SCAN")?;
+                Ok(YCSBLine {
+                    keys: keys,
+                    vals: Vec::default(),
+                    req_type: MsgType::Scan,
+                    arg: count,
                 })
             }
             x => {
@@ -198,6 +262,25 @@ impl YCSBLine {
     pub fn msg_type(&self) -> MsgType {
         self.req_type
     }
+
+    /// Turns a read into a scan of up to `count` keys starting at the read's (first) key.
+    pub fn into_scan(self, count: u64) -> YCSBLine {
+        match self.req_type {
+            MsgType::Get | MsgType::GetM(_) | MsgType::GetList(_) => YCSBLine {
+                keys: self.keys,
+                vals: Vec::default(),
+                req_type: MsgType::Scan,
+                arg: count,
+            },
+            _ => self,
+        }
+    }
+
+    /// Expected version for a CAS request, the delta for an increment or decrement, or the
+    /// number of keys to scan.
+    pub fn get_arg(&self) -> u64 {
+        self.arg
+    }
 }
 
 pub struct YCSBServerLoader {
@@ -206,6 +289,9 @@ pub struct YCSBServerLoader {
     num_keys: usize,
     allocate_contiguously: bool,
     use_linked_list: bool,
+    shard: Option<ShardSpec>,
+    ordered_index: bool,
+    persistence: Option<PersistenceConfig>,
 }
 
 impl YCSBServerLoader {
@@ -222,6 +308,32 @@ impl YCSBServerLoader {
             num_keys: num_keys,
             allocate_contiguously: allocate_contiguously,
             use_linked_list: use_linked_list,
+            shard: None,
+            ordered_index: false,
+            persistence: None,
+        }
+    }
+
+    /// Keep the kv store's keys ordered, so it can serve scans.
+    pub fn set_ordered_index(&mut self, ordered_index: bool) {
+        self.ordered_index = ordered_index;
+    }
+
+    /// Persist the stores in the given directory, recovering them from it instead of loading
+    /// the trace if it already holds their state.
+    pub fn set_persistence(&mut self, config: PersistenceConfig) {
+        self.persistence = Some(config);
+    }
+
+    /// Only load keys the given shard owns.
+    pub fn set_shard(&mut self, shard: ShardSpec) {
+        self.shard = Some(shard);
+    }
+
+    fn owns(&self, key: &str) -> bool {
+        match &self.shard {
+            Some(shard) => shard.owns(key),
+            None => true,
         }
     }
 
@@ -244,14 +356,19 @@ impl YCSBServerLoader {
             MsgType::Put => {
                 assert!(round == 0);
                 let key = request.get_keys()[0].as_str();
+                if !self.owns(key) {
+                    return Ok(());
+                }
                 let value = request.get_values()[0].as_str();
-                let mut datapath_buffer =
-                    allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
-                let _ = datapath_buffer.write(value.as_bytes())?;
                 if use_linked_list_kv_server || self.use_linked_list {
-                    linked_list_kv_server.insert(key.to_string(), datapath_buffer);
+                    linked_list_kv_server.append_with_copies(
+                        key,
+                        value.as_bytes(),
+                        datapath,
+                        mempool_ids,
+                    )?;
                 } else {
-                    kv_server.insert(key.to_string(), datapath_buffer);
+                    kv_server.insert_with_copies(key, value.as_bytes(), datapath, mempool_ids)?;
                 }
             }
             MsgType::PutM(size) => {
@@ -265,14 +382,19 @@ impl YCSBServerLoader {
                     )
                 );
                 let key = request.get_keys()[round].as_str();
+                if !self.owns(key) {
+                    return Ok(());
+                }
                 let value = request.get_values()[round].as_str();
-                let mut datapath_buffer =
-                    allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
-                let _ = datapath_buffer.write(value.as_bytes())?;
                 if use_linked_list_kv_server || self.use_linked_list {
-                    linked_list_kv_server.insert(key.to_string(), datapath_buffer);
+                    linked_list_kv_server.append_with_copies(
+                        key,
+                        value.as_bytes(),
+                        datapath,
+                        mempool_ids,
+                    )?;
                 } else {
-                    kv_server.insert(key.to_string(), datapath_buffer);
+                    kv_server.insert_with_copies(key, value.as_bytes(), datapath, mempool_ids)?;
                 }
             }
             MsgType::PutList(size) => {
@@ -285,19 +407,24 @@ impl YCSBServerLoader {
                     )
                 );
                 let key = request.get_keys()[0].as_str();
+                if !self.owns(key) {
+                    return Ok(());
+                }
                 let value = request.get_values()[round].as_str();
-                let mut datapath_buffer =
-                    allocate_datapath_buffer(datapath, value.len(), mempool_ids)?;
-                let _ = datapath_buffer.write(value.as_bytes())?;
                 if use_linked_list_kv_server || self.use_linked_list {
-                    linked_list_kv_server.insert(key.to_string(), datapath_buffer);
+                    linked_list_kv_server.append_with_copies(
+                        key,
+                        value.as_bytes(),
+                        datapath,
+                        mempool_ids,
+                    )?;
                 } else {
-                    if list_kv_server.contains_key(key) {
-                        let list = list_kv_server.get_mut_map().get_mut(key).unwrap();
-                        list.push(datapath_buffer);
-                    } else {
-                        list_kv_server.insert(key.to_string(), vec![datapath_buffer]);
-                    }
+                    list_kv_server.append_with_copies(
+                        key,
+                        value.as_bytes(),
+                        datapath,
+                        mempool_ids,
+                    )?;
                 }
             }
             _ => {
@@ -313,6 +440,14 @@ impl YCSBServerLoader {
 impl ServerLoadGenerator for YCSBServerLoader {
     type RequestLine = YCSBLine;
 
+    fn ordered_index(&self) -> bool {
+        self.ordered_index
+    }
+
+    fn persistence_config(&self) -> Option<PersistenceConfig> {
+        self.persistence.clone()
+    }
+
     fn read_request(&self, line: &str) -> Result<Self::RequestLine> {
         let value_size = self.value_size.sample();
         YCSBLine::new(
@@ -498,6 +633,11 @@ pub struct YCSBClient {
     num_values: usize,
     num_keys: usize,
     use_linked_list: bool,
+    shard: Option<ShardSpec>,
+    // lines seen so far whose key the shard owns
+    shard_line_id: usize,
+    // YCSB-E: reads become scans of a length drawn from here
+    scan_lengths: Option<Uniform<u64>>,
 }
 
 impl YCSBClient {
@@ -530,9 +670,71 @@ impl YCSBClient {
             num_keys: num_keys,
             num_values: num_values,
             use_linked_list: use_linked_list,
+            shard: None,
+            shard_line_id: 0,
+            scan_lengths: None,
         })
     }
 
+    /// Only send requests whose (first) key the given shard owns. These are split among the
+    /// client threads pinned to the shard, i.e. those with `thread_id % num_shards == shard_id`.
+    pub fn set_shard(&mut self, shard: ShardSpec) -> Result<()> {
+        ensure!(
+            self.thread_id % shard.num_shards == shard.shard_id,
+            "Thread {} cannot send requests for shard {} of {}",
+            self.thread_id,
+            shard.shard_id,
+            shard.num_shards
+        );
+        self.shard = Some(shard);
+        Ok(())
+    }
+
+    /// YCSB workload E: replays the trace with every read turned into a scan starting at the
+    /// read's key, of a length drawn uniformly from 1 to `max_scan_length`. Writes are sent as
+    /// is, so a trace with workload B's 95/5 mix gives workload E's mix of scans and writes.
+    pub fn use_scans(&mut self, max_scan_length: usize) -> Result<()> {
+        ensure!(
+            max_scan_length > 0,
+            "Maximum scan length must be at least 1"
+        );
+        self.scan_lengths = Some(Uniform::new_inclusive(1, max_scan_length as u64));
+        Ok(())
+    }
+
+    fn next_shard_request(&mut self, shard: ShardSpec) -> Result<Option<YCSBLine>> {
+        let (our_index, num_senders) = shard.sender_index(
+            self.client_id,
+            self.thread_id,
+            self.total_num_clients,
+            self.total_num_threads,
+        );
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(s)) => s,
+                Some(Err(e)) => {
+                    bail!("Could not get next line in iterator: {:?}", e);
+                }
+                None => {
+                    return Ok(None);
+                }
+            };
+            self.line_id += 1;
+            // lines look like "<timestamp> <request type> <key> ..."
+            let owned = match line.split(" ").nth(2) {
+                Some(key) => shard.owns(key),
+                None => false,
+            };
+            if !owned {
+                continue;
+            }
+            self.shard_line_id += 1;
+            if (self.shard_line_id - 1) % num_senders == our_index {
+                return Ok(Some(self.get_request(&line)?));
+            }
+        }
+    }
+
     fn increment(&mut self) {
         self.line_id += 1;
         self.increment_client_id_counter();
@@ -702,14 +904,68 @@ impl YCSBClient {
         Ok(())
     }
 
+    /// A not-found status is only a correct answer if the reference state is missing one of the
+    /// requested keys too (or was never loaded).
+    fn check_not_found(
+        &self,
+        request: &<Self as RequestGenerator>::RequestLine,
+        kv: &HashMap<String, String>,
+        list_kv: &HashMap<String, Vec<String>>,
+    ) -> bool {
+        match request.msg_type() {
+            MsgType::GetList(_) => {
+                list_kv.is_empty() || !list_kv.contains_key(&request.get_keys()[0])
+            }
+            _ => kv.is_empty() || request.get_keys().iter().any(|key| !kv.contains_key(key)),
+        }
+    }
+
     fn get_request(&self, line: &str) -> Result<<Self as RequestGenerator>::RequestLine> {
-        YCSBLine::new(
+        let request = YCSBLine::new(
             line,
             self.num_keys,
             self.num_values,
             self.value_size.sample(),
             self.use_linked_list,
-        )
+        )?;
+        match &self.scan_lengths {
+            Some(scan_lengths) => Ok(request.into_scan(scan_lengths.sample(&mut thread_rng()))),
+            None => Ok(request),
+        }
+    }
+
+    fn check_scan(
+        &self,
+        request: &<Self as RequestGenerator>::RequestLine,
+        values: Vec<Vec<u8>>,
+        kv: &HashMap<String, String>,
+    ) -> Result<()> {
+        let max_values = match request.get_arg() as usize {
+            0 => MAX_SCAN_COUNT,
+            count => std::cmp::min(count, MAX_SCAN_COUNT),
+        };
+        ensure!(
+            values.len() <= max_values,
+            format!(
+                "received {} values for a scan of at most {}",
+                values.len(),
+                max_values
+            )
+        );
+        // the scan starts at the request's key, so if that key exists its value comes first
+        if let (Some(expected_value), Some(received_value)) =
+            (kv.get(&request.get_keys()[0]), values.first())
+        {
+            ensure!(
+                expected_value.as_bytes() == received_value.as_slice(),
+                format!(
+                    "Check scan failed. Expected first value: {:?}, Recved: {:?}",
+                    expected_value.as_bytes(),
+                    received_value,
+                )
+            );
+        }
+        Ok(())
     }
 }
 
@@ -741,10 +997,16 @@ impl RequestGenerator for YCSBClient {
             num_keys: DEFAULT_NUM_KEYS,
             num_values: DEFAULT_NUM_VALUES,
             use_linked_list: false,
+            shard: None,
+            shard_line_id: 0,
+            scan_lengths: None,
         })
     }
 
     fn next_request(&mut self) -> Result<Option<Self::RequestLine>> {
+        if let Some(shard) = self.shard {
+            return self.next_shard_request(shard);
+        }
         loop {
             // find the next request with our client and thread id
             if self.cur_client_id == self.client_id && self.cur_thread_id == self.thread_id {
@@ -801,7 +1063,12 @@ impl RequestGenerator for YCSBClient {
             )?,
             MsgType::Put => {
                 let put_data = self.emit_put_data(&request)?;
-                serializer.serialize_put(&mut buf[2..], put_data.0, put_data.1, datapath)?
+                serializer.serialize_put(
+                    &mut buf[REQ_TYPE_SIZE..],
+                    put_data.0,
+                    put_data.1,
+                    datapath,
+                )?
             }
             MsgType::GetM(size) => serializer.serialize_getm(
                 &mut buf[REQ_TYPE_SIZE..],
@@ -840,6 +1107,45 @@ impl RequestGenerator for YCSBClient {
                     datapath,
                 )?
             }
+            MsgType::Delete => serializer.serialize_delete(
+                &mut buf[REQ_TYPE_SIZE..],
+                self.emit_get_data(&request)?,
+                datapath,
+            )?,
+            MsgType::DeleteM(size) => serializer.serialize_deletem(
+                &mut buf[REQ_TYPE_SIZE..],
+                self.emit_getm_data(&request, size)?,
+                datapath,
+            )?,
+            MsgType::CompareAndSwap => {
+                let put_data = self.emit_put_data(&request)?;
+                serializer.serialize_cas(
+                    &mut buf[REQ_TYPE_SIZE..],
+                    put_data.0,
+                    request.get_arg(),
+                    put_data.1,
+                    datapath,
+                )?
+            }
+            MsgType::Increment => serializer.serialize_increment(
+                &mut buf[REQ_TYPE_SIZE..],
+                self.emit_get_data(&request)?,
+                request.get_arg(),
+                datapath,
+            )?,
+            MsgType::Decrement => serializer.serialize_decrement(
+                &mut buf[REQ_TYPE_SIZE..],
+                self.emit_get_data(&request)?,
+                request.get_arg(),
+                datapath,
+            )?,
+            MsgType::Scan => serializer.serialize_scan(
+                &mut buf[REQ_TYPE_SIZE..],
+                self.emit_get_data(&request)?,
+                None,
+                request.get_arg(),
+                datapath,
+            )?,
             _ => {
                 bail!(
                     "YCSB client does not handle request type: {:?}",
@@ -861,6 +1167,27 @@ impl RequestGenerator for YCSBClient {
         S: ClientSerializer<D>,
         D: Datapath,
     {
+        if cfg!(debug_assertions) {
+            match (
+                request.msg_type(),
+                serializer.deserialize_response_status(request.msg_type(), buf)?,
+            ) {
+                (_, ResponseStatus::Ok) => {}
+                // other clients write the same keys, so losing a race is a valid answer
+                (MsgType::CompareAndSwap, ResponseStatus::VersionMismatch)
+                | (MsgType::Delete | MsgType::DeleteM(_), ResponseStatus::NotFound)
+                | (MsgType::Increment | MsgType::Decrement, ResponseStatus::WrongType) => {
+                    return Ok(true);
+                }
+                (_, ResponseStatus::NotFound) => {
+                    return Ok(self.check_not_found(&request, &kv, &list_kv));
+                }
+                (_, status) => {
+                    tracing::warn!(status =? status, "Received error response for {:?}", request);
+                    return Ok(false);
+                }
+            }
+        }
         match request.msg_type() {
             MsgType::Get => {
                 if cfg!(debug_assertions) {
@@ -883,8 +1210,37 @@ impl RequestGenerator for YCSBClient {
             MsgType::Put => {}
             MsgType::PutM(_size) => {}
             MsgType::PutList(_size) => {}
+            MsgType::Delete | MsgType::DeleteM(_) => {}
+            MsgType::CompareAndSwap => {
+                if cfg!(debug_assertions) {
+                    let version = serializer.deserialize_cas_response(buf)?;
+                    ensure!(
+                        version == request.get_arg() + 1,
+                        format!(
+                            "CAS from version {} succeeded but returned version {}",
+                            request.get_arg(),
+                            version
+                        )
+                    );
+                }
+            }
+            MsgType::Increment | MsgType::Decrement => {
+                if cfg!(debug_assertions) {
+                    let val = serializer.deserialize_incr_response(buf)?;
+                    tracing::debug!(key = request.get_keys()[0].as_str(), val, "Incremented");
+                }
+            }
+            MsgType::Scan => {
+                if cfg!(debug_assertions) {
+                    let vals = serializer.deserialize_getm_response(buf)?;
+                    self.check_scan(&request, vals, &kv)?;
+                }
+            }
             _ => {
-                bail!("YCSB Generator does not check requests other than get & put");
+                bail!(
+                    "YCSB Generator does not check request type {:?}",
+                    request.msg_type()
+                );
             }
         }
 
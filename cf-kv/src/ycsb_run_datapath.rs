@@ -1,49 +1,160 @@
-use super::ycsb::YCSBValueSizeGenerator;
+use super::{
+    persistence::PersistenceConfig, replication::ReplicationConfig, shard::check_num_shards,
+    ycsb::YCSBValueSizeGenerator,
+};
 use color_eyre::eyre::{bail, Result};
 use cornflakes_libos::{
-    datapath::{InlineMode, PushBufType},
-    loadgen::request_schedule::DistributionType,
+    admission::AdmissionConfig,
+    allocator::SizeClasses,
+    datapath::{ChecksumMode, InlineMode, PushBufType},
+    loadgen::{flow_control::FlowControlConfig, request_schedule::DistributionType},
+    rss::QueueTargets,
 };
 use cornflakes_utils::{AppMode, CopyingThreshold, SerializationType, TraceLevel};
-use std::net::Ipv4Addr;
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use structopt::StructOpt;
 
 #[macro_export]
 macro_rules! run_server(
     ($kv_server: ty, $datapath: ty, $opt: ident) => {
-        cornflakes_libos::datapath::set_mempool_params($opt.num_pages_per_mempool, $opt.num_registrations, !$opt.do_not_register_at_start);
+        if $opt.num_shards > 1 {
+            $crate::run_sharded_server!($kv_server, $datapath, $opt);
+            return Ok(());
+        }
         let is_baseline = is_baseline(&$opt);
         let mut datapath_params = <$datapath as Datapath>::parse_config_file(&$opt.config_file, &$opt.server_ip)?;
         let addresses = <$datapath as Datapath>::compute_affinity(&datapath_params, 1, None, AppMode::Server)?;
         let per_thread_contexts = <$datapath as Datapath>::global_init(1, &mut datapath_params, addresses)?;
         let mut connection = <$datapath as Datapath>::per_thread_init(datapath_params, per_thread_contexts.into_iter().nth(0).unwrap(),
         AppMode::Server)?;
+        connection.set_mempool_config(cornflakes_libos::allocator::MempoolConfig {
+            num_pages: $opt.num_pages_per_mempool,
+            num_registrations: $opt.num_registrations,
+            register_at_start: !$opt.do_not_register_at_start,
+            auto_grow: $opt.auto_grow_mempools,
+            max_memory_bytes: $opt.max_mempool_memory_mb.map(|mb| mb * 1024 * 1024),
+            size_classes: $opt.size_classes,
+        })?;
 
         connection.set_copying_threshold($opt.copying_threshold.thresh());
         connection.set_inline_mode($opt.inline_mode);
+        if let Some(checksum_mode) = $opt.checksum_mode {
+            connection.set_checksum_mode(checksum_mode)?;
+        }
+        if let Some(capture_pcap) = &$opt.capture_pcap {
+            connection.set_packet_capture(cornflakes_libos::pcap::PacketCapture::new(capture_pcap, cornflakes_libos::pcap::DEFAULT_CAPTURE_QUEUE_DEPTH)?)?;
+        }
+        if let Some(encryption) = cornflakes_libos::encryption::EncryptionConfig::from_config_file(&$opt.config_file)? {
+            connection.set_encryption(encryption)?;
+        }
         tracing::info!(threshold = $opt.copying_threshold.thresh(), "Setting zero-copy copying threshold");
         // init ycsb load generator
-        let load_generator = YCSBServerLoader::new($opt.value_size_generator, $opt.num_values, $opt.num_keys, $opt.allocate_contiguously, $opt.use_linked_list);
+        let mut load_generator = YCSBServerLoader::new($opt.value_size_generator, $opt.num_values, $opt.num_keys, $opt.allocate_contiguously, $opt.use_linked_list);
+        load_generator.set_ordered_index($opt.ordered_index);
+        if let Some(persistence_config) = persistence_config(&$opt) {
+            load_generator.set_persistence(persistence_config);
+        }
         let mut kv_server = <$kv_server>::new($opt.trace_file.as_str(), load_generator, &mut connection, $opt.push_buf_type, $opt.use_linked_list)?;
+        if let Some(replication_config) = &$opt.replication_config {
+            kv_server.set_replicator($crate::replication::Replicator::new(replication_config, &$opt.config_file, $opt.server_ip, &mut connection)?);
+        }
         kv_server.init(&mut connection)?;
         kv_server.write_ready($opt.ready_file.clone())?;
+        let admission_controller = admission_config(&$opt).map(cornflakes_libos::admission::AdmissionController::new);
         if is_baseline {
-            kv_server.run_state_machine_baseline(&mut connection)?;
+            kv_server.run_state_machine_baseline_with_admission_control(&mut connection, admission_controller)?;
+        } else if $opt.copying_threshold.is_adaptive() {
+            tracing::info!("Tuning copying threshold online");
+            kv_server.run_state_machine_with_controllers(&mut connection, Some(cornflakes_libos::adaptive_threshold::AdaptiveThresholdController::default()), admission_controller)?;
         } else {
-            kv_server.run_state_machine(&mut connection)?;
+            kv_server.run_state_machine_with_controllers(&mut connection, None, admission_controller)?;
         }
     }
 );
 
+/// Runs one server thread per shard, each on its own datapath queue with its own stores.
+#[macro_export]
+macro_rules! run_sharded_server(
+    ($kv_server: ty, $datapath: ty, $opt: ident) => {
+        let num_shards = $opt.num_shards;
+        let mut datapath_params = <$datapath as Datapath>::parse_config_file(&$opt.config_file, &$opt.server_ip)?;
+        let addresses = <$datapath as Datapath>::compute_affinity(&datapath_params, num_shards, None, AppMode::Server)?;
+        let per_thread_contexts = <$datapath as Datapath>::global_init(num_shards, &mut datapath_params, addresses)?;
+        let opt_clone = $opt.clone();
+        $crate::shard::run_shards(per_thread_contexts, $opt.forward_misrouted, move |per_thread_context, router, loaded| {
+            let i = router.spec().shard_id;
+            let is_baseline = is_baseline(&opt_clone);
+            let mut connection = <$datapath as Datapath>::per_thread_init(datapath_params, per_thread_context, AppMode::Server)?;
+            connection.set_mempool_config(cornflakes_libos::allocator::MempoolConfig {
+                num_pages: opt_clone.num_pages_per_mempool,
+                num_registrations: opt_clone.num_registrations,
+                register_at_start: !opt_clone.do_not_register_at_start,
+                auto_grow: opt_clone.auto_grow_mempools,
+                max_memory_bytes: opt_clone.max_mempool_memory_mb.map(|mb| mb * 1024 * 1024),
+                size_classes: opt_clone.size_classes,
+            })?;
+            connection.set_copying_threshold(opt_clone.copying_threshold.thresh());
+            connection.set_inline_mode(opt_clone.inline_mode);
+            if let Some(checksum_mode) = opt_clone.checksum_mode {
+                connection.set_checksum_mode(checksum_mode)?;
+            }
+            if let Some(capture_pcap) = &opt_clone.capture_pcap {
+                connection.set_packet_capture(cornflakes_libos::pcap::PacketCapture::new(&format!("{}.{}", capture_pcap, i), cornflakes_libos::pcap::DEFAULT_CAPTURE_QUEUE_DEPTH)?)?;
+            }
+            if let Some(encryption) = cornflakes_libos::encryption::EncryptionConfig::from_config_file(&opt_clone.config_file)? {
+                connection.set_encryption(encryption)?;
+            }
+
+            let shard = router.spec();
+            tracing::info!(shard = shard.shard_id, num_shards = shard.num_shards, "Loading server shard");
+            let mut load_generator = YCSBServerLoader::new(opt_clone.value_size_generator.clone(), opt_clone.num_values, opt_clone.num_keys, opt_clone.allocate_contiguously, opt_clone.use_linked_list);
+            load_generator.set_shard(shard);
+            load_generator.set_ordered_index(opt_clone.ordered_index);
+            // each shard persists its own stores
+            if let Some(persistence_config) = persistence_config(&opt_clone) {
+                load_generator.set_persistence(persistence_config.for_store(&format!("shard-{}", i)));
+            }
+            let mut kv_server = <$kv_server>::new(opt_clone.trace_file.as_str(), load_generator, &mut connection, opt_clone.push_buf_type, opt_clone.use_linked_list)?;
+            kv_server.set_shard_router(router);
+            kv_server.init(&mut connection)?;
+            // the ready file is written once every shard has loaded its part of the trace
+            loaded.wait();
+            if i == 0 {
+                kv_server.write_ready(opt_clone.ready_file.clone())?;
+            }
+            let admission_controller = admission_config(&opt_clone).map(cornflakes_libos::admission::AdmissionController::new);
+            if is_baseline {
+                kv_server.run_state_machine_baseline_with_admission_control(&mut connection, admission_controller)?;
+            } else if opt_clone.copying_threshold.is_adaptive() {
+                kv_server.run_state_machine_with_controllers(&mut connection, Some(cornflakes_libos::adaptive_threshold::AdaptiveThresholdController::default()), admission_controller)?;
+            } else {
+                kv_server.run_state_machine_with_controllers(&mut connection, None, admission_controller)?;
+            }
+            Ok(())
+        })?;
+    }
+);
+
 #[macro_export]
 macro_rules! run_client(
     ($serializer: ty, $datapath: ty, $opt: ident) => {
-        let server_addr = cornflakes_utils::parse_server_addr(&$opt.config_file, &$opt.server_ip)?;
+        let server_ip = primary_server_ip(&$opt)?;
+        let server_addr = cornflakes_utils::parse_server_addr(&$opt.config_file, &server_ip)?;
         let mut datapath_params = <$datapath as Datapath>::parse_config_file(&$opt.config_file, &$opt.our_ip)?;
+        if let Some(num_server_queues) = $opt.num_server_queues {
+            <$datapath as Datapath>::set_server_queue_assignment(
+                &mut datapath_params,
+                cornflakes_libos::rss::ServerQueueAssignment::new(num_server_queues, $opt.server_queue_targets.clone()),
+            )?;
+        }
         let addresses = <$datapath as Datapath>::compute_affinity(
                 &datapath_params,
                 $opt.num_threads,
-                Some($opt.server_ip.clone()),
+                Some(server_ip.clone()),
                 cornflakes_utils::AppMode::Client,
             )?;
         let num_rtts = ($opt.rate as f64 * $opt.total_time as f64 * 1.2) as usize;
@@ -63,8 +174,10 @@ macro_rules! run_client(
             .zip(per_thread_contexts.into_iter())
             .enumerate()
         {
+            // each thread sends to one shard, at the port that shard's queue listens on
+            let shard = i % $opt.num_shards;
         let server_addr_clone =
-            cornflakes_libos::utils::AddressInfo::new(server_addr.2, server_addr.1.clone(), server_addr.0.clone());
+            cornflakes_libos::utils::AddressInfo::from_known_host(<$datapath as Datapath>::server_queue_port(server_addr.2, shard), server_addr.1.clone(), server_addr.0);
             let datapath_params_clone = datapath_params.clone();
 
             let max_num_requests = num_rtts;
@@ -89,9 +202,19 @@ macro_rules! run_client(
                 )?;
 
                 connection.set_copying_threshold(usize::MAX);
+                if let Some(encryption) = cornflakes_libos::encryption::EncryptionConfig::from_config_file(&opt_clone.config_file)? {
+                    connection.set_encryption(encryption)?;
+                }
 
                 let mut ycsb_client = YCSBClient::new_ycsb_client(&opt_clone.queries.as_str(),opt_clone.client_id, i, opt_clone.num_clients, opt_clone.num_threads, opt_clone.value_size_generator.clone(), opt_clone.num_keys, opt_clone.num_values, opt_clone.use_linked_list)?;
 
+                if let Some(max_scan_length) = opt_clone.max_scan_length {
+                    ycsb_client.use_scans(max_scan_length)?;
+                }
+                if opt_clone.num_shards > 1 {
+                    ycsb_client.set_shard($crate::shard::ShardSpec::new(shard, opt_clone.num_shards))?;
+                }
+
                 let mut server_trace: Option<(&str, YCSBServerLoader)> = None;
                 if cfg!(debug_assertions) {
                     if opt_clone.trace_file != "" {
@@ -100,6 +223,12 @@ macro_rules! run_client(
                     }
                 }
                 let mut kv_client: KVClient<YCSBClient, $serializer, $datapath> = KVClient::new(ycsb_client, server_addr_clone, max_num_requests,opt_clone.retries, server_trace)?;
+                if opt_clone.num_shards > 1 {
+                    kv_client.set_shard(shard);
+                }
+                if let Some(flow_control) = flow_control_config(&opt_clone) {
+                    kv_client.set_flow_control(flow_control);
+                }
 
                 kv_client.init(&mut connection)?;
 
@@ -135,6 +264,44 @@ pub fn is_baseline(opt: &YCSBOpt) -> bool {
     !(opt.serialization == SerializationType::CornflakesOneCopyDynamic
         || opt.serialization == SerializationType::CornflakesDynamic)
 }
+/// Persistence settings for the server's stores, if `--persistence_dir` is set.
+pub fn persistence_config(opt: &YCSBOpt) -> Option<PersistenceConfig> {
+    opt.persistence_dir.as_ref().map(|dir| {
+        PersistenceConfig::new(
+            PathBuf::from(dir),
+            opt.snapshot_interval_secs.map(Duration::from_secs),
+            opt.fsync,
+        )
+    })
+}
+
+/// Admission control settings for each server thread, if rate limiting or load shedding is on.
+pub fn admission_config(opt: &YCSBOpt) -> Option<AdmissionConfig> {
+    AdmissionConfig::from_flags(
+        opt.admission_rate,
+        opt.admission_burst,
+        opt.admission_queue_depth,
+    )
+}
+
+/// Flow control settings for each client thread, if `--flow_control_target_us` is set.
+pub fn flow_control_config(opt: &YCSBOpt) -> Option<FlowControlConfig> {
+    opt.flow_control_target_us
+        .map(|target_us| FlowControlConfig {
+            target_delay: Duration::from_micros(target_us),
+            initial_window: opt.flow_control_initial_window,
+            ..Default::default()
+        })
+}
+
+/// Server the client sends to: the primary named in the replication config, if there is one.
+pub fn primary_server_ip(opt: &YCSBOpt) -> Result<IpAddr> {
+    match &opt.replication_config {
+        Some(path) => Ok(ReplicationConfig::from_file(Path::new(path))?.primary_ip()),
+        None => Ok(opt.server_ip),
+    }
+}
+
 pub fn is_cf(opt: &YCSBOpt) -> bool {
     opt.serialization == SerializationType::CornflakesDynamic
         || opt.serialization == SerializationType::CornflakesOneCopyDynamic
@@ -145,6 +312,30 @@ pub fn check_opt(opt: &mut YCSBOpt) -> Result<()> {
         bail!("For non-cornflakes serialization, push buf type must be single buffer.");
     }
 
+    // the baseline and one-copy servers do not pick between copying and zero-copy
+    if opt.copying_threshold.is_adaptive()
+        && (is_baseline(opt) || opt.serialization == SerializationType::CornflakesOneCopyDynamic)
+    {
+        bail!("An adaptive copying threshold is only supported for CornflakesDynamic.");
+    }
+
+    check_num_shards(opt.num_shards)?;
+    // a scan only sees the keys of the shard that owns its start key
+    if opt.num_shards > 1 && (opt.ordered_index || opt.max_scan_length.is_some()) {
+        bail!("Scans are not supported with more than one shard.");
+    }
+    if opt.persistence_dir.is_some() && opt.use_linked_list {
+        bail!("Persistence is not supported for the linked list kv store.");
+    }
+    if opt.replication_config.is_some()
+        && (opt.num_shards > 1 || opt.serialization == SerializationType::Redis)
+    {
+        bail!("Replication is not supported with multiple shards or with Redis.");
+    }
+    if opt.num_shards > 1 && opt.serialization == SerializationType::Redis {
+        bail!("Redis requests do not carry a cf-kv header, so they cannot be sharded.");
+    }
+
     if opt.serialization == SerializationType::CornflakesOneCopyDynamic {
         // copy all segments
         opt.copying_threshold = CopyingThreshold::new(usize::MAX)
@@ -219,9 +410,46 @@ pub struct YCSBOpt {
         default_value = "nothing"
     )]
     pub inline_mode: InlineMode,
+    #[structopt(
+        long = "checksum_mode",
+        help = "UDP checksum mode (disabled, software or offload). Defaults to offload where the datapath supports it, and software otherwise."
+    )]
+    pub checksum_mode: Option<ChecksumMode>,
+    #[structopt(
+        long = "capture_pcap",
+        help = "Server: record sent and received packets to this pcap file (one file per shard, suffixed with the shard id, when sharded)."
+    )]
+    pub capture_pcap: Option<String>,
+    #[structopt(
+        long = "admission_rate",
+        help = "Server: requests per second admitted from each client connection; requests above the rate are shed."
+    )]
+    pub admission_rate: Option<f64>,
+    #[structopt(
+        long = "admission_burst",
+        help = "Server: requests a client connection may send back to back above --admission_rate.",
+        default_value = "32"
+    )]
+    pub admission_burst: u64,
+    #[structopt(
+        long = "admission_queue_depth",
+        help = "Server: admitted requests that may wait to be processed (per shard); requests arriving beyond it are shed with an overloaded reply."
+    )]
+    pub admission_queue_depth: Option<usize>,
+    #[structopt(
+        long = "flow_control_target_us",
+        help = "Client: hold back scheduled requests while response RTTs exceed this target (in microseconds), instead of sending at the scheduled rate."
+    )]
+    pub flow_control_target_us: Option<u64>,
+    #[structopt(
+        long = "flow_control_initial_window",
+        help = "Client: requests in flight per thread when flow control starts.",
+        default_value = "16"
+    )]
+    pub flow_control_initial_window: f64,
     #[structopt(
         long = "copy_threshold",
-        help = "Datapath copy threshold. Copies everything below this threshold. If set to 0, tries to use zero-copy for everything. If set to infinity, uses zero-copy for nothing.",
+        help = "Datapath copy threshold. Copies everything below this threshold. If set to 0, tries to use zero-copy for everything. If set to infinity, uses zero-copy for nothing. If set to adaptive, tunes the threshold online.",
         default_value = "256"
     )]
     pub copying_threshold: CopyingThreshold,
@@ -237,9 +465,9 @@ pub struct YCSBOpt {
         help = "Server ip address",
         default_value = "127.0.0.1"
     )]
-    pub server_ip: Ipv4Addr,
+    pub server_ip: IpAddr,
     #[structopt(long = "our_ip", help = "Our ip address", default_value = "127.0.0.1")]
-    pub our_ip: Ipv4Addr,
+    pub our_ip: IpAddr,
     #[structopt(
         long = "serialization",
         help = "Serialization library to use",
@@ -258,6 +486,17 @@ pub struct YCSBOpt {
         default_value = "1"
     )]
     pub num_threads: usize,
+    #[structopt(
+        long = "num_server_queues",
+        help = "Number of server queues to spread client threads across with RSS. If unset, the datapath picks client ports without regard to server queues."
+    )]
+    pub num_server_queues: Option<usize>,
+    #[structopt(
+        long = "server_queue_targets",
+        help = "With num_server_queues, the server queue each client thread targets: even, or a comma-separated list of queues that threads cycle through (e.g., 0 sends every thread to queue 0).",
+        default_value = "even"
+    )]
+    pub server_queue_targets: QueueTargets,
     #[structopt(
         long = "num_clients",
         help = "Total number of clients",
@@ -302,4 +541,63 @@ pub struct YCSBOpt {
         help = "Register mempool memory at start"
     )]
     pub do_not_register_at_start: bool,
+    #[structopt(
+        long = "auto_grow_mempools",
+        help = "Add mempools when a size class runs out of buffers while serving requests"
+    )]
+    pub auto_grow_mempools: bool,
+    #[structopt(
+        long = "max_mempool_memory_mb",
+        help = "Ceiling on mempool memory (in MB) when growing mempools automatically"
+    )]
+    pub max_mempool_memory_mb: Option<usize>,
+    #[structopt(
+        long = "size_classes",
+        help = "Mempool size classes: pow2, geometric (1.25x) or geometric:<growth percent>",
+        default_value = "pow2"
+    )]
+    pub size_classes: SizeClasses,
+    #[structopt(
+        long = "num_shards",
+        help = "Number of server shards (cores), each owning part of the keyspace.",
+        default_value = "1"
+    )]
+    pub num_shards: usize,
+    #[structopt(
+        long = "forward_misrouted",
+        help = "Forward requests that reach the wrong server shard to the owning shard; with false, shards serve them from their own stores.",
+        parse(try_from_str),
+        default_value = "true"
+    )]
+    pub forward_misrouted: bool,
+    #[structopt(
+        long = "ordered_index",
+        help = "Keep the kv store's keys ordered, so the server can serve scans."
+    )]
+    pub ordered_index: bool,
+    #[structopt(
+        long = "max_scan_length",
+        help = "YCSB-E: send every read as a scan of up to this many keys, starting at the read's key."
+    )]
+    pub max_scan_length: Option<usize>,
+    #[structopt(
+        long = "persistence_dir",
+        help = "Directory to log mutations and snapshot the server's stores in; the server recovers from it on restart."
+    )]
+    pub persistence_dir: Option<String>,
+    #[structopt(
+        long = "snapshot_interval_secs",
+        help = "Seconds between snapshots of persisted stores. Without it, only the startup snapshot is taken."
+    )]
+    pub snapshot_interval_secs: Option<u64>,
+    #[structopt(
+        long = "fsync",
+        help = "Fsync the mutation log after every batch of writes, rather than only flushing it."
+    )]
+    pub fsync: bool,
+    #[structopt(
+        long = "replication_config",
+        help = "JSON file listing the replicas, the primary and how many backups must acknowledge each write. Servers replicate and follow changes to it; clients send to its primary."
+    )]
+    pub replication_config: Option<String>,
 }
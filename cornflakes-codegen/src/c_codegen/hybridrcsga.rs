@@ -790,6 +790,14 @@ pub fn add_received_pkt_functions(
         MsgType::GetFromList => {
             unimplemented!();
         }
+        MsgType::Delete
+        | MsgType::DeleteM(_)
+        | MsgType::CompareAndSwap
+        | MsgType::Increment
+        | MsgType::Decrement
+        | MsgType::Scan => {
+            unimplemented!();
+        }
     }",
     )?;
     compiler.add_func_call(None, "Box::into_raw", vec!["pkt".to_string()], false)?;
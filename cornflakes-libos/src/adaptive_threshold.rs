@@ -0,0 +1,390 @@
+//! Online tuning of the datapath copying threshold.
+//!
+//! The controller alternates between two phases on each datapath thread:
+//!  1. Explore: each candidate threshold is installed for a fixed number of batches, and the
+//!     cost (cycles spent serializing and transmitting responses) per request is sampled.
+//!  2. Exploit: the candidate with the lowest smoothed cost per request is installed for a
+//!     longer stretch of batches, after which the controller explores again (so it can follow
+//!     shifts in the workload).
+use super::datapath::Datapath;
+use serde::{Deserialize, Serialize};
+
+/// Candidate thresholds tried by default; `usize::MAX` means "copy everything".
+pub const DEFAULT_CANDIDATE_THRESHOLDS: [usize; 6] = [0, 128, 256, 512, 1024, usize::MAX];
+/// Batches each candidate is sampled for in one explore phase.
+pub const DEFAULT_SAMPLE_BATCHES: usize = 64;
+/// Batches the best candidate is used for between explore phases.
+pub const DEFAULT_EXPLOIT_BATCHES: usize = 8192;
+/// Weight of the newest explore round in a candidate's smoothed cost.
+const COST_SMOOTHING: f64 = 0.5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdaptiveThresholdConfig {
+    pub candidates: Vec<usize>,
+    pub sample_batches: usize,
+    pub exploit_batches: usize,
+}
+
+impl Default for AdaptiveThresholdConfig {
+    fn default() -> Self {
+        AdaptiveThresholdConfig {
+            candidates: DEFAULT_CANDIDATE_THRESHOLDS.to_vec(),
+            sample_batches: DEFAULT_SAMPLE_BATCHES,
+            exploit_batches: DEFAULT_EXPLOIT_BATCHES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    /// Sampling the candidate at the given index.
+    Explore { candidate_idx: usize },
+    /// Using the best candidate found in the last explore phase.
+    Exploit,
+}
+
+/// Per candidate cost samples.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct CandidateStats {
+    pub threshold: usize,
+    /// Smoothed cost in nanoseconds per request, across explore rounds.
+    pub avg_ns_per_request: Option<f64>,
+    /// Requests sampled under this threshold (in all explore rounds).
+    pub requests_sampled: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct AdaptiveThresholdStats {
+    pub current_threshold: usize,
+    pub best_threshold: usize,
+    /// Completed explore rounds.
+    pub rounds: u64,
+    /// Times the best threshold changed between rounds.
+    pub switches: u64,
+    pub candidates: Vec<CandidateStats>,
+}
+
+impl AdaptiveThresholdStats {
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            current_threshold = self.current_threshold,
+            best_threshold = self.best_threshold,
+            rounds = self.rounds,
+            switches = self.switches,
+            "{}",
+            msg
+        );
+        for candidate in self.candidates.iter() {
+            tracing::info!(
+                threshold = candidate.threshold,
+                avg_ns_per_request = ?candidate.avg_ns_per_request,
+                requests_sampled = candidate.requests_sampled,
+                "{} candidate",
+                msg
+            );
+        }
+    }
+}
+
+/// Picks the copying threshold for one datapath thread, based on measured per-batch costs.
+#[derive(Debug, Clone)]
+pub struct AdaptiveThresholdController {
+    config: AdaptiveThresholdConfig,
+    phase: Phase,
+    /// Batches left in the current phase (or for the current candidate when exploring).
+    batches_left: usize,
+    /// Cycles and requests accumulated for the candidate currently being sampled.
+    cur_cycles: u64,
+    cur_requests: u64,
+    /// Cycle count at the start of the current batch.
+    batch_start: u64,
+    best_idx: usize,
+    stats: AdaptiveThresholdStats,
+}
+
+impl Default for AdaptiveThresholdController {
+    fn default() -> Self {
+        AdaptiveThresholdController::new(AdaptiveThresholdConfig::default())
+    }
+}
+
+impl AdaptiveThresholdController {
+    pub fn new(mut config: AdaptiveThresholdConfig) -> Self {
+        config.candidates.sort();
+        config.candidates.dedup();
+        if config.candidates.is_empty() {
+            config.candidates = DEFAULT_CANDIDATE_THRESHOLDS.to_vec();
+        }
+        config.sample_batches = std::cmp::max(config.sample_batches, 1);
+        config.exploit_batches = std::cmp::max(config.exploit_batches, 1);
+        let candidates = config
+            .candidates
+            .iter()
+            .map(|threshold| CandidateStats {
+                threshold: *threshold,
+                ..Default::default()
+            })
+            .collect::<Vec<CandidateStats>>();
+        let first = config.candidates[0];
+        AdaptiveThresholdController {
+            batches_left: config.sample_batches,
+            config,
+            phase: Phase::Explore { candidate_idx: 0 },
+            cur_cycles: 0,
+            cur_requests: 0,
+            batch_start: 0,
+            best_idx: 0,
+            stats: AdaptiveThresholdStats {
+                current_threshold: first,
+                best_threshold: first,
+                rounds: 0,
+                switches: 0,
+                candidates,
+            },
+        }
+    }
+
+    /// Installs the first candidate on the datapath; call once before processing requests.
+    pub fn init<D: Datapath>(&mut self, datapath: &mut D) {
+        let threshold = self.current_candidate();
+        self.install(datapath, threshold);
+    }
+
+    #[inline]
+    fn current_candidate(&self) -> usize {
+        match self.phase {
+            Phase::Explore { candidate_idx } => self.config.candidates[candidate_idx],
+            Phase::Exploit => self.config.candidates[self.best_idx],
+        }
+    }
+
+    fn install<D: Datapath>(&mut self, datapath: &mut D, threshold: usize) {
+        if datapath.get_copying_threshold() != threshold {
+            datapath.set_copying_threshold(threshold);
+        }
+        self.stats.current_threshold = threshold;
+    }
+
+    /// Marks the start of processing a batch of requests.
+    #[inline]
+    pub fn start_batch<D: Datapath>(&mut self, datapath: &D) {
+        if let Phase::Explore { .. } = self.phase {
+            self.batch_start = datapath.current_cycles();
+        }
+    }
+
+    /// Marks the end of processing a batch of `num_requests` requests; may change the
+    /// datapath's copying threshold.
+    #[inline]
+    pub fn end_batch<D: Datapath>(&mut self, datapath: &mut D, num_requests: usize) {
+        let batch_cycles = match self.phase {
+            Phase::Explore { .. } => datapath.current_cycles() - self.batch_start,
+            Phase::Exploit => 0,
+        };
+        if let Some(threshold) = self.record_batch(batch_cycles, num_requests, |cycles| {
+            datapath.cycles_to_ns(cycles)
+        }) {
+            self.install(datapath, threshold);
+        }
+    }
+
+    /// Accounts for a batch of `num_requests` requests that took `batch_cycles` to process.
+    /// Returns the threshold to install if the batch ended the current phase (or candidate).
+    fn record_batch<F: Fn(u64) -> u64>(
+        &mut self,
+        batch_cycles: u64,
+        num_requests: usize,
+        cycles_to_ns: F,
+    ) -> Option<usize> {
+        if let Phase::Explore { .. } = self.phase {
+            self.cur_cycles += batch_cycles;
+            self.cur_requests += num_requests as u64;
+        }
+        self.batches_left -= 1;
+        if self.batches_left == 0 {
+            self.advance(cycles_to_ns);
+            return Some(self.current_candidate());
+        }
+        None
+    }
+
+    fn advance<F: Fn(u64) -> u64>(&mut self, cycles_to_ns: F) {
+        match self.phase {
+            Phase::Explore { candidate_idx } => {
+                self.record_sample(cycles_to_ns, candidate_idx);
+                if candidate_idx + 1 < self.config.candidates.len() {
+                    self.phase = Phase::Explore {
+                        candidate_idx: candidate_idx + 1,
+                    };
+                    self.batches_left = self.config.sample_batches;
+                } else {
+                    self.finish_round();
+                    self.phase = Phase::Exploit;
+                    self.batches_left = self.config.exploit_batches;
+                }
+            }
+            Phase::Exploit => {
+                self.phase = Phase::Explore { candidate_idx: 0 };
+                self.batches_left = self.config.sample_batches;
+            }
+        }
+    }
+
+    fn record_sample<F: Fn(u64) -> u64>(&mut self, cycles_to_ns: F, candidate_idx: usize) {
+        let cycles = std::mem::take(&mut self.cur_cycles);
+        let requests = std::mem::take(&mut self.cur_requests);
+        if requests == 0 {
+            return;
+        }
+        let sample = cycles_to_ns(cycles) as f64 / requests as f64;
+        let candidate = &mut self.stats.candidates[candidate_idx];
+        candidate.requests_sampled += requests;
+        candidate.avg_ns_per_request = Some(match candidate.avg_ns_per_request {
+            Some(avg) => (1.0 - COST_SMOOTHING) * avg + COST_SMOOTHING * sample,
+            None => sample,
+        });
+    }
+
+    fn finish_round(&mut self) {
+        let best = self
+            .stats
+            .candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, candidate)| candidate.avg_ns_per_request.map(|avg| (idx, avg)))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        if let Some((idx, _)) = best {
+            if idx != self.best_idx {
+                self.stats.switches += 1;
+            }
+            self.best_idx = idx;
+        }
+        self.stats.best_threshold = self.config.candidates[self.best_idx];
+        self.stats.rounds += 1;
+        tracing::debug!(
+            best_threshold = self.stats.best_threshold,
+            rounds = self.stats.rounds,
+            "Finished adaptive copying threshold round"
+        );
+    }
+
+    pub fn best_threshold(&self) -> usize {
+        self.stats.best_threshold
+    }
+
+    pub fn stats(&self) -> &AdaptiveThresholdStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(candidates: Vec<usize>) -> AdaptiveThresholdConfig {
+        AdaptiveThresholdConfig {
+            candidates,
+            sample_batches: 2,
+            exploit_batches: 3,
+        }
+    }
+
+    /// Runs one explore round in which candidate `i` costs `costs[i]` cycles per request.
+    fn explore_round(controller: &mut AdaptiveThresholdController, costs: &[u64]) -> Vec<usize> {
+        let mut installed = vec![];
+        for cost in costs.iter() {
+            for _ in 0..controller.config.sample_batches {
+                if let Some(threshold) = controller.record_batch(cost * 4, 4, |cycles| cycles) {
+                    installed.push(threshold);
+                }
+            }
+        }
+        installed
+    }
+
+    #[test]
+    fn config_is_normalized() {
+        let controller = AdaptiveThresholdController::new(AdaptiveThresholdConfig {
+            candidates: vec![512, 0, 512, 128],
+            sample_batches: 0,
+            exploit_batches: 0,
+        });
+        assert_eq!(controller.config.candidates, vec![0, 128, 512]);
+        assert_eq!(controller.config.sample_batches, 1);
+        assert_eq!(controller.config.exploit_batches, 1);
+        assert_eq!(controller.current_candidate(), 0);
+
+        let controller = AdaptiveThresholdController::new(config(vec![]));
+        assert_eq!(
+            controller.config.candidates,
+            DEFAULT_CANDIDATE_THRESHOLDS.to_vec()
+        );
+    }
+
+    #[test]
+    fn explores_each_candidate_then_exploits_cheapest() {
+        let mut controller = AdaptiveThresholdController::new(config(vec![0, 256, 1024]));
+        // each candidate is installed in turn, then the cheapest one
+        assert_eq!(
+            explore_round(&mut controller, &[300, 100, 200]),
+            vec![256, 1024, 256]
+        );
+        assert_eq!(controller.best_threshold(), 256);
+        assert_eq!(controller.stats().rounds, 1);
+        assert_eq!(controller.stats().switches, 1);
+        assert_eq!(
+            controller.stats().candidates[1].avg_ns_per_request,
+            Some(100.0)
+        );
+        assert_eq!(controller.stats().candidates[1].requests_sampled, 8);
+
+        // batches in the exploit phase are not sampled; the last one starts a new round
+        assert_eq!(controller.record_batch(1_000_000, 4, |cycles| cycles), None);
+        assert_eq!(controller.record_batch(1_000_000, 4, |cycles| cycles), None);
+        assert_eq!(
+            controller.record_batch(1_000_000, 4, |cycles| cycles),
+            Some(0)
+        );
+        assert_eq!(controller.stats().candidates[1].requests_sampled, 8);
+    }
+
+    #[test]
+    fn follows_workload_shifts() {
+        let mut controller = AdaptiveThresholdController::new(config(vec![0, 256]));
+        explore_round(&mut controller, &[100, 300]);
+        assert_eq!(controller.best_threshold(), 0);
+        for _ in 0..3 {
+            controller.record_batch(0, 4, |cycles| cycles);
+        }
+        // costs are smoothed across rounds: 0 averages to 200 and 256 to 200 then 150
+        explore_round(&mut controller, &[300, 100]);
+        assert_eq!(
+            controller.stats().candidates[0].avg_ns_per_request,
+            Some(200.0)
+        );
+        assert_eq!(
+            controller.stats().candidates[1].avg_ns_per_request,
+            Some(200.0)
+        );
+        for _ in 0..3 {
+            controller.record_batch(0, 4, |cycles| cycles);
+        }
+        explore_round(&mut controller, &[300, 100]);
+        assert_eq!(controller.best_threshold(), 256);
+        assert_eq!(controller.stats().rounds, 3);
+        assert_eq!(controller.stats().switches, 1);
+    }
+
+    #[test]
+    fn candidates_without_requests_are_not_picked() {
+        let mut controller = AdaptiveThresholdController::new(config(vec![0, 256]));
+        for _ in 0..2 {
+            controller.record_batch(1000, 0, |cycles| cycles);
+        }
+        for _ in 0..2 {
+            controller.record_batch(4000, 4, |cycles| cycles);
+        }
+        assert_eq!(controller.stats().candidates[0].avg_ns_per_request, None);
+        assert_eq!(controller.best_threshold(), 256);
+    }
+}
@@ -0,0 +1,378 @@
+//! Admission control for servers.
+//!
+//! An `AdmissionController` sits between `Datapath::pop` and request processing in the
+//! `ServerSM` loop, and decides which received requests are processed:
+//!  1. Rate limiting: each connection has a token bucket, refilled at a fixed rate, and requests
+//!     that find their connection's bucket empty are shed. One aggressive client then cannot
+//!     monopolize a server thread.
+//!  2. Load shedding: admitted requests wait in a backlog, from which the server processes one
+//!     batch per poll. Requests that arrive while the backlog is at the queue-depth threshold are
+//!     shed, bounding the queueing delay of the requests that are processed.
+//!
+//! Shed requests are answered with `OVERLOADED_REPLY`, so clients can tell overload apart from
+//! loss, and counted in `AdmissionStats`.
+use super::{
+    datapath::{Datapath, ReceivedPkt},
+    ConnID, MsgID,
+};
+use color_eyre::eyre::Result;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, ops::AddAssign, time::Instant};
+
+/// Payload of the reply to a shed request; the leading zero byte keeps it from looking like a
+/// text (e.g., redis) response.
+pub const OVERLOADED_REPLY: &[u8] = b"\0CORNFLAKES_OVERLOADED";
+/// Requests a connection may send back to back, above its sustained rate, by default.
+pub const DEFAULT_BURST: u64 = 32;
+
+/// Whether a response payload is a server's `OVERLOADED_REPLY`.
+#[inline]
+pub fn is_overloaded_reply(payload: &[u8]) -> bool {
+    payload == OVERLOADED_REPLY
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdmissionConfig {
+    /// Sustained requests per second admitted from each connection; `None` disables rate
+    /// limiting.
+    pub rate_per_conn: Option<f64>,
+    /// Requests a connection may send back to back, above its sustained rate.
+    pub burst: u64,
+    /// Admitted requests that may wait to be processed; `None` disables load shedding (and
+    /// queueing: every admitted request is processed in the poll that received it).
+    pub max_queue_depth: Option<usize>,
+    /// Whether shed requests are answered with `OVERLOADED_REPLY` (or dropped silently).
+    pub reply_when_shed: bool,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        AdmissionConfig {
+            rate_per_conn: None,
+            burst: DEFAULT_BURST,
+            max_queue_depth: None,
+            reply_when_shed: true,
+        }
+    }
+}
+
+impl AdmissionConfig {
+    /// Admission control settings from a server's flags, if rate limiting or load shedding is
+    /// on.
+    pub fn from_flags(
+        rate_per_conn: Option<f64>,
+        burst: u64,
+        max_queue_depth: Option<usize>,
+    ) -> Option<Self> {
+        if rate_per_conn.is_none() && max_queue_depth.is_none() {
+            return None;
+        }
+        Some(AdmissionConfig {
+            rate_per_conn,
+            burst,
+            max_queue_depth,
+            ..Default::default()
+        })
+    }
+}
+
+/// Token bucket holding up to `burst` tokens, one of which each admitted request takes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(burst: f64, now: Instant) -> Self {
+        TokenBucket {
+            tokens: burst,
+            last_refill: now,
+        }
+    }
+
+    /// Refills the bucket for the time since the last refill, and takes a token if there is one.
+    #[inline]
+    pub(crate) fn try_take(&mut self, rate: f64, burst: f64, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = f64::min(burst, self.tokens + elapsed * rate);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Counts of requests an `AdmissionController` admitted or shed.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct AdmissionStats {
+    pub admitted: u64,
+    /// Requests shed because their connection exceeded its rate.
+    pub rate_limited: u64,
+    /// Requests shed because the backlog was at its queue-depth threshold.
+    pub overloaded: u64,
+}
+
+impl AdmissionStats {
+    pub fn shed(&self) -> u64 {
+        self.rate_limited + self.overloaded
+    }
+
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            admitted = self.admitted,
+            rate_limited = self.rate_limited,
+            overloaded = self.overloaded,
+            "{}",
+            msg
+        );
+    }
+}
+
+impl std::ops::Add for AdmissionStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        AdmissionStats {
+            admitted: self.admitted + other.admitted,
+            rate_limited: self.rate_limited + other.rate_limited,
+            overloaded: self.overloaded + other.overloaded,
+        }
+    }
+}
+
+impl AddAssign for AdmissionStats {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+/// Admitted requests waiting to be processed, up to an optional depth.
+#[derive(Debug, Clone)]
+struct RequestQueue<T> {
+    queue: VecDeque<T>,
+    max_depth: Option<usize>,
+}
+
+impl<T> RequestQueue<T> {
+    fn new(max_depth: Option<usize>) -> Self {
+        RequestQueue {
+            queue: VecDeque::default(),
+            max_depth,
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Whether a bounded queue can take another request; an unbounded queue does not hold
+    /// requests across polls, so never asks for more.
+    #[inline]
+    fn has_room(&self) -> bool {
+        match self.max_depth {
+            Some(max_depth) => self.queue.len() < max_depth,
+            None => false,
+        }
+    }
+
+    /// Whether a bounded queue is at its depth.
+    #[inline]
+    fn is_full(&self) -> bool {
+        matches!(self.max_depth, Some(max_depth) if self.queue.len() >= max_depth)
+    }
+
+    #[inline]
+    fn push(&mut self, req: T) {
+        self.queue.push_back(req);
+    }
+
+    /// Takes the oldest requests to process: up to `batch_size` from a bounded queue, and all of
+    /// them from an unbounded one.
+    fn next_batch(&mut self, batch_size: usize) -> Vec<T> {
+        let num = match self.max_depth {
+            Some(_) => std::cmp::min(batch_size, self.queue.len()),
+            None => self.queue.len(),
+        };
+        self.queue.drain(0..num).collect()
+    }
+}
+
+/// Decides which requests one server thread processes, and holds admitted requests until they
+/// are processed.
+#[derive(Debug)]
+pub struct AdmissionController<D>
+where
+    D: Datapath,
+{
+    config: AdmissionConfig,
+    buckets: HashMap<ConnID, TokenBucket>,
+    backlog: RequestQueue<ReceivedPkt<D>>,
+    stats: AdmissionStats,
+}
+
+impl<D> Default for AdmissionController<D>
+where
+    D: Datapath,
+{
+    fn default() -> Self {
+        AdmissionController::new(AdmissionConfig::default())
+    }
+}
+
+impl<D> AdmissionController<D>
+where
+    D: Datapath,
+{
+    pub fn new(mut config: AdmissionConfig) -> Self {
+        config.burst = std::cmp::max(config.burst, 1);
+        AdmissionController {
+            config,
+            buckets: HashMap::default(),
+            backlog: RequestQueue::new(config.max_queue_depth),
+            stats: AdmissionStats::default(),
+        }
+    }
+
+    /// Whether the backlog has room for more requests, i.e., the server should keep draining its
+    /// receive queue into the backlog this poll (so requests beyond the queue depth are shed
+    /// rather than left waiting in the receive queue).
+    pub fn has_room(&self) -> bool {
+        self.backlog.has_room()
+    }
+
+    /// Requests admitted but not yet processed.
+    pub fn backlog_len(&self) -> usize {
+        self.backlog.len()
+    }
+
+    /// Queues the admitted requests among those just received; the rest are shed (and answered,
+    /// if configured to).
+    pub fn enqueue(&mut self, pkts: Vec<ReceivedPkt<D>>, datapath: &mut D) -> Result<()> {
+        let now = Instant::now();
+        let burst = self.config.burst as f64;
+        let mut shed: Vec<(MsgID, ConnID)> = Vec::new();
+        for pkt in pkts.into_iter() {
+            // shed without taking a token, so a request shed for load is not charged to its
+            // connection's rate
+            if self.backlog.is_full() {
+                self.stats.overloaded += 1;
+                shed.push((pkt.msg_id(), pkt.conn_id()));
+                continue;
+            }
+            if let Some(rate) = self.config.rate_per_conn {
+                let bucket = self
+                    .buckets
+                    .entry(pkt.conn_id())
+                    .or_insert_with(|| TokenBucket::new(burst, now));
+                if !bucket.try_take(rate, burst, now) {
+                    self.stats.rate_limited += 1;
+                    shed.push((pkt.msg_id(), pkt.conn_id()));
+                    continue;
+                }
+            }
+            self.stats.admitted += 1;
+            self.backlog.push(pkt);
+        }
+
+        if self.config.reply_when_shed && !shed.is_empty() {
+            tracing::debug!(num_shed = shed.len(), "Replying to shed requests");
+            let replies = shed
+                .iter()
+                .map(|(msg_id, conn_id)| (*msg_id, *conn_id, OVERLOADED_REPLY))
+                .collect::<Vec<(MsgID, ConnID, &[u8])>>();
+            datapath.push_buffers_with_copy(replies.as_slice())?;
+        }
+        Ok(())
+    }
+
+    /// Takes the next requests to process out of the backlog: up to one batch if the backlog is
+    /// bounded, and every admitted request otherwise.
+    pub fn next_batch(&mut self) -> Vec<ReceivedPkt<D>> {
+        self.backlog.next_batch(D::batch_size())
+    }
+
+    pub fn config(&self) -> &AdmissionConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &AdmissionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn token_bucket_allows_burst_then_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(4.0, start);
+        for _ in 0..4 {
+            assert!(bucket.try_take(100.0, 4.0, start));
+        }
+        assert!(!bucket.try_take(100.0, 4.0, start));
+
+        // 100 requests per second: one token every 10 ms
+        let later = start + Duration::from_millis(25);
+        assert!(bucket.try_take(100.0, 4.0, later));
+        assert!(bucket.try_take(100.0, 4.0, later));
+        assert!(!bucket.try_take(100.0, 4.0, later));
+
+        // refills cap at the burst
+        let much_later = later + Duration::from_secs(10);
+        for _ in 0..4 {
+            assert!(bucket.try_take(100.0, 4.0, much_later));
+        }
+        assert!(!bucket.try_take(100.0, 4.0, much_later));
+    }
+
+    #[test]
+    fn bounded_queue_holds_requests_across_batches() {
+        let mut queue = RequestQueue::new(Some(4));
+        for req in 0..4 {
+            assert!(queue.has_room());
+            assert!(!queue.is_full());
+            queue.push(req);
+        }
+        assert!(!queue.has_room());
+        assert!(queue.is_full());
+
+        // the oldest requests are processed first, one batch at a time
+        assert_eq!(queue.next_batch(3), vec![0, 1, 2]);
+        assert_eq!(queue.len(), 1);
+        assert!(!queue.is_full());
+        queue.push(5);
+        assert_eq!(queue.next_batch(3), vec![3, 5]);
+        assert!(queue.next_batch(3).is_empty());
+    }
+
+    #[test]
+    fn unbounded_queue_processes_every_request() {
+        let mut queue = RequestQueue::new(None);
+        for req in 0..10 {
+            queue.push(req);
+            assert!(!queue.is_full());
+        }
+        // nothing is held across polls, so there is never room to drain more into
+        assert!(!queue.has_room());
+        assert_eq!(queue.next_batch(3), (0..10).collect::<Vec<_>>());
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn overloaded_reply_is_recognized() {
+        assert!(is_overloaded_reply(OVERLOADED_REPLY));
+        assert!(!is_overloaded_reply(b"CORNFLAKES_OVERLOADED"));
+        assert!(!is_overloaded_reply(b""));
+    }
+}
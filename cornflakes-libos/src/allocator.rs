@@ -1,11 +1,23 @@
-use super::{datapath::Datapath, mem};
+use super::{
+    datapath::{Datapath, MIN_MEMPOOL_BUF_SIZE},
+    mem,
+};
 use ahash::AHashMap;
-use color_eyre::eyre::{Result, WrapErr};
+use color_eyre::eyre::{bail, ensure, Result, WrapErr};
 #[cfg(feature = "profiler")]
 use demikernel;
 use hashbrown::HashMap;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, str::FromStr};
 pub type MempoolID = u32;
+
+/// Growth (in percent) between consecutive geometric size classes.
+pub const DEFAULT_GEOMETRIC_GROWTH_PERCENT: usize = 125;
+/// Geometric size classes are multiples of this (and power of 2 below it).
+const GEOMETRIC_CLASS_ALIGNMENT: usize = 64;
+/// Largest geometric size class (items never straddle a 2MB page).
+const MAX_GEOMETRIC_CLASS: usize = mem::PGSIZE_2MB;
+
 pub fn align_to_pow2(x: usize) -> usize {
     if x & (x - 1) == 0 {
         return x + (x == 0) as usize;
@@ -33,6 +45,155 @@ pub fn align_up(x: usize, align_size: usize) -> usize {
     }
 }
 
+/// How requested buffer sizes are rounded up to mempool item sizes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeClasses {
+    /// Next power of 2 (up to 2x internal fragmentation).
+    PowerOfTwo,
+    /// Classes grow by the given percentage (e.g., 125 for 1.25x), aligned to 64 bytes; sizes
+    /// below 64 bytes and above 2MB use powers of 2.
+    Geometric { growth_percent: usize },
+}
+
+impl Default for SizeClasses {
+    fn default() -> Self {
+        SizeClasses::PowerOfTwo
+    }
+}
+
+impl FromStr for SizeClasses {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<SizeClasses> {
+        match s {
+            "pow2" | "power_of_two" | "PowerOfTwo" => Ok(SizeClasses::PowerOfTwo),
+            "geometric" | "Geometric" => Ok(SizeClasses::Geometric {
+                growth_percent: DEFAULT_GEOMETRIC_GROWTH_PERCENT,
+            }),
+            x => match x.strip_prefix("geometric:") {
+                Some(percent) => {
+                    let growth_percent = percent.parse::<usize>()?;
+                    ensure!(
+                        growth_percent > 100,
+                        "Geometric size class growth must be above 100 percent, got {}",
+                        growth_percent
+                    );
+                    Ok(SizeClasses::Geometric { growth_percent })
+                }
+                None => {
+                    bail!("Unknown size classes: {:?}", x);
+                }
+            },
+        }
+    }
+}
+
+impl SizeClasses {
+    /// Size classes between 64 bytes and 2MB, in increasing order; empty for powers of 2.
+    pub fn table(&self) -> Vec<usize> {
+        match self {
+            SizeClasses::PowerOfTwo => Vec::default(),
+            SizeClasses::Geometric { growth_percent } => {
+                let mut classes = vec![GEOMETRIC_CLASS_ALIGNMENT];
+                let mut class = GEOMETRIC_CLASS_ALIGNMENT;
+                while class < MAX_GEOMETRIC_CLASS {
+                    let next = align_up(class * growth_percent / 100, GEOMETRIC_CLASS_ALIGNMENT);
+                    class = std::cmp::min(
+                        std::cmp::max(next, class + GEOMETRIC_CLASS_ALIGNMENT),
+                        MAX_GEOMETRIC_CLASS,
+                    );
+                    classes.push(class);
+                }
+                classes
+            }
+        }
+    }
+}
+
+/// Rounds size up to its class in the given table (see `SizeClasses::table`).
+#[inline]
+fn size_class_in_table(table: &[usize], size: usize) -> usize {
+    let size = std::cmp::max(size, MIN_MEMPOOL_BUF_SIZE);
+    if table.is_empty() || size <= table[0] || size > table[table.len() - 1] {
+        return align_to_pow2(size);
+    }
+    table[table.partition_point(|class| *class < size)]
+}
+
+/// Compares requested allocation sizes to the size classes that served them.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AllocatorMemoryStats {
+    /// Buffers allocated from application mempools.
+    pub num_allocations: u64,
+    /// Sum of requested sizes of those buffers.
+    pub requested_bytes: u64,
+    /// Sum of size classes (mempool item sizes) of those buffers.
+    pub class_bytes: u64,
+    /// Bytes of pages currently backing application mempools.
+    pub backing_bytes: u64,
+}
+
+impl AllocatorMemoryStats {
+    /// Fraction of allocated item bytes that were requested (1.0 means no internal
+    /// fragmentation).
+    pub fn efficiency(&self) -> f64 {
+        if self.class_bytes == 0 {
+            return 1.0;
+        }
+        self.requested_bytes as f64 / self.class_bytes as f64
+    }
+
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            num_allocations = self.num_allocations,
+            requested_bytes = self.requested_bytes,
+            class_bytes = self.class_bytes,
+            backing_bytes = self.backing_bytes,
+            efficiency = self.efficiency(),
+            "{}",
+            msg
+        );
+    }
+}
+
+/// Parameters for mempools a datapath allocates on behalf of the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolConfig {
+    /// Number of 2MB pages backing each newly added mempool.
+    pub num_pages: usize,
+    /// Number of registration units each mempool is split into.
+    pub num_registrations: usize,
+    /// Whether mempool memory is registered when the mempool is added (or lazily).
+    pub register_at_start: bool,
+    /// Whether `allocate` adds a mempool when a size class is exhausted.
+    pub auto_grow: bool,
+    /// Ceiling on memory held by application mempools (not counting rx and tx pools); only
+    /// enforced for automatic growth.
+    pub max_memory_bytes: Option<usize>,
+    /// Size classes for application mempools; can only change before any are added.
+    pub size_classes: SizeClasses,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        MempoolConfig {
+            num_pages: 64,
+            num_registrations: 1,
+            register_at_start: true,
+            auto_grow: false,
+            max_memory_bytes: None,
+            size_classes: SizeClasses::default(),
+        }
+    }
+}
+
+impl MempoolConfig {
+    /// Bytes backing one mempool added with this config.
+    pub fn mempool_bytes(&self) -> usize {
+        self.num_pages * mem::PGSIZE_2MB
+    }
+}
+
 /// Trait that datapath's can implement to pr
 pub trait DatapathMemoryPool {
     type DatapathImpl: Datapath;
@@ -98,6 +259,26 @@ where
 
     /// Cache from allocated address -> corresponding mempool ID for 1G pages.
     address_cache_1g: AHashMap<usize, MempoolID>,
+
+    /// Map from mempool ID to (aligned size class, bytes of backing pages), for mempools
+    /// added with `add_mempool`.
+    mempool_sizes: HashMap<MempoolID, (usize, usize)>,
+
+    /// Bytes of backing pages across mempools added with `add_mempool`.
+    allocated_bytes: usize,
+
+    /// Parameters for mempools added on behalf of the application.
+    config: MempoolConfig,
+
+    /// Size classes for the configured policy (empty for powers of 2).
+    size_class_table: Vec<usize>,
+
+    /// Requested versus size class bytes of buffers allocated so far.
+    memory_stats: AllocatorMemoryStats,
+
+    /// IDs of application-owned regions registered for zero-copy (kept in `mempools` and the
+    /// address caches, but never allocated from).
+    external_regions: HashSet<MempoolID>,
 }
 
 impl<M> MemoryPoolAllocator<M>
@@ -132,9 +313,69 @@ where
             address_cache_2mb,
             address_cache_4k,
             address_cache_1g,
+            mempool_sizes: HashMap::default(),
+            allocated_bytes: 0,
+            config: MempoolConfig::default(),
+            size_class_table: Vec::default(),
+            memory_stats: AllocatorMemoryStats::default(),
+            external_regions: HashSet::default(),
         })
     }
 
+    #[inline]
+    pub fn config(&self) -> MempoolConfig {
+        self.config
+    }
+
+    /// Sets parameters for mempools added from now on. Size classes cannot change once
+    /// application mempools exist.
+    pub fn set_config(&mut self, config: MempoolConfig) -> Result<()> {
+        if config.size_classes != self.config.size_classes {
+            ensure!(
+                self.mempool_ids.is_empty(),
+                "Cannot change size classes from {:?} to {:?} with mempools allocated",
+                self.config.size_classes,
+                config.size_classes
+            );
+            self.size_class_table = config.size_classes.table();
+        }
+        self.config = config;
+        Ok(())
+    }
+
+    /// Item size of the mempools that buffers of the given size are allocated from.
+    #[inline]
+    pub fn size_class(&self, size: usize) -> usize {
+        size_class_in_table(&self.size_class_table, size)
+    }
+
+    #[inline]
+    pub fn memory_stats(&self) -> AllocatorMemoryStats {
+        AllocatorMemoryStats {
+            backing_bytes: self.allocated_bytes as u64,
+            ..self.memory_stats
+        }
+    }
+
+    /// Bytes of backing pages across mempools the application can allocate from.
+    #[inline]
+    pub fn allocated_bytes(&self) -> usize {
+        self.allocated_bytes
+    }
+
+    /// Whether `allocate_buffer` misses may be served by adding a mempool of
+    /// `additional_bytes`, given the configured memory ceiling.
+    #[inline]
+    pub fn can_grow(&self, additional_bytes: usize) -> bool {
+        if !self.config.auto_grow {
+            return false;
+        }
+        match self.config.max_memory_bytes {
+            Some(max) => self.allocated_bytes + additional_bytes <= max,
+            None => true,
+        }
+    }
+
     #[inline]
     pub fn is_registered(&self, seg: &[u8]) -> bool {
         if let Some(_) = self.find_mempool_id(seg) {
@@ -178,35 +419,197 @@ where
     #[inline]
     pub fn add_mempool(&mut self, size: usize, handle: M) -> Result<MempoolID> {
         tracing::debug!("Adding mempool");
+        let id = self.next_id_to_allocate;
+        let aligned_size = self.size_class(size);
+        self.insert_mempool(id, aligned_size, handle);
+        self.next_id_to_allocate += 1;
+        tracing::info!("Adding mempool of size {}", aligned_size);
+        Ok(id)
+    }
+
+    fn insert_mempool(&mut self, id: MempoolID, aligned_size: usize, handle: M) {
         // add the mempool into the address cache
         for page in handle.get_2mb_pages().into_iter() {
-            self.address_cache_2mb
-                .insert(page, self.next_id_to_allocate);
+            self.address_cache_2mb.insert(page, id);
         }
         for page in handle.get_4k_pages().into_iter() {
-            self.address_cache_4k.insert(page, self.next_id_to_allocate);
+            self.address_cache_4k.insert(page, id);
         }
         for page in handle.get_1g_pages().into_iter() {
-            self.address_cache_1g.insert(page, self.next_id_to_allocate);
+            self.address_cache_1g.insert(page, id);
         }
 
-        let aligned_size = align_to_pow2(size);
-        tracing::info!("Adding mempool of size {}", aligned_size);
-        match self.mempool_ids.get_mut(&aligned_size) {
-            Some(mempool_ids_list) => {
-                mempool_ids_list.insert(self.next_id_to_allocate);
-                self.mempools.insert(self.next_id_to_allocate, handle);
-                self.next_id_to_allocate += 1;
-            }
+        let bytes = mempool_bytes(&handle);
+        self.mempool_sizes.insert(id, (aligned_size, bytes));
+        self.allocated_bytes += bytes;
+        self.mempool_ids
+            .entry(aligned_size)
+            .or_insert_with(HashSet::default)
+            .insert(id);
+        self.mempools.insert(id, handle);
+    }
+
+    /// Removes the mempool with the given ID, if none of its buffers are still in use: its
+    /// pages are evicted from the address caches and it can no longer be allocated from.
+    /// Returns the mempool, so the datapath can decide when to unregister and free it, or None
+    /// if the mempool still has outstanding buffers.
+    pub fn remove_mempool(&mut self, id: MempoolID) -> Result<Option<M>> {
+        ensure!(id != 0, "Cannot remove receive mempool");
+        let (aligned_size, bytes) = match self.mempool_sizes.get(&id) {
+            Some(info) => *info,
             None => {
-                let mut set = HashSet::default();
-                set.insert(self.next_id_to_allocate);
-                self.mempool_ids.insert(aligned_size, set);
-                self.mempools.insert(self.next_id_to_allocate, handle);
-                self.next_id_to_allocate += 1;
+                bail!("No mempool with ID {}", id);
+            }
+        };
+        if self.mempools.get(&id).unwrap().has_allocated() {
+            tracing::debug!("Mempool {} still has outstanding buffers", id);
+            return Ok(None);
+        }
+
+        let handle = self.mempools.remove(&id).unwrap();
+        self.evict_pages(&handle, id);
+
+        if let Some(ids) = self.mempool_ids.get_mut(&aligned_size) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.mempool_ids.remove(&aligned_size);
+            }
+        }
+        self.mempool_sizes.remove(&id);
+        self.allocated_bytes -= bytes;
+        tracing::info!("Removed mempool {} of size {}", id, aligned_size);
+        Ok(Some(handle))
+    }
+
+    fn evict_pages(&mut self, handle: &M, id: MempoolID) {
+        for page in handle.get_2mb_pages().iter() {
+            if self.address_cache_2mb.get(page) == Some(&id) {
+                self.address_cache_2mb.remove(page);
+            }
+        }
+        for page in handle.get_4k_pages().iter() {
+            if self.address_cache_4k.get(page) == Some(&id) {
+                self.address_cache_4k.remove(page);
+            }
+        }
+        for page in handle.get_1g_pages().iter() {
+            if self.address_cache_1g.get(page) == Some(&id) {
+                self.address_cache_1g.remove(page);
             }
         }
-        Ok(self.next_id_to_allocate - 1)
+    }
+
+    /// Adds an application-owned region (wrapped as a mempool by the datapath) to the address
+    /// caches, so buffers within it are recognized as registered. Regions are never allocated
+    /// from, and cannot overlap with existing mempools or regions.
+    pub fn add_external_region(&mut self, handle: M) -> Result<MempoolID> {
+        let pages_2mb = handle.get_2mb_pages();
+        let pages_4k = handle.get_4k_pages();
+        let pages_1g = handle.get_1g_pages();
+        for page in pages_2mb
+            .iter()
+            .chain(pages_4k.iter())
+            .chain(pages_1g.iter())
+        {
+            let page_ptr = *page as *const u8;
+            ensure!(
+                self.find_mempool_id(unsafe { std::slice::from_raw_parts(page_ptr, 1) })
+                    .is_none(),
+                "External region page {:#x} overlaps with registered memory",
+                *page
+            );
+        }
+
+        let id = self.next_id_to_allocate;
+        for page in pages_2mb.into_iter() {
+            self.address_cache_2mb.insert(page, id);
+        }
+        for page in pages_4k.into_iter() {
+            self.address_cache_4k.insert(page, id);
+        }
+        for page in pages_1g.into_iter() {
+            self.address_cache_1g.insert(page, id);
+        }
+        self.mempools.insert(id, handle);
+        self.external_regions.insert(id);
+        self.next_id_to_allocate += 1;
+        tracing::info!("Added external region {}", id);
+        Ok(id)
+    }
+
+    /// Removes an application-owned region from the address caches, returning it so the
+    /// datapath can unregister it. The datapath must check that no metadata still references
+    /// the region.
+    pub fn remove_external_region(&mut self, id: MempoolID) -> Result<M> {
+        ensure!(
+            self.external_regions.remove(&id),
+            "No external region with ID {}",
+            id
+        );
+        let handle = self.mempools.remove(&id).unwrap();
+        self.evict_pages(&handle, id);
+        tracing::info!("Removed external region {}", id);
+        Ok(handle)
+    }
+
+    #[inline]
+    pub fn external_region(&self, id: MempoolID) -> Option<&M> {
+        match self.external_regions.contains(&id) {
+            true => self.mempools.get(&id),
+            false => None,
+        }
+    }
+
+    /// Returns metadata for this buffer if it lies entirely within an application-owned region.
+    #[inline]
+    pub fn recover_external_buffer(
+        &self,
+        buffer: &[u8],
+    ) -> Result<Option<<<M as DatapathMemoryPool>::DatapathImpl as Datapath>::DatapathMetadata>>
+    {
+        if self.external_regions.is_empty() {
+            return Ok(None);
+        }
+        match self.containing_mempool_id(buffer) {
+            Some(id) if self.external_regions.contains(&id) => {
+                self.recover_from_mempool(id, buffer)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// ID of the mempool or region this buffer lies entirely within, if any.
+    #[inline]
+    pub fn containing_mempool_id(&self, buffer: &[u8]) -> Option<MempoolID> {
+        if buffer.is_empty() {
+            return None;
+        }
+        let id = self.find_mempool_id(buffer)?;
+        match self.find_mempool_id(&buffer[buffer.len() - 1..]) == Some(id) {
+            true => Some(id),
+            false => None,
+        }
+    }
+
+    /// IDs of mempools with no outstanding buffers, leaving at least one mempool per size
+    /// class.
+    pub fn idle_mempool_ids(&self) -> Vec<MempoolID> {
+        let mut idle = Vec::default();
+        for ids in self.mempool_ids.values() {
+            let mut sorted_ids: Vec<MempoolID> = ids.iter().cloned().collect();
+            sorted_ids.sort();
+            let class_idle: Vec<MempoolID> = sorted_ids
+                .into_iter()
+                .filter(|id| !self.mempools.get(id).unwrap().has_allocated())
+                .collect();
+            if class_idle.len() == ids.len() {
+                // keep the oldest mempool so the size class stays available
+                idle.extend(class_idle.into_iter().skip(1));
+            } else {
+                idle.extend(class_idle);
+            }
+        }
+        idle
     }
 
     #[inline]
@@ -218,7 +621,7 @@ where
     }
 
     /// Allocates a datapath buffer (if a mempool is available).
-    /// Size will be aligned to the next power of 2. If no buffers available in that mempool, will
+    /// Size will be aligned to its size class. If no buffers available in that mempool, will
     /// return None.
     /// No guarantees on whether the resulting datapath buffer is registered or not.
     /// Adds buffer to address cache for possible recovery.
@@ -227,13 +630,16 @@ where
         &mut self,
         buf_size: usize,
     ) -> Result<Option<<<M as DatapathMemoryPool>::DatapathImpl as Datapath>::DatapathBuffer>> {
-        let align_size = align_to_pow2(buf_size);
+        let align_size = self.size_class(buf_size);
         match self.mempool_ids.get(&align_size) {
             Some(mempools) => {
                 for mempool_id in mempools.iter() {
                     let mempool = self.mempools.get(mempool_id).unwrap();
                     match mempool.alloc_data_buf()? {
                         Some(x) => {
+                            self.memory_stats.num_allocations += 1;
+                            self.memory_stats.requested_bytes += buf_size as u64;
+                            self.memory_stats.class_bytes += align_size as u64;
                             return Ok(Some(x));
                         }
                         None => {
@@ -306,7 +712,7 @@ where
 
     #[inline]
     pub fn has_mempool(&self, size: usize) -> bool {
-        return self.mempool_ids.contains_key(&size);
+        return self.mempool_ids.contains_key(&self.size_class(size));
     }
 
     #[inline]
@@ -316,3 +722,73 @@ where
         sizes
     }
 }
+
+/// Bytes of backing pages in the given mempool.
+fn mempool_bytes<M>(mempool: &M) -> usize
+where
+    M: DatapathMemoryPool,
+{
+    mempool.get_4k_pages().len() * mem::PGSIZE_4KB
+        + mempool.get_2mb_pages().len() * mem::PGSIZE_2MB
+        + mempool.get_1g_pages().len() * mem::PGSIZE_1GB
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_of_two_classes() {
+        let table = SizeClasses::PowerOfTwo.table();
+        assert!(table.is_empty());
+        assert_eq!(size_class_in_table(&table, 1), MIN_MEMPOOL_BUF_SIZE);
+        assert_eq!(size_class_in_table(&table, 4096), 4096);
+        assert_eq!(size_class_in_table(&table, 4097), 8192);
+    }
+
+    #[test]
+    fn geometric_classes() {
+        let table = SizeClasses::Geometric {
+            growth_percent: DEFAULT_GEOMETRIC_GROWTH_PERCENT,
+        }
+        .table();
+        assert_eq!(table[0], GEOMETRIC_CLASS_ALIGNMENT);
+        assert_eq!(*table.last().unwrap(), MAX_GEOMETRIC_CLASS);
+        for pair in table.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert_eq!(pair[1] % GEOMETRIC_CLASS_ALIGNMENT, 0);
+        }
+
+        // small sizes fall back to powers of 2
+        assert_eq!(size_class_in_table(&table, 3), MIN_MEMPOOL_BUF_SIZE);
+        assert_eq!(size_class_in_table(&table, 33), 64);
+        // sizes that are classes map to themselves
+        for class in table.iter() {
+            assert_eq!(size_class_in_table(&table, *class), *class);
+        }
+        // the next class is at most 1.25x (plus alignment) above the size
+        let class = size_class_in_table(&table, 4097);
+        assert!(class >= 4097 && class < 8192);
+        assert!(class <= 4097 * 5 / 4 + GEOMETRIC_CLASS_ALIGNMENT);
+        // sizes above the largest class use powers of 2
+        assert_eq!(
+            size_class_in_table(&table, MAX_GEOMETRIC_CLASS + 1),
+            2 * MAX_GEOMETRIC_CLASS
+        );
+    }
+
+    #[test]
+    fn parse_size_classes() {
+        assert_eq!(
+            SizeClasses::from_str("pow2").unwrap(),
+            SizeClasses::PowerOfTwo
+        );
+        assert_eq!(
+            SizeClasses::from_str("geometric:150").unwrap(),
+            SizeClasses::Geometric {
+                growth_percent: 150
+            }
+        );
+        assert!(SizeClasses::from_str("geometric:100").is_err());
+    }
+}
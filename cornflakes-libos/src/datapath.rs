@@ -3,13 +3,20 @@ use crate::{
 };
 
 use super::{
-    allocator::MempoolID, dynamic_rcsga_hybrid_hdr::HybridArenaRcSgaHdr,
-    dynamic_sga_hdr::SgaHeaderRepr, utils::AddressInfo, ArenaDatapathSga, ArenaOrderedRcSga,
-    ArenaOrderedSga, ConnID, CopyContext, MsgID, OrderedRcSga, OrderedSga, RcSga, Sga,
+    allocator::{AllocatorMemoryStats, MempoolConfig, MempoolID},
+    dynamic_rcsga_hybrid_hdr::HybridArenaRcSgaHdr,
+    dynamic_sga_hdr::SgaHeaderRepr,
+    encryption::EncryptionConfig,
+    pcap::PacketCapture,
+    rss::ServerQueueAssignment,
+    utils::{AddressInfo, FrameDrop},
+    ArenaDatapathSga, ArenaOrderedRcSga, ArenaOrderedSga, ConnID, CopyContext, MsgID, OrderedRcSga,
+    OrderedSga, RcSga, Sga,
 };
 use byteorder::{ByteOrder, LittleEndian};
 use color_eyre::eyre::{bail, Result};
-use std::{io::Write, net::Ipv4Addr, str::FromStr, time::Duration};
+use serde::{Deserialize, Serialize};
+use std::{io::Write, net::IpAddr, ops::AddAssign, str::FromStr, time::Duration};
 
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum InlineMode {
@@ -18,23 +25,8 @@ pub enum InlineMode {
     ObjectHeader,
 }
 
-pub static mut NUM_PAGES: usize = 64;
-pub static mut NUM_REGISTRATIONS: usize = 1;
-pub static mut REGISTER_AT_START: bool = true;
 pub const MIN_MEMPOOL_BUF_SIZE: usize = 8;
 
-pub fn set_mempool_params(
-    num_pages_per_mempool: usize,
-    num_registrations: usize,
-    register_at_start: bool,
-) {
-    unsafe {
-        NUM_PAGES = num_pages_per_mempool;
-        NUM_REGISTRATIONS = num_registrations;
-        REGISTER_AT_START = register_at_start;
-    }
-}
-
 pub fn pad_mempool_size(size: usize) -> usize {
     if size < MIN_MEMPOOL_BUF_SIZE {
         return MIN_MEMPOOL_BUF_SIZE;
@@ -69,6 +61,289 @@ impl FromStr for InlineMode {
     }
 }
 
+/// Where UDP checksums are generated on transmit and validated on receive.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum ChecksumMode {
+    /// Checksum fields are left zero and not checked.
+    Disabled,
+    /// Computed over the header and every scatter-gather entry, and validated, on the CPU.
+    Software,
+    /// Computed and validated by the NIC (or the kernel stack).
+    Offload,
+}
+
+impl ChecksumMode {
+    /// Offload where the datapath supports it, software everywhere else.
+    pub fn preferred(offload_capable: bool) -> Self {
+        match offload_capable {
+            true => ChecksumMode::Offload,
+            false => ChecksumMode::Software,
+        }
+    }
+}
+
+impl FromStr for ChecksumMode {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<ChecksumMode> {
+        match s {
+            "disabled" | "Disabled" | "DISABLED" | "none" => Ok(ChecksumMode::Disabled),
+            "software" | "Software" | "SOFTWARE" => Ok(ChecksumMode::Software),
+            "offload" | "Offload" | "OFFLOAD" => Ok(ChecksumMode::Offload),
+            x => {
+                bail!("Unknown checksum mode: {:?}", x);
+            }
+        }
+    }
+}
+
+/// Counts of how message payloads were actually transmitted by a datapath.
+/// Segments are counted once per transmitted message; packet headers written by the datapath
+/// (ethernet, ip, udp and message id) and encryption nonces and tags are not counted.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct TransmitStats {
+    /// Number of messages transmitted.
+    pub num_messages: u64,
+    /// Bytes copied into transmit buffers (below the copying threshold).
+    pub copied_bytes: u64,
+    /// Segments copied into transmit buffers (below the copying threshold).
+    pub copied_segments: u64,
+    /// Bytes posted directly from registered memory.
+    pub zero_copied_bytes: u64,
+    /// Segments posted directly from registered memory.
+    pub zero_copied_segments: u64,
+    /// Bytes inlined into the descriptor (see `InlineMode`).
+    pub inlined_bytes: u64,
+    /// Segments inlined into the descriptor (see `InlineMode`).
+    pub inlined_segments: u64,
+    /// Bytes at or above the copying threshold that were copied anyway, because they did not
+    /// live in registered memory.
+    pub fallback_bytes: u64,
+    /// Segments at or above the copying threshold that were copied anyway.
+    pub fallback_segments: u64,
+    /// Bytes copied into transmit buffers to be encrypted (see `Datapath::set_encryption`);
+    /// with encryption on, every payload byte is counted here instead.
+    pub encrypted_bytes: u64,
+    /// Segments copied into transmit buffers to be encrypted.
+    pub encrypted_segments: u64,
+    /// Encrypted bytes that would have been zero-copied without encryption.
+    pub encryption_forced_copy_bytes: u64,
+    /// Encrypted segments that would have been zero-copied without encryption.
+    pub encryption_forced_copy_segments: u64,
+}
+
+impl TransmitStats {
+    #[inline]
+    pub fn record_message(&mut self) {
+        self.num_messages += 1;
+    }
+
+    #[inline]
+    pub fn record_copy(&mut self, len: usize) {
+        self.copied_bytes += len as u64;
+        self.copied_segments += 1;
+    }
+
+    #[inline]
+    pub fn record_zero_copy(&mut self, len: usize) {
+        self.zero_copied_bytes += len as u64;
+        self.zero_copied_segments += 1;
+    }
+
+    #[inline]
+    pub fn record_inline(&mut self, len: usize) {
+        self.inlined_bytes += len as u64;
+        self.inlined_segments += 1;
+    }
+
+    #[inline]
+    pub fn record_fallback(&mut self, len: usize) {
+        self.fallback_bytes += len as u64;
+        self.fallback_segments += 1;
+    }
+
+    /// Records a segment copied to be encrypted; `zero_copy_eligible` is whether it would have
+    /// been zero-copied (at or above the copying threshold, in registered memory) otherwise.
+    #[inline]
+    pub fn record_encrypted_segment(&mut self, len: usize, zero_copy_eligible: bool) {
+        self.encrypted_bytes += len as u64;
+        self.encrypted_segments += 1;
+        if zero_copy_eligible {
+            self.encryption_forced_copy_bytes += len as u64;
+            self.encryption_forced_copy_segments += 1;
+        }
+    }
+
+    /// Records a segment that was copied: counts it as a fallback copy if it was large enough to
+    /// be zero-copied under the given threshold.
+    #[inline]
+    pub fn record_copied_segment(&mut self, len: usize, copying_threshold: usize) {
+        if len >= copying_threshold {
+            self.record_fallback(len);
+        } else {
+            self.record_copy(len);
+        }
+    }
+
+    /// Records a serialized cornflakes object whose entries were posted in a callback: the copied
+    /// fields share a single copy buffer, while each zero-copy entry is its own segment.
+    #[inline]
+    pub fn record_serialized_object(
+        &mut self,
+        header_len: usize,
+        inlined_header: bool,
+        copy_len: usize,
+        zero_copy_len: usize,
+        num_zero_copy_entries: usize,
+    ) {
+        self.record_message();
+        if header_len > 0 {
+            match inlined_header {
+                true => self.record_inline(header_len),
+                false => self.record_copy(header_len),
+            }
+        }
+        if copy_len > 0 {
+            self.record_copy(copy_len);
+        }
+        self.zero_copied_bytes += zero_copy_len as u64;
+        self.zero_copied_segments += num_zero_copy_entries as u64;
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.copied_bytes
+            + self.zero_copied_bytes
+            + self.inlined_bytes
+            + self.fallback_bytes
+            + self.encrypted_bytes
+    }
+
+    /// Fraction of payload bytes that were sent zero-copy.
+    pub fn zero_copy_fraction(&self) -> f64 {
+        match self.total_bytes() {
+            0 => 0.0,
+            total => self.zero_copied_bytes as f64 / total as f64,
+        }
+    }
+
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            num_messages = self.num_messages,
+            copied_bytes = self.copied_bytes,
+            copied_segments = self.copied_segments,
+            zero_copied_bytes = self.zero_copied_bytes,
+            zero_copied_segments = self.zero_copied_segments,
+            inlined_bytes = self.inlined_bytes,
+            inlined_segments = self.inlined_segments,
+            fallback_bytes = self.fallback_bytes,
+            fallback_segments = self.fallback_segments,
+            encrypted_bytes = self.encrypted_bytes,
+            encrypted_segments = self.encrypted_segments,
+            encryption_forced_copy_bytes = self.encryption_forced_copy_bytes,
+            encryption_forced_copy_segments = self.encryption_forced_copy_segments,
+            zero_copy_fraction = ?self.zero_copy_fraction(),
+            "{}",
+            msg
+        );
+    }
+}
+
+impl std::ops::Add for TransmitStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        TransmitStats {
+            num_messages: self.num_messages + other.num_messages,
+            copied_bytes: self.copied_bytes + other.copied_bytes,
+            copied_segments: self.copied_segments + other.copied_segments,
+            zero_copied_bytes: self.zero_copied_bytes + other.zero_copied_bytes,
+            zero_copied_segments: self.zero_copied_segments + other.zero_copied_segments,
+            inlined_bytes: self.inlined_bytes + other.inlined_bytes,
+            inlined_segments: self.inlined_segments + other.inlined_segments,
+            fallback_bytes: self.fallback_bytes + other.fallback_bytes,
+            fallback_segments: self.fallback_segments + other.fallback_segments,
+            encrypted_bytes: self.encrypted_bytes + other.encrypted_bytes,
+            encrypted_segments: self.encrypted_segments + other.encrypted_segments,
+            encryption_forced_copy_bytes: self.encryption_forced_copy_bytes
+                + other.encryption_forced_copy_bytes,
+            encryption_forced_copy_segments: self.encryption_forced_copy_segments
+                + other.encryption_forced_copy_segments,
+        }
+    }
+}
+
+impl AddAssign for TransmitStats {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+/// Counts of received packets a datapath dropped before handing them to the application.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct ReceiveStats {
+    /// Packets whose UDP checksum did not verify.
+    pub checksum_failures: u64,
+    /// IP fragments, which are dropped rather than reassembled.
+    pub fragments_dropped: u64,
+    /// Truncated frames, or frames whose header lengths do not fit the frame.
+    pub malformed_dropped: u64,
+    /// Messages that failed to authenticate (see `Datapath::set_encryption`).
+    pub decryption_failures: u64,
+}
+
+impl ReceiveStats {
+    #[inline]
+    pub fn record_checksum_failure(&mut self) {
+        self.checksum_failures += 1;
+    }
+
+    #[inline]
+    pub fn record_decryption_failure(&mut self) {
+        self.decryption_failures += 1;
+    }
+
+    /// Counts a frame the header parser rejected; frames for other addresses or protocols are
+    /// not counted.
+    #[inline]
+    pub fn record_drop(&mut self, reason: FrameDrop) {
+        match reason {
+            FrameDrop::Fragment => self.fragments_dropped += 1,
+            FrameDrop::Malformed => self.malformed_dropped += 1,
+            FrameDrop::NotForUs | FrameDrop::Unsupported => {}
+        }
+    }
+
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            checksum_failures = self.checksum_failures,
+            fragments_dropped = self.fragments_dropped,
+            malformed_dropped = self.malformed_dropped,
+            decryption_failures = self.decryption_failures,
+            "{}",
+            msg
+        );
+    }
+}
+
+impl std::ops::Add for ReceiveStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        ReceiveStats {
+            checksum_failures: self.checksum_failures + other.checksum_failures,
+            fragments_dropped: self.fragments_dropped + other.fragments_dropped,
+            malformed_dropped: self.malformed_dropped + other.malformed_dropped,
+            decryption_failures: self.decryption_failures + other.decryption_failures,
+        }
+    }
+}
+
+impl AddAssign for ReceiveStats {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
 /// Represents if app is using:
 /// (1) Scatter-gather API without manual ref counting
 /// (2) Manually Reference counted scatter-gather API
@@ -126,6 +401,19 @@ where
     conn: ConnID,
 }
 
+impl<D> std::fmt::Debug for ReceivedPkt<D>
+where
+    D: Datapath,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ReceivedPkt")
+            .field("pkts", &self.pkts)
+            .field("id", &self.id)
+            .field("conn", &self.conn)
+            .finish()
+    }
+}
+
 impl<D> ReceivedPkt<D>
 where
     D: Datapath,
@@ -152,6 +440,11 @@ where
         return false;
     }
 
+    /// Whether this is a server's `OVERLOADED_REPLY` to a shed request.
+    pub fn is_overloaded_reply(&self) -> bool {
+        self.pkts.len() == 1 && super::admission::is_overloaded_reply(self.pkts[0].as_ref())
+    }
+
     pub fn conn_id(&self) -> ConnID {
         self.conn
     }
@@ -312,7 +605,7 @@ pub trait Datapath {
     /// address).
     fn parse_config_file(
         config_file: &str,
-        our_ip: &Ipv4Addr,
+        our_ip: &IpAddr,
     ) -> Result<Self::DatapathSpecificParams>;
 
     /// Given a remote IP address, compute a source IP and port for each queue
@@ -321,10 +614,27 @@ pub trait Datapath {
     fn compute_affinity(
         datapath_params: &Self::DatapathSpecificParams,
         num_queues: usize,
-        remote_ip: Option<Ipv4Addr>,
+        remote_ip: Option<IpAddr>,
         app_mode: cornflakes_utils::AppMode,
     ) -> Result<Vec<AddressInfo>>;
 
+    /// Chooses the server queue each client queue should send to. Datapaths whose server queues
+    /// share one address, and are picked by RSS, then search in `compute_affinity` for client
+    /// source ports that hash to those queues.
+    fn set_server_queue_assignment(
+        _datapath_params: &mut Self::DatapathSpecificParams,
+        _assignment: ServerQueueAssignment,
+    ) -> Result<()> {
+        bail!("This datapath cannot steer client queues to particular server queues");
+    }
+
+    /// UDP port that server queue `queue_id` listens on, given the configured server port.
+    /// Datapaths that steer by destination port give each queue its own port; by default, all
+    /// queues share the configured port and packets are spread across them by RSS.
+    fn server_queue_port(server_port: u16, _queue_id: usize) -> u16 {
+        server_port
+    }
+
     /// Any global initialization required by this datapath.
     /// Initialization might include: memory registration per queue,
     /// and flow initialization.
@@ -355,6 +665,11 @@ pub trait Datapath {
     /// @addr: Address information to connect to. Returns a unique "connection" ID.
     fn connect(&mut self, addr: AddressInfo) -> Result<ConnID>;
 
+    /// Address of the remote end of an open connection, if the datapath tracks it.
+    fn connection_address(&self, _conn_id: ConnID) -> Option<AddressInfo> {
+        None
+    }
+
     /// Send multiple buffers to the specified address.
     /// Args:
     /// @pkts: Vector of (msg id, buffer, connection id) to send.
@@ -630,9 +945,41 @@ pub trait Datapath {
         register_at_start: bool,
     ) -> Result<Vec<MempoolID>>;
 
-    /// Checks whether pointer was allocated by our memory pool.
+    /// Checks whether pointer was allocated by our memory pool (or lies within a registered
+    /// external region).
     fn is_registered(&self, buf: &[u8]) -> bool;
 
+    /// Registers an application-owned memory region (e.g., an mmap'd file or a hugepage arena)
+    /// with the NIC, so buffers within it can be transmitted without copying:
+    /// `is_registered` and `recover_metadata` recognize buffers in the region, and recovered
+    /// metadata holds a reference on the region until it is dropped (and transmission
+    /// completes).
+    /// Args:
+    /// @addr: start of the region, aligned to pagesize.
+    /// @len: length of the region, a multiple of pagesize.
+    /// @pagesize: size of the pages backing the region (4KB, 2MB or 1GB).
+    ///
+    /// Returns:
+    /// ID of the region, to pass to `unregister_external_region`.
+    ///
+    /// # Safety
+    /// The region must stay mapped until `unregister_external_region` succeeds for it.
+    unsafe fn register_external_region(
+        &mut self,
+        _addr: *mut u8,
+        _len: usize,
+        _pagesize: usize,
+    ) -> Result<MempoolID> {
+        bail!("Datapath does not support registering external memory regions");
+    }
+
+    /// Unregisters an external region if no metadata references it anymore.
+    /// Returns whether the region was unregistered; if false, the application must keep the
+    /// region mapped and try again later.
+    fn unregister_external_region(&mut self, _id: MempoolID) -> Result<bool> {
+        bail!("Datapath does not support registering external memory regions");
+    }
+
     fn add_memory_pool_with_size(
         &mut self,
         size: usize,
@@ -656,6 +1003,39 @@ pub trait Datapath {
 
     /// Checks whether datapath has mempool of size size given (must be power of 2).
     fn has_mempool(&self, size: usize) -> bool;
+
+    /// Parameters used for mempools added on behalf of the application.
+    fn get_mempool_config(&self) -> MempoolConfig {
+        MempoolConfig::default()
+    }
+
+    /// Set parameters for mempools added on behalf of the application (including whether
+    /// `allocate` grows exhausted size classes).
+    fn set_mempool_config(&mut self, _config: MempoolConfig) -> Result<()> {
+        Ok(())
+    }
+
+    /// Item size of the mempool that a buffer of the given size is allocated from.
+    fn mempool_size_class(&self, size: usize) -> usize {
+        pad_mempool_size(size)
+    }
+
+    /// Requested versus allocated bytes for buffers allocated from application mempools.
+    fn allocator_memory_stats(&self) -> AllocatorMemoryStats {
+        AllocatorMemoryStats::default()
+    }
+
+    /// Removes the given mempool if none of its buffers are in use.
+    /// Returns whether the mempool was removed.
+    fn remove_memory_pool(&mut self, _id: MempoolID) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Removes mempools with no buffers in use, keeping at least one mempool per size class.
+    /// Returns the IDs of removed mempools.
+    fn remove_idle_mempools(&mut self) -> Result<Vec<MempoolID>> {
+        Ok(Vec::default())
+    }
     fn header_size(&self) -> usize;
 
     /// Number of cycles in a second
@@ -682,6 +1062,55 @@ pub trait Datapath {
     /// Set inline mode (may not be available in all datapaths)
     fn set_inline_mode(&mut self, mode: InlineMode);
 
+    /// Counts of bytes and segments copied, zero-copied, inlined and sent via fallback copies
+    /// since the datapath was initialized (or last reset).
+    fn transmit_stats(&self) -> TransmitStats {
+        TransmitStats::default()
+    }
+
+    /// Resets transmit stats (e.g., after warmup).
+    fn reset_transmit_stats(&mut self) {}
+
+    /// Whether UDP checksums can be computed and validated by the NIC (or the kernel stack).
+    fn checksum_offload_capable(&self) -> bool {
+        false
+    }
+
+    /// Sets where UDP checksums are handled. Fails for modes this datapath cannot provide,
+    /// including offload on datapaths that are not offload capable.
+    fn set_checksum_mode(&mut self, mode: ChecksumMode) -> Result<()> {
+        if mode != ChecksumMode::Disabled {
+            bail!("Checksum mode {:?} not supported by this datapath", mode);
+        }
+        Ok(())
+    }
+
+    fn get_checksum_mode(&self) -> ChecksumMode {
+        ChecksumMode::Disabled
+    }
+
+    /// Counts of received packets dropped by the datapath since it was initialized (or last
+    /// reset).
+    fn receive_stats(&self) -> ReceiveStats {
+        ReceiveStats::default()
+    }
+
+    /// Resets receive stats (e.g., after warmup).
+    fn reset_receive_stats(&mut self) {}
+
+    /// Records every frame this datapath transmits or receives from now on to `capture`, which
+    /// is closed when the datapath is dropped.
+    fn set_packet_capture(&mut self, _capture: PacketCapture) -> Result<()> {
+        bail!("This datapath does not support packet capture");
+    }
+
+    /// Encrypts every payload this datapath transmits from now on, and drops received payloads
+    /// that do not authenticate, with the keys in `config`. Encryption copies every payload into
+    /// a transmit buffer, whatever the copying threshold.
+    fn set_encryption(&mut self, _config: EncryptionConfig) -> Result<()> {
+        bail!("This datapath does not support encryption");
+    }
+
     /// Packet processing batch size.
     fn batch_size() -> usize {
         32
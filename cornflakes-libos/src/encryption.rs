@@ -0,0 +1,791 @@
+//! Optional authenticated encryption of message payloads.
+//!
+//! With encryption on (see `Datapath::set_encryption`), a datapath copies each outgoing payload
+//! into a transmit buffer and seals it with an AEAD cipher under a pre-shared key from the yaml
+//! config; received payloads are authenticated and decrypted in place before they are handed to
+//! the application, and payloads that fail to authenticate are dropped. The packet headers
+//! (ethernet, ip, udp and message id) stay in the clear; the message id, the direction (client to
+//! server or back) and both udp ports are authenticated as associated data, so a sealed payload
+//! cannot be moved to another id, reflected back to its sender or redirected to another port.
+//!
+//! Sealed payloads are laid out as `nonce || ciphertext || tag`, `ENCRYPTION_OVERHEAD` bytes
+//! longer than the plaintext. The nonce is a random 128-bit salt, drawn once per thread, followed
+//! by a counter. Each thread seals under its own subkey, derived with HKDF-SHA256 from the
+//! pre-shared key, its salt and the direction, and receivers derive the same subkey from the salt
+//! in the nonce. Counters then never repeat under a subkey, and subkeys of threads and runs that
+//! share a key only collide if their 128-bit salts do.
+//!
+//! Receivers keep, for each sender (peer ip and salt), the highest counter that authenticated
+//! and which of the `REPLAY_WINDOW` counters below it did, and drop payloads whose counter was
+//! already seen or is older than the window: a captured payload cannot be replayed, while
+//! payloads reordered in the network still open. Deriving the subkey of a salt not seen before
+//! costs an HKDF expansion and a cipher setup, so these derivations are rate limited; payloads
+//! with forged salts past the limit are dropped before any key is derived.
+//!
+//! Keys are per client: the yaml config may list a key for each client ip, used for every
+//! connection to and from that client, and a default key for clients it does not list.
+//! ```yaml
+//! encryption:
+//!     algorithm: chacha20-poly1305 # or aes-256-gcm
+//!     key: "<64 hex digits>"
+//!     client_keys:
+//!         10.10.1.2: "<64 hex digits>"
+//! ```
+use super::{admission::TokenBucket, utils::AddressInfo};
+use aes_gcm::{
+    aead::{AeadInPlace, KeyInit, Nonce, Tag},
+    Aes256Gcm,
+};
+use byteorder::{BigEndian, ByteOrder};
+use chacha20poly1305::ChaCha20Poly1305;
+use color_eyre::eyre::{bail, ensure, eyre, Result, WrapErr};
+use cornflakes_utils::AppMode;
+use hashbrown::HashMap;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::{fs::read_to_string, net::IpAddr, path::Path, str::FromStr, time::Instant};
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Length of pre-shared keys.
+pub const KEY_LEN: usize = 32;
+/// Length of the nonce sealed payloads start with: the sender's salt and message counter.
+pub const NONCE_LEN: usize = SALT_LEN + COUNTER_LEN;
+pub const TAG_LEN: usize = 16;
+/// Bytes a sealed payload adds to its plaintext.
+pub const ENCRYPTION_OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+const SALT_LEN: usize = 16;
+const COUNTER_LEN: usize = 8;
+/// Length of the nonces the AEAD ciphers take; the counter is zero-padded to it.
+const AEAD_NONCE_LEN: usize = 12;
+/// Associated data: message id, direction, and the sender's and receiver's udp ports.
+const AAD_LEN: usize = 4 + 1 + 2 + 2;
+/// HKDF info prefix of subkeys; the direction byte follows it.
+const SUBKEY_INFO: &[u8] = b"cornflakes payload subkey";
+/// Counters below a sender's highest one that may still open, out of order.
+pub const REPLAY_WINDOW: u64 = 1024;
+const REPLAY_WINDOW_WORDS: usize = REPLAY_WINDOW as usize / 64;
+/// Subkeys of unknown salts a thread may derive back to back, and per second after that.
+const NEW_SENDER_BURST: f64 = 64.0;
+const NEW_SENDER_RATE: f64 = 1024.0;
+
+/// Which way a sealed payload travels.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+enum Direction {
+    ClientToServer = 0,
+    ServerToClient = 1,
+}
+
+impl Direction {
+    /// Direction of the payloads an endpoint in `mode` sends.
+    fn sent_by(mode: AppMode) -> Self {
+        match mode {
+            AppMode::Client => Direction::ClientToServer,
+            AppMode::Server => Direction::ServerToClient,
+        }
+    }
+
+    fn reverse(self) -> Self {
+        match self {
+            Direction::ClientToServer => Direction::ServerToClient,
+            Direction::ServerToClient => Direction::ClientToServer,
+        }
+    }
+}
+
+type Salt = [u8; SALT_LEN];
+
+pub type Key = [u8; KEY_LEN];
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum AeadAlgorithm {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl FromStr for AeadAlgorithm {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<AeadAlgorithm> {
+        match s {
+            "aes-256-gcm" | "aes256gcm" | "AES-256-GCM" => Ok(AeadAlgorithm::Aes256Gcm),
+            "chacha20-poly1305" | "chacha20poly1305" | "CHACHA20-POLY1305" => {
+                Ok(AeadAlgorithm::ChaCha20Poly1305)
+            }
+            x => {
+                bail!("Unknown AEAD algorithm: {:?}", x);
+            }
+        }
+    }
+}
+
+/// Decodes a key written as 64 hex digits.
+pub fn parse_key(s: &str) -> Result<Key> {
+    let s = s.trim();
+    ensure!(
+        s.len() == 2 * KEY_LEN && s.is_ascii(),
+        "Key must be {} hex digits; got {} characters",
+        2 * KEY_LEN,
+        s.len()
+    );
+    let mut key = [0u8; KEY_LEN];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16)
+            .wrap_err(format!("Key has a non-hex digit at {}", 2 * i))?;
+    }
+    Ok(key)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptionConfig {
+    pub algorithm: AeadAlgorithm,
+    /// Key for clients without a key of their own.
+    pub default_key: Option<Key>,
+    /// Key of each client, by ip.
+    pub client_keys: HashMap<IpAddr, Key>,
+}
+
+impl EncryptionConfig {
+    pub fn new(algorithm: AeadAlgorithm, default_key: Option<Key>) -> Self {
+        EncryptionConfig {
+            algorithm,
+            default_key,
+            client_keys: HashMap::default(),
+        }
+    }
+
+    /// Reads the `encryption` section of a yaml config; None if there is none.
+    pub fn from_config_file(config_path: &str) -> Result<Option<Self>> {
+        let file_str = read_to_string(Path::new(&config_path))?;
+        let yamls = match YamlLoader::load_from_str(&file_str) {
+            Ok(docs) => docs,
+            Err(e) => {
+                bail!("Could not parse config yaml: {:?}", e);
+            }
+        };
+        Self::from_yaml(&yamls[0]["encryption"])
+    }
+
+    fn from_yaml(yaml: &Yaml) -> Result<Option<Self>> {
+        if yaml.is_badvalue() {
+            return Ok(None);
+        }
+        let algorithm = match yaml["algorithm"].as_str() {
+            Some(algorithm) => AeadAlgorithm::from_str(algorithm)?,
+            None => {
+                bail!("Yaml config encryption section has no algorithm");
+            }
+        };
+        let default_key = match &yaml["key"] {
+            Yaml::BadValue => None,
+            Yaml::String(key) => {
+                Some(parse_key(key).wrap_err("Yaml config encryption key is not valid")?)
+            }
+            entry => {
+                bail!("Yaml config encryption key is not a string: {:?}", entry);
+            }
+        };
+        let mut config = EncryptionConfig::new(algorithm, default_key);
+        match &yaml["client_keys"] {
+            Yaml::BadValue => {}
+            Yaml::Hash(map) => {
+                for (ip, key) in map.iter() {
+                    let (ip, key) = match (ip.as_str(), key.as_str()) {
+                        (Some(ip), Some(key)) => (ip, key),
+                        _ => {
+                            bail!("Yaml config client key entry is not a string: {:?}", key);
+                        }
+                    };
+                    let ip = IpAddr::from_str(ip)
+                        .wrap_err(format!("Yaml config client key ip {:?} is not valid", ip))?;
+                    let key = parse_key(key)
+                        .wrap_err(format!("Yaml config client key for {} is not valid", ip))?;
+                    config.client_keys.insert(ip, key);
+                }
+            }
+            entry => {
+                bail!("Yaml config client_keys is not a map: {:?}", entry);
+            }
+        }
+        ensure!(
+            config.default_key.is_some() || !config.client_keys.is_empty(),
+            "Yaml config encryption section has no keys"
+        );
+        Ok(Some(config))
+    }
+
+    /// Key for connections to and from `client_ip`.
+    pub fn client_key(&self, client_ip: &IpAddr) -> Option<&Key> {
+        self.client_keys
+            .get(client_ip)
+            .or(self.default_key.as_ref())
+    }
+}
+
+/// AEAD cipher keyed for one client.
+#[derive(Clone)]
+enum MessageCipher {
+    Aes256Gcm(Aes256Gcm),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+/// Encrypts `buf` in place and writes its tag into `tag`.
+fn seal_with<C: AeadInPlace>(
+    cipher: &C,
+    nonce: &[u8],
+    aad: &[u8],
+    buf: &mut [u8],
+    tag: &mut [u8],
+) -> Result<()> {
+    let computed = cipher
+        .encrypt_in_place_detached(Nonce::<C>::from_slice(nonce), aad, buf)
+        .map_err(|_| eyre!("Failed to encrypt message"))?;
+    tag.copy_from_slice(computed.as_slice());
+    Ok(())
+}
+
+/// Authenticates and decrypts `buf` in place.
+fn open_with<C: AeadInPlace>(
+    cipher: &C,
+    nonce: &[u8],
+    aad: &[u8],
+    buf: &mut [u8],
+    tag: &[u8],
+) -> Result<()> {
+    cipher
+        .decrypt_in_place_detached(
+            Nonce::<C>::from_slice(nonce),
+            aad,
+            buf,
+            Tag::<C>::from_slice(tag),
+        )
+        .map_err(|_| eyre!("Message failed to authenticate"))
+}
+
+impl MessageCipher {
+    fn new(algorithm: AeadAlgorithm, key: &Key) -> Result<Self> {
+        Ok(match algorithm {
+            AeadAlgorithm::Aes256Gcm => MessageCipher::Aes256Gcm(
+                Aes256Gcm::new_from_slice(key).map_err(|_| eyre!("Invalid key length"))?,
+            ),
+            AeadAlgorithm::ChaCha20Poly1305 => MessageCipher::ChaCha20Poly1305(
+                ChaCha20Poly1305::new_from_slice(key).map_err(|_| eyre!("Invalid key length"))?,
+            ),
+        })
+    }
+
+    /// Cipher keyed with the subkey one sender, identified by its salt, seals payloads going in
+    /// `direction` under.
+    fn for_sender(
+        algorithm: AeadAlgorithm,
+        key: &Key,
+        salt: &Salt,
+        direction: Direction,
+    ) -> Result<Self> {
+        let mut info = [0u8; SUBKEY_INFO.len() + 1];
+        info[0..SUBKEY_INFO.len()].copy_from_slice(SUBKEY_INFO);
+        info[SUBKEY_INFO.len()] = direction as u8;
+        let mut subkey = [0u8; KEY_LEN];
+        Hkdf::<Sha256>::new(Some(salt), key)
+            .expand(&info, &mut subkey)
+            .map_err(|_| eyre!("Invalid subkey length"))?;
+        MessageCipher::new(algorithm, &subkey)
+    }
+
+    fn seal(&self, nonce: &[u8], aad: &[u8], buf: &mut [u8], tag: &mut [u8]) -> Result<()> {
+        match self {
+            MessageCipher::Aes256Gcm(cipher) => seal_with(cipher, nonce, aad, buf, tag),
+            MessageCipher::ChaCha20Poly1305(cipher) => seal_with(cipher, nonce, aad, buf, tag),
+        }
+    }
+
+    fn open(&self, nonce: &[u8], aad: &[u8], buf: &mut [u8], tag: &[u8]) -> Result<()> {
+        match self {
+            MessageCipher::Aes256Gcm(cipher) => open_with(cipher, nonce, aad, buf, tag),
+            MessageCipher::ChaCha20Poly1305(cipher) => open_with(cipher, nonce, aad, buf, tag),
+        }
+    }
+}
+
+/// Counters a receiver has opened from one sender: the highest, and a bitmap of the
+/// `REPLAY_WINDOW` counters up to it, indexed by counter modulo the window.
+#[derive(Debug, Clone, Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    seen: [u64; REPLAY_WINDOW_WORDS],
+}
+
+impl ReplayWindow {
+    fn bit(counter: u64) -> (usize, u64) {
+        let index = counter % REPLAY_WINDOW;
+        ((index / 64) as usize, 1 << (index % 64))
+    }
+
+    /// Fails if `counter` was already opened or is too old to tell.
+    fn check(&self, counter: u64) -> Result<()> {
+        let highest = match self.highest {
+            Some(highest) if counter <= highest => highest,
+            _ => {
+                return Ok(());
+            }
+        };
+        ensure!(
+            highest - counter < REPLAY_WINDOW,
+            "Counter {} is older than the replay window (highest {})",
+            counter,
+            highest
+        );
+        let (word, mask) = Self::bit(counter);
+        ensure!(self.seen[word] & mask == 0, "Counter {} replayed", counter);
+        Ok(())
+    }
+
+    /// Records `counter` as opened; call only once its payload authenticated.
+    fn mark(&mut self, counter: u64) {
+        match self.highest {
+            Some(highest) if counter <= highest => {}
+            Some(highest) if counter - highest < REPLAY_WINDOW => {
+                // forget the counters that slide out of the window
+                for old in highest + 1..=counter {
+                    let (word, mask) = Self::bit(old);
+                    self.seen[word] &= !mask;
+                }
+                self.highest = Some(counter);
+            }
+            _ => {
+                self.seen = [0; REPLAY_WINDOW_WORDS];
+                self.highest = Some(counter);
+            }
+        }
+        let (word, mask) = Self::bit(counter);
+        self.seen[word] |= mask;
+    }
+}
+
+/// Opening state of one of a peer's senders.
+#[derive(Clone)]
+struct Sender {
+    cipher: MessageCipher,
+    replay_window: ReplayWindow,
+}
+
+/// Per-thread encryption state of a datapath: seals and opens the payloads of its connections.
+#[derive(Clone)]
+pub struct TransportEncryption {
+    config: EncryptionConfig,
+    mode: AppMode,
+    /// Our address; as a client, its ip is the one our key belongs to.
+    local: AddressInfo,
+    salt: Salt,
+    counter: u64,
+    /// Cipher sealing payloads to each peer, created on first use.
+    sealing_ciphers: HashMap<IpAddr, MessageCipher>,
+    /// Cipher and replay window of each of a peer's senders (by salt), created once one of its
+    /// payloads authenticates.
+    senders: HashMap<(IpAddr, Salt), Sender>,
+    /// Limits subkey derivations for salts not in `senders`.
+    new_senders: TokenBucket,
+}
+
+impl std::fmt::Debug for TransportEncryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // leave the keys out
+        f.debug_struct("TransportEncryption")
+            .field("algorithm", &self.config.algorithm)
+            .field("mode", &self.mode)
+            .field("local", &self.local)
+            .field("counter", &self.counter)
+            .finish()
+    }
+}
+
+impl TransportEncryption {
+    pub fn new(config: EncryptionConfig, local: AddressInfo, mode: AppMode) -> Self {
+        TransportEncryption {
+            config,
+            mode,
+            local,
+            salt: rand::random::<Salt>(),
+            counter: 0,
+            sealing_ciphers: HashMap::default(),
+            senders: HashMap::default(),
+            new_senders: TokenBucket::new(NEW_SENDER_BURST, Instant::now()),
+        }
+    }
+
+    pub fn algorithm(&self) -> AeadAlgorithm {
+        self.config.algorithm
+    }
+
+    /// Pre-shared key of connections with `peer`.
+    fn key(&self, peer: &AddressInfo) -> Result<&Key> {
+        let client_ip = match self.mode {
+            AppMode::Client => &self.local.ip_addr,
+            AppMode::Server => &peer.ip_addr,
+        };
+        match self.config.client_key(client_ip) {
+            Some(key) => Ok(key),
+            None => {
+                bail!("No pre-shared key for client {}", client_ip);
+            }
+        }
+    }
+
+    /// Associated data of a message going in `direction` from `src_port` to `dst_port`.
+    fn aad(msg_id: u32, direction: Direction, src_port: u16, dst_port: u16) -> [u8; AAD_LEN] {
+        let mut aad = [0u8; AAD_LEN];
+        BigEndian::write_u32(&mut aad[0..4], msg_id);
+        aad[4] = direction as u8;
+        BigEndian::write_u16(&mut aad[5..7], src_port);
+        BigEndian::write_u16(&mut aad[7..9], dst_port);
+        aad
+    }
+
+    /// Seals the `plaintext_len` bytes at `buf[NONCE_LEN..]` for `peer`, writing the nonce before
+    /// them and the tag after; `buf` must be at least `plaintext_len + ENCRYPTION_OVERHEAD` long.
+    /// Returns the length of the sealed payload.
+    pub fn seal(
+        &mut self,
+        peer: &AddressInfo,
+        msg_id: u32,
+        buf: &mut [u8],
+        plaintext_len: usize,
+    ) -> Result<usize> {
+        let sealed_len = plaintext_len + ENCRYPTION_OVERHEAD;
+        ensure!(
+            buf.len() >= sealed_len,
+            "Buffer of len {} too small to seal {} bytes",
+            buf.len(),
+            plaintext_len
+        );
+        let direction = Direction::sent_by(self.mode);
+        let counter = self.counter;
+        self.counter += 1;
+        let mut aead_nonce = [0u8; AEAD_NONCE_LEN];
+        BigEndian::write_u64(&mut aead_nonce[AEAD_NONCE_LEN - COUNTER_LEN..], counter);
+        let aad = Self::aad(msg_id, direction, self.local.udp_port, peer.udp_port);
+
+        if !self.sealing_ciphers.contains_key(&peer.ip_addr) {
+            let cipher = MessageCipher::for_sender(
+                self.config.algorithm,
+                self.key(peer)?,
+                &self.salt,
+                direction,
+            )?;
+            self.sealing_ciphers.insert(peer.ip_addr, cipher);
+        }
+        let cipher = self.sealing_ciphers.get(&peer.ip_addr).unwrap();
+        let (nonce_buf, rest) = buf[0..sealed_len].split_at_mut(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at_mut(plaintext_len);
+        cipher.seal(&aead_nonce, &aad, ciphertext, tag)?;
+        nonce_buf[0..SALT_LEN].copy_from_slice(&self.salt);
+        BigEndian::write_u64(&mut nonce_buf[SALT_LEN..], counter);
+        Ok(sealed_len)
+    }
+
+    /// Authenticates and decrypts a sealed payload from `peer` in place, unless its counter was
+    /// already opened. Returns the length of the plaintext, which starts at `buf[NONCE_LEN..]`.
+    pub fn open(&mut self, peer: &AddressInfo, msg_id: u32, buf: &mut [u8]) -> Result<usize> {
+        self.open_at(peer, msg_id, buf, Instant::now())
+    }
+
+    fn open_at(
+        &mut self,
+        peer: &AddressInfo,
+        msg_id: u32,
+        buf: &mut [u8],
+        now: Instant,
+    ) -> Result<usize> {
+        ensure!(
+            buf.len() >= ENCRYPTION_OVERHEAD,
+            "Payload of len {} too short to be sealed",
+            buf.len()
+        );
+        let plaintext_len = buf.len() - ENCRYPTION_OVERHEAD;
+        let direction = Direction::sent_by(self.mode).reverse();
+        let aad = Self::aad(msg_id, direction, peer.udp_port, self.local.udp_port);
+
+        let (nonce, rest) = buf.split_at_mut(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at_mut(plaintext_len);
+        let mut salt = Salt::default();
+        salt.copy_from_slice(&nonce[0..SALT_LEN]);
+        let counter = BigEndian::read_u64(&nonce[SALT_LEN..]);
+        let mut aead_nonce = [0u8; AEAD_NONCE_LEN];
+        aead_nonce[AEAD_NONCE_LEN - COUNTER_LEN..].copy_from_slice(&nonce[SALT_LEN..]);
+        match self.senders.get_mut(&(peer.ip_addr, salt)) {
+            Some(sender) => {
+                sender.replay_window.check(counter)?;
+                sender.cipher.open(&aead_nonce, &aad, ciphertext, tag)?;
+                sender.replay_window.mark(counter);
+            }
+            None => {
+                ensure!(
+                    self.new_senders
+                        .try_take(NEW_SENDER_RATE, NEW_SENDER_BURST, now),
+                    "Too many unknown salts; dropping payload from {}",
+                    peer.ip_addr
+                );
+                // only keep the subkeys of senders that authenticate, so forged salts cannot
+                // grow the map
+                let cipher = MessageCipher::for_sender(
+                    self.config.algorithm,
+                    self.key(peer)?,
+                    &salt,
+                    direction,
+                )?;
+                cipher.open(&aead_nonce, &aad, ciphertext, tag)?;
+                let mut replay_window = ReplayWindow::default();
+                replay_window.mark(counter);
+                self.senders.insert(
+                    (peer.ip_addr, salt),
+                    Sender {
+                        cipher,
+                        replay_window,
+                    },
+                );
+            }
+        }
+        Ok(plaintext_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eui48::MacAddress;
+    use std::{net::Ipv4Addr, time::Duration};
+
+    const KEY_HEX: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn addr(ip: [u8; 4]) -> AddressInfo {
+        AddressInfo::new(12345, IpAddr::V4(Ipv4Addr::from(ip)), MacAddress::nil())
+    }
+
+    fn endpoints(algorithm: AeadAlgorithm) -> (TransportEncryption, TransportEncryption) {
+        let config = EncryptionConfig::new(algorithm, Some(parse_key(KEY_HEX).unwrap()));
+        let client = TransportEncryption::new(config.clone(), addr([10, 0, 0, 2]), AppMode::Client);
+        let server = TransportEncryption::new(config, addr([10, 0, 0, 1]), AppMode::Server);
+        (client, server)
+    }
+
+    fn seal_message(client: &mut TransportEncryption, msg_id: u32, msg: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0u8; msg.len() + ENCRYPTION_OVERHEAD];
+        buf[NONCE_LEN..NONCE_LEN + msg.len()].copy_from_slice(msg);
+        let sealed_len = client
+            .seal(&addr([10, 0, 0, 1]), msg_id, &mut buf, msg.len())
+            .unwrap();
+        assert_eq!(sealed_len, buf.len());
+        buf
+    }
+
+    #[test]
+    fn sealed_messages_open() {
+        for algorithm in [AeadAlgorithm::Aes256Gcm, AeadAlgorithm::ChaCha20Poly1305] {
+            let (mut client, mut server) = endpoints(algorithm);
+            let msg = b"GET key_1234";
+            let mut first = seal_message(&mut client, 7, msg);
+            let second = seal_message(&mut client, 7, msg);
+            // fresh nonces
+            assert_ne!(first, second);
+            assert_ne!(&first[NONCE_LEN..NONCE_LEN + msg.len()], &msg[..]);
+
+            let len = server.open(&addr([10, 0, 0, 2]), 7, &mut first).unwrap();
+            assert_eq!(&first[NONCE_LEN..NONCE_LEN + len], &msg[..]);
+        }
+    }
+
+    #[test]
+    fn tampered_messages_do_not_open() {
+        let (mut client, mut server) = endpoints(AeadAlgorithm::ChaCha20Poly1305);
+        let msg = b"PUT key_1234 value";
+
+        let mut flipped = seal_message(&mut client, 7, msg);
+        flipped[NONCE_LEN + 3] ^= 1;
+        assert!(server.open(&addr([10, 0, 0, 2]), 7, &mut flipped).is_err());
+
+        // the message id is authenticated
+        let mut sealed = seal_message(&mut client, 7, msg);
+        assert!(server.open(&addr([10, 0, 0, 2]), 8, &mut sealed).is_err());
+
+        assert!(server.open(&addr([10, 0, 0, 2]), 7, &mut [0u8; 8]).is_err());
+    }
+
+    #[test]
+    fn messages_are_bound_to_direction_and_ports() {
+        let (mut client, mut server) = endpoints(AeadAlgorithm::Aes256Gcm);
+        let msg = b"GET key_1234";
+
+        // a request reflected back to the client does not open as a response
+        let mut reflected = seal_message(&mut client, 7, msg);
+        assert!(client
+            .open(&addr([10, 0, 0, 1]), 7, &mut reflected)
+            .is_err());
+
+        // nor does a request from (or redirected to) another port
+        let mut other_port = addr([10, 0, 0, 2]);
+        other_port.udp_port += 1;
+        let mut redirected = seal_message(&mut client, 7, msg);
+        assert!(server.open(&other_port, 7, &mut redirected).is_err());
+
+        // responses open at the client
+        let mut response = vec![0u8; msg.len() + ENCRYPTION_OVERHEAD];
+        response[NONCE_LEN..NONCE_LEN + msg.len()].copy_from_slice(msg);
+        server
+            .seal(&addr([10, 0, 0, 2]), 7, &mut response, msg.len())
+            .unwrap();
+        assert_eq!(
+            client.open(&addr([10, 0, 0, 1]), 7, &mut response).unwrap(),
+            msg.len()
+        );
+    }
+
+    #[test]
+    fn threads_sharing_a_key_seal_under_their_own_subkeys() {
+        let (mut first_thread, mut server) = endpoints(AeadAlgorithm::ChaCha20Poly1305);
+        let (mut second_thread, _) = endpoints(AeadAlgorithm::ChaCha20Poly1305);
+        let msg = b"GET key_1234";
+        // both threads' counters start at zero
+        let mut first = seal_message(&mut first_thread, 7, msg);
+        let mut second = seal_message(&mut second_thread, 7, msg);
+        assert_ne!(&first[0..SALT_LEN], &second[0..SALT_LEN]);
+        assert_eq!(&first[SALT_LEN..NONCE_LEN], &second[SALT_LEN..NONCE_LEN]);
+        assert_ne!(&first[NONCE_LEN..], &second[NONCE_LEN..]);
+
+        assert!(server.open(&addr([10, 0, 0, 2]), 7, &mut first).is_ok());
+        assert!(server.open(&addr([10, 0, 0, 2]), 7, &mut second).is_ok());
+        assert_eq!(server.senders.len(), 2);
+
+        // forged salts are not kept
+        let mut forged = seal_message(&mut first_thread, 7, msg);
+        forged[0] ^= 1;
+        assert!(server.open(&addr([10, 0, 0, 2]), 7, &mut forged).is_err());
+        assert_eq!(server.senders.len(), 2);
+    }
+
+    #[test]
+    fn replayed_messages_do_not_open() {
+        let (mut client, mut server) = endpoints(AeadAlgorithm::ChaCha20Poly1305);
+        let msg = b"PUT key_1234 value";
+        let sealed: Vec<Vec<u8>> = (0..5).map(|_| seal_message(&mut client, 7, msg)).collect();
+        let open = |server: &mut TransportEncryption, sealed: &Vec<u8>| {
+            server.open(&addr([10, 0, 0, 2]), 7, &mut sealed.clone())
+        };
+
+        // out of order within the window is fine, repeats are not
+        assert!(open(&mut server, &sealed[2]).is_ok());
+        assert!(open(&mut server, &sealed[0]).is_ok());
+        assert!(open(&mut server, &sealed[2]).is_err());
+        assert!(open(&mut server, &sealed[0]).is_err());
+        assert!(open(&mut server, &sealed[3]).is_ok());
+        assert!(open(&mut server, &sealed[1]).is_ok());
+        assert!(open(&mut server, &sealed[1]).is_err());
+
+        // an authentic message whose counter fell out of the window is too old to tell
+        client.counter = 4 + REPLAY_WINDOW;
+        assert!(open(&mut server, &seal_message(&mut client, 7, msg)).is_ok());
+        assert!(open(&mut server, &sealed[4]).is_err());
+    }
+
+    #[test]
+    fn replay_window_slides() {
+        let mut window = ReplayWindow::default();
+        window.mark(5);
+        assert!(window.check(5).is_err());
+        assert!(window.check(4).is_ok());
+        window.mark(5 + REPLAY_WINDOW - 1);
+        assert!(window.check(5).is_err());
+        // sliding past 5 forgets it, and its bit is reused by 5 + REPLAY_WINDOW
+        window.mark(5 + REPLAY_WINDOW);
+        assert!(window.check(5).is_err());
+        assert!(window.check(5 + REPLAY_WINDOW).is_err());
+        assert!(window.check(6).is_ok());
+        window.mark(10 * REPLAY_WINDOW);
+        assert!(window.check(6).is_err());
+        assert!(window.check(10 * REPLAY_WINDOW - 1).is_ok());
+    }
+
+    #[test]
+    fn unknown_salts_are_rate_limited() {
+        let (mut client, mut server) = endpoints(AeadAlgorithm::Aes256Gcm);
+        let msg = b"GET key_1234";
+        let now = Instant::now();
+        for _ in 0..NEW_SENDER_BURST as usize {
+            let mut forged = seal_message(&mut client, 7, msg);
+            forged[0] ^= 1;
+            assert!(server
+                .open_at(&addr([10, 0, 0, 2]), 7, &mut forged, now)
+                .is_err());
+        }
+        // a new sender waits for the bucket to refill, while known senders are unaffected
+        let mut sealed = seal_message(&mut client, 7, msg);
+        assert!(server
+            .open_at(&addr([10, 0, 0, 2]), 7, &mut sealed.clone(), now)
+            .is_err());
+        let later = now + Duration::from_secs_f64(2.0 / NEW_SENDER_RATE);
+        assert!(server
+            .open_at(&addr([10, 0, 0, 2]), 7, &mut sealed, later)
+            .is_ok());
+        assert!(server
+            .open_at(
+                &addr([10, 0, 0, 2]),
+                7,
+                &mut seal_message(&mut client, 7, msg),
+                later
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn servers_use_each_clients_key() {
+        let other_key = [7u8; KEY_LEN];
+        let mut config = EncryptionConfig::new(AeadAlgorithm::Aes256Gcm, None);
+        config
+            .client_keys
+            .insert(addr([10, 0, 0, 2]).ip_addr, other_key);
+        let mut client =
+            TransportEncryption::new(config.clone(), addr([10, 0, 0, 2]), AppMode::Client);
+        let mut server = TransportEncryption::new(config, addr([10, 0, 0, 1]), AppMode::Server);
+        let mut sealed = seal_message(&mut client, 1, b"hello");
+        assert_eq!(
+            server.open(&addr([10, 0, 0, 2]), 1, &mut sealed).unwrap(),
+            5
+        );
+        // clients without a key (and no default key) cannot connect
+        assert!(server
+            .open(
+                &addr([10, 0, 0, 3]),
+                1,
+                &mut seal_message(&mut client, 1, b"hello")
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn config_parses_keys() {
+        let yaml = YamlLoader::load_from_str(&format!(
+            "encryption:\n    algorithm: aes-256-gcm\n    key: \"{}\"\n    client_keys:\n        10.0.0.2: \"{}\"\n",
+            KEY_HEX,
+            "ff".repeat(KEY_LEN)
+        ))
+        .unwrap();
+        let config = EncryptionConfig::from_yaml(&yaml[0]["encryption"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.algorithm, AeadAlgorithm::Aes256Gcm);
+        assert_eq!(config.default_key.unwrap()[31], 0x1f);
+        assert_eq!(
+            config.client_key(&addr([10, 0, 0, 2]).ip_addr),
+            Some(&[0xff; KEY_LEN])
+        );
+        assert_eq!(
+            config.client_key(&addr([10, 0, 0, 9]).ip_addr),
+            config.default_key.as_ref()
+        );
+
+        let no_section = YamlLoader::load_from_str("port: 1").unwrap();
+        assert!(EncryptionConfig::from_yaml(&no_section[0]["encryption"])
+            .unwrap()
+            .is_none());
+        assert!(parse_key("00").is_err());
+        assert!(parse_key(&"zz".repeat(KEY_LEN)).is_err());
+    }
+}
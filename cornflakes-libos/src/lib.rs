@@ -5,6 +5,8 @@
 //!  1. An interface for datapaths to implement.
 //!  2. DPDK bindings, which are used to implement the DPDK datapath.
 //!  3. A DPDK based datapath.
+pub mod adaptive_threshold;
+pub mod admission;
 pub mod allocator;
 pub mod datapath;
 pub mod dynamic_object_arena_hdr;
@@ -12,8 +14,12 @@ pub mod dynamic_object_hdr;
 pub mod dynamic_rcsga_hdr;
 pub mod dynamic_rcsga_hybrid_hdr;
 pub mod dynamic_sga_hdr;
+pub mod encryption;
 pub mod loadgen;
 pub mod mem;
+pub mod neighbor;
+pub mod pcap;
+pub mod rss;
 pub mod state_machine;
 pub mod timing;
 pub mod utils;
@@ -28,7 +34,7 @@ use loadgen::request_schedule::{PacketSchedule, SpinTimer};
 use mem::MmapMetadata;
 use std::{
     io::Write,
-    net::Ipv4Addr,
+    net::IpAddr,
     ops::{Fn, FnMut},
     slice::{Iter, IterMut},
     sync::{Arc, Mutex},
@@ -3202,7 +3208,7 @@ pub trait Datapath {
         config_file: &str,
         num_cores: usize,
         app_mode: AppMode,
-        remote_ip: Option<Ipv4Addr>,
+        remote_ip: Option<IpAddr>,
     ) -> Result<(u16, Vec<(Self::RxPacketAllocator, utils::AddressInfo)>)>;
 
     fn per_thread_init(
@@ -3261,7 +3267,7 @@ pub trait Datapath {
     /// Get destination address information from Ipv4 Address
     fn get_outgoing_addr_from_ip(
         &self,
-        dst_addr: &Ipv4Addr,
+        dst_addr: &IpAddr,
         udp_port: u16,
     ) -> Result<utils::AddressInfo>;
 
@@ -3295,7 +3301,7 @@ pub fn no_retries_timeout(_received: usize) -> Duration {
 pub trait ClientSM {
     type Datapath: Datapath;
 
-    fn server_ip(&self) -> Ipv4Addr;
+    fn server_ip(&self) -> IpAddr;
 
     /// Generate next request to be sent and send it with the provided callback.
     fn get_next_msg(&mut self) -> Result<Option<(MsgID, &[u8])>>;
@@ -3323,7 +3329,7 @@ pub trait ClientSM {
         datapath: &mut Self::Datapath,
         num_pkts: u64,
         time_out: impl Fn(usize) -> Duration,
-        server_ip: &Ipv4Addr,
+        server_ip: &IpAddr,
         port: u16,
     ) -> Result<()> {
         let mut recved = 0;
@@ -3367,7 +3373,7 @@ pub trait ClientSM {
         total_time: u64,
         time_out: impl Fn(usize) -> Duration,
         no_retries: bool,
-        server_ip: &Ipv4Addr,
+        server_ip: &IpAddr,
         port: u16,
     ) -> Result<()> {
         let addr_info = datapath.get_outgoing_addr_from_ip(server_ip, port)?;
@@ -1,4 +1,4 @@
-use crate::timing::SizedManualHistogram;
+use crate::{datapath::TransmitStats, timing::SizedManualHistogram};
 
 use super::super::timing::ManualHistogram;
 use color_eyre::eyre::{bail, Result};
@@ -295,6 +295,7 @@ pub struct MeasuredThreadStatsOnly {
     pub summary_histogram: SummaryHistogram,
     pub thread_latencies: ThreadLatencies,
     pub sized_histogram: HashMap<usize, (SummaryHistogram, ThreadLatencies)>,
+    pub transmit_stats: TransmitStats,
 }
 
 impl MeasuredThreadStatsOnly {
@@ -338,6 +339,7 @@ impl MeasuredThreadStatsOnly {
             summary_histogram,
             thread_latencies,
             sized_histogram: sized_map,
+            transmit_stats: TransmitStats::default(),
         })
     }
 
@@ -356,6 +358,8 @@ impl MeasuredThreadStatsOnly {
         for (bucket, (_, latencies)) in self.sized_histogram.iter() {
             latencies.dump_with_size(self.thread_id, *bucket);
         }
+        self.transmit_stats
+            .dump(&format!("thread {} transmit stats", self.thread_id));
     }
 
     pub fn clear_summary_histograms(&mut self) {
@@ -404,6 +408,7 @@ impl std::ops::Add for MeasuredThreadStatsOnly {
             summary_histogram: histogram,
             thread_latencies: latencies,
             sized_histogram: self.sized_histogram,
+            transmit_stats: self.transmit_stats + other.transmit_stats,
         }
     }
 }
@@ -421,6 +426,7 @@ pub struct ThreadStats {
     pub achieved_load_gbps: f64,
     pub summary_histogram: SummaryHistogram,
     pub summary_latencies: ThreadLatencies,
+    pub transmit_stats: TransmitStats,
 }
 
 impl ThreadStats {
@@ -458,6 +464,7 @@ impl ThreadStats {
             achieved_load_gbps: achieved_load_gbps,
             summary_histogram: summary_hist,
             summary_latencies: summary_latencies,
+            transmit_stats: TransmitStats::default(),
         })
     }
 
@@ -476,6 +483,8 @@ impl ThreadStats {
             "thread {} summary stats", self.thread_id
         );
         self.summary_latencies.dump(self.thread_id);
+        self.transmit_stats
+            .dump(&format!("thread {} transmit stats", self.thread_id));
     }
 
     pub fn clear_summary_histogram(&mut self) {
@@ -505,6 +514,7 @@ impl std::ops::Add for ThreadStats {
             achieved_load_gbps: self.achieved_load_gbps + other.achieved_load_gbps,
             summary_histogram: histogram,
             summary_latencies: latencies,
+            transmit_stats: self.transmit_stats + other.transmit_stats,
         }
     }
 }
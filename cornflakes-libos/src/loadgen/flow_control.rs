@@ -0,0 +1,448 @@
+//! Delay-based flow control for clients, in the style of Swift.
+//!
+//! A `FlowController` keeps a congestion window of requests in flight. Each response's RTT
+//! (as measured by `Datapath::pop_with_durations`) is compared against a target delay: below the
+//! target, the window grows additively (by about `additive_increase` per RTT); above it, the
+//! window shrinks in proportion to how far the RTT overshoots, at most once per RTT. Losses
+//! (timeouts) and `OVERLOADED_REPLY`s from a server's admission control shrink the window by
+//! the maximum decrease. Windows below one request are enforced by pacing sends one smoothed
+//! RTT / window apart.
+//!
+//! The open-loop client holds a scheduled send while the window is full, so past saturation
+//! the offered load backs off instead of piling retries onto the server. Requests the server
+//! shed wait in a `RetransmitQueue` for at least one RTT, and then for room in the window,
+//! before they are retransmitted.
+use super::super::{ConnID, MsgID};
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_TARGET_DELAY: Duration = Duration::from_micros(200);
+pub const DEFAULT_INITIAL_WINDOW: f64 = 16.0;
+pub const DEFAULT_MIN_WINDOW: f64 = 0.1;
+pub const DEFAULT_MAX_WINDOW: f64 = 1024.0;
+/// Requests in flight longer than this are considered lost.
+pub const DEFAULT_LOSS_TIMEOUT: Duration = Duration::from_millis(10);
+/// Weight of the newest sample in the smoothed RTT.
+const RTT_SMOOTHING: f64 = 0.125;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlowControlConfig {
+    /// RTT the controller aims to keep responses under.
+    pub target_delay: Duration,
+    pub initial_window: f64,
+    pub min_window: f64,
+    pub max_window: f64,
+    /// Window growth per RTT while RTTs are below the target.
+    pub additive_increase: f64,
+    /// How strongly the window shrinks with the RTT's overshoot of the target.
+    pub beta: f64,
+    /// Largest fraction of the window removed by one decrease.
+    pub max_decrease: f64,
+    pub loss_timeout: Duration,
+}
+
+impl Default for FlowControlConfig {
+    fn default() -> Self {
+        FlowControlConfig {
+            target_delay: DEFAULT_TARGET_DELAY,
+            initial_window: DEFAULT_INITIAL_WINDOW,
+            min_window: DEFAULT_MIN_WINDOW,
+            max_window: DEFAULT_MAX_WINDOW,
+            additive_increase: 1.0,
+            beta: 0.8,
+            max_decrease: 0.5,
+            loss_timeout: DEFAULT_LOSS_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct FlowControlStats {
+    pub window: f64,
+    pub smoothed_rtt_ns: u64,
+    pub increases: u64,
+    pub decreases: u64,
+    /// Requests considered lost (timed out or past the loss timeout).
+    pub losses: u64,
+    pub overloaded_replies: u64,
+    /// Sends held back because the window was full (or the pacing delay had not passed).
+    pub throttled_sends: u64,
+    /// Total time sends were held back.
+    pub throttled_ns: u64,
+}
+
+impl FlowControlStats {
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            window = ?self.window,
+            smoothed_rtt_ns = self.smoothed_rtt_ns,
+            increases = self.increases,
+            decreases = self.decreases,
+            losses = self.losses,
+            overloaded_replies = self.overloaded_replies,
+            throttled_sends = self.throttled_sends,
+            throttled_ns = self.throttled_ns,
+            "{}",
+            msg
+        );
+    }
+}
+
+/// Congestion window of one client connection.
+#[derive(Debug, Clone)]
+pub struct FlowController {
+    config: FlowControlConfig,
+    window: f64,
+    /// Send time of each request in flight.
+    in_flight: HashMap<MsgID, Instant>,
+    smoothed_rtt: Option<Duration>,
+    last_decrease: Option<Instant>,
+    last_send: Option<Instant>,
+    /// When the current stretch of held back sends began.
+    throttled_since: Option<Instant>,
+    stats: FlowControlStats,
+}
+
+impl Default for FlowController {
+    fn default() -> Self {
+        FlowController::new(FlowControlConfig::default())
+    }
+}
+
+impl FlowController {
+    pub fn new(mut config: FlowControlConfig) -> Self {
+        config.min_window = f64::max(config.min_window, f64::MIN_POSITIVE);
+        config.max_window = f64::max(config.max_window, config.min_window);
+        config.max_decrease = config.max_decrease.clamp(0.0, 1.0);
+        let window = config
+            .initial_window
+            .clamp(config.min_window, config.max_window);
+        FlowController {
+            config,
+            window,
+            in_flight: HashMap::default(),
+            smoothed_rtt: None,
+            last_decrease: None,
+            last_send: None,
+            throttled_since: None,
+            stats: FlowControlStats {
+                window,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Whether another request may be sent now.
+    #[inline]
+    pub fn can_send(&self, now: Instant) -> bool {
+        if self.window >= 1.0 {
+            return (self.in_flight.len() as f64) < self.window.floor();
+        }
+        // windows below one request allow one request in flight, paced out
+        if !self.in_flight.is_empty() {
+            return false;
+        }
+        match (self.last_send, self.smoothed_rtt) {
+            (Some(last_send), Some(rtt)) => {
+                now.saturating_duration_since(last_send) >= rtt.div_f64(self.window)
+            }
+            _ => true,
+        }
+    }
+
+    /// Waits until another request may be sent, calling `poll` (which should process responses
+    /// and timeouts) in the meantime. Returns whether the send was held back.
+    pub fn wait_for_window(
+        &mut self,
+        poll: &mut dyn FnMut(&mut Self) -> color_eyre::eyre::Result<()>,
+    ) -> color_eyre::eyre::Result<bool> {
+        let mut now = Instant::now();
+        if self.can_send(now) {
+            return Ok(false);
+        }
+        self.start_throttling(now);
+        while !self.can_send(now) {
+            poll(self)?;
+            now = Instant::now();
+            self.expire(now);
+        }
+        self.stop_throttling(now);
+        Ok(true)
+    }
+
+    fn start_throttling(&mut self, now: Instant) {
+        if self.throttled_since.is_none() {
+            tracing::debug!(
+                window = ?self.window,
+                in_flight = self.in_flight.len(),
+                "Throttling sends"
+            );
+            self.throttled_since = Some(now);
+        }
+        self.stats.throttled_sends += 1;
+    }
+
+    fn stop_throttling(&mut self, now: Instant) {
+        if let Some(since) = self.throttled_since.take() {
+            self.stats.throttled_ns += now.saturating_duration_since(since).as_nanos() as u64;
+        }
+    }
+
+    /// Records a request (or a retransmission of one) being sent.
+    #[inline]
+    pub fn on_send(&mut self, msg_id: MsgID, now: Instant) {
+        self.in_flight.insert(msg_id, now);
+        self.last_send = Some(now);
+    }
+
+    /// Records a response and its RTT; responses to requests not in flight (e.g., ones already
+    /// considered lost) only update the RTT estimate.
+    pub fn on_response(&mut self, msg_id: MsgID, rtt: Duration, now: Instant) {
+        let was_in_flight = self.in_flight.remove(&msg_id).is_some();
+        self.smoothed_rtt = Some(match self.smoothed_rtt {
+            Some(srtt) => srtt.mul_f64(1.0 - RTT_SMOOTHING) + rtt.mul_f64(RTT_SMOOTHING),
+            None => rtt,
+        });
+        self.stats.smoothed_rtt_ns = self.smoothed_rtt.unwrap().as_nanos() as u64;
+        if !was_in_flight {
+            return;
+        }
+        if rtt < self.config.target_delay {
+            // about `additive_increase` per window's worth of responses
+            let increase = match self.window >= 1.0 {
+                true => self.config.additive_increase / self.window,
+                false => self.config.additive_increase,
+            };
+            self.set_window(self.window + increase);
+            self.stats.increases += 1;
+        } else {
+            let overshoot = (rtt - self.config.target_delay).as_secs_f64() / rtt.as_secs_f64();
+            let decrease = f64::min(self.config.beta * overshoot, self.config.max_decrease);
+            self.decrease(decrease, now);
+        }
+    }
+
+    /// Records a server's `OVERLOADED_REPLY` to a request; if it is retransmitted, call `on_send`
+    /// again.
+    pub fn on_overloaded(&mut self, msg_id: MsgID, now: Instant) {
+        self.in_flight.remove(&msg_id);
+        self.stats.overloaded_replies += 1;
+        self.decrease(self.config.max_decrease, now);
+    }
+
+    /// Records a request timing out; if it is retransmitted, call `on_send` again.
+    pub fn on_timeout(&mut self, msg_id: MsgID, now: Instant) {
+        self.in_flight.remove(&msg_id);
+        self.stats.losses += 1;
+        self.decrease(self.config.max_decrease, now);
+    }
+
+    /// Considers requests in flight for longer than the loss timeout lost, so requests that are
+    /// never answered (and never retransmitted) do not hold the window.
+    pub fn expire(&mut self, now: Instant) {
+        let loss_timeout = self.config.loss_timeout;
+        let before = self.in_flight.len();
+        self.in_flight
+            .retain(|_, sent| now.saturating_duration_since(*sent) < loss_timeout);
+        let lost = before - self.in_flight.len();
+        if lost > 0 {
+            self.stats.losses += lost as u64;
+            self.decrease(self.config.max_decrease, now);
+        }
+    }
+
+    /// Shrinks the window by `fraction`, unless it already shrank within the last RTT.
+    fn decrease(&mut self, fraction: f64, now: Instant) {
+        let rtt = self.smoothed_rtt.unwrap_or(self.config.target_delay);
+        if let Some(last_decrease) = self.last_decrease {
+            if now.saturating_duration_since(last_decrease) < rtt {
+                return;
+            }
+        }
+        self.set_window(self.window * (1.0 - fraction));
+        self.last_decrease = Some(now);
+        self.stats.decreases += 1;
+    }
+
+    fn set_window(&mut self, window: f64) {
+        self.window = window.clamp(self.config.min_window, self.config.max_window);
+        self.stats.window = self.window;
+    }
+
+    /// How long to hold back the retransmission of a request the server shed: one smoothed
+    /// RTT, or the target delay before any response arrived.
+    pub fn retransmit_backoff(&self) -> Duration {
+        self.smoothed_rtt.unwrap_or(self.config.target_delay)
+    }
+
+    pub fn window(&self) -> f64 {
+        self.window
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn is_throttling(&self) -> bool {
+        self.throttled_since.is_some()
+    }
+
+    pub fn stats(&self) -> &FlowControlStats {
+        &self.stats
+    }
+}
+
+/// Requests the server shed, waiting to be retransmitted.
+#[derive(Debug, Clone, Default)]
+pub struct RetransmitQueue {
+    /// Each request, with the earliest time it may be retransmitted.
+    queue: VecDeque<(Instant, MsgID, ConnID)>,
+}
+
+impl RetransmitQueue {
+    /// Holds back the retransmission of `msg_id` until `not_before`.
+    pub fn defer(&mut self, msg_id: MsgID, conn_id: ConnID, not_before: Instant) {
+        self.queue.push_back((not_before, msg_id, conn_id));
+    }
+
+    /// Takes the oldest request whose backoff has passed, if any.
+    pub fn pop_ready(&mut self, now: Instant) -> Option<(MsgID, ConnID)> {
+        match self.queue.front() {
+            Some((not_before, _, _)) if *not_before <= now => self
+                .queue
+                .pop_front()
+                .map(|(_, msg_id, conn_id)| (msg_id, conn_id)),
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> FlowControlConfig {
+        FlowControlConfig {
+            target_delay: Duration::from_micros(100),
+            initial_window: 2.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn window_limits_requests_in_flight() {
+        let mut controller = FlowController::new(config());
+        let now = Instant::now();
+        assert!(controller.can_send(now));
+        controller.on_send(0, now);
+        assert!(controller.can_send(now));
+        controller.on_send(1, now);
+        assert!(!controller.can_send(now));
+
+        // a fast response opens the window by half a request (one per window of responses)
+        controller.on_response(0, Duration::from_micros(50), now);
+        assert_eq!(controller.window(), 2.5);
+        assert!(controller.can_send(now));
+        // unknown responses do not change the window
+        controller.on_response(7, Duration::from_micros(50), now);
+        assert_eq!(controller.window(), 2.5);
+    }
+
+    #[test]
+    fn slow_responses_shrink_window_once_per_rtt() {
+        let mut controller = FlowController::new(FlowControlConfig {
+            initial_window: 10.0,
+            ..config()
+        });
+        let start = Instant::now();
+        for id in 0..3 {
+            controller.on_send(id, start);
+        }
+        // 200us RTT against a 100us target: overshoot 0.5, decrease 0.8 * 0.5
+        controller.on_response(0, Duration::from_micros(200), start);
+        assert!((controller.window() - 6.0).abs() < 1e-9);
+        controller.on_response(1, Duration::from_micros(200), start);
+        assert!((controller.window() - 6.0).abs() < 1e-9);
+        let later = start + Duration::from_millis(1);
+        controller.on_response(2, Duration::from_micros(200), later);
+        assert!((controller.window() - 3.6).abs() < 1e-9);
+        assert_eq!(controller.stats().decreases, 2);
+    }
+
+    #[test]
+    fn losses_and_overload_shrink_window() {
+        let mut controller = FlowController::new(FlowControlConfig {
+            initial_window: 8.0,
+            ..config()
+        });
+        let start = Instant::now();
+        controller.on_send(0, start);
+        controller.on_send(1, start);
+        controller.on_overloaded(0, start);
+        assert_eq!(controller.window(), 4.0);
+        assert_eq!(controller.in_flight(), 1);
+
+        // request 1 is never answered
+        let later = start + DEFAULT_LOSS_TIMEOUT;
+        controller.expire(later);
+        assert_eq!(controller.window(), 2.0);
+        assert_eq!(controller.in_flight(), 0);
+        assert_eq!(controller.stats().losses, 1);
+    }
+
+    #[test]
+    fn small_windows_pace_sends() {
+        let mut controller = FlowController::new(FlowControlConfig {
+            initial_window: 0.5,
+            ..config()
+        });
+        let start = Instant::now();
+        controller.on_send(0, start);
+        assert!(!controller.can_send(start));
+        controller.on_response(0, Duration::from_micros(100), start);
+        // one request per RTT / window = 200us
+        assert!(!controller.can_send(start + Duration::from_micros(150)));
+        assert!(controller.can_send(start + Duration::from_micros(200)));
+    }
+
+    #[test]
+    fn retransmits_wait_out_their_backoff() {
+        let mut controller = FlowController::new(config());
+        assert_eq!(controller.retransmit_backoff(), Duration::from_micros(100));
+        let start = Instant::now();
+        controller.on_send(0, start);
+        controller.on_response(0, Duration::from_micros(40), start);
+        assert_eq!(controller.retransmit_backoff(), Duration::from_micros(40));
+
+        let mut retransmits = RetransmitQueue::default();
+        retransmits.defer(1, 0, start + controller.retransmit_backoff());
+        retransmits.defer(2, 0, start + Duration::from_micros(80));
+        assert_eq!(retransmits.pop_ready(start), None);
+        assert_eq!(
+            retransmits.pop_ready(start + Duration::from_micros(40)),
+            Some((1, 0))
+        );
+        assert_eq!(
+            retransmits.pop_ready(start + Duration::from_micros(40)),
+            None
+        );
+        assert_eq!(retransmits.len(), 1);
+        assert_eq!(
+            retransmits.pop_ready(start + Duration::from_micros(80)),
+            Some((2, 0))
+        );
+        assert!(retransmits.is_empty());
+    }
+}
@@ -1,2 +1,3 @@
 pub mod client_threads;
+pub mod flow_control;
 pub mod request_schedule;
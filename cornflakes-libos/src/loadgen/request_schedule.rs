@@ -68,6 +68,13 @@ impl SpinTimer {
         self.last_return = Some(self.clk.raw());
         Ok(())
     }
+
+    /// Forgets time spent behind schedule (e.g., while flow control held sends back), so the
+    /// timer does not send a catch-up burst.
+    pub fn reset_deficit(&mut self) {
+        self.deficit = Duration::from_nanos(0);
+        self.last_return = Some(self.clk.raw());
+    }
 }
 #[inline]
 pub fn rate_pps_to_interarrival_nanos(rate: u64) -> f64 {
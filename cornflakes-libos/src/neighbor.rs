@@ -0,0 +1,532 @@
+//! Neighbor resolution for Ethernet datapaths.
+//!
+//! A `NeighborTable` answers ARP requests for our addresses and resolves the MAC addresses of
+//! peers missing from the static `known_hosts` table, so a new machine only needs its own entry
+//! in its own config file. The table only deals in frames, so every Ethernet-level datapath can
+//! share it: the datapath hands it the ARP frames it receives (see `is_arp_frame`) and transmits
+//! the frames it returns. Resolved entries age out after `NeighborConfig::max_age`; addresses
+//! that were never resolved, or whose entries expired, fall back to the static table.
+//!
+//! Datapaths resolve on `connect` by polling for the reply, so data packets that arrive in the
+//! meantime are set aside and handed out by the next `pop` (see `deferred_with_durations`).
+use super::{
+    datapath::{Datapath, ReceivedPkt},
+    utils::{EtherType2, ETHERNET2_HEADER2_SIZE},
+    ConnID, MsgID,
+};
+use byteorder::{ByteOrder, NetworkEndian};
+use color_eyre::eyre::{bail, ensure, Result};
+use eui48::MacAddress;
+use hashbrown::HashMap;
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::{Duration, Instant},
+};
+
+/// ARP packet for IPv4 over Ethernet.
+pub const ARP_PACKET_SIZE: usize = 28;
+pub const ARP_FRAME_SIZE: usize = ETHERNET2_HEADER2_SIZE + ARP_PACKET_SIZE;
+const ARP_HARDWARE_ETHERNET: u16 = 1;
+const ARP_OPERATION_REQUEST: u16 = 1;
+const ARP_OPERATION_REPLY: u16 = 2;
+const ETHERNET_ADDR_LEN: u8 = 6;
+const IPV4_ADDR_LEN: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpOperation {
+    Request,
+    Reply,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub operation: ArpOperation,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    /// Parses the ARP packet carried in an Ethernet frame. Fails on frames that are not ARP,
+    /// are truncated, or resolve anything other than IPv4 addresses to Ethernet addresses.
+    pub fn parse(frame: &[u8]) -> Result<ArpPacket> {
+        ensure!(
+            frame.len() >= ARP_FRAME_SIZE,
+            "ARP frame of {} bytes is shorter than {} bytes",
+            frame.len(),
+            ARP_FRAME_SIZE
+        );
+        ensure!(is_arp_frame(frame), "Not an ARP frame");
+        let arp = &frame[ETHERNET2_HEADER2_SIZE..ARP_FRAME_SIZE];
+        ensure!(
+            NetworkEndian::read_u16(&arp[0..2]) == ARP_HARDWARE_ETHERNET
+                && NetworkEndian::read_u16(&arp[2..4]) == EtherType2::Ipv4 as u16
+                && arp[4] == ETHERNET_ADDR_LEN
+                && arp[5] == IPV4_ADDR_LEN,
+            "ARP packet does not map IPv4 to Ethernet addresses"
+        );
+        let operation = match NetworkEndian::read_u16(&arp[6..8]) {
+            ARP_OPERATION_REQUEST => ArpOperation::Request,
+            ARP_OPERATION_REPLY => ArpOperation::Reply,
+            x => {
+                bail!("Unknown ARP operation: {}", x);
+            }
+        };
+        Ok(ArpPacket {
+            operation,
+            sender_mac: MacAddress::from_bytes(&arp[8..14])?,
+            sender_ip: Ipv4Addr::from(NetworkEndian::read_u32(&arp[14..18])),
+            target_mac: MacAddress::from_bytes(&arp[18..24])?,
+            target_ip: Ipv4Addr::from(NetworkEndian::read_u32(&arp[24..28])),
+        })
+    }
+
+    /// Writes the packet, inside an Ethernet header from the sender to `dst_mac`, into the first
+    /// `ARP_FRAME_SIZE` bytes of `frame`.
+    pub fn write_frame(&self, dst_mac: &MacAddress, frame: &mut [u8]) -> Result<()> {
+        ensure!(
+            frame.len() >= ARP_FRAME_SIZE,
+            "Buffer of {} bytes cannot hold a {} byte ARP frame",
+            frame.len(),
+            ARP_FRAME_SIZE
+        );
+        frame[0..6].copy_from_slice(dst_mac.as_bytes());
+        frame[6..12].copy_from_slice(self.sender_mac.as_bytes());
+        NetworkEndian::write_u16(&mut frame[12..14], EtherType2::Arp as u16);
+        let arp = &mut frame[ETHERNET2_HEADER2_SIZE..ARP_FRAME_SIZE];
+        NetworkEndian::write_u16(&mut arp[0..2], ARP_HARDWARE_ETHERNET);
+        NetworkEndian::write_u16(&mut arp[2..4], EtherType2::Ipv4 as u16);
+        arp[4] = ETHERNET_ADDR_LEN;
+        arp[5] = IPV4_ADDR_LEN;
+        let operation = match self.operation {
+            ArpOperation::Request => ARP_OPERATION_REQUEST,
+            ArpOperation::Reply => ARP_OPERATION_REPLY,
+        };
+        NetworkEndian::write_u16(&mut arp[6..8], operation);
+        arp[8..14].copy_from_slice(self.sender_mac.as_bytes());
+        arp[14..18].copy_from_slice(&self.sender_ip.octets());
+        arp[18..24].copy_from_slice(self.target_mac.as_bytes());
+        arp[24..28].copy_from_slice(&self.target_ip.octets());
+        Ok(())
+    }
+
+    pub fn to_frame(&self, dst_mac: &MacAddress) -> Vec<u8> {
+        let mut frame = vec![0u8; ARP_FRAME_SIZE];
+        // cannot fail: the buffer is exactly one frame long
+        self.write_frame(dst_mac, &mut frame).unwrap();
+        frame
+    }
+}
+
+/// Whether the Ethernet frame carries ARP; datapaths check this before `check_udp_frame`, which
+/// drops everything but UDP.
+#[inline]
+pub fn is_arp_frame(frame: &[u8]) -> bool {
+    frame.len() >= ETHERNET2_HEADER2_SIZE
+        && NetworkEndian::read_u16(&frame[12..14]) == EtherType2::Arp as u16
+}
+
+/// Static entries for a `NeighborTable` from the `known_hosts` map of a config file. ARP only
+/// resolves IPv4 addresses, so IPv6 hosts are left out.
+pub fn static_ipv4_entries(
+    known_hosts: &HashMap<IpAddr, MacAddress>,
+) -> HashMap<Ipv4Addr, MacAddress> {
+    known_hosts
+        .iter()
+        .filter_map(|(ip, mac)| match ip {
+            IpAddr::V4(ip) => Some((*ip, *mac)),
+            IpAddr::V6(_) => None,
+        })
+        .collect()
+}
+
+/// Pairs packets that were set aside while resolving a peer with the time since their request
+/// was sent, taking them out of the outgoing window as `pop_with_durations` does. Packets that
+/// answer no outstanding request are dropped.
+pub fn deferred_with_durations<D>(
+    pkts: Vec<ReceivedPkt<D>>,
+    outgoing_window: &mut HashMap<(MsgID, ConnID), Instant>,
+) -> Vec<(ReceivedPkt<D>, Duration)>
+where
+    D: Datapath,
+{
+    pkts.into_iter()
+        .filter_map(
+            |pkt| match outgoing_window.remove(&(pkt.msg_id(), pkt.conn_id())) {
+                Some(start_time) => Some((pkt, start_time.elapsed())),
+                None => {
+                    tracing::warn!(
+                        msg_id = pkt.msg_id(),
+                        conn_id = pkt.conn_id(),
+                        "Dropping deferred packet missing from outgoing window"
+                    );
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NeighborConfig {
+    /// How long a resolved entry is trusted before the address has to be resolved again.
+    pub max_age: Duration,
+    /// How long to wait for a reply before sending another request.
+    pub retry_interval: Duration,
+    /// Requests sent for one address before resolution fails.
+    pub max_requests: usize,
+}
+
+impl Default for NeighborConfig {
+    fn default() -> Self {
+        NeighborConfig {
+            max_age: Duration::from_secs(60),
+            retry_interval: Duration::from_millis(200),
+            max_requests: 5,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NeighborStats {
+    /// Requests for one of our addresses that were answered.
+    pub requests_answered: u64,
+    /// Requests sent to resolve a peer.
+    pub requests_sent: u64,
+    /// Resolved entries learned from ARP packets.
+    pub entries_learned: u64,
+    /// Lookups answered from the static table because no resolved entry was valid.
+    pub static_lookups: u64,
+    /// Resolutions that gave up after `NeighborConfig::max_requests` requests.
+    pub failed_resolutions: u64,
+}
+
+impl NeighborStats {
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            requests_answered = self.requests_answered,
+            requests_sent = self.requests_sent,
+            entries_learned = self.entries_learned,
+            static_lookups = self.static_lookups,
+            failed_resolutions = self.failed_resolutions,
+            "{}",
+            msg
+        );
+    }
+}
+
+/// Outcome of one `NeighborTable::resolve` call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    Resolved(MacAddress),
+    /// Not resolved yet: transmit the request frame, if there is one, and feed the ARP frames
+    /// that arrive to `process_frame` before trying again.
+    Pending(Option<Vec<u8>>),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ResolvedEntry {
+    mac: MacAddress,
+    updated: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PendingResolution {
+    requests_sent: usize,
+    last_sent: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct NeighborTable {
+    our_mac: MacAddress,
+    /// Addresses we answer requests for; requests we send come from the first. Empty on IPv6
+    /// hosts, which only resolve peers through static entries.
+    our_ips: Vec<Ipv4Addr>,
+    /// Entries from the config file; they never age out.
+    static_entries: HashMap<Ipv4Addr, MacAddress>,
+    resolved: HashMap<Ipv4Addr, ResolvedEntry>,
+    pending: HashMap<Ipv4Addr, PendingResolution>,
+    config: NeighborConfig,
+    stats: NeighborStats,
+}
+
+impl NeighborTable {
+    pub fn new(
+        our_mac: MacAddress,
+        our_ips: Vec<Ipv4Addr>,
+        static_entries: HashMap<Ipv4Addr, MacAddress>,
+        config: NeighborConfig,
+    ) -> Result<Self> {
+        Ok(NeighborTable {
+            our_mac,
+            our_ips,
+            static_entries,
+            resolved: HashMap::default(),
+            pending: HashMap::default(),
+            config,
+            stats: NeighborStats::default(),
+        })
+    }
+
+    pub fn stats(&self) -> NeighborStats {
+        self.stats
+    }
+
+    /// MAC address for the IP: the resolved entry if it has not aged out, otherwise the static
+    /// one, if any.
+    pub fn lookup(&mut self, ip: &Ipv4Addr, now: Instant) -> Option<MacAddress> {
+        if let Some(entry) = self.resolved.get(ip) {
+            if now.saturating_duration_since(entry.updated) < self.config.max_age {
+                return Some(entry.mac);
+            }
+        }
+        let mac = self.static_entries.get(ip).copied();
+        if mac.is_some() {
+            self.stats.static_lookups += 1;
+        }
+        mac
+    }
+
+    /// Records a mapping learned outside of ARP, e.g. from the source of a received packet.
+    pub fn insert(&mut self, ip: Ipv4Addr, mac: MacAddress, now: Instant) {
+        self.resolved
+            .insert(ip, ResolvedEntry { mac, updated: now });
+        self.pending.remove(&ip);
+    }
+
+    /// Drops resolved entries that have aged out; `lookup` already ignores them, this only
+    /// reclaims their memory.
+    pub fn remove_expired(&mut self, now: Instant) {
+        let max_age = self.config.max_age;
+        self.resolved
+            .retain(|_, entry| now.saturating_duration_since(entry.updated) < max_age);
+    }
+
+    /// Handles a received ARP frame. Following RFC 826, the sender's mapping is learned if the
+    /// packet is addressed to us or refreshes an entry we already hold. Returns the reply to
+    /// transmit if the frame is a request for one of our addresses.
+    pub fn process_frame(&mut self, frame: &[u8], now: Instant) -> Result<Option<Vec<u8>>> {
+        let packet = ArpPacket::parse(frame)?;
+        let for_us = self.our_ips.contains(&packet.target_ip);
+        // probes carry an unspecified sender address, which must not be learned
+        if !packet.sender_ip.is_unspecified()
+            && (for_us || self.resolved.contains_key(&packet.sender_ip))
+        {
+            tracing::debug!(ip =? packet.sender_ip, mac =? packet.sender_mac, "Learned neighbor");
+            self.insert(packet.sender_ip, packet.sender_mac, now);
+            self.stats.entries_learned += 1;
+        }
+
+        if packet.operation != ArpOperation::Request || !for_us {
+            return Ok(None);
+        }
+        self.stats.requests_answered += 1;
+        let reply = ArpPacket {
+            operation: ArpOperation::Reply,
+            sender_mac: self.our_mac,
+            sender_ip: packet.target_ip,
+            target_mac: packet.sender_mac,
+            target_ip: packet.sender_ip,
+        };
+        Ok(Some(reply.to_frame(&packet.sender_mac)))
+    }
+
+    /// Resolves the IP, asking for it with a broadcast request whenever the last one has gone
+    /// unanswered for `NeighborConfig::retry_interval`. Fails once `NeighborConfig::max_requests`
+    /// requests went unanswered; the next call starts over.
+    pub fn resolve(&mut self, ip: &Ipv4Addr, now: Instant) -> Result<Resolution> {
+        if let Some(mac) = self.lookup(ip, now) {
+            return Ok(Resolution::Resolved(mac));
+        }
+        let our_ip = match self.our_ips.first() {
+            Some(our_ip) => *our_ip,
+            None => bail!(
+                "Cannot send ARP requests for {:?} without an ipv4 address",
+                ip
+            ),
+        };
+        let retry_interval = self.config.retry_interval;
+        let pending = self.pending.entry(*ip).or_insert(PendingResolution {
+            requests_sent: 0,
+            last_sent: now,
+        });
+        if pending.requests_sent > 0
+            && now.saturating_duration_since(pending.last_sent) < retry_interval
+        {
+            return Ok(Resolution::Pending(None));
+        }
+        if pending.requests_sent >= self.config.max_requests {
+            self.pending.remove(ip);
+            self.stats.failed_resolutions += 1;
+            bail!(
+                "No ARP reply for {:?} after {} requests",
+                ip,
+                self.config.max_requests
+            );
+        }
+        pending.requests_sent += 1;
+        pending.last_sent = now;
+        self.stats.requests_sent += 1;
+        let request = ArpPacket {
+            operation: ArpOperation::Request,
+            sender_mac: self.our_mac,
+            sender_ip: our_ip,
+            target_mac: MacAddress::nil(),
+            target_ip: *ip,
+        };
+        Ok(Resolution::Pending(Some(
+            request.to_frame(&MacAddress::broadcast()),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(last: u8) -> MacAddress {
+        MacAddress::new([0x02, 0, 0, 0, 0, last])
+    }
+
+    fn table(last: u8, static_entries: &[(u8, u8)]) -> NeighborTable {
+        NeighborTable::new(
+            mac(last),
+            vec![Ipv4Addr::new(10, 0, 0, last)],
+            static_entries
+                .iter()
+                .map(|(ip, m)| (Ipv4Addr::new(10, 0, 0, *ip), mac(*m)))
+                .collect(),
+            NeighborConfig::default(),
+        )
+        .unwrap()
+    }
+
+    fn request_frame(resolution: Resolution) -> Vec<u8> {
+        match resolution {
+            Resolution::Pending(Some(frame)) => frame,
+            x => panic!("Expected a request to send, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn frame_roundtrip() {
+        let packet = ArpPacket {
+            operation: ArpOperation::Reply,
+            sender_mac: mac(1),
+            sender_ip: Ipv4Addr::new(10, 0, 0, 1),
+            target_mac: mac(2),
+            target_ip: Ipv4Addr::new(10, 0, 0, 2),
+        };
+        let frame = packet.to_frame(&mac(2));
+        assert!(is_arp_frame(&frame));
+        assert_eq!(&frame[0..6], mac(2).as_bytes());
+        assert_eq!(ArpPacket::parse(&frame).unwrap(), packet);
+        assert!(ArpPacket::parse(&frame[..ARP_FRAME_SIZE - 1]).is_err());
+
+        let mut ipv4_frame = frame.clone();
+        NetworkEndian::write_u16(&mut ipv4_frame[12..14], EtherType2::Ipv4 as u16);
+        assert!(!is_arp_frame(&ipv4_frame));
+        assert!(ArpPacket::parse(&ipv4_frame).is_err());
+    }
+
+    #[test]
+    fn resolves_over_loopback() {
+        let now = Instant::now();
+        let mut client = table(1, &[]);
+        let mut server = table(2, &[]);
+        let server_ip = Ipv4Addr::new(10, 0, 0, 2);
+
+        let request = request_frame(client.resolve(&server_ip, now).unwrap());
+        assert_eq!(&request[0..6], MacAddress::broadcast().as_bytes());
+        let reply = server.process_frame(&request, now).unwrap().unwrap();
+        assert_eq!(server.stats().requests_answered, 1);
+        // the request taught the server the client's address
+        assert_eq!(
+            server.lookup(&Ipv4Addr::new(10, 0, 0, 1), now),
+            Some(mac(1))
+        );
+
+        assert_eq!(client.process_frame(&reply, now).unwrap(), None);
+        assert_eq!(
+            client.resolve(&server_ip, now).unwrap(),
+            Resolution::Resolved(mac(2))
+        );
+        assert_eq!(client.stats().requests_sent, 1);
+    }
+
+    #[test]
+    fn ignores_requests_for_other_addresses() {
+        let now = Instant::now();
+        let mut client = table(1, &[]);
+        let mut bystander = table(3, &[]);
+        let request = request_frame(client.resolve(&Ipv4Addr::new(10, 0, 0, 2), now).unwrap());
+        assert_eq!(bystander.process_frame(&request, now).unwrap(), None);
+        // nor does it learn from a request it was not the target of
+        assert_eq!(bystander.lookup(&Ipv4Addr::new(10, 0, 0, 1), now), None);
+    }
+
+    #[test]
+    fn retries_then_fails() {
+        let now = Instant::now();
+        let config = NeighborConfig::default();
+        let mut client = table(1, &[]);
+        let ip = Ipv4Addr::new(10, 0, 0, 9);
+
+        let mut at = now;
+        for _ in 0..config.max_requests {
+            request_frame(client.resolve(&ip, at).unwrap());
+            // nothing more to send until the retry interval passes
+            assert_eq!(client.resolve(&ip, at).unwrap(), Resolution::Pending(None));
+            at += config.retry_interval;
+        }
+        assert!(client.resolve(&ip, at).is_err());
+        assert_eq!(client.stats().requests_sent, config.max_requests as u64);
+        assert_eq!(client.stats().failed_resolutions, 1);
+        // a later attempt starts over
+        request_frame(client.resolve(&ip, at).unwrap());
+    }
+
+    #[test]
+    fn static_entries_skip_ipv6_hosts() {
+        let known_hosts: HashMap<IpAddr, MacAddress> = [
+            (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), mac(2)),
+            (IpAddr::V6("fd00::3".parse().unwrap()), mac(3)),
+        ]
+        .into_iter()
+        .collect();
+        let entries = static_ipv4_entries(&known_hosts);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries.get(&Ipv4Addr::new(10, 0, 0, 2)), Some(&mac(2)));
+    }
+
+    #[test]
+    fn aged_entries_fall_back_to_static() {
+        let now = Instant::now();
+        let config = NeighborConfig::default();
+        let mut client = table(1, &[(2, 7)]);
+        let ip = Ipv4Addr::new(10, 0, 0, 2);
+        assert_eq!(client.lookup(&ip, now), Some(mac(7)));
+
+        client.insert(ip, mac(2), now);
+        assert_eq!(client.lookup(&ip, now), Some(mac(2)));
+        let later = now + config.max_age;
+        assert_eq!(client.lookup(&ip, later), Some(mac(7)));
+        client.remove_expired(later);
+        assert_eq!(client.lookup(&ip, now), Some(mac(7)));
+    }
+
+    #[test]
+    fn tables_without_ipv4_only_resolve_static_entries() {
+        let now = Instant::now();
+        let static_entries = [(Ipv4Addr::new(10, 0, 0, 2), mac(2))].into_iter().collect();
+        let mut table =
+            NeighborTable::new(mac(1), vec![], static_entries, NeighborConfig::default()).unwrap();
+        assert_eq!(
+            table.resolve(&Ipv4Addr::new(10, 0, 0, 2), now).unwrap(),
+            Resolution::Resolved(mac(2))
+        );
+        assert!(table.resolve(&Ipv4Addr::new(10, 0, 0, 3), now).is_err());
+        assert_eq!(table.stats().requests_sent, 0);
+    }
+}
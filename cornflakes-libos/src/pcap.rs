@@ -0,0 +1,704 @@
+//! Packet capture and replay in the libpcap file format.
+//!
+//! Bypass datapaths never hand packets to the kernel, so `tcpdump` cannot see them. A datapath
+//! given a `PacketCapture` (see `Datapath::set_packet_capture`) records every frame it transmits
+//! and receives; the frames are copied on the datapath thread and written to the file by a
+//! background thread, and frames that arrive while the writer is behind are dropped rather than
+//! stalling the datapath. Datapaths that do not see the wire format (e.g., kernel sockets)
+//! record their messages with the headers `build_udp_frame` writes.
+//!
+//! `PcapReplay` reads a capture back and splits it into the requests a server received and the
+//! responses it sent, so a datapath can feed the requests to a server offline and check its
+//! responses against the recorded ones.
+use super::{
+    utils::{
+        ip_header_size, parse_udp_frame_headers, udp_checksum, write_eth_hdr, write_ip_hdr,
+        write_pkt_id, write_udp_checksum, write_udp_hdr, AddressInfo, HeaderInfo,
+        ETHERNET2_HEADER2_SIZE, HEADER_ID_SIZE, UDP_HEADER2_SIZE,
+    },
+    MsgID,
+};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use color_eyre::eyre::{bail, ensure, Result, WrapErr};
+use hashbrown::{HashMap, HashSet};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    net::IpAddr,
+    sync::mpsc::{sync_channel, SyncSender, TrySendError},
+    thread::JoinHandle,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Magic number of pcap files with microsecond timestamps.
+pub const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+/// Magic number of pcap files with nanosecond timestamps.
+pub const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+pub const PCAP_VERSION_MAJOR: u16 = 2;
+pub const PCAP_VERSION_MINOR: u16 = 4;
+/// Frames longer than this are truncated in the file.
+pub const PCAP_SNAPLEN: u32 = 65535;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const PCAP_GLOBAL_HEADER_SIZE: usize = 24;
+pub const PCAP_RECORD_HEADER_SIZE: usize = 16;
+/// Frames that can wait for the writer thread before new frames are dropped.
+pub const DEFAULT_CAPTURE_QUEUE_DEPTH: usize = 8192;
+
+/// Writes Ethernet frames to a pcap stream.
+pub struct PcapWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+}
+
+impl<W> PcapWriter<W>
+where
+    W: Write,
+{
+    /// Writes the pcap global header (microsecond timestamps, host byte order).
+    pub fn new(mut writer: W) -> Result<Self> {
+        let mut header = [0u8; PCAP_GLOBAL_HEADER_SIZE];
+        LittleEndian::write_u32(&mut header[0..4], PCAP_MAGIC);
+        LittleEndian::write_u16(&mut header[4..6], PCAP_VERSION_MAJOR);
+        LittleEndian::write_u16(&mut header[6..8], PCAP_VERSION_MINOR);
+        // timezone offset and timestamp accuracy (bytes 8..16) are always zero
+        LittleEndian::write_u32(&mut header[16..20], PCAP_SNAPLEN);
+        LittleEndian::write_u32(&mut header[20..24], LINKTYPE_ETHERNET);
+        writer
+            .write_all(&header)
+            .wrap_err("Failed to write pcap header")?;
+        Ok(PcapWriter { writer })
+    }
+
+    /// Appends a frame captured at `timestamp` (since the Unix epoch), truncated to
+    /// `PCAP_SNAPLEN` bytes.
+    pub fn write_frame(&mut self, timestamp: Duration, frame: &[u8]) -> Result<()> {
+        let captured = std::cmp::min(frame.len(), PCAP_SNAPLEN as usize);
+        let mut header = [0u8; PCAP_RECORD_HEADER_SIZE];
+        LittleEndian::write_u32(&mut header[0..4], timestamp.as_secs() as u32);
+        LittleEndian::write_u32(&mut header[4..8], timestamp.subsec_micros());
+        LittleEndian::write_u32(&mut header[8..12], captured as u32);
+        LittleEndian::write_u32(&mut header[12..16], frame.len() as u32);
+        self.writer
+            .write_all(&header)
+            .wrap_err("Failed to write pcap record header")?;
+        self.writer
+            .write_all(&frame[..captured])
+            .wrap_err("Failed to write pcap record")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush().wrap_err("Failed to flush pcap writer")
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Frame read from a pcap file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcapRecord {
+    /// Capture time since the Unix epoch.
+    pub timestamp: Duration,
+    /// Captured bytes, which may be a prefix of the frame.
+    pub data: Vec<u8>,
+    /// Length of the frame on the wire.
+    pub orig_len: usize,
+}
+
+/// Reads Ethernet frames from a pcap stream, written in either byte order with microsecond or
+/// nanosecond timestamps.
+pub struct PcapReader<R>
+where
+    R: Read,
+{
+    reader: R,
+    big_endian: bool,
+    nanos: bool,
+}
+
+impl<R> PcapReader<R>
+where
+    R: Read,
+{
+    /// Reads and checks the pcap global header.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; PCAP_GLOBAL_HEADER_SIZE];
+        reader
+            .read_exact(&mut header)
+            .wrap_err("Failed to read pcap header")?;
+        let (big_endian, nanos) = match LittleEndian::read_u32(&header[0..4]) {
+            PCAP_MAGIC => (false, false),
+            PCAP_MAGIC_NANOS => (false, true),
+            _ => match BigEndian::read_u32(&header[0..4]) {
+                PCAP_MAGIC => (true, false),
+                PCAP_MAGIC_NANOS => (true, true),
+                magic => bail!("Not a pcap file (magic number {:#x})", magic),
+            },
+        };
+        let pcap_reader = PcapReader {
+            reader,
+            big_endian,
+            nanos,
+        };
+        let linktype = pcap_reader.read_u32(&header[20..24]);
+        ensure!(
+            linktype == LINKTYPE_ETHERNET,
+            "Unsupported pcap link type {}; only Ethernet captures can be read",
+            linktype
+        );
+        Ok(pcap_reader)
+    }
+
+    fn read_u32(&self, buf: &[u8]) -> u32 {
+        match self.big_endian {
+            true => BigEndian::read_u32(buf),
+            false => LittleEndian::read_u32(buf),
+        }
+    }
+
+    /// Returns the next frame, or None at the end of the stream.
+    pub fn next_record(&mut self) -> Result<Option<PcapRecord>> {
+        let mut header = [0u8; PCAP_RECORD_HEADER_SIZE];
+        let mut filled = 0;
+        while filled < header.len() {
+            match self.reader.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => {
+                    return Ok(None);
+                }
+                Ok(0) => bail!("Pcap record header truncated after {} bytes", filled),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e).wrap_err("Failed to read pcap record header"),
+            }
+        }
+        let secs = self.read_u32(&header[0..4]) as u64;
+        let fraction = self.read_u32(&header[4..8]);
+        let captured = self.read_u32(&header[8..12]) as usize;
+        let orig_len = self.read_u32(&header[12..16]) as usize;
+        ensure!(
+            captured <= PCAP_SNAPLEN as usize,
+            "Pcap record of {} bytes is longer than the snapshot length",
+            captured
+        );
+        let timestamp = match self.nanos {
+            true => Duration::new(secs, fraction),
+            false => Duration::new(secs, 0) + Duration::from_micros(fraction as u64),
+        };
+        let mut data = vec![0u8; captured];
+        self.reader
+            .read_exact(&mut data)
+            .wrap_err("Pcap record truncated")?;
+        Ok(Some(PcapRecord {
+            timestamp,
+            data,
+            orig_len,
+        }))
+    }
+}
+
+/// Builds the Ethernet frame the bypass datapaths would send for a message: ethernet, ip and udp
+/// headers (with a UDP checksum), the message id, then the payload segments.
+pub fn build_udp_frame(
+    header_info: &HeaderInfo,
+    msg_id: MsgID,
+    payload: &[&[u8]],
+) -> Result<Vec<u8>> {
+    let ip_start = ETHERNET2_HEADER2_SIZE;
+    let udp_start = ip_start + ip_header_size(&header_info.src_info.ip_addr);
+    let id_start = udp_start + UDP_HEADER2_SIZE;
+    let payload_start = id_start + HEADER_ID_SIZE;
+    let data_len = HEADER_ID_SIZE + payload.iter().map(|seg| seg.len()).sum::<usize>();
+
+    let mut frame = vec![0u8; payload_start];
+    frame.reserve(data_len - HEADER_ID_SIZE);
+    write_eth_hdr(header_info, &mut frame[..ip_start])?;
+    write_ip_hdr(
+        header_info,
+        &mut frame[ip_start..udp_start],
+        UDP_HEADER2_SIZE + data_len,
+    )?;
+    write_udp_hdr(header_info, &mut frame[udp_start..id_start], data_len)?;
+    write_pkt_id(msg_id, &mut frame[id_start..payload_start])?;
+    for seg in payload.iter() {
+        frame.extend_from_slice(seg);
+    }
+
+    let (headers, data) = frame.split_at_mut(payload_start);
+    let checksum = udp_checksum(
+        &header_info.src_info.ip_addr,
+        &header_info.dst_info.ip_addr,
+        &headers[udp_start..id_start],
+        [&headers[id_start..], &data[..]],
+    )?;
+    write_udp_checksum(&mut headers[udp_start..id_start], checksum);
+    Ok(frame)
+}
+
+/// UDP message parsed out of an Ethernet frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedUdpFrame<'a> {
+    /// Sender (`src_info`) and receiver (`dst_info`) of the message.
+    pub header_info: HeaderInfo,
+    pub msg_id: MsgID,
+    /// Message payload, after the message id.
+    pub payload: &'a [u8],
+}
+
+/// Parses a UDP message carrying a cornflakes message id out of an Ethernet frame, without
+/// checking whether it is addressed to us or verifying its checksum.
+pub fn parse_udp_frame(frame: &[u8]) -> Result<ParsedUdpFrame<'_>> {
+    let headers = match parse_udp_frame_headers(frame) {
+        Ok(headers) => headers,
+        Err(drop) => bail!("Cannot parse captured frame: {}", drop),
+    };
+    Ok(ParsedUdpFrame {
+        header_info: headers.header_info,
+        msg_id: headers.msg_id,
+        payload: headers.payload(frame),
+    })
+}
+
+/// Counts of frames a `PacketCapture` queued for writing or dropped.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct CaptureStats {
+    pub captured: u64,
+    /// Frames dropped because the writer thread was behind (or had failed).
+    pub dropped: u64,
+}
+
+/// Records frames to a pcap file from a datapath thread without blocking it: frames are handed
+/// to a writer thread over a bounded queue, and dropped when the queue is full.
+pub struct PacketCapture {
+    sender: Option<SyncSender<(Duration, Vec<u8>)>>,
+    writer_thread: Option<JoinHandle<Result<()>>>,
+    stats: CaptureStats,
+}
+
+impl PacketCapture {
+    /// Creates (or truncates) the pcap file at `path` and starts its writer thread.
+    pub fn new(path: &str, queue_depth: usize) -> Result<Self> {
+        let file =
+            File::create(path).wrap_err(format!("Failed to create capture file {}", path))?;
+        PacketCapture::from_writer(BufWriter::new(file), queue_depth)
+    }
+
+    /// Captures into any writer; the global header is written before this returns.
+    pub fn from_writer<W>(writer: W, queue_depth: usize) -> Result<Self>
+    where
+        W: Write + Send + 'static,
+    {
+        let mut pcap_writer = PcapWriter::new(writer)?;
+        let (sender, receiver) = sync_channel::<(Duration, Vec<u8>)>(queue_depth);
+        let writer_thread = std::thread::Builder::new()
+            .name("pcap-writer".to_string())
+            .spawn(move || {
+                while let Ok((timestamp, frame)) = receiver.recv() {
+                    pcap_writer.write_frame(timestamp, &frame)?;
+                    for (timestamp, frame) in receiver.try_iter() {
+                        pcap_writer.write_frame(timestamp, &frame)?;
+                    }
+                    // flush whenever the queue drains, so little is lost if the process is killed
+                    pcap_writer.flush()?;
+                }
+                Ok(())
+            })
+            .wrap_err("Failed to spawn pcap writer thread")?;
+        Ok(PacketCapture {
+            sender: Some(sender),
+            writer_thread: Some(writer_thread),
+            stats: CaptureStats::default(),
+        })
+    }
+
+    fn enqueue(&mut self, frame: Vec<u8>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let sender = match self.sender.as_ref() {
+            Some(sender) => sender,
+            None => {
+                self.stats.dropped += 1;
+                return;
+            }
+        };
+        match sender.try_send((timestamp, frame)) {
+            Ok(()) => self.stats.captured += 1,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.stats.dropped += 1
+            }
+        }
+    }
+
+    /// Records a frame given as the segments it is transmitted or received in.
+    pub fn record_frame<'a>(&mut self, segments: impl IntoIterator<Item = &'a [u8]>) {
+        let mut frame = Vec::new();
+        for seg in segments {
+            frame.extend_from_slice(seg);
+        }
+        self.enqueue(frame);
+    }
+
+    /// Records a message as the frame `build_udp_frame` writes for it.
+    pub fn record_udp(&mut self, header_info: &HeaderInfo, msg_id: MsgID, payload: &[&[u8]]) {
+        match build_udp_frame(header_info, msg_id, payload) {
+            Ok(frame) => self.enqueue(frame),
+            Err(e) => {
+                tracing::debug!(msg_id, "Could not build frame to capture: {:?}", e);
+                self.stats.dropped += 1;
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CaptureStats {
+        self.stats
+    }
+
+    /// Waits for queued frames to be written and closes the file.
+    pub fn finish(mut self) -> Result<CaptureStats> {
+        self.close()?;
+        Ok(self.stats)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        // dropping the sender ends the writer thread's loop
+        self.sender.take();
+        match self.writer_thread.take() {
+            Some(writer_thread) => match writer_thread.join() {
+                Ok(res) => res,
+                Err(_) => bail!("Pcap writer thread panicked"),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for PacketCapture {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            tracing::warn!("Failed to finish packet capture: {:?}", e);
+        }
+        tracing::info!(
+            captured = self.stats.captured,
+            dropped = self.stats.dropped,
+            "Packet capture finished"
+        );
+    }
+}
+
+/// Returned by datapaths replaying a capture once every request has been delivered, ending the
+/// server's state machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayFinished;
+
+impl std::fmt::Display for ReplayFinished {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Replayed every request in the capture")
+    }
+}
+
+impl std::error::Error for ReplayFinished {}
+
+/// Request read from a capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayedRequest {
+    /// Client that sent the request.
+    pub src: AddressInfo,
+    pub msg_id: MsgID,
+    pub payload: Vec<u8>,
+}
+
+/// Outcome of replaying a capture.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct ReplayStats {
+    /// Requests delivered to the server.
+    pub requests: u64,
+    /// Responses identical to the recorded response.
+    pub matched: u64,
+    /// Responses that differ from the recorded response.
+    pub mismatched: u64,
+    /// Responses to requests whose response was not captured.
+    pub unexpected: u64,
+}
+
+impl ReplayStats {
+    pub fn dump(&self, msg: &str) {
+        tracing::info!(
+            requests = self.requests,
+            matched = self.matched,
+            mismatched = self.mismatched,
+            unexpected = self.unexpected,
+            "{}",
+            msg
+        );
+    }
+}
+
+/// Requests and responses of one server, read from a capture.
+///
+/// Addresses are matched on IP and port only, since captures from different datapaths (or
+/// machines) carry different MAC addresses.
+pub struct PcapReplay {
+    requests: VecDeque<ReplayedRequest>,
+    /// Recorded responses, by client IP, client port and message id.
+    responses: HashMap<(IpAddr, u16, MsgID), Vec<u8>>,
+    answered: HashSet<(IpAddr, u16, MsgID)>,
+    stats: ReplayStats,
+}
+
+impl PcapReplay {
+    /// Reads the capture at `path`, recorded at (or towards) `server`.
+    pub fn from_file(path: &str, server: &AddressInfo) -> Result<Self> {
+        let file = File::open(path).wrap_err(format!("Failed to open capture {}", path))?;
+        PcapReplay::new(PcapReader::new(BufReader::new(file))?, server)
+    }
+
+    /// Frames other than UDP messages to or from `server` are skipped.
+    pub fn new<R>(mut reader: PcapReader<R>, server: &AddressInfo) -> Result<Self>
+    where
+        R: Read,
+    {
+        let is_server =
+            |addr: &AddressInfo| addr.ip_addr == server.ip_addr && addr.udp_port == server.udp_port;
+        let mut requests = VecDeque::default();
+        let mut responses = HashMap::default();
+        let mut skipped = 0;
+        while let Some(record) = reader.next_record()? {
+            let frame = match parse_udp_frame(&record.data) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    tracing::debug!("Skipping captured frame: {:?}", e);
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let HeaderInfo { src_info, dst_info } = frame.header_info;
+            if is_server(&dst_info) {
+                requests.push_back(ReplayedRequest {
+                    src: src_info,
+                    msg_id: frame.msg_id,
+                    payload: frame.payload.to_vec(),
+                });
+            } else if is_server(&src_info) {
+                responses.insert(
+                    (dst_info.ip_addr, dst_info.udp_port, frame.msg_id),
+                    frame.payload.to_vec(),
+                );
+            } else {
+                skipped += 1;
+            }
+        }
+        tracing::info!(
+            requests = requests.len(),
+            responses = responses.len(),
+            skipped,
+            "Loaded capture for replay"
+        );
+        Ok(PcapReplay {
+            requests,
+            responses,
+            answered: HashSet::default(),
+            stats: ReplayStats::default(),
+        })
+    }
+
+    /// Next request to deliver, in capture order.
+    pub fn next_request(&mut self) -> Option<ReplayedRequest> {
+        let request = self.requests.pop_front()?;
+        self.stats.requests += 1;
+        Some(request)
+    }
+
+    /// Compares a response the server sent to `dst` with the recorded one. Returns whether they
+    /// match.
+    pub fn check_response(&mut self, dst: &AddressInfo, msg_id: MsgID, payload: &[u8]) -> bool {
+        let key = (dst.ip_addr, dst.udp_port, msg_id);
+        match self.responses.get(&key) {
+            Some(recorded) if recorded.as_slice() == payload => {
+                self.answered.insert(key);
+                self.stats.matched += 1;
+                true
+            }
+            Some(recorded) => {
+                self.answered.insert(key);
+                tracing::warn!(
+                    ?dst,
+                    msg_id,
+                    recorded_len = recorded.len(),
+                    len = payload.len(),
+                    "Replayed response differs from the recorded response"
+                );
+                self.stats.mismatched += 1;
+                false
+            }
+            None => {
+                tracing::debug!(?dst, msg_id, "No recorded response to compare with");
+                self.stats.unexpected += 1;
+                false
+            }
+        }
+    }
+
+    /// Recorded responses the server has not (yet) sent again.
+    pub fn missing_responses(&self) -> usize {
+        self.responses.len() - self.answered.len()
+    }
+
+    pub fn stats(&self) -> ReplayStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::IPV4_HEADER2_SIZE;
+    use eui48::MacAddress;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn address(last: u8, port: u16) -> AddressInfo {
+        AddressInfo::new(
+            port,
+            Ipv4Addr::new(10, 0, 0, last),
+            MacAddress::new([0x02, 0, 0, 0, 0, last]),
+        )
+    }
+
+    fn capture(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for (i, frame) in frames.iter().enumerate() {
+            writer
+                .write_frame(Duration::from_micros(1_000_000 + i as u64), frame)
+                .unwrap();
+        }
+        writer.into_inner()
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let v4 = HeaderInfo::new(address(1, 50000), address(2, 54321));
+        let frame = build_udp_frame(&v4, 7, &[b"hello ", b"world"]).unwrap();
+        assert_eq!(frame.len(), v4.header_size() + 11);
+        let udp_start = ETHERNET2_HEADER2_SIZE + IPV4_HEADER2_SIZE;
+        assert!(crate::utils::verify_udp_checksum(
+            &v4.src_info.ip_addr,
+            &v4.dst_info.ip_addr,
+            &frame[udp_start..(udp_start + UDP_HEADER2_SIZE)],
+            [&frame[(udp_start + UDP_HEADER2_SIZE)..]],
+        ));
+        let parsed = parse_udp_frame(&frame).unwrap();
+        assert_eq!(parsed.header_info, v4);
+        assert_eq!(parsed.msg_id, 7);
+        assert_eq!(parsed.payload, b"hello world");
+
+        let v6 = HeaderInfo::new(
+            AddressInfo::new(50000, Ipv6Addr::LOCALHOST, MacAddress::default()),
+            AddressInfo::new(54321, Ipv6Addr::LOCALHOST, MacAddress::broadcast()),
+        );
+        let frame = build_udp_frame(&v6, 8, &[]).unwrap();
+        let parsed = parse_udp_frame(&frame).unwrap();
+        assert_eq!(parsed.header_info, v6);
+        assert_eq!(parsed.msg_id, 8);
+        assert!(parsed.payload.is_empty());
+
+        // captured prefixes of a frame do not parse
+        assert!(parse_udp_frame(&frame[..(frame.len() - 1)]).is_err());
+    }
+
+    #[test]
+    fn pcap_round_trip() {
+        let header_info = HeaderInfo::new(address(1, 50000), address(2, 54321));
+        let frames = vec![
+            build_udp_frame(&header_info, 1, &[b"a"]).unwrap(),
+            vec![0xab; PCAP_SNAPLEN as usize + 10],
+        ];
+        let bytes = capture(&frames);
+        let mut reader = PcapReader::new(bytes.as_slice()).unwrap();
+        let first = reader.next_record().unwrap().unwrap();
+        assert_eq!(first.timestamp, Duration::from_secs(1));
+        assert_eq!(first.data, frames[0]);
+        let second = reader.next_record().unwrap().unwrap();
+        assert_eq!(second.data.len(), PCAP_SNAPLEN as usize);
+        assert_eq!(second.orig_len, PCAP_SNAPLEN as usize + 10);
+        assert_eq!(reader.next_record().unwrap(), None);
+
+        // a truncated record is an error rather than the end of the capture
+        let mut reader = PcapReader::new(&bytes[..(bytes.len() - 1)]).unwrap();
+        reader.next_record().unwrap();
+        assert!(reader.next_record().is_err());
+        assert!(PcapReader::new(&[0u8; PCAP_GLOBAL_HEADER_SIZE][..]).is_err());
+    }
+
+    #[test]
+    fn capture_to_file() {
+        let header_info = HeaderInfo::new(address(1, 50000), address(2, 54321));
+        let path = std::env::temp_dir().join(format!("cornflakes-{}.pcap", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut capture = PacketCapture::new(&path, DEFAULT_CAPTURE_QUEUE_DEPTH).unwrap();
+        capture.record_udp(&header_info, 3, &[b"abc"]);
+        let frame = build_udp_frame(&header_info, 4, &[b"de"]).unwrap();
+        capture.record_frame(frame.chunks(5));
+        let stats = capture.finish().unwrap();
+        assert_eq!(
+            stats,
+            CaptureStats {
+                captured: 2,
+                dropped: 0
+            }
+        );
+
+        let mut reader = PcapReader::new(File::open(&path).unwrap()).unwrap();
+        let first = reader.next_record().unwrap().unwrap();
+        assert_eq!(parse_udp_frame(&first.data).unwrap().payload, b"abc");
+        assert_eq!(reader.next_record().unwrap().unwrap().data, frame);
+        assert_eq!(reader.next_record().unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_matches_responses() {
+        let server = address(2, 54321);
+        let client = address(1, 50000);
+        let other = address(3, 50000);
+        let request = HeaderInfo::new(client, server);
+        let response = HeaderInfo::new(server, client);
+        let bytes = capture(&[
+            build_udp_frame(&request, 1, &[b"get a"]).unwrap(),
+            build_udp_frame(&HeaderInfo::new(client, other), 1, &[b"ignored"]).unwrap(),
+            build_udp_frame(&response, 1, &[b"value a"]).unwrap(),
+            build_udp_frame(&request, 2, &[b"get b"]).unwrap(),
+            build_udp_frame(&response, 2, &[b"value b"]).unwrap(),
+            vec![0u8; 10],
+        ]);
+        // replay is matched on ip and port, not mac addresses
+        let replay_server =
+            AddressInfo::new(server.udp_port, server.ip_addr, MacAddress::default());
+        let mut replay =
+            PcapReplay::new(PcapReader::new(bytes.as_slice()).unwrap(), &replay_server).unwrap();
+
+        let first = replay.next_request().unwrap();
+        assert_eq!((first.src, first.msg_id), (client, 1));
+        assert_eq!(first.payload, b"get a");
+        assert!(replay.check_response(&client, 1, b"value a"));
+        assert_eq!(replay.missing_responses(), 1);
+        let second = replay.next_request().unwrap();
+        assert_eq!(second.msg_id, 2);
+        assert!(!replay.check_response(&client, 2, b"value c"));
+        assert!(!replay.check_response(&other, 2, b"value b"));
+        assert!(replay.next_request().is_none());
+        assert_eq!(replay.missing_responses(), 0);
+        assert_eq!(
+            replay.stats(),
+            ReplayStats {
+                requests: 2,
+                matched: 1,
+                mismatched: 1,
+                unexpected: 1,
+            }
+        );
+    }
+}
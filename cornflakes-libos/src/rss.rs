@@ -0,0 +1,349 @@
+//! Software receive-side scaling (RSS).
+//!
+//! NICs spread incoming flows across receive queues by computing a Toeplitz hash over the
+//! packet's addresses and ports, and using the low bits of the hash to index an indirection
+//! table of queues. `RssConfig` reproduces that computation, so a client can choose UDP source
+//! ports whose packets land on particular server queues (see `select_source_ports`), and load
+//! generators can spread their threads evenly across server queues or deliberately skew them.
+use super::utils::AddressInfo;
+use byteorder::{ByteOrder, NetworkEndian};
+use color_eyre::eyre::{bail, ensure, Result};
+use std::{net::IpAddr, str::FromStr};
+
+/// Length of the Toeplitz keys NICs are programmed with.
+pub const RSS_KEY_LEN: usize = 40;
+
+/// Symmetric key: the hash is unchanged when source and destination are swapped, so both
+/// directions of a flow land on the same queue. This is the key the datapaths program.
+pub const SYMMETRIC_RSS_KEY: [u8; RSS_KEY_LEN] = [
+    0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A,
+    0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A,
+    0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A, 0x6D, 0x5A,
+];
+
+/// Default key from Microsoft's RSS specification, used by many NIC drivers.
+pub const MICROSOFT_RSS_KEY: [u8; RSS_KEY_LEN] = [
+    0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f, 0xb0,
+    0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30, 0xf2, 0x0c,
+    0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+];
+
+/// Toeplitz hash of `input` under `key`. Key bits past the end of `key` are treated as zero.
+pub fn toeplitz_hash(key: &[u8], input: &[u8]) -> u32 {
+    let key_bit = |idx: usize| -> u32 {
+        match key.get(idx / 8) {
+            Some(byte) => ((byte >> (7 - idx % 8)) & 1) as u32,
+            None => 0,
+        }
+    };
+    // 32 bit window of the key, starting at the key bit aligned with the current input bit
+    let mut window = (0..32).fold(0u32, |acc, idx| (acc << 1) | key_bit(idx));
+    let mut hash = 0u32;
+    for (i, byte) in input.iter().enumerate() {
+        for bit in 0..8 {
+            if byte & (0x80 >> bit) != 0 {
+                hash ^= window;
+            }
+            window = (window << 1) | key_bit(i * 8 + bit + 32);
+        }
+    }
+    hash
+}
+
+/// How a NIC hashes UDP flows onto its receive queues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RssConfig {
+    pub key: [u8; RSS_KEY_LEN],
+    /// Number of entries in the indirection table; entry `i` holds queue `i % num_queues`.
+    pub indirection_table_size: usize,
+}
+
+impl RssConfig {
+    pub fn new(key: [u8; RSS_KEY_LEN], indirection_table_size: usize) -> Self {
+        RssConfig {
+            key,
+            indirection_table_size,
+        }
+    }
+
+    /// Hash of a UDP packet from `src_ip:src_port` to `dst_ip:dst_port`.
+    pub fn hash(
+        &self,
+        src_ip: &IpAddr,
+        dst_ip: &IpAddr,
+        src_port: u16,
+        dst_port: u16,
+    ) -> Result<u32> {
+        let mut input: Vec<u8> = Vec::with_capacity(36);
+        match (src_ip, dst_ip) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                input.extend_from_slice(&src.octets());
+                input.extend_from_slice(&dst.octets());
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                input.extend_from_slice(&src.octets());
+                input.extend_from_slice(&dst.octets());
+            }
+            _ => {
+                bail!(
+                    "Cannot hash flow between {:?} and {:?}: mismatched address families",
+                    src_ip,
+                    dst_ip
+                );
+            }
+        }
+        let mut ports = [0u8; 4];
+        NetworkEndian::write_u16(&mut ports[0..2], src_port);
+        NetworkEndian::write_u16(&mut ports[2..4], dst_port);
+        input.extend_from_slice(&ports);
+        Ok(toeplitz_hash(&self.key, &input))
+    }
+
+    /// Queue that a packet with the given hash is delivered to.
+    pub fn queue(&self, hash: u32, num_queues: usize) -> usize {
+        (hash as usize % self.indirection_table_size) % num_queues
+    }
+}
+
+/// Which server queue each client queue should target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueTargets {
+    /// Client queue `i` targets server queue `i % num_server_queues`.
+    Even,
+    /// Client queue `i` targets entry `i % len` of the list; e.g., a single entry sends every
+    /// client queue to one server queue.
+    Explicit(Vec<usize>),
+}
+
+impl Default for QueueTargets {
+    fn default() -> Self {
+        QueueTargets::Even
+    }
+}
+
+impl FromStr for QueueTargets {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<QueueTargets> {
+        match s {
+            "even" | "Even" | "EVEN" => Ok(QueueTargets::Even),
+            x => {
+                let queues = x
+                    .split(',')
+                    .map(|q| q.trim().parse::<usize>())
+                    .collect::<std::result::Result<Vec<usize>, _>>();
+                match queues {
+                    Ok(queues) => Ok(QueueTargets::Explicit(queues)),
+                    Err(_) => {
+                        bail!(
+                            "Unknown queue targets: {:?}; expected even or a list of queues",
+                            x
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The server queues a client should spread its queues across.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerQueueAssignment {
+    pub num_server_queues: usize,
+    pub targets: QueueTargets,
+}
+
+impl ServerQueueAssignment {
+    pub fn new(num_server_queues: usize, targets: QueueTargets) -> Self {
+        ServerQueueAssignment {
+            num_server_queues,
+            targets,
+        }
+    }
+
+    /// Target server queue for each of `num_client_queues` client queues.
+    pub fn server_queues(&self, num_client_queues: usize) -> Result<Vec<usize>> {
+        ensure!(self.num_server_queues > 0, "Need at least one server queue");
+        match &self.targets {
+            QueueTargets::Even => Ok((0..num_client_queues)
+                .map(|i| i % self.num_server_queues)
+                .collect()),
+            QueueTargets::Explicit(queues) => {
+                ensure!(!queues.is_empty(), "Need at least one target server queue");
+                if let Some(q) = queues.iter().find(|q| **q >= self.num_server_queues) {
+                    bail!(
+                        "Target server queue {} out of range for {} server queues",
+                        q,
+                        self.num_server_queues
+                    );
+                }
+                Ok((0..num_client_queues)
+                    .map(|i| queues[i % queues.len()])
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Chooses a distinct UDP source port, at or above `start_port`, for each of `num_client_queues`
+/// client queues, such that packets from `client_ip` to `server` land on the server queue the
+/// assignment gives that client queue. If `client_rss` is given, replies to client queue `i` must
+/// also land on client queue `i`.
+pub fn select_source_ports(
+    server_rss: &RssConfig,
+    server: &AddressInfo,
+    client_ip: &IpAddr,
+    start_port: u16,
+    assignment: &ServerQueueAssignment,
+    num_client_queues: usize,
+    client_rss: Option<&RssConfig>,
+) -> Result<Vec<u16>> {
+    let (server_ip, server_port) = (&server.ip_addr, server.udp_port);
+    let server_queues = assignment.server_queues(num_client_queues)?;
+    let mut ports: Vec<u16> = Vec::with_capacity(num_client_queues);
+    for (client_queue, server_queue) in server_queues.iter().enumerate() {
+        let mut chosen = None;
+        for port in start_port..=u16::MAX {
+            if ports.contains(&port) {
+                continue;
+            }
+            let hash = server_rss.hash(client_ip, server_ip, port, server_port)?;
+            if server_rss.queue(hash, assignment.num_server_queues) != *server_queue {
+                continue;
+            }
+            if let Some(client_config) = client_rss {
+                let reply_hash = client_config.hash(server_ip, client_ip, server_port, port)?;
+                if client_config.queue(reply_hash, num_client_queues) != client_queue {
+                    continue;
+                }
+            }
+            chosen = Some(port);
+            break;
+        }
+        match chosen {
+            Some(port) => {
+                tracing::debug!(client_queue, server_queue, port, "Chose source port");
+                ports.push(port);
+            }
+            None => {
+                bail!(
+                    "No source port at or above {} sends client queue {} to server queue {}",
+                    start_port,
+                    client_queue,
+                    server_queue
+                );
+            }
+        }
+    }
+    Ok(ports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eui48::MacAddress;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn v4(s: &str) -> IpAddr {
+        IpAddr::V4(s.parse::<Ipv4Addr>().unwrap())
+    }
+
+    #[test]
+    fn microsoft_verification_vectors() {
+        let config = RssConfig::new(MICROSOFT_RSS_KEY, 128);
+        // (source, source port, destination, destination port, ip-only hash, 4-tuple hash)
+        let vectors = [
+            (
+                "66.9.149.187",
+                2794,
+                "161.142.100.80",
+                1766,
+                0x323e8fc2,
+                0x51ccc178,
+            ),
+            (
+                "199.92.111.2",
+                14230,
+                "65.69.140.83",
+                4739,
+                0xd718262a,
+                0xc626b0ea,
+            ),
+        ];
+        for (src, src_port, dst, dst_port, ip_hash, tuple_hash) in vectors {
+            let mut ips = src.parse::<Ipv4Addr>().unwrap().octets().to_vec();
+            ips.extend_from_slice(&dst.parse::<Ipv4Addr>().unwrap().octets());
+            assert_eq!(toeplitz_hash(&MICROSOFT_RSS_KEY, &ips), ip_hash);
+            assert_eq!(
+                config.hash(&v4(src), &v4(dst), src_port, dst_port).unwrap(),
+                tuple_hash
+            );
+        }
+
+        let src: Ipv6Addr = "3ffe:2501:200:1fff::7".parse().unwrap();
+        let dst: Ipv6Addr = "3ffe:2501:200:3::1".parse().unwrap();
+        assert_eq!(
+            config.hash(&src.into(), &dst.into(), 2794, 1766).unwrap(),
+            0x40207d3d
+        );
+    }
+
+    #[test]
+    fn symmetric_key_is_symmetric() {
+        let config = RssConfig::new(SYMMETRIC_RSS_KEY, 4);
+        let (a, b) = (v4("10.0.0.1"), v4("10.0.0.2"));
+        for port in 50000..50100 {
+            assert_eq!(
+                config.hash(&a, &b, port, 54321).unwrap(),
+                config.hash(&b, &a, 54321, port).unwrap()
+            );
+        }
+        assert!(config.hash(&a, &"fd00::1".parse().unwrap(), 1, 2).is_err());
+    }
+
+    #[test]
+    fn selected_ports_land_on_targets() {
+        let config = RssConfig::new(SYMMETRIC_RSS_KEY, 4);
+        let client = v4("10.0.0.1");
+        let server = AddressInfo::new(54321, v4("10.0.0.2"), MacAddress::default());
+        let even = ServerQueueAssignment::new(4, QueueTargets::Even);
+        let targets = even.server_queues(6).unwrap();
+        assert_eq!(targets, vec![0, 1, 2, 3, 0, 1]);
+        let ports = select_source_ports(&config, &server, &client, 50000, &even, 6, None).unwrap();
+        for (port, target) in ports.iter().zip(targets.iter()) {
+            let hash = config.hash(&client, &server.ip_addr, *port, 54321).unwrap();
+            assert_eq!(config.queue(hash, 4), *target);
+        }
+        let mut deduped = ports.clone();
+        deduped.sort();
+        deduped.dedup();
+        assert_eq!(deduped.len(), ports.len());
+
+        // every client queue on server queue 2
+        let skewed = ServerQueueAssignment::new(4, "2".parse().unwrap());
+        let ports =
+            select_source_ports(&config, &server, &client, 50000, &skewed, 3, None).unwrap();
+        for port in ports.iter() {
+            let hash = config.hash(&client, &server.ip_addr, *port, 54321).unwrap();
+            assert_eq!(config.queue(hash, 4), 2);
+        }
+
+        // replies to client queue i must also land on client queue i
+        let ports =
+            select_source_ports(&config, &server, &client, 50000, &even, 2, Some(&config)).unwrap();
+        for (i, port) in ports.iter().enumerate() {
+            let hash = config.hash(&server.ip_addr, &client, 54321, *port).unwrap();
+            assert_eq!(config.queue(hash, 4), i);
+            assert_eq!(config.queue(hash, 2), i);
+        }
+        // with a symmetric key, a reply hashes like its request, so this cannot be satisfied
+        assert!(
+            select_source_ports(&config, &server, &client, 50000, &skewed, 2, Some(&config))
+                .is_err()
+        );
+
+        assert!(ServerQueueAssignment::new(2, "0,3".parse().unwrap())
+            .server_queues(2)
+            .is_err());
+    }
+}
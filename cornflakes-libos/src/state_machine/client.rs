@@ -3,6 +3,7 @@ use super::super::{
     high_timeout_at_start,
     loadgen::{
         client_threads::{MeasuredThreadStatsOnly, ThreadStats},
+        flow_control::{FlowControlConfig, FlowController, RetransmitQueue},
         request_schedule::{PacketSchedule, SpinTimer},
     },
     no_retries_timeout,
@@ -96,6 +97,12 @@ pub trait ClientSM {
         datapath: &<Self as ClientSM>::Datapath,
     ) -> Result<&[u8]>;
 
+    /// What to do when a request is given up on without a response, e.g., when the server shed
+    /// it and retries are off. By default, does nothing.
+    fn msg_dropped_cb(&mut self, _id: MsgID) -> Result<()> {
+        Ok(())
+    }
+
     /// Initializes any internal state with any datapath specific configuration,
     /// e.g., registering external memory.
     fn init(&mut self, connection: &mut Self::Datapath) -> Result<()>;
@@ -153,6 +160,12 @@ pub trait ClientSM {
         Ok(0)
     }
 
+    /// Flow control for the open-loop client; `None` sends at the scheduled rate regardless of
+    /// the server's backlog.
+    fn flow_control_config(&self) -> Option<FlowControlConfig> {
+        None
+    }
+
     fn dump(&mut self, path: Option<String>, total_time: Duration, app_name: &str) -> Result<()> {
         self.get_mut_rtts().sort()?;
         tracing::info!(
@@ -176,6 +189,66 @@ pub trait ClientSM {
         Ok(())
     }
 
+    /// Handles the server's overloaded reply to a request, which took `rtt`. Popping the reply
+    /// took the request out of the datapath's outgoing window, so it is treated as lost: queued
+    /// for retransmission after at least one RTT, or dropped if retries are off.
+    fn handle_overloaded_reply(
+        &mut self,
+        pkt: &ReceivedPkt<<Self as ClientSM>::Datapath>,
+        rtt: Duration,
+        no_retries: bool,
+        flow_controller: Option<&mut FlowController>,
+        retransmits: &mut RetransmitQueue,
+    ) -> Result<()> {
+        let (msg_id, conn_id) = (pkt.msg_id(), pkt.conn_id());
+        tracing::debug!(msg_id, "Received overloaded reply");
+        let now = Instant::now();
+        let mut backoff = rtt;
+        if let Some(controller) = flow_controller {
+            controller.on_overloaded(msg_id, now);
+            backoff = backoff.max(controller.retransmit_backoff());
+        }
+        if no_retries {
+            return self.msg_dropped_cb(msg_id);
+        }
+        retransmits.defer(msg_id, conn_id, now + backoff);
+        Ok(())
+    }
+
+    /// Retransmits the shed requests whose backoff has passed, while the window has room.
+    fn send_ready_retransmits(
+        &mut self,
+        datapath: &mut Self::Datapath,
+        retransmits: &mut RetransmitQueue,
+        mut flow_controller: Option<&mut FlowController>,
+    ) -> Result<()> {
+        if retransmits.is_empty() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        loop {
+            if let Some(controller) = flow_controller.as_ref() {
+                if !controller.can_send(now) {
+                    break;
+                }
+            }
+            let (msg_id, conn_id) = match retransmits.pop_ready(now) {
+                Some(next) => next,
+                None => break,
+            };
+            self.increment_num_retried();
+            datapath.push_buffers_with_copy(&[(
+                msg_id,
+                conn_id,
+                self.msg_timeout_cb(msg_id, datapath)?,
+            )])?;
+            if let Some(controller) = flow_controller.as_mut() {
+                controller.on_send(msg_id, now);
+            }
+        }
+        Ok(())
+    }
+
     /// Run open loop client
     fn run_closed_loop(
         &mut self,
@@ -190,6 +263,7 @@ pub trait ClientSM {
         let conn_id = datapath
             .connect(self.server_addr())
             .wrap_err("Could not get connection ID")?;
+        let mut retransmits = RetransmitQueue::default();
 
         while let Some((id, msg)) = self.get_next_msg(&datapath)? {
             if recved >= num_pkts {
@@ -198,10 +272,16 @@ pub trait ClientSM {
             datapath.push_buffers_with_copy(&vec![(id, conn_id, msg)])?;
             self.increment_uniq_sent();
             let recved_pkts = loop {
-                let pkts = datapath.pop_with_durations()?;
-                if pkts.len() > 0 {
+                let mut pkts = datapath.pop_with_durations()?;
+                // keep waiting for the response to a request the server shed
+                for (pkt, rtt) in pkts.iter().filter(|(pkt, _)| pkt.is_overloaded_reply()) {
+                    self.handle_overloaded_reply(pkt, *rtt, false, None, &mut retransmits)?;
+                }
+                pkts.retain(|(pkt, _)| !pkt.is_overloaded_reply());
+                if !pkts.is_empty() {
                     break pkts;
                 }
+                self.send_ready_retransmits(datapath, &mut retransmits, None)?;
                 for (id, conn) in datapath
                     .timed_out(time_out(self.uniq_received_so_far()))?
                     .iter()
@@ -236,6 +316,79 @@ pub trait ClientSM {
         Ok(())
     }
 
+    /// Processes received responses and timed out requests while the open loop client waits to
+    /// send.
+    fn poll_open_loop(
+        &mut self,
+        datapath: &mut Self::Datapath,
+        warmup_done: bool,
+        time_out: &impl Fn(usize) -> Duration,
+        no_retries: bool,
+        msg_ids_received: &mut Option<&mut Vec<MsgID>>,
+        mut flow_controller: Option<&mut FlowController>,
+        retransmits: &mut RetransmitQueue,
+    ) -> Result<()> {
+        let recved_pkts = datapath.pop_with_durations()?;
+        for (pkt, rtt) in recved_pkts.into_iter() {
+            if pkt.is_noop() {
+                // received old noop response
+                continue;
+            }
+            let msg_id = pkt.msg_id();
+            if pkt.is_overloaded_reply() {
+                // the server shed the request
+                self.handle_overloaded_reply(
+                    &pkt,
+                    rtt,
+                    no_retries,
+                    flow_controller.as_deref_mut(),
+                    retransmits,
+                )?;
+                continue;
+            }
+            if let Some(controller) = flow_controller.as_mut() {
+                controller.on_response(msg_id, rtt, Instant::now());
+            }
+            let msg_size = pkt.data_len();
+            if self.process_received_msg(pkt, &datapath).wrap_err(format!(
+                "Error in processing received response for pkt {}.",
+                msg_id
+            ))? {
+                if warmup_done {
+                    self.record_rtt(rtt);
+                    if self.recording_size_rtts() {
+                        self.record_sized_rtt(rtt, msg_size);
+                    }
+                }
+                if let Some(msg_ids) = msg_ids_received {
+                    msg_ids.push(msg_id);
+                }
+                self.increment_uniq_received();
+            }
+        }
+
+        if !no_retries {
+            for (id, conn) in datapath
+                .timed_out(time_out(self.uniq_received_so_far()))?
+                .iter()
+            {
+                self.increment_num_retried();
+                self.increment_num_timed_out();
+                datapath.push_buffers_with_copy(&vec![(
+                    *id,
+                    *conn,
+                    self.msg_timeout_cb(*id, &datapath)?,
+                )])?;
+                if let Some(controller) = flow_controller.as_mut() {
+                    let now = Instant::now();
+                    controller.on_timeout(*id, now);
+                    controller.on_send(*id, now);
+                }
+            }
+        }
+        self.send_ready_retransmits(datapath, retransmits, flow_controller)
+    }
+
     /// Run open loop client
     fn run_open_loop(
         &mut self,
@@ -287,60 +440,65 @@ pub trait ClientSM {
         let start = Instant::now();
         let mut spin_timer = SpinTimer::new(schedule, total_time);
 
-        while let Some((id, msg)) = self.get_next_msg(&datapath)? {
+        let mut flow_controller = self.flow_control_config().map(FlowController::new);
+        let mut retransmits = RetransmitQueue::default();
+
+        loop {
             if spin_timer.done() {
                 tracing::debug!("Total time done");
                 break;
             }
 
+            // hold the send until the window has room
+            if let Some(controller) = flow_controller.as_mut() {
+                let warmup_done = spin_timer.warmup_done();
+                if controller.wait_for_window(&mut |controller| {
+                    self.poll_open_loop(
+                        datapath,
+                        warmup_done,
+                        &time_out,
+                        no_retries,
+                        &mut msg_ids_received,
+                        Some(controller),
+                        &mut retransmits,
+                    )
+                })? {
+                    spin_timer.reset_deficit();
+                }
+            }
+
             // Send the next message
+            let (id, msg) = match self.get_next_msg(&datapath)? {
+                Some(next) => next,
+                None => {
+                    break;
+                }
+            };
             datapath.push_buffers_with_copy(&vec![(id, conn_id, msg)])?;
             self.increment_uniq_sent();
+            if let Some(controller) = flow_controller.as_mut() {
+                controller.on_send(id, Instant::now());
+            }
 
             spin_timer.wait(&mut |warmup_done: bool| {
-                let recved_pkts = datapath.pop_with_durations()?;
-                for (pkt, rtt) in recved_pkts.into_iter() {
-                    if pkt.is_noop() {
-                        // received old noop response
-                        continue;
-                    }
-                    let msg_id = pkt.msg_id();
-                    let msg_size = pkt.data_len();
-                    if self.process_received_msg(pkt, &datapath).wrap_err(format!(
-                        "Error in processing received response for pkt {}.",
-                        msg_id
-                    ))? {
-                        if warmup_done {
-                            self.record_rtt(rtt);
-                            if self.recording_size_rtts() {
-                                self.record_sized_rtt(rtt, msg_size);
-                            }
-                        }
-                        if let Some(ref mut msg_ids) = &mut msg_ids_received {
-                            msg_ids.push(msg_id);
-                        }
-                        self.increment_uniq_received();
-                    }
-                }
-
-                if !no_retries {
-                    for (id, conn) in datapath
-                        .timed_out(time_out(self.uniq_received_so_far()))?
-                        .iter()
-                    {
-                        self.increment_num_retried();
-                        self.increment_num_timed_out();
-                        datapath.push_buffers_with_copy(&vec![(
-                            *id,
-                            *conn,
-                            self.msg_timeout_cb(*id, &datapath)?,
-                        )])?;
-                    }
-                }
-                Ok(())
+                self.poll_open_loop(
+                    datapath,
+                    warmup_done,
+                    &time_out,
+                    no_retries,
+                    &mut msg_ids_received,
+                    flow_controller.as_mut(),
+                    &mut retransmits,
+                )
             })?;
         }
 
+        if let Some(controller) = flow_controller.as_ref() {
+            controller
+                .stats()
+                .dump(&format!("Flow control stats for conn {}", conn_id));
+        }
+
         tracing::debug!("Finished sending");
         Ok(start.elapsed())
     }
@@ -423,7 +581,7 @@ where
         "About to calculate stats"
     );
     let sized_rtts = client.get_sized_rtts().clone();
-    let stats = MeasuredThreadStatsOnly::new(
+    let mut stats = MeasuredThreadStatsOnly::new(
         thread_id,
         client.uniq_sent_so_far() - client.get_noops_sent(),
         client.num_received_cutoff(0),
@@ -433,6 +591,7 @@ where
         sized_rtts,
         0,
     )?;
+    stats.transmit_stats = connection.transmit_stats();
     Ok(stats)
 }
 
@@ -500,7 +659,7 @@ where
     }
 
     tracing::info!(thread = thread_id, "About to calculate stats");
-    let stats = ThreadStats::new(
+    let mut stats = ThreadStats::new(
         thread_id as u16,
         client.uniq_sent_so_far() - client.get_noops_sent(),
         client.num_received_cutoff(0),
@@ -511,6 +670,7 @@ where
         client.get_mut_rtts(),
         0,
     )?;
+    stats.transmit_stats = connection.transmit_stats();
 
     Ok(stats)
 }
@@ -3,11 +3,65 @@ use demikernel::perftools;
 #[cfg(feature = "profiler")]
 const PROFILER_DEPTH: usize = 10;
 use super::super::{
+    adaptive_threshold::AdaptiveThresholdController,
+    admission::AdmissionController,
     datapath::{Datapath, PushBufType, ReceivedPkt},
     ArenaOrderedSga,
 };
 use color_eyre::eyre::Result;
-use std::{fs::File, io::Write, time::Instant};
+use std::{
+    fs::File,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// How often the server state machine logs its stats while processing requests.
+const STATS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Gives back mempools that `allocate` grew for a burst once none of their buffers are in use.
+fn remove_idle_mempools<D: Datapath>(datapath: &mut D) -> Result<()> {
+    if !datapath.get_mempool_config().auto_grow {
+        return Ok(());
+    }
+    let removed = datapath.remove_idle_mempools()?;
+    if !removed.is_empty() {
+        tracing::info!(removed = ?removed, "Removed idle mempools");
+    }
+    Ok(())
+}
+
+/// Receives the requests to process this poll. With an admission controller, queues the requests
+/// it admits, draining the receive queue into its backlog while there is room, and takes the next
+/// batch out of the backlog.
+fn poll_requests<S: ServerSM + ?Sized>(
+    server: &mut S,
+    datapath: &mut S::Datapath,
+    admission_controller: &mut Option<AdmissionController<S::Datapath>>,
+) -> Result<Vec<ReceivedPkt<S::Datapath>>> {
+    let pkts = {
+        #[cfg(feature = "profiler")]
+        demikernel::timer!("Datapath pop");
+        datapath.pop()?
+    };
+    let pkts = server.steer_packets(pkts, datapath)?;
+    let controller = match admission_controller {
+        Some(controller) => controller,
+        None => {
+            return Ok(pkts);
+        }
+    };
+    controller.enqueue(pkts, datapath)?;
+    while controller.has_room() {
+        let pkts = datapath.pop()?;
+        if pkts.is_empty() {
+            break;
+        }
+        let pkts = server.steer_packets(pkts, datapath)?;
+        controller.enqueue(pkts, datapath)?;
+    }
+    Ok(controller.next_batch())
+}
+
 pub trait ServerSM {
     type Datapath: Datapath;
 
@@ -108,12 +162,43 @@ pub trait ServerSM {
         Ok(())
     }
 
+    /// Called on every poll with the packets just received, before they are processed. Servers
+    /// that partition state across threads hand off packets another thread owns and add packets
+    /// handed to this one. By default, processes every packet locally.
+    fn steer_packets(
+        &mut self,
+        pkts: Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>,
+        _datapath: &mut Self::Datapath,
+    ) -> Result<Vec<ReceivedPkt<<Self as ServerSM>::Datapath>>> {
+        Ok(pkts)
+    }
+
+    /// Logs server-side stats; called periodically from the state machine loop.
+    /// By default, logs the datapath's transmit (copy versus zero-copy) stats.
+    fn log_stats(&self, datapath: &Self::Datapath) {
+        datapath.transmit_stats().dump("Server transmit stats");
+        datapath
+            .allocator_memory_stats()
+            .dump("Server allocator memory stats");
+    }
+
     fn run_state_machine_baseline(&mut self, datapath: &mut Self::Datapath) -> Result<()> {
+        self.run_state_machine_baseline_with_admission_control(datapath, None)
+    }
+
+    /// Runs the baseline state machine; if a controller is passed in, only the requests it
+    /// admits are processed.
+    fn run_state_machine_baseline_with_admission_control(
+        &mut self,
+        datapath: &mut Self::Datapath,
+        mut admission_controller: Option<AdmissionController<Self::Datapath>>,
+    ) -> Result<()> {
         // run profiler from here
         #[cfg(feature = "profiler")]
         perftools::profiler::reset();
         let mut _last_log: Instant = Instant::now();
         let mut _requests_processed = 0;
+        let mut last_stats_log = Instant::now();
 
         loop {
             #[cfg(feature = "profiler")]
@@ -136,11 +221,7 @@ pub trait ServerSM {
                 }
             }
 
-            let pkts = {
-                #[cfg(feature = "profiler")]
-                demikernel::timer!("Datapath pop");
-                datapath.pop()?
-            };
+            let pkts = poll_requests(self, datapath, &mut admission_controller)?;
             if pkts.len() > 0 {
                 match self.push_buf_type() {
                     PushBufType::SingleBuf => {
@@ -155,16 +236,54 @@ pub trait ServerSM {
                         unreachable!();
                     }
                 }
+                if last_stats_log.elapsed() > STATS_LOG_INTERVAL {
+                    self.log_stats(datapath);
+                    remove_idle_mempools(datapath)?;
+                    if let Some(ref controller) = admission_controller {
+                        tracing::info!(
+                            backlog = controller.backlog_len(),
+                            "Server admission control backlog"
+                        );
+                        controller.stats().dump("Server admission control stats");
+                    }
+                    last_stats_log = Instant::now();
+                }
             }
         }
     }
 
     fn run_state_machine(&mut self, datapath: &mut Self::Datapath) -> Result<()> {
+        self.run_state_machine_with_threshold_controller(datapath, None)
+    }
+
+    /// Runs the state machine; if a controller is passed in, it tunes the datapath's copying
+    /// threshold online based on the measured cost of processing each batch.
+    fn run_state_machine_with_threshold_controller(
+        &mut self,
+        datapath: &mut Self::Datapath,
+        threshold_controller: Option<AdaptiveThresholdController>,
+    ) -> Result<()> {
+        self.run_state_machine_with_controllers(datapath, threshold_controller, None)
+    }
+
+    /// Runs the state machine with an optional copying threshold controller (see
+    /// `run_state_machine_with_threshold_controller`) and an optional admission controller, which
+    /// picks the received requests that are processed.
+    fn run_state_machine_with_controllers(
+        &mut self,
+        datapath: &mut Self::Datapath,
+        mut threshold_controller: Option<AdaptiveThresholdController>,
+        mut admission_controller: Option<AdmissionController<Self::Datapath>>,
+    ) -> Result<()> {
+        if let Some(ref mut controller) = threshold_controller {
+            controller.init(datapath);
+        }
         // run profiler from here
         #[cfg(feature = "profiler")]
         perftools::profiler::reset();
         let mut _last_log: Instant = Instant::now();
         let mut _requests_processed = 0;
+        let mut last_stats_log = Instant::now();
 
         let mut arena = bumpalo::Bump::with_capacity(
             ArenaOrderedSga::arena_size(
@@ -195,12 +314,12 @@ pub trait ServerSM {
                 }
             }
 
-            let pkts = {
-                #[cfg(feature = "profiler")]
-                demikernel::timer!("Datapath pop");
-                datapath.pop()?
-            };
+            let pkts = poll_requests(self, datapath, &mut admission_controller)?;
             if pkts.len() > 0 {
+                let num_pkts = pkts.len();
+                if let Some(ref mut controller) = threshold_controller {
+                    controller.start_batch(datapath);
+                }
                 match self.push_buf_type() {
                     PushBufType::SingleBuf => {
                         self.process_requests_single_buf(pkts, datapath)?;
@@ -250,6 +369,26 @@ pub trait ServerSM {
                         self.process_requests_echo(pkts, datapath)?;
                     }
                 }
+                if let Some(ref mut controller) = threshold_controller {
+                    controller.end_batch(datapath, num_pkts);
+                }
+                if last_stats_log.elapsed() > STATS_LOG_INTERVAL {
+                    self.log_stats(datapath);
+                    remove_idle_mempools(datapath)?;
+                    if let Some(ref controller) = threshold_controller {
+                        controller
+                            .stats()
+                            .dump("Server adaptive copying threshold stats");
+                    }
+                    if let Some(ref controller) = admission_controller {
+                        tracing::info!(
+                            backlog = controller.backlog_len(),
+                            "Server admission control backlog"
+                        );
+                        controller.stats().dump("Server admission control stats");
+                    }
+                    last_stats_log = Instant::now();
+                }
             }
         }
     }
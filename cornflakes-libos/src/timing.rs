@@ -8,7 +8,7 @@ use hdrhistogram::Histogram;
 use std::{
     fs::File,
     io::Write,
-    net::Ipv4Addr,
+    net::IpAddr,
     sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
@@ -300,7 +300,7 @@ pub struct HistogramWrapper {
     /// Actual histogram
     hist: Histogram<u64>,
     /// Map of (IpAddr, PktID) => (start times). TODO: figure out how retries fit into this
-    pkt_map: HashMap<(Ipv4Addr, MsgID), Vec<Instant>>,
+    pkt_map: HashMap<(IpAddr, MsgID), Vec<Instant>>,
     /// Name of the measurement
     name: String,
 }
@@ -332,7 +332,7 @@ impl HistogramWrapper {
         self.add_latency(val)
     }
 
-    pub fn start_entry(&mut self, addr: Ipv4Addr, id: MsgID) -> Result<()> {
+    pub fn start_entry(&mut self, addr: IpAddr, id: MsgID) -> Result<()> {
         if self.pkt_map.contains_key(&(addr, id)) {
             tracing::warn!(
                 hist = ?self.name,
@@ -348,7 +348,7 @@ impl HistogramWrapper {
         Ok(())
     }
 
-    pub fn end_entry(&mut self, addr: Ipv4Addr, id: MsgID) -> Result<()> {
+    pub fn end_entry(&mut self, addr: IpAddr, id: MsgID) -> Result<()> {
         let mut delete = false;
         let head_start = match self.pkt_map.get_mut(&(addr, id)) {
             Some(s) => {
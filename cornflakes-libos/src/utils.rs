@@ -3,30 +3,73 @@ use byteorder::{ByteOrder, LittleEndian, NetworkEndian};
 use color_eyre::eyre::{bail, Result};
 use eui48::MacAddress;
 use std::convert::{TryFrom, TryInto};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use tracing::debug;
 
 // Header setting taken from Demikernel's catnip OS:
 // https://github.com/demikernel/demikernel/blob/master/src/rust/catnip/src/protocols/
 pub const ETHERNET2_HEADER2_SIZE: usize = 14;
 pub const IPV4_HEADER2_SIZE: usize = 20;
+pub const IPV6_HEADER2_SIZE: usize = 40;
 pub const UDP_HEADER2_SIZE: usize = 8;
 pub const DEFAULT_IPV4_TTL: u8 = 64;
 pub const IPV4_IHL_NO_OPTIONS: u8 = 5;
 pub const IPV4_VERSION: u8 = 4;
+pub const IPV6_VERSION: u8 = 6;
 pub const IPDEFTTL: u8 = 64;
 pub const IPPROTO_UDP: u8 = 17;
+/// IPv6 next header value of the fragment extension header.
+pub const IPPROTO_IPV6_FRAGMENT: u8 = 44;
+/// Tag protocol identifier of 802.1Q VLAN tags, in place of the ethertype.
+pub const ETHERTYPE_VLAN: u16 = 0x8100;
+pub const VLAN_TAG_SIZE: usize = 4;
+pub const VLAN_ID_MASK: u16 = 0x0fff;
+pub const ETHERNET_VLAN_HEADER_SIZE: usize = ETHERNET2_HEADER2_SIZE + VLAN_TAG_SIZE;
+const IPV4_MORE_FRAGMENTS: u16 = 0x2000;
+const IPV4_FRAGMENT_OFFSET_MASK: u16 = 0x1fff;
 pub const HEADER_PADDING_SIZE: usize = 0;
 pub const HEADER_ID_SIZE: usize = 4;
 pub const TOTAL_UDP_HEADER_SIZE: usize =
     ETHERNET2_HEADER2_SIZE + IPV4_HEADER2_SIZE + UDP_HEADER2_SIZE;
 pub const TOTAL_HEADER_SIZE: usize = TOTAL_UDP_HEADER_SIZE + HEADER_ID_SIZE + HEADER_PADDING_SIZE;
+pub const TOTAL_UDP_HEADER_SIZE_V6: usize =
+    ETHERNET2_HEADER2_SIZE + IPV6_HEADER2_SIZE + UDP_HEADER2_SIZE;
+pub const TOTAL_HEADER_SIZE_V6: usize =
+    TOTAL_UDP_HEADER_SIZE_V6 + HEADER_ID_SIZE + HEADER_PADDING_SIZE;
+
+/// Size of the IP header used to reach `ip`.
+#[inline]
+pub fn ip_header_size(ip: &IpAddr) -> usize {
+    match ip {
+        IpAddr::V4(_) => IPV4_HEADER2_SIZE,
+        IpAddr::V6(_) => IPV6_HEADER2_SIZE,
+    }
+}
+
+/// Ethernet, IP and UDP header size for packets to or from `ip`.
+#[inline]
+pub fn udp_header_size(ip: &IpAddr) -> usize {
+    match ip {
+        IpAddr::V4(_) => TOTAL_UDP_HEADER_SIZE,
+        IpAddr::V6(_) => TOTAL_UDP_HEADER_SIZE_V6,
+    }
+}
+
+/// Full header size (including the message id) for packets to or from `ip`.
+#[inline]
+pub fn header_size(ip: &IpAddr) -> usize {
+    match ip {
+        IpAddr::V4(_) => TOTAL_HEADER_SIZE,
+        IpAddr::V6(_) => TOTAL_HEADER_SIZE_V6,
+    }
+}
 
 #[repr(u16)]
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum EtherType2 {
     Arp = 0x806,
     Ipv4 = 0x800,
+    Ipv6 = 0x86DD,
 }
 
 impl TryFrom<u16> for EtherType2 {
@@ -37,6 +80,8 @@ impl TryFrom<u16> for EtherType2 {
             Ok(EtherType2::Arp)
         } else if n == EtherType2::Ipv4 as u16 {
             Ok(EtherType2::Ipv4)
+        } else if n == EtherType2::Ipv6 as u16 {
+            Ok(EtherType2::Ipv6)
         } else {
             bail!("Unsupported ether type: {}", n);
         }
@@ -46,7 +91,7 @@ impl TryFrom<u16> for EtherType2 {
 #[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
 pub struct AddressInfo {
     pub udp_port: u16,
-    pub ipv4_addr: Ipv4Addr,
+    pub ip_addr: IpAddr,
     pub ether_addr: MacAddress,
 }
 
@@ -54,21 +99,48 @@ impl Default for AddressInfo {
     fn default() -> AddressInfo {
         AddressInfo {
             udp_port: 12345,
-            ipv4_addr: Ipv4Addr::LOCALHOST,
+            ip_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
             ether_addr: MacAddress::default(),
         }
     }
 }
 
 impl AddressInfo {
-    pub fn new(port: u16, ipv4: Ipv4Addr, mac: MacAddress) -> AddressInfo {
+    pub fn new(port: u16, ip: impl Into<IpAddr>, mac: MacAddress) -> AddressInfo {
         AddressInfo {
             udp_port: port,
-            ipv4_addr: ipv4,
+            ip_addr: ip.into(),
             ether_addr: mac,
         }
     }
 
+    /// Address of a peer looked up in the config file's known hosts. A peer missing from them
+    /// gets a nil MAC address, which Ethernet datapaths resolve with ARP on `connect`.
+    pub fn from_known_host(
+        port: u16,
+        ip: impl Into<IpAddr>,
+        mac: Option<MacAddress>,
+    ) -> AddressInfo {
+        AddressInfo::new(port, ip, mac.unwrap_or_else(MacAddress::nil))
+    }
+
+    /// Whether the MAC address has yet to be resolved; see `from_known_host`.
+    pub fn is_unresolved(&self) -> bool {
+        self.ether_addr.is_nil()
+    }
+
+    /// The address as IPv4, for datapaths whose header layout is IPv4-only.
+    pub fn ipv4_addr(&self) -> Result<Ipv4Addr> {
+        match self.ip_addr {
+            IpAddr::V4(ip) => Ok(ip),
+            IpAddr::V6(ip) => bail!("{:?} is an IPv6 address; expected IPv4", ip),
+        }
+    }
+
+    pub fn header_size(&self) -> usize {
+        header_size(&self.ip_addr)
+    }
+
     pub fn get_outgoing(&self, dst_addr: &AddressInfo) -> HeaderInfo {
         HeaderInfo::new(self.clone(), dst_addr.clone())
     }
@@ -87,6 +159,10 @@ impl HeaderInfo {
             dst_info: dst_info,
         }
     }
+
+    pub fn header_size(&self) -> usize {
+        header_size(&self.src_info.ip_addr)
+    }
 }
 
 #[inline]
@@ -95,11 +171,117 @@ pub fn write_udp_hdr(header_info: &HeaderInfo, buf: &mut [u8], data_len: usize)
     NetworkEndian::write_u16(&mut fixed_buf[0..2], header_info.src_info.udp_port);
     NetworkEndian::write_u16(&mut fixed_buf[2..4], header_info.dst_info.udp_port);
     NetworkEndian::write_u16(&mut fixed_buf[4..6], (UDP_HEADER2_SIZE + data_len) as u16);
-    // no checksum
+    // no checksum; filled in by write_udp_checksum or the NIC, if at all
     NetworkEndian::write_u16(&mut fixed_buf[6..8], 0);
     Ok(())
 }
 
+/// Running ones' complement sum (RFC 1071) over any number of byte slices, which need not have
+/// even lengths.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InternetChecksum {
+    sum: u64,
+    odd_byte: Option<u8>,
+}
+
+impl InternetChecksum {
+    pub fn new() -> Self {
+        InternetChecksum::default()
+    }
+
+    #[inline]
+    pub fn add(&mut self, mut buf: &[u8]) {
+        if buf.is_empty() {
+            return;
+        }
+        if let Some(byte) = self.odd_byte.take() {
+            self.sum += u16::from_be_bytes([byte, buf[0]]) as u64;
+            buf = &buf[1..];
+        }
+        let mut words = buf.chunks_exact(2);
+        for word in &mut words {
+            self.sum += NetworkEndian::read_u16(word) as u64;
+        }
+        if let [byte] = words.remainder() {
+            self.odd_byte = Some(*byte);
+        }
+    }
+
+    /// Folds the sum to 16 bits and returns its ones' complement.
+    #[inline]
+    pub fn finish(&self) -> u16 {
+        let mut sum = self.sum;
+        if let Some(byte) = self.odd_byte {
+            sum += (byte as u64) << 8;
+        }
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+}
+
+/// UDP checksum over the pseudo-header, the UDP header (skipping its checksum field) and the
+/// payload, which may be split across any number of scatter-gather entries. The UDP header must
+/// already hold the datagram length.
+#[inline]
+pub fn udp_checksum<'a>(
+    src_ip: &IpAddr,
+    dst_ip: &IpAddr,
+    udp_hdr: &[u8],
+    payload: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<u16> {
+    let udp_len = NetworkEndian::read_u16(&udp_hdr[4..6]);
+    let mut checksum = InternetChecksum::new();
+    match (src_ip, dst_ip) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            checksum.add(&src.octets());
+            checksum.add(&dst.octets());
+            checksum.add(&[0, IPPROTO_UDP]);
+            checksum.add(&udp_len.to_be_bytes());
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            checksum.add(&src.octets());
+            checksum.add(&dst.octets());
+            checksum.add(&(udp_len as u32).to_be_bytes());
+            checksum.add(&[0, 0, 0, IPPROTO_UDP]);
+        }
+        (src, dst) => bail!("Mismatched ip versions: {:?} and {:?}", src, dst),
+    }
+    checksum.add(&udp_hdr[0..6]);
+    for seg in payload {
+        checksum.add(seg);
+    }
+    // zero means "no checksum", so a computed zero is sent as all ones
+    match checksum.finish() {
+        0 => Ok(0xffff),
+        x => Ok(x),
+    }
+}
+
+#[inline]
+pub fn write_udp_checksum(udp_hdr: &mut [u8], checksum: u16) {
+    NetworkEndian::write_u16(&mut udp_hdr[6..8], checksum);
+}
+
+/// Checks the UDP checksum of a received datagram. IPv4 datagrams sent without a checksum (a zero
+/// checksum field) pass; IPv6 requires one.
+#[inline]
+pub fn verify_udp_checksum<'a>(
+    src_ip: &IpAddr,
+    dst_ip: &IpAddr,
+    udp_hdr: &[u8],
+    payload: impl IntoIterator<Item = &'a [u8]>,
+) -> bool {
+    match NetworkEndian::read_u16(&udp_hdr[6..8]) {
+        0 => src_ip.is_ipv4(),
+        received => match udp_checksum(src_ip, dst_ip, udp_hdr, payload) {
+            Ok(computed) => computed == received,
+            Err(_) => false,
+        },
+    }
+}
+
 #[inline]
 fn ipv4_checksum(buf: &[u8]) -> Result<u16> {
     let buf: &[u8; IPV4_HEADER2_SIZE] = buf.try_into()?;
@@ -127,20 +309,70 @@ pub fn write_ipv4_hdr(header_info: &HeaderInfo, buf: &mut [u8], data_len: usize)
     buf[8] = IPDEFTTL; // time to live
     buf[9] = IPPROTO_UDP; // next_proto_id
 
-    buf[12..16].copy_from_slice(&header_info.src_info.ipv4_addr.octets());
-    buf[16..20].copy_from_slice(&header_info.dst_info.ipv4_addr.octets());
+    let (src_ip, dst_ip) = match (header_info.src_info.ip_addr, header_info.dst_info.ip_addr) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => (src, dst),
+        (src, dst) => bail!("Cannot write ipv4 header from {:?} to {:?}", src, dst),
+    };
+    buf[12..16].copy_from_slice(&src_ip.octets());
+    buf[16..20].copy_from_slice(&dst_ip.octets());
 
     let checksum = ipv4_checksum(buf)?;
     NetworkEndian::write_u16(&mut buf[10..12], checksum);
     Ok(())
 }
 
+#[inline]
+pub fn write_ipv6_hdr(header_info: &HeaderInfo, buf: &mut [u8], data_len: usize) -> Result<()> {
+    let buf: &mut [u8; IPV6_HEADER2_SIZE] = buf.try_into()?;
+    let (src_ip, dst_ip) = match (header_info.src_info.ip_addr, header_info.dst_info.ip_addr) {
+        (IpAddr::V6(src), IpAddr::V6(dst)) => (src, dst),
+        (src, dst) => bail!("Cannot write ipv6 header from {:?} to {:?}", src, dst),
+    };
+    // version, no traffic class or flow label
+    NetworkEndian::write_u32(&mut buf[0..4], (IPV6_VERSION as u32) << 28);
+    NetworkEndian::write_u16(&mut buf[4..6], data_len as u16); // payload size, excluding this header
+    buf[6] = IPPROTO_UDP; // next header
+    buf[7] = IPDEFTTL; // hop limit
+    buf[8..24].copy_from_slice(&src_ip.octets());
+    buf[24..40].copy_from_slice(&dst_ip.octets());
+    Ok(())
+}
+
+/// Writes an ipv4 or ipv6 header depending on the source address; `buf` must be
+/// `ip_header_size` bytes long.
+#[inline]
+pub fn write_ip_hdr(header_info: &HeaderInfo, buf: &mut [u8], data_len: usize) -> Result<()> {
+    match header_info.src_info.ip_addr {
+        IpAddr::V4(_) => write_ipv4_hdr(header_info, buf, data_len),
+        IpAddr::V6(_) => write_ipv6_hdr(header_info, buf, data_len),
+    }
+}
+
 #[inline]
 pub fn write_eth_hdr(header_info: &HeaderInfo, buf: &mut [u8]) -> Result<()> {
     //let buf: &mut [u8; ETHERNET2_HEADER2_SIZE] = buf.try_into()?;
     buf[0..6].copy_from_slice(header_info.dst_info.ether_addr.as_bytes());
     buf[6..12].copy_from_slice(header_info.src_info.ether_addr.as_bytes());
-    NetworkEndian::write_u16(&mut buf[12..14], EtherType2::Ipv4 as u16);
+    let ether_type = match header_info.src_info.ip_addr {
+        IpAddr::V4(_) => EtherType2::Ipv4,
+        IpAddr::V6(_) => EtherType2::Ipv6,
+    };
+    NetworkEndian::write_u16(&mut buf[12..14], ether_type as u16);
+    Ok(())
+}
+
+/// Writes an ethernet header with an 802.1Q tag for `vlan_id` (priority 0); `buf` must be
+/// `ETHERNET_VLAN_HEADER_SIZE` bytes long.
+#[inline]
+pub fn write_vlan_eth_hdr(header_info: &HeaderInfo, vlan_id: u16, buf: &mut [u8]) -> Result<()> {
+    if vlan_id & !VLAN_ID_MASK != 0 {
+        bail!("VLAN id {} does not fit in 12 bits", vlan_id);
+    }
+    write_eth_hdr(header_info, &mut buf[0..ETHERNET2_HEADER2_SIZE])?;
+    // the ethertype moves after the tag
+    buf.copy_within(12..14, 16);
+    NetworkEndian::write_u16(&mut buf[12..14], ETHERTYPE_VLAN);
+    NetworkEndian::write_u16(&mut buf[14..16], vlan_id);
     Ok(())
 }
 
@@ -151,6 +383,91 @@ pub fn write_pkt_id(id: MsgID, buf: &mut [u8]) -> Result<()> {
     Ok(())
 }
 
+/// Writes the ethernet, ip and udp headers of a connection into `buf` as a template for
+/// `fill_in_hdrs`, with zero lengths. `buf` must hold at least `udp_header_size` bytes for the
+/// source address; returns how many were written.
+pub fn write_hdr_template(header_info: &HeaderInfo, buf: &mut [u8]) -> Result<usize> {
+    let udp_start = ETHERNET2_HEADER2_SIZE + ip_header_size(&header_info.src_info.ip_addr);
+    let udp_end = udp_start + UDP_HEADER2_SIZE;
+    if buf.len() < udp_end {
+        bail!(
+            "Buffer of len {} too short for headers of len {}",
+            buf.len(),
+            udp_end
+        );
+    }
+    write_eth_hdr(header_info, &mut buf[..ETHERNET2_HEADER2_SIZE])?;
+    write_ip_hdr(
+        header_info,
+        &mut buf[ETHERNET2_HEADER2_SIZE..udp_start],
+        UDP_HEADER2_SIZE,
+    )?;
+    write_udp_hdr(header_info, &mut buf[udp_start..udp_end], 0)?;
+    Ok(udp_end)
+}
+
+/// Copies the ethernet, ip and udp headers in `template` (see `write_hdr_template`) into `buf`,
+/// sets their lengths for `data_len` bytes following the message id, and writes `msg_id` after
+/// them. The ipv4 header checksum is recomputed; the UDP checksum is left zero for the NIC or
+/// `write_udp_checksum`.
+pub fn fill_in_hdrs(buf: &mut [u8], template: &[u8], msg_id: MsgID, data_len: usize) -> Result<()> {
+    let ip_version = match template.get(ETHERNET2_HEADER2_SIZE) {
+        Some(byte) => byte >> 4,
+        None => bail!("Header template of len {} has no ip header", template.len()),
+    };
+    let ip_hdr_size = match ip_version {
+        IPV4_VERSION => IPV4_HEADER2_SIZE,
+        IPV6_VERSION => IPV6_HEADER2_SIZE,
+        version => bail!("Header template has unknown ip version {}", version),
+    };
+    let udp_start = ETHERNET2_HEADER2_SIZE + ip_hdr_size;
+    let id_start = udp_start + UDP_HEADER2_SIZE;
+    if template.len() < id_start || buf.len() < id_start + HEADER_ID_SIZE {
+        bail!(
+            "Header template of len {} or buffer of len {} too short for headers of len {}",
+            template.len(),
+            buf.len(),
+            id_start + HEADER_ID_SIZE
+        );
+    }
+    buf[..id_start].copy_from_slice(&template[..id_start]);
+    let udp_len = UDP_HEADER2_SIZE + HEADER_ID_SIZE + data_len;
+    let ip_hdr = &mut buf[ETHERNET2_HEADER2_SIZE..udp_start];
+    match ip_version {
+        IPV4_VERSION => {
+            NetworkEndian::write_u16(&mut ip_hdr[2..4], (IPV4_HEADER2_SIZE + udp_len) as u16);
+            let checksum = ipv4_checksum(ip_hdr)?;
+            NetworkEndian::write_u16(&mut ip_hdr[10..12], checksum);
+        }
+        _ => NetworkEndian::write_u16(&mut ip_hdr[4..6], udp_len as u16),
+    }
+    let udp_hdr = &mut buf[udp_start..id_start];
+    NetworkEndian::write_u16(&mut udp_hdr[4..6], udp_len as u16);
+    write_udp_checksum(udp_hdr, 0);
+    write_pkt_id(msg_id, &mut buf[id_start..(id_start + HEADER_ID_SIZE)])
+}
+
+/// Source and destination addresses of the ipv4 or ipv6 header at the start of `buf`, and the
+/// header's length.
+pub fn ip_hdr_addrs(buf: &[u8]) -> Result<(IpAddr, IpAddr, usize)> {
+    match buf.first().map(|byte| byte >> 4) {
+        Some(IPV4_VERSION) if buf.len() >= IPV4_HEADER2_SIZE => Ok((
+            Ipv4Addr::from(NetworkEndian::read_u32(&buf[12..16])).into(),
+            Ipv4Addr::from(NetworkEndian::read_u32(&buf[16..20])).into(),
+            IPV4_HEADER2_SIZE,
+        )),
+        Some(IPV6_VERSION) if buf.len() >= IPV6_HEADER2_SIZE => Ok((
+            Ipv6Addr::from(NetworkEndian::read_u128(&buf[8..24])).into(),
+            Ipv6Addr::from(NetworkEndian::read_u128(&buf[24..40])).into(),
+            IPV6_HEADER2_SIZE,
+        )),
+        _ => bail!(
+            "Buffer of len {} does not start with an ip header",
+            buf.len()
+        ),
+    }
+}
+
 #[inline]
 pub fn check_eth_hdr(hdr_buf: &[u8], my_ether: &MacAddress) -> Result<(MacAddress, MacAddress)> {
     let dst_addr = MacAddress::from_bytes(&hdr_buf[0..6])?;
@@ -164,7 +481,7 @@ pub fn check_eth_hdr(hdr_buf: &[u8], my_ether: &MacAddress) -> Result<(MacAddres
         bail!("Destination ether address does not match mine and is not broadcast.");
     }
 
-    if ether_type != EtherType2::Ipv4 {
+    if ether_type != EtherType2::Ipv4 && ether_type != EtherType2::Ipv6 {
         bail!("Not correct ether type.");
     }
 
@@ -173,6 +490,9 @@ pub fn check_eth_hdr(hdr_buf: &[u8], my_ether: &MacAddress) -> Result<(MacAddres
 
 #[inline]
 pub fn check_ipv4_hdr(hdr_buf: &[u8], my_ip: &Ipv4Addr) -> Result<(Ipv4Addr, Ipv4Addr)> {
+    if hdr_buf[0] >> 4 != IPV4_VERSION {
+        bail!("(recv: dropped) Not an ipv4 header.");
+    }
     let src_addr = Ipv4Addr::from(NetworkEndian::read_u32(&hdr_buf[12..16]));
     let dst_addr = Ipv4Addr::from(NetworkEndian::read_u32(&hdr_buf[16..20]));
     if hdr_buf[9] != IPPROTO_UDP {
@@ -195,6 +515,50 @@ pub fn check_ipv4_hdr(hdr_buf: &[u8], my_ip: &Ipv4Addr) -> Result<(Ipv4Addr, Ipv
     Ok((src_addr, dst_addr))
 }
 
+#[inline]
+pub fn check_ipv6_hdr(hdr_buf: &[u8], my_ip: &Ipv6Addr) -> Result<(Ipv6Addr, Ipv6Addr)> {
+    if hdr_buf[0] >> 4 != IPV6_VERSION {
+        bail!("(recv: dropped) Not an ipv6 header.");
+    }
+    // extension headers are not supported; UDP must be the next header
+    if hdr_buf[6] != IPPROTO_UDP {
+        debug!("(recv: dropped)  ipv6 hdr does not have IPPROTO_UDP.");
+        bail!("(recv: dropped)  ipv6 hdr does not have IPPROTO_UDP.");
+    }
+    let src_addr = Ipv6Addr::from(NetworkEndian::read_u128(&hdr_buf[8..24]));
+    let dst_addr = Ipv6Addr::from(NetworkEndian::read_u128(&hdr_buf[24..40]));
+
+    if dst_addr != *my_ip {
+        debug!(
+            "(recv: dropped) Dest ipv6 addr: {:?} does not match mine {:?}",
+            dst_addr, my_ip
+        );
+        bail!(
+            "Dest ipv6 addr: {:?} does not match mine {:?}",
+            dst_addr,
+            my_ip
+        );
+    }
+
+    Ok((src_addr, dst_addr))
+}
+
+/// Checks an ipv4 or ipv6 header, matching the version of `my_ip`. Returns the source and
+/// destination addresses.
+#[inline]
+pub fn check_ip_hdr(hdr_buf: &[u8], my_ip: &IpAddr) -> Result<(IpAddr, IpAddr)> {
+    match my_ip {
+        IpAddr::V4(ip) => {
+            let (src, dst) = check_ipv4_hdr(hdr_buf, ip)?;
+            Ok((src.into(), dst.into()))
+        }
+        IpAddr::V6(ip) => {
+            let (src, dst) = check_ipv6_hdr(hdr_buf, ip)?;
+            Ok((src.into(), dst.into()))
+        }
+    }
+}
+
 #[inline]
 pub fn check_udp_hdr(hdr_buf: &[u8], my_udp_port: u16) -> Result<(u16, u16, usize)> {
     let src_port = NetworkEndian::read_u16(&hdr_buf[0..2]);
@@ -214,6 +578,12 @@ pub fn check_udp_hdr(hdr_buf: &[u8], my_udp_port: u16) -> Result<(u16, u16, usiz
     }
 
     tracing::debug!("data length recorded in packet udp header: {}", data_len);
+    if data_len < UDP_HEADER2_SIZE + HEADER_ID_SIZE {
+        bail!(
+            "recv dropped) UDP length {} too short for a message id.",
+            data_len
+        );
+    }
     Ok((
         src_port,
         dst_port,
@@ -225,3 +595,470 @@ pub fn check_udp_hdr(hdr_buf: &[u8], my_udp_port: u16) -> Result<(u16, u16, usiz
 pub fn parse_msg_id(hdr_buf: &[u8]) -> MsgID {
     LittleEndian::read_u32(&hdr_buf[0..4])
 }
+
+/// Why a received frame was not handed to the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDrop {
+    /// Not an IPv4 or IPv6 UDP datagram (e.g., another ethertype, QinQ tags, or IPv6 extension
+    /// headers).
+    Unsupported,
+    /// A UDP datagram for another address or port.
+    NotForUs,
+    /// A fragment of an IP datagram; fragments are not reassembled.
+    Fragment,
+    /// Truncated, or with lengths that do not fit the frame.
+    Malformed,
+}
+
+impl std::fmt::Display for FrameDrop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameDrop::Unsupported => write!(f, "Frame is not a UDP datagram"),
+            FrameDrop::NotForUs => write!(f, "Frame is not addressed to us"),
+            FrameDrop::Fragment => write!(f, "Frame is an IP fragment"),
+            FrameDrop::Malformed => write!(f, "Frame is malformed"),
+        }
+    }
+}
+
+impl std::error::Error for FrameDrop {}
+
+/// Where the headers of a received UDP frame end, and who sent it. Offsets are from the start of
+/// the frame, and account for VLAN tags and IPv4 options.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UdpFrameHeaders {
+    /// Sender (`src_info`) and receiver (`dst_info`) of the datagram.
+    pub header_info: HeaderInfo,
+    /// VLAN id of the 802.1Q tag, if the frame was tagged.
+    pub vlan_id: Option<u16>,
+    pub ip_offset: usize,
+    pub udp_offset: usize,
+    /// Offset of the payload, after the message id.
+    pub payload_offset: usize,
+    pub msg_id: MsgID,
+    /// Payload length (excluding the message id), from the UDP header. Frames may carry padding
+    /// past the payload.
+    pub data_len: usize,
+}
+
+impl UdpFrameHeaders {
+    pub fn udp_hdr<'a>(&self, frame: &'a [u8]) -> &'a [u8] {
+        &frame[self.udp_offset..(self.udp_offset + UDP_HEADER2_SIZE)]
+    }
+
+    /// Message id and payload.
+    pub fn udp_data<'a>(&self, frame: &'a [u8]) -> &'a [u8] {
+        &frame[(self.payload_offset - HEADER_ID_SIZE)..(self.payload_offset + self.data_len)]
+    }
+
+    pub fn payload<'a>(&self, frame: &'a [u8]) -> &'a [u8] {
+        &frame[self.payload_offset..(self.payload_offset + self.data_len)]
+    }
+
+    /// Checks the UDP checksum of the datagram in `frame` (see `verify_udp_checksum`).
+    pub fn verify_checksum(&self, frame: &[u8]) -> bool {
+        verify_udp_checksum(
+            &self.header_info.src_info.ip_addr,
+            &self.header_info.dst_info.ip_addr,
+            self.udp_hdr(frame),
+            [self.udp_data(frame)],
+        )
+    }
+}
+
+/// Locates the headers of a UDP frame carrying a message id, whoever it is addressed to. Handles
+/// one 802.1Q tag and IPv4 options, and rejects IP fragments rather than reassembling them.
+pub fn parse_udp_frame_headers(frame: &[u8]) -> std::result::Result<UdpFrameHeaders, FrameDrop> {
+    if frame.len() < ETHERNET2_HEADER2_SIZE {
+        return Err(FrameDrop::Malformed);
+    }
+    let dst_mac = MacAddress::from_bytes(&frame[0..6]).map_err(|_| FrameDrop::Malformed)?;
+    let src_mac = MacAddress::from_bytes(&frame[6..12]).map_err(|_| FrameDrop::Malformed)?;
+    let (vlan_id, ether_type, ip_offset) = match NetworkEndian::read_u16(&frame[12..14]) {
+        ETHERTYPE_VLAN => {
+            if frame.len() < ETHERNET_VLAN_HEADER_SIZE {
+                return Err(FrameDrop::Malformed);
+            }
+            (
+                Some(NetworkEndian::read_u16(&frame[14..16]) & VLAN_ID_MASK),
+                NetworkEndian::read_u16(&frame[16..18]),
+                ETHERNET_VLAN_HEADER_SIZE,
+            )
+        }
+        ether_type => (None, ether_type, ETHERNET2_HEADER2_SIZE),
+    };
+
+    let ip_hdr = &frame[ip_offset..];
+    // (source, destination, ip header length, ip header and payload length)
+    let (src_ip, dst_ip, ip_hdr_len, ip_len): (IpAddr, IpAddr, usize, usize) =
+        match EtherType2::try_from(ether_type) {
+            Ok(EtherType2::Ipv4) => {
+                if ip_hdr.len() < IPV4_HEADER2_SIZE || ip_hdr[0] >> 4 != IPV4_VERSION {
+                    return Err(FrameDrop::Malformed);
+                }
+                let ip_hdr_len = (ip_hdr[0] & 0xf) as usize * 4;
+                let ip_len = NetworkEndian::read_u16(&ip_hdr[2..4]) as usize;
+                if ip_hdr_len < IPV4_HEADER2_SIZE || ip_len < ip_hdr_len || ip_len > ip_hdr.len() {
+                    return Err(FrameDrop::Malformed);
+                }
+                let fragment = NetworkEndian::read_u16(&ip_hdr[6..8]);
+                if fragment & (IPV4_MORE_FRAGMENTS | IPV4_FRAGMENT_OFFSET_MASK) != 0 {
+                    return Err(FrameDrop::Fragment);
+                }
+                if ip_hdr[9] != IPPROTO_UDP {
+                    return Err(FrameDrop::Unsupported);
+                }
+                (
+                    Ipv4Addr::from(NetworkEndian::read_u32(&ip_hdr[12..16])).into(),
+                    Ipv4Addr::from(NetworkEndian::read_u32(&ip_hdr[16..20])).into(),
+                    ip_hdr_len,
+                    ip_len,
+                )
+            }
+            Ok(EtherType2::Ipv6) => {
+                if ip_hdr.len() < IPV6_HEADER2_SIZE || ip_hdr[0] >> 4 != IPV6_VERSION {
+                    return Err(FrameDrop::Malformed);
+                }
+                let ip_len = IPV6_HEADER2_SIZE + NetworkEndian::read_u16(&ip_hdr[4..6]) as usize;
+                if ip_len > ip_hdr.len() {
+                    return Err(FrameDrop::Malformed);
+                }
+                match ip_hdr[6] {
+                    IPPROTO_UDP => {}
+                    IPPROTO_IPV6_FRAGMENT => {
+                        return Err(FrameDrop::Fragment);
+                    }
+                    _ => {
+                        return Err(FrameDrop::Unsupported);
+                    }
+                }
+                (
+                    Ipv6Addr::from(NetworkEndian::read_u128(&ip_hdr[8..24])).into(),
+                    Ipv6Addr::from(NetworkEndian::read_u128(&ip_hdr[24..40])).into(),
+                    IPV6_HEADER2_SIZE,
+                    ip_len,
+                )
+            }
+            _ => {
+                return Err(FrameDrop::Unsupported);
+            }
+        };
+
+    // the UDP datagram must fit within the ip packet, which may be followed by padding
+    if ip_len < ip_hdr_len + UDP_HEADER2_SIZE {
+        return Err(FrameDrop::Malformed);
+    }
+    let udp_offset = ip_offset + ip_hdr_len;
+    let udp_hdr = &frame[udp_offset..(udp_offset + UDP_HEADER2_SIZE)];
+    let udp_len = NetworkEndian::read_u16(&udp_hdr[4..6]) as usize;
+    if udp_len < UDP_HEADER2_SIZE + HEADER_ID_SIZE || udp_len > ip_len - ip_hdr_len {
+        return Err(FrameDrop::Malformed);
+    }
+    let payload_offset = udp_offset + UDP_HEADER2_SIZE + HEADER_ID_SIZE;
+    Ok(UdpFrameHeaders {
+        header_info: HeaderInfo::new(
+            AddressInfo::new(NetworkEndian::read_u16(&udp_hdr[0..2]), src_ip, src_mac),
+            AddressInfo::new(NetworkEndian::read_u16(&udp_hdr[2..4]), dst_ip, dst_mac),
+        ),
+        vlan_id,
+        ip_offset,
+        udp_offset,
+        payload_offset,
+        msg_id: parse_msg_id(&frame[(payload_offset - HEADER_ID_SIZE)..payload_offset]),
+        data_len: udp_len - UDP_HEADER2_SIZE - HEADER_ID_SIZE,
+    })
+}
+
+/// Locates the headers of a received frame (see `parse_udp_frame_headers`), and checks that it
+/// is addressed to `my_addr` (or to the broadcast ethernet address).
+#[inline]
+pub fn check_udp_frame(
+    frame: &[u8],
+    my_addr: &AddressInfo,
+) -> std::result::Result<UdpFrameHeaders, FrameDrop> {
+    let headers = parse_udp_frame_headers(frame)?;
+    let dst_info = &headers.header_info.dst_info;
+    if (dst_info.ether_addr != my_addr.ether_addr && dst_info.ether_addr != MacAddress::broadcast())
+        || dst_info.ip_addr != my_addr.ip_addr
+        || dst_info.udp_port != my_addr.udp_port
+    {
+        debug!(
+            "(recv: dropped) Destination {:?} does not match mine {:?}",
+            dst_info, my_addr
+        );
+        return Err(FrameDrop::NotForUs);
+    }
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses() -> HeaderInfo {
+        HeaderInfo::new(
+            AddressInfo::new(
+                50000,
+                Ipv4Addr::new(10, 0, 0, 1),
+                MacAddress::new([0x02, 0, 0, 0, 0, 1]),
+            ),
+            AddressInfo::new(
+                54321,
+                Ipv4Addr::new(10, 0, 0, 2),
+                MacAddress::new([0x02, 0, 0, 0, 0, 2]),
+            ),
+        )
+    }
+
+    /// Ethernet (optionally tagged), ipv4 (with `options` bytes of options) and udp headers, the
+    /// message id and `payload`, followed by `padding` zero bytes.
+    fn ipv4_frame(vlan_id: Option<u16>, options: usize, payload: &[u8], padding: usize) -> Vec<u8> {
+        let header_info = addresses();
+        let eth_len = match vlan_id {
+            Some(_) => ETHERNET_VLAN_HEADER_SIZE,
+            None => ETHERNET2_HEADER2_SIZE,
+        };
+        let ip_len = IPV4_HEADER2_SIZE + options;
+        let data_len = HEADER_ID_SIZE + payload.len();
+        let mut frame = vec![0u8; eth_len + ip_len + UDP_HEADER2_SIZE + data_len + padding];
+        match vlan_id {
+            Some(vlan_id) => write_vlan_eth_hdr(&header_info, vlan_id, &mut frame[..eth_len]),
+            None => write_eth_hdr(&header_info, &mut frame[..eth_len]),
+        }
+        .unwrap();
+        write_ipv4_hdr(
+            &header_info,
+            &mut frame[eth_len..(eth_len + IPV4_HEADER2_SIZE)],
+            options + UDP_HEADER2_SIZE + data_len,
+        )
+        .unwrap();
+        frame[eth_len] = (IPV4_VERSION << 4) | (ip_len / 4) as u8;
+        let udp_offset = eth_len + ip_len;
+        write_udp_hdr(
+            &header_info,
+            &mut frame[udp_offset..(udp_offset + UDP_HEADER2_SIZE)],
+            data_len,
+        )
+        .unwrap();
+        let payload_offset = udp_offset + UDP_HEADER2_SIZE + HEADER_ID_SIZE;
+        write_pkt_id(
+            9,
+            &mut frame[(payload_offset - HEADER_ID_SIZE)..payload_offset],
+        )
+        .unwrap();
+        frame[payload_offset..(payload_offset + payload.len())].copy_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn parses_plain_tagged_and_optioned_frames() {
+        let header_info = addresses();
+        let my_addr = header_info.dst_info;
+
+        let frame = ipv4_frame(None, 0, b"hello", 0);
+        let headers = check_udp_frame(&frame, &my_addr).unwrap();
+        assert_eq!(headers.header_info, header_info);
+        assert_eq!(headers.vlan_id, None);
+        assert_eq!(headers.payload_offset, TOTAL_HEADER_SIZE);
+        assert_eq!((headers.msg_id, headers.data_len), (9, 5));
+        assert_eq!(headers.payload(&frame), b"hello");
+
+        let frame = ipv4_frame(Some(100), 0, b"hello", 0);
+        let headers = check_udp_frame(&frame, &my_addr).unwrap();
+        assert_eq!(headers.vlan_id, Some(100));
+        assert_eq!(headers.payload_offset, TOTAL_HEADER_SIZE + VLAN_TAG_SIZE);
+        assert_eq!(headers.payload(&frame), b"hello");
+
+        // ipv4 options and trailing ethernet padding
+        let frame = ipv4_frame(Some(7), 8, b"hi", 20);
+        let headers = check_udp_frame(&frame, &my_addr).unwrap();
+        assert_eq!(
+            headers.udp_offset,
+            ETHERNET_VLAN_HEADER_SIZE + IPV4_HEADER2_SIZE + 8
+        );
+        assert_eq!(headers.data_len, 2);
+        assert_eq!(headers.payload(&frame), b"hi");
+
+        // checksums cover the datagram, wherever it starts
+        let mut frame = ipv4_frame(Some(7), 8, b"hi", 0);
+        let udp_offset = headers.udp_offset;
+        let checksum = udp_checksum(
+            &header_info.src_info.ip_addr,
+            &header_info.dst_info.ip_addr,
+            &frame[udp_offset..(udp_offset + UDP_HEADER2_SIZE)],
+            [&frame[(udp_offset + UDP_HEADER2_SIZE)..]],
+        )
+        .unwrap();
+        write_udp_checksum(
+            &mut frame[udp_offset..(udp_offset + UDP_HEADER2_SIZE)],
+            checksum,
+        );
+        assert!(headers.verify_checksum(&frame));
+        frame[headers.payload_offset] ^= 1;
+        assert!(!headers.verify_checksum(&frame));
+    }
+
+    #[test]
+    fn drops_fragments_and_malformed_frames() {
+        let header_info = addresses();
+        let my_addr = header_info.dst_info;
+        let frame = ipv4_frame(None, 0, b"hello", 0);
+        let ip = ETHERNET2_HEADER2_SIZE;
+        let udp = ip + IPV4_HEADER2_SIZE;
+
+        // more fragments flag, then a non-zero fragment offset
+        for fragment in [0x2000u16, 0x0010] {
+            let mut fragmented = frame.clone();
+            NetworkEndian::write_u16(&mut fragmented[(ip + 6)..(ip + 8)], fragment);
+            assert_eq!(
+                check_udp_frame(&fragmented, &my_addr),
+                Err(FrameDrop::Fragment)
+            );
+        }
+        // don't fragment is fine
+        let mut unfragmented = frame.clone();
+        NetworkEndian::write_u16(&mut unfragmented[(ip + 6)..(ip + 8)], 0x4000);
+        assert!(check_udp_frame(&unfragmented, &my_addr).is_ok());
+
+        let truncated = &frame[..(frame.len() - 1)];
+        assert_eq!(
+            check_udp_frame(truncated, &my_addr),
+            Err(FrameDrop::Malformed)
+        );
+        assert_eq!(
+            check_udp_frame(&frame[..10], &my_addr),
+            Err(FrameDrop::Malformed)
+        );
+        // IHL shorter than the minimum header
+        let mut short_ihl = frame.clone();
+        short_ihl[ip] = (IPV4_VERSION << 4) | 4;
+        assert_eq!(
+            check_udp_frame(&short_ihl, &my_addr),
+            Err(FrameDrop::Malformed)
+        );
+        // IHL pointing past the packet
+        let mut long_ihl = frame.clone();
+        long_ihl[ip] = (IPV4_VERSION << 4) | 15;
+        assert_eq!(
+            check_udp_frame(&long_ihl, &my_addr),
+            Err(FrameDrop::Malformed)
+        );
+        // UDP length too short for a message id, and longer than the ip packet
+        for udp_len in [UDP_HEADER2_SIZE as u16, 100] {
+            let mut bad_len = frame.clone();
+            NetworkEndian::write_u16(&mut bad_len[(udp + 4)..(udp + 6)], udp_len);
+            assert_eq!(
+                check_udp_frame(&bad_len, &my_addr),
+                Err(FrameDrop::Malformed)
+            );
+        }
+        // tag without room for the ethertype
+        let tagged = ipv4_frame(Some(1), 0, b"", 0);
+        assert_eq!(
+            check_udp_frame(&tagged[..(ETHERNET_VLAN_HEADER_SIZE - 1)], &my_addr),
+            Err(FrameDrop::Malformed)
+        );
+    }
+
+    #[test]
+    fn drops_frames_for_others() {
+        let header_info = addresses();
+        let frame = ipv4_frame(Some(3), 0, b"hello", 0);
+        let mut other = header_info.dst_info;
+        other.udp_port += 1;
+        assert_eq!(check_udp_frame(&frame, &other), Err(FrameDrop::NotForUs));
+        // the sender's view of the frame: parsing does not check addresses
+        assert!(parse_udp_frame_headers(&frame).is_ok());
+        assert_eq!(
+            check_udp_frame(&frame, &header_info.src_info),
+            Err(FrameDrop::NotForUs)
+        );
+
+        let mut tcp = ipv4_frame(None, 0, b"hello", 0);
+        tcp[ETHERNET2_HEADER2_SIZE + 9] = 6;
+        assert_eq!(
+            check_udp_frame(&tcp, &header_info.dst_info),
+            Err(FrameDrop::Unsupported)
+        );
+        let mut arp = ipv4_frame(None, 0, b"hello", 0);
+        NetworkEndian::write_u16(&mut arp[12..14], EtherType2::Arp as u16);
+        assert_eq!(
+            check_udp_frame(&arp, &header_info.dst_info),
+            Err(FrameDrop::Unsupported)
+        );
+        // broadcast frames are ours
+        let mut broadcast = ipv4_frame(None, 0, b"hello", 0);
+        broadcast[0..6].copy_from_slice(MacAddress::broadcast().as_bytes());
+        assert!(check_udp_frame(&broadcast, &header_info.dst_info).is_ok());
+    }
+
+    #[test]
+    fn ipv6_fragments_are_dropped() {
+        let header_info = HeaderInfo::new(
+            AddressInfo::new(50000, Ipv6Addr::LOCALHOST, MacAddress::default()),
+            AddressInfo::new(54321, Ipv6Addr::LOCALHOST, MacAddress::default()),
+        );
+        let data_len = HEADER_ID_SIZE + 3;
+        let mut frame = vec![0u8; TOTAL_UDP_HEADER_SIZE_V6 + data_len];
+        write_eth_hdr(&header_info, &mut frame[..ETHERNET2_HEADER2_SIZE]).unwrap();
+        write_ipv6_hdr(
+            &header_info,
+            &mut frame[ETHERNET2_HEADER2_SIZE..(ETHERNET2_HEADER2_SIZE + IPV6_HEADER2_SIZE)],
+            UDP_HEADER2_SIZE + data_len,
+        )
+        .unwrap();
+        write_udp_hdr(
+            &header_info,
+            &mut frame[(ETHERNET2_HEADER2_SIZE + IPV6_HEADER2_SIZE)..TOTAL_UDP_HEADER_SIZE_V6],
+            data_len,
+        )
+        .unwrap();
+        let headers = check_udp_frame(&frame, &header_info.dst_info).unwrap();
+        assert_eq!(headers.payload_offset, TOTAL_HEADER_SIZE_V6);
+        assert_eq!(headers.data_len, 3);
+
+        frame[ETHERNET2_HEADER2_SIZE + 6] = IPPROTO_IPV6_FRAGMENT;
+        assert_eq!(
+            check_udp_frame(&frame, &header_info.dst_info),
+            Err(FrameDrop::Fragment)
+        );
+    }
+
+    #[test]
+    fn filled_in_templates_parse_for_both_families() {
+        let ipv6_info = HeaderInfo::new(
+            AddressInfo::new(
+                50000,
+                "fd00::1".parse::<Ipv6Addr>().unwrap(),
+                MacAddress::default(),
+            ),
+            AddressInfo::new(
+                54321,
+                "fd00::2".parse::<Ipv6Addr>().unwrap(),
+                MacAddress::default(),
+            ),
+        );
+        for header_info in [addresses(), ipv6_info] {
+            let mut template = [0u8; TOTAL_UDP_HEADER_SIZE_V6];
+            let template_len = write_hdr_template(&header_info, &mut template).unwrap();
+            assert_eq!(template_len, udp_header_size(&header_info.src_info.ip_addr));
+
+            let payload = b"hello";
+            let mut frame = vec![0u8; header_info.header_size() + payload.len()];
+            fill_in_hdrs(&mut frame, &template[..template_len], 7, payload.len()).unwrap();
+            frame[header_info.header_size()..].copy_from_slice(payload);
+            let (src_ip, dst_ip, ip_len) = ip_hdr_addrs(&frame[ETHERNET2_HEADER2_SIZE..]).unwrap();
+            assert_eq!(src_ip, header_info.src_info.ip_addr);
+            assert_eq!(dst_ip, header_info.dst_info.ip_addr);
+            assert_eq!(ip_len, ip_header_size(&src_ip));
+
+            let headers = check_udp_frame(&frame, &header_info.dst_info).unwrap();
+            assert_eq!(headers.header_info, header_info);
+            assert_eq!(headers.msg_id, 7);
+            assert_eq!(headers.payload(&frame), payload);
+            assert_eq!(headers.payload_offset, header_info.header_size());
+        }
+        assert!(fill_in_hdrs(&mut [0u8; 8], &[0u8; 8], 0, 0).is_err());
+        assert!(ip_hdr_addrs(&[IPV6_VERSION << 4; IPV4_HEADER2_SIZE]).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use color_eyre::eyre::{bail, Result};
 use eui48::MacAddress;
 use hashbrown::HashMap;
-use std::{fs::read_to_string, net::Ipv4Addr, path::Path, str::FromStr};
+use std::{fs::read_to_string, net::IpAddr, path::Path, str::FromStr};
 use tracing::Level;
 use tracing_subscriber;
 use tracing_subscriber::{
@@ -13,16 +13,32 @@ use yaml_rust::{Yaml, YamlLoader};
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct CopyingThreshold {
     thresh: usize,
+    /// Whether the threshold should be tuned online (starting from thresh).
+    adaptive: bool,
 }
 
 impl CopyingThreshold {
     pub fn new(thresh: usize) -> Self {
-        CopyingThreshold { thresh: thresh }
+        CopyingThreshold {
+            thresh: thresh,
+            adaptive: false,
+        }
+    }
+
+    pub fn new_adaptive(initial_thresh: usize) -> Self {
+        CopyingThreshold {
+            thresh: initial_thresh,
+            adaptive: true,
+        }
     }
 
     pub fn thresh(&self) -> usize {
         self.thresh
     }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.adaptive
+    }
 }
 impl std::str::FromStr for CopyingThreshold {
     type Err = color_eyre::eyre::Error;
@@ -32,6 +48,8 @@ impl std::str::FromStr for CopyingThreshold {
             Err(e) => {
                 if s == "infinity" || s == "Infinity" || s == "INFINITY" {
                     return Ok(CopyingThreshold::new(usize::MAX));
+                } else if s == "adaptive" || s == "Adaptive" || s == "ADAPTIVE" {
+                    return Ok(CopyingThreshold::new_adaptive(256));
                 } else {
                     bail!("Could not parse copying threshold from {}: {:?}", s, e);
                 }
@@ -54,23 +72,15 @@ pub fn get_thread_latlog(name: &str, thread_id: usize) -> Result<String> {
     Ok(file_parent.to_str().unwrap().to_string())
 }
 
+/// Looks up the server's MAC address, IP and UDP port in the config file. The MAC address is
+/// None if the server is missing from the known hosts, in which case datapaths resolve it with
+/// ARP on `connect` (see `AddressInfo::from_known_host`).
 pub fn parse_server_addr(
     config_file: &str,
-    server_ip: &Ipv4Addr,
-) -> Result<(MacAddress, Ipv4Addr, u16)> {
+    server_ip: &IpAddr,
+) -> Result<(Option<MacAddress>, IpAddr, u16)> {
     let (ip_to_mac, _mac_to_ip, udp_port, _client_port) = parse_yaml_map(config_file)?;
-    let eth = match ip_to_mac.get(server_ip) {
-        Some(eth) => eth,
-        None => {
-            bail!(
-                "Could not find eth address for ip {:?} in yaml map in file {}",
-                server_ip,
-                config_file
-            );
-        }
-    };
-
-    Ok((eth.clone(), server_ip.clone(), udp_port))
+    Ok((ip_to_mac.get(server_ip).copied(), *server_ip, udp_port))
 }
 
 pub fn parse_server_port(config_file: &str) -> Result<u16> {
@@ -99,8 +109,8 @@ pub fn parse_server_port(config_file: &str) -> Result<u16> {
 pub fn parse_yaml_map(
     config_file: &str,
 ) -> Result<(
-    HashMap<Ipv4Addr, MacAddress>,
-    HashMap<MacAddress, Ipv4Addr>,
+    HashMap<IpAddr, MacAddress>,
+    HashMap<MacAddress, IpAddr>,
     u16,
     u16,
 )> {
@@ -113,8 +123,8 @@ pub fn parse_yaml_map(
     };
 
     let yaml = &yamls[0];
-    let mut ip_to_mac: HashMap<Ipv4Addr, MacAddress> = HashMap::new();
-    let mut mac_to_ip: HashMap<MacAddress, Ipv4Addr> = HashMap::new();
+    let mut ip_to_mac: HashMap<IpAddr, MacAddress> = HashMap::new();
+    let mut mac_to_ip: HashMap<MacAddress, IpAddr> = HashMap::new();
     match yaml["lwip"].as_hash() {
         Some(lwip_map) => {
             let known_hosts = match lwip_map.get(&Yaml::from_str("known_hosts")) {
@@ -125,7 +135,7 @@ pub fn parse_yaml_map(
             };
             for (key, value) in known_hosts.iter() {
                 let mac_addr = MacAddress::from_str(key.as_str().unwrap())?;
-                let ip_addr = Ipv4Addr::from_str(value.as_str().unwrap())?;
+                let ip_addr = IpAddr::from_str(value.as_str().unwrap())?;
                 ip_to_mac.insert(ip_addr, mac_addr);
                 mac_to_ip.insert(mac_addr, ip_addr);
             }
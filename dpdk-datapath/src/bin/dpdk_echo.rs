@@ -11,7 +11,7 @@ use dpdk_datapath::{
     },
     dpdk_bindings,
 };
-use std::{net::Ipv4Addr, process::exit, time::Instant};
+use std::{net::IpAddr, process::exit, time::Instant};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -73,7 +73,7 @@ struct Opt {
         help = "Server ip address",
         default_value = "127.0.0.1"
     )]
-    server_ip: Ipv4Addr,
+    server_ip: IpAddr,
     #[structopt(
         long = "distribution",
         help = "arrival distribution",
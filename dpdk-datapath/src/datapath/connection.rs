@@ -2,8 +2,17 @@ use super::{
     super::dpdk_bindings::*, allocator::MempoolInfo, dpdk_check, dpdk_utils::*, wrapper::*,
 };
 use cornflakes_libos::{
-    allocator::{align_up, MemoryPoolAllocator, MempoolID},
-    datapath::{Datapath, DatapathBufferOps, InlineMode, MetadataOps, ReceivedPkt},
+    allocator::{align_up, AllocatorMemoryStats, MemoryPoolAllocator, MempoolConfig, MempoolID},
+    datapath::{
+        ChecksumMode, Datapath, DatapathBufferOps, InlineMode, MetadataOps, ReceiveStats,
+        ReceivedPkt, TransmitStats,
+    },
+    encryption::{EncryptionConfig, TransportEncryption, ENCRYPTION_OVERHEAD, NONCE_LEN},
+    neighbor::{
+        deferred_with_durations, is_arp_frame, static_ipv4_entries, NeighborConfig, NeighborStats,
+        NeighborTable, Resolution, ARP_FRAME_SIZE,
+    },
+    pcap::PacketCapture,
     utils::AddressInfo,
     ConnID, MsgID, OrderedSga, RcSga, RcSge, Sga, Sge, USING_REF_COUNTING,
 };
@@ -16,7 +25,7 @@ use std::{
     ffi::CString,
     io::Write,
     mem::MaybeUninit,
-    net::Ipv4Addr,
+    net::{IpAddr, Ipv4Addr},
     ptr,
     time::{Duration, Instant},
 };
@@ -46,6 +55,8 @@ const TX_WTHRESH: u8 = 0;
 
 const RX_RING_SIZE: u16 = 2048;
 const TX_RING_SIZE: u16 = 2048;
+/// Shortest Ethernet frame, without the FCS; shorter frames are padded with zeros.
+const MIN_ETHERNET_FRAME_SIZE: usize = 60;
 
 #[derive(PartialEq, Eq)]
 pub struct DpdkBuffer {
@@ -378,6 +389,8 @@ pub struct DpdkPerThreadContext {
     recv_mempool: *mut rte_mempool,
     /// Empty mempool for allocating external buffers
     extbuf_mempool: *mut rte_mempool,
+    /// VLAN the NIC tags transmitted frames with, if any
+    vlan_id: Option<u16>,
 }
 
 unsafe impl Send for DpdkPerThreadContext {}
@@ -403,6 +416,10 @@ impl DpdkPerThreadContext {
     pub fn get_physical_port(&self) -> u16 {
         self.physical_port
     }
+
+    pub fn get_vlan_id(&self) -> Option<u16> {
+        self.vlan_id
+    }
 }
 
 impl Drop for DpdkPerThreadContext {
@@ -418,10 +435,13 @@ impl Drop for DpdkPerThreadContext {
 pub struct DpdkDatapathSpecificParams {
     eal_init: Vec<String>,
     dpdk_port: i16,
-    our_ip: Ipv4Addr,
+    our_ip: IpAddr,
     our_eth: MacAddress,
     starting_client_port: u16,
     server_port: u16,
+    vlan_id: Option<u16>,
+    /// `known_hosts` from the config file, the fallback for ARP resolution.
+    static_neighbors: HashMap<Ipv4Addr, MacAddress>,
 }
 
 impl DpdkDatapathSpecificParams {
@@ -440,8 +460,8 @@ impl DpdkDatapathSpecificParams {
         Ok(self.dpdk_port as u16)
     }
 
-    fn get_ipv4(&self) -> Ipv4Addr {
-        self.our_ip.clone()
+    fn get_ip(&self) -> IpAddr {
+        self.our_ip
     }
 
     pub fn get_mac(&self) -> MacAddress {
@@ -455,6 +475,10 @@ impl DpdkDatapathSpecificParams {
     pub fn get_server_port(&self) -> u16 {
         self.server_port
     }
+
+    pub fn get_vlan_id(&self) -> Option<u16> {
+        self.vlan_id
+    }
 }
 
 pub struct DpdkConnection {
@@ -464,10 +488,11 @@ pub struct DpdkConnection {
     mode: AppMode,
     /// Current window of outstanding packets (used to keep track of rtts)
     outgoing_window: HashMap<(MsgID, ConnID), Instant>,
-    /// Active connections: current connection IDs mapped to addresses.
+    /// Active connections: current connection IDs mapped to addresses and header templates,
+    /// of which the first `udp_header_size` bytes for our address are used.
     active_connections: [Option<(
         AddressInfo,
-        [u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE],
+        [u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE_V6],
     )>; MAX_CONCURRENT_CONNECTIONS],
     /// Map from address info to connection id
     address_to_conn_id: HashMap<AddressInfo, ConnID>,
@@ -481,9 +506,173 @@ pub struct DpdkConnection {
     recv_mbufs: [*mut rte_mbuf; RECEIVE_BURST_SIZE],
     /// Array of mbuf pointers used to send packets
     send_mbufs: [[*mut rte_mbuf; SEND_BURST_SIZE]; MAX_SCATTERS],
+    /// Copy versus zero-copy accounting for transmitted messages.
+    transmit_stats: TransmitStats,
+    /// Whether UDP checksums are computed and checked (in software; offload flags are not set).
+    checksum_mode: ChecksumMode,
+    /// Received packets dropped by this datapath.
+    receive_stats: ReceiveStats,
+    /// Capture of sent and received frames, if enabled.
+    capture: Option<PacketCapture>,
+    /// Payload encryption, if enabled.
+    encryption: Option<TransportEncryption>,
+    /// ARP cache; answers requests for our address and resolves peers on `connect`.
+    neighbors: NeighborTable,
+    /// Packets received while `connect` resolved a peer; `pop` hands them out first.
+    deferred_pkts: Vec<ReceivedPkt<Self>>,
+}
+
+/// Data of each segment in the chain starting at `mbuf`.
+unsafe fn mbuf_chain_segments<'a>(mbuf: *mut rte_mbuf) -> impl Iterator<Item = &'a [u8]> {
+    std::iter::successors(Some(mbuf).filter(|seg| !seg.is_null()), |seg| {
+        Some(access!(*seg, next, *mut rte_mbuf)).filter(|next| !next.is_null())
+    })
+    .map(|seg| mbuf_slice!(seg, 0, access!(seg, data_len, usize)))
+}
+
+/// Computes the UDP checksum of a packet whose first mbuf starts with the ethernet, ip and udp
+/// headers, over every segment in its chain, and writes it into the udp header.
+unsafe fn write_software_checksum(mbuf: *mut rte_mbuf) -> Result<()> {
+    let ip_hdr = mbuf_slice!(
+        mbuf,
+        cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE,
+        access!(mbuf, data_len, usize) - cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
+    );
+    let (src_ip, dst_ip, ip_hdr_size) = cornflakes_libos::utils::ip_hdr_addrs(ip_hdr)?;
+    let udp_offset = cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE + ip_hdr_size;
+    let udp_hdr = mbuf_mut_slice!(mbuf, udp_offset, cornflakes_libos::utils::UDP_HEADER2_SIZE);
+    let payload_offset = udp_offset + cornflakes_libos::utils::UDP_HEADER2_SIZE;
+    let first_segment = mbuf_slice!(
+        mbuf,
+        payload_offset,
+        access!(mbuf, data_len, usize) - payload_offset
+    );
+    let next_segments = mbuf_chain_segments(access!(mbuf, next, *mut rte_mbuf));
+    let checksum = cornflakes_libos::utils::udp_checksum(
+        &src_ip,
+        &dst_ip,
+        udp_hdr,
+        std::iter::once(first_segment).chain(next_segments),
+    )?;
+    cornflakes_libos::utils::write_udp_checksum(udp_hdr, checksum);
+    Ok(())
 }
 
 impl DpdkConnection {
+    pub fn neighbor_stats(&self) -> NeighborStats {
+        self.neighbors.stats()
+    }
+
+    /// Fills in the MAC address of a peer given without one, sending ARP requests until a reply
+    /// arrives. Data packets received in the meantime are deferred to the next `pop`.
+    fn resolve_ether_addr(&mut self, addr: AddressInfo) -> Result<AddressInfo> {
+        let ip = addr
+            .ipv4_addr()
+            .wrap_err("ARP can only resolve IPv4 peers; add the peer to known_hosts")?;
+        loop {
+            match self.neighbors.resolve(&ip, Instant::now())? {
+                Resolution::Resolved(mac) => {
+                    tracing::info!(ip =? ip, mac =? mac, "Resolved peer address");
+                    return Ok(AddressInfo::new(addr.udp_port, ip, mac));
+                }
+                Resolution::Pending(request) => {
+                    if let Some(frame) = request {
+                        self.transmit_frame(&frame)?;
+                    }
+                    // replies are handled in check_received_pkt
+                    let received = self.receive_pkts()?;
+                    self.deferred_pkts.extend(received);
+                }
+            }
+        }
+    }
+
+    /// Hands a received ARP frame to the neighbor table and transmits the reply, if any.
+    fn process_arp(&mut self, frame: &[u8]) -> Result<()> {
+        match self.neighbors.process_frame(frame, Instant::now()) {
+            Ok(Some(reply)) => self.transmit_frame(&reply),
+            Ok(None) => Ok(()),
+            Err(e) => {
+                tracing::debug!(err =? e, "(recv: dropped) Malformed ARP frame");
+                Ok(())
+            }
+        }
+    }
+
+    /// Transmits a complete Ethernet frame as is; no checksum is written, as the frame need not
+    /// be IP.
+    fn transmit_frame(&mut self, frame: &[u8]) -> Result<()> {
+        let mut dpdk_buffer = match self.allocator.allocate_tx_buffer()? {
+            Some(buf) => buf,
+            None => {
+                bail!("No tx mempools to allocate outgoing frame");
+            }
+        };
+        let frame_len = std::cmp::max(frame.len(), MIN_ETHERNET_FRAME_SIZE);
+        let dst = dpdk_buffer.mutable_slice(0, frame_len)?;
+        dst[..frame.len()].copy_from_slice(frame);
+        dst[frame.len()..].fill(0);
+        let mut metadata_mbuf = RteMbufMetadata::from_dpdk_buf(dpdk_buffer);
+        metadata_mbuf.update_metadata(frame_len as _, ptr::null_mut(), None, false, 1);
+        // the NIC frees the mbuf once it is sent
+        metadata_mbuf.increment_refcnt();
+        let mut mbuf = metadata_mbuf.get_inner();
+        if let Some(capture) = self.capture.as_mut() {
+            capture.record_frame(unsafe { mbuf_chain_segments(mbuf) });
+        }
+        if let Some(vlan_id) = self.thread_context.get_vlan_id() {
+            unsafe { set_tx_vlan(mbuf, vlan_id) };
+        }
+        while unsafe {
+            rte_eth_tx_burst(
+                self.thread_context.get_physical_port(),
+                self.thread_context.get_queue_id(),
+                &mut mbuf,
+                1,
+            )
+        } == 0
+        {}
+        Ok(())
+    }
+
+    /// Receives a burst of packets.
+    fn receive_pkts(&mut self) -> Result<Vec<ReceivedPkt<Self>>> {
+        let num_received = unsafe {
+            rte_eth_rx_burst(
+                self.thread_context.get_physical_port(),
+                self.thread_context.get_queue_id(),
+                self.recv_mbufs.as_mut_ptr(),
+                RECEIVE_BURST_SIZE as _,
+            )
+        };
+
+        if num_received == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut ret: Vec<ReceivedPkt<Self>> = Vec::with_capacity(RECEIVE_BURST_SIZE);
+
+        for i in 0..num_received as usize {
+            if let Some(received_pkt) = self
+                .check_received_pkt(i)
+                .wrap_err(format!("Error checking received pkt {}", i))?
+            {
+                tracing::debug!(
+                    "Received pkt with msg ID {}, conn ID {}",
+                    received_pkt.msg_id(),
+                    received_pkt.conn_id()
+                );
+                ret.push(received_pkt);
+            } else {
+                unsafe {
+                    rte_pktmbuf_free(self.recv_mbufs[i]);
+                }
+            }
+            self.recv_mbufs[i] = ptr::null_mut();
+        }
+        Ok(ret)
+    }
+
     fn _debug_check_received_pkt(
         &mut self,
         i: usize,
@@ -516,9 +705,9 @@ impl DpdkConnection {
                 cornflakes_libos::utils::IPV4_HEADER2_SIZE
             )
         };
-        let (src_ip, _) = match cornflakes_libos::utils::check_ipv4_hdr(
+        let (src_ip, _) = match cornflakes_libos::utils::check_ip_hdr(
             ipv4_hdr,
-            &self.thread_context.address_info.ipv4_addr,
+            &self.thread_context.address_info.ip_addr,
         ) {
             Ok(r) => r,
             Err(_) => {
@@ -587,85 +776,61 @@ impl DpdkConnection {
     fn check_received_pkt(&mut self, i: usize) -> Result<Option<ReceivedPkt<Self>>> {
         tracing::debug!("Checking received packet");
         let recv_mbuf = self.recv_mbufs[i];
-        let eth_hdr = unsafe {
-            mbuf_slice!(
-                recv_mbuf,
-                0,
-                cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-            )
-        };
-        let (src_eth, _) = match cornflakes_libos::utils::check_eth_hdr(
-            eth_hdr,
-            &self.thread_context.address_info.ether_addr,
+        let frame = unsafe { mbuf_slice!(recv_mbuf, 0, (*recv_mbuf).data_len as usize) };
+        if is_arp_frame(frame) {
+            self.process_arp(&frame[..std::cmp::min(frame.len(), ARP_FRAME_SIZE)])?;
+            return Ok(None);
+        }
+        let headers = match cornflakes_libos::utils::check_udp_frame(
+            frame,
+            &self.thread_context.address_info,
         ) {
-            Ok(r) => r,
-            Err(_) => {
+            Ok(headers) => headers,
+            Err(reason) => {
+                tracing::debug!("Dropping received frame: {}", reason);
+                self.receive_stats.record_drop(reason);
                 return Ok(None);
             }
         };
+        let data_len = headers.data_len;
+        tracing::debug!("Data len in udp hdr: {:?}", data_len);
 
-        let ipv4_hdr = unsafe {
-            mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE,
-                cornflakes_libos::utils::IPV4_HEADER2_SIZE
-            )
-        };
-        let (src_ip, _) = match cornflakes_libos::utils::check_ipv4_hdr(
-            ipv4_hdr,
-            &self.thread_context.address_info.ipv4_addr,
-        ) {
-            Ok(r) => r,
-            Err(_) => {
-                tracing::debug!("IP hdr wrong");
-                return Ok(None);
-            }
-        };
+        if self.checksum_mode == ChecksumMode::Software && !headers.verify_checksum(frame) {
+            tracing::debug!("UDP checksum wrong");
+            self.receive_stats.record_checksum_failure();
+            return Ok(None);
+        }
 
-        let udp_hdr = unsafe {
-            mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                    + cornflakes_libos::utils::IPV4_HEADER2_SIZE,
-                cornflakes_libos::utils::UDP_HEADER2_SIZE
-            )
-        };
+        if let Some(capture) = self.capture.as_mut() {
+            capture.record_frame(unsafe { mbuf_chain_segments(recv_mbuf) });
+        }
 
-        let (src_port, _, data_len) = match cornflakes_libos::utils::check_udp_hdr(
-            udp_hdr,
-            self.thread_context.address_info.udp_port,
-        ) {
-            Ok(p) => p,
-            Err(_) => {
-                tracing::debug!("UDP hdr wrong");
-                return Ok(None);
+        // decrypt in place, so the application can still reference the received buffer; frames
+        // that fail to authenticate are dropped before they can take up a connection
+        let (payload_offset, data_len) = match self.encryption.as_mut() {
+            Some(encryption) => {
+                let sealed =
+                    unsafe { mbuf_mut_slice!(recv_mbuf, headers.payload_offset, data_len) };
+                match encryption.open(&headers.header_info.src_info, headers.msg_id, sealed) {
+                    Ok(len) => (headers.payload_offset + NONCE_LEN, len),
+                    Err(e) => {
+                        tracing::debug!(msg_id = headers.msg_id, "Dropping message: {:?}", e);
+                        self.receive_stats.record_decryption_failure();
+                        return Ok(None);
+                    }
+                }
             }
+            None => (headers.payload_offset, data_len),
         };
 
-        tracing::debug!("Data len in udp hdr: {:?}", data_len);
-
         // check if this address info is within a current conn_id
-        let src_addr = cornflakes_libos::utils::AddressInfo::new(src_port, src_ip, src_eth);
         let conn_id = self
-            .connect(src_addr)
+            .connect(headers.header_info.src_info)
             .wrap_err("TOO MANY CONCURRENT CONNECTIONS")?;
 
-        let msg_id = unsafe {
-            let slice = mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE,
-                cornflakes_libos::utils::HEADER_ID_SIZE
-            );
-            cornflakes_libos::utils::parse_msg_id(slice)
-        };
+        let datapath_metadata = RteMbufMetadata::new(recv_mbuf, payload_offset, Some(data_len))?;
 
-        let datapath_metadata = RteMbufMetadata::new(
-            recv_mbuf,
-            cornflakes_libos::utils::TOTAL_HEADER_SIZE,
-            Some(data_len),
-        )?;
-
-        let received_pkt = ReceivedPkt::new(vec![datapath_metadata], msg_id, conn_id);
+        let received_pkt = ReceivedPkt::new(vec![datapath_metadata], headers.msg_id, conn_id);
         Ok(Some(received_pkt))
     }
 
@@ -760,7 +925,7 @@ impl DpdkConnection {
             }
         }
         *nb_segs += 1;
-        *pkt_len += cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+        *pkt_len += self.header_size();
         Ok(())
     }
     fn write_header_and_return_new_buffer(
@@ -776,12 +941,63 @@ impl DpdkConnection {
                 bail!("Error allocating mbuf to copy header into");
             }
         };
-        let mutable_slice =
-            dpdk_buffer.mutable_slice(0, cornflakes_libos::utils::TOTAL_HEADER_SIZE)?;
+        let mutable_slice = dpdk_buffer.mutable_slice(0, self.header_size())?;
         self.copy_hdr(conn_id, msg_id, mutable_slice, data_len)?;
         Ok(dpdk_buffer)
     }
 
+    /// Copies `segments` into a new transmit buffer after the packet header, seals them for the
+    /// connection, and places the buffer at `pkt_idx` in the send mbufs. Each segment comes with
+    /// whether it would have been zero-copied without encryption.
+    fn post_encrypted_segments(
+        &mut self,
+        pkt_idx: usize,
+        msg_id: MsgID,
+        conn_id: ConnID,
+        segments: &[(&[u8], bool)],
+    ) -> Result<()> {
+        let peer = match &self.active_connections[conn_id as usize] {
+            Some((addr, _)) => *addr,
+            None => {
+                bail!("Could not find address for connID");
+            }
+        };
+        let data_len: usize = segments.iter().map(|(seg, _)| seg.len()).sum();
+        let sealed_len = data_len + ENCRYPTION_OVERHEAD;
+        let mut dpdk_buffer = self.write_header_and_return_new_buffer(
+            conn_id,
+            msg_id,
+            self.header_size() + sealed_len,
+            sealed_len,
+        )?;
+        let sealed = dpdk_buffer
+            .mutable_slice(self.header_size(), self.header_size() + sealed_len)
+            .wrap_err(format!(
+                "Encrypted message of len {} does not fit in a transmit buffer",
+                sealed_len
+            ))?;
+        let mut offset = NONCE_LEN;
+        for (seg, zero_copy_eligible) in segments.iter() {
+            sealed[offset..offset + seg.len()].copy_from_slice(seg);
+            offset += seg.len();
+            self.transmit_stats
+                .record_encrypted_segment(seg.len(), *zero_copy_eligible);
+        }
+        match self.encryption.as_mut() {
+            Some(encryption) => {
+                encryption.seal(&peer, msg_id, sealed, data_len)?;
+            }
+            None => {
+                bail!("Encryption is not enabled");
+            }
+        }
+
+        // the buffer's data len covers the header and sealed payload
+        let mut nb_segs = 0;
+        let mut pkt_len = 0;
+        self.place_copy_buf_into_send_mbufs(dpdk_buffer, pkt_idx, &mut nb_segs, &mut pkt_len)
+    }
+
     fn zero_copy_rc_seg(&self, seg: &RcSge<Self>) -> bool {
         match seg {
             RcSge::RawRef(_) => false,
@@ -809,6 +1025,14 @@ impl DpdkConnection {
         let mut nb_segs = 0;
         let mut pkt_len = 0;
         let msg_size = sga.data_len();
+        self.transmit_stats.record_message();
+        if self.encryption.is_some() {
+            let segments = sga
+                .iter()
+                .map(|seg| (seg.addr(), self.zero_copy_seg(seg)))
+                .collect::<Vec<(&[u8], bool)>>();
+            return self.post_encrypted_segments(posting_idx, msg_id, conn_id, &segments);
+        }
 
         while sga_idx < sga.len() {
             let curr_seg = sga.get(sga_idx);
@@ -817,7 +1041,7 @@ impl DpdkConnection {
                     let dpdk_buffer = self.write_header_and_return_new_buffer(
                         conn_id,
                         msg_id,
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                        self.header_size(),
                         msg_size,
                     )?;
                     self.place_copy_buf_into_send_mbufs(
@@ -836,6 +1060,8 @@ impl DpdkConnection {
                             bail!("Failed to recover mbuf metadata for given buffer");
                         }
                     };
+                self.transmit_stats
+                    .record_zero_copy(original_mbuf_metadata.data_len());
                 self.place_zero_copy_buf_into_send_mbufs(
                     &mut original_mbuf_metadata,
                     posting_idx,
@@ -855,11 +1081,11 @@ impl DpdkConnection {
                     false => {
                         written_header = true;
                         (
-                            cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                            self.header_size(),
                             self.write_header_and_return_new_buffer(
                                 conn_id,
                                 msg_id,
-                                cornflakes_libos::utils::TOTAL_HEADER_SIZE + data_segment_length,
+                                self.header_size() + data_segment_length,
                                 msg_size,
                             )?,
                         )
@@ -879,8 +1105,10 @@ impl DpdkConnection {
                 };
 
                 for curr_seg_idx in sga_idx..curr_idx {
-                    copy_offset +=
-                        dpdk_buffer.copy_data(sga.get(curr_seg_idx).addr(), copy_offset)?;
+                    let seg = sga.get(curr_seg_idx);
+                    copy_offset += dpdk_buffer.copy_data(seg.addr(), copy_offset)?;
+                    self.transmit_stats
+                        .record_copied_segment(seg.len(), self.copying_threshold);
                 }
 
                 self.place_copy_buf_into_send_mbufs(
@@ -913,6 +1141,16 @@ impl DpdkConnection {
         let mut nb_segs = 0;
         let mut pkt_len = 0;
         let data_len = rc_sga.data_len();
+        self.transmit_stats.record_message();
+        if self.encryption.is_some() {
+            let segments = (0..rc_sga.len())
+                .map(|i| {
+                    let seg = rc_sga.get(i);
+                    (seg.addr(), self.zero_copy_rc_seg(seg))
+                })
+                .collect::<Vec<(&[u8], bool)>>();
+            return self.post_encrypted_segments(posting_idx, msg_id, conn_id, &segments);
+        }
 
         while sga_idx < rc_sga.len() {
             let curr_seg = rc_sga.get_mut(sga_idx);
@@ -921,7 +1159,7 @@ impl DpdkConnection {
                     let dpdk_buffer = self.write_header_and_return_new_buffer(
                         conn_id,
                         msg_id,
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                        self.header_size(),
                         data_len,
                     )?;
                     self.place_copy_buf_into_send_mbufs(
@@ -934,6 +1172,8 @@ impl DpdkConnection {
                 }
                 // make a zero copy segment
                 let mut original_mbuf_metadata = curr_seg.inner_datapath_pkt_mut().unwrap();
+                self.transmit_stats
+                    .record_zero_copy(original_mbuf_metadata.data_len());
                 self.place_zero_copy_buf_into_send_mbufs(
                     &mut original_mbuf_metadata,
                     posting_idx,
@@ -953,11 +1193,11 @@ impl DpdkConnection {
                     false => {
                         written_header = true;
                         (
-                            cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                            self.header_size(),
                             self.write_header_and_return_new_buffer(
                                 conn_id,
                                 msg_id,
-                                cornflakes_libos::utils::TOTAL_HEADER_SIZE + data_segment_length,
+                                self.header_size() + data_segment_length,
                                 data_len,
                             )?,
                         )
@@ -977,8 +1217,10 @@ impl DpdkConnection {
                 };
 
                 for curr_seg_idx in sga_idx..curr_idx {
-                    copy_offset +=
-                        dpdk_buffer.copy_data(rc_sga.get(curr_seg_idx).addr(), copy_offset)?;
+                    let seg = rc_sga.get(curr_seg_idx);
+                    copy_offset += dpdk_buffer.copy_data(seg.addr(), copy_offset)?;
+                    self.transmit_stats
+                        .record_copied_segment(seg.len(), self.copying_threshold);
                 }
 
                 self.place_copy_buf_into_send_mbufs(
@@ -1000,6 +1242,22 @@ impl DpdkConnection {
     }
 
     fn send_current_mbufs(&mut self, ct: u16) -> Result<()> {
+        if self.checksum_mode == ChecksumMode::Software {
+            for i in 0..ct {
+                unsafe { write_software_checksum(self.send_mbufs[0][i as usize])? };
+            }
+        }
+        if let Some(capture) = self.capture.as_mut() {
+            for i in 0..ct {
+                let head_mbuf = self.send_mbufs[0][i as usize];
+                capture.record_frame(unsafe { mbuf_chain_segments(head_mbuf) });
+            }
+        }
+        if let Some(vlan_id) = self.thread_context.get_vlan_id() {
+            for i in 0..ct {
+                unsafe { set_tx_vlan(self.send_mbufs[0][i as usize], vlan_id) };
+            }
+        }
         let mut num_sent: u16 = 0;
         for i in 0..ct {
             let head_mbuf = self.send_mbufs[0][i as usize];
@@ -1039,13 +1297,23 @@ impl DpdkConnection {
         buffer: &mut [u8],
         data_len: usize,
     ) -> Result<()> {
-        let hdr_bytes: &[u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE] =
+        let hdr_bytes: &[u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE_V6] =
             match &self.active_connections[conn_id as usize] {
                 Some((_, hdr_bytes_vec)) => hdr_bytes_vec,
                 None => {
                     bail!("Could not find address for connID");
                 }
             };
+        let our_ip = &self.thread_context.get_address_info().ip_addr;
+        if our_ip.is_ipv6() {
+            // the C fill-in only knows the ipv4 layout
+            return cornflakes_libos::utils::fill_in_hdrs(
+                buffer,
+                &hdr_bytes[..cornflakes_libos::utils::udp_header_size(our_ip)],
+                msg_id,
+                data_len,
+            );
+        }
         unsafe {
             fill_in_hdrs_dpdk(
                 buffer.as_mut_ptr() as _,
@@ -1072,39 +1340,42 @@ impl Datapath for DpdkConnection {
     /// IP not required as dpdk has ability to auto-detect the ethernet address
     fn parse_config_file(
         config_file: &str,
-        our_ip: &Ipv4Addr,
+        our_ip: &IpAddr,
     ) -> Result<Self::DatapathSpecificParams> {
         let (ip_to_mac, _mac_to_ip, udp_port, client_port) =
             parse_yaml_map(config_file).wrap_err("Failed to parse yaml mapping")?;
 
         let eal_init = parse_eal_init(config_file)?;
+        let vlan_id = parse_vlan_id(config_file)?;
 
         // since eal init has not been run yet, we cannot run dpdk get macaddr
         let eth_addr = match ip_to_mac.get(our_ip) {
             Some(e) => e.clone(),
             None => {
-                bail!("Could not find eth addr for passed in ipv4 addr {:?} in config_file ip_to_mac map: {:?}", our_ip, ip_to_mac);
+                bail!("Could not find eth addr for passed in ip addr {:?} in config_file ip_to_mac map: {:?}", our_ip, ip_to_mac);
             }
         };
 
         Ok(DpdkDatapathSpecificParams {
             eal_init: eal_init,
             dpdk_port: -1,
-            our_ip: our_ip.clone(),
+            our_ip: *our_ip,
             our_eth: eth_addr,
             starting_client_port: client_port,
             server_port: udp_port,
+            vlan_id,
+            static_neighbors: static_ipv4_entries(&ip_to_mac),
         })
     }
 
     fn compute_affinity(
         datapath_params: &Self::DatapathSpecificParams,
         num_queues: usize,
-        remote_ip: Option<Ipv4Addr>,
+        remote_ip: Option<IpAddr>,
         app_mode: AppMode,
     ) -> Result<Vec<AddressInfo>> {
         let my_eth = datapath_params.get_mac();
-        let my_starting_ip = datapath_params.get_ipv4();
+        let my_ip = datapath_params.get_ip();
         let my_port = match app_mode {
             AppMode::Server => datapath_params.get_server_port(),
             AppMode::Client => datapath_params.get_client_port(),
@@ -1116,13 +1387,27 @@ impl Datapath for DpdkConnection {
                 bail!("Currently, only 1 queue supported on server side");
             }
 
-            let addr_info = AddressInfo::new(my_port, my_starting_ip, my_eth);
+            let addr_info = AddressInfo::new(my_port, my_ip, my_eth);
             return Ok(vec![addr_info]);
         }
 
-        let server_ip = match remote_ip {
-            Some(x) => x,
-            None => {
+        let (my_starting_ip, server_ip) = match (my_ip, remote_ip) {
+            (IpAddr::V4(my_ip), Some(IpAddr::V4(server_ip))) => (my_ip, server_ip),
+            (IpAddr::V6(_), Some(IpAddr::V6(_))) => {
+                // the flow affinity computation only hashes ipv4 addresses
+                if num_queues > 1 {
+                    bail!("Currently, only 1 queue supported for ipv6 clients");
+                }
+                return Ok(vec![AddressInfo::new(my_port, my_ip, my_eth)]);
+            }
+            (my_ip, Some(server_ip)) => {
+                bail!(
+                    "Client address {:?} and server address {:?} are different ip versions",
+                    my_ip,
+                    server_ip
+                );
+            }
+            (_, None) => {
                 bail!("For client mode, must specify server ip to compute affinity");
             }
         };
@@ -1132,7 +1417,7 @@ impl Datapath for DpdkConnection {
         let mut recv_addrs: Vec<AddressInfo> = Vec::with_capacity(num_queues);
         let in_recv_addrs = |ip: &[u8; 4], port: u16, ref_addrs: &Vec<AddressInfo>| -> bool {
             for current_addr in ref_addrs.iter() {
-                if current_addr.ipv4_addr.octets() == *ip && current_addr.udp_port == port {
+                if current_addr.ip_addr == IpAddr::from(*ip) && current_addr.udp_port == port {
                     return true;
                 }
             }
@@ -1198,6 +1483,13 @@ impl Datapath for DpdkConnection {
             nb_ports
         );
         datapath_params.set_physical_port(nb_ports - 1);
+        if let Some(vlan_id) = datapath_params.get_vlan_id() {
+            ensure!(
+                unsafe { tx_vlan_insert_supported(datapath_params.get_physical_port()?) },
+                "Port cannot insert VLAN tags, so cannot send on VLAN {}",
+                vlan_id
+            );
+        }
 
         // for each core, initialize a native memory pool and external buffer memory pool
         let mut ret: Vec<Self::PerThreadContext> = Vec::with_capacity(num_queues);
@@ -1216,6 +1508,7 @@ impl Datapath for DpdkConnection {
                 recv_mempool: recv_mempool,
                 extbuf_mempool: extbuf_mempool,
                 physical_port: datapath_params.get_physical_port()?,
+                vlan_id: datapath_params.get_vlan_id(),
             });
         }
 
@@ -1279,7 +1572,7 @@ impl Datapath for DpdkConnection {
     }
 
     fn per_thread_init(
-        _datapath_params: Self::DatapathSpecificParams,
+        datapath_params: Self::DatapathSpecificParams,
         context: Self::PerThreadContext,
         mode: AppMode,
     ) -> Result<Self>
@@ -1302,6 +1595,13 @@ impl Datapath for DpdkConnection {
         ))?;
         let tx_mempool = MempoolInfo::new(mempool)?;
         let allocator = MemoryPoolAllocator::new(rx_mempool, tx_mempool)?;
+        let address_info = context.get_address_info().clone();
+        let neighbors = NeighborTable::new(
+            address_info.ether_addr,
+            address_info.ipv4_addr().into_iter().collect(),
+            datapath_params.static_neighbors,
+            NeighborConfig::default(),
+        )?;
 
         Ok(DpdkConnection {
             thread_context: context,
@@ -1314,12 +1614,25 @@ impl Datapath for DpdkConnection {
             max_segments: 33,
             recv_mbufs: [ptr::null_mut(); RECEIVE_BURST_SIZE],
             send_mbufs: [[ptr::null_mut(); SEND_BURST_SIZE]; MAX_SCATTERS],
+            transmit_stats: TransmitStats::default(),
+            checksum_mode: ChecksumMode::Software,
+            receive_stats: ReceiveStats::default(),
+            capture: None,
+            encryption: None,
+            neighbors,
+            deferred_pkts: Vec::default(),
         })
     }
 
     fn connect(&mut self, addr: AddressInfo) -> Result<ConnID> {
         if self.address_to_conn_id.contains_key(&addr) {
             return Ok(*self.address_to_conn_id.get(&addr).unwrap());
+        } else if addr.is_unresolved() {
+            let resolved_addr = self.resolve_ether_addr(addr)?;
+            let conn_id = self.connect(resolved_addr)?;
+            // later connects with the unresolved address reuse the connection
+            self.address_to_conn_id.insert(addr, conn_id);
+            return Ok(conn_id);
         } else {
             if self.address_to_conn_id.len() >= MAX_CONCURRENT_CONNECTIONS {
                 bail!("too many concurrent connections; cannot connect to more");
@@ -1337,8 +1650,8 @@ impl Datapath for DpdkConnection {
             }
             match idx {
                 Some(i) => {
-                    let mut bytes: [u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE] =
-                        [0u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE];
+                    let mut bytes: [u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE_V6] =
+                        [0u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE_V6];
                     let header_info = cornflakes_libos::utils::HeaderInfo::new(
                         self.thread_context.get_address_info().clone(),
                         addr.clone(),
@@ -1346,26 +1659,7 @@ impl Datapath for DpdkConnection {
                     // write in the header to these bytes, assuming data length of 0
                     // data length is updated at runtime and checksums are updated on specific
                     // transmissions
-                    cornflakes_libos::utils::write_eth_hdr(
-                        &header_info,
-                        &mut bytes[0..cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE],
-                    )?;
-                    cornflakes_libos::utils::write_ipv4_hdr(
-                        &header_info,
-                        &mut bytes[cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                            ..(cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                                + cornflakes_libos::utils::IPV4_HEADER2_SIZE)],
-                        42,
-                    )?;
-                    cornflakes_libos::utils::write_udp_hdr(
-                        &header_info,
-                        &mut bytes[(cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                            + cornflakes_libos::utils::IPV4_HEADER2_SIZE)
-                            ..(cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                                + cornflakes_libos::utils::IPV4_HEADER2_SIZE
-                                + cornflakes_libos::utils::UDP_HEADER2_SIZE)],
-                        42,
-                    )?;
+                    cornflakes_libos::utils::write_hdr_template(&header_info, &mut bytes)?;
                     self.active_connections[i] = Some((addr, bytes));
                     return Ok(i);
                 }
@@ -1379,6 +1673,11 @@ impl Datapath for DpdkConnection {
     fn push_buffers_with_copy(&mut self, pkts: &[(MsgID, ConnID, &[u8])]) -> Result<()> {
         for (i, (msg_id, conn_id, buf)) in pkts.iter().enumerate() {
             self.insert_into_outgoing_map(*msg_id, *conn_id);
+            if self.encryption.is_some() {
+                self.transmit_stats.record_message();
+                self.post_encrypted_segments(i, *msg_id, *conn_id, &[(*buf, false)])?;
+                continue;
+            }
             // allocate buffer to copy data into
             let mut dpdk_buffer = match self.allocator.allocate_tx_buffer().wrap_err(format!(
                 "Could not allocate buf to copy into for buf size {}, packet id {}",
@@ -1395,11 +1694,12 @@ impl Datapath for DpdkConnection {
                 }
             };
             // write header into the dpdk buffer
-            let mut mutable_slice =
-                dpdk_buffer.mutable_slice(0, cornflakes_libos::utils::TOTAL_HEADER_SIZE)?;
+            let mut mutable_slice = dpdk_buffer.mutable_slice(0, self.header_size())?;
             self.copy_hdr(*conn_id, *msg_id, &mut mutable_slice, buf.len())
                 .wrap_err("Could not copy header into mutable slice")?;
-            dpdk_buffer.copy_data(buf, cornflakes_libos::utils::TOTAL_HEADER_SIZE)?;
+            dpdk_buffer.copy_data(buf, self.header_size())?;
+            self.transmit_stats.record_message();
+            self.transmit_stats.record_copy(buf.len());
 
             // turn dpdk buffer back into metadata object
             let mut metadata_mbuf = match self.get_metadata(dpdk_buffer)? {
@@ -1410,7 +1710,7 @@ impl Datapath for DpdkConnection {
             };
             // update metadata on packet required to send out
             metadata_mbuf.update_metadata(
-                (buf.len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE) as _,
+                (buf.len() + self.header_size()) as _,
                 ptr::null_mut(),
                 None,
                 false,
@@ -1433,8 +1733,18 @@ impl Datapath for DpdkConnection {
     where
         Self: Sized,
     {
+        if self.encryption.is_some() {
+            // received payloads were decrypted in place, so echoes are sealed into new buffers
+            let bufs = pkts
+                .iter()
+                .map(|pkt| (pkt.msg_id(), pkt.conn_id(), pkt.seg(0).as_ref()))
+                .collect::<Vec<(MsgID, ConnID, &[u8])>>();
+            return self.push_buffers_with_copy(&bufs);
+        }
         for (i, pkt) in pkts.iter().enumerate() {
+            self.transmit_stats.record_message();
             for (scatter_index, dpdk_buffer) in pkt.iter().enumerate() {
+                self.transmit_stats.record_zero_copy(dpdk_buffer.data_len());
                 let mbuf = dpdk_buffer.get_inner();
                 tracing::debug!(
                     "Echoing packet with id {}, mbuf addr {:?}, refcnt {}",
@@ -1497,6 +1807,12 @@ impl Datapath for DpdkConnection {
     where
         Self: Sized,
     {
+        if !self.deferred_pkts.is_empty() {
+            return Ok(deferred_with_durations(
+                std::mem::take(&mut self.deferred_pkts),
+                &mut self.outgoing_window,
+            ));
+        }
         let num_received = unsafe {
             rte_eth_rx_burst(
                 self.thread_context.get_physical_port(),
@@ -1552,40 +1868,10 @@ impl Datapath for DpdkConnection {
     where
         Self: Sized,
     {
-        let num_received = unsafe {
-            rte_eth_rx_burst(
-                self.thread_context.get_physical_port(),
-                self.thread_context.get_queue_id(),
-                self.recv_mbufs.as_mut_ptr(),
-                RECEIVE_BURST_SIZE as _,
-            )
-        };
-
-        if num_received == 0 {
-            return Ok(vec![]);
+        if !self.deferred_pkts.is_empty() {
+            return Ok(std::mem::take(&mut self.deferred_pkts));
         }
-
-        let mut ret: Vec<ReceivedPkt<Self>> = Vec::with_capacity(RECEIVE_BURST_SIZE);
-
-        for i in 0..num_received as usize {
-            if let Some(received_pkt) = self
-                .check_received_pkt(i)
-                .wrap_err(format!("Error checking received pkt {}", i))?
-            {
-                tracing::debug!(
-                    "Received pkt with msg ID {}, conn ID {}",
-                    received_pkt.msg_id(),
-                    received_pkt.conn_id()
-                );
-                ret.push(received_pkt);
-            } else {
-                unsafe {
-                    rte_pktmbuf_free(self.recv_mbufs[i]);
-                }
-            }
-            self.recv_mbufs[i] = ptr::null_mut();
-        }
-        Ok(ret)
+        self.receive_pkts()
     }
 
     fn timed_out(&self, time_out: Duration) -> Result<Vec<(MsgID, ConnID)>> {
@@ -1604,6 +1890,20 @@ impl Datapath for DpdkConnection {
     }
 
     fn allocate(&mut self, size: usize) -> Result<Option<Self::DatapathBuffer>> {
+        if let Some(buf) = self.allocator.allocate_buffer(size)? {
+            return Ok(Some(buf));
+        }
+        let config = self.allocator.config();
+        if !self.allocator.can_grow(config.mempool_bytes()) {
+            return Ok(None);
+        }
+        tracing::info!("Growing mempools of size {}", size);
+        self.add_memory_pool_with_size(
+            size,
+            config.num_pages,
+            config.num_registrations,
+            config.register_at_start,
+        )?;
         self.allocator.allocate_buffer(size)
     }
 
@@ -1688,8 +1988,39 @@ impl Datapath for DpdkConnection {
         self.allocator.has_mempool(size)
     }
 
+    fn get_mempool_config(&self) -> MempoolConfig {
+        self.allocator.config()
+    }
+
+    fn set_mempool_config(&mut self, config: MempoolConfig) -> Result<()> {
+        self.allocator.set_config(config)
+    }
+
+    fn mempool_size_class(&self, size: usize) -> usize {
+        self.allocator.size_class(size)
+    }
+
+    fn allocator_memory_stats(&self) -> AllocatorMemoryStats {
+        self.allocator.memory_stats()
+    }
+
+    fn remove_memory_pool(&mut self, id: MempoolID) -> Result<bool> {
+        // dropping the mempool info frees the underlying dpdk mempool
+        Ok(self.allocator.remove_mempool(id)?.is_some())
+    }
+
+    fn remove_idle_mempools(&mut self) -> Result<Vec<MempoolID>> {
+        let mut removed = Vec::default();
+        for id in self.allocator.idle_mempool_ids().into_iter() {
+            if self.remove_memory_pool(id)? {
+                removed.push(id);
+            }
+        }
+        Ok(removed)
+    }
+
     fn header_size(&self) -> usize {
-        cornflakes_libos::utils::TOTAL_HEADER_SIZE
+        self.thread_context.get_address_info().header_size()
     }
 
     fn timer_hz(&self) -> u64 {
@@ -1720,4 +2051,57 @@ impl Datapath for DpdkConnection {
         self.max_segments
     }
     fn set_inline_mode(&mut self, _inline_mode: InlineMode) {}
+
+    fn transmit_stats(&self) -> TransmitStats {
+        self.transmit_stats
+    }
+
+    fn reset_transmit_stats(&mut self) {
+        self.transmit_stats = TransmitStats::default();
+    }
+
+    fn set_checksum_mode(&mut self, mode: ChecksumMode) -> Result<()> {
+        ensure!(
+            mode != ChecksumMode::Offload,
+            "Dpdk datapath does not set checksum offload flags; use software checksums"
+        );
+        ensure!(
+            mode != ChecksumMode::Disabled
+                || self.thread_context.get_address_info().ip_addr.is_ipv4(),
+            "UDP checksums are required over ipv6"
+        );
+        self.checksum_mode = mode;
+        Ok(())
+    }
+
+    fn get_checksum_mode(&self) -> ChecksumMode {
+        self.checksum_mode
+    }
+
+    fn receive_stats(&self) -> ReceiveStats {
+        self.receive_stats
+    }
+
+    fn reset_receive_stats(&mut self) {
+        self.receive_stats = ReceiveStats::default();
+    }
+
+    fn set_packet_capture(&mut self, capture: PacketCapture) -> Result<()> {
+        self.capture = Some(capture);
+        Ok(())
+    }
+
+    fn set_encryption(&mut self, config: EncryptionConfig) -> Result<()> {
+        tracing::info!(
+            algorithm = ?config.algorithm,
+            copying_threshold = self.copying_threshold,
+            "Encrypting payloads; segments above the copying threshold are copied as well"
+        );
+        self.encryption = Some(TransportEncryption::new(
+            config,
+            self.thread_context.address_info,
+            self.mode,
+        ));
+        Ok(())
+    }
 }
@@ -1,4 +1,4 @@
-use color_eyre::eyre::{bail, Result};
+use color_eyre::eyre::{bail, ensure, Result};
 use std::{fs::read_to_string, path::Path};
 use yaml_rust::{Yaml, YamlLoader};
 
@@ -33,3 +33,30 @@ pub fn parse_eal_init(config_path: &str) -> Result<Vec<String>> {
     }
     Ok(args)
 }
+
+/// Reads the optional `vlan_id` of the `dpdk` section: the VLAN to tag transmitted frames with.
+pub fn parse_vlan_id(config_path: &str) -> Result<Option<u16>> {
+    let file_str = read_to_string(Path::new(&config_path))?;
+    let yamls = match YamlLoader::load_from_str(&file_str) {
+        Ok(docs) => docs,
+        Err(e) => {
+            bail!("Could not parse config yaml: {:?}", e);
+        }
+    };
+
+    let vlan_id = match &yamls[0]["dpdk"]["vlan_id"] {
+        Yaml::BadValue => {
+            return Ok(None);
+        }
+        Yaml::Integer(id) => *id,
+        entry => {
+            bail!("Yaml config dpdk vlan_id is not an integer: {:?}", entry);
+        }
+    };
+    ensure!(
+        vlan_id > 0 && vlan_id < 4095,
+        "Yaml config dpdk vlan_id {} is not a valid VLAN id",
+        vlan_id
+    );
+    Ok(Some(vlan_id as u16))
+}
@@ -14,7 +14,7 @@ use eui48::MacAddress;
 use hashbrown::HashMap;
 use std::{
     mem::MaybeUninit,
-    net::Ipv4Addr,
+    net::{IpAddr, Ipv4Addr},
     ptr, slice,
     sync::{Arc, Mutex},
     time::{Duration, Instant},
@@ -169,7 +169,7 @@ pub struct DPDKConnection {
     /// dpdk_port
     dpdk_port: u16,
     /// Maps ip addresses to corresponding mac addresses.
-    ip_to_mac: HashMap<Ipv4Addr, MacAddress>,
+    ip_to_mac: HashMap<IpAddr, MacAddress>,
     /// Current window of outgoing packets mapped to start time.
     outgoing_window: HashMap<MsgID, Instant>,
     /// Empty mempool for allocating external buffers.
@@ -318,7 +318,7 @@ impl DPDKConnection {
         }
     }
 
-    fn start_entry(&mut self, timer_name: &str, id: MsgID, src: Ipv4Addr) -> Result<()> {
+    fn start_entry(&mut self, timer_name: &str, id: MsgID, src: IpAddr) -> Result<()> {
         let mut hist = match self.timers.contains_key(timer_name) {
             true => match self.timers.get(timer_name).unwrap().lock() {
                 Ok(h) => h,
@@ -332,7 +332,7 @@ impl DPDKConnection {
         Ok(())
     }
 
-    fn end_entry(&mut self, timer_name: &str, id: MsgID, dst: Ipv4Addr) -> Result<()> {
+    fn end_entry(&mut self, timer_name: &str, id: MsgID, dst: IpAddr) -> Result<()> {
         let mut hist = match self.timers.contains_key(timer_name) {
             true => match self.timers.get(timer_name).unwrap().lock() {
                 Ok(h) => h,
@@ -455,7 +455,7 @@ impl Datapath for DPDKConnection {
         config_path: &str,
         num_cores: usize,
         app_mode: AppMode,
-        remote_ip: Option<Ipv4Addr>,
+        remote_ip: Option<IpAddr>,
     ) -> Result<(u16, Vec<(Self::RxPacketAllocator, utils::AddressInfo)>)> {
         let (mempools, nb_ports) =
             wrapper::dpdk_init(config_path, num_cores).wrap_err("Failure in dpdk init.")?;
@@ -508,16 +508,31 @@ impl Datapath for DPDKConnection {
 
         // on client side, the server ip MUST be specified
         let server_ip = match remote_ip {
-            Some(s) => s,
+            Some(IpAddr::V4(s)) => s,
+            Some(IpAddr::V6(s)) => {
+                bail!(
+                    "Flow affinity is only computed for IPv4 servers; got {:?}",
+                    s
+                );
+            }
             None => {
                 bail!("For client app mode, remote server IP must be specified.");
             }
         };
+        let my_ipv4_addr = match my_ip_addr {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(ip) => {
+                bail!(
+                    "Flow affinity is only computed for IPv4 clients; got {:?}",
+                    ip
+                );
+            }
+        };
 
         let mut recv_addrs: Vec<utils::AddressInfo> = Vec::with_capacity(num_cores);
         let in_recv_addrs = |ip: &[u8; 4], ref_addrs: &Vec<utils::AddressInfo>| -> bool {
             for current_addr in ref_addrs.iter() {
-                if current_addr.ipv4_addr.octets() == *ip {
+                if current_addr.ip_addr == IpAddr::from(*ip) {
                     return true;
                 }
             }
@@ -525,7 +540,7 @@ impl Datapath for DPDKConnection {
         };
 
         for queue_id in 0..num_cores as u16 {
-            let mut cur_octets = my_ip_addr.octets();
+            let mut cur_octets = my_ipv4_addr.octets();
             let cur_port = client_port;
             while dpdk_call!(compute_flow_affinity(
                 ip_from_octets(&cur_octets),
@@ -616,7 +631,7 @@ impl Datapath for DPDKConnection {
         match self.mode {
             AppMode::Server => {
                 if cfg!(feature = "timers") {
-                    self.end_entry(PROCESSING_TIMER, buf.0, addr.ipv4_addr)?;
+                    self.end_entry(PROCESSING_TIMER, buf.0, addr.ip_addr)?;
                 }
             }
             AppMode::Client => {
@@ -749,7 +764,7 @@ impl Datapath for DPDKConnection {
             AppMode::Server => {
                 if cfg!(feature = "timers") {
                     for (sga, addr) in sgas.iter() {
-                        self.end_entry(PROCESSING_TIMER, sga.get_id(), addr.ipv4_addr)?;
+                        self.end_entry(PROCESSING_TIMER, sga.get_id(), addr.ip_addr)?;
                     }
                 }
             }
@@ -859,7 +874,7 @@ impl Datapath for DPDKConnection {
             AppMode::Server => {
                 if cfg!(feature = "timers") {
                     for (sga, addr) in sgas.iter() {
-                        self.end_entry(PROCESSING_TIMER, sga.get_id(), addr.ipv4_addr)?;
+                        self.end_entry(PROCESSING_TIMER, sga.get_id(), addr.ip_addr)?;
                     }
                 }
             }
@@ -909,7 +924,7 @@ impl Datapath for DPDKConnection {
         // Debugging end to end processing time
         if cfg!(feature = "timers") && self.mode == AppMode::Server {
             for (_, (msg_id, addr_info, _data_len)) in received.iter() {
-                self.start_entry(PROCESSING_TIMER, *msg_id, addr_info.ipv4_addr.clone())?;
+                self.start_entry(PROCESSING_TIMER, *msg_id, addr_info.ip_addr.clone())?;
             }
         }
 
@@ -1026,13 +1041,13 @@ impl Datapath for DPDKConnection {
     /// Returns a HeaderInfo struct with udp, ethernet and ipv4 header information.
     ///
     /// Arguments:
-    /// * dst_addr - Ipv4Addr that is the destination.
+    /// * dst_addr - IpAddr that is the destination.
     ///
     /// Returns:
     ///  * AddressInfo - struct with destination mac, ip address and udp port
     fn get_outgoing_addr_from_ip(
         &self,
-        dst_addr: &Ipv4Addr,
+        dst_addr: &IpAddr,
         port: u16,
     ) -> Result<utils::AddressInfo> {
         match self.ip_to_mac.get(dst_addr) {
@@ -12,7 +12,7 @@ use hashbrown::HashMap;
 use hdrhistogram::Histogram;
 use std::{
     io::Write,
-    net::Ipv4Addr,
+    net::IpAddr,
     /*rc::Rc,*/ slice,
     sync::{Arc, Mutex},
     time::{Duration, Instant},
@@ -27,7 +27,7 @@ pub struct EchoClient<'a, 'b> {
     retries: usize,
     buffer: Vec<u8>,
     sga: Cornflake<'a, 'b>,
-    server_ip: Ipv4Addr,
+    server_ip: IpAddr,
     rtts: Histogram<u64>,
     external_memory: Option<mem::MmapMetadata>,
 }
@@ -35,7 +35,7 @@ pub struct EchoClient<'a, 'b> {
 impl<'a, 'b> EchoClient<'a, 'b> {
     pub fn new(
         size: usize,
-        server_ip: Ipv4Addr,
+        server_ip: IpAddr,
         zero_copy: bool,
         payload: &'b [u8],
     ) -> Result<EchoClient<'a, 'b>> {
@@ -117,7 +117,7 @@ impl<'a, 'b> ClientSM for EchoClient<'a, 'b> {
         Ok(())
     }
 
-    fn server_ip(&self) -> Ipv4Addr {
+    fn server_ip(&self) -> IpAddr {
         self.server_ip
     }
 
@@ -12,7 +12,7 @@ use std::{
     ffi::CString,
     io::Write,
     mem::{zeroed, MaybeUninit},
-    net::Ipv4Addr,
+    net::{IpAddr, Ipv4Addr},
     process::exit,
     ptr, slice,
     str::FromStr,
@@ -194,6 +194,15 @@ struct ThreadStats {
     pub histogram: HistogramWrapper,
 }
 
+/// Looks up our own address; the headers built here are IPv4-only.
+fn my_ipv4_addr(mac_to_ip: &HashMap<MacAddress, IpAddr>, my_mac: &MacAddress) -> Result<Ipv4Addr> {
+    match mac_to_ip.get(my_mac) {
+        Some(IpAddr::V4(ip)) => Ok(*ip),
+        Some(IpAddr::V6(ip)) => bail!("Fast echo only supports IPv4; got {:?}", ip),
+        None => bail!("No ip address for my mac address {:?}", my_mac),
+    }
+}
+
 pub fn do_client(
     rate: u64,
     total_time: u64,
@@ -226,11 +235,11 @@ pub fn do_client(
     let my_mac = MacAddress::from_bytes(&my_eth.addr_bytes)?;
 
     // what is their ethernet_addr (should be an rte_ether_addr struct)
-    let server_eth_addr_uninit = get_ether_addr(ip_to_mac.get(server_ip).unwrap());
+    let server_eth_addr_uninit = get_ether_addr(ip_to_mac.get(&IpAddr::V4(*server_ip)).unwrap());
     let server_eth = unsafe { server_eth_addr_uninit.assume_init() };
 
     // what is my IpAddr
-    let my_ip_addr = mac_to_ip.get(&my_mac).unwrap();
+    let my_ip_addr = my_ipv4_addr(&mac_to_ip, &my_mac)?;
     let octets = my_ip_addr.octets();
 
     // what is their IpAddr
@@ -432,7 +441,7 @@ pub fn do_server(
     let mut my_eth = wrapper::get_my_macaddr(port)?;
     let my_mac = MacAddress::from_bytes(&my_eth.addr_bytes)?;
     // what is my IpAddr
-    let my_ip_addr = mac_to_ip.get(&my_mac).unwrap();
+    let my_ip_addr = my_ipv4_addr(&mac_to_ip, &my_mac)?;
     let octets = my_ip_addr.octets();
     let my_ip: u32 = dpdk_call!(make_ip(octets[0], octets[1], octets[2], octets[3]));
 
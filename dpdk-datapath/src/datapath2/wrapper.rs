@@ -993,7 +993,7 @@ fn check_valid_packet(
 
     let ipv4_hdr_slice = mbuf_slice2!(pkt, utils::ETHERNET2_HEADER2_SIZE, utils::IPV4_HEADER2_SIZE);
 
-    let src_ip = match utils::check_ipv4_hdr(ipv4_hdr_slice, &my_addr_info.ipv4_addr) {
+    let src_ip = match utils::check_ip_hdr(ipv4_hdr_slice, &my_addr_info.ip_addr) {
         Ok((ip, _)) => ip,
         Err(_) => {
             return None;
@@ -1241,8 +1241,8 @@ mod tests {
             utils::IPV4_HEADER2_SIZE
         );
 
-        let (src_ip, _) = utils::check_ipv4_hdr(ipv4_hdr_slice, &dst_info.ipv4_addr).unwrap();
-        assert!(src_ip == src_info.ipv4_addr);
+        let (src_ip, _) = utils::check_ip_hdr(ipv4_hdr_slice, &dst_info.ip_addr).unwrap();
+        assert!(src_ip == src_info.ip_addr);
 
         let udp_hdr_slice = mbuf_slice2!(
             mbuf,
@@ -418,6 +418,18 @@ void set_lkey_not_present_(struct rte_mbuf *packet) {
     data->lkey_present = 0;
 }
 
+void set_tx_vlan_(struct rte_mbuf *packet, uint16_t vlan_tci) {
+    // the NIC inserts the 802.1Q tag (see RTE_ETH_TX_OFFLOAD_VLAN_INSERT)
+    packet->ol_flags |= RTE_MBUF_F_TX_VLAN;
+    packet->vlan_tci = vlan_tci;
+}
+
+bool tx_vlan_insert_supported_(uint16_t port_id) {
+    struct rte_eth_dev_info dev_info = {};
+    rte_eth_dev_info_get(port_id, &dev_info);
+    return (dev_info.tx_offload_capa & RTE_ETH_TX_OFFLOAD_VLAN_INSERT) != 0;
+}
+
 void set_refers_to_another_(struct rte_mbuf *packet, uint16_t val) {
     struct tx_pktmbuf_priv *data = tx_pktmbuf_get_priv(packet);
     data->refers_to_another = val;
@@ -696,6 +708,7 @@ void eth_dev_configure_ice_(uint16_t port_id, uint16_t rx_rings, uint16_t tx_rin
     port_conf.rx_adv_conf.rss_conf.rss_key_len = 40;
     port_conf.rx_adv_conf.rss_conf.rss_hf = RTE_ETH_RSS_NONFRAG_IPV4_UDP;
     port_conf.txmode.offloads = RTE_ETH_TX_OFFLOAD_IPV4_CKSUM | RTE_ETH_TX_OFFLOAD_UDP_CKSUM;
+    port_conf.txmode.offloads |= dev_info.tx_offload_capa & RTE_ETH_TX_OFFLOAD_VLAN_INSERT;
     port_conf.txmode.mq_mode = RTE_ETH_MQ_TX_NONE;
 
     printf("port_id: %u, rx_rings; %u, tx_rings: %u\n", port_id, rx_rings, tx_rings);
@@ -719,6 +732,7 @@ void eth_dev_configure_(uint16_t port_id, uint16_t rx_rings, uint16_t tx_rings)
     port_conf.rx_adv_conf.rss_conf.rss_key_len = 40;
     port_conf.rx_adv_conf.rss_conf.rss_hf = RTE_ETH_RSS_UDP | RTE_ETH_RSS_IP;
     port_conf.txmode.offloads = RTE_ETH_TX_OFFLOAD_IPV4_CKSUM | RTE_ETH_TX_OFFLOAD_UDP_CKSUM;
+    port_conf.txmode.offloads |= dev_info.tx_offload_capa & RTE_ETH_TX_OFFLOAD_VLAN_INSERT;
     port_conf.txmode.mq_mode = RTE_ETH_MQ_TX_NONE;
 
     printf("port_id: %u, rx_rings; %u, tx_rings: %u\n", port_id, rx_rings, tx_rings);
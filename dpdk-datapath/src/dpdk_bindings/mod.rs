@@ -78,6 +78,10 @@ extern "C" {
 
     fn set_lkey_not_present_(packet: *mut rte_mbuf);
 
+    fn set_tx_vlan_(packet: *mut rte_mbuf, vlan_tci: u16);
+
+    fn tx_vlan_insert_supported_(port_id: u16) -> bool;
+
     fn set_refers_to_another_(packet: *mut rte_mbuf, val: u16);
 
     fn make_ip_(a: u8, b: u8, c: u8, d: u8) -> u32;
@@ -361,6 +365,16 @@ pub unsafe fn set_lkey_not_present(packet: *mut rte_mbuf) {
     set_lkey_not_present_(packet);
 }
 
+#[inline]
+pub unsafe fn set_tx_vlan(packet: *mut rte_mbuf, vlan_tci: u16) {
+    set_tx_vlan_(packet, vlan_tci);
+}
+
+#[inline]
+pub unsafe fn tx_vlan_insert_supported(port_id: u16) -> bool {
+    tx_vlan_insert_supported_(port_id)
+}
+
 #[inline]
 pub unsafe fn set_refers_to_another(packet: *mut rte_mbuf, val: u16) {
     set_refers_to_another_(packet, val);
@@ -296,6 +296,11 @@ where
     ) -> Result<bool> {
         // if in debug mode, check whether the bytes are what they should be
         tracing::debug!(id = sga.msg_id(), size = sga.data_len(), "Received sga");
+        if sga.is_overloaded_reply() {
+            // the server shed the request; it was not echoed
+            tracing::debug!(id = sga.msg_id(), "Received overloaded reply");
+            return Ok(false);
+        }
         if cfg!(debug_assertions) {
             if !self.cerealizer.check_echoed_payload(
                 &sga,
@@ -1,10 +1,12 @@
 use color_eyre::eyre::{bail, Result};
 use cornflakes_libos::{
-    datapath::{InlineMode, PushBufType},
+    admission::AdmissionConfig,
+    datapath::{ChecksumMode, InlineMode, PushBufType},
     loadgen::request_schedule::DistributionType,
+    rss::QueueTargets,
 };
 use cornflakes_utils::{AppMode, SerializationType, SimpleMessageType, TraceLevel};
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 use structopt::StructOpt;
 
 #[macro_export]
@@ -19,6 +21,15 @@ macro_rules! run_server (
 
         connection.set_copying_threshold($opt.copying_threshold);
         connection.set_inline_mode($opt.inline_mode);
+        if let Some(checksum_mode) = $opt.checksum_mode {
+            connection.set_checksum_mode(checksum_mode)?;
+        }
+        if let Some(capture_pcap) = &$opt.capture_pcap {
+            connection.set_packet_capture(cornflakes_libos::pcap::PacketCapture::new(capture_pcap, cornflakes_libos::pcap::DEFAULT_CAPTURE_QUEUE_DEPTH)?)?;
+        }
+        if let Some(encryption) = cornflakes_libos::encryption::EncryptionConfig::from_config_file(&$opt.config_file)? {
+            connection.set_encryption(encryption)?;
+        }
         tracing::info!(threshold = $opt.copying_threshold, "Setting zero-copy copying threshold");
 
         // init echo server
@@ -31,10 +42,11 @@ macro_rules! run_server (
         }
 
         echo_server.write_ready($opt.ready_file.clone())?;
+        let admission_controller = admission_config(&$opt).map(cornflakes_libos::admission::AdmissionController::new);
         if is_baseline {
-            echo_server.run_state_machine_baseline(&mut connection)?;
+            echo_server.run_state_machine_baseline_with_admission_control(&mut connection, admission_controller)?;
         } else {
-            echo_server.run_state_machine(&mut connection)?;
+            echo_server.run_state_machine_with_controllers(&mut connection, None, admission_controller)?;
         }
     }
 );
@@ -44,6 +56,12 @@ macro_rules! run_client (
     ($serializer: ty, $datapath: ty, $opt: ident) => {
         let server_addr = cornflakes_utils::parse_server_addr(&$opt.config_file, &$opt.server_ip)?;
         let mut datapath_params = <$datapath as Datapath>::parse_config_file(&$opt.config_file, &$opt.our_ip)?;
+        if let Some(num_server_queues) = $opt.num_server_queues {
+            <$datapath as Datapath>::set_server_queue_assignment(
+                &mut datapath_params,
+                cornflakes_libos::rss::ServerQueueAssignment::new(num_server_queues, $opt.server_queue_targets.clone()),
+            )?;
+        }
         let addresses = <$datapath as Datapath>::compute_affinity(
                 &datapath_params,
                 $opt.num_threads,
@@ -68,7 +86,7 @@ macro_rules! run_client (
             .enumerate()
         {
         let server_addr_clone =
-            cornflakes_libos::utils::AddressInfo::new(server_addr.2, server_addr.1.clone(), server_addr.0.clone());
+            cornflakes_libos::utils::AddressInfo::from_known_host(server_addr.2, server_addr.1.clone(), server_addr.0);
             let datapath_params_clone = datapath_params.clone();
             let message_type = $opt.message_type.clone();
             let request_sizes = vec![(message_type, get_equal_fields( message_type, $opt.size))];
@@ -94,6 +112,9 @@ macro_rules! run_client (
                 )?;
 
                 connection.set_copying_threshold(std::usize::MAX);
+                if let Some(encryption) = cornflakes_libos::encryption::EncryptionConfig::from_config_file(&opt_clone.config_file)? {
+                    connection.set_encryption(encryption)?;
+                }
 
                 let mut client: EchoClient<$serializer, $datapath> =
                     EchoClient::new(server_addr_clone, request_sizes, max_num_requests, &connection)?;
@@ -138,6 +159,15 @@ fn is_echo(opt: &DsEchoOpt) -> bool {
         || opt.serialization == SerializationType::TwoCopyBaseline
 }
 
+/// Admission control settings for each server thread, if rate limiting or load shedding is on.
+pub fn admission_config(opt: &DsEchoOpt) -> Option<AdmissionConfig> {
+    AdmissionConfig::from_flags(
+        opt.admission_rate,
+        opt.admission_burst,
+        opt.admission_queue_depth,
+    )
+}
+
 pub fn is_baseline(opt: &DsEchoOpt) -> bool {
     !(opt.serialization == SerializationType::CornflakesOneCopyDynamic
         || opt.serialization == SerializationType::CornflakesDynamic)
@@ -180,13 +210,13 @@ pub struct DsEchoOpt {
     #[structopt(long = "mode", help = "App mode: client or server")]
     pub mode: AppMode,
     #[structopt(long = "our_ip", help = "Our IP Address", default_value = "127.0.0.1")]
-    pub our_ip: Ipv4Addr,
+    pub our_ip: IpAddr,
     #[structopt(
         long = "server_ip",
         help = "Our IP Address",
         default_value = "127.0.0.1"
     )]
-    pub server_ip: Ipv4Addr,
+    pub server_ip: IpAddr,
     #[structopt(
         short = "r",
         long = "rate",
@@ -219,6 +249,17 @@ pub struct DsEchoOpt {
         default_value = "1"
     )]
     pub num_threads: usize,
+    #[structopt(
+        long = "num_server_queues",
+        help = "Number of server queues to spread client threads across with RSS. If unset, the datapath picks client ports without regard to server queues."
+    )]
+    pub num_server_queues: Option<usize>,
+    #[structopt(
+        long = "server_queue_targets",
+        help = "With num_server_queues, the server queue each client thread targets: even, or a comma-separated list of queues that threads cycle through (e.g., 0 sends every thread to queue 0).",
+        default_value = "even"
+    )]
+    pub server_queue_targets: QueueTargets,
     #[structopt(
         long = "num_clients",
         help = "Total number of clients",
@@ -240,6 +281,32 @@ pub struct DsEchoOpt {
         default_value = "nothing"
     )]
     pub inline_mode: InlineMode,
+    #[structopt(
+        long = "checksum_mode",
+        help = "UDP checksum mode (disabled, software or offload). Defaults to offload where the datapath supports it, and software otherwise."
+    )]
+    pub checksum_mode: Option<ChecksumMode>,
+    #[structopt(
+        long = "capture_pcap",
+        help = "Server: record sent and received packets to this pcap file."
+    )]
+    pub capture_pcap: Option<String>,
+    #[structopt(
+        long = "admission_rate",
+        help = "Server: requests per second admitted from each client connection; requests above the rate are shed."
+    )]
+    pub admission_rate: Option<f64>,
+    #[structopt(
+        long = "admission_burst",
+        help = "Server: requests a client connection may send back to back above --admission_rate.",
+        default_value = "32"
+    )]
+    pub admission_burst: u64,
+    #[structopt(
+        long = "admission_queue_depth",
+        help = "Server: admitted requests that may wait to be processed; requests arriving beyond it are shed with an overloaded reply."
+    )]
+    pub admission_queue_depth: Option<usize>,
     #[structopt(
         long = "copy_threshold",
         help = "Datapath copy threshold. Copies everything below this threshold. If set to 0, tries to use zero-copy for everything. If set to infinity, uses zero-copy for nothing.",
@@ -91,6 +91,14 @@ size_t custom_ice_post_data_segment(
 int finish_single_transmission(struct custom_ice_per_thread_context *per_thread_context,
         uint16_t last_id);
 
+/* Copies the frame queued in tx descriptors first_id through last_id into dst.
+ * Returns the length of the frame, or 0 if it does not fit in dst_len bytes. */
+size_t custom_ice_copy_frame(struct custom_ice_per_thread_context *per_thread_context,
+        uint16_t first_id,
+        uint16_t last_id,
+        char *dst,
+        size_t dst_len);
+
 void post_queued_segments(struct custom_ice_per_thread_context *per_thread_context, uint16_t tx_id);
 
 size_t custom_ice_get_txd_avail(struct custom_ice_per_thread_context *per_thread_context);
@@ -4,6 +4,7 @@
 #include <rte_ethdev.h>
 #include <ethdev_driver.h>
 #include <rte_malloc.h>
+#include <rte_memcpy.h>
 
 /* Gets per thread context associated with id */
 struct custom_ice_per_thread_context *custom_ice_get_per_thread_context(
@@ -181,6 +182,33 @@ int finish_single_transmission(struct custom_ice_per_thread_context *per_thread_
     return 0;
 }
 
+size_t custom_ice_copy_frame(struct custom_ice_per_thread_context *per_thread_context,
+        uint16_t first_id,
+        uint16_t last_id,
+        char *dst,
+        size_t dst_len) {
+    volatile struct ice_tx_desc *tx_ring = per_thread_context->tx_queue->tx_ring;
+    struct custom_ice_tx_entry *completion_entry;
+    size_t frame_len = 0;
+    uint16_t tx_id = first_id;
+    while (1) {
+        // the completion entry holds the virtual address of the buffer the descriptor points to
+        completion_entry = &per_thread_context->pending_transmissions[tx_id];
+        uint64_t qw1 = rte_le_to_cpu_64(tx_ring[tx_id].cmd_type_offset_bsz);
+        size_t len = (qw1 & ICE_TXD_QW1_TX_BUF_SZ_M) >> ICE_TXD_QW1_TX_BUF_SZ_S;
+        if (frame_len + len > dst_len) {
+            return 0;
+        }
+        rte_memcpy(dst + frame_len, completion_entry->data, len);
+        frame_len += len;
+        if (tx_id == last_id) {
+            break;
+        }
+        tx_id = completion_entry->next_id;
+    }
+    return frame_len;
+}
+
 void post_queued_segments(struct custom_ice_per_thread_context *per_thread_context, uint16_t tx_id) {
     struct custom_ice_tx_entry *completion_entry = &per_thread_context->pending_transmissions[tx_id];
     uint16_t next_tx_id = completion_entry->next_id;
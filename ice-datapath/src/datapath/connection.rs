@@ -13,9 +13,17 @@ use super::{
 };
 use cornflakes_libos::{
     allocator::{MemoryPoolAllocator, MempoolID},
-    datapath::{Datapath, DatapathBufferOps, InlineMode, MetadataOps, ReceivedPkt},
+    datapath::{
+        ChecksumMode, Datapath, DatapathBufferOps, InlineMode, MetadataOps, ReceiveStats,
+        ReceivedPkt, TransmitStats,
+    },
     dynamic_rcsga_hybrid_hdr::HybridArenaRcSgaHdr,
     mem::PGSIZE_2MB,
+    neighbor::{
+        deferred_with_durations, is_arp_frame, static_ipv4_entries, NeighborConfig, NeighborStats,
+        NeighborTable, Resolution, ARP_FRAME_SIZE,
+    },
+    pcap::PacketCapture,
     utils::AddressInfo,
     ConnID, CopyContext, MsgID,
 };
@@ -29,7 +37,7 @@ use std::{
     ffi::CString,
     io::Write,
     mem::MaybeUninit,
-    net::Ipv4Addr,
+    net::{IpAddr, Ipv4Addr},
     ptr,
     sync::Arc,
     time::{Duration, Instant},
@@ -57,6 +65,10 @@ const TX_WTHRESH: u8 = 0;
 
 const RX_RING_SIZE: u16 = 2048;
 const TX_RING_SIZE: u16 = 2048;
+/// Shortest Ethernet frame, without the FCS; shorter frames are padded with zeros.
+const MIN_ETHERNET_FRAME_SIZE: usize = 60;
+/// Larger than any MTU the NIC supports.
+const MAX_CAPTURED_FRAME_SIZE: usize = MAX_BUFFER_SIZE;
 #[derive(PartialEq, Eq)]
 pub struct IceBuffer {
     /// Underlying data pointer.
@@ -676,6 +688,8 @@ pub struct IceDatapathSpecificParams {
     our_eth: MacAddress,
     starting_client_port: u16,
     server_port: u16,
+    /// `known_hosts` from the config file, the fallback for ARP resolution.
+    static_neighbors: HashMap<Ipv4Addr, MacAddress>,
 }
 
 impl IceDatapathSpecificParams {
@@ -735,9 +749,142 @@ pub struct IceConnection {
     recv_mbufs: [*mut dpdk_bindings::rte_mbuf; RECEIVE_BURST_SIZE],
     /// Has outstanding queued data
     has_queued_data: bool,
+    /// Copy versus zero-copy accounting for transmitted messages.
+    transmit_stats: TransmitStats,
+    /// Whether UDP checksums are computed and checked in software.
+    checksum_mode: ChecksumMode,
+    /// Received packets dropped by this datapath.
+    receive_stats: ReceiveStats,
+    /// ARP cache; answers requests for our address and resolves peers on `connect`.
+    neighbors: NeighborTable,
+    /// Packets received while `connect` resolved a peer; `pop` hands them out first.
+    deferred_pkts: Vec<ReceivedPkt<Self>>,
+    /// Capture of sent and received frames, if enabled.
+    capture: Option<PacketCapture>,
+    /// Transmitted frames are gathered here from the tx ring before being captured.
+    capture_buffer: Vec<u8>,
 }
 
 impl IceConnection {
+    pub fn neighbor_stats(&self) -> NeighborStats {
+        self.neighbors.stats()
+    }
+
+    /// Fills in the MAC address of a peer given without one, sending ARP requests until a reply
+    /// arrives. Data packets received in the meantime are deferred to the next `pop`.
+    fn resolve_ether_addr(&mut self, addr: AddressInfo) -> Result<AddressInfo> {
+        let ip = addr
+            .ipv4_addr()
+            .wrap_err("ARP can only resolve IPv4 peers; add the peer to known_hosts")?;
+        loop {
+            match self.neighbors.resolve(&ip, Instant::now())? {
+                Resolution::Resolved(mac) => {
+                    tracing::info!(ip =? ip, mac =? mac, "Resolved peer address");
+                    return Ok(AddressInfo::new(addr.udp_port, ip, mac));
+                }
+                Resolution::Pending(request) => {
+                    if let Some(frame) = request {
+                        self.transmit_frame(&frame)?;
+                    }
+                    // replies are handled in check_received_pkt
+                    let received = self.receive_pkts()?;
+                    self.deferred_pkts.extend(received);
+                }
+            }
+        }
+    }
+
+    /// Hands a received ARP frame to the neighbor table and transmits the reply, if any.
+    fn process_arp(&mut self, frame: &[u8]) -> Result<()> {
+        match self.neighbors.process_frame(frame, Instant::now()) {
+            Ok(Some(reply)) => self.transmit_frame(&reply),
+            Ok(None) => Ok(()),
+            Err(e) => {
+                tracing::debug!(err =? e, "(recv: dropped) Malformed ARP frame");
+                Ok(())
+            }
+        }
+    }
+
+    /// Transmits a complete Ethernet frame as is, along with anything already queued; no
+    /// checksum is written, as the frame need not be IP.
+    fn transmit_frame(&mut self, frame: &[u8]) -> Result<()> {
+        let per_thread_context = self.thread_context.get_context_ptr();
+        let num_required = 1;
+        let mut txd_avail = unsafe { ice_bindings::custom_ice_get_txd_avail(per_thread_context) };
+        let cur_tx_id = unsafe { ice_bindings::get_current_tx_id(per_thread_context) };
+        let last_tx_id =
+            unsafe { ice_bindings::get_last_tx_id_needed(per_thread_context, num_required as _) };
+        while num_required > txd_avail {
+            if self.has_queued_data {
+                unsafe { ice_bindings::post_queued_segments(per_thread_context, last_tx_id as _) }
+                self.has_queued_data = false;
+            }
+            if unsafe { ice_bindings::custom_ice_tx_cleanup(per_thread_context) != 0 } {
+                tracing::debug!("custom_ice_tx_cleanup failed to clean");
+            }
+            txd_avail = unsafe { ice_bindings::custom_ice_get_txd_avail(per_thread_context) };
+        }
+
+        let mut data_buffer = match self.allocator.allocate_tx_buffer()? {
+            Some(data_buf) => data_buf,
+            None => {
+                bail!("No tx mempools to allocate outgoing frame");
+            }
+        };
+        let frame_len = std::cmp::max(frame.len(), MIN_ETHERNET_FRAME_SIZE);
+        let dst = data_buffer.mutable_slice(0, frame_len)?;
+        dst[..frame.len()].copy_from_slice(frame);
+        dst[frame.len()..].fill(0);
+        let mut ice_metadata = IceMetadata::Ice(IceCustomMetadata::from_buf(data_buffer));
+        self.post_ice_metadata(&mut ice_metadata, cur_tx_id, last_tx_id)?;
+        self.finish_single_transmission(last_tx_id as _);
+        unsafe {
+            ice_bindings::post_queued_segments(per_thread_context, last_tx_id as _);
+            ice_bindings::custom_ice_tx_cleanup(per_thread_context);
+        }
+        self.has_queued_data = false;
+        Ok(())
+    }
+
+    /// Receives a burst of packets.
+    fn receive_pkts(&mut self) -> Result<Vec<ReceivedPkt<Self>>> {
+        let num_received = unsafe {
+            dpdk_bindings::rte_eth_rx_burst(
+                self.thread_context.get_dpdk_port(),
+                self.thread_context.get_queue_id(),
+                self.recv_mbufs.as_mut_ptr(),
+                RECEIVE_BURST_SIZE as _,
+            )
+        };
+
+        if num_received == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut ret: Vec<ReceivedPkt<Self>> = Vec::with_capacity(RECEIVE_BURST_SIZE);
+
+        for i in 0..num_received as usize {
+            if let Some(received_pkt) = self
+                .check_received_pkt(i)
+                .wrap_err(format!("Error checking received pkt {}", i))?
+            {
+                tracing::debug!(
+                    "Received pkt with msg ID {}, conn ID {}",
+                    received_pkt.msg_id(),
+                    received_pkt.conn_id()
+                );
+                ret.push(received_pkt);
+            } else {
+                unsafe {
+                    dpdk_bindings::rte_pktmbuf_free(self.recv_mbufs[i]);
+                }
+            }
+            self.recv_mbufs[i] = ptr::null_mut();
+        }
+        Ok(ret)
+    }
+
     fn _debug_check_received_pkt(
         &mut self,
         i: usize,
@@ -746,73 +893,29 @@ impl IceConnection {
         num_received: u16,
     ) -> Result<Option<ReceivedPkt<Self>>> {
         let recv_mbuf = recv_mbufs[i];
-        let eth_hdr = unsafe {
-            dpdk_mbuf_slice!(
-                recv_mbuf,
-                0,
-                cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-            )
-        };
-        let (src_eth, _) = match cornflakes_libos::utils::check_eth_hdr(
-            eth_hdr,
-            &self.thread_context.address_info.ether_addr,
+        let frame = unsafe { dpdk_mbuf_slice!(recv_mbuf, 0, (*recv_mbuf).data_len as usize) };
+        let headers = match cornflakes_libos::utils::check_udp_frame(
+            frame,
+            &self.thread_context.address_info,
         ) {
-            Ok(r) => r,
-            Err(_) => {
+            Ok(headers) => headers,
+            Err(reason) => {
+                self.receive_stats.record_drop(reason);
                 return Ok(None);
             }
         };
+        let data_len = headers.data_len;
 
-        let ipv4_hdr = unsafe {
-            dpdk_mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE,
-                cornflakes_libos::utils::IPV4_HEADER2_SIZE
-            )
-        };
-        let (src_ip, _) = match cornflakes_libos::utils::check_ipv4_hdr(
-            ipv4_hdr,
-            &self.thread_context.address_info.ipv4_addr,
-        ) {
-            Ok(r) => r,
-            Err(_) => {
-                return Ok(None);
-            }
-        };
-
-        let udp_hdr = unsafe {
-            dpdk_mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                    + cornflakes_libos::utils::IPV4_HEADER2_SIZE,
-                cornflakes_libos::utils::UDP_HEADER2_SIZE
-            )
-        };
-
-        let (src_port, _, data_len) = match cornflakes_libos::utils::check_udp_hdr(
-            udp_hdr,
-            self.thread_context.address_info.udp_port,
-        ) {
-            Ok(p) => p,
-            Err(_) => {
-                return Ok(None);
-            }
-        };
+        if !self.verify_checksum(frame, &headers) {
+            return Ok(None);
+        }
 
         // check if this address info is within a current conn_id
-        let src_addr = cornflakes_libos::utils::AddressInfo::new(src_port, src_ip, src_eth);
         let conn_id = self
-            .connect(src_addr)
+            .connect(headers.header_info.src_info)
             .wrap_err("TOO MANY CONCURRENT CONNECTIONS")?;
 
-        let msg_id = unsafe {
-            let slice = dpdk_mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE,
-                cornflakes_libos::utils::HEADER_ID_SIZE
-            );
-            cornflakes_libos::utils::parse_msg_id(slice)
-        };
+        let msg_id = headers.msg_id;
         if let Some((old_idx, old_msg_id, old_conn_id)) = prev_id {
             if old_msg_id == msg_id && old_conn_id == conn_id {
                 tracing::info!(
@@ -830,7 +933,7 @@ impl IceConnection {
 
         let datapath_metadata = IceMetadata::Dpdk(DpdkMetadata::new_from_recv_mbuf(
             recv_mbuf,
-            cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+            headers.payload_offset,
             data_len,
         )?);
 
@@ -841,81 +944,44 @@ impl IceConnection {
     fn check_received_pkt(&mut self, i: usize) -> Result<Option<ReceivedPkt<Self>>> {
         tracing::debug!("Checking received packet");
         let recv_mbuf = self.recv_mbufs[i];
-        let eth_hdr = unsafe {
-            dpdk_mbuf_slice!(
-                recv_mbuf,
-                0,
-                cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-            )
-        };
-        let (src_eth, _) = match cornflakes_libos::utils::check_eth_hdr(
-            eth_hdr,
-            &self.thread_context.address_info.ether_addr,
-        ) {
-            Ok(r) => r,
-            Err(_) => {
-                return Ok(None);
-            }
-        };
-
-        let ipv4_hdr = unsafe {
-            dpdk_mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE,
-                cornflakes_libos::utils::IPV4_HEADER2_SIZE
-            )
-        };
-        let (src_ip, _) = match cornflakes_libos::utils::check_ipv4_hdr(
-            ipv4_hdr,
-            &self.thread_context.address_info.ipv4_addr,
+        let frame = unsafe { dpdk_mbuf_slice!(recv_mbuf, 0, (*recv_mbuf).data_len as usize) };
+        if is_arp_frame(frame) {
+            self.process_arp(&frame[..std::cmp::min(frame.len(), ARP_FRAME_SIZE)])?;
+            return Ok(None);
+        }
+        let headers = match cornflakes_libos::utils::check_udp_frame(
+            frame,
+            &self.thread_context.address_info,
         ) {
-            Ok(r) => r,
-            Err(_) => {
-                tracing::debug!("IP hdr wrong");
+            Ok(headers) => headers,
+            Err(reason) => {
+                tracing::debug!("Dropping received frame: {}", reason);
+                self.receive_stats.record_drop(reason);
                 return Ok(None);
             }
         };
-
-        let udp_hdr = unsafe {
-            dpdk_mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                    + cornflakes_libos::utils::IPV4_HEADER2_SIZE,
-                cornflakes_libos::utils::UDP_HEADER2_SIZE
-            )
-        };
-
-        let (src_port, _, data_len) = match cornflakes_libos::utils::check_udp_hdr(
-            udp_hdr,
-            self.thread_context.address_info.udp_port,
-        ) {
-            Ok(p) => p,
-            Err(_) => {
-                tracing::debug!("UDP hdr wrong");
-                return Ok(None);
-            }
-        };
-
+        let data_len = headers.data_len;
         tracing::debug!("Data len in udp hdr: {:?}", data_len);
 
+        if !self.verify_checksum(frame, &headers) {
+            tracing::debug!("UDP checksum wrong");
+            return Ok(None);
+        }
+
         // check if this address info is within a current conn_id
-        let src_addr = cornflakes_libos::utils::AddressInfo::new(src_port, src_ip, src_eth);
         let conn_id = self
-            .connect(src_addr)
+            .connect(headers.header_info.src_info)
             .wrap_err("TOO MANY CONCURRENT CONNECTIONS")?;
 
-        let msg_id = unsafe {
-            let slice = dpdk_mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE,
-                cornflakes_libos::utils::HEADER_ID_SIZE
-            );
-            cornflakes_libos::utils::parse_msg_id(slice)
-        };
+        let msg_id = headers.msg_id;
+
+        if let Some(capture) = self.capture.as_mut() {
+            capture.record_frame(std::iter::once(frame));
+        }
 
         let datapath_metadata = IceMetadata::Dpdk(DpdkMetadata::new_from_recv_mbuf(
             recv_mbuf,
-            cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+            headers.payload_offset,
             data_len,
         )?);
 
@@ -934,6 +1000,40 @@ impl IceConnection {
         }
     }
 
+    /// If software checksums are on, checks the UDP checksum of a received single-segment frame,
+    /// counting a failure if it is wrong.
+    fn verify_checksum(
+        &mut self,
+        frame: &[u8],
+        headers: &cornflakes_libos::utils::UdpFrameHeaders,
+    ) -> bool {
+        if self.checksum_mode != ChecksumMode::Software {
+            return true;
+        }
+        let ok = headers.verify_checksum(frame);
+        if !ok {
+            self.receive_stats.record_checksum_failure();
+        }
+        ok
+    }
+
+    /// Computes the UDP checksum of a contiguous packet (headers followed by data_len bytes) in
+    /// the given IceBuffer and writes it into the udp header.
+    fn write_software_checksum(data_buffer: &mut IceBuffer, data_len: usize) -> Result<()> {
+        let pkt =
+            data_buffer.mutable_slice(0, cornflakes_libos::utils::TOTAL_HEADER_SIZE + data_len)?;
+        let (hdrs, payload) = pkt.split_at_mut(cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE);
+        let ip_hdr = &hdrs[cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE..];
+        let src_ip = IpAddr::from(<[u8; 4]>::try_from(&ip_hdr[12..16])?);
+        let dst_ip = IpAddr::from(<[u8; 4]>::try_from(&ip_hdr[16..20])?);
+        let udp_hdr = &mut hdrs[cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
+            + cornflakes_libos::utils::IPV4_HEADER2_SIZE..];
+        let checksum =
+            cornflakes_libos::utils::udp_checksum(&src_ip, &dst_ip, udp_hdr, [&payload[..]])?;
+        cornflakes_libos::utils::write_udp_checksum(udp_hdr, checksum);
+        Ok(())
+    }
+
     /// Copies udp header into the front of the given IceBuffer
     fn copy_hdr(
         &self,
@@ -971,6 +1071,32 @@ impl IceConnection {
         unimplemented!();
     }
 
+    /// Finishes the transmission queued from the tx tail through `last_tx_id`, first capturing
+    /// the frame it holds.
+    fn finish_single_transmission(&mut self, last_tx_id: u16) {
+        let per_thread_context = self.thread_context.get_context_ptr();
+        if let Some(capture) = self.capture.as_mut() {
+            let frame_len = unsafe {
+                ice_bindings::custom_ice_copy_frame(
+                    per_thread_context,
+                    ice_bindings::get_current_tx_id(per_thread_context) as _,
+                    last_tx_id,
+                    self.capture_buffer.as_mut_ptr() as _,
+                    self.capture_buffer.len() as _,
+                )
+            };
+            if frame_len == 0 {
+                tracing::debug!("Transmitted frame too large to capture");
+            } else {
+                let frame = &self.capture_buffer[0..frame_len as usize];
+                capture.record_frame(std::iter::once(frame));
+            }
+        }
+        unsafe {
+            ice_bindings::finish_single_transmission(per_thread_context, last_tx_id);
+        }
+    }
+
     fn post_curr_transmissions(&self) -> Result<()> {
         // TODO: fill in logic to post whatever has been queued up to on the ring buffer
         unimplemented!();
@@ -1007,8 +1133,15 @@ impl Datapath for IceConnection {
 
     fn parse_config_file(
         config_file: &str,
-        our_ip: &Ipv4Addr,
+        our_ip: &IpAddr,
     ) -> Result<Self::DatapathSpecificParams> {
+        // packet headers are laid out as IPv4
+        let our_ipv4 = match our_ip {
+            IpAddr::V4(ip) => *ip,
+            IpAddr::V6(_) => {
+                bail!("ice datapath only supports IPv4; got {:?}", our_ip);
+            }
+        };
         let (ip_to_mac, _mac_to_ip, udp_port, client_port) =
             parse_yaml_map(config_file).wrap_err("Failed to parse yaml mapping")?;
 
@@ -1025,17 +1158,18 @@ impl Datapath for IceConnection {
         Ok(IceDatapathSpecificParams {
             eal_init: eal_init,
             dpdk_port: -1,
-            our_ip: our_ip.clone(),
+            our_ip: our_ipv4,
             our_eth: eth_addr,
             starting_client_port: client_port,
             server_port: udp_port,
+            static_neighbors: static_ipv4_entries(&ip_to_mac),
         })
     }
 
     fn compute_affinity(
         datapath_params: &Self::DatapathSpecificParams,
         num_queues: usize,
-        _remote_ip: Option<Ipv4Addr>,
+        _remote_ip: Option<IpAddr>,
         app_mode: AppMode,
     ) -> Result<Vec<AddressInfo>> {
         // TODO: how do we compute affinity for more than one queue for mlx5
@@ -1225,6 +1359,13 @@ impl Datapath for IceConnection {
                 .wrap_err("Incorrect mempool allocation params")?;
         let tx_mempool = IceMempool::new(&mempool_params, false)?;
         let allocator = MemoryPoolAllocator::new(rx_mempool, tx_mempool)?;
+        let address_info = context.get_address_info().clone();
+        let neighbors = NeighborTable::new(
+            address_info.ether_addr,
+            vec![address_info.ipv4_addr()?],
+            datapath_params.static_neighbors,
+            NeighborConfig::default(),
+        )?;
         Ok(IceConnection {
             thread_context: context,
             mode: mode,
@@ -1236,6 +1377,13 @@ impl Datapath for IceConnection {
             max_segments: 32,
             recv_mbufs: [ptr::null_mut(); RECEIVE_BURST_SIZE],
             has_queued_data: false,
+            transmit_stats: TransmitStats::default(),
+            checksum_mode: ChecksumMode::Software,
+            receive_stats: ReceiveStats::default(),
+            neighbors,
+            deferred_pkts: Vec::default(),
+            capture: None,
+            capture_buffer: Vec::new(),
         })
     }
 
@@ -1245,6 +1393,12 @@ impl Datapath for IceConnection {
     fn connect(&mut self, addr: AddressInfo) -> Result<ConnID> {
         if self.address_to_conn_id.contains_key(&addr) {
             return Ok(*self.address_to_conn_id.get(&addr).unwrap());
+        } else if addr.is_unresolved() {
+            let resolved_addr = self.resolve_ether_addr(addr)?;
+            let conn_id = self.connect(resolved_addr)?;
+            // later connects with the unresolved address reuse the connection
+            self.address_to_conn_id.insert(addr, conn_id);
+            return Ok(conn_id);
         } else {
             if self.address_to_conn_id.len() >= MAX_CONCURRENT_CONNECTIONS {
                 bail!("too many concurrent connections; cannot connect to more");
@@ -1313,6 +1467,7 @@ impl Datapath for IceConnection {
         let pkts_len = pkts.len();
         for (i, mut pkt) in pkts.into_iter().enumerate() {
             let msg_id = pkt.msg_id();
+            self.transmit_stats.record_message();
             for (scatter_index, ref mut dpdk_metadata) in pkt.iter_mut().enumerate() {
                 let mbuf = match dpdk_metadata {
                     IceMetadata::Dpdk(dpdk) => dpdk.get_mbuf(),
@@ -1337,6 +1492,7 @@ impl Datapath for IceConnection {
                 }
                 // increment ref count so it does not get dropped here
                 dpdk_metadata.increment_refcnt();
+                self.transmit_stats.record_zero_copy(dpdk_metadata.data_len());
                 send_mbufs[scatter_index as usize][i as usize] = mbuf;
             }
         }
@@ -1448,6 +1604,13 @@ impl Datapath for IceConnection {
         let data_len = serialization_info.header_size 
             + serialization_info.copy_length
             + serialization_info.zero_copy_length;
+        self.transmit_stats.record_serialized_object(
+            serialization_info.header_size,
+            false,
+            serialization_info.copy_length,
+            serialization_info.zero_copy_length,
+            serialization_info.num_zero_copy_entries,
+        );
         
         // copy the packet header into the beginning of the buffer
         self.copy_hdr(&mut allocated_header_buffer, conn_id, msg_id, data_len)?;
@@ -1499,9 +1662,7 @@ impl Datapath for IceConnection {
         }
 
         // finish posting data to queue
-        unsafe {
-            ice_bindings::finish_single_transmission(per_thread_context, last_tx_id as _);
-        }
+        self.finish_single_transmission(last_tx_id as _);
 
         // end batch
         if end_batch {
@@ -1622,6 +1783,13 @@ impl Datapath for IceConnection {
             &mut callback,
             &mut ring_buffer_state,
         )? + header_len;
+        self.transmit_stats.record_serialized_object(
+            header_len,
+            false,
+            copy_context.data_len(),
+            data_len.saturating_sub(header_len + copy_context.data_len()),
+            num_zero_copy_entries,
+        );
 
         // copy the packet header into the beginning of the buffer
         self.copy_hdr(&mut allocated_header_buffer, conn_id, msg_id, data_len)?;
@@ -1662,9 +1830,7 @@ impl Datapath for IceConnection {
         }
 
         // finish posting data to queue
-        unsafe {
-            ice_bindings::finish_single_transmission(per_thread_context, last_tx_id as _);
-        }
+        self.finish_single_transmission(last_tx_id as _);
 
         // end batch
         if end_batch {
@@ -1735,13 +1901,16 @@ impl Datapath for IceConnection {
             data_buffer.write(buf_arr)? == buf_arr.len(),
             "Could not copy whole buffer into allocated buffer"
         );
+        if self.checksum_mode == ChecksumMode::Software {
+            Self::write_software_checksum(&mut data_buffer, buf_arr.len())?;
+        }
+        self.transmit_stats.record_message();
+        self.transmit_stats.record_copy(buf_arr.len());
         let mut ice_metadata = IceMetadata::Ice(IceCustomMetadata::from_buf(data_buffer));
         let _ = self.post_ice_metadata(&mut ice_metadata, cur_tx_id, last_tx_id);
         
         // finish queueing buffer
-        unsafe {
-            ice_bindings::finish_single_transmission(per_thread_context, last_tx_id as _);
-        }
+        self.finish_single_transmission(last_tx_id as _);
 
         // end batch
         if end_batch {
@@ -1784,6 +1953,12 @@ impl Datapath for IceConnection {
     where
         Self: Sized,
     {
+        if !self.deferred_pkts.is_empty() {
+            return Ok(deferred_with_durations(
+                std::mem::take(&mut self.deferred_pkts),
+                &mut self.outgoing_window,
+            ));
+        }
         let num_received = unsafe {
             dpdk_bindings::rte_eth_rx_burst(
                 self.thread_context.get_dpdk_port(),
@@ -1840,40 +2015,10 @@ impl Datapath for IceConnection {
     where
         Self: Sized,
     {
-        let num_received = unsafe {
-            dpdk_bindings::rte_eth_rx_burst(
-                self.thread_context.get_dpdk_port(),
-                self.thread_context.get_queue_id(),
-                self.recv_mbufs.as_mut_ptr(),
-                RECEIVE_BURST_SIZE as _,
-            )
-        };
-
-        if num_received == 0 {
-            return Ok(vec![]);
+        if !self.deferred_pkts.is_empty() {
+            return Ok(std::mem::take(&mut self.deferred_pkts));
         }
-
-        let mut ret: Vec<ReceivedPkt<Self>> = Vec::with_capacity(RECEIVE_BURST_SIZE);
-
-        for i in 0..num_received as usize {
-            if let Some(received_pkt) = self
-                .check_received_pkt(i)
-                .wrap_err(format!("Error checking received pkt {}", i))?
-            {
-                tracing::debug!(
-                    "Received pkt with msg ID {}, conn ID {}",
-                    received_pkt.msg_id(),
-                    received_pkt.conn_id()
-                );
-                ret.push(received_pkt);
-            } else {
-                unsafe {
-                    dpdk_bindings::rte_pktmbuf_free(self.recv_mbufs[i]);
-                }
-            }
-            self.recv_mbufs[i] = ptr::null_mut();
-        }
-        Ok(ret)
+        self.receive_pkts()
     }
 
     /// Check if any outstanding packets have timed out.
@@ -2003,6 +2148,43 @@ impl Datapath for IceConnection {
     /// Set inline mode (may not be available in all datapaths)
     fn set_inline_mode(&mut self, _mode: InlineMode) {}
 
+    fn transmit_stats(&self) -> TransmitStats {
+        self.transmit_stats
+    }
+
+    fn reset_transmit_stats(&mut self) {
+        self.transmit_stats = TransmitStats::default();
+    }
+
+    /// Software checksums are only written for single-buffer copy sends; zero-copy
+    /// scatter-gather sends leave the checksum field zero.
+    fn set_checksum_mode(&mut self, mode: ChecksumMode) -> Result<()> {
+        ensure!(
+            mode != ChecksumMode::Offload,
+            "ice datapath does not support checksum offload; use software checksums"
+        );
+        self.checksum_mode = mode;
+        Ok(())
+    }
+
+    fn get_checksum_mode(&self) -> ChecksumMode {
+        self.checksum_mode
+    }
+
+    fn receive_stats(&self) -> ReceiveStats {
+        self.receive_stats
+    }
+
+    fn reset_receive_stats(&mut self) {
+        self.receive_stats = ReceiveStats::default();
+    }
+
+    fn set_packet_capture(&mut self, capture: PacketCapture) -> Result<()> {
+        self.capture_buffer = vec![0u8; MAX_CAPTURED_FRAME_SIZE];
+        self.capture = Some(capture);
+        Ok(())
+    }
+
     /// Packet processing batch size.
     fn batch_size() -> usize {
         32
@@ -1,7 +1,7 @@
 use cornflakes_libos::{datapath::{Datapath, InlineMode}, OrderedSga};
 use cornflakes_utils::AppMode;
 use linux_datapath::datapath::connection::LinuxConnection;
-use std::{ffi::CStr, net::Ipv4Addr, str::FromStr};
+use std::{ffi::CStr, net::IpAddr, str::FromStr};
 
 fn convert_c_char(ptr: *const ::std::os::raw::c_char) -> String {
     let cstr: &CStr = unsafe { CStr::from_ptr(ptr) };
@@ -43,7 +43,7 @@ pub extern "C" fn LinuxConnection_new(
 ) -> *mut ::std::os::raw::c_void {
     let mut datapath_params = match LinuxConnection::parse_config_file(
         convert_c_char(config_file).as_str(),
-        &Ipv4Addr::from_str(convert_c_char(server_ip).as_str()).unwrap(),
+        &IpAddr::from_str(convert_c_char(server_ip).as_str()).unwrap(),
     ) {
         Ok(x) => x,
         Err(e) => {
@@ -4,8 +4,13 @@ use color_eyre::eyre::WrapErr;
 use color_eyre::eyre::{bail, ensure, Result};
 use cornflakes_libos::{
     allocator::MempoolID,
-    datapath::{Datapath, DatapathBufferOps, InlineMode, MetadataOps, ReceivedPkt},
-    utils::{AddressInfo, HEADER_ID_SIZE},
+    datapath::{
+        ChecksumMode, Datapath, DatapathBufferOps, InlineMode, MetadataOps, ReceiveStats,
+        ReceivedPkt, TransmitStats,
+    },
+    encryption::{EncryptionConfig, TransportEncryption, ENCRYPTION_OVERHEAD, NONCE_LEN},
+    pcap::{PacketCapture, PcapReplay, ReplayFinished},
+    utils::{AddressInfo, HeaderInfo, HEADER_ID_SIZE},
     ConnID, MsgID, OrderedSga, RcSga, Sga,
 };
 use cornflakes_utils::{parse_yaml_map, AppMode};
@@ -13,11 +18,11 @@ use eui48::MacAddress;
 use hashbrown::HashMap;
 use std::{
     io::{self, Write},
-    net::{IpAddr, Ipv4Addr, UdpSocket},
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
     time::{Duration, Instant},
 };
 
-const FILLER_MAC: &str = "ff:ff:ff:ff:ff:ff";
+pub(crate) const FILLER_MAC: &str = "ff:ff:ff:ff:ff:ff";
 const MAX_CONCURRENT_CONNECTIONS: usize = 128;
 // TOOD(ygina): careful with fixed max buffer size...
 const RECEIVE_BUFFER_SIZE: usize = 2048;
@@ -45,10 +50,10 @@ impl MutableByteBuffer {
         }
     }
 
+    /// Empty buffer that writes can fill up to `data_len` bytes.
     pub fn new_with_capacity(data_len: usize) -> Self {
-        // TODO: bad because this is a stack allocation?
         MutableByteBuffer {
-            buf: BytesMut::from(vec![0u8; data_len].as_slice()),
+            buf: BytesMut::with_capacity(data_len),
         }
     }
 
@@ -76,8 +81,10 @@ impl AsRef<[u8]> for MutableByteBuffer {
 
 impl Write for MutableByteBuffer {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let to_write = std::cmp::min(buf.len(), self.buf.len());
-        self.buf.as_mut().write(&buf[0..to_write])
+        // appends, without growing past the capacity the buffer was allocated with
+        let to_write = std::cmp::min(buf.len(), self.buf.capacity() - self.buf.len());
+        self.buf.extend_from_slice(&buf[0..to_write]);
+        Ok(to_write)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -107,11 +114,8 @@ impl ByteBuffer {
     }
 
     pub fn from_raw_buf(buffer: &[u8]) -> Self {
-        let mut bytes = BytesMut::with_capacity(buffer.len());
-        bytes.copy_from_slice(buffer);
-        let buf = bytes.freeze();
         ByteBuffer {
-            bytes: buf,
+            bytes: Bytes::copy_from_slice(buffer),
             offset: 0,
             len: buffer.len(),
         }
@@ -170,18 +174,24 @@ pub struct LinuxPerThreadContext {
     address_info: AddressInfo,
 }
 
+impl LinuxPerThreadContext {
+    pub fn get_address_info(&self) -> AddressInfo {
+        self.address_info
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LinuxDatapathSpecificParams {
     // TODO: insert datapath specific params:
     // Server UDP port, Server IP address, potentially server interface name
-    our_ip: Ipv4Addr,
+    our_ip: IpAddr,
     our_eth: MacAddress,
     client_port: u16,
     server_port: u16,
 }
 
 impl LinuxDatapathSpecificParams {
-    pub fn get_ipv4(&self) -> Ipv4Addr {
+    pub fn get_ip(&self) -> IpAddr {
         self.our_ip.clone()
     }
 
@@ -211,9 +221,55 @@ pub struct LinuxConnection {
     address_to_conn_id: HashMap<AddressInfo, ConnID>,
     /// Addresses of active connections, indexed by connection id
     active_connections: [Option<AddressInfo>; MAX_CONCURRENT_CONNECTIONS],
+    /// Transmit accounting (every payload is copied into a socket buffer).
+    transmit_stats: TransmitStats,
+    /// Received messages dropped by this datapath.
+    receive_stats: ReceiveStats,
+    /// Our address, used for the headers of captured packets.
+    address_info: AddressInfo,
+    /// Capture of sent and received messages, if enabled.
+    capture: Option<PacketCapture>,
+    /// Capture replayed in place of the socket, if any.
+    replay: Option<PcapReplay>,
+    /// Payload encryption, if enabled.
+    encryption: Option<TransportEncryption>,
 }
 
 impl LinuxConnection {
+    fn with_socket(context: LinuxPerThreadContext, mode: AppMode, socket: UdpSocket) -> Self {
+        LinuxConnection {
+            start: Instant::now(),
+            mode,
+            outgoing_window: HashMap::default(),
+            socket,
+            address_to_conn_id: HashMap::default(),
+            active_connections: [None; MAX_CONCURRENT_CONNECTIONS],
+            transmit_stats: TransmitStats::default(),
+            receive_stats: ReceiveStats::default(),
+            address_info: context.address_info,
+            capture: None,
+            replay: None,
+            encryption: None,
+        }
+    }
+
+    /// Server connection that receives the requests recorded in `replay` instead of reading
+    /// its socket, and checks its responses against the recorded ones instead of sending them.
+    /// `context` should hold the server address the capture was recorded at.
+    pub fn new_replay(context: LinuxPerThreadContext, replay: PcapReplay) -> Result<Self> {
+        // the socket is never read or written; bind to any free port
+        let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))?;
+        socket.set_nonblocking(true)?;
+        let mut connection = LinuxConnection::with_socket(context, AppMode::Server, socket);
+        connection.replay = Some(replay);
+        Ok(connection)
+    }
+
+    /// Capture being replayed, if this connection was created with `new_replay`.
+    pub fn replay(&self) -> Option<&PcapReplay> {
+        self.replay.as_ref()
+    }
+
     fn insert_into_outgoing_map(&mut self, msg_id: MsgID, conn_id: ConnID) {
         if self.mode == AppMode::Client {
             if !self.outgoing_window.contains_key(&(msg_id, conn_id)) {
@@ -223,9 +279,38 @@ impl LinuxConnection {
         }
     }
 
+    /// Next request from the capture being replayed, or None once all have been delivered.
+    fn next_replayed_pkt(&mut self) -> Result<Option<ReceivedPkt<Self>>> {
+        let request = match self
+            .replay
+            .as_mut()
+            .and_then(|replay| replay.next_request())
+        {
+            Some(request) => request,
+            None => {
+                return Ok(None);
+            }
+        };
+        let conn_id = self
+            .connect(request.src)
+            .wrap_err("TOO MANY CONCURRENT CONNECTIONS")?;
+        if let Some(capture) = self.capture.as_mut() {
+            capture.record_udp(
+                &request.src.get_outgoing(&self.address_info),
+                request.msg_id,
+                &[&request.payload],
+            );
+        }
+        let bytes = ByteBuffer::new(&request.payload, 0, None)?;
+        Ok(Some(ReceivedPkt::new(vec![bytes], request.msg_id, conn_id)))
+    }
+
     fn check_received_pkt(&mut self) -> Result<Option<ReceivedPkt<Self>>> {
+        if self.replay.is_some() {
+            return self.next_replayed_pkt();
+        }
         let mut buf = [0; RECEIVE_BUFFER_SIZE];
-        let addr = match self.socket.recv_from(&mut buf) {
+        let (n, addr) = match self.socket.recv_from(&mut buf) {
             Ok((n, addr)) => {
                 if n == 0 {
                     tracing::debug!("Received {} bytes from {:?}", n, addr);
@@ -233,7 +318,7 @@ impl LinuxConnection {
                 } else {
                     assert!(n > HEADER_ID_SIZE);
                     tracing::debug!("Received {} bytes from {:?}", n, addr);
-                    addr
+                    (n, addr)
                 }
             }
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
@@ -242,22 +327,36 @@ impl LinuxConnection {
             Err(e) => panic!("encountered IO error: {}", e),
         };
         let msg_id = NetworkEndian::read_u32(&buf[0..4]);
-        let conn_id = {
-            let ipv4 = match addr.ip() {
-                IpAddr::V4(ip) => ip,
-                _ => {
-                    panic!("expected ipv4 address")
-                }
-            };
-            let src_addr = cornflakes_libos::utils::AddressInfo::new(
-                addr.port(),
-                ipv4,
-                MacAddress::parse_str(FILLER_MAC).unwrap(),
+        let src_addr = cornflakes_libos::utils::AddressInfo::new(
+            addr.port(),
+            addr.ip(),
+            MacAddress::parse_str(FILLER_MAC).unwrap(),
+        );
+        if let Some(capture) = self.capture.as_mut() {
+            capture.record_udp(
+                &HeaderInfo::new(src_addr, self.address_info),
+                msg_id,
+                &[&buf[HEADER_ID_SIZE..n]],
             );
-            self.connect(src_addr)
-                .wrap_err("TOO MANY CONCURRENT CONNECTIONS")?
+        }
+        // messages that fail to authenticate are dropped before they can take up a connection
+        let (data_offset, data_len) = match self.encryption.as_mut() {
+            Some(encryption) => {
+                match encryption.open(&src_addr, msg_id, &mut buf[HEADER_ID_SIZE..n]) {
+                    Ok(len) => (HEADER_ID_SIZE + NONCE_LEN, Some(len)),
+                    Err(e) => {
+                        tracing::debug!("Dropping message {} from {:?}: {:?}", msg_id, addr, e);
+                        self.receive_stats.record_decryption_failure();
+                        return Ok(None);
+                    }
+                }
+            }
+            None => (HEADER_ID_SIZE, None),
         };
-        let bytes = ByteBuffer::new(&buf, HEADER_ID_SIZE, None)?;
+        let conn_id = self
+            .connect(src_addr)
+            .wrap_err("TOO MANY CONCURRENT CONNECTIONS")?;
+        let bytes = ByteBuffer::new(&buf[0..n], data_offset, data_len)?;
         let received_pkt = ReceivedPkt::new(vec![bytes], msg_id, conn_id);
         Ok(Some(received_pkt))
     }
@@ -276,7 +375,7 @@ impl Datapath for LinuxConnection {
 
     fn parse_config_file(
         config_file: &str,
-        our_ip: &Ipv4Addr,
+        our_ip: &IpAddr,
     ) -> Result<Self::DatapathSpecificParams> {
         let (_ip_to_mac, _mac_to_ip, udp_port, client_port) =
             parse_yaml_map(config_file).wrap_err("Failed to parse yaml mapping")?;
@@ -291,28 +390,25 @@ impl Datapath for LinuxConnection {
     fn compute_affinity(
         datapath_params: &Self::DatapathSpecificParams,
         num_queues: usize,
-        _remote_ip: Option<Ipv4Addr>,
+        _remote_ip: Option<IpAddr>,
         app_mode: cornflakes_utils::AppMode,
     ) -> Result<Vec<AddressInfo>> {
-        if num_queues > 1 {
-            bail!(
-                "Currently, linux datapath does not support more than one
-               queue"
-            );
-        }
-        match app_mode {
-            AppMode::Client => Ok(vec![AddressInfo::new(
-                datapath_params.get_client_port(),
-                datapath_params.get_ipv4(),
-                datapath_params.get_mac(),
-            )]),
-
-            AppMode::Server => Ok(vec![AddressInfo::new(
-                datapath_params.get_server_port(),
-                datapath_params.get_ipv4(),
-                datapath_params.get_mac(),
-            )]),
-        }
+        // each queue is its own socket, bound to consecutive ports
+        Ok((0..num_queues)
+            .map(|queue_id| {
+                let port = match app_mode {
+                    AppMode::Client => datapath_params.get_client_port() + queue_id as u16,
+                    AppMode::Server => {
+                        Self::server_queue_port(datapath_params.get_server_port(), queue_id)
+                    }
+                };
+                AddressInfo::new(port, datapath_params.get_ip(), datapath_params.get_mac())
+            })
+            .collect())
+    }
+
+    fn server_queue_port(server_port: u16, queue_id: usize) -> u16 {
+        server_port + queue_id as u16
     }
 
     fn global_init(
@@ -320,7 +416,6 @@ impl Datapath for LinuxConnection {
         _datapath_params: &mut Self::DatapathSpecificParams,
         addresses: Vec<AddressInfo>,
     ) -> Result<Vec<Self::PerThreadContext>> {
-        assert_eq!(num_queues, 1);
         assert_eq!(addresses.len(), num_queues);
         let mut ret: Vec<Self::PerThreadContext> = Vec::with_capacity(num_queues);
         for (i, addr) in addresses.into_iter().enumerate() {
@@ -340,21 +435,11 @@ impl Datapath for LinuxConnection {
     where
         Self: Sized,
     {
-        let addr = format!(
-            "{}:{}",
-            context.address_info.ipv4_addr, context.address_info.udp_port,
-        );
+        let addr = SocketAddr::new(context.address_info.ip_addr, context.address_info.udp_port);
         tracing::info!("Binding to {}", addr);
         let socket = UdpSocket::bind(addr).unwrap();
         socket.set_nonblocking(true)?;
-        Ok(LinuxConnection {
-            start: Instant::now(),
-            mode,
-            outgoing_window: HashMap::default(),
-            socket,
-            address_to_conn_id: HashMap::default(),
-            active_connections: [None; MAX_CONCURRENT_CONNECTIONS],
-        })
+        Ok(LinuxConnection::with_socket(context, mode, socket))
     }
 
     fn connect(&mut self, addr: AddressInfo) -> Result<ConnID> {
@@ -387,17 +472,50 @@ impl Datapath for LinuxConnection {
         }
     }
 
+    fn connection_address(&self, conn_id: ConnID) -> Option<AddressInfo> {
+        self.active_connections.get(conn_id).copied().flatten()
+    }
+
     fn push_buffers_with_copy(&mut self, pkts: &[(MsgID, ConnID, &[u8])]) -> Result<()> {
         tracing::debug!("Pushing batch of pkts of length {}", pkts.len());
         for (msg_id, conn_id, data) in pkts.iter() {
             self.insert_into_outgoing_map(*msg_id, *conn_id);
             let mut buf = vec![0, 0, 0, 0];
             NetworkEndian::write_u32(&mut buf, *msg_id);
-            buf.extend_from_slice(data);
-            let addr = {
-                let address_info = self.active_connections[*conn_id].unwrap();
-                format!("{}:{}", address_info.ipv4_addr, address_info.udp_port)
-            };
+            self.transmit_stats.record_message();
+            let address_info = self.active_connections[*conn_id].unwrap();
+            match self.encryption.as_mut() {
+                Some(encryption) => {
+                    buf.resize(HEADER_ID_SIZE + data.len() + ENCRYPTION_OVERHEAD, 0);
+                    buf[HEADER_ID_SIZE + NONCE_LEN..HEADER_ID_SIZE + NONCE_LEN + data.len()]
+                        .copy_from_slice(data);
+                    encryption.seal(
+                        &address_info,
+                        *msg_id,
+                        &mut buf[HEADER_ID_SIZE..],
+                        data.len(),
+                    )?;
+                    self.transmit_stats
+                        .record_encrypted_segment(data.len(), false);
+                }
+                None => {
+                    buf.extend_from_slice(data);
+                    self.transmit_stats.record_copy(data.len());
+                }
+            }
+            if let Some(capture) = self.capture.as_mut() {
+                capture.record_udp(
+                    &self.address_info.get_outgoing(&address_info),
+                    *msg_id,
+                    &[&buf[HEADER_ID_SIZE..]],
+                );
+            }
+            if let Some(replay) = self.replay.as_mut() {
+                // replayed requests never arrived on the socket, so responses are not sent
+                replay.check_response(&address_info, *msg_id, data);
+                continue;
+            }
+            let addr = SocketAddr::new(address_info.ip_addr, address_info.udp_port);
             tracing::debug!("Sending {} bytes to {}", buf.len(), addr);
             let n = self.socket.send_to(&buf, &addr).expect(&format!(
                 "Failed to send data (len {}) to {}",
@@ -409,6 +527,29 @@ impl Datapath for LinuxConnection {
         Ok(())
     }
 
+    fn queue_single_buffer_with_copy(
+        &mut self,
+        buf: (MsgID, ConnID, &[u8]),
+        _end_batch: bool,
+    ) -> Result<()> {
+        self.push_buffers_with_copy(&[buf])
+    }
+
+    fn queue_metadata_vec(
+        &mut self,
+        msg_id: MsgID,
+        conn_id: ConnID,
+        metadata_vec: Vec<Self::DatapathMetadata>,
+        _end_batch: bool,
+    ) -> Result<()> {
+        // sockets take one contiguous payload, so the segments are copied together
+        let mut buf = Vec::with_capacity(metadata_vec.iter().map(|m| m.data_len()).sum());
+        for metadata in metadata_vec.iter() {
+            buf.extend_from_slice(metadata.as_ref());
+        }
+        self.push_buffers_with_copy(&[(msg_id, conn_id, &buf)])
+    }
+
     fn echo(&mut self, mut _pkts: Vec<ReceivedPkt<Self>>) -> Result<()>
     where
         Self: Sized,
@@ -511,20 +652,29 @@ impl Datapath for LinuxConnection {
         }
         if !ret.is_empty() {
             tracing::debug!("Received {} packets", ret.len());
+        } else if self.replay.is_some() {
+            // every request has been delivered and answered
+            return Err(ReplayFinished.into());
         }
         Ok(ret)
     }
 
-    fn timed_out(&self, _time_out: Duration) -> Result<Vec<(MsgID, ConnID)>> {
-        unimplemented!();
+    fn timed_out(&self, time_out: Duration) -> Result<Vec<(MsgID, ConnID)>> {
+        Ok(self
+            .outgoing_window
+            .iter()
+            .filter(|(_, start)| start.elapsed() > time_out)
+            .map(|(id, _)| *id)
+            .collect())
     }
 
     fn is_registered(&self, _buf: &[u8]) -> bool {
         false
     }
 
-    fn allocate(&mut self, _size: usize) -> Result<Option<Self::DatapathBuffer>> {
-        unimplemented!();
+    fn allocate(&mut self, size: usize) -> Result<Option<Self::DatapathBuffer>> {
+        // heap buffers: there are no mempools to run out of
+        Ok(Some(MutableByteBuffer::new_with_capacity(size)))
     }
 
     fn allocate_tx_buffer(&mut self) -> Result<(Option<Self::DatapathBuffer>, usize)> {
@@ -549,6 +699,16 @@ impl Datapath for LinuxConnection {
         Ok(vec![])
     }
 
+    fn allocate_fallback_mempools(
+        &mut self,
+        _mempool_ids: &mut Vec<MempoolID>,
+        _num_pages: usize,
+        _num_registration_units: usize,
+        _register_at_start: bool,
+    ) -> Result<()> {
+        Ok(())
+    }
+
     fn header_size(&self) -> usize {
         unimplemented!();
     }
@@ -590,6 +750,60 @@ impl Datapath for LinuxConnection {
 
     fn set_inline_mode(&mut self, _mode: InlineMode) {}
 
+    fn transmit_stats(&self) -> TransmitStats {
+        self.transmit_stats
+    }
+
+    fn reset_transmit_stats(&mut self) {
+        self.transmit_stats = TransmitStats::default();
+    }
+
+    fn receive_stats(&self) -> ReceiveStats {
+        self.receive_stats
+    }
+
+    fn reset_receive_stats(&mut self) {
+        self.receive_stats = ReceiveStats::default();
+    }
+
+    /// The kernel stack computes checksums on send and drops bad packets on receive.
+    fn checksum_offload_capable(&self) -> bool {
+        true
+    }
+
+    fn set_checksum_mode(&mut self, mode: ChecksumMode) -> Result<()> {
+        ensure!(
+            mode == ChecksumMode::Offload,
+            "Linux datapath always leaves checksums to the kernel; cannot use {:?}",
+            mode
+        );
+        Ok(())
+    }
+
+    fn get_checksum_mode(&self) -> ChecksumMode {
+        ChecksumMode::Offload
+    }
+
+    /// Messages are captured with the headers the bypass datapaths would write, since the
+    /// kernel's headers are not visible to us.
+    fn set_packet_capture(&mut self, capture: PacketCapture) -> Result<()> {
+        self.capture = Some(capture);
+        Ok(())
+    }
+
+    fn set_encryption(&mut self, config: EncryptionConfig) -> Result<()> {
+        ensure!(
+            self.replay.is_none(),
+            "Cannot replay captures with encryption on; captures record sealed payloads"
+        );
+        self.encryption = Some(TransportEncryption::new(
+            config,
+            self.address_info,
+            self.mode,
+        ));
+        Ok(())
+    }
+
     fn max_packet_size() -> usize {
         1500
     }
@@ -0,0 +1,1176 @@
+//! Kernel TCP datapath.
+//!
+//! `LinuxTcpConnection` carries each message over a TCP stream as a frame: a 4 byte length
+//! (covering the message id and payload), the 4 byte `MsgID`, then the payload. Streams are
+//! non-blocking and multiplexed with epoll, and every accepted or connected stream gets its own
+//! `ConnID`. Unlike the UDP datapath, scatter-gather arrays are handed to the kernel with vectored
+//! writes rather than copied together first, and servers can sit behind standard TCP load
+//! balancers. When a server closes a client's stream, the next send over that connection id
+//! (such as a retransmission after a timeout) reconnects it.
+//!
+//! `LinuxRespConnection` is a server-only variant for stock redis clients such as `redis-cli` and
+//! `redis-benchmark`: streams carry bare RESP, without a length or message id. Each run of
+//! complete commands read from a stream becomes one message, numbered in arrival order, and
+//! replies are written back as they are.
+use super::connection::{
+    ByteBuffer, LinuxConnection, LinuxDatapathSpecificParams, LinuxPerThreadContext,
+    MutableByteBuffer, FILLER_MAC,
+};
+use byteorder::{ByteOrder, NetworkEndian};
+use bytes::BytesMut;
+use color_eyre::eyre::{bail, ensure, Result, WrapErr};
+use cornflakes_libos::{
+    allocator::MempoolID,
+    datapath::{ChecksumMode, Datapath, InlineMode, ReceivedPkt, TransmitStats},
+    utils::{AddressInfo, HEADER_ID_SIZE},
+    ConnID, MsgID, OrderedSga, RcSga, Sga,
+};
+use cornflakes_utils::AppMode;
+use eui48::MacAddress;
+use hashbrown::HashMap;
+use std::{
+    collections::VecDeque,
+    io::{self, IoSlice, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    os::unix::io::{AsRawFd, RawFd},
+    time::{Duration, Instant},
+};
+
+/// Kernel TCP datapath speaking bare RESP, for stock redis clients.
+pub type LinuxRespConnection = LinuxTcpConnection<RespFraming>;
+
+const MAX_CONCURRENT_CONNECTIONS: usize = 128;
+const RECEIVE_BURST_SIZE: usize = 32;
+const READ_CHUNK_SIZE: usize = 16384;
+/// Frame header: message length, then message id.
+const FRAME_LENGTH_SIZE: usize = 4;
+const FRAME_HEADER_SIZE: usize = FRAME_LENGTH_SIZE + HEADER_ID_SIZE;
+/// Largest frame accepted from a peer; longer lengths mean the stream is corrupt.
+const MAX_MESSAGE_SIZE: usize = 1 << 24;
+/// Epoll token of the listening socket; streams use their connection id.
+const LISTENER_TOKEN: u64 = u64::MAX;
+const STREAM_EVENTS: u32 = (libc::EPOLLIN | libc::EPOLLRDHUP) as u32;
+
+/// How messages are delimited on a stream, and where their message ids come from.
+pub trait StreamFraming: Default {
+    /// Bytes written in front of each message (at most `FRAME_HEADER_SIZE`).
+    const HEADER_SIZE: usize;
+
+    /// Fails if a connection in `mode` cannot use this framing.
+    fn check_mode(_mode: AppMode) -> Result<()> {
+        Ok(())
+    }
+
+    /// Splits the next complete message off the front of `buf`, if it holds one. Errors mean
+    /// the stream is corrupt.
+    fn split_message(&mut self, buf: &mut BytesMut) -> Result<Option<(MsgID, ByteBuffer)>>;
+
+    /// Writes the header of a message with `payload_len` bytes into the front of `header`.
+    fn write_header(&self, msg_id: MsgID, payload_len: usize, header: &mut [u8]);
+}
+
+/// A 4 byte length covering the message id and payload, then the 4 byte message id.
+#[derive(Debug, Default)]
+pub struct LengthPrefixed;
+
+impl StreamFraming for LengthPrefixed {
+    const HEADER_SIZE: usize = FRAME_HEADER_SIZE;
+
+    fn split_message(&mut self, buf: &mut BytesMut) -> Result<Option<(MsgID, ByteBuffer)>> {
+        if buf.len() < FRAME_LENGTH_SIZE {
+            return Ok(None);
+        }
+        let len = NetworkEndian::read_u32(&buf[0..FRAME_LENGTH_SIZE]) as usize;
+        ensure!(
+            (HEADER_ID_SIZE..=MAX_MESSAGE_SIZE).contains(&len),
+            "Invalid frame length {}",
+            len
+        );
+        if buf.len() < FRAME_LENGTH_SIZE + len {
+            return Ok(None);
+        }
+        // the frame keeps its header; the payload is exposed through the buffer offset
+        let frame = buf.split_to(FRAME_LENGTH_SIZE + len).freeze();
+        let msg_id = NetworkEndian::read_u32(&frame[FRAME_LENGTH_SIZE..FRAME_HEADER_SIZE]);
+        let bytes = ByteBuffer {
+            bytes: frame,
+            offset: FRAME_HEADER_SIZE,
+            len: len - HEADER_ID_SIZE,
+        };
+        Ok(Some((msg_id, bytes)))
+    }
+
+    fn write_header(&self, msg_id: MsgID, payload_len: usize, header: &mut [u8]) {
+        NetworkEndian::write_u32(
+            &mut header[0..FRAME_LENGTH_SIZE],
+            (HEADER_ID_SIZE + payload_len) as u32,
+        );
+        NetworkEndian::write_u32(&mut header[FRAME_LENGTH_SIZE..FRAME_HEADER_SIZE], msg_id);
+    }
+}
+
+/// Bare RESP: every complete command read so far forms one message, and messages are numbered
+/// in the order they arrive. Replies carry no header, and go out in the order requests came in,
+/// as long as the server answers each message before the next. Only servers can use it, since
+/// nothing on the wire says which request a reply belongs to.
+#[derive(Debug, Default)]
+pub struct RespFraming {
+    next_msg_id: MsgID,
+}
+
+impl StreamFraming for RespFraming {
+    const HEADER_SIZE: usize = 0;
+
+    fn check_mode(mode: AppMode) -> Result<()> {
+        ensure!(
+            mode == AppMode::Server,
+            "Bare RESP streams cannot match replies to requests; only servers can use them"
+        );
+        Ok(())
+    }
+
+    fn split_message(&mut self, buf: &mut BytesMut) -> Result<Option<(MsgID, ByteBuffer)>> {
+        let mut len = 0;
+        while let Some(value_len) = resp_value_len(&buf[len..])? {
+            len += value_len;
+        }
+        if len == 0 {
+            ensure!(
+                buf.len() <= MAX_MESSAGE_SIZE,
+                "No complete RESP value in {} buffered bytes",
+                buf.len()
+            );
+            return Ok(None);
+        }
+        let msg_id = self.next_msg_id;
+        self.next_msg_id = self.next_msg_id.wrapping_add(1);
+        let bytes = ByteBuffer {
+            bytes: buf.split_to(len).freeze(),
+            offset: 0,
+            len,
+        };
+        Ok(Some((msg_id, bytes)))
+    }
+
+    fn write_header(&self, _msg_id: MsgID, _payload_len: usize, _header: &mut [u8]) {}
+}
+
+/// Length of the RESP value at the front of `buf`, or `None` if it is not all there yet.
+fn resp_value_len(buf: &[u8]) -> Result<Option<usize>> {
+    let mut pos = 0;
+    // values left to read, including the elements of the arrays started so far
+    let mut remaining: usize = 1;
+    while remaining > 0 {
+        if pos >= buf.len() {
+            return Ok(None);
+        }
+        let line_end = match buf[pos..].windows(2).position(|w| w == b"\r\n") {
+            Some(line_len) => pos + line_len,
+            None => {
+                return Ok(None);
+            }
+        };
+        ensure!(line_end > pos, "RESP line without a type byte");
+        let line = &buf[pos + 1..line_end];
+        remaining -= 1;
+        match buf[pos] {
+            b'+' | b'-' | b':' => {
+                pos = line_end + 2;
+            }
+            b'$' => {
+                pos = line_end + 2;
+                if let Some(len) = parse_resp_length(line)? {
+                    if buf.len() < pos + len + 2 {
+                        return Ok(None);
+                    }
+                    ensure!(
+                        &buf[pos + len..pos + len + 2] == b"\r\n",
+                        "Bulk string not terminated by CRLF"
+                    );
+                    pos += len + 2;
+                }
+            }
+            b'*' => {
+                pos = line_end + 2;
+                if let Some(len) = parse_resp_length(line)? {
+                    remaining += len;
+                }
+            }
+            other => {
+                bail!("Unexpected RESP type byte {:?}", other as char);
+            }
+        }
+    }
+    Ok(Some(pos))
+}
+
+/// Parses the length of a bulk string or array; `-1` stands for nil.
+fn parse_resp_length(line: &[u8]) -> Result<Option<usize>> {
+    if line == b"-1" {
+        return Ok(None);
+    }
+    match std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+    {
+        Some(len) if len <= MAX_MESSAGE_SIZE => Ok(Some(len)),
+        _ => bail!("Invalid RESP length {:?}", String::from_utf8_lossy(line)),
+    }
+}
+
+/// Owned epoll instance.
+struct Epoll {
+    fd: RawFd,
+}
+
+impl Epoll {
+    fn new() -> Result<Self> {
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).wrap_err("Failed to create epoll instance");
+        }
+        Ok(Epoll { fd })
+    }
+
+    fn control(&self, op: libc::c_int, fd: RawFd, events: u32, token: u64) -> Result<()> {
+        let mut event = libc::epoll_event { events, u64: token };
+        if unsafe { libc::epoll_ctl(self.fd, op, fd, &mut event) } < 0 {
+            return Err(io::Error::last_os_error())
+                .wrap_err(format!("epoll_ctl({}) failed for fd {}", op, fd));
+        }
+        Ok(())
+    }
+
+    fn add(&self, fd: RawFd, events: u32, token: u64) -> Result<()> {
+        self.control(libc::EPOLL_CTL_ADD, fd, events, token)
+    }
+
+    fn modify(&self, fd: RawFd, events: u32, token: u64) -> Result<()> {
+        self.control(libc::EPOLL_CTL_MOD, fd, events, token)
+    }
+
+    fn delete(&self, fd: RawFd) -> Result<()> {
+        self.control(libc::EPOLL_CTL_DEL, fd, 0, 0)
+    }
+
+    /// Fills `events` with the currently ready events, without blocking.
+    fn poll(&self, events: &mut Vec<libc::epoll_event>) -> Result<()> {
+        events.clear();
+        let n = unsafe {
+            libc::epoll_wait(
+                self.fd,
+                events.as_mut_ptr(),
+                events.capacity() as libc::c_int,
+                0,
+            )
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(());
+            }
+            return Err(err).wrap_err("epoll_wait failed");
+        }
+        unsafe {
+            events.set_len(n as usize);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+struct TcpStreamState<F> {
+    stream: TcpStream,
+    /// Peer address (with a filler MAC address).
+    addr: AddressInfo,
+    /// Received bytes not yet parsed into complete frames.
+    recv_buf: BytesMut,
+    /// Bytes the socket did not accept yet; flushed once it becomes writable.
+    send_buf: BytesMut,
+    framing: F,
+}
+
+impl<F> TcpStreamState<F>
+where
+    F: StreamFraming,
+{
+    fn new(stream: TcpStream, addr: AddressInfo) -> Self {
+        TcpStreamState {
+            stream,
+            addr,
+            recv_buf: BytesMut::with_capacity(READ_CHUNK_SIZE),
+            send_buf: BytesMut::new(),
+            framing: F::default(),
+        }
+    }
+}
+
+pub struct LinuxTcpConnection<F = LengthPrefixed>
+where
+    F: StreamFraming,
+{
+    /// Start time.
+    start: Instant,
+    /// Server or client mode
+    mode: AppMode,
+    /// Current window of outstanding packets (used for keeping track of RTTs).
+    outgoing_window: HashMap<(MsgID, ConnID), Instant>,
+    /// Listening socket (servers only)
+    listener: Option<TcpListener>,
+    /// Epoll instance watching the listener and every stream
+    epoll: Epoll,
+    /// Buffer for ready epoll events
+    events: Vec<libc::epoll_event>,
+    /// Map from peer address to connection id
+    address_to_conn_id: HashMap<AddressInfo, ConnID>,
+    /// Open streams, indexed by connection id
+    streams: Vec<Option<TcpStreamState<F>>>,
+    /// Peers of client streams that were closed, by connection id; the next send over the
+    /// connection reconnects to them.
+    closed_peers: HashMap<ConnID, AddressInfo>,
+    /// Messages parsed from streams but not yet popped
+    received: VecDeque<ReceivedPkt<Self>>,
+    /// Transmit accounting (every payload is copied into a socket buffer).
+    transmit_stats: TransmitStats,
+}
+
+impl<F> LinuxTcpConnection<F>
+where
+    F: StreamFraming,
+{
+    fn insert_into_outgoing_map(&mut self, msg_id: MsgID, conn_id: ConnID) {
+        if self.mode == AppMode::Client {
+            if !self.outgoing_window.contains_key(&(msg_id, conn_id)) {
+                self.outgoing_window
+                    .insert((msg_id, conn_id), Instant::now());
+            }
+        }
+    }
+
+    fn add_stream(&mut self, stream: TcpStream, addr: AddressInfo) -> Result<ConnID> {
+        // connection ids of closed client streams stay reserved for their reconnection
+        let conn_id = match (0..self.streams.len())
+            .find(|i| self.streams[*i].is_none() && !self.closed_peers.contains_key(i))
+        {
+            Some(i) => i,
+            None => {
+                bail!("too many concurrent connections; cannot connect to more");
+            }
+        };
+        self.open_stream(conn_id, stream, addr)?;
+        Ok(conn_id)
+    }
+
+    fn open_stream(&mut self, conn_id: ConnID, stream: TcpStream, addr: AddressInfo) -> Result<()> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        self.epoll
+            .add(stream.as_raw_fd(), STREAM_EVENTS, conn_id as u64)?;
+        tracing::debug!(conn_id, "Opened stream with {:?}", addr);
+        self.address_to_conn_id.insert(addr, conn_id);
+        self.streams[conn_id] = Some(TcpStreamState::new(stream, addr));
+        Ok(())
+    }
+
+    /// Reconnects a client stream that was closed. Returns whether the connection has an open
+    /// stream again; failed reconnects are retried on the next send.
+    fn reopen_stream(&mut self, conn_id: ConnID) -> Result<bool> {
+        let addr = match self.closed_peers.get(&conn_id) {
+            Some(addr) => *addr,
+            None => {
+                bail!("No open stream for conn id {}", conn_id);
+            }
+        };
+        let socket_addr = SocketAddr::new(addr.ip_addr, addr.udp_port);
+        match TcpStream::connect(socket_addr) {
+            Ok(stream) => {
+                tracing::info!(conn_id, "Reconnected to {}", socket_addr);
+                self.closed_peers.remove(&conn_id);
+                self.open_stream(conn_id, stream, addr)?;
+                Ok(true)
+            }
+            Err(e) => {
+                tracing::warn!(conn_id, "Failed to reconnect to {}: {:?}", socket_addr, e);
+                Ok(false)
+            }
+        }
+    }
+
+    fn close_stream(&mut self, conn_id: ConnID) {
+        if let Some(state) = self.streams[conn_id].take() {
+            tracing::debug!(conn_id, "Closing stream with {:?}", state.addr);
+            if let Err(e) = self.epoll.delete(state.stream.as_raw_fd()) {
+                tracing::warn!(conn_id, "Failed to stop watching stream: {:?}", e);
+            }
+            match self.mode {
+                // outstanding requests time out and are retransmitted over a new stream
+                AppMode::Client => {
+                    self.closed_peers.insert(conn_id, state.addr);
+                }
+                AppMode::Server => {
+                    self.address_to_conn_id.remove(&state.addr);
+                }
+            }
+        }
+    }
+
+    fn accept_streams(&mut self) -> Result<()> {
+        loop {
+            let accepted = match &self.listener {
+                Some(listener) => listener.accept(),
+                None => {
+                    return Ok(());
+                }
+            };
+            match accepted {
+                Ok((stream, peer)) => {
+                    let addr = AddressInfo::new(
+                        peer.port(),
+                        peer.ip(),
+                        MacAddress::parse_str(FILLER_MAC).unwrap(),
+                    );
+                    if let Err(e) = self.add_stream(stream, addr) {
+                        tracing::warn!("Dropping connection from {}: {:?}", peer, e);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(());
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    return Err(e).wrap_err("Failed to accept connection");
+                }
+            }
+        }
+    }
+
+    /// Reads everything available on the stream and queues each complete frame as a received
+    /// packet.
+    fn read_stream(&mut self, conn_id: ConnID) {
+        let state = match self.streams[conn_id].as_mut() {
+            Some(state) => state,
+            None => {
+                return;
+            }
+        };
+        let mut closed = false;
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        loop {
+            match state.stream.read(&mut chunk) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(n) => {
+                    state.recv_buf.extend_from_slice(&chunk[0..n]);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    break;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    tracing::warn!(conn_id, "Failed to read from stream: {:?}", e);
+                    closed = true;
+                    break;
+                }
+            }
+        }
+
+        loop {
+            match state.framing.split_message(&mut state.recv_buf) {
+                Ok(Some((msg_id, bytes))) => {
+                    self.received
+                        .push_back(ReceivedPkt::new(vec![bytes], msg_id, conn_id));
+                }
+                Ok(None) => {
+                    break;
+                }
+                Err(e) => {
+                    tracing::warn!(conn_id, "Corrupt stream; closing it: {:?}", e);
+                    closed = true;
+                    break;
+                }
+            }
+        }
+
+        if closed {
+            self.close_stream(conn_id);
+        }
+    }
+
+    /// Writes out bytes left over from earlier sends.
+    fn flush_stream(&mut self, conn_id: ConnID) -> Result<()> {
+        let state = match self.streams[conn_id].as_mut() {
+            Some(state) => state,
+            None => {
+                return Ok(());
+            }
+        };
+        while !state.send_buf.is_empty() {
+            match state.stream.write(&state.send_buf) {
+                Ok(n) => {
+                    let _ = state.send_buf.split_to(n);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(());
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    tracing::warn!(conn_id, "Failed to write to stream: {:?}", e);
+                    self.close_stream(conn_id);
+                    return Ok(());
+                }
+            }
+        }
+        self.epoll
+            .modify(state.stream.as_raw_fd(), STREAM_EVENTS, conn_id as u64)
+    }
+
+    /// Handles every ready epoll event: accepts new streams, flushes writable ones and reads
+    /// readable ones.
+    fn poll_streams(&mut self) -> Result<()> {
+        let mut events = std::mem::take(&mut self.events);
+        self.epoll.poll(&mut events)?;
+        for event in events.iter() {
+            let (token, flags) = (event.u64, event.events);
+            if token == LISTENER_TOKEN {
+                self.accept_streams()?;
+                continue;
+            }
+            let conn_id = token as ConnID;
+            if flags & libc::EPOLLOUT as u32 != 0 {
+                self.flush_stream(conn_id)?;
+            }
+            if flags & (STREAM_EVENTS | (libc::EPOLLHUP | libc::EPOLLERR) as u32) != 0 {
+                self.read_stream(conn_id);
+            }
+        }
+        self.events = events;
+        Ok(())
+    }
+
+    /// Frames the message and writes the frame header and segments with one vectored write,
+    /// queueing whatever the socket does not accept.
+    fn send_frame(&mut self, msg_id: MsgID, conn_id: ConnID, segments: &[&[u8]]) -> Result<()> {
+        let payload_len: usize = segments.iter().map(|seg| seg.len()).sum();
+        ensure!(
+            HEADER_ID_SIZE + payload_len <= MAX_MESSAGE_SIZE,
+            "Message of {} bytes too large to frame",
+            payload_len
+        );
+        self.insert_into_outgoing_map(msg_id, conn_id);
+        if !matches!(self.streams.get(conn_id), Some(Some(_))) {
+            let reopened = match self.mode {
+                AppMode::Client => self.reopen_stream(conn_id)?,
+                AppMode::Server => {
+                    tracing::debug!(conn_id, msg_id, "Dropping reply to closed stream");
+                    false
+                }
+            };
+            if !reopened {
+                return Ok(());
+            }
+        }
+        self.transmit_stats.record_message();
+        self.transmit_stats.record_copy(payload_len);
+
+        let state = match self.streams.get_mut(conn_id).and_then(|s| s.as_mut()) {
+            Some(state) => state,
+            None => {
+                bail!("No open stream for conn id {}", conn_id);
+            }
+        };
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        let header = &mut header[0..F::HEADER_SIZE];
+        state.framing.write_header(msg_id, payload_len, header);
+        let pending = !state.send_buf.is_empty();
+        let mut written = 0;
+        // anything already queued has to go out first
+        if !pending {
+            let mut slices: Vec<IoSlice> = Vec::with_capacity(segments.len() + 1);
+            slices.push(IoSlice::new(header));
+            slices.extend(segments.iter().map(|seg| IoSlice::new(seg)));
+            written = match state.stream.write_vectored(&slices) {
+                Ok(n) => n,
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::Interrupted =>
+                {
+                    0
+                }
+                Err(e) => {
+                    tracing::warn!(conn_id, "Failed to write to stream: {:?}", e);
+                    self.close_stream(conn_id);
+                    return Ok(());
+                }
+            };
+        }
+
+        if written < F::HEADER_SIZE + payload_len {
+            queue_unwritten(
+                &mut state.send_buf,
+                written,
+                std::iter::once(&header[..]).chain(segments.iter().copied()),
+            );
+            if !pending {
+                self.epoll.modify(
+                    state.stream.as_raw_fd(),
+                    STREAM_EVENTS | libc::EPOLLOUT as u32,
+                    conn_id as u64,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn pop_received(&mut self) -> Result<Vec<ReceivedPkt<Self>>> {
+        if self.received.len() < RECEIVE_BURST_SIZE {
+            self.poll_streams()?;
+        }
+        let n = std::cmp::min(self.received.len(), RECEIVE_BURST_SIZE);
+        let ret: Vec<ReceivedPkt<Self>> = self.received.drain(0..n).collect();
+        if !ret.is_empty() {
+            tracing::debug!("Received {} packets", ret.len());
+        }
+        Ok(ret)
+    }
+}
+
+/// Appends the bytes of `segments` past the first `written`, which a write already accepted, to
+/// `send_buf`.
+fn queue_unwritten<'a>(
+    send_buf: &mut BytesMut,
+    mut written: usize,
+    segments: impl IntoIterator<Item = &'a [u8]>,
+) {
+    for seg in segments {
+        if written >= seg.len() {
+            written -= seg.len();
+            continue;
+        }
+        send_buf.extend_from_slice(&seg[written..]);
+        written = 0;
+    }
+}
+
+impl<F> Datapath for LinuxTcpConnection<F>
+where
+    F: StreamFraming,
+{
+    type DatapathBuffer = MutableByteBuffer;
+
+    type DatapathMetadata = ByteBuffer;
+
+    type CallbackEntryState = ();
+
+    type PerThreadContext = LinuxPerThreadContext;
+
+    type DatapathSpecificParams = LinuxDatapathSpecificParams;
+
+    fn parse_config_file(
+        config_file: &str,
+        our_ip: &IpAddr,
+    ) -> Result<Self::DatapathSpecificParams> {
+        LinuxConnection::parse_config_file(config_file, our_ip)
+    }
+
+    /// Server queues listen on consecutive ports, as in the UDP datapath. Client ports are
+    /// ignored: the kernel picks an ephemeral port for each outgoing stream.
+    fn compute_affinity(
+        datapath_params: &Self::DatapathSpecificParams,
+        num_queues: usize,
+        remote_ip: Option<IpAddr>,
+        app_mode: AppMode,
+    ) -> Result<Vec<AddressInfo>> {
+        LinuxConnection::compute_affinity(datapath_params, num_queues, remote_ip, app_mode)
+    }
+
+    fn server_queue_port(server_port: u16, queue_id: usize) -> u16 {
+        LinuxConnection::server_queue_port(server_port, queue_id)
+    }
+
+    fn global_init(
+        num_queues: usize,
+        datapath_params: &mut Self::DatapathSpecificParams,
+        addresses: Vec<AddressInfo>,
+    ) -> Result<Vec<Self::PerThreadContext>> {
+        LinuxConnection::global_init(num_queues, datapath_params, addresses)
+    }
+
+    fn per_thread_init(
+        _datapath_params: Self::DatapathSpecificParams,
+        context: Self::PerThreadContext,
+        mode: AppMode,
+    ) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        F::check_mode(mode)?;
+        let epoll = Epoll::new()?;
+        let listener = match mode {
+            AppMode::Server => {
+                let address_info = context.get_address_info();
+                let addr = SocketAddr::new(address_info.ip_addr, address_info.udp_port);
+                tracing::info!("Listening on {}", addr);
+                let listener =
+                    TcpListener::bind(addr).wrap_err(format!("Failed to listen on {}", addr))?;
+                listener.set_nonblocking(true)?;
+                epoll.add(listener.as_raw_fd(), libc::EPOLLIN as u32, LISTENER_TOKEN)?;
+                Some(listener)
+            }
+            AppMode::Client => None,
+        };
+        Ok(LinuxTcpConnection {
+            start: Instant::now(),
+            mode,
+            outgoing_window: HashMap::default(),
+            listener,
+            epoll,
+            events: Vec::with_capacity(RECEIVE_BURST_SIZE),
+            address_to_conn_id: HashMap::default(),
+            streams: (0..MAX_CONCURRENT_CONNECTIONS).map(|_| None).collect(),
+            closed_peers: HashMap::default(),
+            received: VecDeque::with_capacity(RECEIVE_BURST_SIZE),
+            transmit_stats: TransmitStats::default(),
+        })
+    }
+
+    fn connect(&mut self, addr: AddressInfo) -> Result<ConnID> {
+        if let Some(conn_id) = self.address_to_conn_id.get(&addr) {
+            return Ok(*conn_id);
+        }
+        let socket_addr = SocketAddr::new(addr.ip_addr, addr.udp_port);
+        tracing::info!("Connecting to {}", socket_addr);
+        let stream = TcpStream::connect(socket_addr)
+            .wrap_err(format!("Failed to connect to {}", socket_addr))?;
+        self.add_stream(stream, addr)
+    }
+
+    fn connection_address(&self, conn_id: ConnID) -> Option<AddressInfo> {
+        self.streams
+            .get(conn_id)
+            .and_then(|state| state.as_ref())
+            .map(|state| state.addr)
+            .or_else(|| self.closed_peers.get(&conn_id).copied())
+    }
+
+    fn push_buffers_with_copy(&mut self, pkts: &[(MsgID, ConnID, &[u8])]) -> Result<()> {
+        tracing::debug!("Pushing batch of pkts of length {}", pkts.len());
+        for (msg_id, conn_id, data) in pkts.iter() {
+            self.send_frame(*msg_id, *conn_id, &[*data])?;
+        }
+        Ok(())
+    }
+
+    fn queue_single_buffer_with_copy(
+        &mut self,
+        buf: (MsgID, ConnID, &[u8]),
+        _end_batch: bool,
+    ) -> Result<()> {
+        self.send_frame(buf.0, buf.1, &[buf.2])
+    }
+
+    fn queue_metadata_vec(
+        &mut self,
+        msg_id: MsgID,
+        conn_id: ConnID,
+        metadata_vec: Vec<Self::DatapathMetadata>,
+        _end_batch: bool,
+    ) -> Result<()> {
+        let segments: Vec<&[u8]> = metadata_vec.iter().map(|m| m.as_ref()).collect();
+        self.send_frame(msg_id, conn_id, &segments)
+    }
+
+    fn echo(&mut self, mut _pkts: Vec<ReceivedPkt<Self>>) -> Result<()>
+    where
+        Self: Sized,
+    {
+        unimplemented!();
+    }
+
+    fn push_rc_sgas(&mut self, _rc_sgas: &mut [(MsgID, ConnID, RcSga<Self>)]) -> Result<()>
+    where
+        Self: Sized,
+    {
+        unimplemented!();
+    }
+
+    fn push_ordered_sgas(&mut self, ordered_sgas: &[(MsgID, ConnID, OrderedSga)]) -> Result<()> {
+        for (msg_id, conn_id, ordered_sga) in ordered_sgas.iter() {
+            let mut segments: Vec<&[u8]> = Vec::with_capacity(ordered_sga.sga().len() + 1);
+            segments.push(ordered_sga.get_hdr());
+            segments.extend(ordered_sga.sga().iter().map(|sge| sge.addr()));
+            self.send_frame(*msg_id, *conn_id, &segments)?;
+        }
+        Ok(())
+    }
+
+    fn push_sgas(&mut self, sgas: &[(MsgID, ConnID, Sga)]) -> Result<()> {
+        for (msg_id, conn_id, sga) in sgas.iter() {
+            let segments: Vec<&[u8]> = sga.iter().map(|sge| sge.addr()).collect();
+            self.send_frame(*msg_id, *conn_id, &segments)?;
+        }
+        Ok(())
+    }
+
+    fn pop_with_durations(&mut self) -> Result<Vec<(ReceivedPkt<Self>, Duration)>>
+    where
+        Self: Sized,
+    {
+        let received_pkts = self.pop_received()?;
+        let mut ret: Vec<(ReceivedPkt<Self>, Duration)> = Vec::with_capacity(received_pkts.len());
+        for received_pkt in received_pkts.into_iter() {
+            let dur = match self
+                .outgoing_window
+                .remove(&(received_pkt.msg_id(), received_pkt.conn_id()))
+            {
+                Some(start_time) => start_time.elapsed(),
+                None => {
+                    bail!(
+                        "Cannot find msg id {} and conn id {} in outgoing window",
+                        received_pkt.msg_id(),
+                        received_pkt.conn_id()
+                    );
+                }
+            };
+            ret.push((received_pkt, dur));
+        }
+        Ok(ret)
+    }
+
+    fn pop(&mut self) -> Result<Vec<ReceivedPkt<Self>>>
+    where
+        Self: Sized,
+    {
+        self.pop_received()
+    }
+
+    fn timed_out(&self, time_out: Duration) -> Result<Vec<(MsgID, ConnID)>> {
+        Ok(self
+            .outgoing_window
+            .iter()
+            .filter(|(_, start)| start.elapsed() > time_out)
+            .map(|(id, _)| *id)
+            .collect())
+    }
+
+    fn is_registered(&self, _buf: &[u8]) -> bool {
+        false
+    }
+
+    fn allocate(&mut self, size: usize) -> Result<Option<Self::DatapathBuffer>> {
+        // heap buffers: there are no mempools to run out of
+        Ok(Some(MutableByteBuffer::new_with_capacity(size)))
+    }
+
+    fn allocate_tx_buffer(&mut self) -> Result<(Option<Self::DatapathBuffer>, usize)> {
+        unimplemented!();
+    }
+
+    fn get_metadata(&self, buf: Self::DatapathBuffer) -> Result<Option<Self::DatapathMetadata>> {
+        Ok(Some(ByteBuffer::from_buf(buf)))
+    }
+
+    fn recover_metadata(&self, buf: &[u8]) -> Result<Option<Self::DatapathMetadata>> {
+        Ok(Some(ByteBuffer::from_raw_buf(buf)))
+    }
+
+    fn add_memory_pool(
+        &mut self,
+        _size: usize,
+        _min_elts: usize,
+        _num_registration_units: usize,
+        _register_at_start: bool,
+    ) -> Result<Vec<MempoolID>> {
+        Ok(vec![])
+    }
+
+    fn allocate_fallback_mempools(
+        &mut self,
+        _mempool_ids: &mut Vec<MempoolID>,
+        _num_pages: usize,
+        _num_registration_units: usize,
+        _register_at_start: bool,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn header_size(&self) -> usize {
+        F::HEADER_SIZE
+    }
+
+    fn timer_hz(&self) -> u64 {
+        // cycles in a second
+        // (arbitrary constant)
+        1_000_000
+    }
+
+    fn cycles_to_ns(&self, t: u64) -> u64 {
+        t * 1_000
+    }
+
+    fn current_cycles(&self) -> u64 {
+        self.start.elapsed().as_micros() as _
+    }
+
+    fn set_copying_threshold(&mut self, _threshold: usize) {}
+
+    fn get_copying_threshold(&self) -> usize {
+        std::usize::MAX
+    }
+
+    fn set_max_segments(&mut self, _segs: usize) {}
+
+    fn get_max_segments(&self) -> usize {
+        std::usize::MAX
+    }
+
+    #[inline]
+    fn has_mempool(&self, _size: usize) -> bool {
+        true
+    }
+
+    fn set_inline_mode(&mut self, _mode: InlineMode) {}
+
+    fn transmit_stats(&self) -> TransmitStats {
+        self.transmit_stats
+    }
+
+    fn reset_transmit_stats(&mut self) {
+        self.transmit_stats = TransmitStats::default();
+    }
+
+    /// The kernel stack computes checksums on send and drops bad segments on receive.
+    fn checksum_offload_capable(&self) -> bool {
+        true
+    }
+
+    fn set_checksum_mode(&mut self, mode: ChecksumMode) -> Result<()> {
+        ensure!(
+            mode == ChecksumMode::Offload,
+            "Linux TCP datapath always leaves checksums to the kernel; cannot use {:?}",
+            mode
+        );
+        Ok(())
+    }
+
+    fn get_checksum_mode(&self) -> ChecksumMode {
+        ChecksumMode::Offload
+    }
+
+    /// Frames are not limited by the MTU; this only sizes the buffers callers build messages in.
+    fn max_packet_size() -> usize {
+        8192
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn split_all<F: StreamFraming>(framing: &mut F, buf: &mut BytesMut) -> Vec<(MsgID, Vec<u8>)> {
+        let mut messages = Vec::new();
+        while let Some((msg_id, bytes)) = framing.split_message(buf).unwrap() {
+            messages.push((msg_id, bytes.as_ref().to_vec()));
+        }
+        messages
+    }
+
+    fn frame(msg_id: MsgID, payload: &[u8]) -> Vec<u8> {
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        LengthPrefixed.write_header(msg_id, payload.len(), &mut header);
+        [&header[..], payload].concat()
+    }
+
+    fn client() -> LinuxTcpConnection {
+        LinuxTcpConnection {
+            start: Instant::now(),
+            mode: AppMode::Client,
+            outgoing_window: HashMap::default(),
+            listener: None,
+            epoll: Epoll::new().unwrap(),
+            events: Vec::with_capacity(RECEIVE_BURST_SIZE),
+            address_to_conn_id: HashMap::default(),
+            streams: (0..MAX_CONCURRENT_CONNECTIONS).map(|_| None).collect(),
+            closed_peers: HashMap::default(),
+            received: VecDeque::with_capacity(RECEIVE_BURST_SIZE),
+            transmit_stats: TransmitStats::default(),
+        }
+    }
+
+    fn read_frame(stream: &mut TcpStream) -> (MsgID, Vec<u8>) {
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        stream.read_exact(&mut header).unwrap();
+        let len = NetworkEndian::read_u32(&header[0..FRAME_LENGTH_SIZE]) as usize;
+        let mut payload = vec![0u8; len - HEADER_ID_SIZE];
+        stream.read_exact(&mut payload).unwrap();
+        (
+            NetworkEndian::read_u32(&header[FRAME_LENGTH_SIZE..FRAME_HEADER_SIZE]),
+            payload,
+        )
+    }
+
+    #[test]
+    fn split_frames_wait_for_the_rest() {
+        let mut framing = LengthPrefixed;
+        let bytes = frame(7, b"hello");
+        let mut buf = BytesMut::new();
+        // cut inside the length, inside the message id and inside the payload
+        for end in [2, FRAME_LENGTH_SIZE + 1, FRAME_HEADER_SIZE + 3] {
+            buf.clear();
+            buf.extend_from_slice(&bytes[0..end]);
+            assert!(split_all(&mut framing, &mut buf).is_empty());
+            assert_eq!(&buf[..], &bytes[0..end]);
+            buf.extend_from_slice(&bytes[end..]);
+            assert_eq!(
+                split_all(&mut framing, &mut buf),
+                vec![(7, b"hello".to_vec())]
+            );
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn coalesced_frames_split_apart() {
+        let mut framing = LengthPrefixed;
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&frame(1, b"first"));
+        buf.extend_from_slice(&frame(2, b""));
+        buf.extend_from_slice(&frame(3, b"third"));
+        buf.extend_from_slice(&frame(4, b"fourth")[0..6]);
+        assert_eq!(
+            split_all(&mut framing, &mut buf),
+            vec![
+                (1, b"first".to_vec()),
+                (2, Vec::new()),
+                (3, b"third".to_vec())
+            ]
+        );
+        assert_eq!(buf.len(), 6);
+    }
+
+    #[test]
+    fn invalid_frame_lengths_are_rejected() {
+        let mut framing = LengthPrefixed;
+        for len in [0, HEADER_ID_SIZE - 1, MAX_MESSAGE_SIZE + 1] {
+            let mut buf = BytesMut::new();
+            buf.extend_from_slice(&(len as u32).to_be_bytes());
+            assert!(framing.split_message(&mut buf).is_err(), "{}", len);
+        }
+    }
+
+    #[test]
+    fn unwritten_bytes_are_queued_in_order() {
+        let header: &[u8] = b"HHHH";
+        let segments: &[&[u8]] = &[b"abc", b"", b"defg"];
+        let all = [header, segments[0], segments[1], segments[2]].concat();
+        // on segment boundaries, inside the header and inside a segment
+        for written in 0..=all.len() {
+            let mut send_buf = BytesMut::new();
+            send_buf.extend_from_slice(b"old");
+            queue_unwritten(
+                &mut send_buf,
+                written,
+                std::iter::once(header).chain(segments.iter().copied()),
+            );
+            assert_eq!(&send_buf[..], &[&b"old"[..], &all[written..]].concat()[..]);
+        }
+    }
+
+    #[test]
+    fn closed_client_streams_reconnect_on_send() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let addr = AddressInfo::new(port, Ipv4Addr::LOCALHOST, MacAddress::default());
+        let mut connection = client();
+        let conn_id = connection.connect(addr).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        connection.send_frame(1, conn_id, &[b"ping"]).unwrap();
+        assert_eq!(read_frame(&mut server), (1, b"ping".to_vec()));
+        drop(server);
+        while connection.streams[conn_id].is_some() {
+            connection.poll_streams().unwrap();
+        }
+        assert_eq!(connection.connection_address(conn_id), Some(addr));
+        assert_eq!(connection.connect(addr).unwrap(), conn_id);
+
+        // the retransmission goes out over a new stream with the same connection id
+        connection.send_frame(1, conn_id, &[b"ping"]).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        assert_eq!(read_frame(&mut server), (1, b"ping".to_vec()));
+        assert!(connection.closed_peers.is_empty());
+        assert!(connection.outgoing_window.contains_key(&(1, conn_id)));
+    }
+
+    #[test]
+    fn resp_values_are_measured_whole() {
+        let values: &[&[u8]] = &[
+            b"+OK\r\n",
+            b"-ERR unknown command\r\n",
+            b":-42\r\n",
+            b"$-1\r\n",
+            b"$0\r\n\r\n",
+            b"$6\r\nfoo\r\nb\r\n",
+            b"*-1\r\n",
+            b"*0\r\n",
+            b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n",
+            b"*2\r\n*1\r\n:1\r\n$-1\r\n",
+        ];
+        for value in values.iter() {
+            assert_eq!(resp_value_len(value).unwrap(), Some(value.len()));
+            for end in 0..value.len() {
+                assert_eq!(resp_value_len(&value[0..end]).unwrap(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_resp_is_rejected() {
+        let values: &[&[u8]] = &[
+            b"GET key\r\n",
+            b"\r\n",
+            b"$abc\r\n",
+            b"$-2\r\n",
+            b"$3\r\nfoobar\r\n",
+            b"*1\r\n!\r\n",
+        ];
+        for value in values.iter() {
+            assert!(resp_value_len(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn resp_commands_read_together_form_one_message() {
+        let mut framing = RespFraming::default();
+        let get = b"*2\r\n$3\r\nGET\r\n$1\r\na\r\n";
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(get);
+        buf.extend_from_slice(get);
+        buf.extend_from_slice(&get[0..5]);
+        assert_eq!(
+            split_all(&mut framing, &mut buf),
+            vec![(0, [&get[..], &get[..]].concat())]
+        );
+        // the partial command stays buffered until the rest of it arrives
+        assert_eq!(&buf[..], &get[0..5]);
+        buf.extend_from_slice(&get[5..]);
+        assert_eq!(split_all(&mut framing, &mut buf), vec![(1, get.to_vec())]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn resp_framing_writes_no_header() {
+        assert_eq!(RespFraming::HEADER_SIZE, 0);
+        assert!(RespFraming::check_mode(AppMode::Server).is_ok());
+        assert!(RespFraming::check_mode(AppMode::Client).is_err());
+    }
+}
@@ -1,18 +1,19 @@
 use bumpalo;
 use cf_kv::{
     retwis::{RetwisServerLoader, RetwisValueSizeGenerator},
+    storage::StoredValue,
     twitter::TwitterServerLoader,
     KVServer, ListKVServer, MsgType, ServerLoadGenerator,
 };
 use cornflakes_libos::{
     allocator::MempoolID,
-    datapath::{pad_mempool_size, Datapath, InlineMode, ReceivedPkt},
+    datapath::{Datapath, InlineMode, ReceivedPkt},
     dynamic_object_arena_hdr::{CFBytes, CFString},
     {ArenaOrderedRcSga, OrderedSga},
 };
 use cornflakes_utils::{global_debug_init_env, AppMode};
 use mlx5_datapath::datapath::connection::{Mlx5Buffer, Mlx5Connection};
-use std::{ffi::CStr, io::Write, net::Ipv4Addr, str::FromStr};
+use std::{ffi::CStr, io::Write, net::IpAddr, str::FromStr};
 
 #[no_mangle]
 pub extern "C" fn Mlx5_global_debug_init() {
@@ -56,7 +57,7 @@ pub extern "C" fn Mlx5Connection_new(
 ) -> *mut ::std::os::raw::c_void {
     let mut datapath_params = match Mlx5Connection::parse_config_file(
         convert_c_char(config_file).as_str(),
-        &Ipv4Addr::from_str(convert_c_char(server_ip).as_str()).unwrap(),
+        &IpAddr::from_str(convert_c_char(server_ip).as_str()).unwrap(),
     ) {
         Ok(x) => x,
         Err(e) => {
@@ -193,14 +194,58 @@ pub extern "C" fn Mlx5Connection_add_memory_pool(
 
 #[no_mangle]
 pub extern "C" fn Mlx5Connection_set_mempool_params(
+    conn: *mut ::std::os::raw::c_void,
     num_pages_per_mempool: usize,
     num_registration_units: usize,
     register_at_start: usize,
 ) {
+    let mut conn_box = unsafe { Box::from_raw(conn as *mut Mlx5Connection) };
+    let mut config = conn_box.get_mempool_config();
+    config.num_pages = num_pages_per_mempool;
+    config.num_registrations = num_registration_units;
+    config.register_at_start = register_at_start == 1;
+    conn_box.set_mempool_config(config).unwrap();
+    Box::into_raw(conn_box);
+}
+
+#[no_mangle]
+pub extern "C" fn Mlx5Connection_register_external_region(
+    conn: *mut ::std::os::raw::c_void,
+    addr: *mut ::std::os::raw::c_void,
+    len: usize,
+    pgsize: usize,
+    region_id: *mut u32,
+) {
+    let mut conn_box = unsafe { Box::from_raw(conn as *mut Mlx5Connection) };
+    let id = unsafe {
+        conn_box
+            .register_external_region(addr as *mut u8, len, pgsize)
+            .unwrap()
+    };
     unsafe {
-        cornflakes_libos::datapath::NUM_PAGES = num_pages_per_mempool;
-        cornflakes_libos::datapath::NUM_REGISTRATIONS = num_registration_units;
-        cornflakes_libos::datapath::REGISTER_AT_START = register_at_start == 1;
+        *region_id = id;
+    }
+    Box::into_raw(conn_box);
+}
+
+/// Returns 1 if the region was unregistered, 0 if it is still referenced.
+#[no_mangle]
+pub extern "C" fn Mlx5Connection_unregister_external_region(
+    conn: *mut ::std::os::raw::c_void,
+    region_id: u32,
+) -> u32 {
+    let mut conn_box = unsafe { Box::from_raw(conn as *mut Mlx5Connection) };
+    let unregistered = conn_box.unregister_external_region(region_id).unwrap();
+    Box::into_raw(conn_box);
+    unregistered as u32
+}
+
+/// Reference to the registered buffer a stored value lives in, for the caller to free with
+/// `Mlx5Connection_free_datapath_buffer`; null for values kept on the heap.
+fn value_box(value: &StoredValue<Mlx5Connection>) -> *mut ::std::os::raw::c_void {
+    match value {
+        StoredValue::Registered(buf) => Box::into_raw(Box::new(buf.clone())) as _,
+        StoredValue::Inline(_) => std::ptr::null_mut(),
     }
 }
 
@@ -208,6 +253,9 @@ pub extern "C" fn Mlx5Connection_set_mempool_params(
 pub extern "C" fn Mlx5Connection_free_datapath_buffer(
     datapath_buffer: *mut ::std::os::raw::c_void,
 ) {
+    if datapath_buffer.is_null() {
+        return;
+    }
     let _datapath_buffer_box = unsafe { Box::from_raw(datapath_buffer as *mut Mlx5Buffer) };
     tracing::debug!(
         "In free datapath buffer, with datapath buffer ptr: {:?}; has cur refcnt {}",
@@ -237,38 +285,30 @@ pub extern "C" fn Mlx5Connection_allocate_and_copy_into_original_datapath_buffer
 ) -> *mut ::std::os::raw::c_void {
     let mut datapath = unsafe { Box::from_raw(conn as *mut Mlx5Connection) };
     let mut mempool_ids_vec_box = unsafe { Box::from_raw(mempool_ids_vec as *mut Vec<MempoolID>) };
-    let mut datapath_buffer = match datapath
-        .allocate(pad_mempool_size(data_buffer_len))
-        .unwrap()
-    {
+    let padded_size = datapath.mempool_size_class(data_buffer_len);
+    let mut datapath_buffer = match datapath.allocate(padded_size).unwrap() {
         Some(buf) => buf,
         None => {
             println!(
                 "Mempool for size {} doesn't exist; allocating (padded size {})",
-                data_buffer_len,
-                pad_mempool_size(data_buffer_len)
+                data_buffer_len, padded_size
             );
             println!("Probably crashing at mempool ids box line",);
             let num_mempools = mempool_ids_vec_box.len();
             println!("Didn't make it past mempool ids box line");
-            let num_pages = unsafe { cornflakes_libos::datapath::NUM_PAGES };
-            let num_registration_units = unsafe { cornflakes_libos::datapath::NUM_REGISTRATIONS };
-            let register_at_start = unsafe { cornflakes_libos::datapath::REGISTER_AT_START };
+            let config = datapath.get_mempool_config();
             mempool_ids_vec_box.append(
                 &mut datapath
                     .add_memory_pool_with_size(
-                        pad_mempool_size(data_buffer_len),
-                        num_pages,
-                        num_registration_units,
-                        register_at_start,
+                        padded_size,
+                        config.num_pages,
+                        config.num_registrations,
+                        config.register_at_start,
                     )
                     .unwrap(),
             );
             println!("Adding mempool # {}", num_mempools);
-            match datapath
-                .allocate(pad_mempool_size(data_buffer_len))
-                .unwrap()
-            {
+            match datapath.allocate(padded_size).unwrap() {
                 Some(buf) => buf,
                 None => {
                     panic!("Could not allocate");
@@ -298,30 +338,29 @@ pub extern "C" fn Mlx5Connection_allocate_datapath_buffer(
 ) -> *mut ::std::os::raw::c_void {
     let mut datapath = unsafe { Box::from_raw(conn as *mut Mlx5Connection) };
     let mut mempool_ids_vec_box = unsafe { Box::from_raw(mempool_ids_vec as *mut Vec<MempoolID>) };
-    let datapath_buffer = match datapath.allocate(pad_mempool_size(size)).unwrap() {
+    let padded_size = datapath.mempool_size_class(size);
+    let datapath_buffer = match datapath.allocate(padded_size).unwrap() {
         Some(buf) => buf,
         None => {
             tracing::debug!(
                 "Mempool for size {} doesn't exist; allocating (padded size {})",
                 size,
-                pad_mempool_size(size)
+                padded_size
             );
             let num_mempools = mempool_ids_vec_box.len();
-            let num_pages = unsafe { cornflakes_libos::datapath::NUM_PAGES };
-            let num_registration_units = unsafe { cornflakes_libos::datapath::NUM_REGISTRATIONS };
-            let register_at_start = unsafe { cornflakes_libos::datapath::REGISTER_AT_START };
+            let config = datapath.get_mempool_config();
             mempool_ids_vec_box.append(
                 &mut datapath
                     .add_memory_pool_with_size(
-                        pad_mempool_size(size),
-                        num_pages,
-                        num_registration_units,
-                        register_at_start,
+                        padded_size,
+                        config.num_pages,
+                        config.num_registrations,
+                        config.register_at_start,
                     )
                     .unwrap(),
             );
             tracing::info!("Adding mempool # {}", num_mempools);
-            match datapath.allocate(pad_mempool_size(size)).unwrap() {
+            match datapath.allocate(padded_size).unwrap() {
                 Some(buf) => buf,
                 None => {
                     panic!("Could not allocate");
@@ -601,6 +640,14 @@ pub extern "C" fn ReceivedPkt_msg_type(
         MsgType::GetFromList => {
             unimplemented!();
         }
+        MsgType::Delete
+        | MsgType::DeleteM(_)
+        | MsgType::CompareAndSwap
+        | MsgType::Increment
+        | MsgType::Decrement
+        | MsgType::Scan => {
+            unimplemented!();
+        }
     }
     Box::into_raw(pkt);
 }
@@ -839,9 +886,8 @@ pub extern "C" fn Mlx5Connection_get_db_value_at(
         *key_len = key.len();
         *key_ptr = key.as_str().as_ptr() as _;
     }
-    let cloned_value_box = Box::new(value.clone());
     unsafe {
-        *value_box_ptr = Box::into_raw(cloned_value_box) as _;
+        *value_box_ptr = value_box(value);
     }
 
     Box::into_raw(db_keys_vec_box);
@@ -890,9 +936,8 @@ pub extern "C" fn Mlx5Connection_list_db_get_value_at_idx(
         *value_len = value.as_ref().len();
         *value_ptr = value.as_ref().as_ptr() as _;
     }
-    let cloned_value_box = Box::new(value.clone());
     unsafe {
-        *value_box_ptr = Box::into_raw(cloned_value_box) as _;
+        *value_box_ptr = value_box(value);
     }
 
     Box::into_raw(list_db_keys_vec_box);
@@ -18,6 +18,7 @@ struct custom_mlx5_registration_info {
 struct custom_mlx5_mempool {
     void **free_items; /* Array of pointers to free items. */
     uint8_t *ref_counts; /* Array of reference counts for each item in the memory pool */
+    uint32_t *external_ref_counts; /* Per page reference counts of application-owned pools (NULL otherwise); wider than ref_counts as all buffers within a page share one count. */
     size_t allocated; /* Number of allocated items. */
     size_t capacity; /* Total capacity of memory pool. */
     void *buf; /* Actual contiguous region of backing data. */
@@ -124,3 +125,35 @@ int custom_mlx5_mempool_create(struct custom_mlx5_mempool *m,
 
 void custom_mlx5_mempool_destroy(struct custom_mlx5_mempool *m);
 
+/*
+ * custom_mlx5_external_mempool_init - wraps application-owned memory (aligned
+ * to and a multiple of pgsize) in a pool with one item per page, without
+ * registering it. Items are never allocated or freed: the refcnt of each page
+ * starts at 1 for the application's own reference, and every buffer leased
+ * from within the page adds to it.
+ */
+int custom_mlx5_external_mempool_init(struct custom_mlx5_mempool *m,
+                                    void *buf,
+                                    size_t len,
+                                    size_t pgsize);
+
+/*
+ * custom_mlx5_external_mempool_destroy - frees the metadata of an external
+ * pool (but not the application's memory).
+ */
+void custom_mlx5_external_mempool_destroy(struct custom_mlx5_mempool *m);
+
+/* Atomically reads the refcnt of a page of an external pool. */
+uint32_t custom_mlx5_external_refcnt_read(struct custom_mlx5_mempool *m, size_t page_index);
+
+/*
+ * custom_mlx5_external_refcnt_update - atomically changes the refcnt of a page
+ * of an external pool, returning the new count. Pages are never freed into the
+ * pool; a release that would drop the application's own reference is refused.
+ */
+uint32_t custom_mlx5_external_refcnt_update(struct custom_mlx5_mempool *m, size_t page_index, int8_t change);
+
+/* Checks whether any page of an external pool is referenced beyond the
+ * application's own reference. */
+int custom_mlx5_external_mempool_in_use(struct custom_mlx5_mempool *m);
+
@@ -59,6 +59,7 @@ struct recv_mbuf_info {
     size_t ref_count_index;
     uint32_t pkt_len;
     uint32_t rss_hash;
+    uint32_t csum_ok;
 };
 
 
@@ -76,6 +76,20 @@ int custom_mlx5_deregister_region(struct ibv_mr *mr);
 /* Unregisters region backing a memory pool, if necessary, and frees memory pool.*/
 int custom_mlx5_deregister_and_free_custom_mlx5_mempool(struct custom_mlx5_mempool *mempool);
 
+/* Wraps application-owned memory (aligned to and a multiple of pgsize) in a
+ * memory pool with one item per page (see custom_mlx5_external_mempool_init),
+ * and registers it with the NIC. */
+int custom_mlx5_create_external_mempool(struct custom_mlx5_global_context *context,
+                                    struct custom_mlx5_mempool *mempool,
+                                    void *buf,
+                                    size_t len,
+                                    size_t pgsize,
+                                    int registry_flags);
+
+/* Unregisters an external memory pool and frees its metadata (but not the
+ * application's memory). */
+int custom_mlx5_deregister_and_free_external_mempool(struct custom_mlx5_mempool *mempool);
+
 /* Initializes the rx mempools in each per thread context with the given params. */
 int custom_mlx5_init_rx_mempools(struct custom_mlx5_global_context *context,
                         size_t item_len,
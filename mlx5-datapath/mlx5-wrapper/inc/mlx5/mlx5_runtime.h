@@ -300,6 +300,22 @@ struct custom_mlx5_transmission_info *custom_mlx5_add_completion_info(struct cus
 int custom_mlx5_finish_single_transmission(struct custom_mlx5_per_thread_context *per_thread_context,
                                 size_t num_wqes);
 
+/* 
+ * copy_current_frame - Copies the frame described by the work request
+ * currently being constructed (its inlined data, then the data of every data
+ * segment) into a buffer. Call before finish_single_transmission.
+ * Arguments:
+ * @per_thread_context: mlx5 per thread context
+ * @dst - buffer to copy the frame into
+ * @dst_len - length of dst
+ *
+ * Returns:
+ * Length of the frame, or 0 if it does not fit in dst.
+ * */
+size_t custom_mlx5_copy_current_frame(struct custom_mlx5_per_thread_context *per_thread_context,
+                                char *dst,
+                                size_t dst_len);
+
 /* 
  * post_transmissions - Rings doorbell and posts new transmissions for the nic
  * to transmit.
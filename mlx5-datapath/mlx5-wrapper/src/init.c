@@ -84,11 +84,13 @@ int custom_mlx5_allocate_mempool(struct custom_mlx5_mempool *mempool,
                     size_t registration_unit_size,
                     uint32_t use_atomic_ops) {
     int ret = 0;
-    size_t len = item_len * num_items;
-    if (len % data_pgsize != 0) {
-        NETPERF_ERROR("Invalid params to create mempool: (%lu x %lu) not aligned to pgsize %lu", item_len, num_items, data_pgsize);           
+    // items never straddle pages, so items that are not a power of 2 leave slack in each page
+    size_t items_per_page = item_len == 0 ? 0 : data_pgsize / item_len;
+    if (items_per_page == 0 || num_items % items_per_page != 0) {
+        NETPERF_ERROR("Invalid params to create mempool: (%lu x %lu) not aligned to pgsize %lu", item_len, num_items, data_pgsize);
         return -EINVAL;
     }
+    size_t len = (num_items / items_per_page) * data_pgsize;
     if (registration_unit_size % data_pgsize != 0) {
         NETPERF_ERROR("Invalid params to create mempool: (%lu registration unit) not aligned to pgsize %lu", registration_unit_size, data_pgsize);
         return -EINVAL;
@@ -220,6 +222,32 @@ int custom_mlx5_deregister_and_free_custom_mlx5_mempool(struct custom_mlx5_mempo
     return 0;
 }
 
+int custom_mlx5_create_external_mempool(struct custom_mlx5_global_context *context,
+                                    struct custom_mlx5_mempool *mempool,
+                                    void *buf,
+                                    size_t len,
+                                    size_t pgsize,
+                                    int registry_flags) {
+    int ret = custom_mlx5_external_mempool_init(mempool, buf, len, pgsize);
+    if (ret != 0) {
+        return ret;
+    }
+
+    ret = custom_mlx5_register_mempool_unit(context, mempool, 0, registry_flags);
+    if (ret != 0) {
+        NETPERF_ERROR("External mempool registration failed: %s", strerror(-ret));
+        custom_mlx5_external_mempool_destroy(mempool);
+        return ret;
+    }
+    return 0;
+}
+
+int custom_mlx5_deregister_and_free_external_mempool(struct custom_mlx5_mempool *mempool) {
+    int ret = custom_mlx5_deregister_mempool_unit(mempool, 0);
+    custom_mlx5_external_mempool_destroy(mempool);
+    return ret;
+}
+
 int custom_mlx5_init_rx_mempools(struct custom_mlx5_global_context *context, 
                         size_t item_len, 
                         size_t num_items, 
@@ -277,6 +305,10 @@ int custom_mlx5_alloc_tx_pool(struct custom_mlx5_per_thread_context *t_context,
 }
 
 uint16_t custom_mlx5_refcnt_read(struct custom_mlx5_mempool *mempool, size_t refcnt_index) {
+    if (mempool->external_ref_counts != NULL) {
+        uint32_t refcnt = custom_mlx5_external_refcnt_read(mempool, refcnt_index);
+        return refcnt > UINT16_MAX ? UINT16_MAX : (uint16_t)refcnt;
+    }
     return __atomic_load_n(&(mempool->ref_counts[refcnt_index]), __ATOMIC_RELAXED);
 }
 
@@ -284,6 +316,11 @@ int custom_mlx5_refcnt_update_or_free(struct custom_mlx5_mempool *mempool,
         void *buf, 
         size_t refcnt_index, 
         int8_t change) {
+    if (mempool->external_ref_counts != NULL) {
+        // pages of application-owned memory are never freed into the pool
+        custom_mlx5_external_refcnt_update(mempool, refcnt_index, change);
+        return 0;
+    }
     //NETPERF_INFO("Calling refcnt change with %d for mempool %p, mempool, data_mempool %p, mempool buf %p, buf %p, refcnt_index %lu; old: %u, new: %u", change, mempool, mempool, mempool->buf, buf, refcnt_index, mempool->ref_counts[refcnt_index], mempool->ref_counts[refcnt_index] + change);
     if (mempool->use_atomic_ops) {
         // must use atomics to update this as potentially accessing from
@@ -82,7 +82,13 @@ int custom_mlx5_mempool_find_index(struct custom_mlx5_mempool *m, void *item) {
         return -1;
     }*/
     //NETPERF_DEBUG("Log item len: %lu, item: %p, mempool buf: %p, dif: %lu, returned index: %d", m->log_item_len, item, m->buf, (char *)item - (char *)m->buf, (int)(((char *)item - (char *)m->buf) >> m->log_item_len));
-    return (int)(((char *)item - (char *)m->buf) >> m->log_item_len);
+    size_t offset = (char *)item - (char *)m->buf;
+    if ((m->item_len & (m->item_len - 1)) == 0) {
+        return (int)(offset >> m->log_item_len);
+    }
+    /* items of non power of 2 size are packed within each page */
+    size_t items_per_page = m->pgsize / m->item_len;
+    return (int)((offset / m->pgsize) * items_per_page + (offset % m->pgsize) / m->item_len);
 }
 
 int custom_mlx5_is_allocated(struct custom_mlx5_mempool *mempool) {
@@ -115,6 +121,7 @@ void custom_mlx5_clear_mempool(struct custom_mlx5_mempool *mempool) {
     mempool->item_len = 0;
     mempool->registrations = NULL;
     mempool->registration_len = 0;
+    mempool->external_ref_counts = NULL;
 }
 
 static int custom_mlx5_mempool_populate(struct custom_mlx5_mempool *m) {
@@ -195,6 +202,7 @@ int custom_mlx5_mempool_create(struct custom_mlx5_mempool *m, size_t len,
     m->log_item_len = (size_t)(log2((float)item_len));
     m->use_atomic_ops = use_atomic_ops;
     m->registration_len = registration_unit;
+    m->external_ref_counts = NULL;
 
 	return custom_mlx5_mempool_populate(m);
 }
@@ -212,3 +220,81 @@ void custom_mlx5_mempool_destroy(struct custom_mlx5_mempool *m)
     munmap(m->buf, m->len);
     return;
 }
+
+int custom_mlx5_external_mempool_init(struct custom_mlx5_mempool *m,
+                                    void *buf,
+                                    size_t len,
+                                    size_t pgsize) {
+    if (buf == NULL || !is_power_of_two(pgsize) || len == 0 || len % pgsize != 0 || ((uintptr_t)buf & (pgsize - 1)) != 0) {
+        NETPERF_WARN("Invalid params to create external mempool: buf %p, len %lu, pgsize %lu", buf, len, pgsize);
+        return -EINVAL;
+    }
+
+    size_t nr_pages = len / pgsize;
+    custom_mlx5_clear_mempool(m);
+    m->free_items = calloc(nr_pages, sizeof(void *));
+    m->ref_counts = NULL;
+    m->external_ref_counts = calloc(nr_pages, sizeof(uint32_t));
+    m->registrations = calloc(1, sizeof(struct custom_mlx5_registration_info));
+    if (!m->free_items || !m->external_ref_counts || !m->registrations) {
+        NETPERF_DEBUG("Calloc didn't allocate external mempool state.");
+        custom_mlx5_external_mempool_destroy(m);
+        return -ENOMEM;
+    }
+
+    // each page is one item, permanently allocated to the application
+    for (size_t i = 0; i < nr_pages; i++) {
+        m->external_ref_counts[i] = 1;
+    }
+    m->allocated = nr_pages;
+    m->capacity = nr_pages;
+    m->buf = buf;
+    m->len = len;
+    m->pgsize = pgsize;
+    m->num_pages = nr_pages;
+    m->item_len = pgsize;
+    m->log_item_len = (size_t)(log2((float)pgsize));
+    m->use_atomic_ops = 1;
+    m->registration_len = len;
+    m->nr_registrations = 1;
+    m->registrations[0].lkey = -1;
+    m->registrations[0].mr = NULL;
+    m->registrations[0].starting_address = buf;
+    return 0;
+}
+
+void custom_mlx5_external_mempool_destroy(struct custom_mlx5_mempool *m) {
+    free(m->free_items);
+    free(m->external_ref_counts);
+    free(m->registrations);
+    custom_mlx5_clear_mempool(m);
+    m->ref_counts = NULL;
+}
+
+uint32_t custom_mlx5_external_refcnt_read(struct custom_mlx5_mempool *m, size_t page_index) {
+    return __atomic_load_n(&(m->external_ref_counts[page_index]), __ATOMIC_RELAXED);
+}
+
+uint32_t custom_mlx5_external_refcnt_update(struct custom_mlx5_mempool *m, size_t page_index, int8_t change) {
+    uint32_t *refcnt = &(m->external_ref_counts[page_index]);
+    if (change >= 0) {
+        return __atomic_add_fetch(refcnt, (uint32_t)change, __ATOMIC_ACQ_REL);
+    }
+    uint32_t cur = __atomic_load_n(refcnt, __ATOMIC_RELAXED);
+    do {
+        if (cur <= (uint32_t)(change * -1)) {
+            NETPERF_WARN("Releasing %d references to page %lu of external mempool %p with refcnt %u.", change * -1, page_index, m, cur);
+            return cur;
+        }
+    } while (!__atomic_compare_exchange_n(refcnt, &cur, cur - (uint32_t)(change * -1), 0, __ATOMIC_ACQ_REL, __ATOMIC_RELAXED));
+    return cur - (uint32_t)(change * -1);
+}
+
+int custom_mlx5_external_mempool_in_use(struct custom_mlx5_mempool *m) {
+    for (size_t i = 0; i < m->num_pages; i++) {
+        if (custom_mlx5_external_refcnt_read(m, i) > 1) {
+            return 1;
+        }
+    }
+    return 0;
+}
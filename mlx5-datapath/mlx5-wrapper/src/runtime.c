@@ -131,6 +131,7 @@ int custom_mlx5_gather_rx(struct custom_mlx5_per_thread_context *per_thread_cont
         recv_info->ref_count_index = custom_mlx5_mempool_find_index(rx_buf_info->mempool, rx_buf_info->buf_addr);
         recv_info->pkt_len = be32toh(cqe->byte_cnt);
         recv_info->rss_hash = custom_mlx5_get_rss_result(cqe);
+        recv_info->csum_ok = custom_mlx5_mlx5_csum_ok(cqe);
         NETPERF_DEBUG("Result of find index for %p data and mempool buf %p: %d; rx_buf_info ptr; %p", rx_buf_info->buf_addr, rx_mempool->buf, custom_mlx5_mempool_find_index(rx_mempool, rx_buf_info->buf_addr), rx_buf_info);
         NETPERF_DEBUG("Received packet with: addr %p, mempool %p, ref_count_index: %lu, pkt_len: %u", rx_buf_info->buf_addr, rx_buf_info->mempool, recv_info->ref_count_index, recv_info->pkt_len);
         recv_info += 1;
@@ -457,6 +458,46 @@ int custom_mlx5_finish_single_transmission(struct custom_mlx5_per_thread_context
     return 0;
 }
 
+size_t custom_mlx5_copy_current_frame(struct custom_mlx5_per_thread_context *per_thread_context,
+                                char *dst,
+                                size_t dst_len) {
+    struct custom_mlx5_txq *v = &per_thread_context->txq;
+    char *end_ptr = custom_mlx5_work_requests_end(v);
+    struct mlx5_wqe_ctrl_seg *ctrl = (struct mlx5_wqe_ctrl_seg *)custom_mlx5_get_work_request(v, custom_mlx5_current_segment(v));
+    struct mlx5_wqe_eth_seg *eseg = (struct mlx5_wqe_eth_seg *)((char *)ctrl + sizeof(struct mlx5_wqe_ctrl_seg));
+    size_t inline_len = be16toh(eseg->inline_hdr_sz);
+    size_t num_octowords = be32toh(ctrl->qpn_ds) & 0xff;
+    size_t num_segs = ((num_octowords - custom_mlx5_num_octowords(inline_len, 0)) * 16) / sizeof(struct mlx5_wqe_data_seg);
+    size_t frame_len = inline_len;
+    if (inline_len > dst_len) {
+        return 0;
+    }
+
+    // inlined data runs on from the ethernet segment, and can wrap around the ring buffer
+    char *inline_ptr = custom_mlx5_work_request_inline_off(v, 0, 0);
+    if ((inline_ptr + inline_len) > end_ptr) {
+        size_t first_half = end_ptr - inline_ptr;
+        custom_mlx5_rte_memcpy(dst, inline_ptr, first_half);
+        custom_mlx5_rte_memcpy(dst + first_half, (char *)v->tx_qp_dv.sq.buf, inline_len - first_half);
+    } else {
+        custom_mlx5_rte_memcpy(dst, inline_ptr, inline_len);
+    }
+
+    // data segments hold virtual addresses
+    struct mlx5_wqe_data_seg *dpseg = custom_mlx5_dpseg_start(v, inline_len);
+    for (size_t i = 0; i < num_segs; i++) {
+        size_t seg_len = be32toh(dpseg->byte_count);
+        if ((frame_len + seg_len) > dst_len) {
+            return 0;
+        }
+        custom_mlx5_rte_memcpy(dst + frame_len, (char *)be64toh(dpseg->addr), seg_len);
+        frame_len += seg_len;
+        dpseg = custom_mlx5_incr_dpseg(v, dpseg);
+    }
+    NETPERF_DEBUG("Copied frame of length %lu with %lu inlined bytes and %lu data segments", frame_len, inline_len, num_segs);
+    return frame_len;
+}
+
 int custom_mlx5_post_transmissions(struct custom_mlx5_per_thread_context *per_thread_context,
                         struct mlx5_wqe_ctrl_seg *first_ctrl) {
     struct custom_mlx5_txq *v = &per_thread_context->txq;
@@ -0,0 +1,102 @@
+/*
+ * test_external_mempool.c - checks leasing and releasing buffers of
+ * application-owned memory wrapped in an external mempool.
+ */
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include <base/mempool.h>
+
+#define TEST_PGSIZE 4096
+#define TEST_NR_PAGES 4
+
+static void *alloc_region(void) {
+    void *buf = NULL;
+    assert(posix_memalign(&buf, TEST_PGSIZE, TEST_PGSIZE * TEST_NR_PAGES) == 0);
+    memset(buf, 0x42, TEST_PGSIZE * TEST_NR_PAGES);
+    return buf;
+}
+
+static void test_rejects_invalid_regions(void) {
+    struct custom_mlx5_mempool m;
+    char *buf = alloc_region();
+    assert(custom_mlx5_external_mempool_init(&m, NULL, TEST_PGSIZE, TEST_PGSIZE) == -EINVAL);
+    assert(custom_mlx5_external_mempool_init(&m, buf + 1, TEST_PGSIZE, TEST_PGSIZE) == -EINVAL);
+    assert(custom_mlx5_external_mempool_init(&m, buf, TEST_PGSIZE + 1, TEST_PGSIZE) == -EINVAL);
+    assert(custom_mlx5_external_mempool_init(&m, buf, TEST_PGSIZE, 3000) == -EINVAL);
+    free(buf);
+}
+
+static void test_pages_start_with_application_reference(void) {
+    struct custom_mlx5_mempool m;
+    char *buf = alloc_region();
+    assert(custom_mlx5_external_mempool_init(&m, buf, TEST_PGSIZE * TEST_NR_PAGES, TEST_PGSIZE) == 0);
+    assert(m.num_pages == TEST_NR_PAGES);
+    assert(m.allocated == TEST_NR_PAGES);
+    assert(m.ref_counts == NULL);
+    for (size_t i = 0; i < TEST_NR_PAGES; i++) {
+        assert(custom_mlx5_external_refcnt_read(&m, i) == 1);
+        assert(custom_mlx5_mempool_find_index(&m, buf + i * TEST_PGSIZE + 17) == (int)i);
+    }
+    assert(custom_mlx5_external_mempool_in_use(&m) == 0);
+    custom_mlx5_external_mempool_destroy(&m);
+    free(buf);
+}
+
+/* More buffers are leased from one page than an 8-bit refcnt could count. */
+static void test_many_leases_of_one_page(void) {
+    struct custom_mlx5_mempool m;
+    char *buf = alloc_region();
+    const uint32_t nr_leases = 1000;
+    assert(custom_mlx5_external_mempool_init(&m, buf, TEST_PGSIZE * TEST_NR_PAGES, TEST_PGSIZE) == 0);
+
+    for (uint32_t i = 0; i < nr_leases; i++) {
+        assert(custom_mlx5_external_refcnt_update(&m, 2, 1) == i + 2);
+    }
+    assert(custom_mlx5_external_refcnt_read(&m, 2) == nr_leases + 1);
+    assert(custom_mlx5_external_refcnt_read(&m, 1) == 1);
+    assert(custom_mlx5_external_mempool_in_use(&m) == 1);
+
+    for (uint32_t i = 0; i < nr_leases; i++) {
+        assert(custom_mlx5_external_refcnt_update(&m, 2, -1) == nr_leases - i);
+        /* the page is never freed into the pool */
+        assert(m.allocated == TEST_NR_PAGES);
+    }
+    assert(custom_mlx5_external_mempool_in_use(&m) == 0);
+
+    custom_mlx5_external_mempool_destroy(&m);
+    free(buf);
+}
+
+static void test_release_keeps_application_reference(void) {
+    struct custom_mlx5_mempool m;
+    char *buf = alloc_region();
+    assert(custom_mlx5_external_mempool_init(&m, buf, TEST_PGSIZE * TEST_NR_PAGES, TEST_PGSIZE) == 0);
+
+    /* a release without a lease is refused */
+    assert(custom_mlx5_external_refcnt_update(&m, 0, -1) == 1);
+    assert(custom_mlx5_external_refcnt_update(&m, 0, 3) == 4);
+    assert(custom_mlx5_external_refcnt_update(&m, 0, -4) == 4);
+    assert(custom_mlx5_external_refcnt_update(&m, 0, -3) == 1);
+    assert(m.allocated == TEST_NR_PAGES);
+
+    /* destroying the pool leaves the application's memory alone */
+    custom_mlx5_external_mempool_destroy(&m);
+    assert(m.buf == NULL && m.external_ref_counts == NULL);
+    for (size_t i = 0; i < TEST_PGSIZE * TEST_NR_PAGES; i++) {
+        assert(buf[i] == 0x42);
+    }
+    free(buf);
+}
+
+int main(void) {
+    test_rejects_invalid_regions();
+    test_pages_start_with_application_reference();
+    test_many_leases_of_one_page();
+    test_release_keeps_application_reference();
+    printf("test_external_mempool: all tests passed\n");
+    return 0;
+}
@@ -12,6 +12,11 @@ use std::boxed::Box;
 #[derive(Debug, PartialEq, Eq)]
 pub struct DataMempool {
     mempool_ptr: *mut [u8],
+    /// Whether the pages belong to the application (registered external region).
+    external: bool,
+    /// Whether every registration unit was registered when the mempool was allocated, rather
+    /// than by the zero copy cache.
+    registered_at_alloc: bool,
 }
 
 impl Drop for DataMempool {
@@ -19,6 +24,17 @@ impl Drop for DataMempool {
         // (a) drop pages behind mempool itself
         // (b) drop box allocated for registered mempool pointer
         unsafe {
+            if self.external {
+                // application owns the pages: only unregister them
+                if custom_mlx5_deregister_and_free_external_mempool(self.mempool()) != 0 {
+                    tracing::warn!(
+                        "Failed to deregister external mempool at {:?}",
+                        self.mempool()
+                    );
+                }
+                let _ = Box::from_raw(self.mempool_ptr);
+                return;
+            }
             // TODO: might need to change drop to not try to unregister mempool
             if custom_mlx5_deregister_and_free_custom_mlx5_mempool(self.mempool()) != 0 {
                 tracing::warn!(
@@ -62,7 +78,58 @@ impl DataMempool {
     #[inline]
     pub fn new_from_ptr(mempool_ptr: *mut [u8]) -> Self {
         tracing::info!("New mempool at ptr from ptr: {:?}", mempool_ptr,);
-        DataMempool { mempool_ptr }
+        DataMempool {
+            mempool_ptr,
+            external: false,
+            registered_at_alloc: false,
+        }
+    }
+
+    /// Wraps and registers application-owned memory; see
+    /// `Datapath::register_external_region`.
+    #[inline]
+    pub fn new_external(
+        buf: *mut u8,
+        len: usize,
+        pgsize: usize,
+        per_thread_context: &Mlx5PerThreadContext,
+    ) -> Result<Self> {
+        let mempool_box = vec![0u8; unsafe { custom_mlx5_get_custom_mlx5_mempool_size() } as _]
+            .into_boxed_slice();
+        let mempool_ptr = Box::<[u8]>::into_raw(mempool_box);
+        if unsafe {
+            custom_mlx5_create_external_mempool(
+                per_thread_context.get_global_context_ptr(),
+                mempool_ptr as _,
+                buf as _,
+                len as _,
+                pgsize as _,
+                ibv_access_flags_IBV_ACCESS_LOCAL_WRITE as _,
+            )
+        } != 0
+        {
+            unsafe {
+                let _ = Box::from_raw(mempool_ptr);
+            }
+            bail!(
+                "Failed to register external region at {:?} of len {} (pgsize {})",
+                buf,
+                len,
+                pgsize
+            );
+        }
+        tracing::info!("New external mempool at ptr: {:?}", mempool_ptr);
+        Ok(DataMempool {
+            mempool_ptr,
+            external: true,
+            registered_at_alloc: false,
+        })
+    }
+
+    /// Whether any metadata still references pages of this external region.
+    #[inline]
+    pub fn external_region_in_use(&self) -> bool {
+        unsafe { custom_mlx5_external_mempool_in_use(self.mempool()) != 0 }
     }
 
     #[inline]
@@ -108,7 +175,11 @@ impl DataMempool {
             bail!("Failed register mempool with params {:?}", mempool_params);
         }
         tracing::info!("New mempool at ptr: {:?}", mempool_ptr,);
-        Ok(DataMempool { mempool_ptr })
+        Ok(DataMempool {
+            mempool_ptr,
+            external: false,
+            registered_at_alloc: register_at_alloc,
+        })
     }
 
     #[inline]
@@ -120,9 +191,20 @@ impl DataMempool {
         let mempool_start = access!(data_pool, buf, usize);
         let item_len = access!(data_pool, item_len, usize);
         let offset_within_alloc = ptr as usize - mempool_start;
-        let index =
-            (offset_within_alloc & !(item_len - 1)) >> access!(data_pool, log_item_len, usize);
-        let data_ptr = (mempool_start + (index << access!(data_pool, log_item_len, usize)))
+        if item_len & (item_len - 1) == 0 {
+            let index =
+                (offset_within_alloc & !(item_len - 1)) >> access!(data_pool, log_item_len, usize);
+            let data_ptr = (mempool_start + (index << access!(data_pool, log_item_len, usize)))
+                as *mut std::os::raw::c_void;
+            return (data_ptr, index, ptr as usize - data_ptr as usize);
+        }
+        // items of non power of 2 size are packed within each page, leaving slack at the end
+        let pgsize = access!(data_pool, pgsize, usize);
+        let items_per_page = pgsize / item_len;
+        let page = offset_within_alloc / pgsize;
+        let item_within_page = (offset_within_alloc % pgsize) / item_len;
+        let index = page * items_per_page + item_within_page;
+        let data_ptr = (mempool_start + page * pgsize + item_within_page * item_len)
             as *mut std::os::raw::c_void;
         (data_ptr, index, ptr as usize - data_ptr as usize)
     }
@@ -202,11 +284,24 @@ impl DatapathMemoryPool for DataMempool {
         buf: &[u8],
     ) -> Result<<<Self as DatapathMemoryPool>::DatapathImpl as Datapath>::DatapathMetadata> {
         let (data_ptr, index, offset) = unsafe { self.recover_metadata_mbuf(buf.as_ptr()) };
+        // external regions are registered as a single unit for their lifetime, and mempools
+        // registered at allocation keep every unit registered until they are freed
+        let lkey = match (self.external, self.registered_at_alloc) {
+            (true, _) => unsafe { custom_mlx5_mempool_get_lkey(self.mempool(), 0) as u32 },
+            (false, true) => unsafe {
+                let registration_unit = custom_mlx5_mempool_find_registration_unit(
+                    self.mempool(),
+                    closest_2mb_page(data_ptr as *const u8) as *mut ::std::os::raw::c_void,
+                );
+                custom_mlx5_mempool_get_lkey(self.mempool(), registration_unit) as u32
+            },
+            (false, false) => 0,
+        };
         {
             Ok(MbufMetadata::new(
                 data_ptr,
                 self.mempool(),
-                0,
+                lkey,
                 index,
                 offset,
                 buf.len(),
@@ -4,11 +4,21 @@ use super::{
     check, sizes,
 };
 use cornflakes_libos::{
-    allocator::{MemoryPoolAllocator, MempoolID},
-    datapath::{Datapath, DatapathBufferOps, InlineMode, MetadataOps, ReceivedPkt},
+    allocator::{AllocatorMemoryStats, MemoryPoolAllocator, MempoolConfig, MempoolID},
+    datapath::{
+        ChecksumMode, Datapath, DatapathBufferOps, InlineMode, MetadataOps, ReceiveStats,
+        ReceivedPkt, TransmitStats,
+    },
     dynamic_rcsga_hybrid_hdr::HybridArenaRcSgaHdr,
     dynamic_sga_hdr::SgaHeaderRepr,
+    encryption::{EncryptionConfig, TransportEncryption, ENCRYPTION_OVERHEAD, NONCE_LEN},
     mem::PGSIZE_2MB,
+    neighbor::{
+        deferred_with_durations, static_ipv4_entries, NeighborConfig, NeighborStats, NeighborTable,
+        Resolution,
+    },
+    pcap::PacketCapture,
+    rss::{select_source_ports, RssConfig, ServerQueueAssignment, SYMMETRIC_RSS_KEY},
     utils::AddressInfo,
     ArenaDatapathSga, ArenaOrderedRcSga, ArenaOrderedSga, ConnID, CopyContext, MsgID, OrderedRcSga,
     OrderedSga, RcSga, RcSge, SerializationInfo, Sga,
@@ -17,14 +27,14 @@ use cornflakes_libos::{
 use color_eyre::eyre::{bail, ensure, Result, WrapErr};
 use cornflakes_utils::{parse_yaml_map, AppMode};
 use eui48::MacAddress;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use std::{
     boxed::Box,
     ffi::CString,
     fs::read_to_string,
     io::Write,
     mem::MaybeUninit,
-    net::Ipv4Addr,
+    net::{IpAddr, Ipv4Addr},
     path::Path,
     ptr,
     sync::Arc,
@@ -39,6 +49,12 @@ const RECEIVE_BURST_SIZE: usize = 32;
 const MAX_BUFFER_SIZE: usize = 16384;
 const MEMPOOL_MIN_ELTS: usize = 8192;
 const TX_POOL_NUM_REGISTRATIONS: usize = 1;
+/// Shortest Ethernet frame, without the FCS; shorter frames are padded with zeros.
+const MIN_ETHERNET_FRAME_SIZE: usize = 60;
+/// Larger than any MTU the NIC supports.
+const MAX_CAPTURED_FRAME_SIZE: usize = MAX_BUFFER_SIZE;
+/// Size of the RSS indirection table set up by custom_mlx5_qs_init_flows.
+const RSS_INDIRECTION_TABLE_SIZE: usize = 4;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CornflakesMlx5Slab {
@@ -602,6 +618,10 @@ impl Mlx5PerThreadContext {
     pub fn get_global_context_rc(&self) -> Arc<Mlx5GlobalContext> {
         self.global_context_rc.clone()
     }
+
+    pub fn get_global_context_ptr(&self) -> *mut custom_mlx5_global_context {
+        self.global_context_rc.ptr()
+    }
     pub fn get_context_ptr(&self) -> *mut custom_mlx5_per_thread_context {
         self.context
     }
@@ -649,10 +669,14 @@ impl Drop for Mlx5PerThreadContext {
 pub struct Mlx5DatapathSpecificParams {
     custom_mlx5_pci_addr: MaybeUninit<custom_mlx5_pci_addr>,
     eth_addr: MaybeUninit<eth_addr>,
-    our_ip: Ipv4Addr,
+    our_ip: IpAddr,
     our_eth: MacAddress,
     client_port: u16,
     server_port: u16,
+    /// `known_hosts` from the config file, the fallback for ARP resolution.
+    static_neighbors: HashMap<Ipv4Addr, MacAddress>,
+    /// Server queues that client queues should land on, if chosen.
+    server_queue_assignment: Option<ServerQueueAssignment>,
 }
 
 impl Mlx5DatapathSpecificParams {
@@ -664,8 +688,8 @@ impl Mlx5DatapathSpecificParams {
         self.eth_addr.as_mut_ptr()
     }
 
-    pub fn get_ipv4(&self) -> Ipv4Addr {
-        self.our_ip.clone()
+    pub fn get_ip(&self) -> IpAddr {
+        self.our_ip
     }
 
     pub fn get_mac(&self) -> MacAddress {
@@ -701,6 +725,7 @@ impl RecvMbufArray {
                 (*ptr).mempool = ptr::null_mut();
                 (*ptr).ref_count_index = 0;
                 (*ptr).rss_hash = 0;
+                (*ptr).csum_ok = 0;
             }
         }
         RecvMbufArray {
@@ -729,6 +754,7 @@ impl RecvMbufArray {
             (*ptr).mempool = ptr::null_mut();
             (*ptr).ref_count_index = 0;
             (*ptr).rss_hash = 0;
+            (*ptr).csum_ok = 0;
         }
     }
 }
@@ -749,11 +775,12 @@ pub struct Mlx5Connection {
     mode: AppMode,
     /// Current window of outstanding packets (used for keeping track of RTTs).
     outgoing_window: HashMap<(MsgID, ConnID), Instant>,
-    /// Active connections:  current connection IDs mapped to addresses.
+    /// Active connections:  current connection IDs mapped to addresses and header templates,
+    /// of which the first `udp_header_size` bytes for our address are used.
     /// TODO: write code to make it array instead of vector.
     active_connections: [Option<(
         AddressInfo,
-        [u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE],
+        [u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE_V6],
     )>; MAX_CONCURRENT_CONNECTIONS],
     /// Map from AddressInfo to connection id
     address_to_conn_id: HashMap<AddressInfo, ConnID>,
@@ -777,6 +804,27 @@ pub struct Mlx5Connection {
     header_buffer: Vec<u8>,
     /// Zero copy cache
     zero_copy_cache: ZeroCopyCache<CornflakesMlx5Slab>,
+    /// Mempools grown on demand. The zero copy cache cannot forget a slab, so these are
+    /// registered whole when allocated and kept out of it, and freed once idle.
+    uncached_mempools: HashSet<MempoolID>,
+    /// Copy versus zero-copy accounting for transmitted messages.
+    transmit_stats: TransmitStats,
+    /// ARP cache; answers requests for our address and resolves peers on `connect`.
+    neighbors: NeighborTable,
+    /// Packets received while `connect` resolved a peer; `pop` hands them out first.
+    deferred_pkts: Vec<ReceivedPkt<Self>>,
+    /// Whether the NIC computes and checks UDP checksums.
+    checksum_mode: ChecksumMode,
+    /// Checksum offload flags set in the ethernet segment of every transmission.
+    tx_checksum_flags: i32,
+    /// Received packets dropped by this datapath.
+    receive_stats: ReceiveStats,
+    /// Capture of sent and received frames, if enabled.
+    capture: Option<PacketCapture>,
+    /// Transmitted frames are gathered here from the send queue before being captured.
+    capture_buffer: Vec<u8>,
+    /// Payload encryption, if enabled.
+    encryption: Option<TransportEncryption>,
 }
 
 impl Mlx5Connection {
@@ -785,14 +833,152 @@ impl Mlx5Connection {
         Ok(())
     }
 
-    fn finish_transmission(&mut self, num_required: usize, end_batch: bool) -> Result<()> {
-        // finish the transmission
+    pub fn neighbor_stats(&self) -> NeighborStats {
+        self.neighbors.stats()
+    }
+
+    /// Fills in the MAC address of a peer given without one, sending ARP requests until a reply
+    /// arrives. Data packets received in the meantime are deferred to the next `pop`.
+    fn resolve_ether_addr(&mut self, addr: AddressInfo) -> Result<AddressInfo> {
+        loop {
+            let ip = addr
+                .ipv4_addr()
+                .wrap_err("ARP can only resolve IPv4 peers; add the peer to known_hosts")?;
+            match self.neighbors.resolve(&ip, Instant::now())? {
+                Resolution::Resolved(mac) => {
+                    tracing::info!(ip =? ip, mac =? mac, "Resolved peer address");
+                    return Ok(AddressInfo::new(addr.udp_port, ip, mac));
+                }
+                Resolution::Pending(request) => {
+                    if let Some(frame) = request {
+                        self.transmit_frame(&frame)?;
+                    }
+                    // replies are handled in check_received_pkt
+                    let received = self.receive_pkts()?;
+                    self.deferred_pkts.extend(received);
+                }
+            }
+        }
+    }
+
+    /// Receives a burst of packets, answering warmup no-ops in place.
+    fn receive_pkts(&mut self) -> Result<Vec<ReceivedPkt<Self>>> {
+        let received = unsafe {
+            custom_mlx5_gather_rx(
+                self.thread_context.get_context_ptr(),
+                self.recv_mbufs.as_recv_mbuf_info_array_ptr(),
+                RECEIVE_BURST_SIZE as _,
+            )
+        };
+        let mut ret: Vec<ReceivedPkt<Self>> = Vec::with_capacity(RECEIVE_BURST_SIZE);
+        for i in 0..received as usize {
+            if let Some(received_pkt) = self
+                .check_received_pkt(i)
+                .wrap_err("Error receiving packets")?
+            {
+                // if is NO-OP, just return a NO-OP to the caller
+                if received_pkt.is_noop() {
+                    tracing::debug!("Processing NO-OP");
+                    self.process_warmup_noop(received_pkt)?;
+                } else {
+                    ret.push(received_pkt);
+                }
+            } else {
+                // free the mbuf
+                let recv_info = self.recv_mbufs.get(i);
+                unsafe {
+                    custom_mlx5_refcnt_update_or_free(
+                        (*recv_info).mempool,
+                        (*recv_info).buf_addr,
+                        (*recv_info).ref_count_index as _,
+                        -1i8,
+                    );
+                }
+                self.recv_mbufs.clear(i);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Hands a received ARP frame to the neighbor table and transmits the reply, if any.
+    fn process_arp(&mut self, frame: &[u8]) -> Result<()> {
+        match self.neighbors.process_frame(frame, Instant::now()) {
+            Ok(Some(reply)) => self.transmit_frame(&reply),
+            Ok(None) => Ok(()),
+            Err(e) => {
+                tracing::debug!(err =? e, "(recv: dropped) Malformed ARP frame");
+                Ok(())
+            }
+        }
+    }
+
+    /// Transmits a complete Ethernet frame as is, after posting whatever is pending.
+    fn transmit_frame(&mut self, frame: &[u8]) -> Result<()> {
+        let _ = self.post_curr_transmissions_and_get_available_wqes()?;
+        let frame_len = std::cmp::max(frame.len(), MIN_ETHERNET_FRAME_SIZE);
+        let mut data_buffer = match self.allocator.allocate_tx_buffer()? {
+            Some(buf) => buf,
+            None => {
+                bail!("No tx mempools to allocate outgoing frame");
+            }
+        };
+        let dst = data_buffer.mutable_slice(0, frame_len)?;
+        dst[..frame.len()].copy_from_slice(frame);
+        dst[frame.len()..].fill(0);
+        let metadata_mbuf = MbufMetadata::from_buf(data_buffer)?;
+
+        let num_octowords = unsafe { custom_mlx5_num_octowords(0, 1) };
+        let num_wqes_required = unsafe { custom_mlx5_num_wqes_required(num_octowords as _) };
+        while unsafe {
+            custom_mlx5_tx_descriptors_available(
+                self.thread_context.get_context_ptr(),
+                num_wqes_required,
+            ) != 1
+        } {
+            self.poll_for_completions()?;
+        }
+        // no checksum offloads: the frame need not be IP
+        let ctrl_seg = unsafe {
+            custom_mlx5_fill_in_hdr_segment(
+                self.thread_context.get_context_ptr(),
+                num_octowords as _,
+                num_wqes_required as _,
+                0,
+                1,
+                0,
+            )
+        };
+        if ctrl_seg.is_null() {
+            bail!("Error posting header segment for frame");
+        }
         unsafe {
-            custom_mlx5_finish_single_transmission(
+            let dpseg = custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), 0);
+            let completion = custom_mlx5_completion_start(self.thread_context.get_context_ptr());
+            custom_mlx5_add_dpseg(
                 self.thread_context.get_context_ptr(),
-                num_required as _,
+                dpseg,
+                metadata_mbuf.data(),
+                metadata_mbuf.mempool(),
+                metadata_mbuf.lkey(),
+                metadata_mbuf.offset() as _,
+                metadata_mbuf.data_len() as _,
+            );
+            custom_mlx5_add_completion_info(
+                self.thread_context.get_context_ptr(),
+                completion,
+                metadata_mbuf.data(),
+                metadata_mbuf.mempool(),
             );
+            self.finish_single_transmission(num_wqes_required as _);
         }
+        let _ = self.post_curr_transmissions(Some(ctrl_seg))?;
+        self.poll_for_completions()?;
+        Ok(())
+    }
+
+    fn finish_transmission(&mut self, num_required: usize, end_batch: bool) -> Result<()> {
+        // finish the transmission
+        self.finish_single_transmission(num_required);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -804,6 +990,31 @@ impl Mlx5Connection {
         Ok(())
     }
 
+    /// Finishes the work request being filled in, first capturing the frame it describes.
+    fn finish_single_transmission(&mut self, num_wqes: usize) {
+        if let Some(capture) = self.capture.as_mut() {
+            let frame_len = unsafe {
+                custom_mlx5_copy_current_frame(
+                    self.thread_context.get_context_ptr(),
+                    self.capture_buffer.as_mut_ptr() as _,
+                    self.capture_buffer.len() as _,
+                )
+            };
+            if frame_len == 0 {
+                tracing::debug!("Transmitted frame too large to capture");
+            } else {
+                let frame = &self.capture_buffer[0..frame_len as usize];
+                capture.record_frame(std::iter::once(frame));
+            }
+        }
+        unsafe {
+            custom_mlx5_finish_single_transmission(
+                self.thread_context.get_context_ptr(),
+                num_wqes as _,
+            );
+        }
+    }
+
     fn post_ctrl_segment(
         &mut self,
         num_required: usize,
@@ -827,7 +1038,7 @@ impl Mlx5Connection {
                 num_required as _,
                 inline_len as _,
                 total_num_entries as _,
-                MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                self.tx_checksum_flags,
             )
         };
         if ctrl_seg.is_null() {
@@ -878,86 +1089,75 @@ impl Mlx5Connection {
                 cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
             )
         };
-        let (src_eth, _) = match cornflakes_libos::utils::check_eth_hdr(
-            eth_hdr,
-            &self.thread_context.address_info.ether_addr,
+        if cornflakes_libos::neighbor::is_arp_frame(eth_hdr) {
+            let frame_len = std::cmp::min(
+                unsafe { (*recv_mbuf).pkt_len as usize },
+                cornflakes_libos::neighbor::ARP_FRAME_SIZE,
+            );
+            let frame = unsafe { recv_mbuf_slice!(recv_mbuf, 0, frame_len) };
+            self.process_arp(frame)?;
+            return Ok(None);
+        }
+        // the frame ends at pkt_len; the UDP length, not pkt_len, gives the payload length since
+        // short frames are padded
+        let frame = unsafe { recv_mbuf_slice!(recv_mbuf, 0, (*recv_mbuf).pkt_len as usize) };
+        let headers = match cornflakes_libos::utils::check_udp_frame(
+            frame,
+            &self.thread_context.address_info,
         ) {
-            Ok(r) => r,
-            Err(_) => {
+            Ok(headers) => headers,
+            Err(reason) => {
+                tracing::debug!("Dropping received frame: {}", reason);
+                self.receive_stats.record_drop(reason);
                 return Ok(None);
             }
         };
+        let data_len = headers.data_len;
 
-        let ipv4_hdr = unsafe {
-            recv_mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE,
-                cornflakes_libos::utils::IPV4_HEADER2_SIZE
-            )
-        };
-        let (src_ip, _) = match cornflakes_libos::utils::check_ipv4_hdr(
-            ipv4_hdr,
-            &self.thread_context.address_info.ipv4_addr,
-        ) {
-            Ok(r) => r,
-            Err(_) => {
-                return Ok(None);
-            }
-        };
+        if self.checksum_mode == ChecksumMode::Offload && unsafe { (*recv_mbuf).csum_ok == 0 } {
+            tracing::debug!(
+                src =? headers.header_info.src_info,
+                "Dropping packet with bad checksum"
+            );
+            self.receive_stats.record_checksum_failure();
+            return Ok(None);
+        }
 
-        let udp_hdr = unsafe {
-            recv_mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                    + cornflakes_libos::utils::IPV4_HEADER2_SIZE,
-                cornflakes_libos::utils::UDP_HEADER2_SIZE
-            )
-        };
+        let msg_id = headers.msg_id;
 
-        let (src_port, _, data_len) = match cornflakes_libos::utils::check_udp_hdr(
-            udp_hdr,
-            self.thread_context.address_info.udp_port,
-        ) {
-            Ok(p) => p,
-            Err(_) => {
-                return Ok(None);
+        if let Some(capture) = self.capture.as_mut() {
+            capture.record_frame(std::iter::once(frame));
+        }
+
+        // decrypt in place, so the application can still reference the received buffer; frames
+        // that fail to authenticate are dropped before they can take up a connection
+        let (payload_offset, data_len) = match self.encryption.as_mut() {
+            Some(encryption) => {
+                let sealed =
+                    unsafe { recv_mbuf_mut_slice!(recv_mbuf, headers.payload_offset, data_len) };
+                match encryption.open(&headers.header_info.src_info, msg_id, sealed) {
+                    Ok(len) => (headers.payload_offset + NONCE_LEN, len),
+                    Err(e) => {
+                        tracing::debug!(msg_id, "Dropping message: {:?}", e);
+                        self.receive_stats.record_decryption_failure();
+                        return Ok(None);
+                    }
+                }
             }
+            None => (headers.payload_offset, data_len),
         };
 
         // check if this address info is within a current conn_id
-        let src_addr = cornflakes_libos::utils::AddressInfo::new(src_port, src_ip, src_eth);
         let conn_id = self
-            .connect(src_addr)
+            .connect(headers.header_info.src_info)
             .wrap_err("TOO MANY CONCURRENT CONNECTIONS")?;
 
-        let msg_id = unsafe {
-            let slice = recv_mbuf_slice!(
-                recv_mbuf,
-                cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE,
-                cornflakes_libos::utils::HEADER_ID_SIZE
-            );
-            cornflakes_libos::utils::parse_msg_id(&slice)
-        };
-
-        ensure!(
-            data_len
-                == unsafe {
-                    (*recv_mbuf).pkt_len as usize - cornflakes_libos::utils::TOTAL_HEADER_SIZE
-                },
-            format!(
-                "Data len in udp header {} doesn't match pkt_len minus header size {}",
-                data_len,
-                unsafe {
-                    (*recv_mbuf).pkt_len as usize - cornflakes_libos::utils::TOTAL_HEADER_SIZE
-                }
-            )
-        );
         let datapath_metadata = MbufMetadata::new(
             unsafe { (*recv_mbuf).buf_addr },
             unsafe { (*recv_mbuf).mempool },
             0, // registration unit is 0 for rx buffer
             unsafe { (*recv_mbuf).ref_count_index as _ },
-            cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+            payload_offset,
             data_len,
         );
         tracing::debug!(
@@ -971,6 +1171,38 @@ impl Mlx5Connection {
         Ok(Some(received_pkt))
     }
 
+    /// Records how the object header (first entry) of a message was sent.
+    #[inline]
+    fn record_hdr_transmission(&mut self, hdr_len: usize, inlined_obj_hdr: bool) {
+        if hdr_len == 0 {
+            return;
+        }
+        match inlined_obj_hdr {
+            true => self.transmit_stats.record_inline(hdr_len),
+            false => self.transmit_stats.record_copy(hdr_len),
+        }
+    }
+
+    /// Records a cornflakes object serialized with a copy context, given the total data length
+    /// returned when iterating over its entries.
+    #[inline]
+    fn record_cornflakes_obj<'arena>(
+        &mut self,
+        header_len: usize,
+        data_len: usize,
+        copy_context: &CopyContext<'arena, Self>,
+        num_zero_copy_entries: usize,
+    ) {
+        let copy_len = copy_context.data_len();
+        self.transmit_stats.record_serialized_object(
+            header_len,
+            self.inline_mode == InlineMode::ObjectHeader,
+            copy_len,
+            data_len.saturating_sub(header_len + copy_len),
+            num_zero_copy_entries,
+        );
+    }
+
     fn zero_copy_rc_seg(&self, seg: &RcSge<Self>) -> bool {
         match seg {
             RcSge::RawRef(_) => false,
@@ -991,24 +1223,35 @@ impl Mlx5Connection {
         msg_id: MsgID,
         data_len: usize,
     ) -> Result<usize> {
-        let hdr_bytes: &[u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE] =
+        let hdr_bytes: &[u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE_V6] =
             match &self.active_connections[conn_id as usize] {
                 Some((_, hdr_bytes_vec)) => hdr_bytes_vec,
                 None => {
                     bail!("Could not find address for connID");
                 }
             };
+        let header_size = self.header_size();
+        let buffer = data_buffer.mutable_slice(0, header_size)?;
+        let our_ip = &self.thread_context.get_address_info().ip_addr;
+        if our_ip.is_ipv6() {
+            // the C fill-in only knows the ipv4 layout
+            cornflakes_libos::utils::fill_in_hdrs(
+                buffer,
+                &hdr_bytes[..cornflakes_libos::utils::udp_header_size(our_ip)],
+                msg_id,
+                data_len,
+            )?;
+            return Ok(header_size);
+        }
         unsafe {
             fill_in_hdrs(
-                data_buffer
-                    .mutable_slice(0, cornflakes_libos::utils::TOTAL_HEADER_SIZE)?
-                    .as_mut_ptr() as _,
+                buffer.as_mut_ptr() as _,
                 hdr_bytes.as_ptr() as _,
                 msg_id,
                 data_len,
             );
         }
-        Ok(cornflakes_libos::utils::TOTAL_HEADER_SIZE)
+        Ok(header_size)
     }
 
     fn inline_hdr(
@@ -1018,15 +1261,37 @@ impl Mlx5Connection {
         inline_len: usize,
         data_len: usize,
     ) -> Result<()> {
-        // inline ethernet header
-        let hdr_bytes: &[u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE] =
+        let hdr_bytes: &[u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE_V6] =
             match &self.active_connections[conn_id as usize] {
                 Some((_, hdr_bytes_vec)) => hdr_bytes_vec,
                 None => {
                     bail!("Could not find address for connID");
                 }
             };
+        let our_ip = &self.thread_context.get_address_info().ip_addr;
+        if our_ip.is_ipv6() {
+            // the NIC inlining helpers only know the ipv4 layout, so fill in the headers here and
+            // inline them as data
+            let mut hdr = [0u8; cornflakes_libos::utils::TOTAL_HEADER_SIZE_V6];
+            cornflakes_libos::utils::fill_in_hdrs(
+                &mut hdr,
+                &hdr_bytes[..cornflakes_libos::utils::udp_header_size(our_ip)],
+                msg_id,
+                data_len,
+            )?;
+            unsafe {
+                custom_mlx5_copy_inline_data(
+                    self.thread_context.get_context_ptr(),
+                    0,
+                    hdr.as_ptr() as _,
+                    hdr.len() as _,
+                    inline_len as _,
+                );
+            }
+            return Ok(());
+        }
 
+        // inline ethernet header
         let eth_hdr = hdr_bytes[0..cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE].as_ptr()
             as *const eth_hdr;
         unsafe {
@@ -1093,7 +1358,7 @@ impl Mlx5Connection {
             InlineMode::ObjectHeader => {
                 if inline_len > 0 && inline_len <= self.max_inline_size {
                     self.inline_hdr(conn_id, msg_id, inline_len, data_len)?;
-                    let mut offset = cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+                    let mut offset = self.header_size();
                     for seg in sga.iter().take(sga.len()) {
                         unsafe {
                             custom_mlx5_copy_inline_data(
@@ -1137,7 +1402,7 @@ impl Mlx5Connection {
             InlineMode::ObjectHeader => {
                 // unreachable: at this point, we don't know how to inline the protobuf header
                 // directly, because, protobuf expects a contiguous buffer to write into
-                if (cornflakes_libos::utils::TOTAL_HEADER_SIZE + data_len) <= self.max_inline_size {
+                if (self.header_size() + data_len) <= self.max_inline_size {
                     unimplemented!();
                 } else {
                     return Ok((false, 0));
@@ -1164,13 +1429,12 @@ impl Mlx5Connection {
                 Ok((true, 0))
             }
             InlineMode::ObjectHeader => {
-                if (cornflakes_libos::utils::TOTAL_HEADER_SIZE + hdr.len()) <= self.max_inline_size
-                {
+                if (self.header_size() + hdr.len()) <= self.max_inline_size {
                     self.inline_hdr(conn_id, msg_id, inline_len, data_len)?;
                     unsafe {
                         custom_mlx5_copy_inline_data(
                             self.thread_context.get_context_ptr(),
-                            cornflakes_libos::utils::TOTAL_HEADER_SIZE as _,
+                            self.header_size() as _,
                             hdr.as_ptr() as _,
                             hdr.len() as _,
                             inline_len as _,
@@ -1203,12 +1467,11 @@ impl Mlx5Connection {
             }
             InlineMode::ObjectHeader => {
                 self.inline_hdr(conn_id, msg_id, inline_len, data_len)?;
-                if (cornflakes_libos::utils::TOTAL_HEADER_SIZE + hdr.len()) <= self.max_inline_size
-                {
+                if (self.header_size() + hdr.len()) <= self.max_inline_size {
                     unsafe {
                         custom_mlx5_copy_inline_data(
                             self.thread_context.get_context_ptr(),
-                            cornflakes_libos::utils::TOTAL_HEADER_SIZE as _,
+                            self.header_size() as _,
                             hdr.as_ptr() as _,
                             hdr.len() as _,
                             inline_len as _,
@@ -1296,6 +1559,104 @@ impl Mlx5Connection {
         }
     }
 
+    /// Allocates a mempool for buffers of `size` with at least `min_elts` items. With
+    /// `register_at_alloc`, every registration unit is registered now and stays registered
+    /// until the mempool is dropped; otherwise the zero copy cache registers it.
+    fn allocate_data_mempool(
+        &self,
+        size: usize,
+        min_elts: usize,
+        num_registration_units: usize,
+        register_at_alloc: bool,
+    ) -> Result<(sizes::MempoolAllocationParams, DataMempool)> {
+        let actual_size = self.allocator.size_class(size);
+        let mempool_params = sizes::MempoolAllocationParams::new(
+            min_elts,
+            PGSIZE_2MB,
+            actual_size,
+            num_registration_units,
+        )
+        .wrap_err("Incorrect mempool allocation params")?;
+        tracing::info!(mempool_params = ?mempool_params, register_at_alloc, "Adding mempool");
+        let data_mempool = DataMempool::new(
+            &mempool_params,
+            &self.thread_context,
+            true,
+            register_at_alloc,
+        )?;
+        Ok((mempool_params, data_mempool))
+    }
+
+    /// Copies `segments` into a new transmit buffer after the packet header, seals them for the
+    /// connection, and posts the buffer as the single data segment of a work request. Each segment
+    /// comes with whether it would have been zero-copied without encryption.
+    /// If end batch is true, rings the doorbell and polls for completions after posting.
+    fn queue_encrypted_segments(
+        &mut self,
+        msg_id: MsgID,
+        conn_id: ConnID,
+        segments: &[(&[u8], bool)],
+        end_batch: bool,
+    ) -> Result<()> {
+        let peer = match &self.active_connections[conn_id as usize] {
+            Some((addr, _)) => *addr,
+            None => {
+                bail!("Could not find address for connID");
+            }
+        };
+        self.insert_into_outgoing_map(msg_id, conn_id);
+        let num_required =
+            unsafe { custom_mlx5_num_wqes_required(custom_mlx5_num_octowords(0, 1)) } as usize;
+        let mut curr_available_wqes: usize =
+            unsafe { custom_mlx5_num_wqes_available(self.thread_context.get_context_ptr()) }
+                as usize;
+        while num_required > curr_available_wqes {
+            curr_available_wqes = self.post_curr_transmissions_and_get_available_wqes()?;
+        }
+
+        let data_len: usize = segments.iter().map(|(seg, _)| seg.len()).sum();
+        let sealed_len = data_len + ENCRYPTION_OVERHEAD;
+        let mut data_buffer = match self.allocator.allocate_tx_buffer()? {
+            Some(buf) => buf,
+            None => {
+                bail!("No tx mempools to allocate outgoing packet");
+            }
+        };
+        self.copy_hdr(&mut data_buffer, conn_id, msg_id, sealed_len)?;
+        let header_size = self.header_size();
+        let sealed = data_buffer
+            .mutable_slice(header_size, header_size + sealed_len)
+            .wrap_err(format!(
+                "Encrypted message of len {} does not fit in a transmit buffer",
+                sealed_len
+            ))?;
+        let mut offset = NONCE_LEN;
+        for (seg, zero_copy_eligible) in segments.iter() {
+            sealed[offset..offset + seg.len()].copy_from_slice(seg);
+            offset += seg.len();
+            self.transmit_stats
+                .record_encrypted_segment(seg.len(), *zero_copy_eligible);
+        }
+        match self.encryption.as_mut() {
+            Some(encryption) => {
+                encryption.seal(&peer, msg_id, sealed, data_len)?;
+            }
+            None => {
+                bail!("Encryption is not enabled");
+            }
+        }
+        self.transmit_stats.record_message();
+
+        // the buffer's data len covers the header and sealed payload
+        self.post_ctrl_segment(num_required, 0, 1)?;
+        let dpseg = unsafe { custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), 0) };
+        let completion =
+            unsafe { custom_mlx5_completion_start(self.thread_context.get_context_ptr()) };
+        let mut metadata_mbuf = MbufMetadata::from_buf(data_buffer)?;
+        let _ = self.post_mbuf_metadata(&mut metadata_mbuf, dpseg, completion);
+        self.finish_transmission(num_required, end_batch)
+    }
+
     /// Post ordered sgas. This function is only called when there is space for these sgas in the
     /// ring buffer.
     fn post_ordered_sgas(&mut self, sgas: &[(MsgID, ConnID, OrderedSga)]) -> Result<()> {
@@ -1320,7 +1681,7 @@ impl Mlx5Connection {
                     num_wqes_required as _,
                     inline_len as _,
                     num_segs as _,
-                    MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                    self.tx_checksum_flags,
                 )
             };
             if ctrl_seg.is_null() {
@@ -1342,11 +1703,13 @@ impl Mlx5Connection {
 
             // TODO: temporary hack for different code surrounding inlining first entry
             let inlined_obj_hdr = entry_idx == 1;
+            self.transmit_stats.record_message();
+            self.record_hdr_transmission(ordered_sga.get_hdr().len(), inlined_obj_hdr);
 
             let first_zero_copy_seg = ordered_sga.num_copy_entries();
             let allocation_size = ordered_sga.copy_length()
                 - (inlined_obj_hdr as usize * ordered_sga.get_hdr().len())
-                + (!header_written as usize * cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+                + (!header_written as usize * self.header_size());
             let mut dpseg = unsafe {
                 custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), inline_len as _)
             };
@@ -1362,7 +1725,7 @@ impl Mlx5Connection {
                 let mut offset = 0;
                 if !header_written {
                     self.copy_hdr(&mut data_buffer, *conn_id, *msg_id, data_len)?;
-                    offset += cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+                    offset += self.header_size();
                 }
                 if !inlined_obj_hdr {
                     let data_slice =
@@ -1376,6 +1739,8 @@ impl Mlx5Connection {
                     unsafe {
                         mlx5_rte_memcpy(dst.as_mut_ptr() as _, seg.addr().as_ptr() as _, seg.len());
                     }
+                    self.transmit_stats
+                        .record_copied_segment(seg.len(), self.copying_threshold);
                     offset += seg.len();
                 }
 
@@ -1403,17 +1768,13 @@ impl Mlx5Connection {
                 };
                 let (curr_dpseg, curr_completion) =
                     self.post_mbuf_metadata(&mut mbuf_metadata, dpseg, completion);
+                self.transmit_stats.record_zero_copy(curr_seg.len());
 
                 dpseg = curr_dpseg;
                 completion = curr_completion;
             }
 
-            unsafe {
-                custom_mlx5_finish_single_transmission(
-                    self.thread_context.get_context_ptr(),
-                    num_wqes_required,
-                );
-            }
+            self.finish_single_transmission(num_wqes_required as _);
         }
 
         if !first_ctrl_seg.is_null() {
@@ -1482,7 +1843,7 @@ impl Mlx5Connection {
                 num_wqes_required as _,
                 inline_len as _,
                 num_segs as _,
-                MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                self.tx_checksum_flags,
             )
         };
         if ctrl_seg.is_null() {
@@ -1497,6 +1858,11 @@ impl Mlx5Connection {
             data_len,
             rc_sga.get(0).addr(),
         )?;
+        self.transmit_stats.record_message();
+        if entry_idx == 1 {
+            self.transmit_stats
+                .record_inline(rc_sga.get(0).addr().len());
+        }
 
         // get first data segment and corresponding completion segment on ring buffers
         let mut curr_data_seg: *mut mlx5_wqe_data_seg = unsafe {
@@ -1532,6 +1898,8 @@ impl Mlx5Connection {
                 mbuf_metadata.increment_refcnt();
                 let (curr_dpseg, completion) =
                     self.post_mbuf_metadata(&mut mbuf_metadata, curr_data_seg, curr_completion);
+                self.transmit_stats
+                    .record_zero_copy(mbuf_metadata.data_len());
                 curr_data_seg = curr_dpseg;
                 curr_completion = completion;
                 entry_idx += 1;
@@ -1570,6 +1938,8 @@ impl Mlx5Connection {
                             curr_seg.len(),
                         );
                     }
+                    self.transmit_stats
+                        .record_copied_segment(curr_seg.len(), self.copying_threshold);
 
                     write_offset += curr_seg.len();
                 }
@@ -1585,12 +1955,7 @@ impl Mlx5Connection {
         }
 
         // finish the transmission
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_wqes_required,
-            );
-        }
+        self.finish_single_transmission(num_wqes_required as _);
 
         return Ok(ctrl_seg);
     }
@@ -1598,15 +1963,10 @@ impl Mlx5Connection {
     fn sga_with_copy_shape(&self, sga: &ArenaOrderedSga) -> (usize, usize) {
         match self.inline_mode {
             InlineMode::Nothing => (0, 1),
-            InlineMode::PacketHeader => (cornflakes_libos::utils::TOTAL_HEADER_SIZE, 1),
+            InlineMode::PacketHeader => (self.header_size(), 1),
             InlineMode::ObjectHeader => {
-                if (cornflakes_libos::utils::TOTAL_HEADER_SIZE + sga.data_len())
-                    < self.max_inline_size
-                {
-                    (
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE + sga.data_len(),
-                        0,
-                    )
+                if (self.header_size() + sga.data_len()) < self.max_inline_size {
+                    (self.header_size() + sga.data_len(), 0)
                 } else {
                     (0, 1)
                 }
@@ -1621,10 +1981,10 @@ impl Mlx5Connection {
         let buf_len = proto.compute_size() as usize;
         match self.inline_mode {
             InlineMode::Nothing => (0, 1),
-            InlineMode::PacketHeader => (cornflakes_libos::utils::TOTAL_HEADER_SIZE, 1),
+            InlineMode::PacketHeader => (self.header_size(), 1),
             InlineMode::ObjectHeader => {
-                if (cornflakes_libos::utils::TOTAL_HEADER_SIZE + buf_len) < self.max_inline_size {
-                    (cornflakes_libos::utils::TOTAL_HEADER_SIZE + buf_len, 0)
+                if (self.header_size() + buf_len) < self.max_inline_size {
+                    (self.header_size() + buf_len, 0)
                 } else {
                     (0, 1)
                 }
@@ -1635,10 +1995,10 @@ impl Mlx5Connection {
     fn single_buffer_shape(&self, buf: &[u8]) -> (usize, usize) {
         match self.inline_mode {
             InlineMode::Nothing => (0, 1),
-            InlineMode::PacketHeader => (cornflakes_libos::utils::TOTAL_HEADER_SIZE, 1),
+            InlineMode::PacketHeader => (self.header_size(), 1),
             InlineMode::ObjectHeader => {
-                if (cornflakes_libos::utils::TOTAL_HEADER_SIZE + buf.len()) < self.max_inline_size {
-                    (cornflakes_libos::utils::TOTAL_HEADER_SIZE + buf.len(), 0)
+                if (self.header_size() + buf.len()) < self.max_inline_size {
+                    (self.header_size() + buf.len(), 0)
                 } else {
                     (0, 1)
                 }
@@ -1652,23 +2012,21 @@ impl Mlx5Connection {
         match self.inline_mode {
             InlineMode::Nothing => (0, ordered_sga.num_zero_copy_entries() + 1),
             InlineMode::PacketHeader => (
-                cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                self.header_size(),
                 ordered_sga.num_zero_copy_entries()
                     + (ordered_sga.num_zero_copy_entries() < ordered_sga.len()
                         || ordered_sga.get_hdr().len() > 0) as usize,
             ),
             InlineMode::ObjectHeader => {
-                match (ordered_sga.get_hdr().len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE)
-                    <= self.max_inline_size
-                {
+                match (ordered_sga.get_hdr().len() + self.header_size()) <= self.max_inline_size {
                     true => (
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE + ordered_sga.get_hdr().len(),
+                        self.header_size() + ordered_sga.get_hdr().len(),
                         ordered_sga.num_zero_copy_entries()
                             + ((ordered_sga.num_zero_copy_entries() < (ordered_sga.len()))
                                 as usize),
                     ),
                     false => (
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                        self.header_size(),
                         ordered_sga.num_zero_copy_entries()
                             + (ordered_sga.num_zero_copy_entries() < ordered_sga.len()
                                 || ordered_sga.get_hdr().len() > 0)
@@ -1685,23 +2043,21 @@ impl Mlx5Connection {
         match self.inline_mode {
             InlineMode::Nothing => (0, ordered_sga.num_zero_copy_entries() + 1),
             InlineMode::PacketHeader => (
-                cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                self.header_size(),
                 ordered_sga.num_zero_copy_entries()
                     + (ordered_sga.num_zero_copy_entries() < ordered_sga.len()
                         || ordered_sga.get_hdr().len() > 0) as usize,
             ),
             InlineMode::ObjectHeader => {
-                match (ordered_sga.get_hdr().len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE)
-                    <= self.max_inline_size
-                {
+                match (ordered_sga.get_hdr().len() + self.header_size()) <= self.max_inline_size {
                     true => (
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE + ordered_sga.get_hdr().len(),
+                        self.header_size() + ordered_sga.get_hdr().len(),
                         ordered_sga.num_zero_copy_entries()
                             + ((ordered_sga.num_zero_copy_entries() < (ordered_sga.len()))
                                 as usize),
                     ),
                     false => (
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                        self.header_size(),
                         ordered_sga.num_zero_copy_entries()
                             + (ordered_sga.num_zero_copy_entries() < ordered_sga.len()
                                 || ordered_sga.get_hdr().len() > 0)
@@ -1724,14 +2080,14 @@ impl Mlx5Connection {
                     || serialization_info.copy_length > 0)
                     as usize;
                 (
-                    cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                    self.header_size(),
                     num_extra_entries + serialization_info.num_zero_copy_entries,
                 )
             }
             InlineMode::ObjectHeader => {
                 let num_extra_entries = (serialization_info.copy_length > 0) as usize;
                 (
-                    cornflakes_libos::utils::TOTAL_HEADER_SIZE + serialization_info.header_size,
+                    self.header_size() + serialization_info.header_size,
                     num_extra_entries + serialization_info.num_zero_copy_entries,
                 )
             }
@@ -1749,18 +2105,15 @@ impl Mlx5Connection {
             InlineMode::PacketHeader => {
                 if header_len > 0 {
                     (
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                        self.header_size(),
                         1 + num_copy_entries + num_zero_copy_entries,
                     )
                 } else {
-                    (
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE,
-                        num_copy_entries + num_zero_copy_entries,
-                    )
+                    (self.header_size(), num_copy_entries + num_zero_copy_entries)
                 }
             }
             InlineMode::ObjectHeader => (
-                cornflakes_libos::utils::TOTAL_HEADER_SIZE + header_len,
+                self.header_size() + header_len,
                 num_copy_entries + num_zero_copy_entries,
             ),
         }
@@ -1788,19 +2141,17 @@ impl Mlx5Connection {
                     extra += 1;
                 }
                 (
-                    cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                    self.header_size(),
                     arena_datapath_sga.num_zero_copy_entries() + extra,
                 )
             }
             InlineMode::ObjectHeader => match arena_datapath_sga.copy_len() > 0 {
                 true => (
-                    cornflakes_libos::utils::TOTAL_HEADER_SIZE
-                        + arena_datapath_sga.get_header().len(),
+                    self.header_size() + arena_datapath_sga.get_header().len(),
                     arena_datapath_sga.num_zero_copy_entries() + 1,
                 ),
                 false => (
-                    cornflakes_libos::utils::TOTAL_HEADER_SIZE
-                        + arena_datapath_sga.get_header().len(),
+                    self.header_size() + arena_datapath_sga.get_header().len(),
                     arena_datapath_sga.num_zero_copy_entries(),
                 ),
             },
@@ -1811,23 +2162,21 @@ impl Mlx5Connection {
         match self.inline_mode {
             InlineMode::Nothing => (0, ordered_sga.num_zero_copy_entries() + 1),
             InlineMode::PacketHeader => (
-                cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                self.header_size(),
                 ordered_sga.num_zero_copy_entries()
                     + (ordered_sga.num_zero_copy_entries() < ordered_sga.len()
                         || ordered_sga.get_hdr().len() > 0) as usize,
             ),
             InlineMode::ObjectHeader => {
-                match (ordered_sga.get_hdr().len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE)
-                    <= self.max_inline_size
-                {
+                match (ordered_sga.get_hdr().len() + self.header_size()) <= self.max_inline_size {
                     true => (
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE + ordered_sga.get_hdr().len(),
+                        self.header_size() + ordered_sga.get_hdr().len(),
                         ordered_sga.num_zero_copy_entries()
                             + ((ordered_sga.num_zero_copy_entries() < (ordered_sga.len()))
                                 as usize),
                     ),
                     false => (
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                        self.header_size(),
                         ordered_sga.num_zero_copy_entries()
                             + (ordered_sga.num_zero_copy_entries() < ordered_sga.len()
                                 || ordered_sga.get_hdr().len() > 0)
@@ -1843,23 +2192,21 @@ impl Mlx5Connection {
         match self.inline_mode {
             InlineMode::Nothing => (0, ordered_sga.num_zero_copy_entries() + 1),
             InlineMode::PacketHeader => (
-                cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                self.header_size(),
                 ordered_sga.num_zero_copy_entries()
                     + (ordered_sga.num_zero_copy_entries() < ordered_sga.len()
                         || ordered_sga.get_hdr().len() > 0) as usize,
             ),
             InlineMode::ObjectHeader => {
-                match (ordered_sga.get_hdr().len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE)
-                    <= self.max_inline_size
-                {
+                match (ordered_sga.get_hdr().len() + self.header_size()) <= self.max_inline_size {
                     true => (
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE + ordered_sga.get_hdr().len(),
+                        self.header_size() + ordered_sga.get_hdr().len(),
                         ordered_sga.num_zero_copy_entries()
                             + ((ordered_sga.num_zero_copy_entries() < (ordered_sga.len()))
                                 as usize),
                     ),
                     false => (
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                        self.header_size(),
                         ordered_sga.num_zero_copy_entries()
                             + (ordered_sga.num_zero_copy_entries() < ordered_sga.len()
                                 || ordered_sga.get_hdr().len() > 0)
@@ -1961,7 +2308,7 @@ impl Mlx5Connection {
                 num_wqes_required as _,
                 inline_len as _,
                 num_segs as _,
-                MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                self.tx_checksum_flags,
             )
         };
         if ctrl_seg.is_null() {
@@ -1971,6 +2318,10 @@ impl Mlx5Connection {
         let data_len = sga.data_len();
         let (mut header_written, mut entry_idx) =
             self.inline_hdr_if_necessary(conn_id, msg_id, inline_len, data_len, sga.get(0).addr())?;
+        self.transmit_stats.record_message();
+        if entry_idx == 1 {
+            self.transmit_stats.record_inline(sga.get(0).addr().len());
+        }
 
         // get first data segment and corresponding completion segment on ring buffers
         let mut curr_data_seg: *mut mlx5_wqe_data_seg = unsafe {
@@ -2006,6 +2357,7 @@ impl Mlx5Connection {
                 let mut mbuf_metadata = self.allocator.recover_buffer(curr_seg)?.unwrap();
                 let (dpseg, completion) =
                     self.post_mbuf_metadata(&mut mbuf_metadata, curr_data_seg, curr_completion);
+                self.transmit_stats.record_zero_copy(curr_seg.len());
                 curr_data_seg = dpseg;
                 curr_completion = completion;
                 entry_idx += 1;
@@ -2014,7 +2366,7 @@ impl Mlx5Connection {
                 tracing::debug!(entry_idx, "Not zero-copy segment post_sga");
                 let mut curr_idx = entry_idx;
                 let mut mbuf_length = match !header_written && curr_idx == 0 {
-                    true => cornflakes_libos::utils::TOTAL_HEADER_SIZE,
+                    true => self.header_size(),
                     false => 0,
                 };
                 tracing::debug!(mbuf_length, "Finding forward index");
@@ -2060,6 +2412,8 @@ impl Mlx5Connection {
                             curr_seg.len(),
                         );
                     }
+                    self.transmit_stats
+                        .record_copied_segment(curr_seg.len(), self.copying_threshold);
 
                     write_offset += curr_seg.len();
                 }
@@ -2075,12 +2429,7 @@ impl Mlx5Connection {
         }
 
         // finish the transmission
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_wqes_required,
-            );
-        }
+        self.finish_single_transmission(num_wqes_required as _);
 
         return Ok(ctrl_seg);
     }
@@ -2090,15 +2439,15 @@ impl Mlx5Connection {
         let mut sga_idx = 0;
         let (inline_size, num_segs) = match self.inline_mode {
             InlineMode::Nothing => (0, 1),
-            InlineMode::PacketHeader => (cornflakes_libos::utils::TOTAL_HEADER_SIZE, 0),
+            InlineMode::PacketHeader => (self.header_size(), 0),
             InlineMode::ObjectHeader => {
-                let inline_size = first_entry_size + cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+                let inline_size = first_entry_size + self.header_size();
                 match inline_size <= self.max_inline_size {
                     true => {
                         sga_idx += 1;
                         (inline_size, 0)
                     }
-                    false => (cornflakes_libos::utils::TOTAL_HEADER_SIZE, 0),
+                    false => (self.header_size(), 0),
                 }
             }
         };
@@ -2191,7 +2540,7 @@ impl Datapath for Mlx5Connection {
 
     fn parse_config_file(
         config_file: &str,
-        our_ip: &Ipv4Addr,
+        our_ip: &IpAddr,
     ) -> Result<Self::DatapathSpecificParams> {
         // parse the IP to Mac hashmap
         let (ip_to_mac, _mac_to_ip, server_port, client_port) =
@@ -2204,7 +2553,7 @@ impl Datapath for Mlx5Connection {
         let eth_addr = match ip_to_mac.get(our_ip) {
             Some(e) => e.clone(),
             None => {
-                bail!("Could not find eth addr for passed in ipv4 addr {:?} in config_file ip_to_mac map: {:?}", our_ip, ip_to_mac);
+                bail!("Could not find eth addr for passed in ip addr {:?} in config_file ip_to_mac map: {:?}", our_ip, ip_to_mac);
             }
         };
 
@@ -2230,38 +2579,93 @@ impl Datapath for Mlx5Connection {
         Ok(Mlx5DatapathSpecificParams {
             custom_mlx5_pci_addr: custom_mlx5_pci_addr_c,
             eth_addr: ether_addr,
-            our_ip: our_ip.clone(),
+            our_ip: *our_ip,
             our_eth: eth_addr.clone(),
             client_port: client_port,
             server_port: server_port,
+            static_neighbors: static_ipv4_entries(&ip_to_mac),
+            server_queue_assignment: None,
         })
     }
 
     fn compute_affinity(
         datapath_params: &Self::DatapathSpecificParams,
         num_queues: usize,
-        _remote_ip: Option<Ipv4Addr>,
+        remote_ip: Option<IpAddr>,
         app_mode: AppMode,
     ) -> Result<Vec<AddressInfo>> {
-        // TODO: how do we compute affinity for more than one queue for mlx5
-        if num_queues > 1 {
-            bail!("Currently, mlx5 datapath does not support more than one queue");
+        // the NIC's RSS hash only covers ipv4 addresses, so ipv6 flows all land on one queue
+        if datapath_params.get_ip().is_ipv6() && num_queues > 1 {
+            bail!("Currently, only 1 queue supported over ipv6");
         }
         match app_mode {
-            AppMode::Client => Ok(vec![AddressInfo::new(
-                datapath_params.get_client_port(),
-                datapath_params.get_ipv4(),
-                datapath_params.get_mac(),
-            )]),
-
-            AppMode::Server => Ok(vec![AddressInfo::new(
-                datapath_params.get_server_port(),
-                datapath_params.get_ipv4(),
-                datapath_params.get_mac(),
-            )]),
+            AppMode::Client => match &datapath_params.server_queue_assignment {
+                None => {
+                    if num_queues > 1 {
+                        bail!("mlx5 datapath needs a server queue assignment to run more than one client queue");
+                    }
+                    Ok(vec![AddressInfo::new(
+                        datapath_params.get_client_port(),
+                        datapath_params.get_ip(),
+                        datapath_params.get_mac(),
+                    )])
+                }
+                // assumes the server also runs the mlx5 datapath, so it hashes with the same key
+                // and indirection table as we do
+                Some(assignment) => {
+                    let server_ip = match remote_ip {
+                        Some(ip) => ip,
+                        None => {
+                            bail!("For client mode, must specify server ip to compute affinity");
+                        }
+                    };
+                    let server = AddressInfo::new(
+                        datapath_params.get_server_port(),
+                        server_ip,
+                        MacAddress::default(),
+                    );
+                    let our_ip = datapath_params.get_ip();
+                    let rss = RssConfig::new(SYMMETRIC_RSS_KEY, RSS_INDIRECTION_TABLE_SIZE);
+                    // with several client queues, replies must also come back to the right one
+                    let client_rss = if num_queues > 1 { Some(&rss) } else { None };
+                    let ports = select_source_ports(
+                        &rss,
+                        &server,
+                        &our_ip,
+                        datapath_params.get_client_port(),
+                        assignment,
+                        num_queues,
+                        client_rss,
+                    )
+                    .wrap_err("Could not find client ports for requested server queues")?;
+                    tracing::info!(?ports, ?assignment, "Chose client ports");
+                    Ok(ports
+                        .into_iter()
+                        .map(|port| AddressInfo::new(port, our_ip, datapath_params.get_mac()))
+                        .collect())
+                }
+            },
+            // server queues share one address; the NIC spreads flows across them with RSS
+            AppMode::Server => Ok((0..num_queues)
+                .map(|queue_id| {
+                    AddressInfo::new(
+                        Self::server_queue_port(datapath_params.get_server_port(), queue_id),
+                        datapath_params.get_ip(),
+                        datapath_params.get_mac(),
+                    )
+                })
+                .collect()),
         }
     }
 
+    fn set_server_queue_assignment(
+        datapath_params: &mut Self::DatapathSpecificParams,
+        assignment: ServerQueueAssignment,
+    ) -> Result<()> {
+        datapath_params.server_queue_assignment = Some(assignment);
+        Ok(())
+    }
+
     fn global_init(
         num_queues: usize,
         datapath_params: &mut Self::DatapathSpecificParams,
@@ -2385,7 +2789,7 @@ impl Datapath for Mlx5Connection {
     }
 
     fn per_thread_init(
-        _datapath_params: Self::DatapathSpecificParams,
+        datapath_params: Self::DatapathSpecificParams,
         context: Self::PerThreadContext,
         mode: AppMode,
     ) -> Result<Self>
@@ -2405,6 +2809,13 @@ impl Datapath for Mlx5Connection {
         let tx_mempool = DataMempool::new(&mempool_params, &context, false, true)?;
 
         let allocator = MemoryPoolAllocator::new(rx_mempool, tx_mempool)?;
+        let address_info = context.get_address_info().clone();
+        let neighbors = NeighborTable::new(
+            address_info.ether_addr,
+            address_info.ipv4_addr().into_iter().collect(),
+            datapath_params.static_neighbors,
+            NeighborConfig::default(),
+        )?;
 
         Ok(Mlx5Connection {
             thread_context: context,
@@ -2422,12 +2833,28 @@ impl Datapath for Mlx5Connection {
             mbuf_metadatas: Default::default(),
             header_buffer: vec![0u8; Self::max_packet_size()],
             zero_copy_cache: ZeroCopyCache::new(),
+            uncached_mempools: HashSet::default(),
+            transmit_stats: TransmitStats::default(),
+            neighbors,
+            deferred_pkts: Vec::default(),
+            checksum_mode: ChecksumMode::Offload,
+            tx_checksum_flags: MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+            receive_stats: ReceiveStats::default(),
+            capture: None,
+            capture_buffer: Vec::new(),
+            encryption: None,
         })
     }
 
     fn connect(&mut self, addr: AddressInfo) -> Result<ConnID> {
         if self.address_to_conn_id.contains_key(&addr) {
             return Ok(*self.address_to_conn_id.get(&addr).unwrap());
+        } else if addr.is_unresolved() {
+            let resolved_addr = self.resolve_ether_addr(addr)?;
+            let conn_id = self.connect(resolved_addr)?;
+            // later connects with the unresolved address reuse the connection
+            self.address_to_conn_id.insert(addr, conn_id);
+            return Ok(conn_id);
         } else {
             if self.address_to_conn_id.len() >= MAX_CONCURRENT_CONNECTIONS {
                 bail!("too many concurrent connections; cannot connect to more");
@@ -2445,8 +2872,8 @@ impl Datapath for Mlx5Connection {
             }
             match idx {
                 Some(i) => {
-                    let mut bytes: [u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE] =
-                        [0u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE];
+                    let mut bytes: [u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE_V6] =
+                        [0u8; cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE_V6];
                     let header_info = cornflakes_libos::utils::HeaderInfo::new(
                         self.thread_context.get_address_info().clone(),
                         addr.clone(),
@@ -2454,26 +2881,7 @@ impl Datapath for Mlx5Connection {
                     // write in the header to these bytes, assuming data length of 0
                     // data length is updated at runtime and checksums are updated on specific
                     // transmissions
-                    cornflakes_libos::utils::write_eth_hdr(
-                        &header_info,
-                        &mut bytes[0..cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE],
-                    )?;
-                    cornflakes_libos::utils::write_ipv4_hdr(
-                        &header_info,
-                        &mut bytes[cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                            ..(cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                                + cornflakes_libos::utils::IPV4_HEADER2_SIZE)],
-                        42,
-                    )?;
-                    cornflakes_libos::utils::write_udp_hdr(
-                        &header_info,
-                        &mut bytes[(cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                            + cornflakes_libos::utils::IPV4_HEADER2_SIZE)
-                            ..(cornflakes_libos::utils::ETHERNET2_HEADER2_SIZE
-                                + cornflakes_libos::utils::IPV4_HEADER2_SIZE
-                                + cornflakes_libos::utils::UDP_HEADER2_SIZE)],
-                        42,
-                    )?;
+                    cornflakes_libos::utils::write_hdr_template(&header_info, &mut bytes)?;
                     self.active_connections[i] = Some((addr, bytes));
                     return Ok(i);
                 }
@@ -2484,23 +2892,34 @@ impl Datapath for Mlx5Connection {
         }
     }
 
+    fn connection_address(&self, conn_id: ConnID) -> Option<AddressInfo> {
+        self.active_connections
+            .get(conn_id as usize)
+            .and_then(|conn| conn.as_ref().map(|(addr, _)| addr.clone()))
+    }
+
     fn push_buffers_with_copy_iterator<'a>(
         &mut self,
         mut pkts: impl Iterator<Item = (MsgID, ConnID, &'a [u8])>,
     ) -> Result<()> {
+        if self.encryption.is_some() {
+            for (msg_id, conn_id, buf) in pkts {
+                self.queue_encrypted_segments(msg_id, conn_id, &[(buf, false)], false)?;
+            }
+            let _ = self.post_curr_transmissions_and_get_available_wqes()?;
+            return Ok(());
+        }
         let mut first_ctrl_seg: Option<*mut mlx5_wqe_ctrl_seg> = None;
         while let Some((msg_id, conn_id, buf)) = pkts.next() {
             self.insert_into_outgoing_map(msg_id, conn_id);
 
             let (buf_size, inline_len) = match self.inline_mode {
-                InlineMode::Nothing => (buf.len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE, 0),
-                InlineMode::PacketHeader => (buf.len(), cornflakes_libos::utils::TOTAL_HEADER_SIZE),
+                InlineMode::Nothing => (buf.len() + self.header_size(), 0),
+                InlineMode::PacketHeader => (buf.len(), self.header_size()),
                 InlineMode::ObjectHeader => {
-                    match (buf.len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE)
-                        > self.max_inline_size
-                    {
-                        true => (buf.len(), cornflakes_libos::utils::TOTAL_HEADER_SIZE),
-                        false => (0, buf.len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE),
+                    match (buf.len() + self.header_size()) > self.max_inline_size {
+                        true => (buf.len(), self.header_size()),
+                        false => (0, buf.len() + self.header_size()),
                     }
                 }
             };
@@ -2540,18 +2959,19 @@ impl Datapath for Mlx5Connection {
                         num_wqes_required as _,
                         inline_len as _,
                         num_segs as _,
-                        MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                        self.tx_checksum_flags,
                     )
                 };
                 if first_ctrl_seg == None {
                     first_ctrl_seg = Some(ctrl_seg);
                 }
+                self.transmit_stats.record_message();
                 // add next segment
                 let mut written_header = false;
                 let allocation_size;
                 match self.inline_mode {
                     InlineMode::Nothing => {
-                        allocation_size = cornflakes_libos::utils::TOTAL_HEADER_SIZE + buf.len();
+                        allocation_size = self.header_size() + buf.len();
                     }
                     InlineMode::PacketHeader | InlineMode::ObjectHeader => {
                         // inline packet header
@@ -2559,18 +2979,18 @@ impl Datapath for Mlx5Connection {
                         written_header = true;
 
                         if self.inline_mode == InlineMode::ObjectHeader
-                            && (cornflakes_libos::utils::TOTAL_HEADER_SIZE + buf.len()
-                                <= self.max_inline_size)
+                            && (self.header_size() + buf.len() <= self.max_inline_size)
                         {
                             unsafe {
                                 custom_mlx5_copy_inline_data(
                                     self.thread_context.get_context_ptr(),
-                                    cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE as _,
+                                    self.header_size() as _,
                                     buf.as_ptr() as _,
                                     buf.len() as _,
                                     inline_len as _,
                                 );
                             }
+                            self.transmit_stats.record_inline(buf.len());
                             allocation_size = buf.len();
                         } else {
                             allocation_size = 0;
@@ -2592,7 +3012,7 @@ impl Datapath for Mlx5Connection {
                         true => {
                             // copy in the header into a buffer
                             self.copy_hdr(&mut data_buffer, conn_id, msg_id, buf.len())?;
-                            cornflakes_libos::utils::TOTAL_HEADER_SIZE
+                            self.header_size()
                         }
                         false => 0,
                     };
@@ -2600,6 +3020,8 @@ impl Datapath for Mlx5Connection {
                     unsafe {
                         mlx5_rte_memcpy(dst.as_mut_ptr() as _, buf.as_ptr() as _, buf.len());
                     }
+                    self.transmit_stats
+                        .record_copied_segment(buf.len(), self.copying_threshold);
 
                     // now put this inside an mbuf and post it.
                     // attach this data buffer to a metadata buffer
@@ -2630,10 +3052,7 @@ impl Datapath for Mlx5Connection {
                         );
 
                         // finish transmission
-                        custom_mlx5_finish_single_transmission(
-                            self.thread_context.get_context_ptr(),
-                            num_wqes_required,
-                        );
+                        self.finish_single_transmission(num_wqes_required as _);
                     }
                 }
             }
@@ -2645,6 +3064,13 @@ impl Datapath for Mlx5Connection {
 
     fn push_buffers_with_copy(&mut self, pkts: &[(MsgID, ConnID, &[u8])]) -> Result<()> {
         tracing::debug!("Pushing batch of pkts of length {}", pkts.len());
+        if self.encryption.is_some() {
+            for (i, (msg_id, conn_id, buf)) in pkts.iter().enumerate() {
+                let end_batch = i == pkts.len() - 1;
+                self.queue_encrypted_segments(*msg_id, *conn_id, &[(*buf, false)], end_batch)?;
+            }
+            return Ok(());
+        }
         let mut pkt_idx = 0;
         let mut first_ctrl_seg: Option<*mut mlx5_wqe_ctrl_seg> = None;
         while pkt_idx < pkts.len() {
@@ -2652,14 +3078,12 @@ impl Datapath for Mlx5Connection {
             self.insert_into_outgoing_map(msg_id, conn_id);
 
             let (buf_size, inline_len) = match self.inline_mode {
-                InlineMode::Nothing => (buf.len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE, 0),
-                InlineMode::PacketHeader => (buf.len(), cornflakes_libos::utils::TOTAL_HEADER_SIZE),
+                InlineMode::Nothing => (buf.len() + self.header_size(), 0),
+                InlineMode::PacketHeader => (buf.len(), self.header_size()),
                 InlineMode::ObjectHeader => {
-                    match (buf.len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE)
-                        > self.max_inline_size
-                    {
-                        true => (buf.len(), cornflakes_libos::utils::TOTAL_HEADER_SIZE),
-                        false => (0, buf.len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE),
+                    match (buf.len() + self.header_size()) > self.max_inline_size {
+                        true => (buf.len(), self.header_size()),
+                        false => (0, buf.len() + self.header_size()),
                     }
                 }
             };
@@ -2699,18 +3123,19 @@ impl Datapath for Mlx5Connection {
                         num_wqes_required as _,
                         inline_len as _,
                         num_segs as _,
-                        MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                        self.tx_checksum_flags,
                     )
                 };
                 if first_ctrl_seg == None {
                     first_ctrl_seg = Some(ctrl_seg);
                 }
+                self.transmit_stats.record_message();
                 // add next segment
                 let mut written_header = false;
                 let allocation_size;
                 match self.inline_mode {
                     InlineMode::Nothing => {
-                        allocation_size = cornflakes_libos::utils::TOTAL_HEADER_SIZE + buf.len();
+                        allocation_size = self.header_size() + buf.len();
                     }
                     InlineMode::PacketHeader | InlineMode::ObjectHeader => {
                         // inline packet header
@@ -2718,18 +3143,18 @@ impl Datapath for Mlx5Connection {
                         written_header = true;
 
                         if self.inline_mode == InlineMode::ObjectHeader
-                            && (cornflakes_libos::utils::TOTAL_HEADER_SIZE + buf.len()
-                                <= self.max_inline_size)
+                            && (self.header_size() + buf.len() <= self.max_inline_size)
                         {
                             unsafe {
                                 custom_mlx5_copy_inline_data(
                                     self.thread_context.get_context_ptr(),
-                                    cornflakes_libos::utils::TOTAL_UDP_HEADER_SIZE as _,
+                                    self.header_size() as _,
                                     buf.as_ptr() as _,
                                     buf.len() as _,
                                     inline_len as _,
                                 );
                             }
+                            self.transmit_stats.record_inline(buf.len());
                             allocation_size = buf.len();
                         } else {
                             allocation_size = 0;
@@ -2751,7 +3176,7 @@ impl Datapath for Mlx5Connection {
                         true => {
                             // copy in the header into a buffer
                             self.copy_hdr(&mut data_buffer, conn_id, msg_id, buf.len())?;
-                            cornflakes_libos::utils::TOTAL_HEADER_SIZE
+                            self.header_size()
                         }
                         false => 0,
                     };
@@ -2759,6 +3184,8 @@ impl Datapath for Mlx5Connection {
                     unsafe {
                         mlx5_rte_memcpy(dst.as_mut_ptr() as _, buf.as_ptr() as _, buf.len());
                     }
+                    self.transmit_stats
+                        .record_copied_segment(buf.len(), self.copying_threshold);
 
                     // now put this inside an mbuf and post it.
                     // attach this data buffer to a metadata buffer
@@ -2790,10 +3217,7 @@ impl Datapath for Mlx5Connection {
                         );
 
                         // finish transmission
-                        custom_mlx5_finish_single_transmission(
-                            self.thread_context.get_context_ptr(),
-                            num_wqes_required,
-                        );
+                        self.finish_single_transmission(num_wqes_required as _);
                     }
                 }
                 pkt_idx += 1;
@@ -2808,6 +3232,14 @@ impl Datapath for Mlx5Connection {
     where
         Self: Sized,
     {
+        if self.encryption.is_some() {
+            // received payloads were decrypted in place, so echoes are sealed into new buffers
+            let bufs = pkts
+                .iter()
+                .map(|pkt| (pkt.msg_id(), pkt.conn_id(), pkt.seg(0).as_ref()))
+                .collect::<Vec<(MsgID, ConnID, &[u8])>>();
+            return self.push_buffers_with_copy(&bufs);
+        }
         // iterate over pkts, flip the header in these packets, and transmit them back
         // need to post to both the normal ring buffer and the completions ring buffer
         let mut pkt_idx = 0;
@@ -2847,11 +3279,12 @@ impl Datapath for Mlx5Connection {
                         num_wqes_required as _,
                         inline_len as _,
                         num_segs as _,
-                        MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                        self.tx_checksum_flags,
                     );
                     if first_ctrl_seg == None {
                         first_ctrl_seg = Some(ctrl_seg);
                     }
+                    self.transmit_stats.record_message();
                     // add a dpseg and a completion info for each received packet in the mbuf
                     let mut curr_dpseg =
                         custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), 0);
@@ -2859,6 +3292,7 @@ impl Datapath for Mlx5Connection {
                         custom_mlx5_completion_start(self.thread_context.get_context_ptr());
                     for seg in received_pkt.iter_mut() {
                         seg.increment_refcnt();
+                        self.transmit_stats.record_zero_copy(seg.data_len());
                         curr_dpseg = custom_mlx5_add_dpseg(
                             self.thread_context.get_context_ptr(),
                             curr_dpseg,
@@ -2866,7 +3300,7 @@ impl Datapath for Mlx5Connection {
                             seg.mempool(),
                             seg.lkey(),
                             0,
-                            (seg.data_len() + cornflakes_libos::utils::TOTAL_HEADER_SIZE) as _,
+                            (seg.data_len() + self.header_size()) as _,
                         );
 
                         curr_completion = custom_mlx5_add_completion_info(
@@ -2878,10 +3312,7 @@ impl Datapath for Mlx5Connection {
                     }
 
                     // now finish the transmission
-                    custom_mlx5_finish_single_transmission(
-                        self.thread_context.get_context_ptr(),
-                        num_wqes_required,
-                    );
+                    self.finish_single_transmission(num_wqes_required as _);
                 }
                 pkt_idx += 1;
             }
@@ -2896,6 +3327,19 @@ impl Datapath for Mlx5Connection {
         Self: Sized,
     {
         tracing::debug!(len = rc_sgas.len(), "Pushing rc_sgas");
+        if self.encryption.is_some() {
+            for (i, (msg_id, conn_id, rc_sga)) in rc_sgas.iter().enumerate() {
+                let segments = (0..rc_sga.len())
+                    .map(|j| {
+                        let seg = rc_sga.get(j);
+                        (seg.addr(), self.zero_copy_rc_seg(seg))
+                    })
+                    .collect::<Vec<(&[u8], bool)>>();
+                let end_batch = i == rc_sgas.len() - 1;
+                self.queue_encrypted_segments(*msg_id, *conn_id, &segments, end_batch)?;
+            }
+            return Ok(());
+        }
         let mut first_ctrl_seg: Option<*mut mlx5_wqe_ctrl_seg> = None;
         let mut sga_idx = 0;
         while sga_idx < rc_sgas.len() {
@@ -2949,6 +3393,22 @@ impl Datapath for Mlx5Connection {
     }
 
     fn push_ordered_sgas(&mut self, sgas: &[(MsgID, ConnID, OrderedSga)]) -> Result<()> {
+        if self.encryption.is_some() {
+            for (i, (msg_id, conn_id, ordered_sga)) in sgas.iter().enumerate() {
+                let segments = std::iter::once((ordered_sga.get_hdr(), false))
+                    .chain(
+                        ordered_sga
+                            .sga()
+                            .iter()
+                            .enumerate()
+                            .map(|(j, seg)| (seg.addr(), j >= ordered_sga.num_copy_entries())),
+                    )
+                    .collect::<Vec<(&[u8], bool)>>();
+                let end_batch = i == sgas.len() - 1;
+                self.queue_encrypted_segments(*msg_id, *conn_id, &segments, end_batch)?;
+            }
+            return Ok(());
+        }
         self.push_ordered_sgas_recursive(sgas)
     }
 
@@ -2994,6 +3454,10 @@ impl Datapath for Mlx5Connection {
         let msg_id = sga.0;
         let conn_id = sga.1;
         let object = sga.2;
+        if self.encryption.is_some() {
+            let buf = object.write_to_bytes()?;
+            return self.queue_encrypted_segments(msg_id, conn_id, &[(&buf, false)], end_batch);
+        }
 
         let num_required = self.wqes_required_protobuf(object);
         while num_required > curr_available_wqes {
@@ -3034,7 +3498,7 @@ impl Datapath for Mlx5Connection {
                     num_wqes_required as _,
                     inline_len as _,
                     num_segs as _,
-                    MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                    self.tx_checksum_flags,
                 )
             };
             if ctrl_seg.is_null() {
@@ -3051,6 +3515,11 @@ impl Datapath for Mlx5Connection {
             self.inline_proto_if_necessary(conn_id, msg_id, inline_len, data_len, object)?;
         // TODO: temporary hack for different code surrounding inlining first entry
         let inlined_obj_hdr = entry_idx > 0;
+        self.transmit_stats.record_message();
+        match inlined_obj_hdr {
+            true => self.transmit_stats.record_inline(data_len),
+            false => self.transmit_stats.record_copy(data_len),
+        }
         tracing::debug!(
             inlined_obj_hdr,
             header_written,
@@ -3059,7 +3528,7 @@ impl Datapath for Mlx5Connection {
         );
 
         let allocation_size = data_len - (inlined_obj_hdr as usize * data_len)
-            + (!header_written as usize * cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+            + (!header_written as usize * self.header_size());
         let dpseg = unsafe {
             custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), inline_len as _)
         };
@@ -3081,7 +3550,7 @@ impl Datapath for Mlx5Connection {
             let mut offset = 0;
             if !header_written {
                 self.copy_hdr(&mut data_buffer, conn_id, msg_id, data_len)?;
-                offset += cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+                offset += self.header_size();
             }
             if !inlined_obj_hdr {
                 // copy protobuf object into datapath buffer
@@ -3096,12 +3565,7 @@ impl Datapath for Mlx5Connection {
             let _ = self.post_mbuf_metadata(&mut metadata_mbuf, dpseg, completion);
         }
 
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_wqes_required as _,
-            );
-        }
+        self.finish_single_transmission(num_wqes_required as _);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -3128,6 +3592,17 @@ impl Datapath for Mlx5Connection {
         let msg_id = sga.0;
         let conn_id = sga.1;
         let sga = sga.2;
+        if self.encryption.is_some() {
+            let segments = std::iter::once((sga.get_hdr(), false))
+                .chain(
+                    sga.entries_slice(0, sga.len())
+                        .iter()
+                        .enumerate()
+                        .map(|(i, seg)| (seg.addr(), i >= sga.num_copy_entries())),
+                )
+                .collect::<Vec<(&[u8], bool)>>();
+            return self.queue_encrypted_segments(msg_id, conn_id, &segments, end_batch);
+        }
         tracing::debug!(
             data_len = sga.data_len(),
             segments = sga.len(),
@@ -3176,7 +3651,7 @@ impl Datapath for Mlx5Connection {
                     num_wqes_required as _,
                     inline_len as _,
                     num_segs as _,
-                    MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                    self.tx_checksum_flags,
                 )
             };
             if ctrl_seg.is_null() {
@@ -3194,6 +3669,10 @@ impl Datapath for Mlx5Connection {
 
         // TODO: temporary hack for different code surrounding inlining first entry
         let inlined_obj_hdr = entry_idx > 0;
+        self.transmit_stats.record_message();
+        if inlined_obj_hdr {
+            self.transmit_stats.record_inline(data_len);
+        }
         tracing::debug!(
             inlined_obj_hdr,
             header_written,
@@ -3202,7 +3681,7 @@ impl Datapath for Mlx5Connection {
         );
 
         let allocation_size = data_len - (inlined_obj_hdr as usize * data_len)
-            + (!header_written as usize * cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+            + (!header_written as usize * self.header_size());
         let dpseg = unsafe {
             custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), inline_len as _)
         };
@@ -3229,13 +3708,14 @@ impl Datapath for Mlx5Connection {
             let mut offset = 0;
             if !header_written {
                 self.copy_hdr(&mut data_buffer, conn_id, msg_id, data_len)?;
-                offset += cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+                offset += self.header_size();
             }
             if !inlined_obj_hdr {
                 for seg in sga.iter().take(sga.len()) {
                     let data_slice = data_buffer.mutable_slice(offset, offset + seg.len())?;
                     data_slice.copy_from_slice(seg.addr());
                     offset += seg.len();
+                    self.transmit_stats.record_copy(seg.len());
                 }
             }
 
@@ -3243,12 +3723,7 @@ impl Datapath for Mlx5Connection {
             let _ = self.post_mbuf_metadata(&mut metadata_mbuf, dpseg, completion);
         }
 
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_wqes_required as _,
-            );
-        }
+        self.finish_single_transmission(num_wqes_required as _);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -3277,6 +3752,9 @@ impl Datapath for Mlx5Connection {
         let msg_id = sga.0;
         let conn_id = sga.1;
         let buf = sga.2;
+        if self.encryption.is_some() {
+            return self.queue_encrypted_segments(msg_id, conn_id, &[(buf, false)], end_batch);
+        }
 
         let num_required = self.wqes_required_single_buffer(buf);
 
@@ -3317,7 +3795,7 @@ impl Datapath for Mlx5Connection {
                     num_wqes_required as _,
                     inline_len as _,
                     num_segs as _,
-                    MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                    self.tx_checksum_flags,
                 )
             };
             if ctrl_seg.is_null() {
@@ -3334,9 +3812,11 @@ impl Datapath for Mlx5Connection {
 
         // TODO: temporary hack for different code surrounding inlining first entry
         let inlined_obj_hdr = entry_idx == 1;
+        self.transmit_stats.record_message();
+        self.record_hdr_transmission(buf.len(), inlined_obj_hdr);
 
         let allocation_size = buf.len() - (inlined_obj_hdr as usize * buf.len())
-            + (!header_written as usize * cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+            + (!header_written as usize * self.header_size());
         let dpseg = unsafe {
             custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), inline_len as _)
         };
@@ -3365,12 +3845,7 @@ impl Datapath for Mlx5Connection {
             let _ = self.post_mbuf_metadata(&mut metadata_mbuf, dpseg, completion);
         }
 
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_wqes_required as _,
-            );
-        }
+        self.finish_single_transmission(num_wqes_required as _);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -3387,6 +3862,10 @@ impl Datapath for Mlx5Connection {
         addr: (ConnID, MsgID),
         data_len: usize,
     ) -> Result<Self::DatapathBuffer> {
+        ensure!(
+            self.encryption.is_none(),
+            "Buffers serialized behind a UDP header cannot be sent with encryption on"
+        );
         let mut data_buffer = {
             match self.allocator.allocate_tx_buffer()? {
                 Some(buf) => buf,
@@ -3398,7 +3877,7 @@ impl Datapath for Mlx5Connection {
         // copy UDP header with provided data length
         self.copy_hdr(&mut data_buffer, addr.0, addr.1, data_len)?;
         // set length on buffer
-        data_buffer.set_len(cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+        data_buffer.set_len(self.header_size());
         Ok(data_buffer)
     }
 
@@ -3407,6 +3886,10 @@ impl Datapath for Mlx5Connection {
         data_buffer: Box<Self::DatapathBuffer>,
         end_batch: bool,
     ) -> Result<()> {
+        ensure!(
+            self.encryption.is_none(),
+            "Buffers serialized behind a UDP header cannot be sent with encryption on"
+        );
         // assume no inlining
         let num_required = 1;
         let mut curr_available_wqes =
@@ -3441,7 +3924,7 @@ impl Datapath for Mlx5Connection {
                 num_required as _,
                 inline_len as _,
                 num_segs as _,
-                MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                self.tx_checksum_flags,
             )
         };
         if ctrl_seg.is_null() {
@@ -3454,15 +3937,14 @@ impl Datapath for Mlx5Connection {
         let dpseg = unsafe { custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), 0) };
         let completion =
             unsafe { custom_mlx5_completion_start(self.thread_context.get_context_ptr()) };
+        // application serialized directly into the transmit buffer
+        self.transmit_stats.record_message();
+        self.transmit_stats
+            .record_copy(data_buffer.data_len.saturating_sub(self.header_size()));
         let mut metadata_mbuf = MbufMetadata::from_buf(*data_buffer)?;
         let _ = self.post_mbuf_metadata(&mut metadata_mbuf, dpseg, completion);
 
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_required as _,
-            );
-        }
+        self.finish_single_transmission(num_required as _);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -3492,6 +3974,18 @@ impl Datapath for Mlx5Connection {
         let msg_id = sga.0;
         let conn_id = sga.1;
         let ordered_sga = sga.2;
+        if self.encryption.is_some() {
+            let segments = std::iter::once((ordered_sga.get_hdr(), false))
+                .chain(
+                    ordered_sga
+                        .entries_slice(0, ordered_sga.len())
+                        .iter()
+                        .enumerate()
+                        .map(|(i, seg)| (seg.addr(), i >= ordered_sga.num_copy_entries())),
+                )
+                .collect::<Vec<(&[u8], bool)>>();
+            return self.queue_encrypted_segments(msg_id, conn_id, &segments, end_batch);
+        }
 
         let num_required = {
             #[cfg(feature = "profiler")]
@@ -3539,7 +4033,7 @@ impl Datapath for Mlx5Connection {
                     num_wqes_required as _,
                     inline_len as _,
                     num_segs as _,
-                    MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                    self.tx_checksum_flags,
                 )
             };
             if ctrl_seg.is_null() {
@@ -3562,11 +4056,13 @@ impl Datapath for Mlx5Connection {
 
         // TODO: temporary hack for different code surrounding inlining first entry
         let inlined_obj_hdr = entry_idx == 1;
+        self.transmit_stats.record_message();
+        self.record_hdr_transmission(ordered_sga.get_hdr().len(), inlined_obj_hdr);
 
         let first_zero_copy_seg = ordered_sga.num_copy_entries();
         let allocation_size = ordered_sga.copy_length()
             - (inlined_obj_hdr as usize * ordered_sga.get_hdr().len())
-            + (!header_written as usize * cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+            + (!header_written as usize * self.header_size());
         let mut dpseg = unsafe {
             custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), inline_len as _)
         };
@@ -3590,7 +4086,7 @@ impl Datapath for Mlx5Connection {
             let mut offset = 0;
             if !header_written {
                 self.copy_hdr(&mut data_buffer, conn_id, msg_id, data_len)?;
-                offset += cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+                offset += self.header_size();
             }
             if !inlined_obj_hdr {
                 let data_slice =
@@ -3607,6 +4103,8 @@ impl Datapath for Mlx5Connection {
                     unsafe {
                         mlx5_rte_memcpy(dst.as_mut_ptr() as _, seg.addr().as_ptr() as _, seg.len());
                     }
+                    self.transmit_stats
+                        .record_copied_segment(seg.len(), self.copying_threshold);
                     offset += seg.len();
                 }
             }
@@ -3631,6 +4129,8 @@ impl Datapath for Mlx5Connection {
                     RcSge::RefCounted(mbuf_metadata) => {
                         let mut mbuf_copy = mbuf_metadata.clone();
                         mbuf_copy.increment_refcnt();
+                        self.transmit_stats
+                            .record_zero_copy(mbuf_metadata.data_len());
                         tracing::debug!(seg =? mbuf_metadata.as_ref().as_ptr(), "Cur posting seg");
                         let (curr_dpseg, curr_completion) = {
                             tracing::debug!(
@@ -3668,12 +4168,7 @@ impl Datapath for Mlx5Connection {
                 }
             }
         }
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_wqes_required as _,
-            );
-        }
+        self.finish_single_transmission(num_wqes_required as _);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -3695,6 +4190,12 @@ impl Datapath for Mlx5Connection {
         #[cfg(feature = "profiler")]
         demikernel::timer!("queue datapath buffer");
 
+        if self.encryption.is_some() {
+            // the buffer is reserved with room for the packet header in front
+            let payload = &datapath_buffer.as_ref()[self.header_size()..];
+            return self.queue_encrypted_segments(msg_id, conn_id, &[(payload, false)], end_batch);
+        }
+
         let mut curr_available_wqes: usize =
             unsafe { custom_mlx5_num_wqes_available(self.thread_context.get_context_ptr()) }
                 as usize;
@@ -3727,7 +4228,7 @@ impl Datapath for Mlx5Connection {
                 num_required as _,
                 0,
                 1,
-                MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                self.tx_checksum_flags,
             )
         };
         if ctrl_seg.is_null() {
@@ -3738,8 +4239,10 @@ impl Datapath for Mlx5Connection {
             self.first_ctrl_seg = ctrl_seg;
         }
         // for queue datapath buffer, copy the header directly into the front
-        let data_len = datapath_buffer.as_ref().len() - cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+        let data_len = datapath_buffer.as_ref().len() - self.header_size();
         self.copy_hdr(&mut datapath_buffer, conn_id, msg_id, data_len)?;
+        self.transmit_stats.record_message();
+        self.transmit_stats.record_copy(data_len);
         let mut metadata_mbuf = MbufMetadata::from_buf(datapath_buffer)?;
 
         let dpseg = unsafe { custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), 0) };
@@ -3747,12 +4250,7 @@ impl Datapath for Mlx5Connection {
             unsafe { custom_mlx5_completion_start(self.thread_context.get_context_ptr()) };
         let _ = self.post_mbuf_metadata(&mut metadata_mbuf, dpseg, completion);
 
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_required as _,
-            );
-        }
+        self.finish_single_transmission(num_required as _);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -3774,6 +4272,14 @@ impl Datapath for Mlx5Connection {
         #[cfg(feature = "profiler")]
         demikernel::timer!("queue metadata vec");
 
+        if self.encryption.is_some() {
+            let segments = metadata_vec
+                .iter()
+                .map(|metadata_mbuf| (metadata_mbuf.as_ref(), true))
+                .collect::<Vec<(&[u8], bool)>>();
+            return self.queue_encrypted_segments(msg_id, conn_id, &segments, end_batch);
+        }
+
         let mut curr_available_wqes: usize =
             unsafe { custom_mlx5_num_wqes_available(self.thread_context.get_context_ptr()) }
                 as usize;
@@ -3784,24 +4290,15 @@ impl Datapath for Mlx5Connection {
                     let num_segs = metadata_vec.len() + 1;
                     let num_octowords = unsafe { custom_mlx5_num_octowords(0, num_segs as u64) };
                     let num_required = unsafe { custom_mlx5_num_wqes_required(num_octowords as _) };
-                    (
-                        num_octowords,
-                        num_required,
-                        0,
-                        cornflakes_libos::utils::TOTAL_HEADER_SIZE,
-                        num_segs,
-                    )
+                    (num_octowords, num_required, 0, self.header_size(), num_segs)
                 }
                 InlineMode::PacketHeader => {
                     let num_segs = metadata_vec.len();
                     let num_octowords = unsafe {
-                        custom_mlx5_num_octowords(
-                            cornflakes_libos::utils::TOTAL_HEADER_SIZE as u64,
-                            num_segs as u64,
-                        )
+                        custom_mlx5_num_octowords(self.header_size() as u64, num_segs as u64)
                     };
                     let num_required = unsafe { custom_mlx5_num_wqes_required(num_octowords as _) };
-                    let inline_len = cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+                    let inline_len = self.header_size();
                     (num_octowords, num_required, inline_len, 0, num_segs)
                 }
                 InlineMode::ObjectHeader => {
@@ -3834,7 +4331,7 @@ impl Datapath for Mlx5Connection {
                 num_required as _,
                 0,
                 num_segs as _,
-                MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                self.tx_checksum_flags,
             )
         };
         if ctrl_seg.is_null() {
@@ -3855,6 +4352,7 @@ impl Datapath for Mlx5Connection {
             .map(|seg| seg.as_ref().len())
             .sum::<usize>();
         let _ = self.inline_hdr_if_necessary(conn_id, msg_id, inline_len, data_len, &[])?;
+        self.transmit_stats.record_message();
         if allocation_size > 0 {
             let mut datapath_buffer = {
                 #[cfg(feature = "profiler")]
@@ -3875,18 +4373,15 @@ impl Datapath for Mlx5Connection {
         }
         // iterate over entries and post in sequence
         for mut metadata in metadata_vec.into_iter() {
+            self.transmit_stats
+                .record_zero_copy(metadata.as_ref().len());
             let (curr_dpseg, curr_completion) =
                 self.post_mbuf_metadata(&mut metadata, dpseg, completion);
             dpseg = curr_dpseg;
             completion = curr_completion;
         }
 
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_required as _,
-            );
-        }
+        self.finish_single_transmission(num_required as _);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -3914,6 +4409,44 @@ impl Datapath for Mlx5Connection {
         #[cfg(feature = "profiler")]
         demikernel::timer!("queue cornflakes hybrid obj");
         tracing::debug!(msg_id, conn_id, end_batch, "Queue cornflakes hybrid obj");
+        if self.encryption.is_some() {
+            // serialize the object header and copied data contiguously, then seal them together
+            // with the zero-copy entries
+            let serialization_info = cornflakes_obj.get_serialization_info();
+            let mut header_buffer =
+                vec![0u8; serialization_info.header_size + serialization_info.copy_length];
+            let mut zero_copy_entries: Vec<MbufMetadata> = Vec::new();
+            let mut copy_buffer: Option<&mut [u8]> = None;
+            let mut cur_copy_offset = 0;
+            let mut cur_zero_copy_offset = 0;
+            cornflakes_obj.iterate_over_entries(
+                &serialization_info,
+                header_buffer.as_mut_slice(),
+                &mut copy_buffer,
+                0,
+                cornflakes_obj.dynamic_header_start(),
+                &mut cur_copy_offset,
+                &mut cur_zero_copy_offset,
+                &mut |metadata_mbuf: &MbufMetadata,
+                      _ring_buffer_state: &mut (
+                    *mut mlx5_wqe_data_seg,
+                    *mut custom_mlx5_transmission_info,
+                )|
+                 -> Result<()> {
+                    zero_copy_entries.push(metadata_mbuf.clone());
+                    Ok(())
+                },
+                &mut (ptr::null_mut(), ptr::null_mut()),
+            )?;
+            let segments = std::iter::once((header_buffer.as_slice(), false))
+                .chain(
+                    zero_copy_entries
+                        .iter()
+                        .map(|metadata_mbuf| (metadata_mbuf.as_ref(), true)),
+                )
+                .collect::<Vec<(&[u8], bool)>>();
+            return self.queue_encrypted_segments(msg_id, conn_id, &segments, end_batch);
+        }
         let mut curr_available_wqes: usize =
             unsafe { custom_mlx5_num_wqes_available(self.thread_context.get_context_ptr()) }
                 as usize;
@@ -3938,6 +4471,13 @@ impl Datapath for Mlx5Connection {
         }
 
         self.post_ctrl_segment(num_required, inline_len, total_num_entries)?;
+        self.transmit_stats.record_serialized_object(
+            serialization_info.header_size,
+            self.inline_mode == InlineMode::ObjectHeader,
+            serialization_info.copy_length,
+            serialization_info.zero_copy_length,
+            serialization_info.num_zero_copy_entries,
+        );
 
         // first, iterate over the entries to fill the headers
         let mut ring_buffer_state = (
@@ -4028,8 +4568,8 @@ impl Datapath for Mlx5Connection {
                 self.copy_hdr(&mut allocated_header_buffer, conn_id, msg_id, data_len)?;
 
                 let header_buffer = allocated_header_buffer.mutable_slice(
-                    cornflakes_libos::utils::TOTAL_HEADER_SIZE,
-                    cornflakes_libos::utils::TOTAL_HEADER_SIZE
+                    self.header_size(),
+                    self.header_size()
                         + serialization_info.header_size
                         + serialization_info.copy_length,
                 )?;
@@ -4049,7 +4589,7 @@ impl Datapath for Mlx5Connection {
                 )?;
                 // set length of copied segment with udp header size + object header + copied data
                 allocated_header_buffer.set_len(
-                    cornflakes_libos::utils::TOTAL_HEADER_SIZE
+                    self.header_size()
                         + serialization_info.header_size
                         + serialization_info.copy_length,
                 );
@@ -4186,6 +4726,44 @@ impl Datapath for Mlx5Connection {
         #[cfg(feature = "profiler")]
         demikernel::timer!("queue cornflakes hybrid obj");
         tracing::debug!(msg_id, conn_id, end_batch, "Queue cornflakes hybrid obj");
+        if self.encryption.is_some() {
+            // serialize the object header and copied data contiguously, then seal them together
+            // with the zero-copy entries
+            let serialization_info = cornflakes_obj.get_serialization_info();
+            let mut header_buffer =
+                vec![0u8; serialization_info.header_size + serialization_info.copy_length];
+            let mut zero_copy_entries: Vec<MbufMetadata> = Vec::new();
+            let mut copy_buffer: Option<&mut [u8]> = None;
+            let mut cur_copy_offset = 0;
+            let mut cur_zero_copy_offset = 0;
+            cornflakes_obj.iterate_over_entries(
+                &serialization_info,
+                header_buffer.as_mut_slice(),
+                &mut copy_buffer,
+                0,
+                cornflakes_obj.dynamic_header_start(),
+                &mut cur_copy_offset,
+                &mut cur_zero_copy_offset,
+                &mut |metadata_mbuf: &MbufMetadata,
+                      _ring_buffer_state: &mut (
+                    *mut mlx5_wqe_data_seg,
+                    *mut custom_mlx5_transmission_info,
+                )|
+                 -> Result<()> {
+                    zero_copy_entries.push(metadata_mbuf.clone());
+                    Ok(())
+                },
+                &mut (ptr::null_mut(), ptr::null_mut()),
+            )?;
+            let segments = std::iter::once((header_buffer.as_slice(), false))
+                .chain(
+                    zero_copy_entries
+                        .iter()
+                        .map(|metadata_mbuf| (metadata_mbuf.as_ref(), true)),
+                )
+                .collect::<Vec<(&[u8], bool)>>();
+            return self.queue_encrypted_segments(msg_id, conn_id, &segments, end_batch);
+        }
         let mut curr_available_wqes: usize =
             unsafe { custom_mlx5_num_wqes_available(self.thread_context.get_context_ptr()) }
                 as usize;
@@ -4210,6 +4788,13 @@ impl Datapath for Mlx5Connection {
         }
 
         self.post_ctrl_segment(num_required, inline_len, total_num_entries)?;
+        self.transmit_stats.record_serialized_object(
+            serialization_info.header_size,
+            self.inline_mode == InlineMode::ObjectHeader,
+            serialization_info.copy_length,
+            serialization_info.zero_copy_length,
+            serialization_info.num_zero_copy_entries,
+        );
 
         // first, iterate over the entries to fill the headers
         let mut ring_buffer_state = (
@@ -4300,8 +4885,8 @@ impl Datapath for Mlx5Connection {
                 self.copy_hdr(&mut allocated_header_buffer, conn_id, msg_id, data_len)?;
 
                 let header_buffer = allocated_header_buffer.mutable_slice(
-                    cornflakes_libos::utils::TOTAL_HEADER_SIZE,
-                    cornflakes_libos::utils::TOTAL_HEADER_SIZE
+                    self.header_size(),
+                    self.header_size()
                         + serialization_info.header_size
                         + serialization_info.copy_length,
                 )?;
@@ -4321,7 +4906,7 @@ impl Datapath for Mlx5Connection {
                 )?;
                 // set length of copied segment with udp header size + object header + copied data
                 allocated_header_buffer.set_len(
-                    cornflakes_libos::utils::TOTAL_HEADER_SIZE
+                    self.header_size()
                         + serialization_info.header_size
                         + serialization_info.copy_length,
                 );
@@ -4460,6 +5045,46 @@ impl Datapath for Mlx5Connection {
         #[cfg(feature = "profiler")]
         demikernel::timer!("queue cornflakes obj");
         tracing::debug!(msg_id, conn_id, end_batch, "Queue cornflakes obj");
+        if self.encryption.is_some() {
+            // serialize the object header, then seal it together with the copied data and the
+            // zero-copy entries
+            let header_len = cornflakes_obj.total_header_size(false, false);
+            let mut header_buffer = vec![0u8; header_len];
+            let mut zero_copy_entries: Vec<MbufMetadata> = Vec::new();
+            let mut cur_entry_ptr: usize = header_len + copy_context.data_len();
+            cornflakes_obj.iterate_over_entries(
+                copy_context,
+                header_len,
+                header_buffer.as_mut_slice(),
+                0,
+                cornflakes_obj.dynamic_header_start(),
+                &mut cur_entry_ptr,
+                &mut |metadata_mbuf: &MbufMetadata,
+                      _ring_buffer_state: &mut (
+                    *mut mlx5_wqe_data_seg,
+                    *mut custom_mlx5_transmission_info,
+                )|
+                 -> Result<()> {
+                    zero_copy_entries.push(metadata_mbuf.clone());
+                    Ok(())
+                },
+                &mut (ptr::null_mut(), ptr::null_mut()),
+            )?;
+            let copy_buffers = copy_context
+                .copy_buffers_slice()
+                .iter()
+                .map(|copy_buf| copy_buf.get_buffer())
+                .collect::<Vec<Mlx5Buffer>>();
+            let segments = std::iter::once((header_buffer.as_slice(), false))
+                .chain(copy_buffers.iter().map(|buf| (buf.as_ref(), false)))
+                .chain(
+                    zero_copy_entries
+                        .iter()
+                        .map(|metadata_mbuf| (metadata_mbuf.as_ref(), true)),
+                )
+                .collect::<Vec<(&[u8], bool)>>();
+            return self.queue_encrypted_segments(msg_id, conn_id, &segments, end_batch);
+        }
         let mut curr_available_wqes: usize =
             unsafe { custom_mlx5_num_wqes_available(self.thread_context.get_context_ptr()) }
                 as usize;
@@ -4508,7 +5133,7 @@ impl Datapath for Mlx5Connection {
                 num_required as _,
                 inline_len as _,
                 total_num_entries as _,
-                MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                self.tx_checksum_flags,
             )
         };
         if ctrl_seg.is_null() {
@@ -4603,10 +5228,8 @@ impl Datapath for Mlx5Connection {
                     cornflakes_obj.iterate_over_entries(
                         copy_context,
                         header_len,
-                        allocated_header_buffer.mutable_slice(
-                            cornflakes_libos::utils::TOTAL_HEADER_SIZE,
-                            cornflakes_libos::utils::TOTAL_HEADER_SIZE + header_len,
-                        )?,
+                        allocated_header_buffer
+                            .mutable_slice(self.header_size(), self.header_size() + header_len)?,
                         0,
                         cornflakes_obj.dynamic_header_start(),
                         &mut cur_entry_ptr,
@@ -4614,11 +5237,16 @@ impl Datapath for Mlx5Connection {
                         &mut ring_buffer_state,
                     )? + header_len
                 };
+                self.record_cornflakes_obj(
+                    header_len,
+                    data_len,
+                    copy_context,
+                    num_zero_copy_entries,
+                );
 
                 // copy the packet header into the beginning of the buffer
                 self.copy_hdr(&mut allocated_header_buffer, conn_id, msg_id, data_len)?;
-                allocated_header_buffer
-                    .set_len(header_len + cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+                allocated_header_buffer.set_len(header_len + self.header_size());
                 // reset ring buffer state
                 ring_buffer_state.0 = first_copy_dpseg;
                 ring_buffer_state.1 = first_copy_completion;
@@ -4652,6 +5280,12 @@ impl Datapath for Mlx5Connection {
                     &mut callback,
                     &mut ring_buffer_state,
                 )? + header_len;
+                self.record_cornflakes_obj(
+                    header_len,
+                    data_len,
+                    copy_context,
+                    num_zero_copy_entries,
+                );
                 // inline (just) packet header
                 let _ = self.inline_hdr_if_necessary(
                     conn_id,
@@ -4687,6 +5321,12 @@ impl Datapath for Mlx5Connection {
                     &mut callback,
                     &mut ring_buffer_state,
                 )? + header_len;
+                self.record_cornflakes_obj(
+                    header_len,
+                    data_len,
+                    copy_context,
+                    num_zero_copy_entries,
+                );
                 // inline packet header and object header
                 let _ = self.inline_hdr_if_necessary(
                     conn_id,
@@ -4718,12 +5358,7 @@ impl Datapath for Mlx5Connection {
         }
 
         // finish the transmission
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_required as _,
-            );
-        }
+        self.finish_single_transmission(num_required as _);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -4750,6 +5385,24 @@ impl Datapath for Mlx5Connection {
         let msg_id = sga.0;
         let conn_id = sga.1;
         let mut arena_datapath_sga = sga.2;
+        if self.encryption.is_some() {
+            let copy_buffers = arena_datapath_sga
+                .copy_context()
+                .copy_buffers_slice()
+                .iter()
+                .map(|copy_buf| copy_buf.get_buffer())
+                .collect::<Vec<Mlx5Buffer>>();
+            let zero_copy_entries = arena_datapath_sga.zero_copy_entries_mut_slice().to_vec();
+            let segments = std::iter::once((arena_datapath_sga.get_header(), false))
+                .chain(copy_buffers.iter().map(|buf| (buf.as_ref(), false)))
+                .chain(
+                    zero_copy_entries
+                        .iter()
+                        .map(|metadata_mbuf| (metadata_mbuf.as_ref(), true)),
+                )
+                .collect::<Vec<(&[u8], bool)>>();
+            return self.queue_encrypted_segments(msg_id, conn_id, &segments, end_batch);
+        }
 
         let num_required = {
             #[cfg(feature = "profiler")]
@@ -4797,7 +5450,7 @@ impl Datapath for Mlx5Connection {
                     num_wqes_required as _,
                     inline_len as _,
                     num_segs as _,
-                    MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                    self.tx_checksum_flags,
                 )
             };
             if ctrl_seg.is_null() {
@@ -4821,9 +5474,11 @@ impl Datapath for Mlx5Connection {
 
         // TODO: temporary hack for different code surrounding inlining first entry
         let inlined_obj_hdr = entry_idx == 1;
+        self.transmit_stats.record_message();
+        self.record_hdr_transmission(arena_datapath_sga.get_header().len(), inlined_obj_hdr);
 
         let allocation_size = (!inlined_obj_hdr as usize * arena_datapath_sga.get_header().len())
-            + (!header_written as usize * cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+            + (!header_written as usize * self.header_size());
         let mut dpseg = unsafe {
             custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), inline_len as _)
         };
@@ -4872,6 +5527,7 @@ impl Datapath for Mlx5Connection {
                 .iter()
             {
                 let buffer = serialization_copy_buf.get_buffer();
+                self.transmit_stats.record_copy(buffer.data_len);
                 let mut metadata_mbuf = MbufMetadata::from_buf(buffer)?;
                 let (curr_dpseg, curr_completion) =
                     self.post_mbuf_metadata(&mut metadata_mbuf, dpseg, completion);
@@ -4882,6 +5538,8 @@ impl Datapath for Mlx5Connection {
 
         // rest are zero copy segs
         for metadata_mbuf in arena_datapath_sga.zero_copy_entries_mut_slice().iter_mut() {
+            self.transmit_stats
+                .record_zero_copy(metadata_mbuf.data_len());
             let (curr_dpseg, curr_completion) =
                 self.post_mbuf_metadata(metadata_mbuf, dpseg, completion);
             dpseg = curr_dpseg;
@@ -4889,12 +5547,7 @@ impl Datapath for Mlx5Connection {
         }
 
         // end
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_wqes_required as _,
-            );
-        }
+        self.finish_single_transmission(num_wqes_required as _);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -4926,6 +5579,18 @@ impl Datapath for Mlx5Connection {
         let msg_id = sga.0;
         let conn_id = sga.1;
         let ordered_sga = sga.2;
+        if self.encryption.is_some() {
+            let segments = std::iter::once((ordered_sga.get_hdr(), false))
+                .chain(
+                    ordered_sga
+                        .entries_slice(0, ordered_sga.len())
+                        .iter()
+                        .enumerate()
+                        .map(|(i, seg)| (seg.addr(), i >= ordered_sga.num_copy_entries())),
+                )
+                .collect::<Vec<(&[u8], bool)>>();
+            return self.queue_encrypted_segments(msg_id, conn_id, &segments, end_batch);
+        }
 
         let num_required = {
             #[cfg(feature = "profiler")]
@@ -4973,7 +5638,7 @@ impl Datapath for Mlx5Connection {
                     num_wqes_required as _,
                     inline_len as _,
                     num_segs as _,
-                    MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                    self.tx_checksum_flags,
                 )
             };
             if ctrl_seg.is_null() {
@@ -4996,11 +5661,13 @@ impl Datapath for Mlx5Connection {
 
         // TODO: temporary hack for different code surrounding inlining first entry
         let inlined_obj_hdr = entry_idx == 1;
+        self.transmit_stats.record_message();
+        self.record_hdr_transmission(ordered_sga.get_hdr().len(), inlined_obj_hdr);
 
         let first_zero_copy_seg = ordered_sga.num_copy_entries();
         let allocation_size = ordered_sga.copy_length()
             - (inlined_obj_hdr as usize * ordered_sga.get_hdr().len())
-            + (!header_written as usize * cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+            + (!header_written as usize * self.header_size());
         let mut dpseg = unsafe {
             custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), inline_len as _)
         };
@@ -5039,6 +5706,8 @@ impl Datapath for Mlx5Connection {
                         data_buffer.write(seg.addr())? == seg.len(),
                         "Failed to copy segment into data buffer"
                     );
+                    self.transmit_stats
+                        .record_copied_segment(seg.len(), self.copying_threshold);
                 }
             }
             let mut metadata_mbuf = MbufMetadata::from_buf(data_buffer)?;
@@ -5062,6 +5731,8 @@ impl Datapath for Mlx5Connection {
                     RcSge::RefCounted(mbuf_metadata) => {
                         let mut mbuf_copy = mbuf_metadata.clone();
                         mbuf_copy.increment_refcnt();
+                        self.transmit_stats
+                            .record_zero_copy(mbuf_metadata.data_len());
                         tracing::debug!(seg =? mbuf_metadata.as_ref().as_ptr(), "Cur posting seg");
                         let (curr_dpseg, curr_completion) = {
                             tracing::debug!(
@@ -5101,12 +5772,7 @@ impl Datapath for Mlx5Connection {
         }
         // end
 
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_wqes_required as _,
-            );
-        }
+        self.finish_single_transmission(num_wqes_required as _);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -5149,6 +5815,23 @@ impl Datapath for Mlx5Connection {
                 self.copying_threshold == std::usize::MAX,
             )?;
         }
+        if self.encryption.is_some() {
+            let segments = std::iter::once((ordered_sga.get_hdr(), false))
+                .chain(
+                    ordered_sga
+                        .entries_slice(0, ordered_sga.len())
+                        .iter()
+                        .enumerate()
+                        .map(|(i, seg)| (seg.addr(), i >= ordered_sga.num_copy_entries())),
+                )
+                .collect::<Vec<(&[u8], bool)>>();
+            self.queue_encrypted_segments(msg_id, conn_id, &segments, end_batch)?;
+            // the zero-copy entries were copied, so release their recovered metadata
+            for mbuf_metadata_option in self.mbuf_metadatas.iter_mut().take(ordered_sga.len()) {
+                *mbuf_metadata_option = None;
+            }
+            return Ok(());
+        }
 
         let num_required = {
             #[cfg(feature = "profiler")]
@@ -5196,7 +5879,7 @@ impl Datapath for Mlx5Connection {
                     num_wqes_required as _,
                     inline_len as _,
                     num_segs as _,
-                    MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                    self.tx_checksum_flags,
                 )
             };
             if ctrl_seg.is_null() {
@@ -5219,11 +5902,13 @@ impl Datapath for Mlx5Connection {
 
         // TODO: temporary hack for different code surrounding inlining first entry
         let inlined_obj_hdr = entry_idx == 1;
+        self.transmit_stats.record_message();
+        self.record_hdr_transmission(ordered_sga.get_hdr().len(), inlined_obj_hdr);
 
         let first_zero_copy_seg = ordered_sga.num_copy_entries();
         let allocation_size = ordered_sga.copy_length()
             - (inlined_obj_hdr as usize * ordered_sga.get_hdr().len())
-            + (!header_written as usize * cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+            + (!header_written as usize * self.header_size());
         let mut dpseg = unsafe {
             custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), inline_len as _)
         };
@@ -5247,7 +5932,7 @@ impl Datapath for Mlx5Connection {
             let mut offset = 0;
             if !header_written {
                 self.copy_hdr(&mut data_buffer, conn_id, msg_id, data_len)?;
-                offset += cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+                offset += self.header_size();
             }
             if !inlined_obj_hdr {
                 let data_slice =
@@ -5264,6 +5949,8 @@ impl Datapath for Mlx5Connection {
                     unsafe {
                         mlx5_rte_memcpy(dst.as_mut_ptr() as _, seg.addr().as_ptr() as _, seg.len());
                     }
+                    self.transmit_stats
+                        .record_copied_segment(seg.len(), self.copying_threshold);
                     offset += seg.len();
                 }
             }
@@ -5287,6 +5974,8 @@ impl Datapath for Mlx5Connection {
                 match mbuf_metadata_option {
                     Some(ref mut mbuf_metadata) => {
                         mbuf_metadata.increment_refcnt();
+                        self.transmit_stats
+                            .record_zero_copy(mbuf_metadata.data_len());
                         tracing::debug!(seg =? mbuf_metadata.as_ref().as_ptr(), "Cur posting seg");
                         let (curr_dpseg, curr_completion) = {
                             tracing::debug!(
@@ -5326,12 +6015,7 @@ impl Datapath for Mlx5Connection {
                 *mbuf_metadata_option = None;
             }
         }
-        unsafe {
-            custom_mlx5_finish_single_transmission(
-                self.thread_context.get_context_ptr(),
-                num_wqes_required as _,
-            );
-        }
+        self.finish_single_transmission(num_wqes_required as _);
 
         if end_batch {
             if !self.first_ctrl_seg.is_null() {
@@ -5347,6 +6031,23 @@ impl Datapath for Mlx5Connection {
         &mut self,
         mut sgas: impl Iterator<Item = Result<(MsgID, ConnID, ArenaOrderedSga<'sge>)>>,
     ) -> Result<()> {
+        if self.encryption.is_some() {
+            for res in sgas {
+                let (msg_id, conn_id, ordered_sga) = res?;
+                let segments = std::iter::once((ordered_sga.get_hdr(), false))
+                    .chain(
+                        ordered_sga
+                            .entries_slice(0, ordered_sga.len())
+                            .iter()
+                            .enumerate()
+                            .map(|(i, seg)| (seg.addr(), i >= ordered_sga.num_copy_entries())),
+                    )
+                    .collect::<Vec<(&[u8], bool)>>();
+                self.queue_encrypted_segments(msg_id, conn_id, &segments, false)?;
+            }
+            let _ = self.post_curr_transmissions_and_get_available_wqes()?;
+            return Ok(());
+        }
         #[cfg(feature = "profiler")]
         demikernel::timer!("Push arena sgas iterator func");
         let mut curr_available_wqes: usize =
@@ -5415,7 +6116,7 @@ impl Datapath for Mlx5Connection {
                         num_wqes_required as _,
                         inline_len as _,
                         num_segs as _,
-                        MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                        self.tx_checksum_flags,
                     )
                 };
                 if ctrl_seg.is_null() {
@@ -5438,11 +6139,13 @@ impl Datapath for Mlx5Connection {
 
             // TODO: temporary hack for different code surrounding inlining first entry
             let inlined_obj_hdr = entry_idx == 1;
+            self.transmit_stats.record_message();
+            self.record_hdr_transmission(ordered_sga.get_hdr().len(), inlined_obj_hdr);
 
             let first_zero_copy_seg = ordered_sga.num_copy_entries();
             let allocation_size = ordered_sga.copy_length()
                 - (inlined_obj_hdr as usize * ordered_sga.get_hdr().len())
-                + (!header_written as usize * cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+                + (!header_written as usize * self.header_size());
             let mut dpseg = unsafe {
                 custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), inline_len as _)
             };
@@ -5466,7 +6169,7 @@ impl Datapath for Mlx5Connection {
                 let mut offset = 0;
                 if !header_written {
                     self.copy_hdr(&mut data_buffer, conn_id, msg_id, data_len)?;
-                    offset += cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+                    offset += self.header_size();
                 }
                 if !inlined_obj_hdr {
                     let data_slice =
@@ -5480,6 +6183,8 @@ impl Datapath for Mlx5Connection {
                     unsafe {
                         mlx5_rte_memcpy(dst.as_mut_ptr() as _, seg.addr().as_ptr() as _, seg.len());
                     }
+                    self.transmit_stats
+                        .record_copied_segment(seg.len(), self.copying_threshold);
                     offset += seg.len();
                 }
 
@@ -5503,6 +6208,7 @@ impl Datapath for Mlx5Connection {
                         bail!("Failed to recover mbuf metadata for seg{:?}", curr_seg);
                     }
                 };
+                self.transmit_stats.record_zero_copy(curr_seg.len());
                 let (curr_dpseg, curr_completion) =
                     self.post_mbuf_metadata(&mut mbuf_metadata, dpseg, completion);
 
@@ -5510,12 +6216,7 @@ impl Datapath for Mlx5Connection {
                 completion = curr_completion;
             }
 
-            unsafe {
-                custom_mlx5_finish_single_transmission(
-                    self.thread_context.get_context_ptr(),
-                    num_wqes_required as _,
-                );
-            }
+            self.finish_single_transmission(num_wqes_required as _);
             _sent += 1;
             obj = {
                 #[cfg(feature = "profiler")]
@@ -5536,6 +6237,23 @@ impl Datapath for Mlx5Connection {
         &mut self,
         mut sgas: impl Iterator<Item = Result<(MsgID, ConnID, OrderedSga<'sge>)>>,
     ) -> Result<()> {
+        if self.encryption.is_some() {
+            for res in sgas {
+                let (msg_id, conn_id, ordered_sga) = res?;
+                let segments = std::iter::once((ordered_sga.get_hdr(), false))
+                    .chain(
+                        ordered_sga
+                            .entries_slice(0, ordered_sga.len())
+                            .iter()
+                            .enumerate()
+                            .map(|(i, seg)| (seg.addr(), i >= ordered_sga.num_copy_entries())),
+                    )
+                    .collect::<Vec<(&[u8], bool)>>();
+                self.queue_encrypted_segments(msg_id, conn_id, &segments, false)?;
+            }
+            let _ = self.post_curr_transmissions_and_get_available_wqes()?;
+            return Ok(());
+        }
         #[cfg(feature = "profiler")]
         demikernel::timer!("Push sgas iterator func");
         let mut curr_available_wqes: usize =
@@ -5604,7 +6322,7 @@ impl Datapath for Mlx5Connection {
                         num_wqes_required as _,
                         inline_len as _,
                         num_segs as _,
-                        MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+                        self.tx_checksum_flags,
                     )
                 };
                 if ctrl_seg.is_null() {
@@ -5627,11 +6345,13 @@ impl Datapath for Mlx5Connection {
 
             // TODO: temporary hack for different code surrounding inlining first entry
             let inlined_obj_hdr = entry_idx == 1;
+            self.transmit_stats.record_message();
+            self.record_hdr_transmission(ordered_sga.get_hdr().len(), inlined_obj_hdr);
 
             let first_zero_copy_seg = ordered_sga.num_copy_entries();
             let allocation_size = ordered_sga.copy_length()
                 - (inlined_obj_hdr as usize * ordered_sga.get_hdr().len())
-                + (!header_written as usize * cornflakes_libos::utils::TOTAL_HEADER_SIZE);
+                + (!header_written as usize * self.header_size());
             let mut dpseg = unsafe {
                 custom_mlx5_dpseg_start(self.thread_context.get_context_ptr(), inline_len as _)
             };
@@ -5655,7 +6375,7 @@ impl Datapath for Mlx5Connection {
                 let mut offset = 0;
                 if !header_written {
                     self.copy_hdr(&mut data_buffer, conn_id, msg_id, data_len)?;
-                    offset += cornflakes_libos::utils::TOTAL_HEADER_SIZE;
+                    offset += self.header_size();
                 }
                 if !inlined_obj_hdr {
                     let data_slice =
@@ -5669,6 +6389,8 @@ impl Datapath for Mlx5Connection {
                     unsafe {
                         mlx5_rte_memcpy(dst.as_mut_ptr() as _, seg.addr().as_ptr() as _, seg.len());
                     }
+                    self.transmit_stats
+                        .record_copied_segment(seg.len(), self.copying_threshold);
                     offset += seg.len();
                 }
 
@@ -5697,6 +6419,7 @@ impl Datapath for Mlx5Connection {
                         bail!("Failed to recover mbuf metadata for seg{:?}", curr_seg);
                     }
                 };
+                self.transmit_stats.record_zero_copy(curr_seg.len());
                 let (curr_dpseg, curr_completion) =
                     self.post_mbuf_metadata(&mut mbuf_metadata, dpseg, completion);
 
@@ -5704,12 +6427,7 @@ impl Datapath for Mlx5Connection {
                 completion = curr_completion;
             }
 
-            unsafe {
-                custom_mlx5_finish_single_transmission(
-                    self.thread_context.get_context_ptr(),
-                    num_wqes_required as _,
-                );
-            }
+            self.finish_single_transmission(num_wqes_required as _);
             _sent += 1;
             obj = {
                 #[cfg(feature = "profiler")]
@@ -5730,6 +6448,17 @@ impl Datapath for Mlx5Connection {
         let mut first_ctrl_seg: Option<*mut mlx5_wqe_ctrl_seg> = None;
         let mut sga_idx = 0;
         tracing::debug!(len = sgas.len(), "Pushing sgas");
+        if self.encryption.is_some() {
+            for (i, (msg_id, conn_id, sga)) in sgas.iter().enumerate() {
+                let segments = sga
+                    .iter()
+                    .map(|seg| (seg.addr(), self.zero_copy_seg(seg.addr())))
+                    .collect::<Vec<(&[u8], bool)>>();
+                let end_batch = i == sgas.len() - 1;
+                self.queue_encrypted_segments(*msg_id, *conn_id, &segments, end_batch)?;
+            }
+            return Ok(());
+        }
         while sga_idx < sgas.len() {
             let (msg_id, conn_id, sga) = &sgas[sga_idx];
             self.insert_into_outgoing_map(*msg_id, *conn_id);
@@ -5783,6 +6512,12 @@ impl Datapath for Mlx5Connection {
     where
         Self: Sized,
     {
+        if !self.deferred_pkts.is_empty() {
+            return Ok(deferred_with_durations(
+                std::mem::take(&mut self.deferred_pkts),
+                &mut self.outgoing_window,
+            ));
+        }
         let received = unsafe {
             custom_mlx5_gather_rx(
                 self.thread_context.get_context_ptr(),
@@ -5840,46 +6575,14 @@ impl Datapath for Mlx5Connection {
         Ok(ret)
     }
 
-    // TODO: if the packet is a no-op, simply respond to the caller
     fn pop(&mut self) -> Result<Vec<ReceivedPkt<Self>>>
     where
         Self: Sized,
     {
-        let received = unsafe {
-            custom_mlx5_gather_rx(
-                self.thread_context.get_context_ptr(),
-                self.recv_mbufs.as_recv_mbuf_info_array_ptr(),
-                RECEIVE_BURST_SIZE as _,
-            )
-        };
-        let mut ret: Vec<ReceivedPkt<Self>> = Vec::with_capacity(RECEIVE_BURST_SIZE);
-        for i in 0..received as usize {
-            if let Some(received_pkt) = self
-                .check_received_pkt(i)
-                .wrap_err("Error receiving packets")?
-            {
-                // if is NO-OP, just return a NO-OP to the caller
-                if received_pkt.is_noop() {
-                    tracing::debug!("Processing NO-OP");
-                    self.process_warmup_noop(received_pkt)?;
-                } else {
-                    ret.push(received_pkt);
-                }
-            } else {
-                // free the mbuf
-                let recv_info = self.recv_mbufs.get(i);
-                unsafe {
-                    custom_mlx5_refcnt_update_or_free(
-                        (*recv_info).mempool,
-                        (*recv_info).buf_addr,
-                        (*recv_info).ref_count_index as _,
-                        -1i8,
-                    );
-                }
-                self.recv_mbufs.clear(i);
-            }
+        if !self.deferred_pkts.is_empty() {
+            return Ok(std::mem::take(&mut self.deferred_pkts));
         }
-        Ok(ret)
+        self.receive_pkts()
     }
 
     fn timed_out(&self, time_out: Duration) -> Result<Vec<(MsgID, ConnID)>> {
@@ -5900,6 +6603,24 @@ impl Datapath for Mlx5Connection {
     }
 
     fn allocate(&mut self, size: usize) -> Result<Option<Self::DatapathBuffer>> {
+        if let Some(buf) = self.allocator.allocate_buffer(size)? {
+            return Ok(Some(buf));
+        }
+        let config = self.allocator.config();
+        if !self.allocator.can_grow(config.mempool_bytes()) {
+            return Ok(None);
+        }
+        tracing::info!("Growing mempools of size {}", size);
+        let (mempool_params, data_mempool) = self.allocate_data_mempool(
+            size,
+            config.num_pages * PGSIZE_2MB / size,
+            config.num_registrations,
+            true,
+        )?;
+        let id = self
+            .allocator
+            .add_mempool(mempool_params.get_item_len(), data_mempool)?;
+        self.uncached_mempools.insert(id);
         self.allocator.allocate_buffer(size)
     }
 
@@ -5919,6 +6640,33 @@ impl Datapath for Mlx5Connection {
         self.allocator.recover_buffer(buf)
     }
 
+    unsafe fn register_external_region(
+        &mut self,
+        addr: *mut u8,
+        len: usize,
+        pagesize: usize,
+    ) -> Result<MempoolID> {
+        let region = DataMempool::new_external(addr, len, pagesize, &self.thread_context)?;
+        self.allocator.add_external_region(region)
+    }
+
+    fn unregister_external_region(&mut self, id: MempoolID) -> Result<bool> {
+        match self.allocator.external_region(id) {
+            Some(region) => {
+                if region.external_region_in_use() {
+                    tracing::debug!("External region {} still referenced", id);
+                    return Ok(false);
+                }
+            }
+            None => {
+                bail!("No external region with ID {}", id);
+            }
+        }
+        // dropping the region unregisters it
+        let _ = self.allocator.remove_external_region(id)?;
+        Ok(true)
+    }
+
     #[inline]
     fn recover_metadata_if_pinned_and_insert_into_zero_copy_cache(
         &mut self,
@@ -5948,7 +6696,14 @@ impl Datapath for Mlx5Connection {
             }
             None => {
                 tracing::debug!("Zero copy cache doesn't have addr {:?}", buf.as_ptr());
-                Ok(None)
+                // application-owned regions and mempools grown on demand are registered outside
+                // the zero copy cache
+                match self.allocator.containing_mempool_id(buf) {
+                    Some(id) if self.uncached_mempools.contains(&id) => {
+                        self.allocator.recover_from_mempool(id, buf)
+                    }
+                    _ => self.allocator.recover_external_buffer(buf),
+                }
             }
         }
     }
@@ -5981,16 +6736,8 @@ impl Datapath for Mlx5Connection {
     ) -> Result<Vec<MempoolID>> {
         // use 2MB pages for data, 2MB pages for metadata (?)
         //println!("In add memory pool: size {}, min_elts {}", size, min_elts);
-        let actual_size = cornflakes_libos::allocator::align_to_pow2(size);
-        let mempool_params = sizes::MempoolAllocationParams::new(
-            min_elts,
-            PGSIZE_2MB,
-            actual_size,
-            num_registration_units,
-        )
-        .wrap_err("Incorrect mempool allocation params")?;
-        tracing::info!(mempool_params = ?mempool_params, "Adding mempool");
-        let data_mempool = DataMempool::new(&mempool_params, &self.thread_context, true, false)?;
+        let (mempool_params, data_mempool) =
+            self.allocate_data_mempool(size, min_elts, num_registration_units, false)?;
         let mut cornflakes_slab = data_mempool.get_cornflakes_mlx5_slab()?;
         tracing::debug!("Cornflakes slab: {:?}", cornflakes_slab);
         let id = self
@@ -6014,8 +6761,51 @@ impl Datapath for Mlx5Connection {
         return self.allocator.has_mempool(size);
     }
 
+    fn get_mempool_config(&self) -> MempoolConfig {
+        self.allocator.config()
+    }
+
+    fn set_mempool_config(&mut self, config: MempoolConfig) -> Result<()> {
+        self.allocator.set_config(config)
+    }
+
+    fn mempool_size_class(&self, size: usize) -> usize {
+        self.allocator.size_class(size)
+    }
+
+    fn allocator_memory_stats(&self) -> AllocatorMemoryStats {
+        self.allocator.memory_stats()
+    }
+
+    fn remove_memory_pool(&mut self, id: MempoolID) -> Result<bool> {
+        if !self.uncached_mempools.contains(&id) {
+            // the zero copy cache may still hold registrations over the mempool's pages
+            tracing::debug!("Mempool {} belongs to the zero copy cache; keeping it", id);
+            return Ok(false);
+        }
+        match self.allocator.remove_mempool(id)? {
+            Some(data_mempool) => {
+                // dropping the mempool deregisters and frees its pages
+                drop(data_mempool);
+                self.uncached_mempools.remove(&id);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn remove_idle_mempools(&mut self) -> Result<Vec<MempoolID>> {
+        let mut removed = Vec::default();
+        for id in self.allocator.idle_mempool_ids().into_iter() {
+            if self.remove_memory_pool(id)? {
+                removed.push(id);
+            }
+        }
+        Ok(removed)
+    }
+
     fn header_size(&self) -> usize {
-        cornflakes_libos::utils::TOTAL_HEADER_SIZE
+        self.thread_context.get_address_info().header_size()
     }
 
     fn timer_hz(&self) -> u64 {
@@ -6050,6 +6840,68 @@ impl Datapath for Mlx5Connection {
         self.inline_mode = inline_mode;
     }
 
+    fn transmit_stats(&self) -> TransmitStats {
+        self.transmit_stats
+    }
+
+    fn reset_transmit_stats(&mut self) {
+        self.transmit_stats = TransmitStats::default();
+    }
+
+    fn checksum_offload_capable(&self) -> bool {
+        true
+    }
+
+    fn set_checksum_mode(&mut self, mode: ChecksumMode) -> Result<()> {
+        ensure!(
+            mode != ChecksumMode::Disabled
+                || self.thread_context.get_address_info().ip_addr.is_ipv4(),
+            "UDP checksums are required over ipv6"
+        );
+        // the ipv4 header checksum is always offloaded
+        self.tx_checksum_flags = match mode {
+            ChecksumMode::Offload => MLX5_ETH_WQE_L3_CSUM as i32 | MLX5_ETH_WQE_L4_CSUM as i32,
+            ChecksumMode::Disabled => MLX5_ETH_WQE_L3_CSUM as i32,
+            ChecksumMode::Software => {
+                bail!("Mlx5 datapath offloads checksums; software checksums not supported");
+            }
+        };
+        self.checksum_mode = mode;
+        Ok(())
+    }
+
+    fn get_checksum_mode(&self) -> ChecksumMode {
+        self.checksum_mode
+    }
+
+    fn receive_stats(&self) -> ReceiveStats {
+        self.receive_stats
+    }
+
+    fn reset_receive_stats(&mut self) {
+        self.receive_stats = ReceiveStats::default();
+    }
+
+    fn set_packet_capture(&mut self, capture: PacketCapture) -> Result<()> {
+        self.capture_buffer = vec![0u8; MAX_CAPTURED_FRAME_SIZE];
+        self.capture = Some(capture);
+        Ok(())
+    }
+
+    fn set_encryption(&mut self, config: EncryptionConfig) -> Result<()> {
+        tracing::info!(
+            algorithm = ?config.algorithm,
+            copying_threshold = self.copying_threshold,
+            "Encrypting payloads; segments above the copying threshold are copied as well"
+        );
+        self.encryption = Some(TransportEncryption::new(
+            config,
+            self.thread_context.address_info,
+            self.mode,
+        ));
+        Ok(())
+    }
+
     fn batch_size() -> usize {
         RECEIVE_BURST_SIZE
     }
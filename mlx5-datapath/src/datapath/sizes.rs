@@ -56,9 +56,9 @@ impl MempoolAllocationParams {
             bail!("Data pgsize provided: {} not 4KB, 2MB, or 1GB", data_pgsize);
         }
 
-        if data_pgsize % item_size != 0 {
+        if item_size == 0 || item_size > data_pgsize {
             bail!(
-                "Item size provided: {} not aligned to pgsize: {}",
+                "Item size provided: {} does not fit in pgsize: {}",
                 item_size,
                 data_pgsize
             );
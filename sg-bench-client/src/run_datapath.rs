@@ -7,7 +7,7 @@ use rand::{
     seq::SliceRandom,
     thread_rng, SeedableRng,
 };
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 use structopt::StructOpt;
 
 #[macro_export]
@@ -42,7 +42,7 @@ macro_rules! run_client(
             .enumerate()
         {
         let server_addr_clone =
-            cornflakes_libos::utils::AddressInfo::new(server_addr.2, server_addr.1.clone(), server_addr.0.clone());
+            cornflakes_libos::utils::AddressInfo::from_known_host(server_addr.2, server_addr.1.clone(), server_addr.0);
             let datapath_params_clone = datapath_params.clone();
 
             let max_num_requests = num_rtts;
@@ -181,9 +181,9 @@ pub struct SgBenchOpt {
         help = "Server ip address",
         default_value = "127.0.0.1"
     )]
-    pub server_ip: Ipv4Addr,
+    pub server_ip: IpAddr,
     #[structopt(long = "our_ip", help = "Our ip address", default_value = "127.0.0.1")]
-    pub our_ip: Ipv4Addr,
+    pub our_ip: IpAddr,
     #[structopt(long = "retries", help = "Enable client retries.")]
     pub retries: bool,
     #[structopt(long = "logfile", help = "Logfile to log all client RTTs.")]
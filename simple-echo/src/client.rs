@@ -109,6 +109,11 @@ where
     ) -> Result<bool> {
         // if in debug mode, check whether the bytes are what they should be
         tracing::debug!(id = sga.msg_id(), "Received sga");
+        if sga.is_overloaded_reply() {
+            // the server shed the request; it was not echoed
+            tracing::debug!(id = sga.msg_id(), "Received overloaded reply");
+            return Ok(false);
+        }
         if cfg!(debug_assertions) {
             let bytes = sga.flatten();
             if bytes != self.bytes_to_transmit {
@@ -1,10 +1,11 @@
 use super::RequestShape;
 use cornflakes_libos::{
+    admission::AdmissionConfig,
     datapath::{InlineMode, PushBufType},
     loadgen::request_schedule::DistributionType,
 };
 use cornflakes_utils::{AppMode, TraceLevel};
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 use structopt::StructOpt;
 
 #[macro_export]
@@ -25,7 +26,8 @@ macro_rules! run_server (
 
             echo_server.init(&mut connection)?;
 
-            echo_server.run_state_machine_baseline(&mut connection)?;
+            let admission_controller = admission_config(&$opt).map(cornflakes_libos::admission::AdmissionController::new);
+            echo_server.run_state_machine_baseline_with_admission_control(&mut connection, admission_controller)?;
     }
 );
 
@@ -58,7 +60,7 @@ macro_rules! run_client (
             .enumerate()
         {
         let server_addr_clone =
-            cornflakes_libos::utils::AddressInfo::new(server_addr.2, server_addr.1.clone(), server_addr.0.clone());
+            cornflakes_libos::utils::AddressInfo::from_known_host(server_addr.2, server_addr.1.clone(), server_addr.0);
             let datapath_params_clone = datapath_params.clone();
             let request_shape = $opt.request_shape.clone();
             let max_num_requests = num_rtts;
@@ -116,6 +118,15 @@ macro_rules! run_client (
     }
 );
 
+/// Admission control settings for each server thread, if rate limiting or load shedding is on.
+pub fn admission_config(opt: &SimpleEchoOpt) -> Option<AdmissionConfig> {
+    AdmissionConfig::from_flags(
+        opt.admission_rate,
+        opt.admission_burst,
+        opt.admission_queue_depth,
+    )
+}
+
 #[derive(Debug, StructOpt, Clone)]
 #[structopt(
     name = "Simple echo command line",
@@ -138,13 +149,13 @@ pub struct SimpleEchoOpt {
     #[structopt(long = "mode", help = "App mode: client or server")]
     pub mode: AppMode,
     #[structopt(long = "our_ip", help = "Our IP Address", default_value = "127.0.0.1")]
-    pub our_ip: Ipv4Addr,
+    pub our_ip: IpAddr,
     #[structopt(
         long = "server_ip",
         help = "Our IP Address",
         default_value = "127.0.0.1"
     )]
-    pub server_ip: Ipv4Addr,
+    pub server_ip: IpAddr,
     #[structopt(
         short = "r",
         long = "rate",
@@ -201,4 +212,20 @@ pub struct SimpleEchoOpt {
         default_value = "256"
     )]
     pub copying_threshold: usize,
+    #[structopt(
+        long = "admission_rate",
+        help = "Server: requests per second admitted from each client connection; requests above the rate are shed."
+    )]
+    pub admission_rate: Option<f64>,
+    #[structopt(
+        long = "admission_burst",
+        help = "Server: requests a client connection may send back to back above --admission_rate.",
+        default_value = "32"
+    )]
+    pub admission_burst: u64,
+    #[structopt(
+        long = "admission_queue_depth",
+        help = "Server: admitted requests that may wait to be processed; requests arriving beyond it are shed with an overloaded reply."
+    )]
+    pub admission_queue_depth: Option<usize>,
 }